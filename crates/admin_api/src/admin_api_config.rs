@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use utils::configs::{Config, ConfigName};
+
+/// What a token is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// Only GET requests
+    ReadOnly,
+    ReadWrite,
+}
+impl TokenScope {
+    /// ReadWrite tokens can do everything a ReadOnly token can
+    pub fn allows(&self, required: TokenScope) -> bool {
+        *self == TokenScope::ReadWrite || required == TokenScope::ReadOnly
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct APIToken {
+    /// Shown in the logs. Never the token itself
+    pub name: String,
+    /// The hex encoded SHA-256 of the token. So the config file does not contain the token
+    pub token_sha256: String,
+    pub scope: TokenScope,
+}
+
+/// # Example
+/// ```toml
+/// bind = "127.0.0.1:8081"
+///
+/// # printf %s "$TOKEN" | sha256sum
+/// [[tokens]]
+/// name = "portal"
+/// token_sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+/// scope = "read_write"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AdminAPIConfig {
+    /// The address the HTTP server binds to. Do not expose it to the internet
+    pub bind: String,
+    /// No requests are accepted until a token is added
+    #[serde(default)]
+    pub tokens: Vec<APIToken>,
+}
+impl Default for AdminAPIConfig {
+    fn default() -> Self {
+        AdminAPIConfig {
+            bind: "127.0.0.1:8081".to_string(),
+            tokens: Vec::new(),
+        }
+    }
+}
+impl AdminAPIConfig {
+    /// The configured token matching a bearer token
+    pub fn find_token(&self, token: &str) -> Option<&APIToken> {
+        let hash = format!("{:x}", Sha256::digest(token.as_bytes()));
+        self.tokens
+            .iter()
+            .find(|api_token| api_token.token_sha256.eq_ignore_ascii_case(&hash))
+    }
+}
+impl Config for AdminAPIConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/admin_api")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("admin_api.toml")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::admin_api_config::{AdminAPIConfig, TokenScope};
+
+    #[test]
+    pub fn test_find_token() {
+        let config: AdminAPIConfig = toml::from_str(
+            r#"
+            bind = "127.0.0.1:8081"
+
+            [[tokens]]
+            name = "portal"
+            # test
+            token_sha256 = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08"
+            scope = "read_write"
+
+            [[tokens]]
+            name = "monitoring"
+            # monitoring
+            token_sha256 = "14a2326b6bb54f4045dad6bee6f667f64f143e354a6aa77f4bdb5f6ed19ca167"
+            scope = "read_only"
+            "#,
+        )
+        .unwrap();
+        let token = config.find_token("test").unwrap();
+        assert_eq!(token.name, "portal");
+        assert_eq!(token.scope, TokenScope::ReadWrite);
+        let token = config.find_token("monitoring").unwrap();
+        assert_eq!(token.scope, TokenScope::ReadOnly);
+        assert!(config.find_token("TEST").is_none());
+        assert!(config.find_token("").is_none());
+
+        assert!(TokenScope::ReadWrite.allows(TokenScope::ReadOnly));
+        assert!(TokenScope::ReadOnly.allows(TokenScope::ReadOnly));
+        assert!(!TokenScope::ReadOnly.allows(TokenScope::ReadWrite));
+    }
+}
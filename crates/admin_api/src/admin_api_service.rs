@@ -0,0 +1,470 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::io;
+use std::net::{AddrParseError, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use directories::directory_type::Directory;
+use directories::ValidateDirectoryRequest;
+use storages::storage_type::Storage;
+use utils::admin::{AccountAddress, AccountUpdate, AdminError, NewAccount, NewGroup};
+use utils::configs::{Config, IOOrToml};
+use utils::helper_types::EmailAddress;
+use utils::service::ServiceAccess;
+
+use crate::admin_api_config::{AdminAPIConfig, TokenScope};
+use crate::authentication::bearer_token;
+use crate::health::{directory_health, storage_health, Health, ServiceVersion};
+use crate::openapi::openapi_document;
+use crate::routes::{find_route, Operation, Route};
+
+/// Larger request bodies are refused with 413 Payload Too Large
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum AdminAPIServiceError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Config(#[from] IOOrToml),
+    #[error("Invalid bind address: {0}")]
+    InvalidBind(#[from] AddrParseError),
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+}
+
+/// Why a request failed. Returned as a `application/problem+json` body
+#[derive(Debug, Error)]
+enum RequestError {
+    #[error(transparent)]
+    Admin(#[from] AdminError),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("Invalid request body: {0}")]
+    InvalidBody(String),
+    #[error("The request body is larger than {MAX_BODY_SIZE} bytes")]
+    BodyTooLarge,
+    #[error("The directory service is not available: {0}")]
+    DirectoryUnavailable(Box<dyn Error + Send + Sync + 'static>),
+    #[error("The directory service failed: {0}")]
+    Directory(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+impl RequestError {
+    fn directory(error: impl Error + Send + Sync + 'static) -> Self {
+        RequestError::Directory(Box::new(error))
+    }
+    fn status(&self) -> StatusCode {
+        match self {
+            RequestError::Admin(AdminError::NotSupported) => StatusCode::NOT_IMPLEMENTED,
+            RequestError::Admin(
+                AdminError::AccountExists(_)
+                | AdminError::GroupExists(_)
+                | AdminError::AddressInUse(_),
+            ) => StatusCode::CONFLICT,
+            RequestError::Admin(AdminError::AccountNotFound(_) | AdminError::GroupNotFound(_))
+            | RequestError::NotFound(_) => StatusCode::NOT_FOUND,
+            RequestError::Admin(
+                AdminError::InvalidEmailAddress(_)
+                | AdminError::InvalidPassword(_)
+                | AdminError::InvalidUsername(_),
+            ) => StatusCode::UNPROCESSABLE_ENTITY,
+            RequestError::InvalidBody(_) | RequestError::Hyper(_) => StatusCode::BAD_REQUEST,
+            RequestError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            RequestError::DirectoryUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            RequestError::Directory(_) | RequestError::Json(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PasswordBody {
+    password: String,
+}
+#[derive(Debug, Deserialize)]
+struct ActiveBody {
+    active: bool,
+}
+#[derive(Debug, Deserialize)]
+struct RenameBody {
+    username: String,
+}
+
+pub struct AdminAPIServiceInner<DirectoryAccess: ServiceAccess, StorageAccess: ServiceAccess>
+where
+    DirectoryAccess::ServiceResponse: Directory,
+    StorageAccess::ServiceResponse: Storage,
+{
+    pub config: AdminAPIConfig,
+    /// Sent to the directory to check its health
+    pub validate_directory_request: ValidateDirectoryRequest,
+    pub directory_service_access: DirectoryAccess,
+    pub storage_service_access: StorageAccess,
+}
+pub type AdminAPIServiceAccess<DirectoryAccess, StorageAccess> =
+    Arc<AdminAPIServiceInner<DirectoryAccess, StorageAccess>>;
+
+pub struct AdminAPIService<DirectoryAccess: ServiceAccess, StorageAccess: ServiceAccess>
+where
+    DirectoryAccess::ServiceResponse: Directory,
+    StorageAccess::ServiceResponse: Storage,
+{
+    pub inner: AdminAPIServiceAccess<DirectoryAccess, StorageAccess>,
+    handle: JoinHandle<()>,
+}
+
+impl<DirectoryAccess: ServiceAccess, StorageAccess: ServiceAccess>
+    AdminAPIService<DirectoryAccess, StorageAccess>
+where
+    DirectoryAccess::ServiceResponse: Directory,
+    StorageAccess::ServiceResponse: Storage,
+{
+    pub fn start(
+        working_directory: PathBuf,
+        validate_directory_request: ValidateDirectoryRequest,
+        directory_service_access: DirectoryAccess,
+        storage_service_access: StorageAccess,
+    ) -> Result<Self, AdminAPIServiceError> {
+        let config = AdminAPIConfig::get_or_save_default(working_directory)?;
+        let bind: SocketAddr = config.bind.parse()?;
+        let server = Server::try_bind(&bind)?;
+        if config.tokens.is_empty() {
+            info!("The admin API has no tokens. Add them to admin_api.toml");
+        }
+
+        let service = Arc::new(AdminAPIServiceInner {
+            config,
+            validate_directory_request,
+            directory_service_access,
+            storage_service_access,
+        });
+        let make_service = {
+            let service = service.clone();
+            make_service_fn(move |_| {
+                let service = service.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        Self::handle_request(service.clone(), request)
+                    }))
+                }
+            })
+        };
+        info!("Starting admin API on {}", bind);
+        let handle = tokio::spawn(async move {
+            if let Err(e) = server.serve(make_service).await {
+                error!("Error in admin API: {:?}", e);
+            }
+        });
+        Ok(AdminAPIService {
+            inner: service,
+            handle,
+        })
+    }
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+
+    async fn handle_request(
+        service: AdminAPIServiceAccess<DirectoryAccess, StorageAccess>,
+        request: Request<Body>,
+    ) -> Result<Response<Body>, Infallible> {
+        let (route, parameters) = match find_route(request.method(), request.uri().path()) {
+            Ok(found) => found,
+            Err(status) => return Ok(Self::status(status)),
+        };
+        if let Some(required) = route.scope {
+            let token =
+                bearer_token(request.headers()).and_then(|token| service.config.find_token(token));
+            let Some(token) = token else {
+                return Ok(Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(WWW_AUTHENTICATE, "Bearer realm=\"nitro_mail admin\"")
+                    .body(Body::empty())
+                    .unwrap());
+            };
+            if !token.scope.allows(required) {
+                return Ok(Self::status(StatusCode::FORBIDDEN));
+            }
+            if required == TokenScope::ReadWrite {
+                info!(
+                    "Admin API {} {} by {}",
+                    request.method(),
+                    request.uri().path(),
+                    token.name
+                );
+            }
+        }
+
+        let response = match Self::handle_operation(&service, route, parameters, request).await {
+            Ok(Some(value)) => Self::json(route.status, "application/json", &value),
+            Ok(None) => Self::status(route.status),
+            Err(error) => {
+                let status = error.status();
+                if status.is_server_error() {
+                    error!("Admin API {:?} failed: {}", route.operation, error);
+                }
+                let problem = json!({
+                    "status": status.as_u16(),
+                    "detail": error.to_string(),
+                });
+                Self::json(status, "application/problem+json", &problem)
+            }
+        };
+        Ok(response)
+    }
+
+    /// The JSON of a successful response. None if it has no body
+    async fn handle_operation(
+        service: &AdminAPIServiceAccess<DirectoryAccess, StorageAccess>,
+        route: &Route,
+        parameters: Vec<String>,
+        request: Request<Body>,
+    ) -> Result<Option<Value>, RequestError> {
+        match route.operation {
+            Operation::OpenAPI => return Ok(Some(openapi_document())),
+            Operation::Health => {
+                let services = vec![
+                    directory_health(
+                        &service.directory_service_access,
+                        service.validate_directory_request.clone(),
+                    )
+                    .await,
+                    storage_health(&service.storage_service_access).await,
+                ];
+                return Self::to_json(Health {
+                    healthy: services.iter().all(|service| service.healthy),
+                    admin_api: ServiceVersion::admin_api(),
+                    services,
+                });
+            }
+            Operation::Version => return Self::to_json(ServiceVersion::admin_api()),
+            _ => {}
+        }
+
+        let directory = service
+            .directory_service_access
+            .get_service()
+            .await
+            .map_err(|e| RequestError::DirectoryUnavailable(Box::new(e)))?;
+        let mut parameters = parameters.into_iter();
+        let mut parameter = || parameters.next().unwrap_or_default();
+        match route.operation {
+            Operation::Capabilities => {
+                let capabilities = directory
+                    .directory_capabilities()
+                    .await
+                    .map_err(RequestError::directory)?;
+                Self::to_json(capabilities)
+            }
+            Operation::ListAccounts => {
+                let accounts = directory
+                    .list_accounts()
+                    .await
+                    .map_err(RequestError::directory)??;
+                Self::to_json(accounts)
+            }
+            Operation::CreateAccount => {
+                let account: NewAccount = Self::body(request).await?;
+                let account = directory
+                    .create_account(account)
+                    .await
+                    .map_err(RequestError::directory)??;
+                Self::to_json(account)
+            }
+            Operation::GetAccount => {
+                let username = parameter();
+                let account = directory
+                    .get_account(username.clone())
+                    .await
+                    .map_err(RequestError::directory)?
+                    .ok_or(AdminError::AccountNotFound(username))?;
+                Self::to_json(account)
+            }
+            Operation::UpdateAccount => {
+                let update: AccountUpdate = Self::body(request).await?;
+                let account = directory
+                    .update_account(parameter(), update)
+                    .await
+                    .map_err(RequestError::directory)??;
+                Self::to_json(account)
+            }
+            Operation::RenameAccount => {
+                let RenameBody { username } = Self::body(request).await?;
+                let account = directory
+                    .rename_account(parameter(), username)
+                    .await
+                    .map_err(RequestError::directory)??;
+                Self::to_json(account)
+            }
+            Operation::DeleteAccount => {
+                let username = parameter();
+                let deleted = directory
+                    .delete_account(username.clone())
+                    .await
+                    .map_err(RequestError::directory)??;
+                if !deleted {
+                    return Err(AdminError::AccountNotFound(username).into());
+                }
+                Ok(None)
+            }
+            Operation::SetPassword => {
+                let PasswordBody { password } = Self::body(request).await?;
+                directory
+                    .set_password(parameter(), password)
+                    .await
+                    .map_err(RequestError::directory)??;
+                Ok(None)
+            }
+            Operation::SetActive => {
+                let ActiveBody { active } = Self::body(request).await?;
+                directory
+                    .set_account_active(parameter(), active)
+                    .await
+                    .map_err(RequestError::directory)??;
+                Ok(None)
+            }
+            Operation::AddAddress => {
+                let address: AccountAddress = Self::body(request).await?;
+                directory
+                    .add_email_address(parameter(), address.email_address, address.email_type)
+                    .await
+                    .map_err(RequestError::directory)??;
+                Ok(None)
+            }
+            Operation::RemoveAddress => {
+                let username = parameter();
+                let address = parameter();
+                // The directory removes the address from whichever account has it
+                let account = directory
+                    .get_account(username.clone())
+                    .await
+                    .map_err(RequestError::directory)?
+                    .ok_or_else(|| AdminError::AccountNotFound(username.clone()))?;
+                let normalized = EmailAddress::new_lenient(address.as_str())
+                    .map_err(|_| AdminError::InvalidEmailAddress(address.clone()))?;
+                let owned = account.addresses.iter().any(|account_address| {
+                    EmailAddress::new_lenient(account_address.email_address.as_str())
+                        .is_ok_and(|account_address| account_address == normalized)
+                });
+                if !owned {
+                    return Err(RequestError::NotFound(format!(
+                        "{username} does not have the address {address}"
+                    )));
+                }
+                directory
+                    .remove_email_address(address)
+                    .await
+                    .map_err(RequestError::directory)??;
+                Ok(None)
+            }
+            Operation::ListGroups => {
+                let groups = directory
+                    .get_groups()
+                    .await
+                    .map_err(RequestError::directory)?;
+                Self::to_json(groups)
+            }
+            Operation::CreateGroup => {
+                let group: NewGroup = Self::body(request).await?;
+                directory
+                    .create_group(group)
+                    .await
+                    .map_err(RequestError::directory)??;
+                Ok(None)
+            }
+            Operation::DeleteGroup => {
+                let group = parameter();
+                let deleted = directory
+                    .delete_group(group.clone())
+                    .await
+                    .map_err(RequestError::directory)??;
+                if !deleted {
+                    return Err(AdminError::GroupNotFound(group).into());
+                }
+                Ok(None)
+            }
+            Operation::ListGroupMembers => {
+                let members = directory
+                    .list_group_members(parameter())
+                    .await
+                    .map_err(RequestError::directory)??;
+                Self::to_json(members)
+            }
+            Operation::AddGroupMember => {
+                directory
+                    .add_group_member(parameter(), parameter())
+                    .await
+                    .map_err(RequestError::directory)??;
+                Ok(None)
+            }
+            Operation::RemoveGroupMember => {
+                let group = parameter();
+                let username = parameter();
+                let removed = directory
+                    .remove_group_member(group.clone(), username.clone())
+                    .await
+                    .map_err(RequestError::directory)??;
+                if !removed {
+                    return Err(RequestError::NotFound(format!(
+                        "{username} is not a member of {group}"
+                    )));
+                }
+                Ok(None)
+            }
+            Operation::OpenAPI | Operation::Health | Operation::Version => {
+                unreachable!("Handled above")
+            }
+        }
+    }
+
+    async fn body<T: DeserializeOwned>(request: Request<Body>) -> Result<T, RequestError> {
+        let mut body = request.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+                return Err(RequestError::BodyTooLarge);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        serde_json::from_slice(&bytes).map_err(|error| RequestError::InvalidBody(error.to_string()))
+    }
+    fn to_json(value: impl Serialize) -> Result<Option<Value>, RequestError> {
+        Ok(Some(serde_json::to_value(value)?))
+    }
+
+    fn json(status: StatusCode, content_type: &str, value: &impl Serialize) -> Response<Body> {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, content_type)
+                .body(Body::from(body))
+                .unwrap(),
+            Err(error) => {
+                error!("Failed to serialize admin API response: {}", error);
+                Self::status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+    fn status(status: StatusCode) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,116 @@
+use serde::Serialize;
+
+use directories::directory_type::Directory;
+use directories::ValidateDirectoryRequest;
+use storages::storage_type::Storage;
+use utils::service::ServiceAccess;
+use utils::service_configuration::{GitInfo, ServiceConfigurationResponse, ServiceType};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceVersion {
+    pub name: String,
+    pub version: String,
+    pub git: GitInfo,
+}
+impl ServiceVersion {
+    pub fn admin_api() -> Self {
+        ServiceVersion {
+            name: "admin_api".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git: GitInfo {
+                commit: env!("VERGEN_GIT_SHA").to_string(),
+                branch: env!("VERGEN_GIT_BRANCH").to_string(),
+                commit_date: env!("VERGEN_GIT_COMMIT_DATE").to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceHealth {
+    pub service_type: ServiceType,
+    pub healthy: bool,
+    /// The internal service name. For example the directory backend
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+impl ServiceHealth {
+    fn unhealthy(service_type: ServiceType, error: impl ToString) -> Self {
+        ServiceHealth {
+            service_type,
+            healthy: false,
+            name: None,
+            version: None,
+            git: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    /// True if every service is healthy
+    pub healthy: bool,
+    pub admin_api: ServiceVersion,
+    pub services: Vec<ServiceHealth>,
+}
+
+/// Validating the config is the only request every directory answers with its version
+pub async fn directory_health<DirectoryAccess>(
+    access: &DirectoryAccess,
+    request: ValidateDirectoryRequest,
+) -> ServiceHealth
+where
+    DirectoryAccess: ServiceAccess,
+    DirectoryAccess::ServiceResponse: Directory,
+{
+    let directory = match access.get_service().await {
+        Ok(directory) => directory,
+        Err(error) => return ServiceHealth::unhealthy(ServiceType::Directory, error),
+    };
+    match directory.validate_config(request).await {
+        Ok(ServiceConfigurationResponse::Success {
+            internal_service_name,
+            git,
+            service_type,
+            version,
+            ..
+        }) => ServiceHealth {
+            service_type,
+            healthy: true,
+            name: Some(internal_service_name),
+            version: Some(version),
+            git: Some(git),
+            error: None,
+        },
+        Ok(ServiceConfigurationResponse::NamespaceMismatch) => {
+            ServiceHealth::unhealthy(ServiceType::Directory, "Namespace mismatch")
+        }
+        Err(error) => ServiceHealth::unhealthy(ServiceType::Directory, error),
+    }
+}
+
+/// Storage does not report a version yet. So only the connection is checked
+pub async fn storage_health<StorageAccess>(access: &StorageAccess) -> ServiceHealth
+where
+    StorageAccess: ServiceAccess,
+    StorageAccess::ServiceResponse: Storage,
+{
+    match access.get_service().await {
+        Ok(_) => ServiceHealth {
+            service_type: ServiceType::Storage,
+            healthy: true,
+            name: Some(StorageAccess::ServiceResponse::storage_name().to_string()),
+            version: None,
+            git: None,
+            error: None,
+        },
+        Err(error) => ServiceHealth::unhealthy(ServiceType::Storage, error),
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use directories::directory_type::Directory;
+use directories::ValidateDirectoryRequest;
+use storages::storage_type::Storage;
+use utils::service::ServiceAccess;
+
+use crate::admin_api_service::{AdminAPIService, AdminAPIServiceError};
+
+pub mod admin_api_config;
+pub mod admin_api_service;
+pub mod authentication;
+pub mod health;
+pub mod openapi;
+pub mod routes;
+
+/// Starts the HTTP admin API
+///
+/// It manages the directory through the [Directory] administration methods and reports the health of the services.
+/// Requests authenticate with a Bearer token from `admin_api.toml`. Read only tokens can only make GET requests.
+/// The OpenAPI document is served at `/api/openapi.json`
+pub fn start_admin_api_service<DirectoryAccess, StorageAccess>(
+    working_directory: PathBuf,
+    validate_directory_request: ValidateDirectoryRequest,
+    directory_service_access: DirectoryAccess,
+    storage_service_access: StorageAccess,
+) -> Result<AdminAPIService<DirectoryAccess, StorageAccess>, AdminAPIServiceError>
+where
+    DirectoryAccess: ServiceAccess,
+    DirectoryAccess::ServiceResponse: Directory,
+    StorageAccess: ServiceAccess,
+    StorageAccess::ServiceResponse: Storage,
+{
+    AdminAPIService::start(
+        working_directory,
+        validate_directory_request,
+        directory_service_access,
+        storage_service_access,
+    )
+}
@@ -0,0 +1,320 @@
+//! The [OpenAPI 3.0](https://spec.openapis.org/oas/v3.0.3) document of the admin API
+use serde_json::{json, Map, Value};
+use strum::IntoEnumIterator;
+
+use utils::common_types::{AccountType, EmailType};
+use utils::groups::{GroupType, PostingPolicy};
+
+use crate::admin_api_config::TokenScope;
+use crate::routes::{path_parameters, Content, Route, ROUTES};
+
+const SECURITY_SCHEME: &str = "token";
+
+fn reference(schema: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{schema}") })
+}
+fn content_schema(content: Content) -> Option<Value> {
+    match content {
+        Content::None => None,
+        Content::Object(schema) => Some(reference(schema)),
+        Content::List(schema) => Some(json!({ "type": "array", "items": reference(schema) })),
+    }
+}
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+/// The variants as serde names them
+fn string_enum<E: IntoEnumIterator + AsRef<str>>() -> Value {
+    let values: Vec<String> = E::iter().map(|value| value.as_ref().to_string()).collect();
+    json!({ "type": "string", "enum": values })
+}
+
+fn operation(route: &Route) -> Value {
+    let operation_id: &'static str = route.operation.into();
+    let mut operation = json!({
+        "operationId": operation_id,
+        "summary": route.summary,
+        "tags": [route.tag],
+    });
+    let parameters: Vec<Value> = path_parameters(route.path)
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            })
+        })
+        .collect();
+    if !parameters.is_empty() {
+        operation["parameters"] = parameters.into();
+    }
+    if let Some(schema) = content_schema(route.request) {
+        operation["requestBody"] = json!({ "required": true, "content": json_content(schema) });
+    }
+
+    let mut responses = Map::new();
+    let mut success =
+        json!({ "description": route.status.canonical_reason().unwrap_or("Success") });
+    if let Some(schema) = content_schema(route.response) {
+        success["content"] = json_content(schema);
+    }
+    responses.insert(route.status.as_u16().to_string(), success);
+    match route.scope {
+        None => {
+            operation["security"] = json!([]);
+        }
+        Some(scope) => {
+            responses.insert(
+                "401".to_string(),
+                json!({ "description": "The token is missing or unknown" }),
+            );
+            if scope == TokenScope::ReadWrite {
+                responses.insert(
+                    "403".to_string(),
+                    json!({ "description": "The token is read only" }),
+                );
+            }
+        }
+    }
+    responses.insert(
+        "default".to_string(),
+        json!({
+            "description": "An error",
+            "content": { "application/problem+json": { "schema": reference("Problem") } },
+        }),
+    );
+    operation["responses"] = responses.into();
+    operation
+}
+
+fn schemas() -> Value {
+    json!({
+        "Problem": {
+            "description": "RFC 7807 problem details",
+            "type": "object",
+            "required": ["status", "detail"],
+            "properties": {
+                "status": { "type": "integer" },
+                "detail": { "type": "string" },
+            },
+        },
+        "ServiceVersion": {
+            "type": "object",
+            "required": ["name", "version", "git"],
+            "properties": {
+                "name": { "type": "string" },
+                "version": { "type": "string" },
+                "git": reference("GitInfo"),
+            },
+        },
+        "GitInfo": {
+            "type": "object",
+            "required": ["commit", "branch", "commit_date"],
+            "properties": {
+                "commit": { "type": "string" },
+                "branch": { "type": "string" },
+                "commit_date": { "type": "string" },
+            },
+        },
+        "ServiceType": { "type": "string", "enum": ["Directory", "Storage"] },
+        "ServiceHealth": {
+            "type": "object",
+            "required": ["service_type", "healthy"],
+            "properties": {
+                "service_type": reference("ServiceType"),
+                "healthy": { "type": "boolean" },
+                "name": { "type": "string" },
+                "version": { "type": "string" },
+                "git": reference("GitInfo"),
+                "error": { "type": "string" },
+            },
+        },
+        "Health": {
+            "type": "object",
+            "required": ["healthy", "admin_api", "services"],
+            "properties": {
+                "healthy": { "type": "boolean", "description": "True if every service is healthy" },
+                "admin_api": reference("ServiceVersion"),
+                "services": { "type": "array", "items": reference("ServiceHealth") },
+            },
+        },
+        "DirectoryCapabilities": {
+            "type": "object",
+            "required": ["accounts", "passwords", "addresses", "groups"],
+            "properties": {
+                "accounts": { "type": "boolean" },
+                "passwords": { "type": "boolean" },
+                "addresses": { "type": "boolean" },
+                "groups": { "type": "boolean" },
+            },
+        },
+        "AccountType": string_enum::<AccountType>(),
+        "EmailType": string_enum::<EmailType>(),
+        "GroupType": string_enum::<GroupType>(),
+        "PostingPolicy": string_enum::<PostingPolicy>(),
+        "Account": {
+            "type": "object",
+            "required": [
+                "username", "name", "account_type", "mailbox_id", "active", "quota", "addresses",
+                "groups",
+            ],
+            "properties": {
+                "username": { "type": "string" },
+                "name": { "type": "string" },
+                "description": { "type": "string", "nullable": true },
+                "account_type": reference("AccountType"),
+                "mailbox_id": {
+                    "type": "string",
+                    "format": "uuid",
+                    "description": "Never changes, even if the account is renamed",
+                },
+                "active": { "type": "boolean" },
+                "quota": reference("Quota"),
+                "addresses": { "type": "array", "items": reference("AccountAddress") },
+                "groups": { "type": "array", "items": reference("GroupName") },
+            },
+        },
+        "Quota": {
+            "description": "0 for no limit",
+            "type": "object",
+            "required": ["storage", "messages"],
+            "properties": {
+                "storage": { "type": "integer", "description": "Bytes" },
+                "messages": { "type": "integer" },
+            },
+        },
+        "AccountAddress": {
+            "type": "object",
+            "required": ["email_address", "email_type"],
+            "properties": {
+                "email_address": { "type": "string" },
+                "email_type": reference("EmailType"),
+            },
+        },
+        "NewAccount": {
+            "type": "object",
+            "required": ["username", "password"],
+            "properties": {
+                "username": { "type": "string" },
+                "name": { "type": "string", "description": "The username if not set" },
+                "description": { "type": "string" },
+                "password": { "type": "string" },
+                "account_type": reference("AccountType"),
+                "primary_address": { "type": "string" },
+            },
+        },
+        "AccountUpdate": {
+            "description": "Fields that are not set are left unchanged",
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "description": { "type": "string" },
+                "account_type": reference("AccountType"),
+            },
+        },
+        "Rename": {
+            "type": "object",
+            "required": ["username"],
+            "properties": { "username": { "type": "string", "description": "The new username" } },
+        },
+        "Password": {
+            "type": "object",
+            "required": ["password"],
+            "properties": { "password": { "type": "string" } },
+        },
+        "Active": {
+            "type": "object",
+            "required": ["active"],
+            "properties": { "active": { "type": "boolean" } },
+        },
+        "NewGroup": {
+            "type": "object",
+            "required": ["name"],
+            "properties": {
+                "name": { "type": "string" },
+                "group_type": reference("GroupType"),
+                "posting_policy": reference("PostingPolicy"),
+                "list_address": { "type": "string", "description": "Where mail for a List is sent" },
+            },
+        },
+        "GroupName": { "type": "string" },
+        "Username": { "type": "string" },
+    })
+}
+
+/// Built from [ROUTES], so it always matches what the server accepts
+pub fn openapi_document() -> Value {
+    let mut paths = Map::new();
+    for route in ROUTES {
+        let path = paths
+            .entry(route.path)
+            .or_insert_with(|| Value::Object(Map::new()));
+        path[route.method.as_str().to_lowercase()] = operation(route);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "nitro_mail admin API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "security": [{ SECURITY_SCHEME: [] }],
+        "components": {
+            "securitySchemes": {
+                SECURITY_SCHEME: {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "A token from admin_api.toml",
+                },
+            },
+            "schemas": schemas(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::openapi::openapi_document;
+    use crate::routes::{Content, ROUTES};
+
+    /// Every schema an operation references must exist
+    #[test]
+    pub fn test_openapi_document() {
+        let document = openapi_document();
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        for route in ROUTES {
+            let operation = &document["paths"][route.path][route.method.as_str().to_lowercase()];
+            assert!(operation.is_object(), "{} {}", route.method, route.path);
+            for content in [route.request, route.response] {
+                if let Content::Object(schema) | Content::List(schema) = content {
+                    assert!(schemas.contains_key(schema), "{schema}");
+                }
+            }
+        }
+        fn references(value: &Value, found: &mut Vec<String>) {
+            match value {
+                Value::Object(object) => {
+                    if let Some(Value::String(reference)) = object.get("$ref") {
+                        found.push(reference.clone());
+                    }
+                    object.values().for_each(|value| references(value, found));
+                }
+                Value::Array(array) => array.iter().for_each(|value| references(value, found)),
+                _ => {}
+            }
+        }
+        let mut found = Vec::new();
+        references(&document, &mut found);
+        for reference in found {
+            let schema = reference.strip_prefix("#/components/schemas/").unwrap();
+            assert!(schemas.contains_key(schema), "{reference}");
+        }
+        assert_eq!(
+            document["components"]["schemas"]["AccountType"]["enum"][0],
+            "Individual"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Every endpoint of the admin API. The router and the OpenAPI document are both built from [ROUTES]
+use hyper::{Method, StatusCode};
+use percent_encoding::percent_decode_str;
+use strum::IntoStaticStr;
+
+use crate::admin_api_config::TokenScope;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr)]
+#[strum(serialize_all = "camelCase")]
+pub enum Operation {
+    OpenAPI,
+    Health,
+    Version,
+    Capabilities,
+    ListAccounts,
+    CreateAccount,
+    GetAccount,
+    UpdateAccount,
+    RenameAccount,
+    DeleteAccount,
+    SetPassword,
+    SetActive,
+    AddAddress,
+    RemoveAddress,
+    ListGroups,
+    CreateGroup,
+    DeleteGroup,
+    ListGroupMembers,
+    AddGroupMember,
+    RemoveGroupMember,
+}
+
+/// The JSON of a request or response body. The names are schemas in the OpenAPI document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Content {
+    None,
+    Object(&'static str),
+    List(&'static str),
+}
+
+#[derive(Debug)]
+pub struct Route {
+    pub method: Method,
+    /// Parameters are written as `{name}`
+    pub path: &'static str,
+    pub operation: Operation,
+    /// None if no token is needed
+    pub scope: Option<TokenScope>,
+    pub tag: &'static str,
+    pub summary: &'static str,
+    pub request: Content,
+    /// The status of a successful response
+    pub status: StatusCode,
+    pub response: Content,
+}
+
+macro_rules! route {
+    (
+        $method:ident $path:literal => $operation:ident,
+        $scope:expr, $tag:literal, $summary:literal,
+        $request:expr => $status:ident $response:expr
+    ) => {
+        Route {
+            method: Method::$method,
+            path: $path,
+            operation: Operation::$operation,
+            scope: $scope,
+            tag: $tag,
+            summary: $summary,
+            request: $request,
+            status: StatusCode::$status,
+            response: $response,
+        }
+    };
+}
+const READ: Option<TokenScope> = Some(TokenScope::ReadOnly);
+const WRITE: Option<TokenScope> = Some(TokenScope::ReadWrite);
+
+pub static ROUTES: &[Route] = &[
+    route!(GET "/api/openapi.json" => OpenAPI, None, "Service", "This document",
+        Content::None => OK Content::None),
+    route!(GET "/api/health" => Health, READ, "Service",
+        "The status, version and git commit of every service",
+        Content::None => OK Content::Object("Health")),
+    route!(GET "/api/version" => Version, READ, "Service", "The version of the admin API",
+        Content::None => OK Content::Object("ServiceVersion")),
+    route!(GET "/api/capabilities" => Capabilities, READ, "Service",
+        "The changes the directory supports",
+        Content::None => OK Content::Object("DirectoryCapabilities")),
+    route!(GET "/api/accounts" => ListAccounts, READ, "Accounts", "Lists every account",
+        Content::None => OK Content::List("Account")),
+    route!(POST "/api/accounts" => CreateAccount, WRITE, "Accounts", "Creates an account",
+        Content::Object("NewAccount") => CREATED Content::Object("Account")),
+    route!(GET "/api/accounts/{username}" => GetAccount, READ, "Accounts",
+        "An account with its addresses and groups",
+        Content::None => OK Content::Object("Account")),
+    route!(PATCH "/api/accounts/{username}" => UpdateAccount, WRITE, "Accounts",
+        "Changes the name, description or type of an account",
+        Content::Object("AccountUpdate") => OK Content::Object("Account")),
+    route!(POST "/api/accounts/{username}/rename" => RenameAccount, WRITE, "Accounts",
+        "Changes the username. The account keeps its mail, addresses and groups",
+        Content::Object("Rename") => OK Content::Object("Account")),
+    route!(DELETE "/api/accounts/{username}" => DeleteAccount, WRITE, "Accounts",
+        "Deletes an account with its addresses, app passwords and group memberships",
+        Content::None => NO_CONTENT Content::None),
+    route!(PUT "/api/accounts/{username}/password" => SetPassword, WRITE, "Accounts",
+        "Sets the password of an account",
+        Content::Object("Password") => NO_CONTENT Content::None),
+    route!(PUT "/api/accounts/{username}/active" => SetActive, WRITE, "Accounts",
+        "Inactive accounts can not log in or receive mail",
+        Content::Object("Active") => NO_CONTENT Content::None),
+    route!(POST "/api/accounts/{username}/addresses" => AddAddress, WRITE, "Accounts",
+        "Adds an email address. A new primary address turns the current one into an alias",
+        Content::Object("AccountAddress") => NO_CONTENT Content::None),
+    route!(DELETE "/api/accounts/{username}/addresses/{address}" => RemoveAddress, WRITE,
+        "Accounts", "Removes an email address",
+        Content::None => NO_CONTENT Content::None),
+    route!(GET "/api/groups" => ListGroups, READ, "Groups", "The names of every group",
+        Content::None => OK Content::List("GroupName")),
+    route!(POST "/api/groups" => CreateGroup, WRITE, "Groups", "Creates a group or mailing list",
+        Content::Object("NewGroup") => CREATED Content::None),
+    route!(DELETE "/api/groups/{group}" => DeleteGroup, WRITE, "Groups", "Deletes a group",
+        Content::None => NO_CONTENT Content::None),
+    route!(GET "/api/groups/{group}/members" => ListGroupMembers, READ, "Groups",
+        "The usernames of the members",
+        Content::None => OK Content::List("Username")),
+    route!(PUT "/api/groups/{group}/members/{username}" => AddGroupMember, WRITE, "Groups",
+        "Adds an account to a group",
+        Content::None => NO_CONTENT Content::None),
+    route!(DELETE "/api/groups/{group}/members/{username}" => RemoveGroupMember, WRITE,
+        "Groups", "Removes an account from a group",
+        Content::None => NO_CONTENT Content::None),
+];
+
+/// The names of the parameters of a path
+pub fn path_parameters(path: &'static str) -> impl Iterator<Item = &'static str> {
+    path.split('/').filter_map(|segment| {
+        segment
+            .strip_prefix('{')
+            .and_then(|segment| segment.strip_suffix('}'))
+    })
+}
+
+/// The percent decoded parameters if the path matches the template
+fn match_path(template: &str, path: &str) -> Option<Vec<String>> {
+    let mut template = template.split('/');
+    let mut path = path.split('/');
+    let mut parameters = Vec::new();
+    loop {
+        match (template.next(), path.next()) {
+            (None, None) => return Some(parameters),
+            (Some(expected), Some(segment)) => {
+                if expected.starts_with('{') {
+                    if segment.is_empty() {
+                        return None;
+                    }
+                    parameters.push(percent_decode_str(segment).decode_utf8().ok()?.into_owned());
+                } else if expected != segment {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// The route and its parameters.
+/// Fails with 405 Method Not Allowed if only the method is wrong. Otherwise 404 Not Found
+pub fn find_route(
+    method: &Method,
+    path: &str,
+) -> Result<(&'static Route, Vec<String>), StatusCode> {
+    let mut status = StatusCode::NOT_FOUND;
+    for route in ROUTES {
+        if let Some(parameters) = match_path(route.path, path) {
+            if route.method == method {
+                return Ok((route, parameters));
+            }
+            status = StatusCode::METHOD_NOT_ALLOWED;
+        }
+    }
+    Err(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, StatusCode};
+
+    use crate::routes::{find_route, path_parameters, Operation, ROUTES};
+
+    #[test]
+    pub fn test_find_route() {
+        let (route, parameters) = find_route(&Method::GET, "/api/accounts").unwrap();
+        assert_eq!(route.operation, Operation::ListAccounts);
+        assert!(parameters.is_empty());
+
+        let (route, parameters) = find_route(
+            &Method::DELETE,
+            "/api/accounts/jane/addresses/jane%40example.com",
+        )
+        .unwrap();
+        assert_eq!(route.operation, Operation::RemoveAddress);
+        assert_eq!(parameters, vec!["jane", "jane@example.com"]);
+
+        assert_eq!(
+            find_route(&Method::PUT, "/api/accounts").unwrap_err(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            find_route(&Method::GET, "/api/accounts/").unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            find_route(&Method::GET, "/api/accounts/jane/unknown").unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    pub fn test_routes_are_unique() {
+        for (index, route) in ROUTES.iter().enumerate() {
+            let (found, _) = find_route(&route.method, route.path).unwrap();
+            assert!(
+                std::ptr::eq(found, &ROUTES[index]),
+                "{} {} is shadowed",
+                route.method,
+                route.path
+            );
+        }
+        assert_eq!(
+            path_parameters("/api/groups/{group}/members/{username}").collect::<Vec<_>>(),
+            vec!["group", "username"]
+        );
+    }
+}
@@ -0,0 +1,707 @@
+//! Caches directory lookups so every RCPT and login does not reach the directory service
+//!
+//! Logins are only cached when they succeed and the password is never kept.
+//! A login with the same username, password and protocol matches the salted hash of the one that succeeded.
+//! Failed logins always reach the directory so lockouts and password changes apply at once
+use std::future::Future;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::Duration as ChronoDuration;
+use lru::LruCache;
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use utils::account::Account;
+use utils::admin::{AccountUpdate, AdminResult, DirectoryCapabilities, NewAccount, NewGroup};
+use utils::app_password::{AppPassword, LoginProtocol, NewAppPassword};
+use utils::auth_failures::{Lockout, LockoutKey};
+use utils::common_types::EmailType;
+use utils::configs::{Config, ConfigDuration, ConfigName};
+use utils::groups::MailingList;
+use utils::quota::Quota;
+use utils::service::{Service, ServiceAccess};
+use utils::service_configuration::ServiceConfigurationResponse;
+use utils::two_factor::TotpEnrollment;
+use uuid::Uuid;
+
+use crate::directory_type::Directory;
+use crate::ValidateDirectoryRequest;
+
+fn default_positive_ttl() -> ConfigDuration {
+    ChronoDuration::minutes(1).into()
+}
+fn default_negative_ttl() -> ConfigDuration {
+    ChronoDuration::seconds(10).into()
+}
+fn default_login_ttl() -> ConfigDuration {
+    ChronoDuration::seconds(30).into()
+}
+fn default_max_entries() -> usize {
+    10_000
+}
+
+/// A TTL of `0s` turns that part of the cache off
+///
+/// # Example
+/// ```toml
+/// positive_ttl = "1m"
+/// negative_ttl = "10s"
+/// login_ttl = "30s"
+/// max_entries = 10000
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// How long accounts and mailing lists that were found are kept
+    #[serde(default = "default_positive_ttl")]
+    pub positive_ttl: ConfigDuration,
+    /// How long lookups that found nothing are kept
+    #[serde(default = "default_negative_ttl")]
+    pub negative_ttl: ConfigDuration,
+    /// How long a successful login is kept. A changed password keeps working this long
+    #[serde(default = "default_login_ttl")]
+    pub login_ttl: ConfigDuration,
+    /// The most entries of each kind. Least recently used entries are dropped first
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+impl Config for CacheConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/directory_cache")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("directory_cache.toml")
+    }
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            positive_ttl: default_positive_ttl(),
+            negative_ttl: default_negative_ttl(),
+            login_ttl: default_login_ttl(),
+            max_entries: default_max_entries(),
+        }
+    }
+}
+
+struct TtlCache<K: Hash + Eq, V: Clone> {
+    entries: Mutex<LruCache<K, (V, Instant)>>,
+    enabled: bool,
+}
+impl<K: Hash + Eq + Clone, V: Clone> TtlCache<K, V> {
+    fn new(max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(
+                NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN),
+            )),
+            enabled: max_entries > 0,
+        }
+    }
+    fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock();
+        match entries.get(key) {
+            Some((value, expires)) if *expires > Instant::now() => Some(value.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+    fn insert(&self, key: K, value: V, ttl: Duration) {
+        if self.enabled && !ttl.is_zero() {
+            self.entries.lock().put(key, (value, Instant::now() + ttl));
+        }
+    }
+    fn remove(&self, key: &K) {
+        self.entries.lock().pop(key);
+    }
+    fn remove_where(&self, mut matches: impl FnMut(&K, &V) -> bool) {
+        let mut entries = self.entries.lock();
+        let keys: Vec<K> = entries
+            .iter()
+            .filter(|(key, (value, _))| matches(key, value))
+            .map(|(key, _)| key)
+            .cloned()
+            .collect();
+        for key in keys {
+            entries.pop(&key);
+        }
+    }
+    fn clear(&self) {
+        self.entries.lock().clear();
+    }
+}
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+struct LoginKey {
+    username: String,
+    protocol: LoginProtocol,
+    /// Salted with [DirectoryCache::salt]
+    password_hash: [u8; 32],
+}
+
+/// The cache shared by every [CachingDirectory] created from the same [CachingDirectoryAccess]
+pub struct DirectoryCache {
+    config: CacheConfig,
+    /// Random for every cache so the hashes are useless outside this process
+    salt: [u8; 32],
+    accounts: TtlCache<String, Option<Account>>,
+    emails: TtlCache<String, Option<Account>>,
+    mailing_lists: TtlCache<String, Option<MailingList>>,
+    quotas: TtlCache<Uuid, Option<Quota>>,
+    logins: TtlCache<LoginKey, Account>,
+}
+impl DirectoryCache {
+    pub fn new(config: CacheConfig) -> Self {
+        let mut salt = [0; 32];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self {
+            accounts: TtlCache::new(config.max_entries),
+            emails: TtlCache::new(config.max_entries),
+            mailing_lists: TtlCache::new(config.max_entries),
+            quotas: TtlCache::new(config.max_entries),
+            logins: TtlCache::new(config.max_entries),
+            salt,
+            config,
+        }
+    }
+    fn ttl(duration: &ConfigDuration) -> Duration {
+        duration.to_std().unwrap_or_default()
+    }
+    fn lookup_ttl<T>(&self, value: &Option<T>) -> Duration {
+        match value {
+            Some(_) => Self::ttl(&self.config.positive_ttl),
+            None => Self::ttl(&self.config.negative_ttl),
+        }
+    }
+    fn login_key(&self, username: &str, password: &str, protocol: LoginProtocol) -> LoginKey {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt);
+        hasher.update((username.len() as u64).to_be_bytes());
+        hasher.update(username.as_bytes());
+        hasher.update(password.as_bytes());
+        LoginKey {
+            username: username.to_string(),
+            protocol,
+            password_hash: hasher.finalize().into(),
+        }
+    }
+
+    /// Forgets the account, its addresses and its logins
+    pub fn invalidate_account(&self, username: &str) {
+        self.accounts.remove(&username.to_string());
+        self.emails.remove_where(|_, account| {
+            account
+                .as_ref()
+                .is_some_and(|account| account.username == username)
+        });
+        self.invalidate_logins(username);
+    }
+    /// Forgets the account or mailing list that receives mail for the address
+    pub fn invalidate_address(&self, email_address: &str) {
+        self.emails.remove(&email_address.to_string());
+        self.mailing_lists.remove(&email_address.to_string());
+    }
+    /// Forgets the accounts that list the address
+    pub fn invalidate_address_owner(&self, email_address: &str) {
+        self.invalidate_accounts_where(|account| {
+            account
+                .addresses
+                .iter()
+                .any(|address| address.email_address == email_address)
+        });
+    }
+    /// Forgets the accounts that list the group
+    pub fn invalidate_group_members(&self, group: &str) {
+        self.invalidate_accounts_where(|account| account.groups.iter().any(|name| name == group));
+    }
+    fn invalidate_accounts_where(&self, matches: impl Fn(&Account) -> bool) {
+        let mut usernames = Vec::new();
+        let mut collect = |_: &String, account: &Option<Account>| {
+            if let Some(account) = account.as_ref().filter(|account| matches(account)) {
+                usernames.push(account.username.clone());
+            }
+            false
+        };
+        self.accounts.remove_where(&mut collect);
+        self.emails.remove_where(&mut collect);
+        for username in usernames {
+            self.invalidate_account(&username);
+        }
+    }
+    pub fn invalidate_logins(&self, username: &str) {
+        self.logins.remove_where(|key, _| key.username == username);
+    }
+    pub fn invalidate_mailing_lists(&self) {
+        self.mailing_lists.clear();
+    }
+    pub fn invalidate_quota(&self, mailbox_id: Uuid) {
+        self.quotas.remove(&mailbox_id);
+    }
+    pub fn invalidate_all(&self) {
+        self.accounts.clear();
+        self.emails.clear();
+        self.mailing_lists.clear();
+        self.quotas.clear();
+        self.logins.clear();
+    }
+}
+
+/// Caches the lookups of the wrapped [Directory]
+///
+/// Changes made through the wrapper, such as new app passwords, TOTP or administration, invalidate what they affect.
+/// Changes made anywhere else are seen once the entries expire or are [invalidated](DirectoryCache::invalidate_account)
+#[derive(Clone)]
+pub struct CachingDirectory<D: Directory> {
+    directory: D,
+    cache: Arc<DirectoryCache>,
+}
+impl<D: Directory> CachingDirectory<D> {
+    pub fn new(directory: D, cache: Arc<DirectoryCache>) -> Self {
+        Self { directory, cache }
+    }
+    pub fn cache(&self) -> &Arc<DirectoryCache> {
+        &self.cache
+    }
+    pub fn inner(&self) -> &D {
+        &self.directory
+    }
+}
+
+/// Gives every service from the wrapped [ServiceAccess] the same cache
+///
+/// For [DirectoryServiceDirectoryAccess](crate::directory_service::directory_service_directory::DirectoryServiceDirectoryAccess)
+/// this caches on the client side of the connection
+#[derive(Clone)]
+pub struct CachingDirectoryAccess<A> {
+    access: A,
+    cache: Arc<DirectoryCache>,
+}
+impl<A> CachingDirectoryAccess<A>
+where
+    A: ServiceAccess,
+    A::ServiceResponse: Directory,
+{
+    pub fn new(access: A, config: CacheConfig) -> Self {
+        Self {
+            access,
+            cache: Arc::new(DirectoryCache::new(config)),
+        }
+    }
+    pub fn cache(&self) -> &Arc<DirectoryCache> {
+        &self.cache
+    }
+}
+impl<A> ServiceAccess for CachingDirectoryAccess<A>
+where
+    A: ServiceAccess,
+    A::ServiceResponse: Directory,
+{
+    type ServiceResponse = CachingDirectory<A::ServiceResponse>;
+    type Error = A::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::ServiceResponse, A::Error>> + Send>>;
+
+    fn get_service(&self) -> Self::Future {
+        let service = self.access.get_service();
+        let cache = self.cache.clone();
+        Box::pin(async move { Ok(CachingDirectory::new(service.await?, cache)) })
+    }
+}
+
+impl<D: Directory> Service for CachingDirectory<D> {
+    type ServiceConfig = (D::ServiceConfig, CacheConfig);
+    type ServiceError = D::ServiceError;
+}
+
+#[async_trait]
+impl<D: Directory> Directory for CachingDirectory<D> {
+    fn directory_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        D::directory_name()
+    }
+
+    async fn load(config: Self::ServiceConfig) -> Result<Self, Self::ServiceError>
+    where
+        Self: Sized,
+    {
+        let (directory_config, cache_config) = config;
+        Ok(Self::new(
+            D::load(directory_config).await?,
+            Arc::new(DirectoryCache::new(cache_config)),
+        ))
+    }
+
+    async fn get_account(&self, username: String) -> Result<Option<Account>, Self::ServiceError> {
+        if let Some(account) = self.cache.accounts.get(&username) {
+            return Ok(account);
+        }
+        let account = self.directory.get_account(username.clone()).await?;
+        let ttl = self.cache.lookup_ttl(&account);
+        self.cache.accounts.insert(username, account.clone(), ttl);
+        Ok(account)
+    }
+
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        if let Some(account) = self.cache.emails.get(&email_address) {
+            return Ok(account);
+        }
+        let account = self
+            .directory
+            .get_account_by_email(email_address.clone())
+            .await?;
+        let ttl = self.cache.lookup_ttl(&account);
+        self.cache
+            .emails
+            .insert(email_address, account.clone(), ttl);
+        Ok(account)
+    }
+
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, Self::ServiceError> {
+        if let Some(list) = self.cache.mailing_lists.get(&email_address) {
+            return Ok(list);
+        }
+        let list = self
+            .directory
+            .get_mailing_list(email_address.clone())
+            .await?;
+        let ttl = self.cache.lookup_ttl(&list);
+        self.cache
+            .mailing_lists
+            .insert(email_address, list.clone(), ttl);
+        Ok(list)
+    }
+
+    async fn get_quota(&self, mailbox_id: Uuid) -> Result<Option<Quota>, Self::ServiceError> {
+        if let Some(quota) = self.cache.quotas.get(&mailbox_id) {
+            return Ok(quota);
+        }
+        let quota = self.directory.get_quota(mailbox_id).await?;
+        let ttl = self.cache.lookup_ttl(&quota);
+        self.cache.quotas.insert(mailbox_id, quota, ttl);
+        Ok(quota)
+    }
+
+    async fn login_account(
+        &self,
+        username: String,
+        password: String,
+        protocol: LoginProtocol,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let key = self.cache.login_key(&username, &password, protocol);
+        if let Some(account) = self.cache.logins.get(&key) {
+            return Ok(Some(account));
+        }
+        let account = self
+            .directory
+            .login_account(username, password, protocol)
+            .await?;
+        if let Some(account) = &account {
+            let ttl = DirectoryCache::ttl(&self.cache.config.login_ttl);
+            self.cache.logins.insert(key, account.clone(), ttl);
+        }
+        Ok(account)
+    }
+
+    /// Never cached. Codes can only be used once
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        self.directory
+            .login_account_with_totp(username, password, code)
+            .await
+    }
+
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, Self::ServiceError> {
+        self.directory
+            .create_app_password(username, name, protocols)
+            .await
+    }
+
+    async fn list_app_passwords(
+        &self,
+        username: String,
+    ) -> Result<Vec<AppPassword>, Self::ServiceError> {
+        self.directory.list_app_passwords(username).await
+    }
+
+    async fn revoke_app_password(
+        &self,
+        username: String,
+        id: i64,
+    ) -> Result<bool, Self::ServiceError> {
+        let revoked = self
+            .directory
+            .revoke_app_password(username.clone(), id)
+            .await?;
+        self.cache.invalidate_logins(&username);
+        Ok(revoked)
+    }
+
+    async fn enroll_totp(
+        &self,
+        username: String,
+    ) -> Result<Option<TotpEnrollment>, Self::ServiceError> {
+        self.directory.enroll_totp(username).await
+    }
+
+    /// Finishing enrollment stops password logins, so the cached logins are dropped
+    async fn verify_totp(
+        &self,
+        username: String,
+        code: String,
+    ) -> Result<bool, Self::ServiceError> {
+        let verified = self.directory.verify_totp(username.clone(), code).await?;
+        self.cache.invalidate_logins(&username);
+        Ok(verified)
+    }
+
+    async fn disable_totp(&self, username: String) -> Result<bool, Self::ServiceError> {
+        let disabled = self.directory.disable_totp(username.clone()).await?;
+        self.cache.invalidate_logins(&username);
+        Ok(disabled)
+    }
+
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        self.directory.check_auth_lockout(remote, username).await
+    }
+
+    /// A lockout may deactivate the account, so it is forgotten
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        let lockout = self
+            .directory
+            .record_auth_failure(remote, username.clone())
+            .await?;
+        if let (Some(_), Some(username)) = (&lockout, &username) {
+            self.cache.invalidate_account(username);
+        }
+        Ok(lockout)
+    }
+
+    async fn record_auth_success(&self, username: String) -> Result<bool, Self::ServiceError> {
+        self.directory.record_auth_success(username).await
+    }
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, Self::ServiceError> {
+        self.directory.list_lockouts().await
+    }
+
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, Self::ServiceError> {
+        self.directory.clear_lockout(key).await
+    }
+
+    async fn directory_capabilities(&self) -> Result<DirectoryCapabilities, Self::ServiceError> {
+        self.directory.directory_capabilities().await
+    }
+
+    async fn list_accounts(&self) -> Result<AdminResult<Vec<Account>>, Self::ServiceError> {
+        self.directory.list_accounts().await
+    }
+
+    /// The username and address may be cached as missing
+    async fn create_account(
+        &self,
+        account: NewAccount,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        let username = account.username.clone();
+        let primary_address = account.primary_address.clone();
+        let created = self.directory.create_account(account).await?;
+        self.cache.invalidate_account(&username);
+        if let Some(primary_address) = primary_address {
+            self.cache.invalidate_address(&primary_address);
+        }
+        Ok(created)
+    }
+
+    async fn update_account(
+        &self,
+        username: String,
+        update: AccountUpdate,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        let updated = self
+            .directory
+            .update_account(username.clone(), update)
+            .await?;
+        self.cache.invalidate_account(&username);
+        Ok(updated)
+    }
+
+    async fn rename_account(
+        &self,
+        username: String,
+        new_username: String,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        let renamed = self
+            .directory
+            .rename_account(username.clone(), new_username.clone())
+            .await?;
+        self.cache.invalidate_account(&username);
+        self.cache.invalidate_account(&new_username);
+        Ok(renamed)
+    }
+
+    async fn set_account_active(
+        &self,
+        username: String,
+        active: bool,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let changed = self
+            .directory
+            .set_account_active(username.clone(), active)
+            .await?;
+        self.cache.invalidate_account(&username);
+        Ok(changed)
+    }
+
+    async fn delete_account(
+        &self,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let deleted = self.directory.delete_account(username.clone()).await?;
+        self.cache.invalidate_account(&username);
+        Ok(deleted)
+    }
+
+    async fn set_password(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let changed = self
+            .directory
+            .set_password(username.clone(), password)
+            .await?;
+        self.cache.invalidate_logins(&username);
+        Ok(changed)
+    }
+
+    async fn add_email_address(
+        &self,
+        username: String,
+        email_address: String,
+        email_type: EmailType,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let added = self
+            .directory
+            .add_email_address(username.clone(), email_address.clone(), email_type)
+            .await?;
+        self.cache.invalidate_account(&username);
+        self.cache.invalidate_address(&email_address);
+        Ok(added)
+    }
+
+    async fn remove_email_address(
+        &self,
+        email_address: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let removed = self
+            .directory
+            .remove_email_address(email_address.clone())
+            .await?;
+        self.cache.invalidate_address_owner(&email_address);
+        self.cache.invalidate_address(&email_address);
+        Ok(removed)
+    }
+
+    async fn create_group(&self, group: NewGroup) -> Result<AdminResult<()>, Self::ServiceError> {
+        let list_address = group.list_address.clone();
+        let created = self.directory.create_group(group).await?;
+        if let Some(list_address) = list_address {
+            self.cache.invalidate_address(&list_address);
+        }
+        Ok(created)
+    }
+
+    async fn delete_group(&self, name: String) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let deleted = self.directory.delete_group(name.clone()).await?;
+        self.cache.invalidate_group_members(&name);
+        self.cache.invalidate_mailing_lists();
+        Ok(deleted)
+    }
+
+    async fn list_group_members(
+        &self,
+        group: String,
+    ) -> Result<AdminResult<Vec<String>>, Self::ServiceError> {
+        self.directory.list_group_members(group).await
+    }
+
+    async fn add_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let added = self
+            .directory
+            .add_group_member(group, username.clone())
+            .await?;
+        self.cache.invalidate_account(&username);
+        self.cache.invalidate_mailing_lists();
+        Ok(added)
+    }
+
+    async fn remove_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let removed = self
+            .directory
+            .remove_group_member(group, username.clone())
+            .await?;
+        self.cache.invalidate_account(&username);
+        self.cache.invalidate_mailing_lists();
+        Ok(removed)
+    }
+
+    async fn get_groups(&self) -> Result<Vec<String>, Self::ServiceError> {
+        self.directory.get_groups().await
+    }
+
+    async fn validate_config(
+        &self,
+        validate_config_request: ValidateDirectoryRequest,
+    ) -> Result<ServiceConfigurationResponse, Self::ServiceError> {
+        self.directory
+            .validate_config(validate_config_request)
+            .await
+    }
+}
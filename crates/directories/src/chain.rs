@@ -0,0 +1,1091 @@
+//! Combines several directories into one
+//!
+//! Each directory is asked in order. The first directory that has an account owns the username,
+//! so logins, app passwords and TOTP always go to that directory even if a later one has an account with the same name.
+//! Changes to an account go to its owner as well. New accounts and groups are created in the first directory that supports them.
+//!
+//! A [ChainDirectory] is a [Directory] itself. It can be used in process or served with a [DirectoryService](crate::directory_service::DirectoryService)
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures_util::future::{BoxFuture, Ready};
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, Display, EnumIs, EnumString, IntoStaticStr};
+use thiserror::Error;
+use tracing::warn;
+
+use utils::account::Account;
+use utils::admin::{
+    AccountUpdate, AdminError, AdminResult, DirectoryCapabilities, NewAccount, NewGroup,
+};
+use utils::app_password::{AppPassword, LoginProtocol, NewAppPassword};
+use utils::auth_failures::{Lockout, LockoutKey};
+use utils::common_types::EmailType;
+use utils::configs::{Config, ConfigName};
+use utils::groups::MailingList;
+use utils::quota::Quota;
+use utils::service::{Service, ServiceAccess};
+use utils::service_configuration::ServiceConfigurationResponse;
+use utils::two_factor::TotpEnrollment;
+use uuid::Uuid;
+
+use crate::directory_type::Directory;
+use crate::ValidateDirectoryRequest;
+
+type BoxedError = Box<dyn Error + Send + Sync + 'static>;
+
+#[derive(Debug, Error)]
+pub enum ChainDirectoryError {
+    #[error("{directory}: {source}")]
+    Directory {
+        directory: &'static str,
+        source: BoxedError,
+    },
+    #[error("The chain has no directories")]
+    Empty,
+}
+
+/// What happens when a directory in the chain fails
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    Serialize,
+    Deserialize,
+    AsRefStr,
+    IntoStaticStr,
+    EnumIs,
+    EnumString,
+    Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ChainMode {
+    /// A failing directory is skipped and the next one is asked.
+    /// Nothing is found only if every directory answered
+    #[default]
+    FirstMatch,
+    /// A failing directory fails the lookup, unless an earlier directory already matched.
+    /// A later directory can never answer for an account of a directory that is down
+    FailClosed,
+}
+
+/// # Example
+/// ```toml
+/// mode = "fail_closed"
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChainConfig {
+    #[serde(default)]
+    pub mode: ChainMode,
+}
+impl Config for ChainConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/chain_directory")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("chain.directory.toml")
+    }
+}
+
+/// [Directory] without the associated types so directories of different types can be stored together
+#[async_trait]
+trait ErasedDirectory: Send + Sync + 'static {
+    async fn get_account(&self, username: String) -> Result<Option<Account>, BoxedError>;
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, BoxedError>;
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, BoxedError>;
+    async fn get_quota(&self, mailbox_id: Uuid) -> Result<Option<Quota>, BoxedError>;
+    async fn login_account(
+        &self,
+        username: String,
+        password: String,
+        protocol: LoginProtocol,
+    ) -> Result<Option<Account>, BoxedError>;
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<Option<Account>, BoxedError>;
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, BoxedError>;
+    async fn list_app_passwords(&self, username: String) -> Result<Vec<AppPassword>, BoxedError>;
+    async fn revoke_app_password(&self, username: String, id: i64) -> Result<bool, BoxedError>;
+    async fn enroll_totp(&self, username: String) -> Result<Option<TotpEnrollment>, BoxedError>;
+    async fn verify_totp(&self, username: String, code: String) -> Result<bool, BoxedError>;
+    async fn disable_totp(&self, username: String) -> Result<bool, BoxedError>;
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, BoxedError>;
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, BoxedError>;
+    async fn record_auth_success(&self, username: String) -> Result<bool, BoxedError>;
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, BoxedError>;
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, BoxedError>;
+    async fn directory_capabilities(&self) -> Result<DirectoryCapabilities, BoxedError>;
+    async fn list_accounts(&self) -> Result<AdminResult<Vec<Account>>, BoxedError>;
+    async fn create_account(&self, account: NewAccount)
+        -> Result<AdminResult<Account>, BoxedError>;
+    async fn update_account(
+        &self,
+        username: String,
+        update: AccountUpdate,
+    ) -> Result<AdminResult<Account>, BoxedError>;
+    async fn rename_account(
+        &self,
+        username: String,
+        new_username: String,
+    ) -> Result<AdminResult<Account>, BoxedError>;
+    async fn set_account_active(
+        &self,
+        username: String,
+        active: bool,
+    ) -> Result<AdminResult<()>, BoxedError>;
+    async fn delete_account(&self, username: String) -> Result<AdminResult<bool>, BoxedError>;
+    async fn set_password(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<AdminResult<()>, BoxedError>;
+    async fn add_email_address(
+        &self,
+        username: String,
+        email_address: String,
+        email_type: EmailType,
+    ) -> Result<AdminResult<()>, BoxedError>;
+    async fn remove_email_address(
+        &self,
+        email_address: String,
+    ) -> Result<AdminResult<bool>, BoxedError>;
+    async fn create_group(&self, group: NewGroup) -> Result<AdminResult<()>, BoxedError>;
+    async fn delete_group(&self, name: String) -> Result<AdminResult<bool>, BoxedError>;
+    async fn list_group_members(
+        &self,
+        group: String,
+    ) -> Result<AdminResult<Vec<String>>, BoxedError>;
+    async fn add_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<()>, BoxedError>;
+    async fn remove_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<bool>, BoxedError>;
+    async fn get_groups(&self) -> Result<Vec<String>, BoxedError>;
+    async fn validate_config(
+        &self,
+        validate_config_request: ValidateDirectoryRequest,
+    ) -> Result<ServiceConfigurationResponse, BoxedError>;
+}
+#[async_trait]
+impl<D: Directory> ErasedDirectory for D {
+    async fn get_account(&self, username: String) -> Result<Option<Account>, BoxedError> {
+        Ok(Directory::get_account(self, username).await?)
+    }
+
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, BoxedError> {
+        Ok(Directory::get_account_by_email(self, email_address).await?)
+    }
+
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, BoxedError> {
+        Ok(Directory::get_mailing_list(self, email_address).await?)
+    }
+
+    async fn get_quota(&self, mailbox_id: Uuid) -> Result<Option<Quota>, BoxedError> {
+        Ok(Directory::get_quota(self, mailbox_id).await?)
+    }
+
+    async fn login_account(
+        &self,
+        username: String,
+        password: String,
+        protocol: LoginProtocol,
+    ) -> Result<Option<Account>, BoxedError> {
+        Ok(Directory::login_account(self, username, password, protocol).await?)
+    }
+
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<Option<Account>, BoxedError> {
+        Ok(Directory::login_account_with_totp(self, username, password, code).await?)
+    }
+
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, BoxedError> {
+        Ok(Directory::create_app_password(self, username, name, protocols).await?)
+    }
+
+    async fn list_app_passwords(&self, username: String) -> Result<Vec<AppPassword>, BoxedError> {
+        Ok(Directory::list_app_passwords(self, username).await?)
+    }
+
+    async fn revoke_app_password(&self, username: String, id: i64) -> Result<bool, BoxedError> {
+        Ok(Directory::revoke_app_password(self, username, id).await?)
+    }
+
+    async fn enroll_totp(&self, username: String) -> Result<Option<TotpEnrollment>, BoxedError> {
+        Ok(Directory::enroll_totp(self, username).await?)
+    }
+
+    async fn verify_totp(&self, username: String, code: String) -> Result<bool, BoxedError> {
+        Ok(Directory::verify_totp(self, username, code).await?)
+    }
+
+    async fn disable_totp(&self, username: String) -> Result<bool, BoxedError> {
+        Ok(Directory::disable_totp(self, username).await?)
+    }
+
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, BoxedError> {
+        Ok(Directory::check_auth_lockout(self, remote, username).await?)
+    }
+
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, BoxedError> {
+        Ok(Directory::record_auth_failure(self, remote, username).await?)
+    }
+
+    async fn record_auth_success(&self, username: String) -> Result<bool, BoxedError> {
+        Ok(Directory::record_auth_success(self, username).await?)
+    }
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, BoxedError> {
+        Ok(Directory::list_lockouts(self).await?)
+    }
+
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, BoxedError> {
+        Ok(Directory::clear_lockout(self, key).await?)
+    }
+
+    async fn directory_capabilities(&self) -> Result<DirectoryCapabilities, BoxedError> {
+        Ok(Directory::directory_capabilities(self).await?)
+    }
+
+    async fn list_accounts(&self) -> Result<AdminResult<Vec<Account>>, BoxedError> {
+        Ok(Directory::list_accounts(self).await?)
+    }
+
+    async fn create_account(
+        &self,
+        account: NewAccount,
+    ) -> Result<AdminResult<Account>, BoxedError> {
+        Ok(Directory::create_account(self, account).await?)
+    }
+
+    async fn update_account(
+        &self,
+        username: String,
+        update: AccountUpdate,
+    ) -> Result<AdminResult<Account>, BoxedError> {
+        Ok(Directory::update_account(self, username, update).await?)
+    }
+
+    async fn rename_account(
+        &self,
+        username: String,
+        new_username: String,
+    ) -> Result<AdminResult<Account>, BoxedError> {
+        Ok(Directory::rename_account(self, username, new_username).await?)
+    }
+
+    async fn set_account_active(
+        &self,
+        username: String,
+        active: bool,
+    ) -> Result<AdminResult<()>, BoxedError> {
+        Ok(Directory::set_account_active(self, username, active).await?)
+    }
+
+    async fn delete_account(&self, username: String) -> Result<AdminResult<bool>, BoxedError> {
+        Ok(Directory::delete_account(self, username).await?)
+    }
+
+    async fn set_password(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<AdminResult<()>, BoxedError> {
+        Ok(Directory::set_password(self, username, password).await?)
+    }
+
+    async fn add_email_address(
+        &self,
+        username: String,
+        email_address: String,
+        email_type: EmailType,
+    ) -> Result<AdminResult<()>, BoxedError> {
+        Ok(Directory::add_email_address(self, username, email_address, email_type).await?)
+    }
+
+    async fn remove_email_address(
+        &self,
+        email_address: String,
+    ) -> Result<AdminResult<bool>, BoxedError> {
+        Ok(Directory::remove_email_address(self, email_address).await?)
+    }
+
+    async fn create_group(&self, group: NewGroup) -> Result<AdminResult<()>, BoxedError> {
+        Ok(Directory::create_group(self, group).await?)
+    }
+
+    async fn delete_group(&self, name: String) -> Result<AdminResult<bool>, BoxedError> {
+        Ok(Directory::delete_group(self, name).await?)
+    }
+
+    async fn list_group_members(
+        &self,
+        group: String,
+    ) -> Result<AdminResult<Vec<String>>, BoxedError> {
+        Ok(Directory::list_group_members(self, group).await?)
+    }
+
+    async fn add_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<()>, BoxedError> {
+        Ok(Directory::add_group_member(self, group, username).await?)
+    }
+
+    async fn remove_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<bool>, BoxedError> {
+        Ok(Directory::remove_group_member(self, group, username).await?)
+    }
+
+    async fn get_groups(&self) -> Result<Vec<String>, BoxedError> {
+        Ok(Directory::get_groups(self).await?)
+    }
+
+    async fn validate_config(
+        &self,
+        validate_config_request: ValidateDirectoryRequest,
+    ) -> Result<ServiceConfigurationResponse, BoxedError> {
+        Ok(Directory::validate_config(self, validate_config_request).await?)
+    }
+}
+
+struct ChainMember {
+    name: &'static str,
+    directory: Box<dyn ErasedDirectory>,
+}
+impl ChainMember {
+    fn error(&self, source: BoxedError) -> ChainDirectoryError {
+        ChainDirectoryError::Directory {
+            directory: self.name,
+            source,
+        }
+    }
+}
+
+/// An ordered list of directories
+///
+/// # Example
+/// ```ignore
+/// let directory = ChainDirectory::new(ChainMode::FailClosed)
+///     .with(sql_directory)
+///     .with(ldap_directory);
+/// ```
+#[derive(Clone)]
+pub struct ChainDirectory {
+    mode: ChainMode,
+    members: Vec<Arc<ChainMember>>,
+}
+impl ServiceAccess for ChainDirectory {
+    type ServiceResponse = Self;
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn get_service(&self) -> Self::Future {
+        futures_util::future::ready(Ok(self.clone()))
+    }
+}
+impl ChainDirectory {
+    pub fn new(mode: ChainMode) -> Self {
+        Self {
+            mode,
+            members: vec![],
+        }
+    }
+    /// Adds a directory after the ones already in the chain
+    pub fn with<D: Directory>(mut self, directory: D) -> Self {
+        self.members.push(Arc::new(ChainMember {
+            name: D::directory_name(),
+            directory: Box::new(directory),
+        }));
+        self
+    }
+    pub fn mode(&self) -> ChainMode {
+        self.mode
+    }
+    /// The names of the directories in order
+    pub fn directory_names(&self) -> Vec<&'static str> {
+        self.members.iter().map(|member| member.name).collect()
+    }
+
+    /// Asks each directory in order until one has a value
+    async fn find<'a, T, F>(
+        &'a self,
+        mut lookup: F,
+    ) -> Result<Option<(&'a ChainMember, T)>, ChainDirectoryError>
+    where
+        T: Send,
+        F: FnMut(&'a dyn ErasedDirectory) -> BoxFuture<'a, Result<Option<T>, BoxedError>> + Send,
+    {
+        let mut failure = None;
+        for member in &self.members {
+            match lookup(member.directory.as_ref()).await {
+                Ok(Some(value)) => return Ok(Some((member, value))),
+                Ok(None) => {}
+                Err(error) => {
+                    let error = member.error(error);
+                    if self.mode.is_fail_closed() {
+                        return Err(error);
+                    }
+                    warn!("Skipping directory {}", error);
+                    failure.get_or_insert(error);
+                }
+            }
+        }
+        failure.map_or(Ok(None), Err)
+    }
+
+    /// Asks every directory. In [ChainMode::FirstMatch] failing directories are left out
+    async fn merge<'a, T, F>(&'a self, mut lookup: F) -> Result<Vec<T>, ChainDirectoryError>
+    where
+        T: Send,
+        F: FnMut(&'a dyn ErasedDirectory) -> BoxFuture<'a, Result<T, BoxedError>> + Send,
+    {
+        let mut values = Vec::with_capacity(self.members.len());
+        for member in &self.members {
+            match lookup(member.directory.as_ref()).await {
+                Ok(value) => values.push(value),
+                Err(error) => {
+                    let error = member.error(error);
+                    if self.mode.is_fail_closed() {
+                        return Err(error);
+                    }
+                    warn!("Skipping directory {}", error);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// The first directory with an account for the username
+    async fn owner(&self, username: &str) -> Result<Option<&ChainMember>, ChainDirectoryError> {
+        Ok(self
+            .find(|directory| directory.get_account(username.to_string()))
+            .await?
+            .map(|(member, _)| member))
+    }
+
+    /// The first directory with a group of that name
+    async fn group_owner(&self, group: &str) -> Result<Option<&ChainMember>, ChainDirectoryError> {
+        Ok(self
+            .find(|directory| {
+                let group = group.to_string();
+                Box::pin(async move {
+                    let groups = directory.get_groups().await?;
+                    Ok(groups.contains(&group).then_some(()))
+                })
+            })
+            .await?
+            .map(|(member, _)| member))
+    }
+
+    /// The first directory whose capabilities allow a change
+    async fn first_capable(
+        &self,
+        capable: fn(&DirectoryCapabilities) -> bool,
+    ) -> Result<Option<&ChainMember>, ChainDirectoryError> {
+        Ok(self
+            .find(|directory| {
+                Box::pin(async move {
+                    let capabilities = directory.directory_capabilities().await?;
+                    Ok(capable(&capabilities).then_some(()))
+                })
+            })
+            .await?
+            .map(|(member, _)| member))
+    }
+}
+impl Service for ChainDirectory {
+    type ServiceConfig = ChainConfig;
+    type ServiceError = ChainDirectoryError;
+}
+
+#[async_trait]
+impl Directory for ChainDirectory {
+    fn directory_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "directory_chain"
+    }
+
+    /// An empty chain. Directories are added with [with](ChainDirectory::with)
+    async fn load(config: Self::ServiceConfig) -> Result<Self, Self::ServiceError>
+    where
+        Self: Sized,
+    {
+        Ok(Self::new(config.mode))
+    }
+
+    async fn get_account(&self, username: String) -> Result<Option<Account>, Self::ServiceError> {
+        Ok(self
+            .find(|directory| directory.get_account(username.clone()))
+            .await?
+            .map(|(_, account)| account))
+    }
+
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        Ok(self
+            .find(|directory| directory.get_account_by_email(email_address.clone()))
+            .await?
+            .map(|(_, account)| account))
+    }
+
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, Self::ServiceError> {
+        Ok(self
+            .find(|directory| directory.get_mailing_list(email_address.clone()))
+            .await?
+            .map(|(_, list)| list))
+    }
+
+    /// Mailbox ids are unique across directories, so the first directory that has limits for it sets them
+    async fn get_quota(&self, mailbox_id: Uuid) -> Result<Option<Quota>, Self::ServiceError> {
+        Ok(self
+            .find(|directory| directory.get_quota(mailbox_id))
+            .await?
+            .map(|(_, quota)| quota))
+    }
+
+    /// Only the directory that owns the username checks the password
+    async fn login_account(
+        &self,
+        username: String,
+        password: String,
+        protocol: LoginProtocol,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(None);
+        };
+        owner
+            .directory
+            .login_account(username, password, protocol)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(None);
+        };
+        owner
+            .directory
+            .login_account_with_totp(username, password, code)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(None);
+        };
+        owner
+            .directory
+            .create_app_password(username, name, protocols)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn list_app_passwords(
+        &self,
+        username: String,
+    ) -> Result<Vec<AppPassword>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(vec![]);
+        };
+        owner
+            .directory
+            .list_app_passwords(username)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn revoke_app_password(
+        &self,
+        username: String,
+        id: i64,
+    ) -> Result<bool, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(false);
+        };
+        owner
+            .directory
+            .revoke_app_password(username, id)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn enroll_totp(
+        &self,
+        username: String,
+    ) -> Result<Option<TotpEnrollment>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(None);
+        };
+        owner
+            .directory
+            .enroll_totp(username)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn verify_totp(
+        &self,
+        username: String,
+        code: String,
+    ) -> Result<bool, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(false);
+        };
+        owner
+            .directory
+            .verify_totp(username, code)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn disable_totp(&self, username: String) -> Result<bool, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(false);
+        };
+        owner
+            .directory
+            .disable_totp(username)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    /// Locked if any directory has a lockout
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        Ok(self
+            .find(|directory| directory.check_auth_lockout(remote, username.clone()))
+            .await?
+            .map(|(_, lockout)| lockout))
+    }
+
+    /// Counted by the directory that owns the username, or the first directory if nobody does
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        let owner = match &username {
+            Some(username) => self.owner(username).await?,
+            None => None,
+        };
+        let Some(member) = owner.or(self.members.first().map(Arc::as_ref)) else {
+            return Ok(None);
+        };
+        member
+            .directory
+            .record_auth_failure(remote, username)
+            .await
+            .map_err(|error| member.error(error))
+    }
+
+    async fn record_auth_success(&self, username: String) -> Result<bool, Self::ServiceError> {
+        let cleared = self
+            .merge(|directory| directory.record_auth_success(username.clone()))
+            .await?;
+        Ok(cleared.into_iter().any(|cleared| cleared))
+    }
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, Self::ServiceError> {
+        let lockouts = self.merge(|directory| directory.list_lockouts()).await?;
+        Ok(lockouts.into_iter().flatten().collect())
+    }
+
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, Self::ServiceError> {
+        let cleared = self
+            .merge(|directory| directory.clear_lockout(key.clone()))
+            .await?;
+        Ok(cleared.into_iter().any(|cleared| cleared))
+    }
+
+    /// Everything any of the directories supports
+    async fn directory_capabilities(&self) -> Result<DirectoryCapabilities, Self::ServiceError> {
+        let capabilities = self
+            .merge(|directory| directory.directory_capabilities())
+            .await?;
+        Ok(capabilities
+            .into_iter()
+            .fold(DirectoryCapabilities::READ_ONLY, |all, capabilities| {
+                DirectoryCapabilities {
+                    accounts: all.accounts || capabilities.accounts,
+                    passwords: all.passwords || capabilities.passwords,
+                    addresses: all.addresses || capabilities.addresses,
+                    groups: all.groups || capabilities.groups,
+                }
+            }))
+    }
+
+    /// The accounts of every directory that can list them. A username is listed once, for its owner
+    async fn list_accounts(&self) -> Result<AdminResult<Vec<Account>>, Self::ServiceError> {
+        let mut accounts: Vec<Account> = vec![];
+        let mut supported = false;
+        for result in self.merge(|directory| directory.list_accounts()).await? {
+            let directory_accounts = match result {
+                Ok(directory_accounts) => directory_accounts,
+                Err(AdminError::NotSupported) => continue,
+                Err(error) => return Ok(Err(error)),
+            };
+            supported = true;
+            for directory_account in directory_accounts {
+                if !accounts
+                    .iter()
+                    .any(|account| account.username == directory_account.username)
+                {
+                    accounts.push(directory_account);
+                }
+            }
+        }
+        if !supported {
+            return Ok(Err(AdminError::NotSupported));
+        }
+        Ok(Ok(accounts))
+    }
+
+    /// Created in the first directory that supports accounts, unless any directory already owns the username
+    async fn create_account(
+        &self,
+        account: NewAccount,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        if self.owner(&account.username).await?.is_some() {
+            return Ok(Err(AdminError::AccountExists(account.username)));
+        }
+        let Some(member) = self
+            .first_capable(|capabilities| capabilities.accounts)
+            .await?
+        else {
+            return Ok(Err(AdminError::NotSupported));
+        };
+        member
+            .directory
+            .create_account(account)
+            .await
+            .map_err(|error| member.error(error))
+    }
+
+    async fn update_account(
+        &self,
+        username: String,
+        update: AccountUpdate,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        owner
+            .directory
+            .update_account(username, update)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    /// The new username must not be used by any directory in the chain
+    async fn rename_account(
+        &self,
+        username: String,
+        new_username: String,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        if self.owner(&new_username).await?.is_some() {
+            return Ok(Err(AdminError::AccountExists(new_username)));
+        }
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        owner
+            .directory
+            .rename_account(username, new_username)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn set_account_active(
+        &self,
+        username: String,
+        active: bool,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        owner
+            .directory
+            .set_account_active(username, active)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn delete_account(
+        &self,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(Ok(false));
+        };
+        owner
+            .directory
+            .delete_account(username)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn set_password(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        owner
+            .directory
+            .set_password(username, password)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn add_email_address(
+        &self,
+        username: String,
+        email_address: String,
+        email_type: EmailType,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let Some(owner) = self.owner(&username).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        owner
+            .directory
+            .add_email_address(username, email_address, email_type)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    /// Removed from the directory that resolves the address
+    async fn remove_email_address(
+        &self,
+        email_address: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let Some((member, _)) = self
+            .find(|directory| directory.get_account_by_email(email_address.clone()))
+            .await?
+        else {
+            return Ok(Ok(false));
+        };
+        member
+            .directory
+            .remove_email_address(email_address)
+            .await
+            .map_err(|error| member.error(error))
+    }
+
+    /// Created in the first directory that supports groups, unless any directory already has the group
+    async fn create_group(&self, group: NewGroup) -> Result<AdminResult<()>, Self::ServiceError> {
+        if self.group_owner(&group.name).await?.is_some() {
+            return Ok(Err(AdminError::GroupExists(group.name)));
+        }
+        let Some(member) = self
+            .first_capable(|capabilities| capabilities.groups)
+            .await?
+        else {
+            return Ok(Err(AdminError::NotSupported));
+        };
+        member
+            .directory
+            .create_group(group)
+            .await
+            .map_err(|error| member.error(error))
+    }
+
+    async fn delete_group(&self, name: String) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let Some(owner) = self.group_owner(&name).await? else {
+            return Ok(Ok(false));
+        };
+        owner
+            .directory
+            .delete_group(name)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn list_group_members(
+        &self,
+        group: String,
+    ) -> Result<AdminResult<Vec<String>>, Self::ServiceError> {
+        let Some(owner) = self.group_owner(&group).await? else {
+            return Ok(Err(AdminError::GroupNotFound(group)));
+        };
+        owner
+            .directory
+            .list_group_members(group)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    /// The account must be in the same directory as the group
+    async fn add_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let Some(owner) = self.group_owner(&group).await? else {
+            return Ok(Err(AdminError::GroupNotFound(group)));
+        };
+        owner
+            .directory
+            .add_group_member(group, username)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    async fn remove_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let Some(owner) = self.group_owner(&group).await? else {
+            return Ok(Ok(false));
+        };
+        owner
+            .directory
+            .remove_group_member(group, username)
+            .await
+            .map_err(|error| owner.error(error))
+    }
+
+    /// The groups of every directory without duplicates
+    async fn get_groups(&self) -> Result<Vec<String>, Self::ServiceError> {
+        let mut groups: Vec<String> = vec![];
+        for group in self
+            .merge(|directory| directory.get_groups())
+            .await?
+            .into_iter()
+            .flatten()
+        {
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Every directory must accept the namespaces, whatever the [ChainMode]
+    async fn validate_config(
+        &self,
+        validate_config_request: ValidateDirectoryRequest,
+    ) -> Result<ServiceConfigurationResponse, Self::ServiceError> {
+        let mut new_install = false;
+        let mut first = None;
+        for member in &self.members {
+            let response = member
+                .directory
+                .validate_config(validate_config_request.clone())
+                .await
+                .map_err(|error| member.error(error))?;
+            let ServiceConfigurationResponse::Success {
+                new_install: member_new_install,
+                ..
+            } = &response
+            else {
+                return Ok(response);
+            };
+            new_install |= *member_new_install;
+            first.get_or_insert(response);
+        }
+        let Some(mut response) = first else {
+            return Err(ChainDirectoryError::Empty);
+        };
+        if let ServiceConfigurationResponse::Success {
+            new_install: first_new_install,
+            ..
+        } = &mut response
+        {
+            *first_new_install = new_install;
+        }
+        Ok(response)
+    }
+}
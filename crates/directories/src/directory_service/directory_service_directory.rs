@@ -1,6 +1,7 @@
 use std::future::Future;
 use std::io;
 use std::io::ErrorKind;
+use std::net::IpAddr;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::sync::Arc;
@@ -16,13 +17,23 @@ use thiserror::Error;
 use tracing::trace;
 
 use utils::account::Account;
+use utils::admin::{AccountUpdate, AdminResult, DirectoryCapabilities, NewAccount, NewGroup};
+use utils::app_password::{AppPassword, LoginProtocol, NewAppPassword};
+use utils::auth_failures::{Lockout, LockoutKey};
+use utils::common_types::EmailType;
+use utils::groups::MailingList;
 use utils::interprocess_guard::InterprocessConnectionInner;
+use utils::quota::Quota;
 use utils::service::{Service, ServiceAccess};
 use utils::service_configuration::ServiceConfigurationResponse;
+use utils::two_factor::TotpEnrollment;
+use uuid::Uuid;
 
-use crate::directory_service::packets::{FromServicePackets, ToServicePackets};
+use crate::directory_service::packets::{
+    FromServicePackets, FromServiceSystemPackets, ToServicePackets, ToServiceSystemPackets,
+};
 use crate::directory_type::Directory;
-use crate::{ValidateDirectoryRequest, SOCKET_NAME};
+use crate::{ValidateDirectoryRequest, PROTOCOL_VERSION, SOCKET_NAME};
 
 #[derive(Debug, Error)]
 pub enum DirectoryServiceError {
@@ -30,6 +41,8 @@ pub enum DirectoryServiceError {
     Service(String),
     #[error(transparent)]
     Connection(#[from] io::Error),
+    #[error("The directory service speaks protocol version {service}. Expected {client}")]
+    ProtocolMismatch { client: u32, service: u32 },
 }
 pub struct DirectoryServiceDirectory(Arc<InterprocessConnectionInner>);
 impl Deref for DirectoryServiceDirectory {
@@ -95,6 +108,15 @@ impl DirectoryServiceDirectory {
             DirectoryServiceError::Connection(io::Error::new(ErrorKind::InvalidData, e.to_string()))
         })
     }
+    /// The error for a response that does not answer the request
+    fn unexpected_packet(packet: FromServicePackets) -> DirectoryServiceError {
+        match packet {
+            FromServicePackets::InternalDirectoryError(error) => {
+                DirectoryServiceError::Service(error)
+            }
+            packet => DirectoryServiceError::Service(format!("Unexpected response {:?}", packet)),
+        }
+    }
 }
 impl Service for DirectoryServiceDirectory {
     type ServiceConfig = ();
@@ -113,9 +135,19 @@ impl Directory for DirectoryServiceDirectory {
     where
         Self: Sized,
     {
-        let connection = LocalSocketStream::connect(SOCKET_NAME)
-            .await
-            .expect("Failed to connect to directory service");
+        let mut connection = LocalSocketStream::connect(SOCKET_NAME).await?;
+        connection
+            .write_all(&PROTOCOL_VERSION.to_be_bytes())
+            .await?;
+        let mut version = [0u8; 4];
+        connection.read_exact(&mut version).await?;
+        let version = u32::from_be_bytes(version);
+        if version != PROTOCOL_VERSION {
+            return Err(DirectoryServiceError::ProtocolMismatch {
+                client: PROTOCOL_VERSION,
+                service: version,
+            });
+        }
 
         Ok(Self(Arc::new(InterprocessConnectionInner::new(connection))))
     }
@@ -138,15 +170,71 @@ impl Directory for DirectoryServiceDirectory {
             })
     }
 
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::GetAccountByEmail(email_address),
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::GetAccountByEmail(a) => a,
+                _ => None,
+            })
+    }
+
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::GetMailingList(email_address),
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::GetMailingList(list) => list,
+                _ => None,
+            })
+    }
+
+    async fn get_quota(&self, mailbox_id: Uuid) -> Result<Option<Quota>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::GetQuota(mailbox_id),
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::GetQuota(quota) => quota,
+                _ => None,
+            })
+    }
+
     async fn login_account(
         &self,
         username: String,
         password: String,
+        protocol: LoginProtocol,
     ) -> Result<Option<Account>, Self::ServiceError> {
         let mut connection = self.get_guard_panic();
         Self::write_packet(
             connection.deref_mut(),
-            ToServicePackets::LoginAccount { username, password },
+            ToServicePackets::LoginAccount {
+                username,
+                password,
+                protocol,
+            },
         )
         .await?;
         Self::get_packet(connection.deref_mut())
@@ -157,14 +245,471 @@ impl Directory for DirectoryServiceDirectory {
             })
     }
 
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::CreateAppPassword {
+                username,
+                name,
+                protocols,
+            },
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::CreateAppPassword(app_password) => app_password,
+                _ => None,
+            })
+    }
+
+    async fn list_app_passwords(
+        &self,
+        username: String,
+    ) -> Result<Vec<AppPassword>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::ListAppPasswords(username),
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::ListAppPasswords(app_passwords) => app_passwords,
+                _ => Vec::new(),
+            })
+    }
+
+    async fn revoke_app_password(
+        &self,
+        username: String,
+        id: i64,
+    ) -> Result<bool, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::RevokeAppPassword { username, id },
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::RevokeAppPassword(revoked) => revoked,
+                _ => false,
+            })
+    }
+
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::LoginAccountWithTotp {
+                username,
+                password,
+                code,
+            },
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::LoginAccountWithTotp(a) => a,
+                _ => None,
+            })
+    }
+
+    async fn enroll_totp(
+        &self,
+        username: String,
+    ) -> Result<Option<TotpEnrollment>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::EnrollTotp(username),
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::EnrollTotp(enrollment) => enrollment,
+                _ => None,
+            })
+    }
+
+    async fn verify_totp(
+        &self,
+        username: String,
+        code: String,
+    ) -> Result<bool, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::VerifyTotp { username, code },
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::VerifyTotp(valid) => valid,
+                _ => false,
+            })
+    }
+
+    async fn disable_totp(&self, username: String) -> Result<bool, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::DisableTotp(username),
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::DisableTotp(disabled) => disabled,
+                _ => false,
+            })
+    }
+
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::CheckAuthLockout { remote, username },
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::CheckAuthLockout(lockout) => lockout,
+                _ => None,
+            })
+    }
+
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::RecordAuthFailure { remote, username },
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::RecordAuthFailure(lockout) => lockout,
+                _ => None,
+            })
+    }
+
+    async fn record_auth_success(&self, username: String) -> Result<bool, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::RecordAuthSuccess(username),
+        )
+        .await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::RecordAuthSuccess(cleared) => cleared,
+                _ => false,
+            })
+    }
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(connection.deref_mut(), ToServicePackets::ListLockouts).await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::ListLockouts(lockouts) => lockouts,
+                _ => vec![],
+            })
+    }
+
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(connection.deref_mut(), ToServicePackets::ClearLockout(key)).await?;
+        Self::get_packet(connection.deref_mut())
+            .await
+            .map(|p| match p {
+                FromServicePackets::ClearLockout(cleared) => cleared,
+                _ => false,
+            })
+    }
+
+    async fn directory_capabilities(&self) -> Result<DirectoryCapabilities, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(connection.deref_mut(), ToServicePackets::GetCapabilities).await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::GetCapabilities(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn list_accounts(&self) -> Result<AdminResult<Vec<Account>>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(connection.deref_mut(), ToServicePackets::ListAccounts).await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::ListAccounts(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn create_account(
+        &self,
+        account: NewAccount,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::CreateAccount(account),
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::CreateAccount(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn update_account(
+        &self,
+        username: String,
+        update: AccountUpdate,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::UpdateAccount { username, update },
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::UpdateAccount(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn rename_account(
+        &self,
+        username: String,
+        new_username: String,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::RenameAccount {
+                username,
+                new_username,
+            },
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::RenameAccount(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn set_account_active(
+        &self,
+        username: String,
+        active: bool,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::SetAccountActive { username, active },
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::SetAccountActive(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn delete_account(
+        &self,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::DeleteAccount(username),
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::DeleteAccount(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn set_password(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::SetPassword { username, password },
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::SetPassword(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn add_email_address(
+        &self,
+        username: String,
+        email_address: String,
+        email_type: EmailType,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::AddEmailAddress {
+                username,
+                email_address,
+                email_type,
+            },
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::AddEmailAddress(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn remove_email_address(
+        &self,
+        email_address: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::RemoveEmailAddress(email_address),
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::RemoveEmailAddress(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn create_group(&self, group: NewGroup) -> Result<AdminResult<()>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(connection.deref_mut(), ToServicePackets::CreateGroup(group)).await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::CreateGroup(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn delete_group(&self, name: String) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(connection.deref_mut(), ToServicePackets::DeleteGroup(name)).await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::DeleteGroup(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn list_group_members(
+        &self,
+        group: String,
+    ) -> Result<AdminResult<Vec<String>>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::ListGroupMembers(group),
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::ListGroupMembers(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn add_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::AddGroupMember { group, username },
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::AddGroupMember(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn remove_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::RemoveGroupMember { group, username },
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::RemoveGroupMember(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
     async fn get_groups(&self) -> Result<Vec<String>, Self::ServiceError> {
-        todo!()
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(connection.deref_mut(), ToServicePackets::GetGroups).await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::GetGroups(groups) => Ok(groups),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
     }
 
     async fn validate_config(
         &self,
         validate_config_request: ValidateDirectoryRequest,
     ) -> Result<ServiceConfigurationResponse, Self::ServiceError> {
-        todo!()
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(
+            connection.deref_mut(),
+            ToServicePackets::SystemPacket(ToServiceSystemPackets::ValidateConfigurations(
+                validate_config_request,
+            )),
+        )
+        .await?;
+        match Self::get_packet(connection.deref_mut()).await? {
+            FromServicePackets::SystemPacket(FromServiceSystemPackets::ValidateConfigurations(
+                response,
+            )) => Ok(response),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
     }
 }
@@ -10,7 +10,7 @@ use utils::service::Service;
 
 use crate::directory_service::packets::{FromServicePackets, ToServicePackets};
 use crate::directory_type::Directory;
-use crate::SOCKET_NAME;
+use crate::{PROTOCOL_VERSION, SOCKET_NAME};
 
 pub mod directory_service_directory;
 
@@ -62,6 +62,17 @@ impl<D: Directory + Clone> DirectoryService<D> {
         mut stream: LocalSocketStream,
     ) -> Result<(), anyhow::Error> {
         let mut number_buffer = [0; 4];
+        // The client sends its protocol version first. The service answers with its own
+        stream.read_exact(&mut number_buffer).await?;
+        let client_version = u32::from_be_bytes(number_buffer);
+        stream.write_all(&PROTOCOL_VERSION.to_be_bytes()).await?;
+        if client_version != PROTOCOL_VERSION {
+            anyhow::bail!(
+                "Client speaks protocol version {}. Expected {}",
+                client_version,
+                PROTOCOL_VERSION
+            );
+        }
         let mut buffer = BytesMut::new();
         loop {
             stream.read_exact(&mut number_buffer).await?;
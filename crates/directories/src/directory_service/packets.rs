@@ -1,9 +1,18 @@
+use std::net::IpAddr;
+
 use rkyv::{Archive, Deserialize, Serialize};
 use uuid::Uuid;
 
 use helper_macros::ToServicePacket;
 use utils::account::Account;
+use utils::admin::{AccountUpdate, AdminResult, DirectoryCapabilities, NewAccount, NewGroup};
+use utils::app_password::{AppPassword, LoginProtocol, NewAppPassword};
+use utils::auth_failures::{Lockout, LockoutKey};
+use utils::common_types::EmailType;
+use utils::groups::MailingList;
+use utils::quota::Quota;
 use utils::service_configuration::ServiceConfigurationResponse;
+use utils::two_factor::TotpEnrollment;
 
 use crate::directory_type::Directory;
 use crate::ValidateDirectoryRequest;
@@ -41,10 +50,193 @@ pub enum ToServicePackets {
     )]
     GetAccount(String),
     #[packet(
+    service_method = Directory::get_account_by_email,
+    from_service_variant = FromServicePackets::GetAccountByEmail
+    )]
+    GetAccountByEmail(String),
+    #[packet(
+    service_method = Directory::get_mailing_list,
+    from_service_variant = FromServicePackets::GetMailingList
+    )]
+    GetMailingList(String),
+    #[packet(
+    service_method = Directory::get_quota,
+    from_service_variant = FromServicePackets::GetQuota
+    )]
+    GetQuota(Uuid),
+    #[packet(
     service_method = Directory::login_account,
     from_service_variant = FromServicePackets::LoginAccount
     )]
-    LoginAccount { username: String, password: String },
+    LoginAccount {
+        username: String,
+        password: String,
+        protocol: LoginProtocol,
+    },
+    #[packet(
+    service_method = Directory::create_app_password,
+    from_service_variant = FromServicePackets::CreateAppPassword
+    )]
+    CreateAppPassword {
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    },
+    #[packet(
+    service_method = Directory::list_app_passwords,
+    from_service_variant = FromServicePackets::ListAppPasswords
+    )]
+    ListAppPasswords(String),
+    #[packet(
+    service_method = Directory::revoke_app_password,
+    from_service_variant = FromServicePackets::RevokeAppPassword
+    )]
+    RevokeAppPassword { username: String, id: i64 },
+    #[packet(
+    service_method = Directory::login_account_with_totp,
+    from_service_variant = FromServicePackets::LoginAccountWithTotp
+    )]
+    LoginAccountWithTotp {
+        username: String,
+        password: String,
+        code: String,
+    },
+    #[packet(
+    service_method = Directory::enroll_totp,
+    from_service_variant = FromServicePackets::EnrollTotp
+    )]
+    EnrollTotp(String),
+    #[packet(
+    service_method = Directory::verify_totp,
+    from_service_variant = FromServicePackets::VerifyTotp
+    )]
+    VerifyTotp { username: String, code: String },
+    #[packet(
+    service_method = Directory::disable_totp,
+    from_service_variant = FromServicePackets::DisableTotp
+    )]
+    DisableTotp(String),
+    #[packet(
+    service_method = Directory::check_auth_lockout,
+    from_service_variant = FromServicePackets::CheckAuthLockout
+    )]
+    CheckAuthLockout {
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    },
+    #[packet(
+    service_method = Directory::record_auth_failure,
+    from_service_variant = FromServicePackets::RecordAuthFailure
+    )]
+    RecordAuthFailure {
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    },
+    #[packet(
+    service_method = Directory::record_auth_success,
+    from_service_variant = FromServicePackets::RecordAuthSuccess
+    )]
+    RecordAuthSuccess(String),
+    #[packet(
+    service_method = Directory::list_lockouts,
+    from_service_variant = FromServicePackets::ListLockouts
+    )]
+    ListLockouts,
+    #[packet(
+    service_method = Directory::clear_lockout,
+    from_service_variant = FromServicePackets::ClearLockout
+    )]
+    ClearLockout(LockoutKey),
+    #[packet(
+    service_method = Directory::get_groups,
+    from_service_variant = FromServicePackets::GetGroups
+    )]
+    GetGroups,
+    #[packet(
+    service_method = Directory::directory_capabilities,
+    from_service_variant = FromServicePackets::GetCapabilities
+    )]
+    GetCapabilities,
+    #[packet(
+    service_method = Directory::list_accounts,
+    from_service_variant = FromServicePackets::ListAccounts
+    )]
+    ListAccounts,
+    #[packet(
+    service_method = Directory::create_account,
+    from_service_variant = FromServicePackets::CreateAccount
+    )]
+    CreateAccount(NewAccount),
+    #[packet(
+    service_method = Directory::update_account,
+    from_service_variant = FromServicePackets::UpdateAccount
+    )]
+    UpdateAccount {
+        username: String,
+        update: AccountUpdate,
+    },
+    #[packet(
+    service_method = Directory::rename_account,
+    from_service_variant = FromServicePackets::RenameAccount
+    )]
+    RenameAccount {
+        username: String,
+        new_username: String,
+    },
+    #[packet(
+    service_method = Directory::set_account_active,
+    from_service_variant = FromServicePackets::SetAccountActive
+    )]
+    SetAccountActive { username: String, active: bool },
+    #[packet(
+    service_method = Directory::delete_account,
+    from_service_variant = FromServicePackets::DeleteAccount
+    )]
+    DeleteAccount(String),
+    #[packet(
+    service_method = Directory::set_password,
+    from_service_variant = FromServicePackets::SetPassword
+    )]
+    SetPassword { username: String, password: String },
+    #[packet(
+    service_method = Directory::add_email_address,
+    from_service_variant = FromServicePackets::AddEmailAddress
+    )]
+    AddEmailAddress {
+        username: String,
+        email_address: String,
+        email_type: EmailType,
+    },
+    #[packet(
+    service_method = Directory::remove_email_address,
+    from_service_variant = FromServicePackets::RemoveEmailAddress
+    )]
+    RemoveEmailAddress(String),
+    #[packet(
+    service_method = Directory::create_group,
+    from_service_variant = FromServicePackets::CreateGroup
+    )]
+    CreateGroup(NewGroup),
+    #[packet(
+    service_method = Directory::delete_group,
+    from_service_variant = FromServicePackets::DeleteGroup
+    )]
+    DeleteGroup(String),
+    #[packet(
+    service_method = Directory::list_group_members,
+    from_service_variant = FromServicePackets::ListGroupMembers
+    )]
+    ListGroupMembers(String),
+    #[packet(
+    service_method = Directory::add_group_member,
+    from_service_variant = FromServicePackets::AddGroupMember
+    )]
+    AddGroupMember { group: String, username: String },
+    #[packet(
+    service_method = Directory::remove_group_member,
+    from_service_variant = FromServicePackets::RemoveGroupMember
+    )]
+    RemoveGroupMember { group: String, username: String },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Archive)]
@@ -59,7 +251,38 @@ pub enum FromServiceSystemPackets {
 pub enum FromServicePackets {
     SystemPacket(FromServiceSystemPackets),
     GetAccount(Option<Account>),
+    GetAccountByEmail(Option<Account>),
+    GetMailingList(Option<MailingList>),
+    GetQuota(Option<Quota>),
     LoginAccount(Option<Account>),
+    CreateAppPassword(Option<NewAppPassword>),
+    ListAppPasswords(Vec<AppPassword>),
+    RevokeAppPassword(bool),
+    LoginAccountWithTotp(Option<Account>),
+    EnrollTotp(Option<TotpEnrollment>),
+    VerifyTotp(bool),
+    DisableTotp(bool),
+    CheckAuthLockout(Option<Lockout>),
+    RecordAuthFailure(Option<Lockout>),
+    RecordAuthSuccess(bool),
+    ListLockouts(Vec<Lockout>),
+    ClearLockout(bool),
+    GetGroups(Vec<String>),
+    GetCapabilities(DirectoryCapabilities),
+    ListAccounts(AdminResult<Vec<Account>>),
+    CreateAccount(AdminResult<Account>),
+    UpdateAccount(AdminResult<Account>),
+    RenameAccount(AdminResult<Account>),
+    SetAccountActive(AdminResult<()>),
+    DeleteAccount(AdminResult<bool>),
+    SetPassword(AdminResult<()>),
+    AddEmailAddress(AdminResult<()>),
+    RemoveEmailAddress(AdminResult<bool>),
+    CreateGroup(AdminResult<()>),
+    DeleteGroup(AdminResult<bool>),
+    ListGroupMembers(AdminResult<Vec<String>>),
+    AddGroupMember(AdminResult<()>),
+    RemoveGroupMember(AdminResult<bool>),
     /// If the account is valid then valid is true
     /// If the account is invalid then valid is false
     ///
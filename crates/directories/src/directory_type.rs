@@ -1,10 +1,21 @@
 use async_trait::async_trait;
 use auto_impl::auto_impl;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use utils::account::Account;
+use utils::admin::{
+    AccountUpdate, AdminError, AdminResult, DirectoryCapabilities, NewAccount, NewGroup,
+};
+use utils::app_password::{AppPassword, LoginProtocol, NewAppPassword};
+use utils::auth_failures::{Lockout, LockoutKey};
+use utils::common_types::EmailType;
+use utils::groups::MailingList;
+use utils::quota::Quota;
 use utils::service::Service;
 use utils::service_configuration::ServiceConfigurationResponse;
+use utils::two_factor::TotpEnrollment;
+use uuid::Uuid;
 
 use crate::ValidateDirectoryRequest;
 
@@ -18,13 +29,223 @@ pub trait Directory: Service {
     where
         Self: Sized;
     async fn get_account(&self, username: String) -> Result<Option<Account>, Self::ServiceError>;
+    /// Finds the account that owns the email address
+    ///
+    /// Only exact matches. Sub-addresses and catch-alls are handled by [resolve_recipient](crate::recipient::resolve_recipient)
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, Self::ServiceError>;
+    /// Finds the mailing list that receives mail sent to the email address
+    ///
+    /// Only groups with [GroupType::List](utils::groups::GroupType::List) are mailing lists
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, Self::ServiceError>;
 
+    /// The limits of the mailbox of an account or group. None if there are no limits
+    ///
+    /// The usage is counted by storage
+    async fn get_quota(&self, _mailbox_id: Uuid) -> Result<Option<Quota>, Self::ServiceError> {
+        Ok(None)
+    }
+
+    /// Checks the account password or an app password that allows the protocol
+    ///
+    /// Accounts with TOTP enabled only accept app passwords here.
+    /// Logins that can prompt for a code use [login_account_with_totp](Directory::login_account_with_totp)
     async fn login_account(
         &self,
         username: String,
         password: String,
+        protocol: LoginProtocol,
     ) -> Result<Option<Account>, Self::ServiceError>;
 
+    /// Checks the account password and, if TOTP is enabled, the TOTP or recovery code
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<Option<Account>, Self::ServiceError>;
+
+    /// Creates an app password for the account. None if the account does not exist
+    ///
+    /// Protocols that do not [accept app passwords](LoginProtocol::accepts_app_passwords) are ignored
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, Self::ServiceError>;
+
+    async fn list_app_passwords(
+        &self,
+        username: String,
+    ) -> Result<Vec<AppPassword>, Self::ServiceError>;
+
+    /// Returns false if the account has no app password with the id
+    async fn revoke_app_password(
+        &self,
+        username: String,
+        id: i64,
+    ) -> Result<bool, Self::ServiceError>;
+
+    /// Creates a new TOTP secret and recovery codes for the account
+    ///
+    /// TOTP is enabled once a code is checked with [verify_totp](Directory::verify_totp).
+    /// None if the account does not exist or already has TOTP enabled
+    async fn enroll_totp(
+        &self,
+        username: String,
+    ) -> Result<Option<TotpEnrollment>, Self::ServiceError>;
+
+    /// Checks a TOTP code or recovery code. A valid TOTP code finishes enrollment
+    ///
+    /// Each code can only be used once
+    async fn verify_totp(&self, username: String, code: String)
+        -> Result<bool, Self::ServiceError>;
+
+    /// Removes the TOTP secret and recovery codes. Returns false if there were none
+    async fn disable_totp(&self, username: String) -> Result<bool, Self::ServiceError>;
+
+    /// The active lockout on the remote address or username. Logins must be refused with a temporary error while locked
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError>;
+
+    /// Counts a failed login. Returns the lockout if this failure caused one
+    ///
+    /// Accounts may be deactivated after repeated lockouts
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError>;
+
+    /// Clears the failures counted for the username. Returns false if there were none
+    async fn record_auth_success(&self, username: String) -> Result<bool, Self::ServiceError>;
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, Self::ServiceError>;
+
+    /// Returns false if nothing was locked out or counted for the key
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, Self::ServiceError>;
+
+    // Administration. Directories that can not be changed keep the defaults
+
+    /// The changes this directory supports. Changes it does not support return [AdminError::NotSupported]
+    async fn directory_capabilities(&self) -> Result<DirectoryCapabilities, Self::ServiceError> {
+        Ok(DirectoryCapabilities::READ_ONLY)
+    }
+
+    async fn list_accounts(&self) -> Result<AdminResult<Vec<Account>>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    async fn create_account(
+        &self,
+        _account: NewAccount,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    async fn update_account(
+        &self,
+        _username: String,
+        _update: AccountUpdate,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    /// Changes the username. The account keeps its mailbox id, addresses, app passwords and group memberships
+    async fn rename_account(
+        &self,
+        _username: String,
+        _new_username: String,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    /// Inactive accounts can not log in or receive mail
+    async fn set_account_active(
+        &self,
+        _username: String,
+        _active: bool,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    /// Removes the account with its addresses, app passwords and group memberships. Returns false if it did not exist
+    async fn delete_account(
+        &self,
+        _username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    async fn set_password(
+        &self,
+        _username: String,
+        _password: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    /// A new [EmailType::Primary] address turns the current primary address into an alias
+    async fn add_email_address(
+        &self,
+        _username: String,
+        _email_address: String,
+        _email_type: EmailType,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    /// Removes an address of an account. Returns false if no account has it
+    async fn remove_email_address(
+        &self,
+        _email_address: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    async fn create_group(&self, _group: NewGroup) -> Result<AdminResult<()>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    /// Returns false if the group did not exist
+    async fn delete_group(&self, _name: String) -> Result<AdminResult<bool>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    /// The usernames of the members
+    async fn list_group_members(
+        &self,
+        _group: String,
+    ) -> Result<AdminResult<Vec<String>>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    async fn add_group_member(
+        &self,
+        _group: String,
+        _username: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
+    /// Returns false if the account was not a member
+    async fn remove_group_member(
+        &self,
+        _group: String,
+        _username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        Ok(Err(AdminError::NotSupported))
+    }
+
     async fn get_groups(&self) -> Result<Vec<String>, Self::ServiceError>;
 
     async fn validate_config(
@@ -52,12 +273,218 @@ impl<T: Directory> Directory for Arc<T> {
         (**self).get_account(username).await
     }
 
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        (**self).get_account_by_email(email_address).await
+    }
+
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, Self::ServiceError> {
+        (**self).get_mailing_list(email_address).await
+    }
+
+    async fn get_quota(&self, mailbox_id: Uuid) -> Result<Option<Quota>, Self::ServiceError> {
+        (**self).get_quota(mailbox_id).await
+    }
+
     async fn login_account(
         &self,
         username: String,
         password: String,
+        protocol: LoginProtocol,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        (**self).login_account(username, password, protocol).await
+    }
+
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
     ) -> Result<Option<Account>, Self::ServiceError> {
-        (**self).login_account(username, password).await
+        (**self)
+            .login_account_with_totp(username, password, code)
+            .await
+    }
+
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, Self::ServiceError> {
+        (**self)
+            .create_app_password(username, name, protocols)
+            .await
+    }
+
+    async fn list_app_passwords(
+        &self,
+        username: String,
+    ) -> Result<Vec<AppPassword>, Self::ServiceError> {
+        (**self).list_app_passwords(username).await
+    }
+
+    async fn revoke_app_password(
+        &self,
+        username: String,
+        id: i64,
+    ) -> Result<bool, Self::ServiceError> {
+        (**self).revoke_app_password(username, id).await
+    }
+
+    async fn enroll_totp(
+        &self,
+        username: String,
+    ) -> Result<Option<TotpEnrollment>, Self::ServiceError> {
+        (**self).enroll_totp(username).await
+    }
+
+    async fn verify_totp(
+        &self,
+        username: String,
+        code: String,
+    ) -> Result<bool, Self::ServiceError> {
+        (**self).verify_totp(username, code).await
+    }
+
+    async fn disable_totp(&self, username: String) -> Result<bool, Self::ServiceError> {
+        (**self).disable_totp(username).await
+    }
+
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        (**self).check_auth_lockout(remote, username).await
+    }
+
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        (**self).record_auth_failure(remote, username).await
+    }
+
+    async fn record_auth_success(&self, username: String) -> Result<bool, Self::ServiceError> {
+        (**self).record_auth_success(username).await
+    }
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, Self::ServiceError> {
+        (**self).list_lockouts().await
+    }
+
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, Self::ServiceError> {
+        (**self).clear_lockout(key).await
+    }
+
+    async fn directory_capabilities(&self) -> Result<DirectoryCapabilities, Self::ServiceError> {
+        (**self).directory_capabilities().await
+    }
+
+    async fn list_accounts(&self) -> Result<AdminResult<Vec<Account>>, Self::ServiceError> {
+        (**self).list_accounts().await
+    }
+
+    async fn create_account(
+        &self,
+        account: NewAccount,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        (**self).create_account(account).await
+    }
+
+    async fn update_account(
+        &self,
+        username: String,
+        update: AccountUpdate,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        (**self).update_account(username, update).await
+    }
+
+    async fn rename_account(
+        &self,
+        username: String,
+        new_username: String,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        (**self).rename_account(username, new_username).await
+    }
+
+    async fn set_account_active(
+        &self,
+        username: String,
+        active: bool,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        (**self).set_account_active(username, active).await
+    }
+
+    async fn delete_account(
+        &self,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        (**self).delete_account(username).await
+    }
+
+    async fn set_password(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        (**self).set_password(username, password).await
+    }
+
+    async fn add_email_address(
+        &self,
+        username: String,
+        email_address: String,
+        email_type: EmailType,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        (**self)
+            .add_email_address(username, email_address, email_type)
+            .await
+    }
+
+    async fn remove_email_address(
+        &self,
+        email_address: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        (**self).remove_email_address(email_address).await
+    }
+
+    async fn create_group(&self, group: NewGroup) -> Result<AdminResult<()>, Self::ServiceError> {
+        (**self).create_group(group).await
+    }
+
+    async fn delete_group(&self, name: String) -> Result<AdminResult<bool>, Self::ServiceError> {
+        (**self).delete_group(name).await
+    }
+
+    async fn list_group_members(
+        &self,
+        group: String,
+    ) -> Result<AdminResult<Vec<String>>, Self::ServiceError> {
+        (**self).list_group_members(group).await
+    }
+
+    async fn add_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        (**self).add_group_member(group, username).await
+    }
+
+    async fn remove_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        (**self).remove_group_member(group, username).await
     }
 
     async fn get_groups(&self) -> Result<Vec<String>, Self::ServiceError> {
@@ -2,10 +2,19 @@ use crate::directory_type::Directory;
 use utils::service::ServiceAccess;
 use uuid::Uuid;
 
+pub mod caching;
+pub mod chain;
 pub mod directory_service;
 pub mod directory_type;
+pub mod oauth;
+pub mod recipient;
 
 pub static SOCKET_NAME: &str = "nitro_mail_directory_service";
+/// The version of the packets exchanged with the directory service
+///
+/// rkyv archives carry no schema, so a client and a service built with different packets would misread each other.
+/// Bump it whenever a packet or a type it carries, such as [Account](utils::account::Account), changes
+pub const PROTOCOL_VERSION: u32 = 3;
 #[derive(Debug, Clone, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
 #[archive(compare(PartialEq), check_bytes)]
 pub struct ValidateDirectoryRequest {
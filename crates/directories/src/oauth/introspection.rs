@@ -0,0 +1,112 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::oauth::{Claims, TokenError, TokenValidator};
+
+/// [RFC 7662](https://www.rfc-editor.org/rfc/rfc7662) token introspection
+///
+/// # Example
+/// ```toml
+/// [oauth.validator]
+/// type = "introspection"
+/// endpoint = "https://sso.example.com/oauth2/introspect"
+/// client_id = "nitro_mail"
+/// client_secret = "<CLIENT_SECRET>"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntrospectionConfig {
+    pub endpoint: String,
+    /// Sent with HTTP Basic authentication
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+}
+
+/// Asks the authorization server if the token is active
+#[derive(Debug, Clone)]
+pub struct IntrospectionValidator {
+    client: reqwest::Client,
+    config: IntrospectionConfig,
+}
+impl IntrospectionValidator {
+    pub fn new(config: IntrospectionConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+}
+#[async_trait]
+impl TokenValidator for IntrospectionValidator {
+    async fn validate(&self, token: &str) -> Result<Option<Claims>, TokenError> {
+        let claims: Claims = self
+            .client
+            .post(&self.config.endpoint)
+            .basic_auth(&self.config.client_id, self.config.client_secret.as_ref())
+            .form(&[("token", token), ("token_type_hint", "access_token")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let active = claims
+            .get("active")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        Ok(active.then_some(claims))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::oauth::introspection::{IntrospectionConfig, IntrospectionValidator};
+    use crate::oauth::TokenValidator;
+
+    /// Answers one request with the body and returns the request it received
+    async fn serve_once(listener: TcpListener, body: serde_json::Value) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(&request).contains("token=") {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let body = body.to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8(request).unwrap()
+    }
+    async fn introspect(body: serde_json::Value) -> (Option<crate::oauth::Claims>, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let validator = IntrospectionValidator::new(IntrospectionConfig {
+            endpoint: format!("http://{}/introspect", listener.local_addr().unwrap()),
+            client_id: "nitro_mail".to_string(),
+            client_secret: Some("secret".to_string()),
+        });
+        let server = tokio::spawn(serve_once(listener, body));
+        let claims = validator.validate("abc").await.unwrap();
+        (claims, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    pub async fn test_introspection() {
+        let (claims, request) = introspect(json!({"active": true, "sub": "test"})).await;
+        assert_eq!(claims.unwrap().get("sub").unwrap(), "test");
+        assert!(request.starts_with("POST /introspect"));
+        assert!(request.contains("token=abc&token_type_hint=access_token"));
+        // nitro_mail:secret
+        assert!(request.contains("bml0cm9fbWFpbDpzZWNyZXQ="));
+
+        let (claims, _) = introspect(json!({"active": false})).await;
+        assert!(claims.is_none());
+    }
+}
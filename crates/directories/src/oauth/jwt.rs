@@ -0,0 +1,207 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::oauth::{Claims, TokenError, TokenValidator};
+
+/// # Example
+/// ```toml
+/// [oauth.validator]
+/// type = "jwt"
+/// jwks = "/etc/nitro_mail/jwks.json"
+/// issuer = "https://sso.example.com"
+/// audience = ["mail"]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtConfig {
+    /// A JSON Web Key Set file with the public keys of the issuer
+    pub jwks: PathBuf,
+    /// Checked against the `iss` claim if set
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// The `aud` claim must contain one of these if any are set
+    #[serde(default)]
+    pub audience: Vec<String>,
+}
+
+/// Validates JWTs signed by one of the keys in a local JWKS
+#[derive(Debug, Clone)]
+pub struct JwtValidator {
+    keys: JwkSet,
+    issuer: Option<String>,
+    audience: Vec<String>,
+}
+impl JwtValidator {
+    pub fn new(keys: JwkSet, issuer: Option<String>, audience: Vec<String>) -> Self {
+        Self {
+            keys,
+            issuer,
+            audience,
+        }
+    }
+    pub fn load(config: &JwtConfig) -> Result<Self, TokenError> {
+        let keys = std::fs::read_to_string(&config.jwks)?;
+        Ok(Self::new(
+            serde_json::from_str(&keys)?,
+            config.issuer.clone(),
+            config.audience.clone(),
+        ))
+    }
+
+    /// The key named by `kid`. Tokens without a `kid` can only be used with a single key
+    fn find_key(&self, kid: Option<&str>) -> Option<&Jwk> {
+        match kid {
+            Some(kid) => self.keys.find(kid),
+            None if self.keys.keys.len() == 1 => self.keys.keys.first(),
+            None => None,
+        }
+    }
+}
+#[async_trait]
+impl TokenValidator for JwtValidator {
+    async fn validate(&self, token: &str) -> Result<Option<Claims>, TokenError> {
+        let header = match decode_header(token) {
+            Ok(ok) => ok,
+            Err(error) => {
+                debug!("Invalid JWT header: {}", error);
+                return Ok(None);
+            }
+        };
+        let Some(jwk) = self.find_key(header.kid.as_deref()) else {
+            debug!("No key for JWT with kid {:?}", header.kid);
+            return Ok(None);
+        };
+        // The key decides the algorithm. The header can not switch it
+        if let Some(algorithm) = jwk.common.key_algorithm {
+            if algorithm.to_string() != format!("{:?}", header.alg) {
+                debug!("JWT algorithm {:?} does not match the key", header.alg);
+                return Ok(None);
+            }
+        }
+        let key = match DecodingKey::from_jwk(jwk) {
+            Ok(ok) => ok,
+            Err(error) => {
+                debug!("Unusable key in JWKS: {}", error);
+                return Ok(None);
+            }
+        };
+        let mut validation = Validation::new(header.alg);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if self.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audience);
+        }
+        match decode::<Claims>(token, &key, &validation) {
+            Ok(token) => Ok(Some(token.claims)),
+            Err(error) => {
+                debug!("Rejected JWT: {}", error);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use jsonwebtoken::jwk::JwkSet;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+
+    use crate::oauth::jwt::JwtValidator;
+    use crate::oauth::TokenValidator;
+
+    /// A freshly generated Ed25519 key and the JWKS with its public half
+    fn generate_key(kid: &str) -> (EncodingKey, JwkSet) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let jwks = json!({
+            "keys": [{
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "alg": "EdDSA",
+                "kid": kid,
+                "x": URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+            }]
+        });
+        (
+            EncodingKey::from_ed_der(pkcs8.as_ref()),
+            serde_json::from_value(jwks).unwrap(),
+        )
+    }
+    fn sign(key: &EncodingKey, kid: Option<&str>, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = kid.map(str::to_string);
+        encode(&header, &claims, key).unwrap()
+    }
+    fn expires() -> u64 {
+        jsonwebtoken::get_current_timestamp() + 600
+    }
+
+    #[tokio::test]
+    pub async fn test_jwt() {
+        let (key, jwks) = generate_key("main");
+        let validator = JwtValidator::new(
+            jwks,
+            Some("https://sso.example.com".to_string()),
+            vec!["mail".to_string()],
+        );
+        let claims = json!({
+            "sub": "test",
+            "iss": "https://sso.example.com",
+            "aud": "mail",
+            "exp": expires(),
+        });
+        let valid = validator
+            .validate(&sign(&key, Some("main"), claims.clone()))
+            .await
+            .unwrap()
+            .expect("Token should be valid");
+        assert_eq!(valid.get("sub").unwrap(), "test");
+        // The only key is used when there is no kid
+        assert!(validator
+            .validate(&sign(&key, None, claims.clone()))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(validator
+            .validate(&sign(&key, Some("other"), claims.clone()))
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut wrong_audience = claims.clone();
+        wrong_audience["aud"] = json!("calendar");
+        assert!(validator
+            .validate(&sign(&key, Some("main"), wrong_audience))
+            .await
+            .unwrap()
+            .is_none());
+        let mut expired = claims.clone();
+        expired["exp"] = json!(jsonwebtoken::get_current_timestamp() - 600);
+        assert!(validator
+            .validate(&sign(&key, Some("main"), expired))
+            .await
+            .unwrap()
+            .is_none());
+
+        // Signed by a key that is not in the JWKS
+        let (other_key, _) = generate_key("main");
+        assert!(validator
+            .validate(&sign(&other_key, Some("main"), claims))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(validator.validate("not a jwt").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,223 @@
+//! Authenticates OAuth 2.0 bearer tokens sent with SASL OAUTHBEARER or XOAUTH2
+//!
+//! A [TokenValidator] checks the token and returns its claims. The configured claim is then looked up in the [Directory]
+use std::error::Error;
+use std::fmt::Debug;
+use std::io;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use strum::{AsRefStr, Display, EnumIs, EnumString, IntoStaticStr};
+use thiserror::Error;
+use tracing::debug;
+
+use utils::account::Account;
+use utils::app_password::LoginProtocol;
+use utils::helper_types::EmailAddress;
+use utils::sasl::BearerCredentials;
+
+use crate::directory_type::Directory;
+use crate::oauth::introspection::{IntrospectionConfig, IntrospectionValidator};
+use crate::oauth::jwt::{JwtConfig, JwtValidator};
+
+pub mod introspection;
+pub mod jwt;
+
+pub type Claims = serde_json::Map<String, Value>;
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("Unable to read the JWKS file: {0}")]
+    ReadingKeys(#[from] io::Error),
+    #[error("Invalid JWKS: {0}")]
+    InvalidKeys(#[from] serde_json::Error),
+    #[error("Token introspection failed: {0}")]
+    Introspection(#[from] reqwest::Error),
+    #[error(transparent)]
+    Directory(Box<dyn Error + Send + Sync + 'static>),
+}
+
+/// Checks a bearer token
+#[async_trait]
+pub trait TokenValidator: Debug + Send + Sync + 'static {
+    /// Returns the claims of a valid token. None if the token was rejected
+    ///
+    /// Errors are only for failures that say nothing about the token, like an unreachable endpoint
+    async fn validate(&self, token: &str) -> Result<Option<Claims>, TokenError>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ValidatorConfig {
+    Jwt(JwtConfig),
+    Introspection(IntrospectionConfig),
+}
+
+/// How the claim is matched to an account
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    Serialize,
+    Deserialize,
+    AsRefStr,
+    IntoStaticStr,
+    EnumIs,
+    EnumString,
+    Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum AccountLookup {
+    /// The claim is the username
+    #[default]
+    Username,
+    /// The claim is an email address of the account
+    Email,
+}
+
+fn default_account_claim() -> String {
+    "sub".to_string()
+}
+
+/// # Example
+/// ```toml
+/// [oauth]
+/// account_claim = "email"
+/// account_lookup = "email"
+///
+/// [oauth.validator]
+/// type = "jwt"
+/// jwks = "/etc/nitro_mail/jwks.json"
+/// issuer = "https://sso.example.com"
+/// audience = ["mail"]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthConfig {
+    /// The claim that identifies the account
+    #[serde(default = "default_account_claim")]
+    pub account_claim: String,
+    #[serde(default)]
+    pub account_lookup: AccountLookup,
+    pub validator: ValidatorConfig,
+}
+
+/// The result of [OAuthAuthenticator::authenticate]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BearerAuthentication {
+    Authenticated(Account),
+    /// The token is valid but its account can not log in, or the client asked to act as someone else.
+    /// Holds the username of the account the token belongs to
+    Refused(String),
+    /// The token was rejected or belongs to no account. Nothing the client sent identifies an account
+    Invalid,
+}
+impl BearerAuthentication {
+    pub fn account(self) -> Option<Account> {
+        match self {
+            BearerAuthentication::Authenticated(account) => Some(account),
+            BearerAuthentication::Refused(_) | BearerAuthentication::Invalid => None,
+        }
+    }
+    /// The username failures should be counted against. None if only the client address is known
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            BearerAuthentication::Authenticated(account) => Some(&account.username),
+            BearerAuthentication::Refused(username) => Some(username),
+            BearerAuthentication::Invalid => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OAuthAuthenticator {
+    validator: Arc<dyn TokenValidator>,
+    account_claim: String,
+    account_lookup: AccountLookup,
+}
+impl OAuthAuthenticator {
+    pub fn new(
+        validator: Arc<dyn TokenValidator>,
+        account_claim: impl Into<String>,
+        account_lookup: AccountLookup,
+    ) -> Self {
+        Self {
+            validator,
+            account_claim: account_claim.into(),
+            account_lookup,
+        }
+    }
+    /// Reads the JWKS file for [ValidatorConfig::Jwt]
+    pub fn from_config(config: &OAuthConfig) -> Result<Self, TokenError> {
+        let validator: Arc<dyn TokenValidator> = match &config.validator {
+            ValidatorConfig::Jwt(jwt) => Arc::new(JwtValidator::load(jwt)?),
+            ValidatorConfig::Introspection(introspection) => {
+                Arc::new(IntrospectionValidator::new(introspection.clone()))
+            }
+        };
+        Ok(Self::new(
+            validator,
+            config.account_claim.clone(),
+            config.account_lookup,
+        ))
+    }
+
+    /// Validates the token and finds its account
+    ///
+    /// If the client asked to act as a user it must be the account the token belongs to.
+    /// Accounts that can not log in with the protocol, or are inactive, are refused
+    pub async fn authenticate<D: Directory>(
+        &self,
+        directory: &D,
+        credentials: &BearerCredentials,
+        protocol: LoginProtocol,
+    ) -> Result<BearerAuthentication, TokenError> {
+        let Some(claims) = self.validator.validate(&credentials.token).await? else {
+            return Ok(BearerAuthentication::Invalid);
+        };
+        let Some(claim) = claims.get(&self.account_claim).and_then(Value::as_str) else {
+            debug!("Token is missing the {} claim", self.account_claim);
+            return Ok(BearerAuthentication::Invalid);
+        };
+        let account = match self.account_lookup {
+            AccountLookup::Username => directory.get_account(claim.to_string()).await,
+            AccountLookup::Email => directory.get_account_by_email(claim.to_string()).await,
+        }
+        .map_err(|e| TokenError::Directory(Box::new(e)))?;
+        let Some(account) = account else {
+            return Ok(BearerAuthentication::Invalid);
+        };
+        if !account.can_login(protocol) {
+            debug!("{} can not log in with {}", account.username, protocol);
+            return Ok(BearerAuthentication::Refused(account.username));
+        }
+        if let Some(authzid) = &credentials.authzid {
+            if !Self::is_same_user(authzid, claim, &account) {
+                debug!(
+                    "Token for {} can not be used to act as {}",
+                    account.username, authzid
+                );
+                return Ok(BearerAuthentication::Refused(account.username));
+            }
+        }
+        Ok(BearerAuthentication::Authenticated(account))
+    }
+
+    fn is_same_user(authzid: &str, claim: &str, account: &Account) -> bool {
+        if authzid == account.username || authzid == claim {
+            return true;
+        }
+        match (
+            EmailAddress::new_lenient(authzid),
+            EmailAddress::new_lenient(claim),
+        ) {
+            (Ok(authzid), Ok(claim)) => authzid == claim,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use utils::account::Account;
+use utils::configs::domain_configs::DomainConfiguration;
+use utils::helper_types::EmailAddress;
+
+use crate::directory_type::Directory;
+
+/// How a recipient was matched to its account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecipientMatch {
+    /// The address belongs to the account
+    Exact,
+    /// The address without its detail belongs to the account
+    SubAddress,
+    /// Nothing matched and the domain has a catch-all
+    CatchAll,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedRecipient {
+    pub account: Account,
+    /// The address the message was sent to
+    pub recipient: EmailAddress,
+    /// The detail of a sub-address. `tag` in `user+tag@example.com`
+    ///
+    /// Can be used to file the message into a folder of the same name
+    pub detail: Option<String>,
+    pub match_type: RecipientMatch,
+}
+
+/// Finds the account a message sent to `recipient` should be delivered to
+///
+/// In order:
+/// 1. The exact address
+/// 2. The address with the detail removed using the domain's `sub_address_separator`
+/// 3. The domain's `catch_all`
+///
+/// Domains missing from the [DomainConfiguration] only get exact matches
+pub async fn resolve_recipient<D: Directory>(
+    directory: &D,
+    domains: &DomainConfiguration,
+    recipient: &EmailAddress,
+) -> Result<Option<ResolvedRecipient>, D::ServiceError> {
+    let resolved = |account: Account, detail: Option<String>, match_type: RecipientMatch| {
+        Some(ResolvedRecipient {
+            account,
+            recipient: recipient.clone(),
+            detail,
+            match_type,
+        })
+    };
+    if let Some(account) = directory
+        .get_account_by_email(recipient.to_string())
+        .await?
+    {
+        return Ok(resolved(account, None, RecipientMatch::Exact));
+    }
+    let Some(domain) = domains.get_domain(recipient.domain()) else {
+        return Ok(None);
+    };
+    if let Some((base, detail)) = recipient.split_sub_address(&domain.sub_address_separator) {
+        if let Some(account) = directory.get_account_by_email(base.to_string()).await? {
+            return Ok(resolved(account, Some(detail), RecipientMatch::SubAddress));
+        }
+    }
+    if let Some(catch_all) = &domain.catch_all {
+        if let Some(account) = directory
+            .get_account_by_email(catch_all.to_string())
+            .await?
+        {
+            return Ok(resolved(account, None, RecipientMatch::CatchAll));
+        }
+    }
+    Ok(None)
+}
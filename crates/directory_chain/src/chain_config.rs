@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+use directories::chain::ChainConfig;
+use utils::configs::{Config, ConfigName};
+
+/// A directory backend. Each one reads its own config file from the working directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChainedDirectory {
+    /// `database.directory.toml`
+    Sql,
+    /// `ldap.directory.toml`
+    Ldap,
+    /// `file.directory.toml`
+    File,
+}
+
+fn default_directories() -> Vec<ChainedDirectory> {
+    vec![ChainedDirectory::Sql]
+}
+
+/// # Example
+/// ```toml
+/// mode = "first_match"
+/// directories = ["sql", "ldap"]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainServiceConfig {
+    #[serde(flatten)]
+    pub chain: ChainConfig,
+    /// Asked in order
+    #[serde(default = "default_directories")]
+    pub directories: Vec<ChainedDirectory>,
+}
+impl Config for ChainServiceConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        ChainConfig::config_header()
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ChainConfig::config_name()
+    }
+}
+impl Default for ChainServiceConfig {
+    fn default() -> Self {
+        ChainServiceConfig {
+            chain: ChainConfig::default(),
+            directories: default_directories(),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::env::current_dir;
+
+use sea_orm::DatabaseConnection;
+
+use directories::chain::ChainDirectory;
+use directories::directory_service::DirectoryService;
+use directories::directory_type::Directory;
+use directory_file::file_config::FileDirectoryConfig;
+use directory_file::file_directory::FileDirectory;
+use directory_ldap::ldap_config::LdapConfig;
+use directory_ldap::ldap_directory::LdapDirectory;
+use directory_sql::database_config::DatabaseConfig;
+use directory_sql::database_directory::DatabaseDirectory;
+use utils::configs::Config;
+
+use crate::chain_config::{ChainServiceConfig, ChainedDirectory};
+
+pub mod chain_config;
+
+#[tokio::main]
+async fn main() {
+    let working_directory = current_dir().unwrap();
+    let config = ChainServiceConfig::get_or_save_default(working_directory.clone()).unwrap();
+    let mut directory = ChainDirectory::load(config.chain).await.unwrap();
+    for chained in config.directories {
+        directory = match chained {
+            ChainedDirectory::Sql => {
+                let config =
+                    DatabaseConfig::get_or_save_default(working_directory.clone()).unwrap();
+                directory.with(
+                    DatabaseDirectory::<DatabaseConnection>::load(config)
+                        .await
+                        .unwrap(),
+                )
+            }
+            ChainedDirectory::Ldap => {
+                let config = LdapConfig::get_or_save_default(working_directory.clone()).unwrap();
+                directory.with(LdapDirectory::load(config).await.unwrap())
+            }
+            ChainedDirectory::File => {
+                let config =
+                    FileDirectoryConfig::get_or_save_default(working_directory.clone()).unwrap();
+                directory.with(FileDirectory::load(config).await.unwrap())
+            }
+        };
+    }
+    let service = DirectoryService::new(directory);
+    service.run().await;
+}
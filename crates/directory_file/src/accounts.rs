@@ -0,0 +1,442 @@
+//! The accounts file. Either TOML or passwd style lines
+use std::path::Path;
+
+use ahash::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use utils::account::Account;
+use utils::admin::AccountAddress;
+use utils::common_types::{AccountType, EmailType};
+use utils::configs::{Config, ConfigName, IOOrToml};
+use utils::groups::{Group, GroupType, MailingList, PostingPolicy};
+use utils::helper_types::{EmailAddress, Password};
+use utils::quota::Quota;
+
+#[derive(Debug, Error)]
+pub enum AccountsError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Toml(#[from] IOOrToml),
+    #[error("Line {line}: {message}")]
+    Passwd { line: usize, message: String },
+    #[error("{0} is used more than once")]
+    Duplicate(String),
+    #[error("Invalid email address {0}")]
+    InvalidEmailAddress(String),
+    #[error("Group {group} has unknown member {member}")]
+    UnknownMember { group: String, member: String },
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// # Example
+/// ```toml
+/// [[accounts]]
+/// username = "john"
+/// password = "$argon2id$v=19$m=19456,t=2,p=1$..."
+/// email = "john@example.com"
+/// aliases = ["j@example.com"]
+/// quota = { storage = 1073741824 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileAccount {
+    pub username: String,
+    /// The username if not set
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// A password hash. `{SCHEME}` prefixes from Dovecot and LDAP are accepted
+    pub password: String,
+    #[serde(default = "default_active")]
+    pub active: bool,
+    #[serde(default)]
+    pub account_type: AccountType,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub quota: Quota,
+    /// Derived from the username if not set. Set it to the old id when renaming an account so it keeps its mail
+    #[serde(default)]
+    pub mailbox_id: Option<Uuid>,
+}
+
+/// # Example
+/// ```toml
+/// [[groups]]
+/// name = "staff"
+/// group_type = "List"
+/// list_address = "staff@example.com"
+/// posting_policy = "MembersOnly"
+/// members = ["john"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileGroup {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub group_type: GroupType,
+    /// Required for mail to reach a [GroupType::List]
+    #[serde(default)]
+    pub list_address: Option<String>,
+    #[serde(default)]
+    pub posting_policy: PostingPolicy,
+    /// Usernames
+    #[serde(default)]
+    pub members: Vec<String>,
+    /// Derived from the name if not set
+    #[serde(default)]
+    pub mailbox_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountsFile {
+    #[serde(default)]
+    pub accounts: Vec<FileAccount>,
+    #[serde(default)]
+    pub groups: Vec<FileGroup>,
+}
+impl Config for AccountsFile {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/file_directory")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("accounts.toml")
+    }
+}
+impl AccountsFile {
+    /// TOML if the file ends in `.toml`, otherwise passwd style
+    pub fn read(path: &Path) -> Result<Self, AccountsError> {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            Ok(Self::load_from_path(path.to_path_buf())?)
+        } else {
+            Self::parse_passwd(&std::fs::read_to_string(path)?)
+        }
+    }
+
+    /// One account per line as `username:hash[:email[,alias...]]`
+    ///
+    /// Blank lines and lines starting with `#` are skipped. A line without addresses is the htpasswd format
+    pub fn parse_passwd(content: &str) -> Result<Self, AccountsError> {
+        let mut accounts = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| AccountsError::Passwd {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let (username, rest) = line
+                .split_once(':')
+                .ok_or_else(|| error("Expected username:hash"))?;
+            // Hashes such as {SHA512-CRYPT}$6$... never contain ':'
+            let (password, addresses) = match rest.split_once(':') {
+                Some((password, addresses)) => (password, Some(addresses)),
+                None => (rest, None),
+            };
+            if username.is_empty() || password.is_empty() {
+                return Err(error("Missing username or password hash"));
+            }
+            let mut addresses = addresses
+                .into_iter()
+                .flat_map(|addresses| addresses.split(','))
+                .map(str::trim)
+                .filter(|address| !address.is_empty())
+                .map(str::to_string);
+            accounts.push(FileAccount {
+                username: username.to_string(),
+                name: None,
+                description: None,
+                password: password.to_string(),
+                active: true,
+                account_type: AccountType::default(),
+                email: addresses.next(),
+                aliases: addresses.collect(),
+                quota: Quota::default(),
+                mailbox_id: None,
+            });
+        }
+        Ok(Self {
+            accounts,
+            groups: vec![],
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccountEntry {
+    pub account: Account,
+    pub password: Password,
+    /// Normalized
+    pub email: Option<String>,
+}
+
+/// The accounts file indexed for lookups
+#[derive(Debug, Clone, Default)]
+pub struct Accounts {
+    pub accounts: HashMap<String, AccountEntry>,
+    /// Normalized address to username and the type of address
+    pub addresses: HashMap<String, (String, EmailType)>,
+    /// Normalized list address to index in `groups`
+    pub lists: HashMap<String, usize>,
+    pub groups: Vec<FileGroup>,
+    pub group_namespace: Uuid,
+}
+impl Accounts {
+    fn normalize(address: &str) -> Result<String, AccountsError> {
+        EmailAddress::new_lenient(address)
+            .map(Into::into)
+            .map_err(|_| AccountsError::InvalidEmailAddress(address.to_string()))
+    }
+    /// Fails on duplicate usernames, addresses or mailbox ids and unknown group members
+    pub fn index(
+        file: AccountsFile,
+        account_namespace: &Uuid,
+        group_namespace: &Uuid,
+    ) -> Result<Self, AccountsError> {
+        let mut result = Accounts {
+            group_namespace: *group_namespace,
+            ..Default::default()
+        };
+        let mut mailbox_ids = HashSet::default();
+        for account in file.accounts {
+            let mailbox_id = account.mailbox_id.unwrap_or_else(|| {
+                Account::generate_mailbox_id(account_namespace, &account.username)
+            });
+            if !mailbox_ids.insert(mailbox_id) {
+                return Err(AccountsError::Duplicate(mailbox_id.to_string()));
+            }
+            let mut add_address = |address: &str, email_type: EmailType| {
+                let address = Self::normalize(address)?;
+                if result
+                    .addresses
+                    .insert(address.clone(), (account.username.clone(), email_type))
+                    .is_some()
+                {
+                    return Err(AccountsError::Duplicate(address));
+                }
+                Ok(address)
+            };
+            let email = account
+                .email
+                .as_deref()
+                .map(|email| add_address(email, EmailType::Primary))
+                .transpose()?;
+            let mut addresses: Vec<AccountAddress> = email
+                .iter()
+                .map(|email| AccountAddress {
+                    email_address: email.clone(),
+                    email_type: EmailType::Primary,
+                })
+                .collect();
+            for alias in &account.aliases {
+                addresses.push(AccountAddress {
+                    email_address: add_address(alias, EmailType::Alias)?,
+                    email_type: EmailType::Alias,
+                });
+            }
+            let entry = AccountEntry {
+                account: Account {
+                    name: account.name.unwrap_or_else(|| account.username.clone()),
+                    username: account.username.clone(),
+                    description: account.description,
+                    account_type: account.account_type,
+                    mailbox_id,
+                    active: account.active,
+                    quota: account.quota,
+                    addresses,
+                    groups: vec![],
+                },
+                password: Password::new_hashed(account.password),
+                email,
+            };
+            if result
+                .accounts
+                .insert(account.username.clone(), entry)
+                .is_some()
+            {
+                return Err(AccountsError::Duplicate(account.username));
+            }
+        }
+        for (index, group) in file.groups.iter().enumerate() {
+            if let Some(member) = group
+                .members
+                .iter()
+                .find(|member| !result.accounts.contains_key(*member))
+            {
+                return Err(AccountsError::UnknownMember {
+                    group: group.name.clone(),
+                    member: member.clone(),
+                });
+            }
+            for member in &group.members {
+                if let Some(entry) = result.accounts.get_mut(member) {
+                    entry.account.groups.push(group.name.clone());
+                }
+            }
+            let Some(list_address) = &group.list_address else {
+                continue;
+            };
+            let list_address = Self::normalize(list_address)?;
+            if result.addresses.contains_key(&list_address)
+                || result.lists.insert(list_address.clone(), index).is_some()
+            {
+                return Err(AccountsError::Duplicate(list_address));
+            }
+        }
+        result.groups = file.groups;
+        Ok(result)
+    }
+
+    pub fn get_by_email(&self, address: &str) -> Option<&AccountEntry> {
+        let address = Self::normalize(address).ok()?;
+        let (username, _) = self.addresses.get(&address)?;
+        self.accounts.get(username)
+    }
+
+    pub fn get_mailing_list(&self, address: &str) -> Option<MailingList> {
+        let address = Self::normalize(address).ok()?;
+        let group = &self.groups[*self.lists.get(&address)?];
+        if group.group_type != GroupType::List {
+            return None;
+        }
+        let members = group
+            .members
+            .iter()
+            .filter_map(|member| self.accounts.get(member))
+            .filter(|member| member.account.active)
+            .filter_map(|member| member.email.clone())
+            .collect();
+        Some(MailingList {
+            group: Group {
+                group_type: group.group_type.clone(),
+                name: group.name.clone(),
+                description: group.description.clone(),
+                mailbox_id: group.mailbox_id.unwrap_or_else(|| {
+                    Group::generate_mailbox_id(&self.group_namespace, &group.name)
+                }),
+            },
+            list_address: address,
+            posting_policy: group.posting_policy,
+            members,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use utils::account::Account;
+
+    use crate::accounts::{Accounts, AccountsError, AccountsFile};
+
+    #[test]
+    pub fn test_parse_passwd() {
+        let file = AccountsFile::parse_passwd(
+            "# comment\n\
+             john:$2y$05$abc:john@example.com, j@example.com\n\
+             \n\
+             jane:{SHA512-CRYPT}$6$salt$hash\n",
+        )
+        .unwrap();
+        assert_eq!(file.accounts.len(), 2);
+        assert_eq!(file.accounts[0].password, "$2y$05$abc");
+        assert_eq!(file.accounts[0].email.as_deref(), Some("john@example.com"));
+        assert_eq!(file.accounts[0].aliases, vec!["j@example.com"]);
+        assert_eq!(file.accounts[1].password, "{SHA512-CRYPT}$6$salt$hash");
+        assert_eq!(file.accounts[1].email, None);
+
+        assert!(matches!(
+            AccountsFile::parse_passwd("john\n"),
+            Err(AccountsError::Passwd { line: 1, .. })
+        ));
+    }
+    #[test]
+    pub fn test_index() {
+        let file: AccountsFile = toml::from_str(
+            r#"
+            [[accounts]]
+            username = "john"
+            password = "$2y$05$abc"
+            email = "john@Example.com"
+            quota = { storage = 1024 }
+            [[accounts]]
+            username = "jane"
+            password = "$2y$05$abc"
+            email = "jane@example.com"
+            active = false
+            [[groups]]
+            name = "staff"
+            list_address = "staff@example.com"
+            members = ["john", "jane"]
+            "#,
+        )
+        .unwrap();
+        let accounts = Accounts::index(file.clone(), &Uuid::nil(), &Uuid::nil()).unwrap();
+        let john = accounts.get_by_email("john@EXAMPLE.com").unwrap();
+        assert_eq!(john.account.username, "john");
+        assert_eq!(john.account.name, "john");
+        assert_eq!(john.account.primary_address(), Some("john@example.com"));
+        assert_eq!(john.account.groups, vec!["staff"]);
+        assert_eq!(john.account.quota.storage, 1024);
+        let list = accounts.get_mailing_list("staff@example.com").unwrap();
+        // Inactive accounts do not receive list mail
+        assert_eq!(list.members, vec!["john@example.com"]);
+
+        let mut duplicate = file.clone();
+        duplicate.accounts[1].email = Some("john@example.com".to_string());
+        assert!(matches!(
+            Accounts::index(duplicate, &Uuid::nil(), &Uuid::nil()),
+            Err(AccountsError::Duplicate(_))
+        ));
+        assert_eq!(
+            john.account.mailbox_id,
+            Account::generate_mailbox_id(&Uuid::nil(), "john")
+        );
+
+        // An account renamed by hand keeps its mailbox id
+        let mut renamed = file.clone();
+        renamed.accounts[0].username = "johnny".to_string();
+        renamed.accounts[0].mailbox_id = Some(john.account.mailbox_id);
+        renamed.groups[0].members[0] = "johnny".to_string();
+        let renamed = Accounts::index(renamed, &Uuid::nil(), &Uuid::nil()).unwrap();
+        assert_eq!(
+            renamed.accounts["johnny"].account.mailbox_id,
+            john.account.mailbox_id
+        );
+        let mut duplicate = file.clone();
+        duplicate.accounts[1].mailbox_id = Some(john.account.mailbox_id);
+        assert!(matches!(
+            Accounts::index(duplicate, &Uuid::nil(), &Uuid::nil()),
+            Err(AccountsError::Duplicate(_))
+        ));
+
+        let mut unknown = file;
+        unknown.groups[0].members.push("nobody".to_string());
+        assert!(matches!(
+            Accounts::index(unknown, &Uuid::nil(), &Uuid::nil()),
+            Err(AccountsError::UnknownMember { .. })
+        ));
+    }
+}
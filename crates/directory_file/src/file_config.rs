@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use utils::configs::brute_force::BruteForceConfig;
+use utils::configs::password::PasswordConfig;
+use utils::configs::two_factor::TwoFactorConfig;
+use utils::configs::{Config, ConfigDuration, ConfigName};
+
+fn default_accounts_file() -> PathBuf {
+    PathBuf::from("accounts.toml")
+}
+fn default_state_file() -> PathBuf {
+    PathBuf::from("accounts.state.toml")
+}
+fn default_reload_interval() -> ConfigDuration {
+    Duration::seconds(5).into()
+}
+
+/// Accounts and groups are read from `accounts_file`. It is reloaded when it changes.
+/// Namespaces, app passwords and TOTP secrets are written to `state_file`
+///
+/// The mailbox ids of accounts and groups without one in `accounts_file` are derived from
+/// their name and a namespace. A new state file stores `account_namespace` and `group_namespace`,
+/// or random namespaces if they are not set. After that the stored namespaces are used
+///
+/// # Example
+/// ```toml
+/// accounts_file = "accounts.toml"
+/// state_file = "accounts.state.toml"
+/// reload_interval = "5s"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDirectoryConfig {
+    /// TOML if it ends in `.toml`, otherwise `username:hash[:email[,alias...]]` lines
+    #[serde(default = "default_accounts_file")]
+    pub accounts_file: PathBuf,
+    #[serde(default = "default_state_file")]
+    pub state_file: PathBuf,
+    /// How often the accounts file is checked for changes
+    #[serde(default = "default_reload_interval")]
+    pub reload_interval: ConfigDuration,
+    #[serde(default)]
+    pub password: PasswordConfig,
+    #[serde(default)]
+    pub two_factor: TwoFactorConfig,
+    #[serde(default)]
+    pub brute_force: BruteForceConfig,
+    /// The namespaces nitro_mail was installed with
+    #[serde(default)]
+    pub account_namespace: Option<Uuid>,
+    #[serde(default)]
+    pub group_namespace: Option<Uuid>,
+}
+impl Config for FileDirectoryConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/file_directory")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("file.directory.toml")
+    }
+}
+impl Default for FileDirectoryConfig {
+    fn default() -> Self {
+        FileDirectoryConfig {
+            accounts_file: default_accounts_file(),
+            state_file: default_state_file(),
+            reload_interval: default_reload_interval(),
+            password: PasswordConfig::default(),
+            two_factor: TwoFactorConfig::default(),
+            brute_force: BruteForceConfig::default(),
+            account_namespace: None,
+            group_namespace: None,
+        }
+    }
+}
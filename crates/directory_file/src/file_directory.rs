@@ -0,0 +1,671 @@
+use std::convert::Infallible;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::future::Ready;
+use parking_lot::{Mutex, RwLock};
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+use directories::directory_type::Directory;
+use directories::ValidateDirectoryRequest;
+use utils::account::Account;
+use utils::app_password::{
+    generate_app_password, normalize_app_password, AppPassword, LoginProtocol, NewAppPassword,
+};
+use utils::auth_failures::{AuthFailureTracker, Lockout, LockoutKey};
+use utils::configs::IOOrToml;
+use utils::groups::MailingList;
+use utils::helper_types::password::PasswordErrors;
+use utils::helper_types::Password;
+use utils::quota::Quota;
+use utils::service::{Service, ServiceAccess};
+use utils::service_configuration::{GitInfo, ServiceConfigurationResponse, ServiceType};
+use utils::two_factor::{
+    enrollment, generate_recovery_codes, generate_totp_secret, normalize_recovery_code,
+    verify_totp, TotpEnrollment, TwoFactorError,
+};
+use uuid::Uuid;
+
+use crate::accounts::{AccountEntry, Accounts, AccountsError, AccountsFile};
+use crate::file_config::FileDirectoryConfig;
+use crate::state::{DirectoryState, StoredAppPassword, StoredTotp};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Unable to load the accounts file: {0}")]
+    Accounts(#[from] AccountsError),
+    #[error("Unable to load the state file: {0}")]
+    State(#[from] IOOrToml),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Password(#[from] PasswordErrors),
+    #[error(transparent)]
+    TwoFactor(#[from] TwoFactorError),
+}
+
+/// The modification time and length of the accounts file when it was read
+type FileVersion = Option<(SystemTime, u64)>;
+
+#[derive(Debug)]
+pub struct FileDirectoryInner {
+    pub(crate) config: FileDirectoryConfig,
+    /// Replaced as a whole on reload
+    pub(crate) accounts: RwLock<Arc<Accounts>>,
+    pub(crate) version: Mutex<FileVersion>,
+    pub(crate) state: Mutex<DirectoryState>,
+    pub(crate) auth_failures: AuthFailureTracker,
+    /// If load created the state with new namespaces
+    pub(crate) new_install: bool,
+}
+
+/// A directory read from a file of accounts and groups
+///
+/// Passwords are never rehashed because the accounts file is only edited by hand
+#[derive(Debug, Clone)]
+pub struct FileDirectory(pub(crate) Arc<FileDirectoryInner>);
+impl ServiceAccess for FileDirectory {
+    type ServiceResponse = Self;
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn get_service(&self) -> Self::Future {
+        futures::future::ready(Ok(self.clone()))
+    }
+}
+impl FileDirectory {
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+    fn file_version(path: &Path) -> FileVersion {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+    /// The state always has namespaces once [load](Directory::load) returns
+    fn read_accounts(
+        path: &Path,
+        state: &DirectoryState,
+    ) -> Result<(Accounts, FileVersion), Error> {
+        let version = Self::file_version(path);
+        let accounts = Accounts::index(
+            AccountsFile::read(path)?,
+            &state.account_namespace.unwrap_or_default(),
+            &state.group_namespace.unwrap_or_default(),
+        )?;
+        Ok((accounts, version))
+    }
+    fn replace_accounts(&self) -> Result<(), Error> {
+        let state = self.0.state.lock().clone();
+        let (accounts, version) = Self::read_accounts(&self.0.config.accounts_file, &state)?;
+        *self.0.accounts.write() = Arc::new(accounts);
+        *self.0.version.lock() = version;
+        Ok(())
+    }
+
+    /// Reads the accounts file again if it changed. Returns true if it was reloaded
+    ///
+    /// The current accounts are kept if the file is invalid
+    pub fn reload(&self) -> Result<bool, Error> {
+        if Self::file_version(&self.0.config.accounts_file) == *self.0.version.lock() {
+            return Ok(false);
+        }
+        self.replace_accounts()?;
+        Ok(true)
+    }
+    /// Checks the accounts file every `reload_interval` until the directory is dropped
+    fn watch(&self) {
+        let interval = self
+            .0
+            .config
+            .reload_interval
+            .to_std()
+            .unwrap_or(Duration::from_secs(5));
+        let directory: Weak<FileDirectoryInner> = Arc::downgrade(&self.0);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                let Some(directory) = directory.upgrade() else {
+                    return;
+                };
+                match FileDirectory(directory).reload() {
+                    Ok(true) => info!("Reloaded the accounts file"),
+                    Ok(false) => {}
+                    Err(error) => error!("Keeping the current accounts: {}", error),
+                }
+            }
+        });
+    }
+
+    fn accounts(&self) -> Arc<Accounts> {
+        self.0.accounts.read().clone()
+    }
+    fn active_account(&self, username: &str) -> Option<AccountEntry> {
+        let accounts = self.accounts();
+        let entry = accounts.accounts.get(username)?;
+        if !entry.account.active || self.0.state.lock().is_deactivated(username) {
+            return None;
+        }
+        Some(entry.clone())
+    }
+    /// Applies the change and writes the state file. Nothing changes if writing fails
+    fn update_state<T>(&self, update: impl FnOnce(&mut DirectoryState) -> T) -> Result<T, Error> {
+        let mut state = self.0.state.lock();
+        let mut new_state = state.clone();
+        let result = update(&mut new_state);
+        if new_state != *state {
+            new_state.write(&self.0.config.state_file)?;
+            *state = new_state;
+        }
+        Ok(result)
+    }
+
+    fn check_account_password(entry: &AccountEntry, password: &str) -> bool {
+        match entry.password.check_password(password) {
+            Ok(result) => result,
+            Err(error) => {
+                error!(
+                    "Unable to check password for {}: {}",
+                    entry.account.username, error
+                );
+                false
+            }
+        }
+    }
+    /// Checks the password against the app passwords of the account that allow the protocol
+    ///
+    /// Updates the last used time of the matching app password
+    fn check_app_passwords(
+        &self,
+        username: &str,
+        password: &str,
+        protocol: LoginProtocol,
+    ) -> Result<bool, Error> {
+        let password = normalize_app_password(password);
+        let app_passwords: Vec<StoredAppPassword> = self
+            .0
+            .state
+            .lock()
+            .app_passwords
+            .iter()
+            .filter(|app_password| app_password.username == username)
+            .filter(|app_password| AppPassword::from(*app_password).allows(protocol))
+            .cloned()
+            .collect();
+        for app_password in app_passwords {
+            match Password::new_hashed(app_password.password).check_password(&password) {
+                Ok(true) => {
+                    let now = Self::now() as i64;
+                    self.update_state(|state| {
+                        let stored = state
+                            .app_passwords
+                            .iter_mut()
+                            .find(|stored| stored.id == app_password.id);
+                        if let Some(stored) = stored {
+                            stored.last_used = Some(now);
+                        }
+                    })?;
+                    return Ok(true);
+                }
+                Ok(false) => {}
+                Err(error) => {
+                    error!(
+                        "Unable to check app password {} for {}: {}",
+                        app_password.id, username, error
+                    );
+                }
+            }
+        }
+        Ok(false)
+    }
+    /// Checks the TOTP code and marks its time step as used
+    fn check_totp_code(&self, totp: &StoredTotp, code: &str) -> Result<bool, Error> {
+        let secret = STANDARD
+            .decode(&totp.secret)
+            .map_err(|_| TwoFactorError::InvalidEncryptionKey)?;
+        let secret = self.0.config.two_factor.secret_key()?.decrypt(&secret)?;
+        let Some(step) = verify_totp(&secret, code, Self::now(), totp.last_step) else {
+            return Ok(false);
+        };
+        // Another login may have used the step since the secret was read
+        self.update_state(|state| {
+            let Some(stored) = state.totp_mut(&totp.username) else {
+                return false;
+            };
+            if stored.last_step.is_some_and(|last_step| last_step >= step) {
+                return false;
+            }
+            stored.last_step = Some(step);
+            stored.enabled = true;
+            true
+        })
+    }
+    /// Checks the code against the unused recovery codes. A matching code is removed
+    fn check_recovery_code(&self, totp: &StoredTotp, code: &str) -> Result<bool, Error> {
+        let code = normalize_recovery_code(code);
+        let Some(hash) = totp.recovery_codes.iter().find(|hash| {
+            Password::new_hashed(hash.as_str())
+                .check_password(&code)
+                .unwrap_or(false)
+        }) else {
+            return Ok(false);
+        };
+        self.update_state(|state| {
+            let Some(stored) = state.totp_mut(&totp.username) else {
+                return false;
+            };
+            let before = stored.recovery_codes.len();
+            stored.recovery_codes.retain(|stored| stored != hash);
+            stored.recovery_codes.len() != before
+        })
+    }
+    /// Checks a TOTP or recovery code for an account with TOTP enabled
+    fn check_second_factor(&self, totp: &StoredTotp, code: &str) -> Result<bool, Error> {
+        if self.check_totp_code(totp, code)? {
+            return Ok(true);
+        }
+        self.check_recovery_code(totp, code)
+    }
+    fn totp(&self, username: &str) -> Option<StoredTotp> {
+        self.0.state.lock().totp(username).cloned()
+    }
+
+    fn log_failed_configuration_check(
+        validate_config_request: &ValidateDirectoryRequest,
+        state: &DirectoryState,
+    ) -> ServiceConfigurationResponse {
+        error!("Namespace mismatch");
+        error!("User namespace: {:?}", state.account_namespace);
+        error!("Group namespace: {:?}", state.group_namespace);
+        error!("Expected: {:?}", validate_config_request);
+        // TODO add instructions
+        error!("Please follow the instructions here: https://docs.nitro-mail.kingtux.dev/");
+        ServiceConfigurationResponse::NamespaceMismatch {}
+    }
+    fn get_success_response(new_install: bool) -> ServiceConfigurationResponse {
+        ServiceConfigurationResponse::Success {
+            new_install,
+            internal_service_name: Self::directory_name().to_string(),
+            git: GitInfo {
+                commit: env!("VERGEN_GIT_SHA").to_string(),
+                branch: env!("VERGEN_GIT_BRANCH").to_string(),
+                commit_date: env!("VERGEN_GIT_COMMIT_DATE").to_string(),
+            },
+            service_type: ServiceType::Directory,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+impl Service for FileDirectory {
+    type ServiceConfig = FileDirectoryConfig;
+    type ServiceError = Error;
+}
+
+#[async_trait]
+impl Directory for FileDirectory {
+    fn directory_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "directory_file"
+    }
+
+    async fn load(config: Self::ServiceConfig) -> Result<Self, Self::ServiceError>
+    where
+        Self: Sized,
+    {
+        let mut state = DirectoryState::read(&config.state_file)?;
+        // Mailbox ids are derived from the namespaces, so they have to exist before any account is indexed
+        let new_install = state.account_namespace.is_none() || state.group_namespace.is_none();
+        if new_install {
+            state
+                .account_namespace
+                .get_or_insert(config.account_namespace.unwrap_or_else(Uuid::new_v4));
+            state
+                .group_namespace
+                .get_or_insert(config.group_namespace.unwrap_or_else(Uuid::new_v4));
+            state.write(&config.state_file)?;
+        }
+        let (accounts, version) = Self::read_accounts(&config.accounts_file, &state)?;
+        if config.two_factor.encryption_key.is_some() {
+            config.two_factor.secret_key()?;
+        }
+        let directory = Self(Arc::new(FileDirectoryInner {
+            accounts: RwLock::new(Arc::new(accounts)),
+            version: Mutex::new(version),
+            state: Mutex::new(state),
+            auth_failures: AuthFailureTracker::new(config.brute_force.clone()),
+            new_install,
+            config,
+        }));
+        directory.watch();
+        Ok(directory)
+    }
+
+    /// Accounts deactivated by the state file are returned as inactive
+    async fn get_account(&self, username: String) -> Result<Option<Account>, Self::ServiceError> {
+        let Some(mut account) = self
+            .accounts()
+            .accounts
+            .get(&username)
+            .map(|entry| entry.account.clone())
+        else {
+            return Ok(None);
+        };
+        if self.0.state.lock().is_deactivated(&username) {
+            account.active = false;
+        }
+        Ok(Some(account))
+    }
+
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let accounts = self.accounts();
+        let Some(entry) = accounts.get_by_email(&email_address) else {
+            return Ok(None);
+        };
+        Ok(self
+            .active_account(&entry.account.username)
+            .map(|entry| entry.account))
+    }
+
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, Self::ServiceError> {
+        Ok(self.accounts().get_mailing_list(&email_address))
+    }
+
+    async fn get_quota(&self, mailbox_id: Uuid) -> Result<Option<Quota>, Self::ServiceError> {
+        Ok(self
+            .accounts()
+            .accounts
+            .values()
+            .find(|entry| entry.account.mailbox_id == mailbox_id)
+            .map(|entry| entry.account.quota)
+            .filter(|quota| !quota.is_unlimited()))
+    }
+
+    async fn login_account(
+        &self,
+        username: String,
+        password: String,
+        protocol: LoginProtocol,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let Some(entry) = self
+            .active_account(&username)
+            .filter(|entry| entry.account.account_type.allows_login(protocol))
+        else {
+            return Ok(None);
+        };
+        let totp_enabled = self.totp(&username).is_some_and(|totp| totp.enabled);
+        // With TOTP on the account password needs a code, so only app passwords work here
+        if !totp_enabled && Self::check_account_password(&entry, &password) {
+            return Ok(Some(entry.account));
+        }
+        if protocol.accepts_app_passwords()
+            && self.check_app_passwords(&username, &password, protocol)?
+        {
+            return Ok(Some(entry.account));
+        }
+        Ok(None)
+    }
+
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        // TOTP logins come from the web interface
+        let Some(entry) = self
+            .active_account(&username)
+            .filter(|entry| entry.account.account_type.allows_login(LoginProtocol::Http))
+        else {
+            return Ok(None);
+        };
+        if !Self::check_account_password(&entry, &password) {
+            return Ok(None);
+        }
+        if let Some(totp) = self.totp(&username).filter(|totp| totp.enabled) {
+            if !self.check_second_factor(&totp, &code)? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(entry.account))
+    }
+
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, Self::ServiceError> {
+        if !self.accounts().accounts.contains_key(&username) {
+            return Ok(None);
+        }
+        let password = generate_app_password();
+        let hashed =
+            Password::new_preferred(normalize_app_password(&password), &self.0.config.password)?;
+        let protocols: Vec<LoginProtocol> = protocols
+            .into_iter()
+            .filter(LoginProtocol::accepts_app_passwords)
+            .collect();
+        let created = Self::now() as i64;
+        let app_password = self.update_state(|state| {
+            let id = state
+                .app_passwords
+                .iter()
+                .map(|app_password| app_password.id)
+                .max()
+                .unwrap_or_default()
+                + 1;
+            let stored = StoredAppPassword {
+                username,
+                id,
+                name,
+                password: hashed.into(),
+                protocols,
+                created,
+                last_used: None,
+            };
+            let app_password = AppPassword::from(&stored);
+            state.app_passwords.push(stored);
+            app_password
+        })?;
+        Ok(Some(NewAppPassword {
+            app_password,
+            password,
+        }))
+    }
+
+    async fn list_app_passwords(
+        &self,
+        username: String,
+    ) -> Result<Vec<AppPassword>, Self::ServiceError> {
+        let mut app_passwords: Vec<AppPassword> = self
+            .0
+            .state
+            .lock()
+            .app_passwords
+            .iter()
+            .filter(|app_password| app_password.username == username)
+            .map(AppPassword::from)
+            .collect();
+        app_passwords.sort_by_key(|app_password| app_password.id);
+        Ok(app_passwords)
+    }
+
+    async fn revoke_app_password(
+        &self,
+        username: String,
+        id: i64,
+    ) -> Result<bool, Self::ServiceError> {
+        self.update_state(|state| {
+            let before = state.app_passwords.len();
+            state
+                .app_passwords
+                .retain(|app_password| app_password.username != username || app_password.id != id);
+            state.app_passwords.len() != before
+        })
+    }
+
+    async fn enroll_totp(
+        &self,
+        username: String,
+    ) -> Result<Option<TotpEnrollment>, Self::ServiceError> {
+        if !self.accounts().accounts.contains_key(&username) {
+            return Ok(None);
+        }
+        if self.totp(&username).is_some_and(|totp| totp.enabled) {
+            return Ok(None);
+        }
+        let secret_key = self.0.config.two_factor.secret_key()?;
+        let secret = generate_totp_secret();
+        let recovery_codes = generate_recovery_codes();
+        let enrollment = enrollment(
+            secret.clone(),
+            &self.0.config.two_factor.issuer,
+            &username,
+            recovery_codes.clone(),
+        )?;
+        let mut hashed_codes = Vec::with_capacity(recovery_codes.len());
+        for recovery_code in &recovery_codes {
+            hashed_codes.push(
+                Password::new_preferred(
+                    normalize_recovery_code(recovery_code),
+                    &self.0.config.password,
+                )?
+                .into(),
+            );
+        }
+        let enrolled = self.update_state(|state| {
+            if state.totp(&username).is_some_and(|totp| totp.enabled) {
+                return false;
+            }
+            // Replace the unfinished enrollment
+            state.totp.retain(|totp| totp.username != username);
+            state.totp.push(StoredTotp {
+                username,
+                secret: STANDARD.encode(secret_key.encrypt(&secret)),
+                enabled: false,
+                last_step: None,
+                recovery_codes: hashed_codes,
+            });
+            true
+        })?;
+        Ok(enrolled.then_some(enrollment))
+    }
+
+    async fn verify_totp(
+        &self,
+        username: String,
+        code: String,
+    ) -> Result<bool, Self::ServiceError> {
+        let Some(totp) = self.totp(&username) else {
+            return Ok(false);
+        };
+        if totp.enabled {
+            self.check_second_factor(&totp, &code)
+        } else {
+            // Recovery codes can not finish an enrollment
+            self.check_totp_code(&totp, &code)
+        }
+    }
+
+    async fn disable_totp(&self, username: String) -> Result<bool, Self::ServiceError> {
+        self.update_state(|state| {
+            let before = state.totp.len();
+            state.totp.retain(|totp| totp.username != username);
+            state.totp.len() != before
+        })
+    }
+
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        Ok(self
+            .0
+            .auth_failures
+            .check(remote, username.as_deref(), Self::now() as i64))
+    }
+
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        let existing_account = username
+            .as_ref()
+            .is_some_and(|username| self.accounts().accounts.contains_key(username));
+        let outcome = self.0.auth_failures.record_failure(
+            remote,
+            username.as_deref(),
+            existing_account,
+            Self::now() as i64,
+        );
+        if let (true, Some(username)) = (outcome.deactivate, username) {
+            self.update_state(|state| {
+                if !state.is_deactivated(&username) {
+                    warn!("Deactivated {} after repeated failed logins", username);
+                    state.deactivated.push(username);
+                }
+            })?;
+        }
+        Ok(outcome.lockout)
+    }
+
+    async fn record_auth_success(&self, username: String) -> Result<bool, Self::ServiceError> {
+        Ok(self.0.auth_failures.record_success(&username))
+    }
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, Self::ServiceError> {
+        Ok(self.0.auth_failures.lockouts(Self::now() as i64))
+    }
+
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, Self::ServiceError> {
+        Ok(self.0.auth_failures.clear(&key))
+    }
+
+    async fn get_groups(&self) -> Result<Vec<String>, Self::ServiceError> {
+        Ok(self
+            .accounts()
+            .groups
+            .iter()
+            .map(|group| group.name.clone())
+            .collect())
+    }
+
+    async fn validate_config(
+        &self,
+        validate_config_request: ValidateDirectoryRequest,
+    ) -> Result<ServiceConfigurationResponse, Self::ServiceError> {
+        let state = self.0.state.lock().clone();
+        match (state.account_namespace, state.group_namespace) {
+            (Some(account_namespace), Some(group_namespace))
+                if account_namespace == validate_config_request.account_namespace
+                    && group_namespace == validate_config_request.group_namespace =>
+            {
+                Ok(Self::get_success_response(self.0.new_install))
+            }
+            _ => Ok(Self::log_failed_configuration_check(
+                &validate_config_request,
+                &state,
+            )),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use uuid::Uuid;
+
+use directories::directory_type::Directory;
+use directories::ValidateDirectoryRequest;
+use utils::account::Account;
+use utils::app_password::LoginProtocol;
+use utils::configs::brute_force::BruteForceConfig;
+use utils::configs::two_factor::TwoFactorConfig;
+use utils::helper_types::Password;
+use utils::service_configuration::ServiceConfigurationResponse;
+use utils::two_factor::{totp_code, totp_step};
+
+use crate::file_config::FileDirectoryConfig;
+use crate::file_directory::FileDirectory;
+use crate::state::DirectoryState;
+
+/// A directory in the temp folder that is removed on drop
+struct TestFiles(PathBuf);
+impl TestFiles {
+    fn new() -> Self {
+        let path =
+            std::env::temp_dir().join(format!("nitro_mail_file_directory_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+    fn config(&self, accounts_file: &str) -> FileDirectoryConfig {
+        FileDirectoryConfig {
+            accounts_file: self.0.join(accounts_file),
+            state_file: self.0.join("accounts.state.toml"),
+            two_factor: TwoFactorConfig {
+                encryption_key: Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+    fn write(&self, name: &str, content: &str) {
+        std::fs::write(self.0.join(name), content).unwrap();
+    }
+}
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn hash(password: &str) -> String {
+    Password::new_argon2(password).unwrap().into()
+}
+/// `john` with the password `password` and the inactive account `jane`
+fn accounts_toml() -> String {
+    format!(
+        r#"
+        [[accounts]]
+        username = "john"
+        password = "{}"
+        email = "john@example.com"
+        aliases = ["j@example.com"]
+        [[accounts]]
+        username = "jane"
+        password = "{}"
+        email = "jane@example.com"
+        active = false
+        [[groups]]
+        name = "staff"
+        list_address = "staff@example.com"
+        members = ["john", "jane"]
+        "#,
+        hash("password"),
+        hash("password"),
+    )
+}
+
+#[tokio::test]
+async fn test_login() {
+    let files = TestFiles::new();
+    files.write("accounts.toml", &accounts_toml());
+    let directory = FileDirectory::load(files.config("accounts.toml"))
+        .await
+        .unwrap();
+    let login = |username: &str, password: &str| {
+        directory.login_account(
+            username.to_string(),
+            password.to_string(),
+            LoginProtocol::Imap,
+        )
+    };
+    assert!(login("john", "password").await.unwrap().is_some());
+    assert!(login("john", "wrong").await.unwrap().is_none());
+    assert!(login("jane", "password").await.unwrap().is_none());
+    assert!(login("nobody", "password").await.unwrap().is_none());
+
+    let account = directory
+        .get_account_by_email("j@example.com".to_string())
+        .await
+        .unwrap();
+    assert_eq!(account.unwrap().username, "john");
+    assert!(directory
+        .get_account_by_email("jane@example.com".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    let list = directory
+        .get_mailing_list("staff@example.com".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(list.members, vec!["john@example.com"]);
+    assert_eq!(directory.get_groups().await.unwrap(), vec!["staff"]);
+}
+
+#[tokio::test]
+async fn test_passwd_file() {
+    let files = TestFiles::new();
+    files.write(
+        "passwd",
+        &format!(
+            "# username:hash:addresses\njohn:{}:john@example.com,j@example.com\n",
+            hash("password")
+        ),
+    );
+    let directory = FileDirectory::load(files.config("passwd")).await.unwrap();
+    assert!(directory
+        .login_account(
+            "john".to_string(),
+            "password".to_string(),
+            LoginProtocol::Smtp
+        )
+        .await
+        .unwrap()
+        .is_some());
+    assert!(directory
+        .get_account_by_email("j@example.com".to_string())
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn test_reload() {
+    let files = TestFiles::new();
+    files.write("accounts.toml", &accounts_toml());
+    let directory = FileDirectory::load(files.config("accounts.toml"))
+        .await
+        .unwrap();
+    assert!(!directory.reload().unwrap());
+
+    let added = format!(
+        "{}\n[[accounts]]\nusername = \"new\"\npassword = \"{}\"\n",
+        accounts_toml(),
+        hash("new password")
+    );
+    files.write("accounts.toml", &added);
+    assert!(directory.reload().unwrap());
+    assert!(directory
+        .get_account("new".to_string())
+        .await
+        .unwrap()
+        .is_some());
+
+    // An invalid file keeps the accounts that were loaded
+    files.write(
+        "accounts.toml",
+        &format!(
+            "{}\n[[accounts]]\nusername = \"john\"\npassword = \"\"\n",
+            added
+        ),
+    );
+    assert!(directory.reload().is_err());
+    assert!(directory
+        .get_account("new".to_string())
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn test_validate_config() {
+    let files = TestFiles::new();
+    files.write("accounts.toml", &accounts_toml());
+    let request = ValidateDirectoryRequest {
+        group_namespace: Uuid::new_v4(),
+        account_namespace: Uuid::new_v4(),
+    };
+    let config = FileDirectoryConfig {
+        account_namespace: Some(request.account_namespace),
+        group_namespace: Some(request.group_namespace),
+        ..files.config("accounts.toml")
+    };
+    let directory = FileDirectory::load(config).await.unwrap();
+    // The namespaces are stored before any account is indexed
+    let john = directory
+        .get_account("john".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        john.mailbox_id,
+        Account::generate_mailbox_id(&request.account_namespace, "john")
+    );
+    assert!(matches!(
+        directory.validate_config(request.clone()).await.unwrap(),
+        ServiceConfigurationResponse::Success {
+            new_install: true,
+            ..
+        }
+    ));
+    drop(directory);
+
+    // The namespaces are read back from the state file
+    let directory = FileDirectory::load(files.config("accounts.toml"))
+        .await
+        .unwrap();
+    assert!(matches!(
+        directory.validate_config(request.clone()).await.unwrap(),
+        ServiceConfigurationResponse::Success {
+            new_install: false,
+            ..
+        }
+    ));
+    assert!(matches!(
+        directory
+            .validate_config(ValidateDirectoryRequest {
+                account_namespace: Uuid::new_v4(),
+                ..request
+            })
+            .await
+            .unwrap(),
+        ServiceConfigurationResponse::NamespaceMismatch
+    ));
+}
+
+#[tokio::test]
+async fn test_mailbox_ids_are_stable() {
+    let files = TestFiles::new();
+    files.write("accounts.toml", &accounts_toml());
+    let directory = FileDirectory::load(files.config("accounts.toml"))
+        .await
+        .unwrap();
+    let mailbox_id = |directory: FileDirectory| async move {
+        directory
+            .get_account("john".to_string())
+            .await
+            .unwrap()
+            .unwrap()
+            .mailbox_id
+    };
+    let john = mailbox_id(directory.clone()).await;
+    assert_ne!(john, Account::generate_mailbox_id(&Uuid::nil(), "john"));
+    // Checking other namespaces changes nothing
+    assert!(matches!(
+        directory
+            .validate_config(ValidateDirectoryRequest {
+                group_namespace: Uuid::new_v4(),
+                account_namespace: Uuid::new_v4(),
+            })
+            .await
+            .unwrap(),
+        ServiceConfigurationResponse::NamespaceMismatch
+    ));
+    assert_eq!(mailbox_id(directory.clone()).await, john);
+    drop(directory);
+    let directory = FileDirectory::load(files.config("accounts.toml"))
+        .await
+        .unwrap();
+    assert_eq!(mailbox_id(directory).await, john);
+}
+
+#[tokio::test]
+async fn test_app_passwords_and_totp() {
+    let files = TestFiles::new();
+    files.write("accounts.toml", &accounts_toml());
+    let config = files.config("accounts.toml");
+    let directory = FileDirectory::load(config.clone()).await.unwrap();
+    let new_app_password = directory
+        .create_app_password(
+            "john".to_string(),
+            "Phone".to_string(),
+            vec![LoginProtocol::Smtp, LoginProtocol::Http],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        new_app_password.app_password.protocols,
+        vec![LoginProtocol::Smtp]
+    );
+
+    let enrollment = directory
+        .enroll_totp("john".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    let state = DirectoryState::read(&config.state_file).unwrap();
+    let secret = config
+        .two_factor
+        .secret_key()
+        .unwrap()
+        .decrypt(
+            &STANDARD
+                .decode(&state.totp("john").unwrap().secret)
+                .unwrap(),
+        )
+        .unwrap();
+    let step = totp_step(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    );
+    assert!(directory
+        .verify_totp("john".to_string(), totp_code(&secret, step))
+        .await
+        .unwrap());
+    drop(directory);
+
+    // App passwords and TOTP survive a restart
+    let directory = FileDirectory::load(config).await.unwrap();
+    let login = |password: &str, protocol: LoginProtocol| {
+        directory.login_account("john".to_string(), password.to_string(), protocol)
+    };
+    assert!(login("password", LoginProtocol::Smtp)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(login(&new_app_password.password, LoginProtocol::Smtp)
+        .await
+        .unwrap()
+        .is_some());
+    assert!(login(&new_app_password.password, LoginProtocol::Imap)
+        .await
+        .unwrap()
+        .is_none());
+    let app_passwords = directory
+        .list_app_passwords("john".to_string())
+        .await
+        .unwrap();
+    assert!(app_passwords[0].last_used.is_some());
+
+    let login_with_totp = |code: &str| {
+        directory.login_account_with_totp(
+            "john".to_string(),
+            "password".to_string(),
+            code.to_string(),
+        )
+    };
+    // The code was used to finish enrollment
+    assert!(login_with_totp(&totp_code(&secret, step))
+        .await
+        .unwrap()
+        .is_none());
+    let recovery_code = &enrollment.recovery_codes[0];
+    assert!(login_with_totp(recovery_code).await.unwrap().is_some());
+    assert!(login_with_totp(recovery_code).await.unwrap().is_none());
+
+    assert!(directory
+        .revoke_app_password("john".to_string(), new_app_password.app_password.id)
+        .await
+        .unwrap());
+    assert!(directory.disable_totp("john".to_string()).await.unwrap());
+    assert!(login("password", LoginProtocol::Smtp)
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn test_repeated_lockouts_deactivate_account() {
+    let files = TestFiles::new();
+    files.write("accounts.toml", &accounts_toml());
+    let config = FileDirectoryConfig {
+        brute_force: BruteForceConfig {
+            max_username_failures: 1,
+            deactivate_after: Some(1),
+            ..Default::default()
+        },
+        ..files.config("accounts.toml")
+    };
+    let directory = FileDirectory::load(config.clone()).await.unwrap();
+    assert!(directory
+        .record_auth_failure(None, Some("john".to_string()))
+        .await
+        .unwrap()
+        .is_some());
+    directory
+        .clear_lockout(utils::auth_failures::LockoutKey::username("john"))
+        .await
+        .unwrap();
+    assert!(directory
+        .login_account(
+            "john".to_string(),
+            "password".to_string(),
+            LoginProtocol::Imap
+        )
+        .await
+        .unwrap()
+        .is_none());
+    let state = DirectoryState::read(&config.state_file).unwrap();
+    assert_eq!(state.deactivated, vec!["john"]);
+}
@@ -0,0 +1,6 @@
+pub mod accounts;
+pub mod file_config;
+pub mod file_directory;
+#[cfg(test)]
+pub mod file_tests;
+pub mod state;
@@ -0,0 +1,16 @@
+use std::env::current_dir;
+
+use directories::directory_service::DirectoryService;
+use directories::directory_type::Directory;
+use utils::configs::Config;
+
+use directory_file::file_config::FileDirectoryConfig;
+use directory_file::file_directory::FileDirectory;
+
+#[tokio::main]
+async fn main() {
+    let file_config = FileDirectoryConfig::get_or_save_default(current_dir().unwrap()).unwrap();
+    let directory = FileDirectory::load(file_config).await.unwrap();
+    let service = DirectoryService::new(directory);
+    service.run().await;
+}
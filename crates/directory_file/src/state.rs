@@ -0,0 +1,90 @@
+//! Everything the directory writes. Kept beside the accounts file so that file is only ever edited by hand
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use utils::app_password::{AppPassword, LoginProtocol};
+use utils::configs::IOOrToml;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredAppPassword {
+    pub username: String,
+    pub id: i64,
+    pub name: String,
+    /// Hash of the normalized password
+    pub password: String,
+    pub protocols: Vec<LoginProtocol>,
+    pub created: i64,
+    #[serde(default)]
+    pub last_used: Option<i64>,
+}
+impl From<&StoredAppPassword> for AppPassword {
+    fn from(value: &StoredAppPassword) -> Self {
+        AppPassword {
+            id: value.id,
+            name: value.name.clone(),
+            protocols: value.protocols.clone(),
+            created: value.created,
+            last_used: value.last_used,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredTotp {
+    pub username: String,
+    /// Encrypted with the two_factor encryption_key and encoded as base64
+    pub secret: String,
+    pub enabled: bool,
+    #[serde(default)]
+    pub last_step: Option<i64>,
+    /// Hashes of the unused recovery codes
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryState {
+    #[serde(default)]
+    pub account_namespace: Option<Uuid>,
+    #[serde(default)]
+    pub group_namespace: Option<Uuid>,
+    #[serde(default)]
+    pub app_passwords: Vec<StoredAppPassword>,
+    #[serde(default)]
+    pub totp: Vec<StoredTotp>,
+    /// Accounts deactivated after repeated lockouts. Removing a name here reactivates it
+    #[serde(default)]
+    pub deactivated: Vec<String>,
+}
+impl DirectoryState {
+    /// An empty state if the file does not exist yet
+    pub fn read(path: &Path) -> Result<Self, IOOrToml> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+    /// Writes to a temporary file first so a crash never leaves a partial state
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let content = toml::to_string(self)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, content)?;
+        std::fs::rename(&temporary, path)
+    }
+
+    pub fn totp(&self, username: &str) -> Option<&StoredTotp> {
+        self.totp.iter().find(|totp| totp.username == username)
+    }
+    pub fn totp_mut(&mut self, username: &str) -> Option<&mut StoredTotp> {
+        self.totp.iter_mut().find(|totp| totp.username == username)
+    }
+    pub fn is_deactivated(&self, username: &str) -> bool {
+        self.deactivated.iter().any(|name| name == username)
+    }
+}
@@ -0,0 +1,290 @@
+//! LDAP search filters and their [RFC 4515](https://www.rfc-editor.org/rfc/rfc4515) string form
+use std::fmt;
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Invalid LDAP filter at {position}: {filter}")]
+pub struct FilterError {
+    pub filter: String,
+    pub position: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Equality(String, String),
+    /// `initial*any*any*final`. Empty parts are left out
+    Substrings {
+        attribute: String,
+        initial: Option<String>,
+        any: Vec<String>,
+        last: Option<String>,
+    },
+    GreaterOrEqual(String, String),
+    LessOrEqual(String, String),
+    Present(String),
+    Approximate(String, String),
+}
+impl Filter {
+    pub fn equality(attribute: impl Into<String>, value: impl Into<String>) -> Self {
+        Filter::Equality(attribute.into(), value.into())
+    }
+
+    /// Parses a filter such as `(&(objectClass=person)(uid=john))`
+    pub fn parse(filter: &str) -> Result<Self, FilterError> {
+        let mut parser = Parser {
+            filter,
+            position: 0,
+        };
+        let result = parser.filter()?;
+        if parser.position != filter.len() {
+            return Err(parser.error());
+        }
+        Ok(result)
+    }
+}
+/// The string form with every value escaped, which is what is sent to the server
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::And(filters) => write_list(f, '&', filters),
+            Filter::Or(filters) => write_list(f, '|', filters),
+            Filter::Not(filter) => write!(f, "(!{})", filter),
+            Filter::Equality(attribute, value) => {
+                write!(f, "({}={})", attribute, escape_value(value))
+            }
+            Filter::Substrings {
+                attribute,
+                initial,
+                any,
+                last,
+            } => {
+                write!(f, "({}=", attribute)?;
+                if let Some(initial) = initial {
+                    f.write_str(&escape_value(initial))?;
+                }
+                for any in any {
+                    write!(f, "*{}", escape_value(any))?;
+                }
+                f.write_str("*")?;
+                if let Some(last) = last {
+                    f.write_str(&escape_value(last))?;
+                }
+                f.write_str(")")
+            }
+            Filter::GreaterOrEqual(attribute, value) => {
+                write!(f, "({}>={})", attribute, escape_value(value))
+            }
+            Filter::LessOrEqual(attribute, value) => {
+                write!(f, "({}<={})", attribute, escape_value(value))
+            }
+            Filter::Present(attribute) => write!(f, "({}=*)", attribute),
+            Filter::Approximate(attribute, value) => {
+                write!(f, "({}~={})", attribute, escape_value(value))
+            }
+        }
+    }
+}
+fn write_list(f: &mut fmt::Formatter<'_>, operator: char, filters: &[Filter]) -> fmt::Result {
+    write!(f, "({}", operator)?;
+    for filter in filters {
+        write!(f, "{}", filter)?;
+    }
+    f.write_str(")")
+}
+
+/// Escapes a value for use inside a filter string
+pub fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '*' | '(' | ')' | '\\' | '\0' => {
+                escaped.push_str(&format!("\\{:02x}", character as u8))
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+struct Parser<'a> {
+    filter: &'a str,
+    position: usize,
+}
+impl Parser<'_> {
+    fn error(&self) -> FilterError {
+        FilterError {
+            filter: self.filter.to_string(),
+            position: self.position,
+        }
+    }
+    fn rest(&self) -> &str {
+        &self.filter[self.position..]
+    }
+    fn expect(&mut self, character: char) -> Result<(), FilterError> {
+        if !self.rest().starts_with(character) {
+            return Err(self.error());
+        }
+        self.position += character.len_utf8();
+        Ok(())
+    }
+
+    fn filter(&mut self) -> Result<Filter, FilterError> {
+        self.expect('(')?;
+        let filter = match self.rest().chars().next() {
+            Some('&') => {
+                self.position += 1;
+                Filter::And(self.filter_list()?)
+            }
+            Some('|') => {
+                self.position += 1;
+                Filter::Or(self.filter_list()?)
+            }
+            Some('!') => {
+                self.position += 1;
+                Filter::Not(Box::new(self.filter()?))
+            }
+            _ => self.item()?,
+        };
+        self.expect(')')?;
+        Ok(filter)
+    }
+    fn filter_list(&mut self) -> Result<Vec<Filter>, FilterError> {
+        let mut filters = Vec::new();
+        while self.rest().starts_with('(') {
+            filters.push(self.filter()?);
+        }
+        if filters.is_empty() {
+            return Err(self.error());
+        }
+        Ok(filters)
+    }
+    fn item(&mut self) -> Result<Filter, FilterError> {
+        let rest = self.rest();
+        let end = rest.find(')').ok_or_else(|| self.error())?;
+        let item = &rest[..end];
+        let equals = item.find('=').ok_or_else(|| self.error())?;
+        let value = &item[equals + 1..];
+        let (attribute, operator) = match item[..equals].chars().last() {
+            Some(operator @ ('~' | '>' | '<')) => (&item[..equals - 1], operator),
+            _ => (&item[..equals], '='),
+        };
+        if attribute.is_empty() || attribute.contains(['~', '>', '<']) {
+            return Err(self.error());
+        }
+        let attribute = attribute.to_string();
+        let filter = match operator {
+            '~' => Filter::Approximate(attribute, self.unescape(value)?),
+            '>' => Filter::GreaterOrEqual(attribute, self.unescape(value)?),
+            '<' => Filter::LessOrEqual(attribute, self.unescape(value)?),
+            _ if value == "*" => Filter::Present(attribute),
+            _ if value.contains('*') => {
+                let mut parts = value.split('*');
+                let initial = parts.next().filter(|part| !part.is_empty());
+                let mut any: Vec<&str> = parts.collect();
+                let last = any.pop().filter(|part| !part.is_empty());
+                Filter::Substrings {
+                    attribute,
+                    initial: initial.map(|part| self.unescape(part)).transpose()?,
+                    any: any
+                        .into_iter()
+                        .filter(|part| !part.is_empty())
+                        .map(|part| self.unescape(part))
+                        .collect::<Result<_, _>>()?,
+                    last: last.map(|part| self.unescape(part)).transpose()?,
+                }
+            }
+            _ => Filter::Equality(attribute, self.unescape(value)?),
+        };
+        self.position += end;
+        Ok(filter)
+    }
+    /// `\XX` is the byte with the hex value XX
+    fn unescape(&self, value: &str) -> Result<String, FilterError> {
+        let mut bytes = Vec::with_capacity(value.len());
+        let mut rest = value.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte != b'\\' {
+                bytes.push(byte);
+                rest = tail;
+                continue;
+            }
+            let hex = tail
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| self.error())?;
+            bytes.push(hex);
+            rest = &tail[2..];
+        }
+        String::from_utf8(bytes).map_err(|_| self.error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::{escape_value, Filter};
+
+    #[test]
+    pub fn test_parse() {
+        assert_eq!(
+            Filter::parse("(&(objectClass=inetOrgPerson)(!(uid=jo\\2a))(mail=*))").unwrap(),
+            Filter::And(vec![
+                Filter::equality("objectClass", "inetOrgPerson"),
+                Filter::Not(Box::new(Filter::equality("uid", "jo*"))),
+                Filter::Present("mail".to_string()),
+            ])
+        );
+        assert_eq!(
+            Filter::parse("(cn=a*b*c)").unwrap(),
+            Filter::Substrings {
+                attribute: "cn".to_string(),
+                initial: Some("a".to_string()),
+                any: vec!["b".to_string()],
+                last: Some("c".to_string()),
+            }
+        );
+        assert_eq!(
+            Filter::parse("(uidNumber>=1000)").unwrap(),
+            Filter::GreaterOrEqual("uidNumber".to_string(), "1000".to_string())
+        );
+        for invalid in [
+            "uid=john",
+            "(uid=john",
+            "(&)",
+            "(=john)",
+            "(uid=\\zz)",
+            "(a=b)x",
+        ] {
+            assert!(Filter::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+    #[test]
+    pub fn test_escape() {
+        let escaped = escape_value("*)(uid=*");
+        assert_eq!(escaped, "\\2a\\29\\28uid=\\2a");
+        assert_eq!(
+            Filter::parse(&format!("(uid={})", escaped)).unwrap(),
+            Filter::equality("uid", "*)(uid=*")
+        );
+    }
+    #[test]
+    pub fn test_to_string() {
+        for filter in [
+            "(&(objectClass=inetOrgPerson)(!(uid=jo\\2a))(mail=*))",
+            "(|(cn=a*b*c)(cn=*b*)(cn=a*))",
+            "(uidNumber>=1000)",
+            "(uidNumber<=1000)",
+            "(cn~=john)",
+        ] {
+            assert_eq!(Filter::parse(filter).unwrap().to_string(), filter);
+        }
+        assert_eq!(
+            Filter::equality("uid", "*)(uid=*").to_string(),
+            "(uid=\\2a\\29\\28uid=\\2a)"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Connections to the LDAP server through [ldap3]
+//!
+//! Searches share a pool of connections bound as `bind_dn`. Logins bind on a connection of their own
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ahash::{HashMap, HashMapExt};
+use async_trait::async_trait;
+use deadpool::managed::{self, RecycleError, RecycleResult};
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchResult};
+use tokio_rustls::rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore};
+use tracing::warn;
+
+use crate::filter::Filter;
+use crate::ldap_config::LdapConfig;
+use crate::ldap_directory::Error;
+
+const SUCCESS: u32 = 0;
+const NO_SUCH_OBJECT: u32 = 32;
+const INVALID_CREDENTIALS: u32 = 49;
+
+pub type ConnectionPool = managed::Pool<ServiceConnections>;
+
+/// An entry returned by a search. Attribute names are lowercase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchEntry {
+    pub dn: String,
+    pub attributes: HashMap<String, Vec<String>>,
+}
+impl SearchEntry {
+    pub fn values(&self, attribute: &str) -> &[String] {
+        self.attributes
+            .get(&attribute.to_lowercase())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+    pub fn first(&self, attribute: &str) -> Option<&str> {
+        self.values(attribute).first().map(String::as_str)
+    }
+}
+impl From<ldap3::SearchEntry> for SearchEntry {
+    /// Values that are not UTF-8 are left out
+    fn from(entry: ldap3::SearchEntry) -> Self {
+        let mut attributes = HashMap::with_capacity(entry.attrs.len());
+        for (name, values) in entry.attrs {
+            attributes
+                .entry(name.to_lowercase())
+                .or_insert_with(Vec::new)
+                .extend(values);
+        }
+        Self {
+            dn: entry.dn,
+            attributes,
+        }
+    }
+}
+
+fn tls_config(ca_file: Option<&Path>) -> Result<ClientConfig, Error> {
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(ca_file) => {
+            let certificates = rustls_pemfile::certs(&mut BufReader::new(File::open(ca_file)?))?;
+            for certificate in certificates {
+                roots
+                    .add(&Certificate(certificate))
+                    .map_err(|error| Error::Tls(error.to_string()))?;
+            }
+        }
+        None => {
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+        }
+    }
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// How every connection is opened. The CA file is read once here
+#[derive(Clone)]
+pub struct ConnectionSettings {
+    url: String,
+    timeout: Duration,
+    settings: LdapConnSettings,
+}
+impl ConnectionSettings {
+    pub fn new(config: &LdapConfig) -> Result<Self, Error> {
+        let timeout = config.timeout.to_std().unwrap_or(Duration::from_secs(10));
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(timeout)
+            .set_starttls(config.starttls)
+            .set_config(Arc::new(tls_config(config.ca_file.as_deref())?));
+        Ok(Self {
+            url: config.url.clone(),
+            timeout,
+            settings,
+        })
+    }
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Connects and upgrades with StartTLS if configured. The connection is driven on its own task
+    pub async fn connect(&self) -> Result<Ldap, LdapError> {
+        let (connection, ldap) =
+            LdapConnAsync::with_settings(self.settings.clone(), &self.url).await?;
+        tokio::spawn(async move {
+            if let Err(error) = connection.drive().await {
+                warn!("LDAP connection closed: {}", error);
+            }
+        });
+        Ok(ldap)
+    }
+
+    /// Simple bind. Returns false if the credentials are invalid
+    ///
+    /// An empty password is an unauthenticated bind that servers accept for any DN, so it is refused here
+    pub async fn bind(&self, ldap: &mut Ldap, dn: &str, password: &str) -> Result<bool, LdapError> {
+        if password.is_empty() {
+            return Ok(false);
+        }
+        let result = ldap
+            .with_timeout(self.timeout)
+            .simple_bind(dn, password)
+            .await?;
+        match result.rc {
+            SUCCESS => Ok(true),
+            INVALID_CREDENTIALS => Ok(false),
+            _ => Err(result.into()),
+        }
+    }
+
+    /// Returns no entries if the base does not exist. References are skipped
+    pub async fn search(
+        &self,
+        ldap: &mut Ldap,
+        base: &str,
+        scope: Scope,
+        filter: &Filter,
+        attributes: &[&str],
+    ) -> Result<Vec<SearchEntry>, LdapError> {
+        let SearchResult(entries, result) = ldap
+            .with_timeout(self.timeout)
+            .search(base, scope, &filter.to_string(), attributes)
+            .await?;
+        if result.rc != NO_SUCH_OBJECT {
+            result.success()?;
+        }
+        Ok(entries
+            .into_iter()
+            .filter(|entry| !entry.is_ref() && !entry.is_intermediate())
+            .map(|entry| ldap3::SearchEntry::construct(entry).into())
+            .collect())
+    }
+}
+
+/// Creates the pooled connections and binds them as `bind_dn`
+pub struct ServiceConnections {
+    pub settings: ConnectionSettings,
+    pub bind_dn: String,
+    pub bind_password: String,
+}
+#[async_trait]
+impl managed::Manager for ServiceConnections {
+    type Type = Ldap;
+    type Error = Error;
+
+    async fn create(&self) -> Result<Ldap, Error> {
+        let mut ldap = self.settings.connect().await?;
+        if !self
+            .settings
+            .bind(&mut ldap, &self.bind_dn, &self.bind_password)
+            .await?
+        {
+            return Err(Error::ServiceBind);
+        }
+        Ok(ldap)
+    }
+
+    async fn recycle(&self, ldap: &mut Ldap) -> RecycleResult<Error> {
+        if ldap.is_closed() {
+            return Err(RecycleError::StaticMessage(
+                "The LDAP server closed the connection",
+            ));
+        }
+        Ok(())
+    }
+}
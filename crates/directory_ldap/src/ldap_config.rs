@@ -0,0 +1,289 @@
+use std::path::PathBuf;
+
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+use utils::common_types::EmailType;
+use utils::configs::brute_force::BruteForceConfig;
+use utils::configs::{Config, ConfigDuration, ConfigName};
+
+use crate::filter::{Filter, FilterError};
+
+fn default_url() -> String {
+    "ldap://localhost:389".to_string()
+}
+fn default_timeout() -> ConfigDuration {
+    Duration::seconds(10).into()
+}
+fn default_pool_size() -> usize {
+    10
+}
+fn default_user_filter() -> String {
+    "(objectClass=inetOrgPerson)".to_string()
+}
+fn default_username_attribute() -> String {
+    "uid".to_string()
+}
+fn default_email_attributes() -> Vec<EmailAttribute> {
+    vec![
+        EmailAttribute {
+            attribute: "mail".to_string(),
+            email_type: EmailType::Primary,
+        },
+        EmailAttribute {
+            attribute: "mailAlternateAddress".to_string(),
+            email_type: EmailType::Alias,
+        },
+    ]
+}
+fn default_group_filter() -> String {
+    "(objectClass=groupOfNames)".to_string()
+}
+fn default_name_attribute() -> String {
+    "cn".to_string()
+}
+fn default_description_attribute() -> String {
+    "description".to_string()
+}
+fn default_mail_attribute() -> String {
+    "mail".to_string()
+}
+fn default_member_attribute() -> String {
+    "member".to_string()
+}
+fn default_member_of_attribute() -> String {
+    "memberOf".to_string()
+}
+fn default_mailbox_id_attribute() -> String {
+    "entryUUID".to_string()
+}
+
+/// An attribute holding email addresses of the given type
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmailAttribute {
+    pub attribute: String,
+    pub email_type: EmailType,
+}
+
+/// How the members of a group are found
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Membership {
+    /// The group lists the DN of each member in `member_attribute`
+    #[default]
+    GroupOfNames,
+    /// Each user lists the DN of their groups in `member_of_attribute`
+    MemberOf,
+}
+
+/// # Example
+/// ```toml
+/// [users]
+/// base_dn = "ou=people,dc=example,dc=com"
+/// filter = "(&(objectClass=inetOrgPerson)(!(nsAccountLock=TRUE)))"
+/// username_attribute = "uid"
+/// email_attributes = [
+///     { attribute = "mail", email_type = "Primary" },
+///     { attribute = "mailAlternateAddress", email_type = "Alias" },
+/// ]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserConfig {
+    /// Defaults to the top level `base_dn`
+    #[serde(default)]
+    pub base_dn: Option<String>,
+    /// Only entries matching the filter are accounts
+    #[serde(default = "default_user_filter")]
+    pub filter: String,
+    #[serde(default = "default_username_attribute")]
+    pub username_attribute: String,
+    /// The display name, such as `cn` or `displayName`. The username if not set
+    #[serde(default)]
+    pub name_attribute: Option<String>,
+    #[serde(default = "default_email_attributes")]
+    pub email_attributes: Vec<EmailAttribute>,
+    /// An attribute that never changes so a renamed user keeps their mail. Values that are not UUIDs are hashed into one.
+    /// Entries without it are not accounts
+    #[serde(default = "default_mailbox_id_attribute")]
+    pub mailbox_id_attribute: String,
+}
+impl Default for UserConfig {
+    fn default() -> Self {
+        UserConfig {
+            base_dn: None,
+            filter: default_user_filter(),
+            username_attribute: default_username_attribute(),
+            name_attribute: None,
+            email_attributes: default_email_attributes(),
+            mailbox_id_attribute: default_mailbox_id_attribute(),
+        }
+    }
+}
+
+/// Groups with an address in `mail_attribute` are mailing lists
+///
+/// # Example
+/// ```toml
+/// [groups]
+/// base_dn = "ou=groups,dc=example,dc=com"
+/// filter = "(objectClass=groupOfNames)"
+/// membership = "GroupOfNames"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupConfig {
+    /// Defaults to the top level `base_dn`
+    #[serde(default)]
+    pub base_dn: Option<String>,
+    #[serde(default = "default_group_filter")]
+    pub filter: String,
+    #[serde(default = "default_name_attribute")]
+    pub name_attribute: String,
+    #[serde(default = "default_description_attribute")]
+    pub description_attribute: String,
+    #[serde(default = "default_mail_attribute")]
+    pub mail_attribute: String,
+    #[serde(default)]
+    pub membership: Membership,
+    /// Used with [Membership::GroupOfNames]
+    #[serde(default = "default_member_attribute")]
+    pub member_attribute: String,
+    /// Used with [Membership::MemberOf]
+    #[serde(default = "default_member_of_attribute")]
+    pub member_of_attribute: String,
+    /// See [UserConfig::mailbox_id_attribute]. Groups without it are not mailing lists
+    #[serde(default = "default_mailbox_id_attribute")]
+    pub mailbox_id_attribute: String,
+}
+impl Default for GroupConfig {
+    fn default() -> Self {
+        GroupConfig {
+            base_dn: None,
+            filter: default_group_filter(),
+            name_attribute: default_name_attribute(),
+            description_attribute: default_description_attribute(),
+            mail_attribute: default_mail_attribute(),
+            membership: Membership::default(),
+            member_attribute: default_member_attribute(),
+            member_of_attribute: default_member_of_attribute(),
+            mailbox_id_attribute: default_mailbox_id_attribute(),
+        }
+    }
+}
+
+/// The directory binds as `bind_dn` to search. Logins bind as the user that was found
+///
+/// # Example
+/// ```toml
+/// url = "ldaps://ldap.example.com"
+/// bind_dn = "cn=nitro_mail,ou=services,dc=example,dc=com"
+/// bind_password = "password"
+/// base_dn = "dc=example,dc=com"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LdapConfig {
+    /// `ldap://` or `ldaps://`
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// Upgrade `ldap://` connections with StartTLS before binding. Ignored for `ldaps://`
+    #[serde(default)]
+    pub starttls: bool,
+    /// PEM certificates trusted for `ldaps://` and StartTLS. Defaults to the Mozilla root certificates
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+    #[serde(default = "default_timeout")]
+    pub timeout: ConfigDuration,
+    /// The most connections bound as `bind_dn` kept open for searches
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    pub bind_dn: String,
+    pub bind_password: String,
+    pub base_dn: String,
+    #[serde(default)]
+    pub users: UserConfig,
+    #[serde(default)]
+    pub groups: GroupConfig,
+    #[serde(default)]
+    pub brute_force: BruteForceConfig,
+}
+impl LdapConfig {
+    pub fn user_base_dn(&self) -> &str {
+        self.users.base_dn.as_deref().unwrap_or(&self.base_dn)
+    }
+    pub fn group_base_dn(&self) -> &str {
+        self.groups.base_dn.as_deref().unwrap_or(&self.base_dn)
+    }
+    pub fn user_filter(&self) -> Result<Filter, FilterError> {
+        Filter::parse(&self.users.filter)
+    }
+    pub fn group_filter(&self) -> Result<Filter, FilterError> {
+        Filter::parse(&self.groups.filter)
+    }
+}
+impl Config for LdapConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/ldap_directory")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("ldap.directory.toml")
+    }
+}
+impl Default for LdapConfig {
+    fn default() -> Self {
+        LdapConfig {
+            url: default_url(),
+            starttls: false,
+            ca_file: None,
+            timeout: default_timeout(),
+            pool_size: default_pool_size(),
+            bind_dn: "<BIND_DN>".to_string(),
+            bind_password: "<BIND_PASSWORD>".to_string(),
+            base_dn: "<BASE_DN>".to_string(),
+            users: UserConfig::default(),
+            groups: GroupConfig::default(),
+            brute_force: BruteForceConfig::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::common_types::EmailType;
+
+    use crate::ldap_config::{LdapConfig, Membership};
+
+    #[test]
+    pub fn test_parse() {
+        let config: LdapConfig = toml::from_str(
+            r#"
+            url = "ldap://ldap.example.com"
+            starttls = true
+            bind_dn = "cn=admin,dc=example,dc=com"
+            bind_password = "password"
+            base_dn = "dc=example,dc=com"
+            [users]
+            base_dn = "ou=people,dc=example,dc=com"
+            email_attributes = [{ attribute = "mail", email_type = "Primary" }]
+            [groups]
+            membership = "MemberOf"
+            "#,
+        )
+        .unwrap();
+        assert!(config.starttls);
+        assert_eq!(config.pool_size, 10);
+        assert_eq!(config.user_base_dn(), "ou=people,dc=example,dc=com");
+        assert_eq!(config.group_base_dn(), "dc=example,dc=com");
+        assert_eq!(config.users.email_attributes.len(), 1);
+        assert_eq!(
+            config.users.email_attributes[0].email_type,
+            EmailType::Primary
+        );
+        assert_eq!(config.groups.membership, Membership::MemberOf);
+        assert!(config.user_filter().is_ok());
+    }
+}
@@ -0,0 +1,548 @@
+use std::convert::Infallible;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use deadpool::managed::{Object, PoolError};
+use deadpool::Runtime;
+use futures::future::Ready;
+use ldap3::{LdapError, Scope};
+use thiserror::Error;
+use tracing::warn;
+use uuid::Uuid;
+
+use directories::directory_type::Directory;
+use directories::ValidateDirectoryRequest;
+use utils::account::Account;
+use utils::admin::AccountAddress;
+use utils::app_password::{AppPassword, LoginProtocol, NewAppPassword};
+use utils::auth_failures::{AuthFailureTracker, Lockout, LockoutKey};
+use utils::common_types::{AccountType, EmailType};
+use utils::groups::{Group, GroupType, MailingList, PostingPolicy};
+use utils::service::{Service, ServiceAccess};
+use utils::service_configuration::{GitInfo, ServiceConfigurationResponse, ServiceType};
+use utils::two_factor::TotpEnrollment;
+
+use crate::filter::{Filter, FilterError};
+use crate::ldap_client::{ConnectionPool, ConnectionSettings, SearchEntry, ServiceConnections};
+use crate::ldap_config::{LdapConfig, Membership};
+
+/// Hashes `mailbox_id_attribute` values that are not UUIDs. Never change it, every such mailbox id would change
+pub(crate) const MAILBOX_ID_NAMESPACE: Uuid =
+    Uuid::from_u128(0x6e2f_94c1_3b7d_4a58_9e0f_51c8_d2a7_b634);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Ldap(#[from] LdapError),
+    #[error("Invalid filter in the config: {0}")]
+    Filter(#[from] FilterError),
+    #[error("The LDAP server refused the bind_dn and bind_password in the config")]
+    ServiceBind,
+    #[error("Unable to read the ca_file: {0}")]
+    CaFile(#[from] io::Error),
+    #[error("TLS error: {0}")]
+    Tls(String),
+    #[error("No LDAP connection was free in time")]
+    PoolTimeout,
+    #[error("LDAP connection pool error: {0}")]
+    Pool(String),
+}
+impl From<PoolError<Error>> for Error {
+    fn from(error: PoolError<Error>) -> Self {
+        match error {
+            PoolError::Backend(error) => error,
+            PoolError::Timeout(_) => Error::PoolTimeout,
+            error => Error::Pool(error.to_string()),
+        }
+    }
+}
+
+/// A read only directory backed by an LDAP server
+///
+/// Searches use a pool of connections bound as `bind_dn`. Passwords are checked by binding as the user on a new connection.
+/// App passwords and TOTP are not supported
+#[derive(Clone)]
+pub struct LdapDirectory {
+    pub(crate) config: Arc<LdapConfig>,
+    pub(crate) user_filter: Filter,
+    pub(crate) group_filter: Filter,
+    pub(crate) settings: ConnectionSettings,
+    /// Bound as `bind_dn`. A connection is dropped after an error instead of being returned
+    pub(crate) connections: ConnectionPool,
+    /// Shared by every clone so all connections count toward the same lockouts
+    pub(crate) auth_failures: Arc<AuthFailureTracker>,
+}
+impl ServiceAccess for LdapDirectory {
+    type ServiceResponse = Self;
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn get_service(&self) -> Self::Future {
+        futures::future::ready(Ok(self.clone()))
+    }
+}
+impl LdapDirectory {
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+    async fn search(
+        &self,
+        base: &str,
+        scope: Scope,
+        filter: &Filter,
+        attributes: &[&str],
+    ) -> Result<Vec<SearchEntry>, Error> {
+        let mut connection = self.connections.get().await?;
+        match self
+            .settings
+            .search(&mut connection, base, scope, filter, attributes)
+            .await
+        {
+            Ok(entries) => return Ok(entries),
+            Err(error @ LdapError::LdapResult { .. }) => return Err(error.into()),
+            // The server may have closed the connection while it was in the pool, so that failure is retried on a new one
+            Err(error) => {
+                warn!("LDAP search failed, reconnecting: {}", error);
+                drop(Object::take(connection));
+            }
+        }
+        let mut connection = self.connections.get().await?;
+        let result = self
+            .settings
+            .search(&mut connection, base, scope, filter, attributes)
+            .await;
+        if result.is_err() {
+            drop(Object::take(connection));
+        }
+        Ok(result?)
+    }
+
+    /// The attributes needed to build an account and its email addresses
+    fn user_attributes(&self) -> Vec<&str> {
+        let users = &self.config.users;
+        std::iter::once(users.username_attribute.as_str())
+            .chain(users.name_attribute.as_deref())
+            .chain(
+                users
+                    .email_attributes
+                    .iter()
+                    .map(|email| email.attribute.as_str()),
+            )
+            .chain(std::iter::once(users.mailbox_id_attribute.as_str()))
+            .collect()
+    }
+    /// The value of the attribute. Values that are not UUIDs are hashed into one
+    ///
+    /// Nothing is derived from names so a rename keeps the mailbox. None if the entry does not have the attribute
+    pub(crate) fn attribute_mailbox_id(entry: &SearchEntry, attribute: &str) -> Option<Uuid> {
+        let Some(value) = entry.first(attribute) else {
+            warn!(
+                "LDAP entry {} has no {} and is ignored",
+                entry.dn, attribute
+            );
+            return None;
+        };
+        Some(
+            Uuid::parse_str(value)
+                .unwrap_or_else(|_| Uuid::new_v5(&MAILBOX_ID_NAMESPACE, value.as_bytes())),
+        )
+    }
+    async fn search_users(&self, filter: Filter) -> Result<Vec<SearchEntry>, Error> {
+        let filter = Filter::And(vec![self.user_filter.clone(), filter]);
+        self.search(
+            self.config.user_base_dn(),
+            Scope::Subtree,
+            &filter,
+            &self.user_attributes(),
+        )
+        .await
+    }
+    /// None if no user or more than one user matches
+    async fn find_user(&self, filter: Filter) -> Result<Option<SearchEntry>, Error> {
+        let mut entries = self.search_users(filter).await?;
+        if entries.len() > 1 {
+            warn!(
+                "{} LDAP entries match the same user: {:?}",
+                entries.len(),
+                entries.iter().map(|entry| &entry.dn).collect::<Vec<_>>()
+            );
+            return Ok(None);
+        }
+        Ok(entries.pop())
+    }
+    async fn find_user_by_username(&self, username: &str) -> Result<Option<SearchEntry>, Error> {
+        self.find_user(Filter::equality(
+            &self.config.users.username_attribute,
+            username,
+        ))
+        .await
+    }
+    /// Group memberships are not looked up. Finding them takes a search of every group
+    fn account(&self, entry: &SearchEntry) -> Option<Account> {
+        let users = &self.config.users;
+        let username = entry.first(&users.username_attribute)?.to_string();
+        let mailbox_id = Self::attribute_mailbox_id(entry, &users.mailbox_id_attribute)?;
+        let name = users
+            .name_attribute
+            .as_deref()
+            .and_then(|attribute| entry.first(attribute))
+            .map(str::to_string)
+            .unwrap_or_else(|| username.clone());
+        let addresses = self
+            .email_addresses(entry)
+            .map(|(email_address, email_type)| AccountAddress {
+                email_address: email_address.to_string(),
+                email_type,
+            })
+            .collect();
+        let mut account = Account::new(username, AccountType::Individual, mailbox_id);
+        account.name = name;
+        account.addresses = addresses;
+        Some(account)
+    }
+    /// Every address of the entry with the type of the attribute it is in
+    pub(crate) fn email_addresses<'a>(
+        &'a self,
+        entry: &'a SearchEntry,
+    ) -> impl Iterator<Item = (&'a str, EmailType)> + 'a {
+        self.config
+            .users
+            .email_attributes
+            .iter()
+            .flat_map(move |email| {
+                entry
+                    .values(&email.attribute)
+                    .iter()
+                    .map(move |address| (address.as_str(), email.email_type))
+            })
+    }
+    fn primary_email(&self, entry: &SearchEntry) -> Option<String> {
+        self.email_addresses(entry)
+            .find(|(_, email_type)| *email_type == EmailType::Primary)
+            .map(|(address, _)| address.to_string())
+    }
+
+    async fn group_members(&self, group: &SearchEntry) -> Result<Vec<SearchEntry>, Error> {
+        let groups = &self.config.groups;
+        match groups.membership {
+            Membership::GroupOfNames => {
+                let mut members = Vec::new();
+                for member in group.values(&groups.member_attribute) {
+                    members.extend(
+                        self.search(
+                            member,
+                            Scope::Base,
+                            &self.user_filter,
+                            &self.user_attributes(),
+                        )
+                        .await?,
+                    );
+                }
+                Ok(members)
+            }
+            Membership::MemberOf => {
+                self.search_users(Filter::equality(&groups.member_of_attribute, &group.dn))
+                    .await
+            }
+        }
+    }
+
+    fn get_success_response() -> ServiceConfigurationResponse {
+        ServiceConfigurationResponse::Success {
+            new_install: false,
+            internal_service_name: Self::directory_name().to_string(),
+            git: GitInfo {
+                commit: env!("VERGEN_GIT_SHA").to_string(),
+                branch: env!("VERGEN_GIT_BRANCH").to_string(),
+                commit_date: env!("VERGEN_GIT_COMMIT_DATE").to_string(),
+            },
+            service_type: ServiceType::Directory,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+impl Service for LdapDirectory {
+    type ServiceConfig = LdapConfig;
+    type ServiceError = Error;
+}
+
+#[async_trait]
+impl Directory for LdapDirectory {
+    fn directory_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "directory_ldap"
+    }
+
+    async fn load(config: Self::ServiceConfig) -> Result<Self, Self::ServiceError>
+    where
+        Self: Sized,
+    {
+        let user_filter = config.user_filter()?;
+        let group_filter = config.group_filter()?;
+        let settings = ConnectionSettings::new(&config)?;
+        let connections = ConnectionPool::builder(ServiceConnections {
+            settings: settings.clone(),
+            bind_dn: config.bind_dn.clone(),
+            bind_password: config.bind_password.clone(),
+        })
+        .max_size(config.pool_size.max(1))
+        .wait_timeout(Some(settings.timeout()))
+        .runtime(Runtime::Tokio1)
+        .build()
+        .map_err(|error| Error::Pool(error.to_string()))?;
+        // Fails early if the server can not be reached or refuses the service bind
+        drop(connections.get().await?);
+        Ok(Self {
+            user_filter,
+            group_filter,
+            settings,
+            connections,
+            auth_failures: Arc::new(AuthFailureTracker::new(config.brute_force.clone())),
+            config: Arc::new(config),
+        })
+    }
+
+    async fn get_account(&self, username: String) -> Result<Option<Account>, Self::ServiceError> {
+        let entry = self.find_user_by_username(&username).await?;
+        Ok(entry.and_then(|entry| self.account(&entry)))
+    }
+
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let attributes: Vec<Filter> = self
+            .config
+            .users
+            .email_attributes
+            .iter()
+            .filter(|email| email.email_type != EmailType::List)
+            .map(|email| Filter::equality(&email.attribute, &email_address))
+            .collect();
+        if attributes.is_empty() {
+            return Ok(None);
+        }
+        let entry = self.find_user(Filter::Or(attributes)).await?;
+        Ok(entry.and_then(|entry| self.account(&entry)))
+    }
+
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, Self::ServiceError> {
+        let groups = &self.config.groups;
+        let filter = Filter::And(vec![
+            self.group_filter.clone(),
+            Filter::equality(&groups.mail_attribute, &email_address),
+        ]);
+        let attributes = [
+            groups.name_attribute.as_str(),
+            &groups.description_attribute,
+            &groups.mail_attribute,
+            &groups.member_attribute,
+            &groups.mailbox_id_attribute,
+        ];
+        let entries = self
+            .search(
+                self.config.group_base_dn(),
+                Scope::Subtree,
+                &filter,
+                &attributes,
+            )
+            .await?;
+        let Some(entry) = entries.into_iter().next() else {
+            return Ok(None);
+        };
+        let Some(name) = entry.first(&groups.name_attribute) else {
+            return Ok(None);
+        };
+        let list_address = entry
+            .values(&groups.mail_attribute)
+            .iter()
+            .find(|address| address.eq_ignore_ascii_case(&email_address))
+            .cloned()
+            .unwrap_or(email_address);
+        let members = self
+            .group_members(&entry)
+            .await?
+            .iter()
+            .filter_map(|member| self.primary_email(member))
+            .collect();
+        let Some(mailbox_id) = Self::attribute_mailbox_id(&entry, &groups.mailbox_id_attribute)
+        else {
+            return Ok(None);
+        };
+        Ok(Some(MailingList {
+            group: Group {
+                group_type: GroupType::List,
+                name: name.to_string(),
+                description: entry
+                    .first(&groups.description_attribute)
+                    .unwrap_or_default()
+                    .to_string(),
+                mailbox_id,
+            },
+            list_address,
+            posting_policy: PostingPolicy::default(),
+            members,
+        }))
+    }
+
+    async fn login_account(
+        &self,
+        username: String,
+        password: String,
+        protocol: LoginProtocol,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        if password.is_empty() {
+            return Ok(None);
+        }
+        let Some(entry) = self.find_user_by_username(&username).await? else {
+            return Ok(None);
+        };
+        // A separate connection so the pooled connections stay bound as bind_dn
+        let mut connection = self.settings.connect().await?;
+        let bound = self
+            .settings
+            .bind(&mut connection, &entry.dn, &password)
+            .await?;
+        if let Err(error) = connection.unbind().await {
+            warn!("Unable to unbind from LDAP: {}", error);
+        }
+        if !bound {
+            return Ok(None);
+        }
+        Ok(self
+            .account(&entry)
+            .filter(|account| account.can_login(protocol)))
+    }
+
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        _code: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        // No account has TOTP enabled
+        self.login_account(username, password, LoginProtocol::Http)
+            .await
+    }
+
+    async fn create_app_password(
+        &self,
+        _username: String,
+        _name: String,
+        _protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, Self::ServiceError> {
+        Ok(None)
+    }
+
+    async fn list_app_passwords(
+        &self,
+        _username: String,
+    ) -> Result<Vec<AppPassword>, Self::ServiceError> {
+        Ok(vec![])
+    }
+
+    async fn revoke_app_password(
+        &self,
+        _username: String,
+        _id: i64,
+    ) -> Result<bool, Self::ServiceError> {
+        Ok(false)
+    }
+
+    async fn enroll_totp(
+        &self,
+        _username: String,
+    ) -> Result<Option<TotpEnrollment>, Self::ServiceError> {
+        Ok(None)
+    }
+
+    async fn verify_totp(
+        &self,
+        _username: String,
+        _code: String,
+    ) -> Result<bool, Self::ServiceError> {
+        Ok(false)
+    }
+
+    async fn disable_totp(&self, _username: String) -> Result<bool, Self::ServiceError> {
+        Ok(false)
+    }
+
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        Ok(self
+            .auth_failures
+            .check(remote, username.as_deref(), Self::now() as i64))
+    }
+
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        // The directory is read only, so accounts are never deactivated. The lockout still applies
+        Ok(self
+            .auth_failures
+            .record_failure(remote, username.as_deref(), false, Self::now() as i64)
+            .lockout)
+    }
+
+    async fn record_auth_success(&self, username: String) -> Result<bool, Self::ServiceError> {
+        Ok(self.auth_failures.record_success(&username))
+    }
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, Self::ServiceError> {
+        Ok(self.auth_failures.lockouts(Self::now() as i64))
+    }
+
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, Self::ServiceError> {
+        Ok(self.auth_failures.clear(&key))
+    }
+
+    async fn get_groups(&self) -> Result<Vec<String>, Self::ServiceError> {
+        let name_attribute = &self.config.groups.name_attribute;
+        let entries = self
+            .search(
+                self.config.group_base_dn(),
+                Scope::Subtree,
+                &self.group_filter,
+                &[name_attribute],
+            )
+            .await?;
+        Ok(entries
+            .iter()
+            .filter_map(|entry| entry.first(name_attribute))
+            .map(str::to_string)
+            .collect())
+    }
+
+    async fn validate_config(
+        &self,
+        _: ValidateDirectoryRequest,
+    ) -> Result<ServiceConfigurationResponse, Self::ServiceError> {
+        // Mailbox ids come from `mailbox_id_attribute` so the namespaces are not used.
+        // Searching the base checks the server is reachable and the service bind works
+        self.search(
+            &self.config.base_dn,
+            Scope::Base,
+            &Filter::Present("objectClass".to_string()),
+            &[],
+        )
+        .await?;
+        Ok(Self::get_success_response())
+    }
+}
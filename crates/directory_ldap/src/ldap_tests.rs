@@ -0,0 +1,288 @@
+use uuid::Uuid;
+
+use directories::directory_type::Directory;
+use directories::ValidateDirectoryRequest;
+use utils::app_password::LoginProtocol;
+
+use crate::ldap_config::{GroupConfig, LdapConfig, Membership, UserConfig};
+use crate::ldap_directory::{Error, LdapDirectory, MAILBOX_ID_NAMESPACE};
+use crate::test_server::{start_server, TestEntry};
+
+const JOHN_DN: &str = "uid=john,ou=people,dc=example,dc=com";
+const JANE_DN: &str = "uid=jane,ou=people,dc=example,dc=com";
+const STAFF_DN: &str = "cn=staff,ou=groups,dc=example,dc=com";
+const JOHN_UUID: &str = "5d2f1b34-8e0a-4f43-9c6a-2b7f5c1e9a10";
+const JANE_UUID: &str = "0c9a7e52-14d3-4b6f-a8e1-7f2d3c5b9e04";
+const STAFF_UUID: &str = "b83e6f1a-2c47-4d90-9a5e-c16f0d7b2e38";
+
+fn entries() -> Vec<TestEntry> {
+    vec![
+        TestEntry::new("cn=nitro_mail,dc=example,dc=com").password("service"),
+        TestEntry::new(JOHN_DN)
+            .password("john password")
+            .attribute("objectClass", &["inetOrgPerson"])
+            .attribute("uid", &["john"])
+            .attribute("mail", &["john@example.com"])
+            .attribute("mailAlternateAddress", &["j@example.com"])
+            .attribute("entryUUID", &[JOHN_UUID])
+            .attribute("memberOf", &[STAFF_DN]),
+        TestEntry::new(JANE_DN)
+            .password("jane password")
+            .attribute("objectClass", &["inetOrgPerson"])
+            .attribute("uid", &["jane"])
+            .attribute("mail", &["jane@example.com"])
+            .attribute("entryUUID", &[JANE_UUID])
+            .attribute("memberOf", &[STAFF_DN]),
+        // Not matched by the user filter
+        TestEntry::new("uid=printer,ou=people,dc=example,dc=com")
+            .password("printer password")
+            .attribute("objectClass", &["device"])
+            .attribute("uid", &["printer"])
+            .attribute("mail", &["printer@example.com"])
+            .attribute("entryUUID", &["e4f1a2b3-5c6d-4e7f-8091-a2b3c4d5e6f7"]),
+        TestEntry::new(STAFF_DN)
+            .attribute("objectClass", &["groupOfNames"])
+            .attribute("cn", &["staff"])
+            .attribute("description", &["Everyone"])
+            .attribute("mail", &["staff@example.com"])
+            .attribute("entryUUID", &[STAFF_UUID])
+            .attribute("member", &[JOHN_DN, JANE_DN]),
+        TestEntry::new("cn=admins,ou=groups,dc=example,dc=com")
+            .attribute("objectClass", &["groupOfNames"])
+            .attribute("cn", &["admins"])
+            .attribute("entryUUID", &["7a1c9d3e-6b2f-4e85-b0a4-3d8e5f1c2a96"])
+            .attribute("member", &[JOHN_DN]),
+    ]
+}
+fn config(url: String, membership: Membership) -> LdapConfig {
+    LdapConfig {
+        url,
+        bind_dn: "cn=nitro_mail,dc=example,dc=com".to_string(),
+        bind_password: "service".to_string(),
+        base_dn: "dc=example,dc=com".to_string(),
+        users: UserConfig {
+            base_dn: Some("ou=people,dc=example,dc=com".to_string()),
+            ..Default::default()
+        },
+        groups: GroupConfig {
+            base_dn: Some("ou=groups,dc=example,dc=com".to_string()),
+            membership,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+async fn ldap_directory(membership: Membership) -> LdapDirectory {
+    let address = start_server(entries()).await;
+    LdapDirectory::load(config(format!("ldap://{}", address), membership))
+        .await
+        .expect("Failed to connect to the test server")
+}
+
+#[tokio::test]
+async fn test_service_bind() {
+    let address = start_server(entries()).await;
+    let mut config = config(format!("ldap://{}", address), Membership::GroupOfNames);
+    config.bind_password = "wrong".to_string();
+    assert!(matches!(
+        LdapDirectory::load(config).await,
+        Err(Error::ServiceBind)
+    ));
+}
+
+#[tokio::test]
+async fn test_starttls() {
+    // The test server refuses StartTLS, so nothing is sent before the upgrade
+    let address = start_server(entries()).await;
+    let mut config = config(format!("ldap://{}", address), Membership::GroupOfNames);
+    config.starttls = true;
+    assert!(matches!(
+        LdapDirectory::load(config).await,
+        Err(Error::Ldap(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_concurrent_searches() {
+    let directory = ldap_directory(Membership::GroupOfNames).await;
+    let searches = (0..32).map(|index| {
+        let directory = directory.clone();
+        let username = if index % 2 == 0 { "john" } else { "jane" };
+        async move { directory.get_account(username.to_string()).await }
+    });
+    for account in futures::future::join_all(searches).await {
+        assert!(account.unwrap().is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_get_account() {
+    let directory = ldap_directory(Membership::GroupOfNames).await;
+    let account = directory
+        .get_account("john".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.username, "john");
+    assert_eq!(account.name, "john");
+    assert_eq!(account.primary_address(), Some("john@example.com"));
+    assert_eq!(account.addresses.len(), 2);
+    assert!(directory
+        .get_account("printer".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(directory
+        .get_account("missing".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    // Filter values are never parsed so they can not widen the search
+    assert!(directory
+        .get_account("*".to_string())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_mailbox_ids() {
+    let directory = ldap_directory(Membership::GroupOfNames).await;
+    let mailbox_id = |username: &str| {
+        let directory = directory.clone();
+        let username = username.to_string();
+        async move {
+            directory
+                .get_account(username)
+                .await
+                .unwrap()
+                .map(|account| account.mailbox_id)
+        }
+    };
+    // The same ids before and after validate_config
+    assert_eq!(
+        mailbox_id("john").await,
+        Some(Uuid::parse_str(JOHN_UUID).unwrap())
+    );
+    directory
+        .validate_config(ValidateDirectoryRequest {
+            group_namespace: Uuid::new_v4(),
+            account_namespace: Uuid::new_v4(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        mailbox_id("john").await,
+        Some(Uuid::parse_str(JOHN_UUID).unwrap())
+    );
+    assert_eq!(
+        mailbox_id("jane").await,
+        Some(Uuid::parse_str(JANE_UUID).unwrap())
+    );
+    let list = directory
+        .get_mailing_list("staff@example.com".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(list.group.mailbox_id, Uuid::parse_str(STAFF_UUID).unwrap());
+
+    let address = start_server(entries()).await;
+    // Values that are not UUIDs are hashed
+    let mut config = config(format!("ldap://{}", address), Membership::GroupOfNames);
+    config.users.mailbox_id_attribute = "mail".to_string();
+    let directory = LdapDirectory::load(config.clone()).await.unwrap();
+    let john = directory
+        .get_account("john".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        john.mailbox_id,
+        Uuid::new_v5(&MAILBOX_ID_NAMESPACE, b"john@example.com")
+    );
+    // Entries without the attribute are not accounts
+    config.users.mailbox_id_attribute = "employeeNumber".to_string();
+    let directory = LdapDirectory::load(config).await.unwrap();
+    assert!(directory
+        .get_account("john".to_string())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_login() {
+    let directory = ldap_directory(Membership::GroupOfNames).await;
+    let login = |username: &str, password: &str| {
+        directory.login_account(
+            username.to_string(),
+            password.to_string(),
+            LoginProtocol::Smtp,
+        )
+    };
+    let account = login("john", "john password").await.unwrap();
+    assert_eq!(account.unwrap().username, "john");
+    assert!(login("john", "jane password").await.unwrap().is_none());
+    // An empty password would be an unauthenticated bind
+    assert!(login("john", "").await.unwrap().is_none());
+    assert!(login("printer", "printer password")
+        .await
+        .unwrap()
+        .is_none());
+
+    // The service connection is still bound as the service account
+    assert!(directory
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn test_get_account_by_email() {
+    let directory = ldap_directory(Membership::GroupOfNames).await;
+    for address in ["john@example.com", "J@Example.com"] {
+        let account = directory
+            .get_account_by_email(address.to_string())
+            .await
+            .unwrap();
+        assert_eq!(account.unwrap().username, "john", "{}", address);
+    }
+    assert!(directory
+        .get_account_by_email("staff@example.com".to_string())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_mailing_list() {
+    for membership in [Membership::GroupOfNames, Membership::MemberOf] {
+        let directory = ldap_directory(membership).await;
+        let list = directory
+            .get_mailing_list("staff@example.com".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(list.group.name, "staff");
+        assert_eq!(list.group.description, "Everyone");
+        assert_eq!(list.list_address, "staff@example.com");
+        let mut members = list.members.clone();
+        members.sort();
+        assert_eq!(members, vec!["jane@example.com", "john@example.com"]);
+
+        assert!(directory
+            .get_mailing_list("john@example.com".to_string())
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_get_groups() {
+    let directory = ldap_directory(Membership::GroupOfNames).await;
+    let mut groups = directory.get_groups().await.unwrap();
+    groups.sort();
+    assert_eq!(groups, vec!["admins", "staff"]);
+}
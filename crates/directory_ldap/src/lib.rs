@@ -0,0 +1,8 @@
+pub mod filter;
+pub mod ldap_client;
+pub mod ldap_config;
+pub mod ldap_directory;
+#[cfg(test)]
+pub mod ldap_tests;
+#[cfg(test)]
+pub mod test_server;
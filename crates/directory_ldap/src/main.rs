@@ -0,0 +1,16 @@
+use std::env::current_dir;
+
+use directories::directory_service::DirectoryService;
+use directories::directory_type::Directory;
+use utils::configs::Config;
+
+use directory_ldap::ldap_config::LdapConfig;
+use directory_ldap::ldap_directory::LdapDirectory;
+
+#[tokio::main]
+async fn main() {
+    let ldap_config = LdapConfig::get_or_save_default(current_dir().unwrap()).unwrap();
+    let directory = LdapDirectory::load(ldap_config).await.unwrap();
+    let service = DirectoryService::new(directory);
+    service.run().await;
+}
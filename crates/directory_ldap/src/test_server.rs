@@ -0,0 +1,320 @@
+//! An in process LDAP server for tests
+//!
+//! Supports simple bind, search and unbind against a fixed list of entries. StartTLS is refused
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use ldap3::asn1::{
+    parse_tag, parse_uint, write, ASNTag, Enumerated, Integer, OctetString, Sequence, Set,
+    StructureTag, Tag, TagClass, PL,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::filter::Filter;
+
+const PROTOCOL_ERROR: i64 = 2;
+const INSUFFICIENT_ACCESS_RIGHTS: i64 = 50;
+const INVALID_CREDENTIALS: i64 = 49;
+
+const BIND_REQUEST: u64 = 0;
+const BIND_RESPONSE: u64 = 1;
+const UNBIND_REQUEST: u64 = 2;
+const SEARCH_REQUEST: u64 = 3;
+const SEARCH_RESULT_ENTRY: u64 = 4;
+const SEARCH_RESULT_DONE: u64 = 5;
+const EXTENDED_REQUEST: u64 = 23;
+const EXTENDED_RESPONSE: u64 = 24;
+
+#[derive(Debug, Clone)]
+pub struct TestEntry {
+    pub dn: String,
+    pub password: Option<String>,
+    pub attributes: Vec<(String, Vec<String>)>,
+}
+impl TestEntry {
+    pub fn new(dn: &str) -> Self {
+        Self {
+            dn: dn.to_string(),
+            password: None,
+            attributes: vec![],
+        }
+    }
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+    pub fn attribute(mut self, name: &str, values: &[&str]) -> Self {
+        self.attributes.push((
+            name.to_string(),
+            values.iter().map(|value| value.to_string()).collect(),
+        ));
+        self
+    }
+    fn values(&self, attribute: &str) -> &[String] {
+        self.attributes
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+            .map(|(_, values)| values.as_slice())
+            .unwrap_or_default()
+    }
+    fn in_scope(&self, base: &str, scope: i64) -> bool {
+        let dn = self.dn.to_lowercase();
+        let base = base.to_lowercase();
+        let parent = dn.split_once(',').map(|(_, parent)| parent);
+        match scope {
+            0 => dn == base,
+            1 => parent == Some(base.as_str()),
+            _ => dn == base || dn.ends_with(&format!(",{}", base)),
+        }
+    }
+    fn matches(&self, filter: &Filter) -> bool {
+        match filter {
+            Filter::And(filters) => filters.iter().all(|filter| self.matches(filter)),
+            Filter::Or(filters) => filters.iter().any(|filter| self.matches(filter)),
+            Filter::Not(filter) => !self.matches(filter),
+            Filter::Equality(attribute, value) | Filter::Approximate(attribute, value) => self
+                .values(attribute)
+                .iter()
+                .any(|found| found.eq_ignore_ascii_case(value)),
+            Filter::Substrings {
+                attribute,
+                initial,
+                any,
+                last,
+            } => self.values(attribute).iter().any(|found| {
+                let found = found.to_lowercase();
+                let mut rest = found.as_str();
+                if let Some(initial) = initial {
+                    let Some(after) = rest.strip_prefix(&initial.to_lowercase()) else {
+                        return false;
+                    };
+                    rest = after;
+                }
+                for any in any {
+                    let any = any.to_lowercase();
+                    let Some(index) = rest.find(&any) else {
+                        return false;
+                    };
+                    rest = &rest[index + any.len()..];
+                }
+                last.as_ref()
+                    .is_none_or(|last| rest.ends_with(&last.to_lowercase()))
+            }),
+            Filter::GreaterOrEqual(attribute, value) => {
+                self.values(attribute).iter().any(|found| found >= value)
+            }
+            Filter::LessOrEqual(attribute, value) => {
+                self.values(attribute).iter().any(|found| found <= value)
+            }
+            Filter::Present(attribute) => !self.values(attribute).is_empty(),
+        }
+    }
+}
+
+fn children(tag: &StructureTag) -> &[StructureTag] {
+    match &tag.payload {
+        PL::C(children) => children,
+        PL::P(_) => panic!("Expected a constructed value: {:?}", tag),
+    }
+}
+fn string(tag: &StructureTag) -> &str {
+    match &tag.payload {
+        PL::P(value) => std::str::from_utf8(value).unwrap(),
+        PL::C(_) => panic!("Expected a primitive value: {:?}", tag),
+    }
+}
+fn integer(tag: &StructureTag) -> i64 {
+    match &tag.payload {
+        PL::P(value) => parse_uint(value).unwrap().1 as i64,
+        PL::C(_) => panic!("Expected a primitive value: {:?}", tag),
+    }
+}
+fn octet_string(value: &str) -> Tag {
+    Tag::OctetString(OctetString {
+        inner: value.as_bytes().to_vec(),
+        ..Default::default()
+    })
+}
+fn sequence(inner: Vec<Tag>) -> Tag {
+    Tag::Sequence(Sequence {
+        inner,
+        ..Default::default()
+    })
+}
+
+fn decode_filter(tag: &StructureTag) -> Filter {
+    assert_eq!(tag.class, TagClass::Context);
+    let pair = || {
+        let children = children(tag);
+        (
+            string(&children[0]).to_string(),
+            string(&children[1]).to_string(),
+        )
+    };
+    match tag.id {
+        0 => Filter::And(children(tag).iter().map(decode_filter).collect()),
+        1 => Filter::Or(children(tag).iter().map(decode_filter).collect()),
+        2 => Filter::Not(Box::new(decode_filter(&children(tag)[0]))),
+        3 => {
+            let (attribute, value) = pair();
+            Filter::Equality(attribute, value)
+        }
+        4 => {
+            let mut initial = None;
+            let mut any = vec![];
+            let mut last = None;
+            for part in children(&children(tag)[1]) {
+                let value = string(part).to_string();
+                match part.id {
+                    0 => initial = Some(value),
+                    1 => any.push(value),
+                    _ => last = Some(value),
+                }
+            }
+            Filter::Substrings {
+                attribute: string(&children(tag)[0]).to_string(),
+                initial,
+                any,
+                last,
+            }
+        }
+        5 => {
+            let (attribute, value) = pair();
+            Filter::GreaterOrEqual(attribute, value)
+        }
+        6 => {
+            let (attribute, value) = pair();
+            Filter::LessOrEqual(attribute, value)
+        }
+        7 => Filter::Present(string(tag).to_string()),
+        8 => {
+            let (attribute, value) = pair();
+            Filter::Approximate(attribute, value)
+        }
+        id => panic!("Unknown filter tag {}", id),
+    }
+}
+
+fn result(id: u64, code: i64) -> Tag {
+    Tag::Sequence(Sequence {
+        id,
+        class: TagClass::Application,
+        inner: vec![
+            Tag::Enumerated(Enumerated {
+                inner: code,
+                ..Default::default()
+            }),
+            octet_string(""),
+            octet_string(""),
+        ],
+    })
+}
+
+/// Starts the server on a random local port
+pub async fn start_server(entries: Vec<TestEntry>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let entries = Arc::new(entries);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle_connection(stream, entries.clone()));
+        }
+    });
+    address
+}
+
+async fn handle_connection(mut stream: TcpStream, entries: Arc<Vec<TestEntry>>) {
+    let mut buffer = Vec::new();
+    let mut bound = false;
+    loop {
+        // An error is an incomplete message until the connection closes
+        let (message, used) = match parse_tag(&buffer) {
+            Ok((rest, message)) => (message, buffer.len() - rest.len()),
+            Err(_) => {
+                let mut chunk = [0; 4096];
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+                }
+                continue;
+            }
+        };
+        buffer.drain(..used);
+        let id = integer(&children(&message)[0]);
+        let operation = &children(&message)[1];
+        assert_eq!(operation.class, TagClass::Application);
+        let mut responses = vec![];
+        match operation.id {
+            BIND_REQUEST => {
+                let dn = string(&children(operation)[1]);
+                let password = string(&children(operation)[2]);
+                bound = !password.is_empty()
+                    && entries.iter().any(|entry| {
+                        entry.dn.eq_ignore_ascii_case(dn)
+                            && entry.password.as_deref() == Some(password)
+                    });
+                let code = if bound { 0 } else { INVALID_CREDENTIALS };
+                responses.push(result(BIND_RESPONSE, code));
+            }
+            UNBIND_REQUEST => return,
+            SEARCH_REQUEST if !bound => {
+                responses.push(result(SEARCH_RESULT_DONE, INSUFFICIENT_ACCESS_RIGHTS));
+            }
+            SEARCH_REQUEST => {
+                let search = children(operation);
+                let base = string(&search[0]);
+                let scope = integer(&search[1]);
+                let filter = decode_filter(&search[6]);
+                let requested: Vec<&str> = children(&search[7]).iter().map(string).collect();
+                for entry in entries
+                    .iter()
+                    .filter(|entry| entry.in_scope(base, scope) && entry.matches(&filter))
+                {
+                    let attributes = entry
+                        .attributes
+                        .iter()
+                        .filter(|(name, _)| {
+                            requested.is_empty()
+                                || requested
+                                    .iter()
+                                    .any(|requested| requested.eq_ignore_ascii_case(name))
+                        })
+                        .map(|(name, values)| {
+                            sequence(vec![
+                                octet_string(name),
+                                Tag::Set(Set {
+                                    inner: values.iter().map(|value| octet_string(value)).collect(),
+                                    ..Default::default()
+                                }),
+                            ])
+                        })
+                        .collect();
+                    responses.push(Tag::Sequence(Sequence {
+                        id: SEARCH_RESULT_ENTRY,
+                        class: TagClass::Application,
+                        inner: vec![octet_string(&entry.dn), sequence(attributes)],
+                    }));
+                }
+                responses.push(result(SEARCH_RESULT_DONE, 0));
+            }
+            EXTENDED_REQUEST => responses.push(result(EXTENDED_RESPONSE, PROTOCOL_ERROR)),
+            id => panic!("Unsupported LDAP operation {}", id),
+        }
+        for response in responses {
+            let message = sequence(vec![
+                Tag::Integer(Integer {
+                    inner: id,
+                    ..Default::default()
+                }),
+                response,
+            ]);
+            let mut bytes = BytesMut::new();
+            write::encode_into(&mut bytes, message.into_structure()).unwrap();
+            if stream.write_all(&bytes).await.is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -1 +1,22 @@
+use utils::account::Account;
+use utils::admin::AccountAddress;
+use utils::quota::Quota;
 
+use crate::account::Model;
+
+impl Model {
+    /// The addresses and groups are stored in their own tables
+    pub fn into_account(self, addresses: Vec<AccountAddress>, groups: Vec<String>) -> Account {
+        Account {
+            username: self.username,
+            name: self.name,
+            description: self.description,
+            account_type: self.account_type,
+            mailbox_id: self.mailbox_id,
+            active: self.active,
+            quota: Quota::from_limits(self.quota, self.quota_messages),
+            addresses,
+            groups,
+        }
+    }
+}
@@ -20,12 +20,19 @@ pub struct Model {
     #[serde(skip_serializing)]
     #[sea_orm(column_type = "Text")]
     pub password: Password,
+    /// Bytes. 0 for no limit
     #[sea_orm(default_value = "0")]
     pub quota: i64,
+    /// 0 for no limit
+    #[sea_orm(default_value = "0")]
+    pub quota_messages: i64,
     #[sea_orm(default_value = "individual", column_type = "Text")]
     pub account_type: AccountType,
     #[sea_orm(default_value = "true")]
     pub active: bool,
+    /// Set when the account is created and never changed. See [Account::mailbox_id](utils::account::Account::mailbox_id)
+    #[sea_orm(unique)]
+    pub mailbox_id: Uuid,
     #[sea_orm(default_expr = "Expr::current_timestamp()")]
     pub created: DateTimeWithTimeZone,
 }
@@ -40,6 +47,12 @@ pub enum Relation {
     GroupAccountRel,
     #[sea_orm(has_many = "super::emails::Entity")]
     Email,
+    #[sea_orm(has_many = "super::app_passwords::Entity")]
+    AppPassword,
+    #[sea_orm(has_one = "super::totp_secrets::Entity")]
+    TotpSecret,
+    #[sea_orm(has_many = "super::recovery_codes::Entity")]
+    RecoveryCode,
 }
 
 impl Related<super::emails::Entity> for Entity {
@@ -47,6 +60,21 @@ impl Related<super::emails::Entity> for Entity {
         Relation::Email.def()
     }
 }
+impl Related<super::app_passwords::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AppPassword.def()
+    }
+}
+impl Related<super::totp_secrets::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TotpSecret.def()
+    }
+}
+impl Related<super::recovery_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RecoveryCode.def()
+    }
+}
 impl Related<super::group_account_rels::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::GroupAccountRel.def()
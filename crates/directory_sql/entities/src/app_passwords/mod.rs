@@ -0,0 +1,78 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+use utils::app_password::{AppPassword, LoginProtocol};
+use utils::helper_types::Password;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "app_passwords")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    pub account: i64,
+    #[sea_orm(column_type = "Text")]
+    pub name: String,
+    #[serde(skip_serializing)]
+    #[sea_orm(column_type = "Text")]
+    pub password: Password,
+    pub allow_smtp: bool,
+    pub allow_imap: bool,
+    pub allow_pop3: bool,
+    #[sea_orm(default_expr = "Expr::current_timestamp()")]
+    pub created: DateTimeWithTimeZone,
+    pub last_used: Option<DateTimeWithTimeZone>,
+}
+impl Model {
+    pub fn protocols(&self) -> Vec<LoginProtocol> {
+        [
+            (self.allow_smtp, LoginProtocol::Smtp),
+            (self.allow_imap, LoginProtocol::Imap),
+            (self.allow_pop3, LoginProtocol::Pop3),
+        ]
+        .into_iter()
+        .filter_map(|(allowed, protocol)| allowed.then_some(protocol))
+        .collect()
+    }
+    pub fn allows(&self, protocol: LoginProtocol) -> bool {
+        match protocol {
+            LoginProtocol::Smtp => self.allow_smtp,
+            LoginProtocol::Imap => self.allow_imap,
+            LoginProtocol::Pop3 => self.allow_pop3,
+            LoginProtocol::Http => false,
+        }
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for AppPassword {
+    fn from(value: Model) -> Self {
+        AppPassword {
+            id: value.id,
+            protocols: value.protocols(),
+            name: value.name,
+            created: value.created.timestamp(),
+            last_used: value.last_used.map(|last_used| last_used.timestamp()),
+        }
+    }
+}
+
+// Foreign Key account to account::id
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::account::Entity",
+        from = "Column::Account",
+        to = "super::account::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Account,
+}
+
+impl Related<super::account::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Account.def()
+    }
+}
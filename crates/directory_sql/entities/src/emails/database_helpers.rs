@@ -0,0 +1,63 @@
+use sea_orm::prelude::*;
+use sea_orm::QuerySelect;
+
+use utils::helper_types::EmailAddress;
+
+use crate::emails::Column;
+use crate::EmailEntity;
+
+/// Stores every address in its normalized form, so lookups with a normalized address find rows
+/// that were stored before addresses were normalized. Returns how many were changed
+///
+/// A row that normalizes to an address its account or group already has is removed.
+/// Fails without changing anything if an address can not be parsed
+pub async fn normalize_email_addresses<C: ConnectionTrait>(connection: &C) -> Result<u64, DbErr> {
+    let rows: Vec<(i64, Option<i64>, Option<i64>, String)> = EmailEntity::find()
+        .select_only()
+        .column(Column::Id)
+        .column(Column::Account)
+        .column(Column::Group)
+        .column(Column::EmailAddress)
+        .into_tuple()
+        .all(connection)
+        .await?;
+    let mut normalized = Vec::with_capacity(rows.len());
+    let mut invalid = Vec::new();
+    for (id, account, group, address) in rows {
+        match EmailAddress::new_lenient(&*address) {
+            Ok(email_address) => normalized.push((id, account, group, address, email_address)),
+            Err(error) => invalid.push(format!("{address:?} ({error})")),
+        }
+    }
+    if !invalid.is_empty() {
+        return Err(DbErr::Custom(format!(
+            "These addresses can not be parsed. Fix or remove them and migrate again: {}",
+            invalid.join(", ")
+        )));
+    }
+
+    let mut kept: Vec<(Option<i64>, Option<i64>, EmailAddress)> = normalized
+        .iter()
+        .filter(|(_, _, _, address, email_address)| email_address == address)
+        .map(|(_, account, group, _, email_address)| (*account, *group, email_address.clone()))
+        .collect();
+    let mut changed = 0;
+    for (id, account, group, address, email_address) in normalized {
+        if email_address == address {
+            continue;
+        }
+        let owner = (account, group, email_address);
+        if kept.contains(&owner) {
+            EmailEntity::delete_by_id(id).exec(connection).await?;
+        } else {
+            EmailEntity::update_many()
+                .col_expr(Column::EmailAddress, Expr::value(owner.2.to_string()))
+                .filter(Column::Id.eq(id))
+                .exec(connection)
+                .await?;
+            kept.push(owner);
+        }
+        changed += 1;
+    }
+    Ok(changed)
+}
@@ -5,6 +5,8 @@ use serde::Serialize;
 use utils::common_types::EmailType;
 use utils::helper_types::EmailAddress;
 
+pub mod database_helpers;
+
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
 #[sea_orm(table_name = "emails")]
 pub struct Model {
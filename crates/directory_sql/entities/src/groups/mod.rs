@@ -1,6 +1,8 @@
 use sea_orm::entity::prelude::*;
 use serde::Serialize;
 
+use utils::groups::{Group, GroupType, PostingPolicy};
+
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
 #[sea_orm(table_name = "groups")]
 pub struct Model {
@@ -10,12 +12,37 @@ pub struct Model {
     pub has_mail_box: bool,
     #[sea_orm(unique, column_type = "Text")]
     pub group_name: String,
+    #[sea_orm(default_value = "Group", column_type = "Text")]
+    pub group_type: GroupType,
+    /// Only used by [GroupType::List]
+    #[sea_orm(default_value = "MembersOnly", column_type = "Text")]
+    pub posting_policy: PostingPolicy,
+    /// Set when the group is created and never changed
+    #[sea_orm(unique)]
+    pub mailbox_id: Uuid,
+    /// Bytes. 0 for no limit
+    #[sea_orm(default_value = "0")]
+    pub quota: i64,
+    /// 0 for no limit
+    #[sea_orm(default_value = "0")]
+    pub quota_messages: i64,
     #[sea_orm(default_expr = "Expr::current_timestamp()")]
     pub created: DateTimeWithTimeZone,
 }
 
 impl ActiveModelBehavior for ActiveModel {}
 
+impl From<Model> for Group {
+    fn from(value: Model) -> Self {
+        Group {
+            group_type: value.group_type,
+            name: value.group_name,
+            description: String::new(),
+            mailbox_id: value.mailbox_id,
+        }
+    }
+}
+
 // Foreign Key group_id to Group::id
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -1,19 +1,33 @@
 pub use account::{
     ActiveModel as ActiveAccountModel, Entity as AccountEntity, Model as AccountModel,
 };
+pub use app_passwords::{
+    ActiveModel as ActiveAppPasswordModel, Entity as AppPasswordEntity, Model as AppPasswordModel,
+};
 pub use emails::{ActiveModel as EmailActiveModel, Entity as EmailEntity, Model as EmailModel};
 pub use group_account_rels::{
     ActiveModel as ActiveGroupAccountRelModel, Entity as GroupAccountRelEntity,
     Model as GroupAccountRelModel,
 };
 pub use groups::{ActiveModel as ActiveGroupModel, Entity as GroupEntity, Model as GroupModel};
+pub use recovery_codes::{
+    ActiveModel as ActiveRecoveryCodeModel, Entity as RecoveryCodeEntity,
+    Model as RecoveryCodeModel,
+};
 pub use system_configuration::{
     ActiveModel as ActiveSystemConfigurationModel, Entity as SystemConfigurationEntity,
     Model as SystemConfigurationModel,
 };
+pub use totp_secrets::{
+    ActiveModel as ActiveTotpSecretModel, Entity as TotpSecretEntity, Model as TotpSecretModel,
+};
 
 pub mod account;
+pub mod app_passwords;
 pub mod emails;
 pub mod group_account_rels;
 pub mod groups;
+pub mod mailbox_ids;
+pub mod recovery_codes;
 pub mod system_configuration;
+pub mod totp_secrets;
@@ -0,0 +1,51 @@
+//! Mailbox ids used to be derived from the username or group name on every lookup. Now they are stored
+use sea_orm::prelude::*;
+use sea_orm::QuerySelect;
+
+use utils::account::Account;
+use utils::groups::Group;
+
+use crate::{account, groups, AccountEntity, GroupEntity};
+
+/// Gives every account and group without a mailbox id the one it was using before the ids were stored.
+/// Returns how many were changed
+pub async fn fill_missing_mailbox_ids<C: ConnectionTrait>(
+    connection: &C,
+    account_namespace: &Uuid,
+    group_namespace: &Uuid,
+) -> Result<u64, DbErr> {
+    let accounts: Vec<(i64, String)> = AccountEntity::find()
+        .select_only()
+        .column(account::Column::Id)
+        .column(account::Column::Username)
+        .filter(account::Column::MailboxId.is_null())
+        .into_tuple()
+        .all(connection)
+        .await?;
+    let groups: Vec<(i64, String)> = GroupEntity::find()
+        .select_only()
+        .column(groups::Column::Id)
+        .column(groups::Column::GroupName)
+        .filter(groups::Column::MailboxId.is_null())
+        .into_tuple()
+        .all(connection)
+        .await?;
+    let changed = (accounts.len() + groups.len()) as u64;
+    for (id, username) in accounts {
+        let mailbox_id = Account::generate_mailbox_id(account_namespace, &username);
+        AccountEntity::update_many()
+            .col_expr(account::Column::MailboxId, Expr::value(mailbox_id))
+            .filter(account::Column::Id.eq(id))
+            .exec(connection)
+            .await?;
+    }
+    for (id, name) in groups {
+        let mailbox_id = Group::generate_mailbox_id(group_namespace, &name);
+        GroupEntity::update_many()
+            .col_expr(groups::Column::MailboxId, Expr::value(mailbox_id))
+            .filter(groups::Column::Id.eq(id))
+            .exec(connection)
+            .await?;
+    }
+    Ok(changed)
+}
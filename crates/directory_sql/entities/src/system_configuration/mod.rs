@@ -40,6 +40,22 @@ impl SystemConfigurationOptions {
             ),
         ]
     }
+    /// None until nitro_mail has validated the directory config
+    pub async fn get_namespace<C: ConnectionTrait>(
+        self,
+        connection: &C,
+    ) -> Result<Option<Uuid>, DbErr> {
+        let stored = Entity::find()
+            .filter(Column::Key.eq(self))
+            .one(connection)
+            .await?;
+        stored
+            .map(|stored| {
+                Uuid::from_slice(&stored.value)
+                    .map_err(|error| DbErr::Custom(format!("Invalid {self}: {error}")))
+            })
+            .transpose()
+    }
 }
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
 #[sea_orm(table_name = "system_configuration")]
@@ -0,0 +1,44 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// The TOTP secret of an account. At most one per account
+///
+/// The secret is encrypted with the configured two-factor key
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "totp_secrets")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    #[sea_orm(unique)]
+    pub account: i64,
+    #[serde(skip_serializing)]
+    pub secret: Vec<u8>,
+    /// False until the first code is verified
+    pub enabled: bool,
+    /// The last time step a code was accepted for. Codes can not be reused
+    pub last_step: Option<i64>,
+    #[sea_orm(default_expr = "Expr::current_timestamp()")]
+    pub created: DateTimeWithTimeZone,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+// Foreign Key account to account::id
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::account::Entity",
+        from = "Column::Account",
+        to = "super::account::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Account,
+}
+
+impl Related<super::account::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Account.def()
+    }
+}
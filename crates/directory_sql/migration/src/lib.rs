@@ -2,6 +2,13 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
 mod m20230804_133020_system_configurations;
+mod m20261019_000001_mailing_lists;
+mod m20261019_000002_app_passwords;
+mod m20261019_000003_two_factor;
+mod m20261019_000004_mailbox_ids;
+mod m20261019_000005_quotas;
+mod m20261019_000006_required_mailbox_ids;
+mod m20261019_000007_normalize_email_addresses;
 
 pub struct Migrator;
 
@@ -23,6 +30,13 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20220101_000001_create_table::Migration),
             Box::new(m20230804_133020_system_configurations::Migration),
+            Box::new(m20261019_000001_mailing_lists::Migration),
+            Box::new(m20261019_000002_app_passwords::Migration),
+            Box::new(m20261019_000003_two_factor::Migration),
+            Box::new(m20261019_000004_mailbox_ids::Migration),
+            Box::new(m20261019_000005_quotas::Migration),
+            Box::new(m20261019_000006_required_mailbox_ids::Migration),
+            Box::new(m20261019_000007_normalize_email_addresses::Migration),
         ]
     }
 }
@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+use entities::groups::Column;
+use entities::GroupEntity;
+
+use crate::sea_orm::EntityName;
+
+/// Adds the columns used by mailing lists to groups
+///
+/// Fresh installs already have them because the first migration creates the tables from the entities
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let table = GroupEntity.table_name();
+        if !manager.has_column(table, "group_type").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(GroupEntity.table_ref())
+                        .add_column(
+                            ColumnDef::new(Column::GroupType)
+                                .text()
+                                .not_null()
+                                .default("Group"),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+        if !manager.has_column(table, "posting_policy").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(GroupEntity.table_ref())
+                        .add_column(
+                            ColumnDef::new(Column::PostingPolicy)
+                                .text()
+                                .not_null()
+                                .default("MembersOnly"),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GroupEntity.table_ref())
+                    .drop_column(Column::GroupType)
+                    .drop_column(Column::PostingPolicy)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,30 @@
+use sea_orm_migration::prelude::*;
+
+use entities::AppPasswordEntity;
+
+use crate::sea_orm::Schema;
+
+/// Creates the table for app passwords
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(
+                schema
+                    .create_table_from_entity(AppPasswordEntity)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AppPasswordEntity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+use entities::{RecoveryCodeEntity, TotpSecretEntity};
+
+use crate::sea_orm::Schema;
+
+/// Creates the tables for TOTP secrets and recovery codes
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(
+                schema
+                    .create_table_from_entity(TotpSecretEntity)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                schema
+                    .create_table_from_entity(RecoveryCodeEntity)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RecoveryCodeEntity).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(TotpSecretEntity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,119 @@
+use sea_orm_migration::prelude::*;
+
+use entities::mailbox_ids::fill_missing_mailbox_ids;
+use entities::system_configuration::SystemConfigurationOptions;
+use entities::{account, groups, AccountEntity, GroupEntity};
+
+use crate::sea_orm::EntityName;
+
+/// Stores the mailbox id of every account and group
+///
+/// Existing rows get the id that was derived from their name, so their mail stays where it is.
+/// If the namespaces are not stored yet [the next migration](crate::m20261019_000006_required_mailbox_ids) fills them in
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const ACCOUNT_INDEX: &str = "idx-accounts-mailbox_id";
+const GROUP_INDEX: &str = "idx-groups-mailbox_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite can not add a unique column. So the column and the index are added separately
+        if !manager
+            .has_column(AccountEntity.table_name(), "mailbox_id")
+            .await?
+        {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(AccountEntity.table_ref())
+                        .add_column(ColumnDef::new(account::Column::MailboxId).uuid())
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .create_index(
+                    Index::create()
+                        .name(ACCOUNT_INDEX)
+                        .table(AccountEntity.table_ref())
+                        .col(account::Column::MailboxId)
+                        .unique()
+                        .to_owned(),
+                )
+                .await?;
+        }
+        if !manager
+            .has_column(GroupEntity.table_name(), "mailbox_id")
+            .await?
+        {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(GroupEntity.table_ref())
+                        .add_column(ColumnDef::new(groups::Column::MailboxId).uuid())
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .create_index(
+                    Index::create()
+                        .name(GROUP_INDEX)
+                        .table(GroupEntity.table_ref())
+                        .col(groups::Column::MailboxId)
+                        .unique()
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        let connection = manager.get_connection();
+        let account_namespace = SystemConfigurationOptions::AccountNamespace
+            .get_namespace(connection)
+            .await?;
+        let group_namespace = SystemConfigurationOptions::GroupNamespace
+            .get_namespace(connection)
+            .await?;
+        if let (Some(account_namespace), Some(group_namespace)) =
+            (account_namespace, group_namespace)
+        {
+            fill_missing_mailbox_ids(connection, &account_namespace, &group_namespace).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(ACCOUNT_INDEX)
+                    .table(AccountEntity.table_ref())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(GROUP_INDEX)
+                    .table(GroupEntity.table_ref())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AccountEntity.table_ref())
+                    .drop_column(account::Column::MailboxId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GroupEntity.table_ref())
+                    .drop_column(groups::Column::MailboxId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,85 @@
+use sea_orm_migration::prelude::*;
+
+use entities::{account, groups, AccountEntity, GroupEntity};
+
+use crate::sea_orm::EntityName;
+
+/// Adds the message limit to accounts and both limits to groups. Existing rows have no limits
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if !manager
+            .has_column(AccountEntity.table_name(), "quota_messages")
+            .await?
+        {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(AccountEntity.table_ref())
+                        .add_column(
+                            ColumnDef::new(account::Column::QuotaMessages)
+                                .big_integer()
+                                .not_null()
+                                .default(0),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+        let table = GroupEntity.table_name();
+        if !manager.has_column(table, "quota").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(GroupEntity.table_ref())
+                        .add_column(
+                            ColumnDef::new(groups::Column::Quota)
+                                .big_integer()
+                                .not_null()
+                                .default(0),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+        if !manager.has_column(table, "quota_messages").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(GroupEntity.table_ref())
+                        .add_column(
+                            ColumnDef::new(groups::Column::QuotaMessages)
+                                .big_integer()
+                                .not_null()
+                                .default(0),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AccountEntity.table_ref())
+                    .drop_column(account::Column::QuotaMessages)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GroupEntity.table_ref())
+                    .drop_column(groups::Column::Quota)
+                    .drop_column(groups::Column::QuotaMessages)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,148 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::prelude::Uuid;
+use sea_orm_migration::sea_orm::{
+    ColumnTrait, ConnectionTrait, DbBackend, EntityTrait, QueryFilter, QuerySelect,
+};
+
+use entities::mailbox_ids::fill_missing_mailbox_ids;
+use entities::system_configuration::SystemConfigurationOptions;
+use entities::{account, groups, AccountEntity, GroupEntity};
+
+use crate::sea_orm::EntityName;
+
+/// Makes the mailbox id of accounts and groups required
+///
+/// Rows still without one get the id derived from their name. If the namespaces were never stored
+/// nitro_mail never ran against the directory, so no mail was stored and they are given random ids.
+///
+/// SQLite can not change a column to NOT NULL without copying the table, so triggers reject NULL instead
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const TRIGGERS: [(&str, &str, &str); 4] = [
+    ("trg-accounts-mailbox_id-insert", "INSERT", "accounts"),
+    ("trg-accounts-mailbox_id-update", "UPDATE", "accounts"),
+    ("trg-groups-mailbox_id-insert", "INSERT", "groups"),
+    ("trg-groups-mailbox_id-update", "UPDATE", "groups"),
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let connection = manager.get_connection();
+        let account_namespace = SystemConfigurationOptions::AccountNamespace
+            .get_namespace(connection)
+            .await?;
+        let group_namespace = SystemConfigurationOptions::GroupNamespace
+            .get_namespace(connection)
+            .await?;
+        if let (Some(account_namespace), Some(group_namespace)) =
+            (account_namespace, group_namespace)
+        {
+            fill_missing_mailbox_ids(connection, &account_namespace, &group_namespace).await?;
+        }
+        fill_random_mailbox_ids(connection).await?;
+
+        match manager.get_database_backend() {
+            DbBackend::Sqlite => {
+                for (name, event, table) in TRIGGERS {
+                    connection
+                        .execute_unprepared(&format!(
+                            "CREATE TRIGGER IF NOT EXISTS \"{name}\" BEFORE {event} ON \"{table}\" \
+                             WHEN NEW.mailbox_id IS NULL \
+                             BEGIN SELECT RAISE(ABORT, '{table}.mailbox_id may not be NULL'); END"
+                        ))
+                        .await?;
+                }
+            }
+            DbBackend::Postgres | DbBackend::MySql => {
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(AccountEntity.table_ref())
+                            .modify_column(
+                                ColumnDef::new(account::Column::MailboxId).uuid().not_null(),
+                            )
+                            .to_owned(),
+                    )
+                    .await?;
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(GroupEntity.table_ref())
+                            .modify_column(
+                                ColumnDef::new(groups::Column::MailboxId).uuid().not_null(),
+                            )
+                            .to_owned(),
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        match manager.get_database_backend() {
+            DbBackend::Sqlite => {
+                for (name, _, _) in TRIGGERS {
+                    manager
+                        .get_connection()
+                        .execute_unprepared(&format!("DROP TRIGGER IF EXISTS \"{name}\""))
+                        .await?;
+                }
+                Ok(())
+            }
+            DbBackend::Postgres | DbBackend::MySql => {
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(AccountEntity.table_ref())
+                            .modify_column(ColumnDef::new(account::Column::MailboxId).uuid().null())
+                            .to_owned(),
+                    )
+                    .await?;
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(GroupEntity.table_ref())
+                            .modify_column(ColumnDef::new(groups::Column::MailboxId).uuid().null())
+                            .to_owned(),
+                    )
+                    .await
+            }
+        }
+    }
+}
+
+/// For the rows [fill_missing_mailbox_ids] could not fill
+async fn fill_random_mailbox_ids<C: ConnectionTrait>(connection: &C) -> Result<(), DbErr> {
+    let accounts: Vec<i64> = AccountEntity::find()
+        .select_only()
+        .column(account::Column::Id)
+        .filter(account::Column::MailboxId.is_null())
+        .into_tuple()
+        .all(connection)
+        .await?;
+    for id in accounts {
+        AccountEntity::update_many()
+            .col_expr(account::Column::MailboxId, Expr::value(Uuid::new_v4()))
+            .filter(account::Column::Id.eq(id))
+            .exec(connection)
+            .await?;
+    }
+    let groups: Vec<i64> = GroupEntity::find()
+        .select_only()
+        .column(groups::Column::Id)
+        .filter(groups::Column::MailboxId.is_null())
+        .into_tuple()
+        .all(connection)
+        .await?;
+    for id in groups {
+        GroupEntity::update_many()
+            .col_expr(groups::Column::MailboxId, Expr::value(Uuid::new_v4()))
+            .filter(groups::Column::Id.eq(id))
+            .exec(connection)
+            .await?;
+    }
+    Ok(())
+}
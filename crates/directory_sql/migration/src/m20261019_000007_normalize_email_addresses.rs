@@ -0,0 +1,22 @@
+use sea_orm_migration::prelude::*;
+
+use entities::emails::database_helpers::normalize_email_addresses;
+
+/// Normalizes the stored addresses
+///
+/// Addresses are normalized when they are parsed, so rows stored before that, such as
+/// `User@Example.COM`, were no longer found
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        normalize_email_addresses(manager.get_connection()).await?;
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
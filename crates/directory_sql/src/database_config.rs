@@ -4,6 +4,9 @@ use sea_orm::ConnectOptions;
 use serde::{Deserialize, Serialize};
 
 use helper_macros::const_and_default_function;
+use utils::configs::brute_force::BruteForceConfig;
+use utils::configs::password::PasswordConfig;
+use utils::configs::two_factor::TwoFactorConfig;
 use utils::configs::{Config, ConfigName};
 
 use crate::database_config::mysql::MysqlSettings;
@@ -44,6 +47,12 @@ impl Default for PoolConfig {
 pub struct DatabaseConfig {
     pub database: Database,
     pub pool: PoolConfig,
+    #[serde(default)]
+    pub password: PasswordConfig,
+    #[serde(default)]
+    pub two_factor: TwoFactorConfig,
+    #[serde(default)]
+    pub brute_force: BruteForceConfig,
 }
 
 impl Into<ConnectOptions> for DatabaseConfig {
@@ -80,6 +89,9 @@ impl Default for DatabaseConfig {
         DatabaseConfig {
             database: Database::default(),
             pool: PoolConfig::default(),
+            password: PasswordConfig::default(),
+            two_factor: TwoFactorConfig::default(),
+            brute_force: BruteForceConfig::default(),
         }
     }
 }
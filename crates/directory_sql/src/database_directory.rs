@@ -2,23 +2,53 @@ use async_trait::async_trait;
 use futures::future::Ready;
 use sea_orm::prelude::*;
 use sea_orm::{
-    ActiveValue, ColumnTrait, ConnectOptions, Database, DatabaseConnection, QueryFilter,
+    ActiveValue, ColumnTrait, ConnectOptions, Database, DatabaseConnection, QueryFilter, QueryOrder,
 };
 use sqlx::Connection;
 use std::convert::Infallible;
 use std::fmt::Debug;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
-use tracing::error;
+use tracing::{error, warn};
 
 use directories::directory_type::Directory;
 use directories::ValidateDirectoryRequest;
 use entities::system_configuration::SystemConfigurationOptions;
+use entities::{
+    AccountModel, ActiveAccountModel, ActiveAppPasswordModel, ActiveGroupAccountRelModel,
+    ActiveGroupModel, ActiveRecoveryCodeModel, ActiveTotpSecretModel, EmailActiveModel, GroupModel,
+    TotpSecretModel,
+};
 use utils::account::Account;
+use utils::admin::{
+    AccountAddress, AccountUpdate, AdminError, AdminResult, DirectoryCapabilities, NewAccount,
+    NewGroup,
+};
+use utils::app_password::{
+    generate_app_password, normalize_app_password, AppPassword, LoginProtocol, NewAppPassword,
+};
+use utils::auth_failures::{AuthFailureTracker, Lockout, LockoutKey};
+use utils::common_types::EmailType;
+use utils::configs::password::PasswordConfig;
+use utils::configs::two_factor::TwoFactorConfig;
+use utils::groups::{Group, GroupType, MailingList};
+use utils::helper_types::password::PasswordErrors;
+use utils::helper_types::{EmailAddress, Password};
+use utils::quota::Quota;
 use utils::service::{Service, ServiceAccess};
 use utils::service_configuration::{GitInfo, ServiceConfigurationResponse, ServiceType};
+use utils::two_factor::{
+    enrollment, generate_recovery_codes, generate_totp_secret, normalize_recovery_code,
+    verify_totp, TotpEnrollment, TwoFactorError,
+};
 
 use crate::database_config::DatabaseConfig;
 
+/// Bytes. Usernames are also used as login names so they are kept short
+const MAX_USERNAME_LENGTH: usize = 255;
+
 #[async_trait]
 pub trait DatabaseDirectoryTrait: Send + Sync + 'static + ConnectionTrait + Clone {
     async fn connect(connection_options: ConnectOptions) -> Result<Self, Error>
@@ -49,10 +79,20 @@ pub enum Error {
     DatabaseError(#[from] DbErr),
     #[error(transparent)]
     SQLXError(#[from] sqlx::Error),
+    #[error(transparent)]
+    Password(#[from] PasswordErrors),
+    #[error(transparent)]
+    TwoFactor(#[from] TwoFactorError),
+    #[error("The {0} is stored when nitro_mail first validates the directory config")]
+    MissingNamespace(SystemConfigurationOptions),
 }
 #[derive(Debug, Clone)]
 pub struct DatabaseDirectory<Connection: DatabaseDirectoryTrait> {
     pub(crate) database: Connection,
+    pub(crate) password_config: PasswordConfig,
+    pub(crate) two_factor: TwoFactorConfig,
+    /// Shared by every clone so all connections count toward the same lockouts
+    pub(crate) auth_failures: Arc<AuthFailureTracker>,
 }
 impl<Connection: DatabaseDirectoryTrait> ServiceAccess for DatabaseDirectory<Connection> {
     type ServiceResponse = Self;
@@ -77,6 +117,327 @@ impl<Connection: DatabaseDirectoryTrait> DatabaseDirectory<Connection> {
         error!("Please follow the instructions here: https://docs.nitro-mail.kingtux.dev/");
         ServiceConfigurationResponse::NamespaceMismatch {}
     }
+    /// Replaces the hash with one using the preferred type and parameters.
+    ///
+    /// Only updates the account if the hash has not changed since it was checked.
+    /// Failures are logged because the login itself succeeded.
+    async fn rehash_password(&self, account: &AccountModel, password: &str) {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let new_password = match Password::new_preferred(password, &self.password_config) {
+            Ok(ok) => ok,
+            Err(error) => {
+                error!(
+                    "Unable to rehash password for {}: {}",
+                    account.username, error
+                );
+                return;
+            }
+        };
+        let result = AccountEntity::update_many()
+            .col_expr(AccountColumn::Password, Expr::value(new_password))
+            .filter(AccountColumn::Id.eq(account.id))
+            .filter(AccountColumn::Password.eq(account.password.clone()))
+            .exec(&self.database)
+            .await;
+        if let Err(error) = result {
+            error!(
+                "Unable to store rehashed password for {}: {}",
+                account.username, error
+            );
+        }
+    }
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+    /// Sets `accounts.active` to false
+    async fn deactivate_account(&self, username: &str) -> Result<(), Error> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let result = AccountEntity::update_many()
+            .col_expr(AccountColumn::Active, Expr::value(false))
+            .filter(AccountColumn::Username.eq(username))
+            .exec(&self.database)
+            .await?;
+        if result.rows_affected > 0 {
+            warn!("Deactivated {} after repeated failed logins", username);
+        }
+        Ok(())
+    }
+    async fn namespace(&self, option: SystemConfigurationOptions) -> Result<Uuid, Error> {
+        option
+            .get_namespace(&self.database)
+            .await?
+            .ok_or(Error::MissingNamespace(option))
+    }
+    /// The id derived from the username. A random one if it is taken,
+    /// which happens when an account was renamed and the old username is used again
+    async fn new_account_mailbox_id(&self, username: &str) -> Result<Uuid, Error> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let namespace = self
+            .namespace(SystemConfigurationOptions::AccountNamespace)
+            .await?;
+        let mailbox_id = Account::generate_mailbox_id(&namespace, username);
+        let taken = AccountEntity::find()
+            .filter(AccountColumn::MailboxId.eq(mailbox_id))
+            .count(&self.database)
+            .await?
+            > 0;
+        Ok(if taken { Uuid::new_v4() } else { mailbox_id })
+    }
+    async fn new_group_mailbox_id(&self, name: &str) -> Result<Uuid, Error> {
+        use entities::groups::Column as GroupColumn;
+        use entities::GroupEntity;
+        let namespace = self
+            .namespace(SystemConfigurationOptions::GroupNamespace)
+            .await?;
+        let mailbox_id = Group::generate_mailbox_id(&namespace, name);
+        let taken = GroupEntity::find()
+            .filter(GroupColumn::MailboxId.eq(mailbox_id))
+            .count(&self.database)
+            .await?
+            > 0;
+        Ok(if taken { Uuid::new_v4() } else { mailbox_id })
+    }
+    async fn find_account(&self, username: String) -> Result<Option<AccountModel>, Error> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        Ok(AccountEntity::find()
+            .filter(AccountColumn::Username.eq(username))
+            .one(&self.database)
+            .await?)
+    }
+    /// Checks the account password. Rehashes it if it uses a weaker scheme
+    async fn check_account_password(&self, account: &AccountModel, password: &str) -> bool {
+        match account.password.check_password(password) {
+            Ok(true) => {}
+            Ok(false) => return false,
+            Err(error) => {
+                error!(
+                    "Unable to check password for {}: {}",
+                    account.username, error
+                );
+                return false;
+            }
+        }
+        if account.password.needs_rehash(&self.password_config) {
+            self.rehash_password(account, password).await;
+        }
+        true
+    }
+    async fn find_totp_secret(
+        &self,
+        account: &AccountModel,
+    ) -> Result<Option<TotpSecretModel>, Error> {
+        use entities::totp_secrets::Column as TotpSecretColumn;
+        use entities::TotpSecretEntity;
+        Ok(TotpSecretEntity::find()
+            .filter(TotpSecretColumn::Account.eq(account.id))
+            .one(&self.database)
+            .await?)
+    }
+    /// Checks the TOTP code and marks its time step as used
+    ///
+    /// The update only succeeds for one of two concurrent logins with the same code
+    async fn check_totp_code(
+        &self,
+        totp_secret: &TotpSecretModel,
+        code: &str,
+    ) -> Result<bool, Error> {
+        use entities::totp_secrets::Column as TotpSecretColumn;
+        use entities::TotpSecretEntity;
+        let secret = self.two_factor.secret_key()?.decrypt(&totp_secret.secret)?;
+        let Some(step) = verify_totp(&secret, code, Self::now(), totp_secret.last_step) else {
+            return Ok(false);
+        };
+        let result = TotpSecretEntity::update_many()
+            .col_expr(TotpSecretColumn::LastStep, Expr::value(step))
+            .col_expr(TotpSecretColumn::Enabled, Expr::value(true))
+            .filter(TotpSecretColumn::Id.eq(totp_secret.id))
+            .filter(
+                TotpSecretColumn::LastStep
+                    .is_null()
+                    .or(TotpSecretColumn::LastStep.lt(step)),
+            )
+            .exec(&self.database)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+    /// Checks the code against the unused recovery codes. A matching code is removed
+    async fn check_recovery_code(&self, account: &AccountModel, code: &str) -> Result<bool, Error> {
+        use entities::recovery_codes::Column as RecoveryCodeColumn;
+        use entities::RecoveryCodeEntity;
+        let code = normalize_recovery_code(code);
+        let recovery_codes = RecoveryCodeEntity::find()
+            .filter(RecoveryCodeColumn::Account.eq(account.id))
+            .all(&self.database)
+            .await?;
+        for recovery_code in recovery_codes {
+            if recovery_code.code.check_password(&code).unwrap_or(false) {
+                let result = RecoveryCodeEntity::delete_by_id(recovery_code.id)
+                    .exec(&self.database)
+                    .await?;
+                return Ok(result.rows_affected > 0);
+            }
+        }
+        Ok(false)
+    }
+    /// Checks a TOTP or recovery code for an account with TOTP enabled
+    async fn check_second_factor(
+        &self,
+        account: &AccountModel,
+        totp_secret: &TotpSecretModel,
+        code: &str,
+    ) -> Result<bool, Error> {
+        if self.check_totp_code(totp_secret, code).await? {
+            return Ok(true);
+        }
+        self.check_recovery_code(account, code).await
+    }
+    /// Checks the password against the app passwords of the account that allow the protocol
+    ///
+    /// Updates the last used time of the matching app password
+    async fn check_app_passwords(
+        &self,
+        account: &AccountModel,
+        password: &str,
+        protocol: LoginProtocol,
+    ) -> Result<bool, Error> {
+        use entities::app_passwords::Column as AppPasswordColumn;
+        use entities::AppPasswordEntity;
+        let password = normalize_app_password(password);
+        let app_passwords = AppPasswordEntity::find()
+            .filter(AppPasswordColumn::Account.eq(account.id))
+            .all(&self.database)
+            .await?;
+        for app_password in app_passwords
+            .into_iter()
+            .filter(|app_password| app_password.allows(protocol))
+        {
+            match app_password.password.check_password(&password) {
+                Ok(true) => {
+                    AppPasswordEntity::update_many()
+                        .col_expr(
+                            AppPasswordColumn::LastUsed,
+                            Expr::current_timestamp().into(),
+                        )
+                        .filter(AppPasswordColumn::Id.eq(app_password.id))
+                        .exec(&self.database)
+                        .await?;
+                    return Ok(true);
+                }
+                Ok(false) => {}
+                Err(error) => {
+                    error!(
+                        "Unable to check app password {} for {}: {}",
+                        app_password.id, account.username, error
+                    );
+                }
+            }
+        }
+        Ok(false)
+    }
+    async fn find_group(&self, name: String) -> Result<Option<GroupModel>, Error> {
+        use entities::groups::Column as GroupColumn;
+        use entities::GroupEntity;
+        Ok(GroupEntity::find()
+            .filter(GroupColumn::GroupName.eq(name))
+            .one(&self.database)
+            .await?)
+    }
+    /// Checks the username of a new or renamed account. `renamed` is the id of the account being renamed
+    ///
+    /// A username that is an email address may only be the address of the account itself
+    async fn check_username(
+        &self,
+        username: &str,
+        renamed: Option<i64>,
+    ) -> Result<AdminResult<()>, Error> {
+        use entities::emails::Column as EmailColumn;
+        use entities::EmailEntity;
+        if username.is_empty()
+            || username.len() > MAX_USERNAME_LENGTH
+            || username
+                .chars()
+                .any(|character| character.is_whitespace() || character.is_control())
+        {
+            return Ok(Err(AdminError::InvalidUsername(username.to_string())));
+        }
+        if self.find_account(username.to_string()).await?.is_some() {
+            return Ok(Err(AdminError::AccountExists(username.to_string())));
+        }
+        if let Ok(address) = EmailAddress::new_lenient(username.to_string()) {
+            let owner = EmailEntity::find()
+                .filter(EmailColumn::EmailAddress.eq(address))
+                .one(&self.database)
+                .await?;
+            if let Some(owner) = owner {
+                if renamed.is_none() || owner.account != renamed {
+                    return Ok(Err(AdminError::AddressInUse(username.to_string())));
+                }
+            }
+        }
+        Ok(Ok(()))
+    }
+    /// Normalizes the address and checks that no account or group has it
+    async fn unused_email_address(
+        &self,
+        email_address: String,
+    ) -> Result<AdminResult<EmailAddress>, Error> {
+        use entities::emails::Column as EmailColumn;
+        use entities::EmailEntity;
+        let Ok(normalized) = EmailAddress::new_lenient(email_address.clone()) else {
+            return Ok(Err(AdminError::InvalidEmailAddress(email_address)));
+        };
+        let existing = EmailEntity::find()
+            .filter(EmailColumn::EmailAddress.eq(normalized.clone()))
+            .one(&self.database)
+            .await?;
+        if existing.is_some() {
+            return Ok(Err(AdminError::AddressInUse(email_address)));
+        }
+        Ok(Ok(normalized))
+    }
+    fn hash_password(&self, password: &str) -> AdminResult<Password> {
+        if password.is_empty() {
+            return Err(AdminError::InvalidPassword(
+                "The password is empty".to_string(),
+            ));
+        }
+        Password::new_preferred(password, &self.password_config)
+            .map_err(|error| AdminError::InvalidPassword(error.to_string()))
+    }
+    /// Loads the addresses and groups of the account
+    async fn account(&self, account: AccountModel) -> Result<Account, Error> {
+        use entities::emails::Column as EmailColumn;
+        use entities::group_account_rels::Column as GroupAccountRelColumn;
+        use entities::{EmailEntity, GroupAccountRelEntity, GroupEntity};
+        let addresses = EmailEntity::find()
+            .filter(EmailColumn::Account.eq(account.id))
+            .order_by_asc(EmailColumn::Id)
+            .all(&self.database)
+            .await?
+            .into_iter()
+            .map(|email| AccountAddress {
+                email_address: email.email_address.into(),
+                email_type: email.email_type,
+            })
+            .collect();
+        let groups = GroupAccountRelEntity::find()
+            .filter(GroupAccountRelColumn::Account.eq(account.id))
+            .find_also_related(GroupEntity)
+            .all(&self.database)
+            .await?
+            .into_iter()
+            .filter_map(|(_, group)| group)
+            .map(|group| group.group_name)
+            .collect();
+        Ok(account.into_account(addresses, groups))
+    }
     fn get_success_response(new_install: bool) -> ServiceConfigurationResponse {
         ServiceConfigurationResponse::Success {
             new_install,
@@ -112,23 +473,816 @@ impl<Connection: DatabaseDirectoryTrait> Directory for DatabaseDirectory<Connect
         // TODO pool options
         let database =
             Connection::connect(ConnectOptions::new(config.database.to_string())).await?;
-        Ok(Self { database })
+        if config.two_factor.encryption_key.is_some() {
+            config.two_factor.secret_key()?;
+        }
+        Ok(Self {
+            database,
+            password_config: config.password,
+            two_factor: config.two_factor,
+            auth_failures: Arc::new(AuthFailureTracker::new(config.brute_force)),
+        })
     }
 
     async fn get_account(&self, username: String) -> Result<Option<Account>, Self::ServiceError> {
-        todo!()
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let account = AccountEntity::find()
+            .filter(AccountColumn::Username.eq(username))
+            .one(&self.database)
+            .await?;
+        match account {
+            Some(account) => Ok(Some(self.account(account).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        use entities::emails::Column as EmailColumn;
+        use entities::{AccountEntity, EmailEntity};
+        // Addresses are stored normalized
+        let Ok(email_address) = EmailAddress::new_lenient(email_address) else {
+            return Ok(None);
+        };
+        let email = EmailEntity::find()
+            .filter(EmailColumn::EmailAddress.eq(email_address))
+            .find_also_related(AccountEntity)
+            .one(&self.database)
+            .await?;
+        match email
+            .and_then(|(_, account)| account)
+            .filter(|account| account.active)
+        {
+            Some(account) => Ok(Some(self.account(account).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, Self::ServiceError> {
+        use entities::emails::Column as EmailColumn;
+        use entities::group_account_rels::Column as GroupAccountRelColumn;
+        use entities::{AccountEntity, EmailEntity, GroupAccountRelEntity, GroupEntity};
+        let Ok(email_address) = EmailAddress::new_lenient(email_address) else {
+            return Ok(None);
+        };
+        let list = EmailEntity::find()
+            .filter(EmailColumn::EmailAddress.eq(email_address))
+            .filter(EmailColumn::EmailType.eq(EmailType::List))
+            .find_also_related(GroupEntity)
+            .one(&self.database)
+            .await?;
+        let Some((list_email, Some(group))) = list else {
+            return Ok(None);
+        };
+        if group.group_type != GroupType::List {
+            return Ok(None);
+        }
+        let member_accounts = GroupAccountRelEntity::find()
+            .filter(GroupAccountRelColumn::Group.eq(group.id))
+            .find_also_related(AccountEntity)
+            .all(&self.database)
+            .await?
+            .into_iter()
+            .filter_map(|(_, account)| account)
+            .filter(|account| account.active)
+            .map(|account| account.id)
+            .collect::<Vec<_>>();
+        let members = EmailEntity::find()
+            .filter(EmailColumn::Account.is_in(member_accounts))
+            .filter(EmailColumn::EmailType.eq(EmailType::Primary))
+            .all(&self.database)
+            .await?
+            .into_iter()
+            .map(|email| email.email_address.into())
+            .collect();
+        Ok(Some(MailingList {
+            posting_policy: group.posting_policy,
+            group: Group::from(group),
+            list_address: list_email.email_address.into(),
+            members,
+        }))
+    }
+
+    async fn get_quota(&self, mailbox_id: Uuid) -> Result<Option<Quota>, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::groups::Column as GroupColumn;
+        use entities::{AccountEntity, GroupEntity};
+        let account = AccountEntity::find()
+            .filter(AccountColumn::MailboxId.eq(mailbox_id))
+            .one(&self.database)
+            .await?;
+        let quota = match account {
+            Some(account) => Quota::from_limits(account.quota, account.quota_messages),
+            None => match GroupEntity::find()
+                .filter(GroupColumn::MailboxId.eq(mailbox_id))
+                .one(&self.database)
+                .await?
+            {
+                Some(group) => Quota::from_limits(group.quota, group.quota_messages),
+                None => return Ok(None),
+            },
+        };
+        Ok((!quota.is_unlimited()).then_some(quota))
     }
 
     async fn login_account(
         &self,
         username: String,
         password: String,
+        protocol: LoginProtocol,
     ) -> Result<Option<Account>, Self::ServiceError> {
-        todo!()
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let account = AccountEntity::find()
+            .filter(AccountColumn::Username.eq(username))
+            .filter(AccountColumn::Active.eq(true))
+            .one(&self.database)
+            .await?;
+        let Some(account) = account.filter(|account| account.account_type.allows_login(protocol))
+        else {
+            return Ok(None);
+        };
+        let totp_enabled = self
+            .find_totp_secret(&account)
+            .await?
+            .is_some_and(|totp_secret| totp_secret.enabled);
+        // With TOTP on the account password needs a code, so only app passwords work here
+        if !totp_enabled && self.check_account_password(&account, &password).await {
+            return Ok(Some(self.account(account).await?));
+        }
+        if protocol.accepts_app_passwords()
+            && self
+                .check_app_passwords(&account, &password, protocol)
+                .await?
+        {
+            return Ok(Some(self.account(account).await?));
+        }
+        Ok(None)
+    }
+
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let Some(account) = self.find_account(username).await? else {
+            return Ok(None);
+        };
+        // TOTP logins come from the web interface
+        if !account.active
+            || !account.account_type.allows_login(LoginProtocol::Http)
+            || !self.check_account_password(&account, &password).await
+        {
+            return Ok(None);
+        }
+        let totp_secret = self
+            .find_totp_secret(&account)
+            .await?
+            .filter(|totp_secret| totp_secret.enabled);
+        if let Some(totp_secret) = totp_secret {
+            if !self
+                .check_second_factor(&account, &totp_secret, &code)
+                .await?
+            {
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.account(account).await?))
+    }
+
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let account = AccountEntity::find()
+            .filter(AccountColumn::Username.eq(username))
+            .one(&self.database)
+            .await?;
+        let Some(account) = account else {
+            return Ok(None);
+        };
+        let password = generate_app_password();
+        let hashed =
+            Password::new_preferred(normalize_app_password(&password), &self.password_config)?;
+        let app_password = ActiveAppPasswordModel {
+            account: ActiveValue::Set(account.id),
+            name: ActiveValue::Set(name),
+            password: ActiveValue::Set(hashed),
+            allow_smtp: ActiveValue::Set(protocols.contains(&LoginProtocol::Smtp)),
+            allow_imap: ActiveValue::Set(protocols.contains(&LoginProtocol::Imap)),
+            allow_pop3: ActiveValue::Set(protocols.contains(&LoginProtocol::Pop3)),
+            last_used: ActiveValue::Set(None),
+            ..Default::default()
+        }
+        .insert(&self.database)
+        .await?;
+        Ok(Some(NewAppPassword {
+            app_password: app_password.into(),
+            password,
+        }))
+    }
+
+    async fn list_app_passwords(
+        &self,
+        username: String,
+    ) -> Result<Vec<AppPassword>, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::app_passwords::Column as AppPasswordColumn;
+        use entities::{AccountEntity, AppPasswordEntity};
+        let app_passwords = AppPasswordEntity::find()
+            .inner_join(AccountEntity)
+            .filter(AccountColumn::Username.eq(username))
+            .order_by_asc(AppPasswordColumn::Id)
+            .all(&self.database)
+            .await?;
+        Ok(app_passwords.into_iter().map(AppPassword::from).collect())
+    }
+
+    async fn revoke_app_password(
+        &self,
+        username: String,
+        id: i64,
+    ) -> Result<bool, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::app_passwords::Column as AppPasswordColumn;
+        use entities::{AccountEntity, AppPasswordEntity};
+        let account = AccountEntity::find()
+            .filter(AccountColumn::Username.eq(username))
+            .one(&self.database)
+            .await?;
+        let Some(account) = account else {
+            return Ok(false);
+        };
+        let result = AppPasswordEntity::delete_many()
+            .filter(AppPasswordColumn::Id.eq(id))
+            .filter(AppPasswordColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    async fn enroll_totp(
+        &self,
+        username: String,
+    ) -> Result<Option<TotpEnrollment>, Self::ServiceError> {
+        use entities::recovery_codes::Column as RecoveryCodeColumn;
+        use entities::{RecoveryCodeEntity, TotpSecretEntity};
+        let Some(account) = self.find_account(username).await? else {
+            return Ok(None);
+        };
+        if let Some(totp_secret) = self.find_totp_secret(&account).await? {
+            if totp_secret.enabled {
+                return Ok(None);
+            }
+            // Replace the unfinished enrollment
+            TotpSecretEntity::delete_by_id(totp_secret.id)
+                .exec(&self.database)
+                .await?;
+        }
+        RecoveryCodeEntity::delete_many()
+            .filter(RecoveryCodeColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+
+        let secret_key = self.two_factor.secret_key()?;
+        let secret = generate_totp_secret();
+        let recovery_codes = generate_recovery_codes();
+        let enrollment = enrollment(
+            secret.clone(),
+            &self.two_factor.issuer,
+            &account.username,
+            recovery_codes.clone(),
+        )?;
+        ActiveTotpSecretModel {
+            account: ActiveValue::Set(account.id),
+            secret: ActiveValue::Set(secret_key.encrypt(&secret)),
+            enabled: ActiveValue::Set(false),
+            last_step: ActiveValue::Set(None),
+            ..Default::default()
+        }
+        .insert(&self.database)
+        .await?;
+        let mut hashed_codes = Vec::with_capacity(recovery_codes.len());
+        for recovery_code in &recovery_codes {
+            hashed_codes.push(ActiveRecoveryCodeModel {
+                account: ActiveValue::Set(account.id),
+                code: ActiveValue::Set(Password::new_preferred(
+                    normalize_recovery_code(recovery_code),
+                    &self.password_config,
+                )?),
+                ..Default::default()
+            });
+        }
+        RecoveryCodeEntity::insert_many(hashed_codes)
+            .exec(&self.database)
+            .await?;
+        Ok(Some(enrollment))
+    }
+
+    async fn verify_totp(
+        &self,
+        username: String,
+        code: String,
+    ) -> Result<bool, Self::ServiceError> {
+        let Some(account) = self.find_account(username).await? else {
+            return Ok(false);
+        };
+        let Some(totp_secret) = self.find_totp_secret(&account).await? else {
+            return Ok(false);
+        };
+        if totp_secret.enabled {
+            self.check_second_factor(&account, &totp_secret, &code)
+                .await
+        } else {
+            // Recovery codes can not finish an enrollment
+            self.check_totp_code(&totp_secret, &code).await
+        }
+    }
+
+    async fn disable_totp(&self, username: String) -> Result<bool, Self::ServiceError> {
+        use entities::recovery_codes::Column as RecoveryCodeColumn;
+        use entities::totp_secrets::Column as TotpSecretColumn;
+        use entities::{RecoveryCodeEntity, TotpSecretEntity};
+        let Some(account) = self.find_account(username).await? else {
+            return Ok(false);
+        };
+        RecoveryCodeEntity::delete_many()
+            .filter(RecoveryCodeColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        let result = TotpSecretEntity::delete_many()
+            .filter(TotpSecretColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        Ok(self
+            .auth_failures
+            .check(remote, username.as_deref(), Self::now() as i64))
+    }
+
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let existing_account = match &username {
+            Some(username) => {
+                AccountEntity::find()
+                    .filter(AccountColumn::Username.eq(username))
+                    .count(&self.database)
+                    .await?
+                    > 0
+            }
+            None => false,
+        };
+        let outcome = self.auth_failures.record_failure(
+            remote,
+            username.as_deref(),
+            existing_account,
+            Self::now() as i64,
+        );
+        if let (true, Some(username)) = (outcome.deactivate, &username) {
+            self.deactivate_account(username).await?;
+        }
+        Ok(outcome.lockout)
+    }
+
+    async fn record_auth_success(&self, username: String) -> Result<bool, Self::ServiceError> {
+        Ok(self.auth_failures.record_success(&username))
+    }
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, Self::ServiceError> {
+        Ok(self.auth_failures.lockouts(Self::now() as i64))
+    }
+
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, Self::ServiceError> {
+        Ok(self.auth_failures.clear(&key))
+    }
+
+    async fn directory_capabilities(&self) -> Result<DirectoryCapabilities, Self::ServiceError> {
+        Ok(DirectoryCapabilities::ALL)
+    }
+
+    async fn list_accounts(&self) -> Result<AdminResult<Vec<Account>>, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let accounts = AccountEntity::find()
+            .order_by_asc(AccountColumn::Username)
+            .all(&self.database)
+            .await?;
+        let mut loaded = Vec::with_capacity(accounts.len());
+        for account in accounts {
+            loaded.push(self.account(account).await?);
+        }
+        Ok(Ok(loaded))
+    }
+
+    async fn create_account(
+        &self,
+        account: NewAccount,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        use entities::AccountEntity;
+        if let Err(error) = self.check_username(&account.username, None).await? {
+            return Ok(Err(error));
+        }
+        let primary_address = match account.primary_address {
+            Some(primary_address) => match self.unused_email_address(primary_address).await? {
+                Ok(primary_address) => Some(primary_address),
+                Err(error) => return Ok(Err(error)),
+            },
+            None => None,
+        };
+        let password = match self.hash_password(&account.password) {
+            Ok(password) => password,
+            Err(error) => return Ok(Err(error)),
+        };
+        let mailbox_id = self.new_account_mailbox_id(&account.username).await?;
+        let model = ActiveAccountModel {
+            name: ActiveValue::Set(account.name.unwrap_or_else(|| account.username.clone())),
+            mailbox_id: ActiveValue::Set(mailbox_id),
+            username: ActiveValue::Set(account.username),
+            description: ActiveValue::Set(account.description),
+            password: ActiveValue::Set(password),
+            quota: ActiveValue::Set(0),
+            account_type: ActiveValue::Set(account.account_type),
+            active: ActiveValue::Set(true),
+            ..Default::default()
+        };
+        let result = AccountEntity::insert(model).exec(&self.database).await?;
+        if let Some(primary_address) = primary_address {
+            EmailActiveModel {
+                account: ActiveValue::Set(Some(result.last_insert_id)),
+                group: ActiveValue::Set(None),
+                email_address: ActiveValue::Set(primary_address),
+                email_type: ActiveValue::Set(EmailType::Primary),
+                ..Default::default()
+            }
+            .insert(&self.database)
+            .await?;
+        }
+        let created = AccountEntity::find_by_id(result.last_insert_id)
+            .one(&self.database)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound("The new account".to_string()))?;
+        Ok(Ok(self.account(created).await?))
+    }
+
+    async fn update_account(
+        &self,
+        username: String,
+        update: AccountUpdate,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let Some(mut account) = self.find_account(username.clone()).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        let mut query = AccountEntity::update_many().filter(AccountColumn::Id.eq(account.id));
+        let mut changed = false;
+        if let Some(name) = update.name {
+            query = query.col_expr(AccountColumn::Name, Expr::value(name.clone()));
+            account.name = name;
+            changed = true;
+        }
+        if let Some(description) = update.description {
+            query = query.col_expr(AccountColumn::Description, Expr::value(description.clone()));
+            account.description = Some(description);
+            changed = true;
+        }
+        if let Some(account_type) = update.account_type {
+            query = query.col_expr(
+                AccountColumn::AccountType,
+                Expr::value(account_type.clone()),
+            );
+            account.account_type = account_type;
+            changed = true;
+        }
+        if changed {
+            query.exec(&self.database).await?;
+        }
+        Ok(Ok(self.account(account).await?))
+    }
+
+    /// Only the username changes. Everything else references the account by its id
+    async fn rename_account(
+        &self,
+        username: String,
+        new_username: String,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let Some(mut account) = self.find_account(username.clone()).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        if let Err(error) = self.check_username(&new_username, Some(account.id)).await? {
+            return Ok(Err(error));
+        }
+        AccountEntity::update_many()
+            .col_expr(AccountColumn::Username, Expr::value(new_username.clone()))
+            .filter(AccountColumn::Id.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        account.username = new_username;
+        Ok(Ok(self.account(account).await?))
+    }
+
+    async fn set_account_active(
+        &self,
+        username: String,
+        active: bool,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let Some(account) = self.find_account(username.clone()).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        AccountEntity::update_many()
+            .col_expr(AccountColumn::Active, Expr::value(active))
+            .filter(AccountColumn::Id.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        Ok(Ok(()))
+    }
+
+    /// The rows that reference the account are deleted first because SQLite does not enforce the foreign keys
+    async fn delete_account(
+        &self,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        use entities::app_passwords::Column as AppPasswordColumn;
+        use entities::emails::Column as EmailColumn;
+        use entities::group_account_rels::Column as GroupAccountRelColumn;
+        use entities::recovery_codes::Column as RecoveryCodeColumn;
+        use entities::totp_secrets::Column as TotpSecretColumn;
+        use entities::{
+            AccountEntity, AppPasswordEntity, EmailEntity, GroupAccountRelEntity,
+            RecoveryCodeEntity, TotpSecretEntity,
+        };
+        let Some(account) = self.find_account(username).await? else {
+            return Ok(Ok(false));
+        };
+        EmailEntity::delete_many()
+            .filter(EmailColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        GroupAccountRelEntity::delete_many()
+            .filter(GroupAccountRelColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        AppPasswordEntity::delete_many()
+            .filter(AppPasswordColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        RecoveryCodeEntity::delete_many()
+            .filter(RecoveryCodeColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        TotpSecretEntity::delete_many()
+            .filter(TotpSecretColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        let result = AccountEntity::delete_by_id(account.id)
+            .exec(&self.database)
+            .await?;
+        Ok(Ok(result.rows_affected > 0))
+    }
+
+    async fn set_password(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        use entities::account::Column as AccountColumn;
+        use entities::AccountEntity;
+        let Some(account) = self.find_account(username.clone()).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        let password = match self.hash_password(&password) {
+            Ok(password) => password,
+            Err(error) => return Ok(Err(error)),
+        };
+        AccountEntity::update_many()
+            .col_expr(AccountColumn::Password, Expr::value(password))
+            .filter(AccountColumn::Id.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        Ok(Ok(()))
+    }
+
+    /// [EmailType::List] addresses belong to groups and are set with [create_group](Directory::create_group)
+    async fn add_email_address(
+        &self,
+        username: String,
+        email_address: String,
+        email_type: EmailType,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        use entities::emails::Column as EmailColumn;
+        use entities::EmailEntity;
+        if email_type == EmailType::List {
+            return Ok(Err(AdminError::NotSupported));
+        }
+        let Some(account) = self.find_account(username.clone()).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        let email_address = match self.unused_email_address(email_address).await? {
+            Ok(email_address) => email_address,
+            Err(error) => return Ok(Err(error)),
+        };
+        if email_type == EmailType::Primary {
+            EmailEntity::update_many()
+                .col_expr(EmailColumn::EmailType, Expr::value(EmailType::Alias))
+                .filter(EmailColumn::Account.eq(account.id))
+                .filter(EmailColumn::EmailType.eq(EmailType::Primary))
+                .exec(&self.database)
+                .await?;
+        }
+        EmailActiveModel {
+            account: ActiveValue::Set(Some(account.id)),
+            group: ActiveValue::Set(None),
+            email_address: ActiveValue::Set(email_address),
+            email_type: ActiveValue::Set(email_type),
+            ..Default::default()
+        }
+        .insert(&self.database)
+        .await?;
+        Ok(Ok(()))
+    }
+
+    async fn remove_email_address(
+        &self,
+        email_address: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        use entities::emails::Column as EmailColumn;
+        use entities::EmailEntity;
+        let Ok(email_address) = EmailAddress::new_lenient(email_address) else {
+            return Ok(Ok(false));
+        };
+        let result = EmailEntity::delete_many()
+            .filter(EmailColumn::EmailAddress.eq(email_address))
+            .filter(EmailColumn::Account.is_not_null())
+            .exec(&self.database)
+            .await?;
+        Ok(Ok(result.rows_affected > 0))
+    }
+
+    async fn create_group(&self, group: NewGroup) -> Result<AdminResult<()>, Self::ServiceError> {
+        if self.find_group(group.name.clone()).await?.is_some() {
+            return Ok(Err(AdminError::GroupExists(group.name)));
+        }
+        let list_address = match group.list_address {
+            Some(list_address) => match self.unused_email_address(list_address).await? {
+                Ok(list_address) => Some(list_address),
+                Err(error) => return Ok(Err(error)),
+            },
+            None => None,
+        };
+        let mailbox_id = self.new_group_mailbox_id(&group.name).await?;
+        let created = ActiveGroupModel {
+            has_mail_box: ActiveValue::Set(false),
+            mailbox_id: ActiveValue::Set(mailbox_id),
+            group_name: ActiveValue::Set(group.name),
+            group_type: ActiveValue::Set(group.group_type),
+            posting_policy: ActiveValue::Set(group.posting_policy),
+            ..Default::default()
+        }
+        .insert(&self.database)
+        .await?;
+        if let Some(list_address) = list_address {
+            EmailActiveModel {
+                account: ActiveValue::Set(None),
+                group: ActiveValue::Set(Some(created.id)),
+                email_address: ActiveValue::Set(list_address),
+                email_type: ActiveValue::Set(EmailType::List),
+                ..Default::default()
+            }
+            .insert(&self.database)
+            .await?;
+        }
+        Ok(Ok(()))
+    }
+
+    async fn delete_group(&self, name: String) -> Result<AdminResult<bool>, Self::ServiceError> {
+        use entities::emails::Column as EmailColumn;
+        use entities::group_account_rels::Column as GroupAccountRelColumn;
+        use entities::{EmailEntity, GroupAccountRelEntity, GroupEntity};
+        let Some(group) = self.find_group(name).await? else {
+            return Ok(Ok(false));
+        };
+        EmailEntity::delete_many()
+            .filter(EmailColumn::Group.eq(group.id))
+            .exec(&self.database)
+            .await?;
+        GroupAccountRelEntity::delete_many()
+            .filter(GroupAccountRelColumn::Group.eq(group.id))
+            .exec(&self.database)
+            .await?;
+        let result = GroupEntity::delete_by_id(group.id)
+            .exec(&self.database)
+            .await?;
+        Ok(Ok(result.rows_affected > 0))
+    }
+
+    async fn list_group_members(
+        &self,
+        group: String,
+    ) -> Result<AdminResult<Vec<String>>, Self::ServiceError> {
+        use entities::group_account_rels::Column as GroupAccountRelColumn;
+        use entities::{AccountEntity, GroupAccountRelEntity};
+        let Some(group_model) = self.find_group(group.clone()).await? else {
+            return Ok(Err(AdminError::GroupNotFound(group)));
+        };
+        let members = GroupAccountRelEntity::find()
+            .filter(GroupAccountRelColumn::Group.eq(group_model.id))
+            .order_by_asc(GroupAccountRelColumn::Id)
+            .find_also_related(AccountEntity)
+            .all(&self.database)
+            .await?
+            .into_iter()
+            .filter_map(|(_, account)| account)
+            .map(|account| account.username)
+            .collect();
+        Ok(Ok(members))
+    }
+
+    async fn add_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        use entities::group_account_rels::Column as GroupAccountRelColumn;
+        use entities::GroupAccountRelEntity;
+        let Some(group_model) = self.find_group(group.clone()).await? else {
+            return Ok(Err(AdminError::GroupNotFound(group)));
+        };
+        let Some(account) = self.find_account(username.clone()).await? else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        let existing = GroupAccountRelEntity::find()
+            .filter(GroupAccountRelColumn::Group.eq(group_model.id))
+            .filter(GroupAccountRelColumn::Account.eq(account.id))
+            .one(&self.database)
+            .await?;
+        if existing.is_none() {
+            ActiveGroupAccountRelModel {
+                group: ActiveValue::Set(group_model.id),
+                account: ActiveValue::Set(account.id),
+                ..Default::default()
+            }
+            .insert(&self.database)
+            .await?;
+        }
+        Ok(Ok(()))
+    }
+
+    async fn remove_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        use entities::group_account_rels::Column as GroupAccountRelColumn;
+        use entities::GroupAccountRelEntity;
+        let Some(group_model) = self.find_group(group).await? else {
+            return Ok(Ok(false));
+        };
+        let Some(account) = self.find_account(username).await? else {
+            return Ok(Ok(false));
+        };
+        let result = GroupAccountRelEntity::delete_many()
+            .filter(GroupAccountRelColumn::Group.eq(group_model.id))
+            .filter(GroupAccountRelColumn::Account.eq(account.id))
+            .exec(&self.database)
+            .await?;
+        Ok(Ok(result.rows_affected > 0))
     }
 
     async fn get_groups(&self) -> Result<Vec<String>, Self::ServiceError> {
-        todo!()
+        use entities::groups::Column as GroupColumn;
+        use entities::GroupEntity;
+        let groups = GroupEntity::find()
+            .order_by_asc(GroupColumn::Id)
+            .all(&self.database)
+            .await?;
+        Ok(groups.into_iter().map(|group| group.group_name).collect())
     }
 
     async fn validate_config(
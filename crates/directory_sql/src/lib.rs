@@ -0,0 +1,4 @@
+pub mod database_config;
+pub mod database_directory;
+#[cfg(test)]
+pub mod database_tests;
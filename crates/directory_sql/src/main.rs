@@ -3,20 +3,14 @@ use std::io::read_to_string;
 use std::path::PathBuf;
 
 use sea_orm::DatabaseConnection;
-use thiserror::__private::PathAsDisplay;
 
 use directories::directory_service::DirectoryService;
 use directories::directory_type::Directory;
 use migration::MigratorTrait;
 use utils::configs::Config;
 
-use crate::database_config::DatabaseConfig;
-use crate::database_directory::DatabaseDirectory;
-
-pub mod database_config;
-pub mod database_directory;
-#[cfg(test)]
-pub mod database_tests;
+use directory_sql::database_config::DatabaseConfig;
+use directory_sql::database_directory::DatabaseDirectory;
 
 #[tokio::main]
 async fn main() {
@@ -0,0 +1,670 @@
+use crate::imap_config::IMAPHost;
+use crate::imap_service::{IMAPServiceAccess, IMAPServiceError};
+use crate::imap_session::{bad, no, ok, Command, Session, SessionResponse, MAX_COMMAND_LINE};
+use crate::quota::{
+    check_append, over_quota_response, quota_response, quota_root_response, ACCOUNT_QUOTA_ROOT,
+};
+use directories::directory_type::Directory;
+use directories::oauth::{BearerAuthentication, OAuthAuthenticator};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use storages::folders::{NewMessage, StorageError, INBOX};
+use storages::storage_type::Storage;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf,
+};
+use tokio::net::{TcpStream, UnixStream};
+use tracing::warn;
+use utils::app_password::LoginProtocol;
+use utils::quota::{Quota, QuotaUsage};
+use utils::sasl::{self, SaslMechanism};
+use utils::service::ServiceAccess;
+use uuid::Uuid;
+
+/// A stream accepted by an [Instance](crate::imap_listener::Instance)
+pub enum IMAPStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+impl AsyncRead for IMAPStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            IMAPStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            IMAPStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+impl AsyncWrite for IMAPStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            IMAPStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            IMAPStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            IMAPStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            IMAPStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            IMAPStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            IMAPStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+pub struct Connection<
+    D: Directory,
+    DirectoryAccess: ServiceAccess<ServiceResponse = D>,
+    S: Storage,
+    StorageAccess: ServiceAccess<ServiceResponse = S>,
+> {
+    pub stream: IMAPStream,
+    /// None for Unix sockets
+    pub addr: Option<SocketAddr>,
+    pub host: IMAPHost,
+    pub service: IMAPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+}
+impl<
+        D: Directory,
+        DirectoryAccess: ServiceAccess<ServiceResponse = D>,
+        S: Storage,
+        StorageAccess: ServiceAccess<ServiceResponse = S>,
+    > Connection<D, DirectoryAccess, S, StorageAccess>
+{
+    pub async fn run(self) -> Result<(), IMAPServiceError> {
+        let directory = self
+            .service
+            .directory_service_access
+            .get_service()
+            .await
+            .map_err(|e| IMAPServiceError::GettingDirectoryAccess(Box::new(e)))?;
+
+        let mut session = Session::new();
+        if self.service.oauth.is_some() {
+            session.auth_mechanisms = vec![SaslMechanism::OAuthBearer, SaslMechanism::XOAuth2];
+        }
+        let (reader, mut writer) = tokio::io::split(self.stream);
+        let mut reader = BufReader::new(reader);
+        writer
+            .write_all(session.greeting(self.host.greeting.as_deref()).as_bytes())
+            .await?;
+
+        let max_literal_size = self.service.config.max_literal_size;
+        let remote = self.addr.map(|addr| addr.ip());
+        loop {
+            let command = match read_command(&mut reader, &mut writer, max_literal_size).await? {
+                ReadCommand::Command(command) => command,
+                ReadCommand::Refused(response) => {
+                    writer.write_all(response.as_bytes()).await?;
+                    continue;
+                }
+                ReadCommand::Closed => break,
+            };
+            let directory_error = |e| IMAPServiceError::Directory(Box::new(e));
+            let response = match session.handle_command(&command) {
+                SessionResponse::Reply(response) => response,
+                SessionResponse::Logout(response) => {
+                    writer.write_all(response.as_bytes()).await?;
+                    break;
+                }
+                SessionResponse::Login {
+                    tag,
+                    username,
+                    password,
+                } => {
+                    let lockout = directory
+                        .check_auth_lockout(remote, Some(username.clone()))
+                        .await
+                        .map_err(directory_error)?;
+                    if lockout.is_some() {
+                        writer
+                            .write_all(session.locked_out(&tag).as_bytes())
+                            .await?;
+                        continue;
+                    }
+                    let account = directory
+                        .login_account(username.clone(), password, LoginProtocol::Imap)
+                        .await
+                        .map_err(directory_error)?;
+                    match account {
+                        Some(account) => {
+                            directory
+                                .record_auth_success(account.username.clone())
+                                .await
+                                .map_err(directory_error)?;
+                            session.authenticated(&tag, account)
+                        }
+                        None => {
+                            directory
+                                .record_auth_failure(remote, Some(username))
+                                .await
+                                .map_err(directory_error)?;
+                            session.authentication_failed(&tag)
+                        }
+                    }
+                }
+                SessionResponse::Authenticate {
+                    tag,
+                    mechanism,
+                    initial_response,
+                } => {
+                    Self::authenticate(
+                        self.service.oauth.as_ref(),
+                        &directory,
+                        remote,
+                        &mut session,
+                        &mut reader,
+                        &mut writer,
+                        &tag,
+                        mechanism,
+                        initial_response,
+                    )
+                    .await?
+                }
+                SessionResponse::GetQuota {
+                    tag,
+                    mailbox_id,
+                    root,
+                } => {
+                    if root != ACCOUNT_QUOTA_ROOT {
+                        no(&tag, "[NONEXISTENT] No such quota root")
+                    } else {
+                        let (quota, usage) =
+                            Self::mailbox_quota(&self.service, &directory, mailbox_id).await?;
+                        format!(
+                            "{}{}",
+                            quota_response(ACCOUNT_QUOTA_ROOT, &quota.unwrap_or_default(), &usage),
+                            ok(&tag, "GETQUOTA completed")
+                        )
+                    }
+                }
+                SessionResponse::GetQuotaRoot {
+                    tag,
+                    mailbox_id,
+                    folder,
+                } => {
+                    let folders = Self::storage(&self.service)
+                        .await?
+                        .list_folders(mailbox_id)
+                        .await
+                        .map_err(storage_error)?;
+                    match folders {
+                        // The INBOX always exists for IMAP even before the first message
+                        Ok(folders)
+                            if folder == INBOX
+                                || folders.iter().any(|existing| existing.name == folder) =>
+                        {
+                            let (quota, usage) =
+                                Self::mailbox_quota(&self.service, &directory, mailbox_id).await?;
+                            format!(
+                                "{}{}{}",
+                                quota_root_response(&folder, &[ACCOUNT_QUOTA_ROOT]),
+                                quota_response(
+                                    ACCOUNT_QUOTA_ROOT,
+                                    &quota.unwrap_or_default(),
+                                    &usage
+                                ),
+                                ok(&tag, "GETQUOTAROOT completed")
+                            )
+                        }
+                        Ok(_) => no(&tag, "[NONEXISTENT] No such mailbox"),
+                        Err(error) => no(&tag, &error.to_string()),
+                    }
+                }
+                SessionResponse::Append {
+                    tag,
+                    mailbox_id,
+                    folder,
+                    message,
+                } => {
+                    Self::append(&self.service, &directory, &tag, mailbox_id, folder, message)
+                        .await?
+                }
+                SessionResponse::Select {
+                    tag,
+                    mailbox_id,
+                    folder,
+                    read_only,
+                } => {
+                    Self::select(
+                        &self.service,
+                        &mut session,
+                        &tag,
+                        mailbox_id,
+                        folder,
+                        read_only,
+                    )
+                    .await?
+                }
+                SessionResponse::Search {
+                    tag,
+                    mailbox_id,
+                    folder,
+                    query,
+                    uid,
+                } => {
+                    let found = Self::storage(&self.service)
+                        .await?
+                        .search(mailbox_id, folder, query)
+                        .await
+                        .map_err(storage_error)?;
+                    match found {
+                        Ok(uids) => session.search_results(&tag, uid, &uids),
+                        Err(error) => no(&tag, &error.to_string()),
+                    }
+                }
+            };
+            writer.write_all(response.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// Opens the folder with its messages. The INBOX is created if it does not exist yet
+    async fn select(
+        service: &IMAPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+        session: &mut Session,
+        tag: &str,
+        mailbox_id: Uuid,
+        folder: String,
+        read_only: bool,
+    ) -> Result<String, IMAPServiceError> {
+        let storage = Self::storage(service).await?;
+        let folders = match storage
+            .list_folders(mailbox_id)
+            .await
+            .map_err(storage_error)?
+        {
+            Ok(folders) => folders,
+            Err(error) => return Ok(no(tag, &error.to_string())),
+        };
+        let existing = match folders.into_iter().find(|existing| existing.name == folder) {
+            Some(existing) => existing,
+            None if folder == INBOX => {
+                match storage
+                    .create_folder(mailbox_id, folder.clone())
+                    .await
+                    .map_err(storage_error)?
+                {
+                    Ok(created) => created,
+                    Err(error) => return Ok(no(tag, &error.to_string())),
+                }
+            }
+            None => return Ok(session.select_failed(tag)),
+        };
+        let messages = storage
+            .list_messages(mailbox_id, folder)
+            .await
+            .map_err(storage_error)?;
+        Ok(match messages {
+            Ok(messages) => session.selected(tag, &existing, read_only, &messages),
+            Err(StorageError::FolderNotFound(_)) => session.select_failed(tag),
+            Err(error) => no(tag, &error.to_string()),
+        })
+    }
+
+    async fn storage(
+        service: &IMAPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+    ) -> Result<S, IMAPServiceError> {
+        service
+            .storage_service_access
+            .get_service()
+            .await
+            .map_err(|e| IMAPServiceError::GettingStorageAccess(Box::new(e)))
+    }
+
+    /// The limits of the mailbox and its usage. None if it has no limits
+    async fn mailbox_quota(
+        service: &IMAPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+        directory: &D,
+        mailbox_id: Uuid,
+    ) -> Result<(Option<Quota>, QuotaUsage), IMAPServiceError> {
+        let quota = directory
+            .get_quota(mailbox_id)
+            .await
+            .map_err(|e| IMAPServiceError::Directory(Box::new(e)))?;
+        let usage = Self::storage(service)
+            .await?
+            .quota_usage(mailbox_id)
+            .await
+            .map_err(storage_error)?;
+        Ok((quota, usage))
+    }
+
+    /// Stores the message unless it would exceed the quota of the mailbox
+    async fn append(
+        service: &IMAPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+        directory: &D,
+        tag: &str,
+        mailbox_id: Uuid,
+        folder: String,
+        message: NewMessage,
+    ) -> Result<String, IMAPServiceError> {
+        if let (Some(quota), usage) = Self::mailbox_quota(service, directory, mailbox_id).await? {
+            let size = message.contents.len() as u64;
+            if let Err(resource) = check_append(&quota, &usage, size) {
+                return Ok(over_quota_response(tag, resource));
+            }
+        }
+        let storage = Self::storage(service).await?;
+        let mut appended = storage
+            .append_message(mailbox_id, folder.clone(), message.clone())
+            .await
+            .map_err(storage_error)?;
+        if matches!(appended, Err(StorageError::FolderNotFound(_))) && folder == INBOX {
+            // The INBOX of a new account is created by its first message
+            storage
+                .create_folder(mailbox_id, folder.clone())
+                .await
+                .map_err(storage_error)?
+                .ok();
+            appended = storage
+                .append_message(mailbox_id, folder, message)
+                .await
+                .map_err(storage_error)?;
+        }
+        Ok(match appended {
+            Ok(_) => ok(tag, "APPEND completed"),
+            Err(StorageError::FolderNotFound(_)) => no(tag, "[TRYCREATE] No such mailbox"),
+            Err(error) => no(tag, &error.to_string()),
+        })
+    }
+
+    /// Runs the SASL exchange for OAUTHBEARER or XOAUTH2 and returns the tagged response
+    #[allow(clippy::too_many_arguments)]
+    async fn authenticate(
+        oauth: Option<&OAuthAuthenticator>,
+        directory: &D,
+        remote: Option<IpAddr>,
+        session: &mut Session,
+        reader: &mut (impl AsyncBufReadExt + Unpin),
+        writer: &mut (impl AsyncWrite + Unpin),
+        tag: &str,
+        mechanism: SaslMechanism,
+        initial_response: Option<String>,
+    ) -> Result<String, IMAPServiceError> {
+        let Some(oauth) = oauth else {
+            return Ok(no(tag, "Unsupported authentication mechanism"));
+        };
+        let response = match initial_response {
+            Some(response) => response,
+            None => {
+                writer.write_all(b"+ \r\n").await?;
+                match read_response(reader).await? {
+                    Some(response) => response,
+                    None => return Ok(bad(tag, "Authentication response is too long")),
+                }
+            }
+        };
+        if response == "*" {
+            return Ok(bad(tag, "Authentication cancelled"));
+        }
+        let credentials = match sasl::decode_response(&response)
+            .and_then(|response| sasl::parse_response(mechanism, &response))
+        {
+            Ok(ok) => ok,
+            Err(error) => return Ok(bad(tag, &error.to_string())),
+        };
+        let directory_error = |e| IMAPServiceError::Directory(Box::new(e));
+        // The authzid is chosen by the client. So only the address is checked until the token names the account
+        let lockout = directory
+            .check_auth_lockout(remote, None)
+            .await
+            .map_err(directory_error)?;
+        if lockout.is_some() {
+            return Ok(session.locked_out(tag));
+        }
+        match oauth
+            .authenticate(directory, &credentials, LoginProtocol::Imap)
+            .await
+        {
+            Ok(BearerAuthentication::Authenticated(account)) => {
+                let lockout = directory
+                    .check_auth_lockout(None, Some(account.username.clone()))
+                    .await
+                    .map_err(directory_error)?;
+                if lockout.is_some() {
+                    return Ok(session.locked_out(tag));
+                }
+                directory
+                    .record_auth_success(account.username.clone())
+                    .await
+                    .map_err(directory_error)?;
+                Ok(session.authenticated(tag, account))
+            }
+            Ok(failed) => {
+                directory
+                    .record_auth_failure(remote, failed.username().map(str::to_string))
+                    .await
+                    .map_err(directory_error)?;
+                // The client has to acknowledge the error before the exchange fails
+                let challenge = format!("+ {}\r\n", sasl::error_challenge("invalid_token"));
+                writer.write_all(challenge.as_bytes()).await?;
+                read_response(reader).await?;
+                Ok(session.authentication_failed(tag))
+            }
+            Err(error) => {
+                warn!("Unable to check the bearer token: {}", error);
+                Ok(no(tag, "[UNAVAILABLE] Temporary authentication failure"))
+            }
+        }
+    }
+}
+
+fn storage_error(error: impl std::error::Error + Send + Sync + 'static) -> IMAPServiceError {
+    IMAPServiceError::Storage(Box::new(error))
+}
+
+/// A line sent during AUTHENTICATE without the CRLF. None if it is longer than [MAX_COMMAND_LINE]
+async fn read_response(reader: &mut (impl AsyncBufReadExt + Unpin)) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    match read_line(reader, &mut line, MAX_COMMAND_LINE).await? {
+        Line::Complete => {}
+        Line::TooLong => return Ok(None),
+        Line::Closed => {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed during AUTHENTICATE",
+            ))
+        }
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// The result of [read_command]
+#[derive(Debug, PartialEq, Eq)]
+enum ReadCommand {
+    Command(Command),
+    /// The command was not read. Send the response and wait for the next one
+    Refused(String),
+    Closed,
+}
+
+/// The size of the `{size}` or `{size+}` literal that ends the line and whether it is non-synchronizing.
+/// [RFC 7888](https://www.rfc-editor.org/rfc/rfc7888)
+fn literal_marker(line: &str) -> Option<(usize, u64, bool)> {
+    let open = line.strip_suffix('}')?.rfind('{')?;
+    let size = &line[open + 1..line.len() - 1];
+    let (size, non_synchronizing) = match size.strip_suffix('+') {
+        Some(size) => (size, true),
+        None => (size, false),
+    };
+    if size.is_empty() || !size.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((open, size.parse().ok()?, non_synchronizing))
+}
+
+/// Reads a command with its literals. Synchronizing literals are asked for with a continuation request.
+/// The literals may add up to `max_literal_size` octets
+async fn read_command(
+    reader: &mut (impl AsyncBufReadExt + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    max_literal_size: u64,
+) -> io::Result<ReadCommand> {
+    let mut command = Command::default();
+    let mut literal_size = 0;
+    let mut line = Vec::new();
+    loop {
+        match read_line(reader, &mut line, MAX_COMMAND_LINE).await? {
+            Line::Complete => {}
+            Line::TooLong => return Ok(ReadCommand::Refused(Session::new().line_too_long())),
+            Line::Closed => return Ok(ReadCommand::Closed),
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\r', '\n']);
+        let Some((marker, size, non_synchronizing)) = literal_marker(text) else {
+            command.segments.push(text.to_string());
+            return Ok(ReadCommand::Command(command));
+        };
+        command.segments.push(text[..marker].to_string());
+        literal_size += size;
+        if literal_size > max_literal_size {
+            let tag = command.segments[0]
+                .split(' ')
+                .next()
+                .filter(|tag| !tag.is_empty())
+                .unwrap_or("*");
+            if non_synchronizing {
+                // It is already on its way. Reading past it keeps the next command intact
+                tokio::io::copy(&mut reader.take(size), &mut tokio::io::sink()).await?;
+                skip_rest_of_command(reader).await?;
+            }
+            return Ok(ReadCommand::Refused(no(tag, "[TOOBIG] Literal too large")));
+        }
+        if !non_synchronizing {
+            writer.write_all(b"+ Ready for literal data\r\n").await?;
+        }
+        let mut literal = vec![0; size as usize];
+        reader.read_exact(&mut literal).await?;
+        command.literals.push(literal);
+    }
+}
+
+/// Discards the lines and non-synchronizing literals left of a refused command
+async fn skip_rest_of_command(reader: &mut (impl AsyncBufReadExt + Unpin)) -> io::Result<()> {
+    let mut line = Vec::new();
+    loop {
+        if read_line(reader, &mut line, MAX_COMMAND_LINE).await? == Line::Closed {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&line);
+        match literal_marker(text.trim_end_matches(['\r', '\n'])) {
+            Some((_, size, true)) => {
+                tokio::io::copy(&mut reader.take(size), &mut tokio::io::sink()).await?;
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// The result of [read_line]
+#[derive(Debug, PartialEq, Eq)]
+enum Line {
+    Complete,
+    /// The line was longer than the limit. It has been read to the end and discarded
+    TooLong,
+    Closed,
+}
+
+/// Reads a line including the `\n` into `line` without buffering more than `limit` octets
+async fn read_line(
+    reader: &mut (impl AsyncBufReadExt + Unpin),
+    line: &mut Vec<u8>,
+    limit: usize,
+) -> io::Result<Line> {
+    line.clear();
+    let mut too_long = false;
+    loop {
+        let buffer = reader.fill_buf().await?;
+        if buffer.is_empty() {
+            return Ok(Line::Closed);
+        }
+        let (used, complete) = match buffer.iter().position(|byte| *byte == b'\n') {
+            Some(end) => (end + 1, true),
+            None => (buffer.len(), false),
+        };
+        if !too_long {
+            if line.len() + used > limit {
+                too_long = true;
+                line.clear();
+            } else {
+                line.extend_from_slice(&buffer[..used]);
+            }
+        }
+        reader.consume(used);
+        if complete {
+            return Ok(if too_long {
+                Line::TooLong
+            } else {
+                Line::Complete
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::imap_client::{literal_marker, read_command, ReadCommand};
+    use crate::imap_session::Command;
+
+    #[test]
+    pub fn test_literal_marker() {
+        assert_eq!(literal_marker("a1 LOGIN {4}"), Some((9, 4, false)));
+        assert_eq!(
+            literal_marker("a1 APPEND INBOX {310+}"),
+            Some((16, 310, true))
+        );
+        assert_eq!(literal_marker("a1 LOGIN {}"), None);
+        assert_eq!(literal_marker("a1 SEARCH SUBJECT \"{4}\""), None);
+    }
+
+    #[tokio::test]
+    pub async fn test_read_command() {
+        let mut reader: &[u8] = b"a1 LOGIN {4}\r\njohn {8+}\r\npassword\r\na2 NOOP\r\n";
+        let mut writer = Vec::new();
+        assert_eq!(
+            read_command(&mut reader, &mut writer, 100).await.unwrap(),
+            ReadCommand::Command(Command {
+                segments: vec!["a1 LOGIN ".to_string(), " ".to_string(), String::new()],
+                literals: vec![b"john".to_vec(), b"password".to_vec()],
+            })
+        );
+        // Only the synchronizing literal is asked for
+        assert_eq!(writer, b"+ Ready for literal data\r\n");
+        assert_eq!(
+            read_command(&mut reader, &mut writer, 100).await.unwrap(),
+            ReadCommand::Command(Command::line("a2 NOOP"))
+        );
+        assert_eq!(
+            read_command(&mut reader, &mut writer, 100).await.unwrap(),
+            ReadCommand::Closed
+        );
+
+        // A refused non-synchronizing literal is skipped with the rest of its command
+        let mut reader: &[u8] = b"a3 APPEND INBOX {11+}\r\nHello world\r\na4 NOOP\r\n";
+        assert_eq!(
+            read_command(&mut reader, &mut writer, 10).await.unwrap(),
+            ReadCommand::Refused("a3 NO [TOOBIG] Literal too large\r\n".to_string())
+        );
+        assert_eq!(
+            read_command(&mut reader, &mut writer, 10).await.unwrap(),
+            ReadCommand::Command(Command::line("a4 NOOP"))
+        );
+    }
+}
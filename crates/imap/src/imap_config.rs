@@ -0,0 +1,71 @@
+use directories::oauth::OAuthConfig;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use utils::configs::{Config, ConfigName};
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IMAPConfig {
+    pub hosts: Vec<IMAPHost>,
+    /// Enables AUTHENTICATE OAUTHBEARER and XOAUTH2
+    #[serde(default)]
+    pub oauth: Option<OAuthConfig>,
+    /// Bytes. Larger literals, such as the message of an APPEND, are refused
+    #[serde(default = "default_max_literal_size")]
+    pub max_literal_size: u64,
+}
+fn default_max_literal_size() -> u64 {
+    50 * 1024 * 1024
+}
+impl Default for IMAPConfig {
+    fn default() -> Self {
+        IMAPConfig {
+            hosts: vec![IMAPHost {
+                bind: "0.0.0.0:143".to_string(),
+                greeting: None,
+            }],
+            oauth: None,
+            max_literal_size: default_max_literal_size(),
+        }
+    }
+}
+impl Config for IMAPConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/imap")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("imap.toml")
+    }
+}
+/// Where an [IMAPHost] listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+/// # Example
+/// ```toml
+/// [[hosts]]
+/// bind = "0.0.0.0:143"
+/// greeting = "nitro_mail ready"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IMAPHost {
+    /// A TCP socket address or `unix:<path>` for a Unix socket
+    pub bind: String,
+    pub greeting: Option<String>,
+}
+impl IMAPHost {
+    pub fn bind_address(&self) -> BindAddress {
+        match self.bind.strip_prefix("unix:") {
+            Some(path) => BindAddress::Unix(PathBuf::from(path)),
+            None => BindAddress::Tcp(self.bind.clone()),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::imap_client::{Connection, IMAPStream};
+use crate::imap_config::{BindAddress, IMAPHost};
+use crate::imap_service::{IMAPServiceAccess, IMAPServiceError};
+use directories::directory_type::Directory;
+use std::net::SocketAddr;
+
+use storages::storage_type::Storage;
+use tokio::net::{TcpListener, UnixListener};
+use utils::service::ServiceAccess;
+
+pub struct Instance<
+    D: Directory,
+    DirectoryAccess: ServiceAccess<ServiceResponse = D>,
+    S: Storage,
+    StorageAccess: ServiceAccess<ServiceResponse = S>,
+> {
+    pub service: IMAPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+    pub host: IMAPHost,
+}
+impl<
+        D: Directory,
+        DirectoryAccess: ServiceAccess<ServiceResponse = D>,
+        S: Storage,
+        StorageAccess: ServiceAccess<ServiceResponse = S>,
+    > Instance<D, DirectoryAccess, S, StorageAccess>
+{
+    pub async fn run(self) -> Result<(), IMAPServiceError> {
+        match self.host.bind_address() {
+            BindAddress::Tcp(bind) => {
+                let socket = TcpListener::bind(bind).await?;
+                while let Ok((stream, addr)) = socket.accept().await {
+                    self.spawn_connection(IMAPStream::Tcp(stream), Some(addr));
+                }
+            }
+            BindAddress::Unix(path) => {
+                if path.exists() {
+                    // Left behind by a previous run
+                    std::fs::remove_file(&path)?;
+                }
+                let socket = UnixListener::bind(path)?;
+                while let Ok((stream, _)) = socket.accept().await {
+                    self.spawn_connection(IMAPStream::Unix(stream), None);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn spawn_connection(&self, stream: IMAPStream, addr: Option<SocketAddr>) {
+        let connection = Connection {
+            stream,
+            addr,
+            host: self.host.clone(),
+            service: self.service.clone(),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = connection.run().await {
+                eprintln!("Error in IMAP connection: {:?}", e);
+            }
+        });
+    }
+}
@@ -0,0 +1,100 @@
+use crate::imap_config::IMAPConfig;
+use crate::imap_listener::Instance;
+use directories::directory_type::Directory;
+use directories::oauth::{OAuthAuthenticator, TokenError};
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use storages::storage_type::Storage;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use utils::configs::{Config, IOOrToml};
+use utils::service::ServiceAccess;
+
+#[derive(Debug, Error)]
+pub enum IMAPServiceError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Config(#[from] IOOrToml),
+    #[error(transparent)]
+    GettingDirectoryAccess(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    Directory(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    GettingStorageAccess(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    Storage(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    OAuth(#[from] TokenError),
+}
+pub struct IMAPServiceInner<
+    D: Directory,
+    DirectoryAccess: ServiceAccess<ServiceResponse = D>,
+    S: Storage,
+    StorageAccess: ServiceAccess<ServiceResponse = S>,
+> {
+    pub config: IMAPConfig,
+    /// Built from [IMAPConfig::oauth]
+    pub oauth: Option<OAuthAuthenticator>,
+    pub directory_service_access: DirectoryAccess,
+    pub storage_service_access: StorageAccess,
+}
+
+impl<
+        D: Directory,
+        DirectoryAccess: ServiceAccess<ServiceResponse = D>,
+        S: Storage,
+        StorageAccess: ServiceAccess<ServiceResponse = S>,
+    > IMAPService<D, DirectoryAccess, S, StorageAccess>
+{
+    pub fn start(
+        working_directory: PathBuf,
+        directory_service_access: DirectoryAccess,
+        storage_service_access: StorageAccess,
+    ) -> Result<IMAPService<D, DirectoryAccess, S, StorageAccess>, IMAPServiceError> {
+        let imap_config = IMAPConfig::get_or_save_default(working_directory)?;
+        let oauth = imap_config
+            .oauth
+            .as_ref()
+            .map(OAuthAuthenticator::from_config)
+            .transpose()?;
+
+        let service = Arc::new(IMAPServiceInner {
+            config: imap_config.clone(),
+            oauth,
+            directory_service_access,
+            storage_service_access,
+        });
+        let mut instances = Vec::with_capacity(imap_config.hosts.len());
+        for host in imap_config.hosts {
+            let instance = Instance {
+                service: service.clone(),
+                host,
+            };
+            let handle = tokio::spawn(async move {
+                if let Err(e) = instance.run().await {
+                    eprintln!("Error in IMAP instance: {:?}", e);
+                }
+            });
+            instances.push(handle);
+        }
+
+        Ok(IMAPService {
+            inner: service,
+            instances,
+        })
+    }
+}
+pub struct IMAPService<
+    D: Directory,
+    DirectoryAccess: ServiceAccess<ServiceResponse = D>,
+    S: Storage,
+    StorageAccess: ServiceAccess<ServiceResponse = S>,
+> {
+    pub inner: IMAPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+    pub instances: Vec<JoinHandle<()>>,
+}
+pub type IMAPServiceAccess<D, DirectoryAccess, S, StorageAccess> =
+    Arc<IMAPServiceInner<D, DirectoryAccess, S, StorageAccess>>;
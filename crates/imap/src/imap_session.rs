@@ -0,0 +1,715 @@
+//! The IMAP command state machine shared by every connection
+//!
+//! Only part of [IMAP4rev1](https://www.rfc-editor.org/rfc/rfc3501) is served. Commands it does not
+//! know get a tagged `BAD`
+use chrono::DateTime;
+use uuid::Uuid;
+
+use storages::folders::{Folder, MessageInfo, NewMessage, INBOX};
+use storages::search::SearchQuery;
+use utils::account::Account;
+use utils::sasl::SaslMechanism;
+
+use crate::quota;
+use crate::search::{bad_charset_response, parse_search, SearchParseError};
+
+/// The system flags of [RFC 3501](https://www.rfc-editor.org/rfc/rfc3501#section-2.3.2)
+const SYSTEM_FLAGS: &str = "\\Answered \\Flagged \\Deleted \\Seen \\Draft";
+
+/// Octets in a command line between literals, including the CRLF
+pub const MAX_COMMAND_LINE: usize = 8192;
+
+/// A command as read by the connection. The line is split where literals were sent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Command {
+    /// The text around the literals without their `{size}` markers. One more than there are literals
+    pub segments: Vec<String>,
+    pub literals: Vec<Vec<u8>>,
+}
+impl Command {
+    /// A command without literals
+    pub fn line(line: impl Into<String>) -> Self {
+        Self {
+            segments: vec![line.into()],
+            literals: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    Atom(String),
+    Quoted(String),
+    Literal(Vec<u8>),
+    Open,
+    Close,
+}
+impl Token {
+    /// An atom, quoted string or literal
+    fn astring(self) -> Option<String> {
+        match self {
+            Token::Atom(value) | Token::Quoted(value) => Some(value),
+            Token::Literal(value) => String::from_utf8(value).ok(),
+            Token::Open | Token::Close => None,
+        }
+    }
+}
+
+fn tokenize_segment(segment: &str, tokens: &mut Vec<Token>) {
+    let mut characters = segment.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            ' ' | '\t' | '\r' | '\n' => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut quoted = String::new();
+                while let Some(character) = characters.next() {
+                    match character {
+                        '"' => break,
+                        '\\' => quoted.extend(characters.next()),
+                        _ => quoted.push(character),
+                    }
+                }
+                tokens.push(Token::Quoted(quoted));
+            }
+            _ => {
+                let mut atom = String::from(character);
+                while let Some(&next) = characters.peek() {
+                    if matches!(next, ' ' | '\t' | '\r' | '\n' | '(' | ')' | '"') {
+                        break;
+                    }
+                    atom.push(next);
+                    characters.next();
+                }
+                tokens.push(Token::Atom(atom));
+            }
+        }
+    }
+}
+
+pub(crate) fn tokenize(command: &Command) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut literals = command.literals.iter();
+    for segment in &command.segments {
+        tokenize_segment(segment, &mut tokens);
+        if let Some(literal) = literals.next() {
+            tokens.push(Token::Literal(literal.clone()));
+        }
+    }
+    tokens
+}
+
+/// The folder a mailbox name refers to. `INBOX` is case-insensitive
+pub(crate) fn folder_name(mailbox: String) -> String {
+    if mailbox.eq_ignore_ascii_case(INBOX) {
+        INBOX.to_string()
+    } else {
+        mailbox
+    }
+}
+
+/// Unix seconds of an IMAP `date-time` such as `17-Jul-1996 02:44:25 -0700`
+fn parse_date_time(value: &str) -> Option<i64> {
+    DateTime::parse_from_str(value.trim_start(), "%d-%b-%Y %H:%M:%S %z")
+        .ok()
+        .map(|date| date.timestamp())
+}
+
+/// Quotes a string as an IMAP quoted string
+pub(crate) fn quoted(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for character in value.chars() {
+        if matches!(character, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(character);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The SEARCH criteria as text for [parse_search]. Literals become quoted strings
+fn search_criteria(arguments: Vec<Token>) -> String {
+    let criteria: Vec<String> = arguments
+        .into_iter()
+        .map(|token| match token {
+            Token::Atom(atom) => atom,
+            Token::Quoted(value) => quoted(&value),
+            Token::Literal(value) => quoted(&String::from_utf8_lossy(&value)),
+            Token::Open => "(".to_string(),
+            Token::Close => ")".to_string(),
+        })
+        .collect();
+    criteria.join(" ")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    NotAuthenticated,
+    Authenticated,
+    Selected,
+    Logout,
+}
+
+/// The folder opened with SELECT or EXAMINE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selected {
+    pub folder: String,
+    /// Opened with EXAMINE
+    pub read_only: bool,
+    /// The UIDs of the messages in sequence number order
+    pub uids: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionResponse {
+    /// Untagged responses followed by the tagged one
+    Reply(String),
+    /// Send the reply and close the connection
+    Logout(String),
+    /// Check the lockout and the password then call [Session::authenticated],
+    /// [Session::authentication_failed] or [Session::locked_out]
+    Login {
+        tag: String,
+        username: String,
+        password: String,
+    },
+    /// Run the SASL exchange then call [Session::authenticated] or [Session::authentication_failed]
+    Authenticate {
+        tag: String,
+        mechanism: SaslMechanism,
+        /// The base64 initial response sent with the command. [RFC 4959](https://www.rfc-editor.org/rfc/rfc4959)
+        initial_response: Option<String>,
+    },
+    /// Send the [quota_response](crate::quota::quota_response) of the root
+    GetQuota {
+        tag: String,
+        mailbox_id: Uuid,
+        root: String,
+    },
+    /// Check the folder exists then send its quota roots and their quotas
+    GetQuotaRoot {
+        tag: String,
+        mailbox_id: Uuid,
+        folder: String,
+    },
+    /// Check the quota then store the message
+    Append {
+        tag: String,
+        mailbox_id: Uuid,
+        folder: String,
+        message: NewMessage,
+    },
+    /// List the messages of the folder then call [Session::selected] or [Session::select_failed]
+    Select {
+        tag: String,
+        mailbox_id: Uuid,
+        folder: String,
+        read_only: bool,
+    },
+    /// Search the selected folder then call [Session::search_results]
+    Search {
+        tag: String,
+        mailbox_id: Uuid,
+        folder: String,
+        query: SearchQuery,
+        /// UID SEARCH
+        uid: bool,
+    },
+}
+
+/// This does not do any I/O. The connection feeds it commands and writes back the responses
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub state: SessionState,
+    /// Offered with AUTHENTICATE. Empty when only LOGIN is available
+    pub auth_mechanisms: Vec<SaslMechanism>,
+    /// The logged in account
+    pub account: Option<Account>,
+    pub selected: Option<Selected>,
+}
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            state: SessionState::NotAuthenticated,
+            auth_mechanisms: vec![],
+            account: None,
+            selected: None,
+        }
+    }
+    pub fn greeting(&self, greeting: Option<&str>) -> String {
+        format!(
+            "* OK [CAPABILITY {}] {}\r\n",
+            self.capabilities().join(" "),
+            greeting.unwrap_or("nitro_mail ready")
+        )
+    }
+    pub fn capabilities(&self) -> Vec<String> {
+        let mut capabilities = vec![
+            "IMAP4rev1".to_string(),
+            "LITERAL+".to_string(),
+            "SASL-IR".to_string(),
+        ];
+        if self.state == SessionState::NotAuthenticated {
+            capabilities.extend(
+                self.auth_mechanisms
+                    .iter()
+                    .map(|mechanism| format!("AUTH={}", mechanism)),
+            );
+        } else {
+            capabilities.extend(quota::capabilities());
+        }
+        capabilities
+    }
+
+    pub fn handle_command(&mut self, command: &Command) -> SessionResponse {
+        let mut tokens = tokenize(command).into_iter();
+        let Some(Token::Atom(tag)) = tokens.next() else {
+            return SessionResponse::Reply("* BAD Missing command tag\r\n".to_string());
+        };
+        let Some(Token::Atom(name)) = tokens.next() else {
+            return SessionResponse::Reply(bad(&tag, "Missing command"));
+        };
+        let arguments: Vec<Token> = tokens.collect();
+        let name = name.to_ascii_uppercase();
+        match name.as_str() {
+            "CAPABILITY" => SessionResponse::Reply(format!(
+                "* CAPABILITY {}\r\n{}",
+                self.capabilities().join(" "),
+                ok(&tag, "CAPABILITY completed")
+            )),
+            "NOOP" => SessionResponse::Reply(ok(&tag, "NOOP completed")),
+            "LOGOUT" => {
+                self.state = SessionState::Logout;
+                SessionResponse::Logout(format!(
+                    "* BYE Logging out\r\n{}",
+                    ok(&tag, "LOGOUT completed")
+                ))
+            }
+            "LOGIN" | "AUTHENTICATE" if self.state != SessionState::NotAuthenticated => {
+                SessionResponse::Reply(bad(&tag, "Already authenticated"))
+            }
+            "LOGIN" => self.login(tag, arguments),
+            "AUTHENTICATE" => self.authenticate(tag, arguments),
+            _ if self.state == SessionState::NotAuthenticated => {
+                SessionResponse::Reply(bad(&tag, "Log in first"))
+            }
+            "GETQUOTA" => self.get_quota(tag, arguments),
+            "GETQUOTAROOT" => self.get_quota_root(tag, arguments),
+            "APPEND" => self.append(tag, arguments),
+            "SELECT" => self.select(tag, arguments, false),
+            "EXAMINE" => self.select(tag, arguments, true),
+            "SEARCH" => self.search(tag, arguments, false),
+            "UID" => match arguments.split_first() {
+                Some((Token::Atom(command), arguments))
+                    if command.eq_ignore_ascii_case("SEARCH") =>
+                {
+                    self.search(tag, arguments.to_vec(), true)
+                }
+                _ => SessionResponse::Reply(bad(&tag, "Unknown command")),
+            },
+            _ => SessionResponse::Reply(bad(&tag, "Unknown command")),
+        }
+    }
+
+    /// The mailbox of the logged in account
+    fn mailbox_id(&self) -> Uuid {
+        self.account
+            .as_ref()
+            .map(|account| account.mailbox_id)
+            .unwrap_or_default()
+    }
+
+    /// `GETQUOTA <root>`
+    fn get_quota(&mut self, tag: String, arguments: Vec<Token>) -> SessionResponse {
+        let mut arguments = arguments.into_iter().map(Token::astring);
+        match (arguments.next(), arguments.next()) {
+            (Some(Some(root)), None) => SessionResponse::GetQuota {
+                tag,
+                mailbox_id: self.mailbox_id(),
+                root,
+            },
+            _ => SessionResponse::Reply(bad(&tag, "Syntax: GETQUOTA root")),
+        }
+    }
+
+    /// `GETQUOTAROOT <mailbox>`
+    fn get_quota_root(&mut self, tag: String, arguments: Vec<Token>) -> SessionResponse {
+        let mut arguments = arguments.into_iter().map(Token::astring);
+        match (arguments.next(), arguments.next()) {
+            (Some(Some(mailbox)), None) => SessionResponse::GetQuotaRoot {
+                tag,
+                mailbox_id: self.mailbox_id(),
+                folder: folder_name(mailbox),
+            },
+            _ => SessionResponse::Reply(bad(&tag, "Syntax: GETQUOTAROOT mailbox")),
+        }
+    }
+
+    /// `APPEND <mailbox> [(flags)] [date-time] <literal>`. Without a date the message is dated now
+    fn append(&mut self, tag: String, arguments: Vec<Token>) -> SessionResponse {
+        let syntax = || {
+            SessionResponse::Reply(bad(
+                &tag,
+                "Syntax: APPEND mailbox [flags] [date-time] literal",
+            ))
+        };
+        let mut arguments = arguments.into_iter().peekable();
+        let Some(Some(mailbox)) = arguments.next().map(Token::astring) else {
+            return syntax();
+        };
+        let mut flags = Vec::new();
+        if arguments.next_if_eq(&Token::Open).is_some() {
+            loop {
+                match arguments.next() {
+                    Some(Token::Close) => break,
+                    Some(Token::Atom(flag)) => flags.push(flag),
+                    _ => return syntax(),
+                }
+            }
+        }
+        let mut internal_date = chrono::Utc::now().timestamp();
+        if let Some(Token::Quoted(date)) = arguments.peek() {
+            let Some(date) = parse_date_time(date) else {
+                return SessionResponse::Reply(bad(&tag, "Invalid date-time"));
+            };
+            internal_date = date;
+            arguments.next();
+        }
+        let (Some(Token::Literal(contents)), None) = (arguments.next(), arguments.next()) else {
+            return syntax();
+        };
+        SessionResponse::Append {
+            mailbox_id: self.mailbox_id(),
+            folder: folder_name(mailbox),
+            message: NewMessage {
+                flags,
+                internal_date,
+                contents,
+            },
+            tag,
+        }
+    }
+
+    fn login(&mut self, tag: String, arguments: Vec<Token>) -> SessionResponse {
+        let mut arguments = arguments.into_iter().map(Token::astring);
+        match (arguments.next(), arguments.next(), arguments.next()) {
+            (Some(Some(username)), Some(Some(password)), None) => SessionResponse::Login {
+                tag,
+                username,
+                password,
+            },
+            _ => SessionResponse::Reply(bad(&tag, "Syntax: LOGIN username password")),
+        }
+    }
+
+    /// `AUTHENTICATE <mechanism> [initial-response]`
+    fn authenticate(&mut self, tag: String, arguments: Vec<Token>) -> SessionResponse {
+        let mut arguments = arguments.into_iter();
+        let Some(Token::Atom(mechanism)) = arguments.next() else {
+            return SessionResponse::Reply(bad(&tag, "Syntax: AUTHENTICATE mechanism"));
+        };
+        let mechanism = SaslMechanism::from_name(&mechanism)
+            .filter(|mechanism| self.auth_mechanisms.contains(mechanism));
+        let Some(mechanism) = mechanism else {
+            return SessionResponse::Reply(no(&tag, "Unsupported authentication mechanism"));
+        };
+        let initial_response = match arguments.next() {
+            Some(Token::Atom(response)) => Some(response),
+            None => None,
+            Some(_) => {
+                return SessionResponse::Reply(bad(&tag, "Invalid initial response"));
+            }
+        };
+        SessionResponse::Authenticate {
+            tag,
+            mechanism,
+            initial_response,
+        }
+    }
+
+    /// `SELECT <mailbox>` or `EXAMINE <mailbox>`
+    fn select(&mut self, tag: String, arguments: Vec<Token>, read_only: bool) -> SessionResponse {
+        let mut arguments = arguments.into_iter().map(Token::astring);
+        match (arguments.next(), arguments.next()) {
+            (Some(Some(mailbox)), None) => SessionResponse::Select {
+                tag,
+                mailbox_id: self.mailbox_id(),
+                folder: folder_name(mailbox),
+                read_only,
+            },
+            _ => SessionResponse::Reply(bad(&tag, "Syntax: SELECT mailbox")),
+        }
+    }
+
+    /// `[UID] SEARCH [CHARSET <charset>] <keys>`
+    fn search(&mut self, tag: String, arguments: Vec<Token>, uid: bool) -> SessionResponse {
+        let Some(selected) = &self.selected else {
+            return SessionResponse::Reply(bad(&tag, "No mailbox selected"));
+        };
+        let query = match parse_search(&search_criteria(arguments)) {
+            Ok(query) => query,
+            Err(SearchParseError::BadCharset(_)) => {
+                return SessionResponse::Reply(bad_charset_response(&tag))
+            }
+            Err(error @ SearchParseError::NotIndexed(_)) => {
+                return SessionResponse::Reply(no(&tag, &error.to_string()))
+            }
+            Err(error) => return SessionResponse::Reply(bad(&tag, &error.to_string())),
+        };
+        SessionResponse::Search {
+            mailbox_id: self.mailbox_id(),
+            folder: selected.folder.clone(),
+            query,
+            uid,
+            tag,
+        }
+    }
+
+    /// Opens the folder. `messages` are sorted by UID
+    pub fn selected(
+        &mut self,
+        tag: &str,
+        folder: &Folder,
+        read_only: bool,
+        messages: &[MessageInfo],
+    ) -> String {
+        self.selected = Some(Selected {
+            folder: folder.name.clone(),
+            read_only,
+            uids: messages.iter().map(|message| message.uid).collect(),
+        });
+        self.state = SessionState::Selected;
+        let (access, command) = if read_only {
+            ("READ-ONLY", "EXAMINE")
+        } else {
+            ("READ-WRITE", "SELECT")
+        };
+        format!(
+            "* FLAGS ({SYSTEM_FLAGS})\r\n\
+             * OK [PERMANENTFLAGS ({SYSTEM_FLAGS} \\*)] Flags permitted\r\n\
+             * {} EXISTS\r\n\
+             * 0 RECENT\r\n\
+             * OK [UIDVALIDITY {}] UIDs valid\r\n\
+             * OK [UIDNEXT {}] Predicted next UID\r\n\
+             {}",
+            messages.len(),
+            folder.uid_validity,
+            folder.uid_next,
+            ok(tag, &format!("[{access}] {command} completed"))
+        )
+    }
+    /// A failed SELECT or EXAMINE closes the folder that was selected
+    pub fn select_failed(&mut self, tag: &str) -> String {
+        self.selected = None;
+        self.state = SessionState::Authenticated;
+        no(tag, "[NONEXISTENT] No such mailbox")
+    }
+
+    /// The sorted UIDs found by [SessionResponse::Search]. Plain SEARCH answers with sequence numbers,
+    /// so messages that arrived after SELECT are left out
+    pub fn search_results(&self, tag: &str, uid: bool, uids: &[u32]) -> String {
+        let selected_uids = self
+            .selected
+            .as_ref()
+            .map(|selected| selected.uids.as_slice())
+            .unwrap_or_default();
+        let mut response = "* SEARCH".to_string();
+        for &found in uids {
+            let number = if uid {
+                Some(found)
+            } else {
+                selected_uids
+                    .binary_search(&found)
+                    .ok()
+                    .map(|index| index as u32 + 1)
+            };
+            if let Some(number) = number {
+                response.push_str(&format!(" {}", number));
+            }
+        }
+        response.push_str("\r\n");
+        let command = if uid { "UID SEARCH" } else { "SEARCH" };
+        response.push_str(&ok(tag, &format!("{command} completed")));
+        response
+    }
+
+    pub fn authenticated(&mut self, tag: &str, account: Account) -> String {
+        self.account = Some(account);
+        self.state = SessionState::Authenticated;
+        format!(
+            "{} OK [CAPABILITY {}] Logged in\r\n",
+            tag,
+            self.capabilities().join(" ")
+        )
+    }
+    pub fn authentication_failed(&self, tag: &str) -> String {
+        no(tag, "[AUTHENTICATIONFAILED] Invalid credentials")
+    }
+    /// Sent instead of checking credentials while the address or account is locked out
+    pub fn locked_out(&self, tag: &str) -> String {
+        no(tag, "[UNAVAILABLE] Too many failed logins, try again later")
+    }
+
+    /// For command lines longer than [MAX_COMMAND_LINE]
+    pub fn line_too_long(&self) -> String {
+        "* BAD Command line too long\r\n".to_string()
+    }
+}
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn ok(tag: &str, text: &str) -> String {
+    format!("{} OK {}\r\n", tag, text)
+}
+pub fn no(tag: &str, text: &str) -> String {
+    format!("{} NO {}\r\n", tag, text)
+}
+pub fn bad(tag: &str, text: &str) -> String {
+    format!("{} BAD {}\r\n", tag, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use storages::folders::NewMessage;
+    use utils::account::Account;
+    use utils::common_types::AccountType;
+    use utils::sasl::SaslMechanism;
+    use uuid::Uuid;
+
+    use crate::imap_session::{tokenize, Command, Session, SessionResponse, SessionState, Token};
+
+    fn reply(session: &mut Session, line: &str) -> String {
+        match session.handle_command(&Command::line(line)) {
+            SessionResponse::Reply(reply) | SessionResponse::Logout(reply) => reply,
+            response => panic!("Expected a reply to {line}, got {response:?}"),
+        }
+    }
+
+    #[test]
+    pub fn test_tokenize() {
+        let command = Command {
+            segments: vec![
+                "a1 LOGIN ".to_string(),
+                " \"pass \\\"word\\\"\" (x y)".to_string(),
+            ],
+            literals: vec![b"john".to_vec()],
+        };
+        assert_eq!(
+            tokenize(&command),
+            vec![
+                Token::Atom("a1".to_string()),
+                Token::Atom("LOGIN".to_string()),
+                Token::Literal(b"john".to_vec()),
+                Token::Quoted("pass \"word\"".to_string()),
+                Token::Open,
+                Token::Atom("x".to_string()),
+                Token::Atom("y".to_string()),
+                Token::Close,
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_not_authenticated() {
+        let mut session = Session::new();
+        assert_eq!(
+            reply(&mut session, "a1 CAPABILITY"),
+            "* CAPABILITY IMAP4rev1 LITERAL+ SASL-IR\r\na1 OK CAPABILITY completed\r\n"
+        );
+        assert_eq!(
+            reply(&mut session, "a2 SELECT INBOX"),
+            "a2 BAD Log in first\r\n"
+        );
+        assert_eq!(
+            reply(&mut session, "a3 AUTHENTICATE XOAUTH2"),
+            "a3 NO Unsupported authentication mechanism\r\n"
+        );
+        assert_eq!(reply(&mut session, ""), "* BAD Missing command tag\r\n");
+        assert_eq!(
+            reply(&mut session, "a4 LOGIN john"),
+            "a4 BAD Syntax: LOGIN username password\r\n"
+        );
+        assert_eq!(
+            session.handle_command(&Command::line("a5 login john \"secret\"")),
+            SessionResponse::Login {
+                tag: "a5".to_string(),
+                username: "john".to_string(),
+                password: "secret".to_string(),
+            }
+        );
+        assert_eq!(
+            reply(&mut session, "a6 LOGOUT"),
+            "* BYE Logging out\r\na6 OK LOGOUT completed\r\n"
+        );
+        assert_eq!(session.state, SessionState::Logout);
+    }
+
+    #[test]
+    pub fn test_authenticate() {
+        let mut session = Session::new();
+        session.auth_mechanisms = vec![SaslMechanism::OAuthBearer, SaslMechanism::XOAuth2];
+        assert!(reply(&mut session, "a1 CAPABILITY").starts_with(
+            "* CAPABILITY IMAP4rev1 LITERAL+ SASL-IR AUTH=OAUTHBEARER AUTH=XOAUTH2\r\n"
+        ));
+        assert_eq!(
+            session.handle_command(&Command::line("a2 AUTHENTICATE xoauth2 dXNlcj0=")),
+            SessionResponse::Authenticate {
+                tag: "a2".to_string(),
+                mechanism: SaslMechanism::XOAuth2,
+                initial_response: Some("dXNlcj0=".to_string()),
+            }
+        );
+        assert_eq!(
+            reply(&mut session, "a3 AUTHENTICATE PLAIN"),
+            "a3 NO Unsupported authentication mechanism\r\n"
+        );
+        assert_eq!(
+            session.authentication_failed("a4"),
+            "a4 NO [AUTHENTICATIONFAILED] Invalid credentials\r\n"
+        );
+    }
+
+    #[test]
+    pub fn test_append() {
+        let mut session = Session::new();
+        let mailbox_id = Uuid::from_u128(1);
+        session.authenticated(
+            "a1",
+            Account::new("john", AccountType::Individual, mailbox_id),
+        );
+        let command = Command {
+            segments: vec![
+                "a2 APPEND inbox (\\Seen \\Flagged) \" 7-Jul-2024 02:44:25 -0700\" ".to_string(),
+                String::new(),
+            ],
+            literals: vec![b"Subject: Hi\r\n\r\n".to_vec()],
+        };
+        assert_eq!(
+            session.handle_command(&command),
+            SessionResponse::Append {
+                tag: "a2".to_string(),
+                mailbox_id,
+                folder: "INBOX".to_string(),
+                message: NewMessage {
+                    flags: vec!["\\Seen".to_string(), "\\Flagged".to_string()],
+                    internal_date: 1720345465,
+                    contents: b"Subject: Hi\r\n\r\n".to_vec(),
+                },
+            }
+        );
+        assert_eq!(
+            reply(&mut session, "a3 APPEND INBOX \"yesterday\" \"x\""),
+            "a3 BAD Invalid date-time\r\n"
+        );
+        assert_eq!(
+            reply(&mut session, "a4 APPEND INBOX (\\Seen)"),
+            "a4 BAD Syntax: APPEND mailbox [flags] [date-time] literal\r\n"
+        );
+    }
+}
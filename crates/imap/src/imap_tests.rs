@@ -0,0 +1,373 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use interprocess::local_socket::tokio::LocalSocketListener;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::UnixStream;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use directories::directory_type::Directory;
+use directories::oauth::{AccountLookup, Claims, OAuthAuthenticator, TokenError, TokenValidator};
+use directory_file::file_config::FileDirectoryConfig;
+use directory_file::file_directory::FileDirectory;
+use storage_mail_directory::maildir_storage::MaildirStorage;
+use storage_search::indexed_storage::IndexedStorage;
+use storage_search::search_index::SearchIndex;
+use storages::storage_service::storage_service_storage::StorageServiceStorageAccess;
+use storages::storage_service::StorageService;
+use storages::storage_type::Storage;
+use utils::configs::brute_force::BruteForceConfig;
+use utils::helper_types::Password;
+
+use crate::imap_client::{Connection, IMAPStream};
+use crate::imap_config::{IMAPConfig, IMAPHost};
+use crate::imap_service::{IMAPServiceError, IMAPServiceInner};
+
+const JOHN_MAILBOX: Uuid = Uuid::from_u128(1);
+
+/// Accepts the token `valid` as one issued to `john`
+#[derive(Debug)]
+struct StaticTokenValidator;
+#[async_trait]
+impl TokenValidator for StaticTokenValidator {
+    async fn validate(&self, token: &str) -> Result<Option<Claims>, TokenError> {
+        let mut claims = Claims::new();
+        claims.insert("sub".to_string(), "john".into());
+        Ok((token == "valid").then_some(claims))
+    }
+}
+
+/// A directory in the temp folder that is removed on drop
+struct TestFiles(PathBuf);
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `john` with the password `password` who may keep two messages
+async fn file_directory(files: &TestFiles, brute_force: BruteForceConfig) -> FileDirectory {
+    std::fs::create_dir_all(&files.0).unwrap();
+    let password: String = Password::new_argon2("password").unwrap().into();
+    let accounts = format!(
+        r#"
+        [[accounts]]
+        username = "john"
+        password = "{password}"
+        email = "john@example.com"
+        mailbox_id = "{JOHN_MAILBOX}"
+        quota = {{ messages = 2 }}
+        "#
+    );
+    std::fs::write(files.0.join("accounts.toml"), accounts).unwrap();
+    FileDirectory::load(FileDirectoryConfig {
+        accounts_file: files.0.join("accounts.toml"),
+        state_file: files.0.join("accounts.state.toml"),
+        brute_force,
+        ..Default::default()
+    })
+    .await
+    .unwrap()
+}
+
+/// An IMAP session whose storage is reached through a storage service. Bearer tokens are accepted
+struct TestSession {
+    reader: BufReader<ReadHalf<UnixStream>>,
+    writer: WriteHalf<UnixStream>,
+    session: JoinHandle<Result<(), IMAPServiceError>>,
+    /// The storage behind the storage service
+    storage: IndexedStorage<MaildirStorage>,
+    _files: TestFiles,
+}
+impl TestSession {
+    async fn start() -> Self {
+        Self::start_with(BruteForceConfig::default()).await
+    }
+    async fn start_with(brute_force: BruteForceConfig) -> Self {
+        let files =
+            TestFiles(std::env::temp_dir().join(format!("nitro_mail_imap_{}", Uuid::new_v4())));
+        let directory = file_directory(&files, brute_force).await;
+
+        let index = Arc::new(SearchIndex::in_memory(15_000_000).unwrap());
+        let storage = IndexedStorage::new(MaildirStorage::new(files.0.join("mail")), index);
+        let socket_name = format!("nitro_mail_storage_test_{}", Uuid::new_v4());
+        let listener = LocalSocketListener::bind(socket_name.as_str()).unwrap();
+        tokio::spawn(StorageService::new(storage.clone()).serve(listener));
+
+        let service = Arc::new(IMAPServiceInner {
+            config: IMAPConfig::default(),
+            oauth: Some(OAuthAuthenticator::new(
+                Arc::new(StaticTokenValidator),
+                "sub",
+                AccountLookup::Username,
+            )),
+            directory_service_access: directory,
+            storage_service_access: StorageServiceStorageAccess { socket_name },
+        });
+        let (client, server) = UnixStream::pair().unwrap();
+        let connection = Connection {
+            stream: IMAPStream::Unix(server),
+            addr: None,
+            host: IMAPHost {
+                bind: "unix:/run/nitro_mail/imap.sock".to_string(),
+                greeting: None,
+            },
+            service,
+        };
+        let (reader, writer) = tokio::io::split(client);
+        let mut session = TestSession {
+            reader: BufReader::new(reader),
+            writer,
+            session: tokio::spawn(connection.run()),
+            storage,
+            _files: files,
+        };
+        assert!(session.line().await.starts_with("* OK [CAPABILITY"));
+        session
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{line}\r\n").as_bytes())
+            .await
+            .unwrap();
+    }
+    async fn line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).await.unwrap();
+        line.trim_end().to_string()
+    }
+    async fn command(&mut self, tag: &str, command: &str) -> Vec<String> {
+        self.send(&format!("{tag} {command}")).await;
+        self.response(tag).await
+    }
+    /// The lines of the response up to the tagged one
+    async fn response(&mut self, tag: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.line().await;
+            let tagged = line.starts_with(&format!("{tag} "));
+            lines.push(line);
+            if tagged {
+                return lines;
+            }
+        }
+    }
+    /// Appends the message with a non-synchronizing literal and returns the tagged line
+    async fn append(&mut self, tag: &str, folder: &str, message: &str) -> String {
+        self.send(&format!("{tag} APPEND {folder} {{{}+}}", message.len()))
+            .await;
+        self.send(message).await;
+        self.response(tag).await.pop().unwrap()
+    }
+    /// The tagged line of the response
+    async fn tagged(&mut self, tag: &str, command: &str) -> String {
+        self.command(tag, command).await.pop().unwrap()
+    }
+
+    async fn logout(mut self) {
+        let lines = self.command("z", "LOGOUT").await;
+        assert_eq!(lines, vec!["* BYE Logging out", "z OK LOGOUT completed"]);
+        self.session.await.unwrap().unwrap();
+    }
+}
+
+fn base64(value: &str) -> String {
+    STANDARD.encode(value)
+}
+
+#[tokio::test]
+async fn test_login() {
+    let mut session = TestSession::start().await;
+    assert_eq!(
+        session.tagged("a1", "LOGIN john wrong").await,
+        "a1 NO [AUTHENTICATIONFAILED] Invalid credentials"
+    );
+    // The password as a literal
+    session.send("a2 LOGIN john {8}").await;
+    assert_eq!(session.line().await, "+ Ready for literal data");
+    session.send("password").await;
+    let response = session.response("a2").await;
+    assert!(response[0].starts_with("a2 OK [CAPABILITY IMAP4rev1"));
+    assert_eq!(
+        session.tagged("a3", "LOGIN john password").await,
+        "a3 BAD Already authenticated"
+    );
+    session.logout().await;
+}
+
+#[tokio::test]
+async fn test_authenticate_bearer() {
+    let mut session = TestSession::start().await;
+    let capabilities = session.command("a1", "CAPABILITY").await;
+    assert!(capabilities[0].ends_with("AUTH=OAUTHBEARER AUTH=XOAUTH2"));
+
+    // The server asks for the response and fails it once the error was acknowledged
+    session.send("a2 AUTHENTICATE OAUTHBEARER").await;
+    assert_eq!(session.line().await, "+");
+    session
+        .send(&base64("n,a=john,\x01auth=Bearer expired\x01\x01"))
+        .await;
+    assert!(session
+        .line()
+        .await
+        .starts_with("+ eyJzdGF0dXMiOiJpbnZhbGlkX3Rva2Vu"));
+    session.send(&base64("\x01")).await;
+    assert_eq!(
+        session.response("a2").await,
+        vec!["a2 NO [AUTHENTICATIONFAILED] Invalid credentials"]
+    );
+
+    let response = base64("user=john\x01auth=Bearer valid\x01\x01");
+    assert!(session
+        .tagged("a3", &format!("AUTHENTICATE XOAUTH2 {response}"))
+        .await
+        .starts_with("a3 OK"));
+    session.logout().await;
+}
+
+#[tokio::test]
+async fn test_login_lockout() {
+    let mut session = TestSession::start_with(BruteForceConfig {
+        max_username_failures: 2,
+        ..Default::default()
+    })
+    .await;
+    for tag in ["a1", "a2"] {
+        assert_eq!(
+            session.tagged(tag, "LOGIN john wrong").await,
+            format!("{tag} NO [AUTHENTICATIONFAILED] Invalid credentials")
+        );
+    }
+    // The right password is not checked while the account is locked
+    assert_eq!(
+        session.tagged("a3", "LOGIN john password").await,
+        "a3 NO [UNAVAILABLE] Too many failed logins, try again later"
+    );
+    let response = base64("user=john\x01auth=Bearer valid\x01\x01");
+    assert_eq!(
+        session
+            .tagged("a4", &format!("AUTHENTICATE XOAUTH2 {response}"))
+            .await,
+        "a4 NO [UNAVAILABLE] Too many failed logins, try again later"
+    );
+    session.logout().await;
+}
+
+#[tokio::test]
+async fn test_quota() {
+    let mut session = TestSession::start().await;
+    assert!(session
+        .tagged("a1", "LOGIN john password")
+        .await
+        .contains(" QUOTA QUOTA=RES-STORAGE QUOTA=RES-MESSAGE]"));
+    assert_eq!(
+        session.command("a2", "GETQUOTAROOT inbox").await,
+        vec![
+            "* QUOTAROOT \"INBOX\" \"\"",
+            "* QUOTA \"\" (MESSAGE 0 2)",
+            "a2 OK GETQUOTAROOT completed"
+        ]
+    );
+    assert_eq!(
+        session.tagged("a3", "GETQUOTA staff").await,
+        "a3 NO [NONEXISTENT] No such quota root"
+    );
+
+    let message = "Subject: Hello\r\n\r\nHi John\r\n";
+    assert_eq!(
+        session.append("a4", "Archive", message).await,
+        "a4 NO [TRYCREATE] No such mailbox"
+    );
+    session
+        .send(&format!(
+            "a5 APPEND INBOX (\\Seen) \" 7-Jul-2024 02:44:25 -0700\" {{{}}}",
+            message.len()
+        ))
+        .await;
+    assert_eq!(session.line().await, "+ Ready for literal data");
+    session.send(message).await;
+    assert_eq!(session.response("a5").await, vec!["a5 OK APPEND completed"]);
+    assert_eq!(
+        session.command("a6", "GETQUOTA \"\"").await,
+        vec!["* QUOTA \"\" (MESSAGE 1 2)", "a6 OK GETQUOTA completed"]
+    );
+
+    assert_eq!(
+        session.append("a7", "INBOX", message).await,
+        "a7 OK APPEND completed"
+    );
+    assert_eq!(
+        session.append("a8", "INBOX", message).await,
+        "a8 NO [OVERQUOTA] Mailbox has too many messages"
+    );
+    session.logout().await;
+}
+
+#[tokio::test]
+async fn test_search() {
+    let mut session = TestSession::start().await;
+    assert!(session
+        .tagged("a1", "LOGIN john password")
+        .await
+        .starts_with("a1 OK"));
+    assert_eq!(
+        session.tagged("a2", "SEARCH ALL").await,
+        "a2 BAD No mailbox selected"
+    );
+    for (tag, message) in [
+        ("a3", "Subject: Weekly meeting\r\n\r\nAgenda\r\n"),
+        ("a4", "Subject: Lunch\r\n\r\nPizza\r\n"),
+    ] {
+        assert_eq!(
+            session.append(tag, "INBOX", message).await,
+            format!("{tag} OK APPEND completed")
+        );
+    }
+    session
+        .storage
+        .rebuild_search_index(JOHN_MAILBOX)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let selected = session.command("a5", "SELECT inbox").await;
+    assert!(selected.contains(&"* 2 EXISTS".to_string()));
+    assert_eq!(
+        selected.last().unwrap(),
+        "a5 OK [READ-WRITE] SELECT completed"
+    );
+    assert_eq!(
+        session.command("a6", "SEARCH SUBJECT meeting").await,
+        vec!["* SEARCH 1", "a6 OK SEARCH completed"]
+    );
+    // The search string as a literal
+    session.send("a7 UID SEARCH TEXT {5+}").await;
+    session.send("pizza").await;
+    assert_eq!(
+        session.response("a7").await,
+        vec!["* SEARCH 2", "a7 OK UID SEARCH completed"]
+    );
+    assert_eq!(
+        session.tagged("a8", "SEARCH CHARSET KOI8-R ALL").await,
+        "a8 NO [BADCHARSET (UTF-8 US-ASCII)] Unsupported charset"
+    );
+    assert_eq!(
+        session.tagged("a9", "SEARCH SEEN").await,
+        "a9 NO SEEN is not answered by the search index"
+    );
+
+    assert_eq!(
+        session.tagged("a10", "EXAMINE Archive").await,
+        "a10 NO [NONEXISTENT] No such mailbox"
+    );
+    assert_eq!(
+        session.tagged("a11", "SEARCH ALL").await,
+        "a11 BAD No mailbox selected"
+    );
+    session.logout().await;
+}
@@ -1,3 +1,36 @@
+use crate::imap_service::IMAPService;
+use directories::directory_type::Directory;
+use std::path::PathBuf;
+use storages::storage_type::Storage;
+use utils::service::ServiceAccess;
+
+pub mod imap_client;
+pub mod imap_config;
+pub mod imap_listener;
+pub mod imap_service;
+pub mod imap_session;
+#[cfg(test)]
+mod imap_tests;
+pub mod quota;
+pub mod search;
+
+pub fn start_imap_service<
+    D: Directory,
+    S: Storage,
+    DirectoryAccess: ServiceAccess<ServiceResponse = D>,
+    StorageAccess: ServiceAccess<ServiceResponse = S>,
+>(
+    working_directory: PathBuf,
+    directory_service_access: DirectoryAccess,
+    storage_service_access: StorageAccess,
+) -> Result<IMAPService<D, DirectoryAccess, S, StorageAccess>, imap_service::IMAPServiceError> {
+    IMAPService::start(
+        working_directory,
+        directory_service_access,
+        storage_service_access,
+    )
+}
+
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
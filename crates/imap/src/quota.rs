@@ -0,0 +1,120 @@
+//! The [IMAP QUOTA extension](https://www.rfc-editor.org/rfc/rfc9208)
+//!
+//! Every account has one quota root, `""`, covering all of its own mailboxes.
+//! GETQUOTA, GETQUOTAROOT and APPEND use these responses and checks
+use strum::IntoEnumIterator;
+
+use utils::quota::{Quota, QuotaResource, QuotaUsage};
+
+use crate::imap_session::quoted;
+
+/// The quota root of the mailboxes of the logged in account
+pub const ACCOUNT_QUOTA_ROOT: &str = "";
+
+/// Advertised in the CAPABILITY response
+pub fn capabilities() -> Vec<String> {
+    let mut capabilities = vec!["QUOTA".to_string()];
+    capabilities.extend(QuotaResource::iter().map(|resource| format!("QUOTA=RES-{}", resource)));
+    capabilities
+}
+
+/// `* QUOTA <root> (<resource> <usage> <limit> ...)` sent for GETQUOTA and GETQUOTAROOT
+///
+/// Only resources with a limit are listed. Storage is counted in KiB
+pub fn quota_response(root: &str, quota: &Quota, usage: &QuotaUsage) -> String {
+    let limited: Vec<String> = QuotaResource::iter()
+        .filter_map(|resource| {
+            let limit = quota.limit(resource)?;
+            let limit = match resource {
+                QuotaResource::Storage => limit / 1024,
+                QuotaResource::Message => limit,
+            };
+            Some(format!(
+                "{} {} {}",
+                resource,
+                usage.imap_value(resource),
+                limit
+            ))
+        })
+        .collect();
+    format!("* QUOTA {} ({})\r\n", quoted(root), limited.join(" "))
+}
+
+/// `* QUOTAROOT <mailbox> <root> ...` sent for GETQUOTAROOT
+pub fn quota_root_response(mailbox: &str, roots: &[&str]) -> String {
+    let mut response = format!("* QUOTAROOT {}", quoted(mailbox));
+    for root in roots {
+        response.push(' ');
+        response.push_str(&quoted(root));
+    }
+    response.push_str("\r\n");
+    response
+}
+
+/// Whether APPEND may store a message of `size` bytes.
+/// Rejected messages get an [over_quota_response]
+pub fn check_append(quota: &Quota, usage: &QuotaUsage, size: u64) -> Result<(), QuotaResource> {
+    match quota.exceeded_by(usage, size, 1) {
+        Some(resource) => Err(resource),
+        None => Ok(()),
+    }
+}
+
+/// The tagged `NO [OVERQUOTA]` response for APPEND and COPY
+pub fn over_quota_response(tag: &str, resource: QuotaResource) -> String {
+    let reason = match resource {
+        QuotaResource::Storage => "Mailbox is full",
+        QuotaResource::Message => "Mailbox has too many messages",
+    };
+    format!("{} NO [OVERQUOTA] {}\r\n", tag, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::quota::{Quota, QuotaResource, QuotaUsage};
+
+    use crate::quota::{
+        capabilities, check_append, over_quota_response, quota_response, quota_root_response,
+        ACCOUNT_QUOTA_ROOT,
+    };
+
+    #[test]
+    pub fn test_quota_responses() {
+        assert_eq!(
+            capabilities(),
+            vec!["QUOTA", "QUOTA=RES-STORAGE", "QUOTA=RES-MESSAGE"]
+        );
+        let quota = Quota {
+            storage: 10 * 1024 * 1024,
+            messages: 0,
+        };
+        let usage = QuotaUsage {
+            storage: 1025,
+            messages: 3,
+        };
+        assert_eq!(
+            quota_root_response("INBOX", &[ACCOUNT_QUOTA_ROOT]),
+            "* QUOTAROOT \"INBOX\" \"\"\r\n"
+        );
+        assert_eq!(
+            quota_response(ACCOUNT_QUOTA_ROOT, &quota, &usage),
+            "* QUOTA \"\" (STORAGE 2 10240)\r\n"
+        );
+        assert_eq!(
+            quota_response("staff", &Quota::default(), &usage),
+            "* QUOTA \"staff\" ()\r\n"
+        );
+
+        assert_eq!(check_append(&quota, &usage, 1024), Ok(()));
+        let full = Quota {
+            storage: 0,
+            messages: 3,
+        };
+        let resource = check_append(&full, &usage, 1024).unwrap_err();
+        assert_eq!(resource, QuotaResource::Message);
+        assert_eq!(
+            over_quota_response("A003", resource),
+            "A003 NO [OVERQUOTA] Mailbox has too many messages\r\n"
+        );
+    }
+}
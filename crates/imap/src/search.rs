@@ -0,0 +1,274 @@
+//! Translates [IMAP SEARCH](https://www.rfc-editor.org/rfc/rfc3501#section-6.4.4) keys into a [SearchQuery]
+//!
+//! Only keys the search index can answer are translated. Flag, size and sequence set keys are
+//! [NotIndexed](SearchParseError::NotIndexed). Literals must already be replaced by quoted strings
+use chrono::NaiveDate;
+use thiserror::Error;
+
+use storages::search::SearchQuery;
+
+/// Charsets accepted after `CHARSET`. Listed in the BADCHARSET response
+pub const CHARSETS: [&str; 2] = ["UTF-8", "US-ASCII"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SearchParseError {
+    #[error("Missing search keys")]
+    UnexpectedEnd,
+    #[error("Unknown search key {0}")]
+    UnknownKey(String),
+    #[error("{0} is not answered by the search index")]
+    NotIndexed(String),
+    #[error("Invalid date {0}")]
+    InvalidDate(String),
+    #[error("Unsupported charset {0}")]
+    BadCharset(String),
+    #[error("Unbalanced parentheses")]
+    UnbalancedParentheses,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Atom(String),
+    Quoted(String),
+    Open,
+    Close,
+}
+
+fn tokenize(criteria: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut characters = criteria.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            ' ' | '\t' | '\r' | '\n' => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut quoted = String::new();
+                while let Some(character) = characters.next() {
+                    match character {
+                        '"' => break,
+                        '\\' => quoted.extend(characters.next()),
+                        _ => quoted.push(character),
+                    }
+                }
+                tokens.push(Token::Quoted(quoted));
+            }
+            _ => {
+                let mut atom = String::from(character);
+                while let Some(&next) = characters.peek() {
+                    if matches!(next, ' ' | '\t' | '\r' | '\n' | '(' | ')' | '"') {
+                        break;
+                    }
+                    atom.push(next);
+                    characters.next();
+                }
+                tokens.push(Token::Atom(atom));
+            }
+        }
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+}
+impl Parser {
+    fn next(&mut self) -> Result<Token, SearchParseError> {
+        self.tokens.next().ok_or(SearchParseError::UnexpectedEnd)
+    }
+    /// An atom or quoted string
+    fn string(&mut self) -> Result<String, SearchParseError> {
+        match self.next()? {
+            Token::Atom(value) | Token::Quoted(value) => Ok(value),
+            Token::Open | Token::Close => Err(SearchParseError::UnbalancedParentheses),
+        }
+    }
+    /// `d-Mon-yyyy` as the unix time the day starts. IMAP dates have no time zone so UTC is used
+    fn date(&mut self) -> Result<i64, SearchParseError> {
+        let date = self.string()?;
+        NaiveDate::parse_from_str(&date, "%d-%b-%Y")
+            .ok()
+            .and_then(|day| day.and_hms_opt(0, 0, 0))
+            .map(|day| day.and_utc().timestamp())
+            .ok_or(SearchParseError::InvalidDate(date))
+    }
+
+    fn key(&mut self) -> Result<SearchQuery, SearchParseError> {
+        let key = match self.next()? {
+            Token::Atom(key) => key,
+            Token::Quoted(key) => return Err(SearchParseError::UnknownKey(key)),
+            Token::Open => return self.group(),
+            Token::Close => return Err(SearchParseError::UnbalancedParentheses),
+        };
+        Ok(match key.to_ascii_uppercase().as_str() {
+            "ALL" => SearchQuery::All,
+            "FROM" => SearchQuery::From(self.string()?),
+            "TO" => SearchQuery::To(self.string()?),
+            "CC" => SearchQuery::Cc(self.string()?),
+            "BCC" => SearchQuery::Bcc(self.string()?),
+            "SUBJECT" => SearchQuery::Subject(self.string()?),
+            "BODY" => SearchQuery::Body(self.string()?),
+            "TEXT" => SearchQuery::Text(self.string()?),
+            "HEADER" => SearchQuery::Header(self.string()?, self.string()?),
+            "BEFORE" => SearchQuery::ReceivedBefore(self.date()?),
+            "ON" => SearchQuery::received_on(self.date()?),
+            "SINCE" => SearchQuery::ReceivedSince(self.date()?),
+            "SENTBEFORE" => SearchQuery::SentBefore(self.date()?),
+            "SENTON" => SearchQuery::sent_on(self.date()?),
+            "SENTSINCE" => SearchQuery::SentSince(self.date()?),
+            "NOT" => SearchQuery::Not(Box::new(self.key()?)),
+            "OR" => SearchQuery::Or(vec![self.key()?, self.key()?]),
+            "ANSWERED" | "DELETED" | "DRAFT" | "FLAGGED" | "NEW" | "OLD" | "RECENT" | "SEEN"
+            | "UNANSWERED" | "UNDELETED" | "UNDRAFT" | "UNFLAGGED" | "UNSEEN" | "KEYWORD"
+            | "UNKEYWORD" | "LARGER" | "SMALLER" | "UID" => {
+                return Err(SearchParseError::NotIndexed(key))
+            }
+            _ if key
+                .starts_with(|character: char| character.is_ascii_digit() || character == '*') =>
+            {
+                return Err(SearchParseError::NotIndexed(key))
+            }
+            _ => return Err(SearchParseError::UnknownKey(key)),
+        })
+    }
+    /// The keys up to the closing parenthesis
+    fn group(&mut self) -> Result<SearchQuery, SearchParseError> {
+        let mut keys = Vec::new();
+        loop {
+            match self.tokens.as_slice().first() {
+                Some(Token::Close) => {
+                    self.tokens.next();
+                    break;
+                }
+                Some(_) => keys.push(self.key()?),
+                None => return Err(SearchParseError::UnbalancedParentheses),
+            }
+        }
+        if keys.is_empty() {
+            return Err(SearchParseError::UnexpectedEnd);
+        }
+        Ok(SearchQuery::and(keys))
+    }
+}
+
+/// The keys of a SEARCH command. An optional `CHARSET` comes first
+pub fn parse_search(criteria: &str) -> Result<SearchQuery, SearchParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(criteria).into_iter(),
+    };
+    let has_charset = matches!(
+        parser.tokens.as_slice().first(),
+        Some(Token::Atom(atom)) if atom.eq_ignore_ascii_case("CHARSET")
+    );
+    if has_charset {
+        parser.tokens.next();
+        let charset = parser.string()?;
+        if !CHARSETS
+            .iter()
+            .any(|supported| supported.eq_ignore_ascii_case(&charset))
+        {
+            return Err(SearchParseError::BadCharset(charset));
+        }
+    }
+    let mut keys = Vec::new();
+    while !parser.tokens.as_slice().is_empty() {
+        keys.push(parser.key()?);
+    }
+    if keys.is_empty() {
+        return Err(SearchParseError::UnexpectedEnd);
+    }
+    Ok(SearchQuery::and(keys))
+}
+
+/// The tagged `NO [BADCHARSET]` response listing the supported charsets
+pub fn bad_charset_response(tag: &str) -> String {
+    format!(
+        "{} NO [BADCHARSET ({})] Unsupported charset\r\n",
+        tag,
+        CHARSETS.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use storages::search::{SearchQuery, DAY};
+
+    use crate::search::{bad_charset_response, parse_search, SearchParseError};
+
+    /// 1994-02-01 00:00:00 UTC
+    const FEB_1: i64 = 760_060_800;
+
+    #[test]
+    pub fn test_parse_search() {
+        assert_eq!(
+            parse_search("FROM \"Alice Smith\" subject report").unwrap(),
+            SearchQuery::And(vec![
+                SearchQuery::From("Alice Smith".to_string()),
+                SearchQuery::Subject("report".to_string()),
+            ])
+        );
+        assert_eq!(
+            parse_search("CHARSET UTF-8 TEXT \"say \\\"hi\\\"\"").unwrap(),
+            SearchQuery::Text("say \"hi\"".to_string())
+        );
+        assert_eq!(
+            parse_search("HEADER X-Priority \"\"").unwrap(),
+            SearchQuery::Header("X-Priority".to_string(), String::new())
+        );
+        assert_eq!(
+            parse_search("OR BODY lunch NOT (TO bob CC carol)").unwrap(),
+            SearchQuery::Or(vec![
+                SearchQuery::Body("lunch".to_string()),
+                SearchQuery::Not(Box::new(SearchQuery::And(vec![
+                    SearchQuery::To("bob".to_string()),
+                    SearchQuery::Cc("carol".to_string()),
+                ]))),
+            ])
+        );
+        assert_eq!(
+            parse_search("SINCE 1-Feb-1994 BEFORE \"3-feb-1994\"").unwrap(),
+            SearchQuery::And(vec![
+                SearchQuery::ReceivedSince(FEB_1),
+                SearchQuery::ReceivedBefore(FEB_1 + 2 * DAY),
+            ])
+        );
+        assert_eq!(
+            parse_search("SENTON 1-Feb-1994").unwrap(),
+            SearchQuery::sent_on(FEB_1)
+        );
+    }
+
+    #[test]
+    pub fn test_parse_search_errors() {
+        assert_eq!(parse_search(""), Err(SearchParseError::UnexpectedEnd));
+        assert_eq!(parse_search("FROM"), Err(SearchParseError::UnexpectedEnd));
+        assert_eq!(
+            parse_search("(FROM alice"),
+            Err(SearchParseError::UnbalancedParentheses)
+        );
+        assert_eq!(
+            parse_search("UNSEEN"),
+            Err(SearchParseError::NotIndexed("UNSEEN".to_string()))
+        );
+        assert_eq!(
+            parse_search("1:*"),
+            Err(SearchParseError::NotIndexed("1:*".to_string()))
+        );
+        assert_eq!(
+            parse_search("FUZZY alice"),
+            Err(SearchParseError::UnknownKey("FUZZY".to_string()))
+        );
+        assert_eq!(
+            parse_search("ON 31-Feb-1994"),
+            Err(SearchParseError::InvalidDate("31-Feb-1994".to_string()))
+        );
+        assert_eq!(
+            parse_search("CHARSET KOI8-R FROM alice"),
+            Err(SearchParseError::BadCharset("KOI8-R".to_string()))
+        );
+        assert_eq!(
+            bad_charset_response("A004"),
+            "A004 NO [BADCHARSET (UTF-8 US-ASCII)] Unsupported charset\r\n"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Builds the RFC 5322 message of an email created with Email/set
+//!
+//! Only the common header properties and a text and/or HTML body are supported. The body values
+//! are referenced by `partId` like [RFC 8621 Section 4.6](https://www.rfc-editor.org/rfc/rfc8621#section-4.6)
+//! describes
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+/// Lines longer than this are sent base64 encoded. RFC 5322 allows 998
+const MAX_LINE_LENGTH: usize = 998;
+
+/// The properties of an email that can be set when creating it. Other than these only
+/// mailboxIds, keywords and receivedAt are accepted
+pub const HEADER_PROPERTIES: [&str; 11] = [
+    "from",
+    "sender",
+    "replyTo",
+    "to",
+    "cc",
+    "bcc",
+    "subject",
+    "sentAt",
+    "messageId",
+    "inReplyTo",
+    "references",
+];
+pub const BODY_PROPERTIES: [&str; 3] = ["textBody", "htmlBody", "bodyValues"];
+
+/// The property that could not be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidProperty(pub &'static str);
+
+/// An encoded word if the text is not plain ASCII
+fn encode_text(text: &str) -> String {
+    if text
+        .chars()
+        .all(|c| c.is_ascii() && (!c.is_ascii_control() || c == '\t'))
+    {
+        text.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", STANDARD.encode(text))
+    }
+}
+
+/// `[{"name": ..., "email": ...}]` as an address list
+fn addresses(property: &'static str, value: &Value) -> Result<String, InvalidProperty> {
+    let addresses = value.as_array().ok_or(InvalidProperty(property))?;
+    let mut list = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let email = address
+            .get("email")
+            .and_then(Value::as_str)
+            .filter(|email| email.contains('@') && !email.contains(['<', '>', ' ', '\r', '\n']))
+            .ok_or(InvalidProperty(property))?;
+        match address.get("name").and_then(Value::as_str) {
+            Some(name) if !name.is_empty() => {
+                let name = encode_text(name);
+                if name.starts_with("=?") {
+                    list.push(format!("{name} <{email}>"));
+                } else {
+                    list.push(format!(
+                        "\"{}\" <{email}>",
+                        name.replace('\\', "\\\\").replace('"', "\\\"")
+                    ));
+                }
+            }
+            _ => list.push(format!("<{email}>")),
+        }
+    }
+    Ok(list.join(", "))
+}
+
+/// Message ids without the angle brackets
+fn message_ids(property: &'static str, value: &Value) -> Result<String, InvalidProperty> {
+    let ids = value.as_array().ok_or(InvalidProperty(property))?;
+    let ids = ids
+        .iter()
+        .map(|id| {
+            id.as_str()
+                .filter(|id| id.contains('@') && !id.contains(['<', '>', ' ', '\r', '\n']))
+                .map(|id| format!("<{id}>"))
+                .ok_or(InvalidProperty(property))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ids.join(" "))
+}
+
+/// The value of the first body part of the property
+fn body_value(
+    create: &Map<String, Value>,
+    property: &'static str,
+) -> Result<Option<String>, InvalidProperty> {
+    let Some(parts) = create.get(property) else {
+        return Ok(None);
+    };
+    let part = match parts.as_array().map(Vec::as_slice) {
+        Some([part]) => part,
+        _ => return Err(InvalidProperty(property)),
+    };
+    let part_id = part
+        .get("partId")
+        .and_then(Value::as_str)
+        .ok_or(InvalidProperty(property))?;
+    let value = create
+        .get("bodyValues")
+        .and_then(|values| values.get(part_id))
+        .and_then(|value| value.get("value"))
+        .and_then(Value::as_str)
+        .ok_or(InvalidProperty("bodyValues"))?;
+    Ok(Some(value.to_string()))
+}
+
+/// A single part with its Content-Type and Content-Transfer-Encoding
+fn body_part(content_type: &str, body: &str) -> String {
+    let body = body.replace("\r\n", "\n").replace('\n', "\r\n");
+    let plain = body.is_ascii() && body.split("\r\n").all(|line| line.len() <= MAX_LINE_LENGTH);
+    if plain {
+        return format!(
+            "Content-Type: {content_type}; charset=utf-8\r\nContent-Transfer-Encoding: 7bit\r\n\r\n{body}\r\n"
+        );
+    }
+    let encoded = STANDARD.encode(body.as_bytes());
+    let lines: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect();
+    format!(
+        "Content-Type: {content_type}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n",
+        lines.join("\r\n")
+    )
+}
+
+/// The raw message. `Message-ID` and `Date` are generated when they are not given
+pub fn compose(
+    create: &Map<String, Value>,
+    now: DateTime<Utc>,
+) -> Result<Vec<u8>, InvalidProperty> {
+    let mut message = String::new();
+    let date = match create.get("sentAt") {
+        Some(sent_at) => sent_at
+            .as_str()
+            .and_then(|sent_at| DateTime::parse_from_rfc3339(sent_at).ok())
+            .ok_or(InvalidProperty("sentAt"))?
+            .to_rfc2822(),
+        None => now.to_rfc2822(),
+    };
+    message.push_str(&format!("Date: {date}\r\n"));
+    for (property, header) in [
+        ("from", "From"),
+        ("sender", "Sender"),
+        ("replyTo", "Reply-To"),
+        ("to", "To"),
+        ("cc", "Cc"),
+        ("bcc", "Bcc"),
+    ] {
+        if let Some(value) = create.get(property).filter(|value| !value.is_null()) {
+            message.push_str(&format!("{header}: {}\r\n", addresses(property, value)?));
+        }
+    }
+    if let Some(subject) = create.get("subject").filter(|value| !value.is_null()) {
+        let subject = subject.as_str().ok_or(InvalidProperty("subject"))?;
+        if subject.contains(['\r', '\n']) {
+            return Err(InvalidProperty("subject"));
+        }
+        message.push_str(&format!("Subject: {}\r\n", encode_text(subject)));
+    }
+    let message_id = match create.get("messageId").filter(|value| !value.is_null()) {
+        Some(message_id) => match message_ids("messageId", message_id)?.as_str() {
+            "" => return Err(InvalidProperty("messageId")),
+            message_id if message_id.contains(' ') => return Err(InvalidProperty("messageId")),
+            message_id => message_id.to_string(),
+        },
+        None => {
+            let domain = create
+                .get("from")
+                .and_then(|from| from.get(0))
+                .and_then(|from| from.get("email"))
+                .and_then(Value::as_str)
+                .and_then(|email| email.rsplit_once('@'))
+                .map_or("localhost", |(_, domain)| domain);
+            format!("<{}@{domain}>", Uuid::new_v4().simple())
+        }
+    };
+    message.push_str(&format!("Message-ID: {message_id}\r\n"));
+    for (property, header) in [("inReplyTo", "In-Reply-To"), ("references", "References")] {
+        if let Some(value) = create.get(property).filter(|value| !value.is_null()) {
+            message.push_str(&format!("{header}: {}\r\n", message_ids(property, value)?));
+        }
+    }
+    message.push_str("MIME-Version: 1.0\r\n");
+
+    let text = body_value(create, "textBody")?;
+    let html = body_value(create, "htmlBody")?;
+    match (text, html) {
+        (Some(text), Some(html)) => {
+            let boundary = format!("=_{}", Uuid::new_v4().simple());
+            message.push_str(&format!(
+                "Content-Type: multipart/alternative; boundary=\"{boundary}\"\r\n\r\n"
+            ));
+            message.push_str(&format!("--{boundary}\r\n"));
+            message.push_str(&body_part("text/plain", &text));
+            message.push_str(&format!("--{boundary}\r\n"));
+            message.push_str(&body_part("text/html", &html));
+            message.push_str(&format!("--{boundary}--\r\n"));
+        }
+        (None, Some(html)) => message.push_str(&body_part("text/html", &html)),
+        (text, None) => message.push_str(&body_part("text/plain", &text.unwrap_or_default())),
+    }
+    Ok(message.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use mail_parser::MessageParser;
+    use serde_json::json;
+
+    use crate::compose::{compose, InvalidProperty};
+
+    #[test]
+    pub fn test_compose() {
+        let now = Utc.timestamp_opt(1700000000, 0).unwrap();
+        let create = json!({
+            "from": [{"name": "Jörg", "email": "joerg@example.com"}],
+            "to": [{"email": "test@example.com"}, {"name": "A \"B\"", "email": "ab@example.com"}],
+            "subject": "Grüße",
+            "inReplyTo": ["1@example.com"],
+            "textBody": [{"partId": "1", "type": "text/plain"}],
+            "htmlBody": [{"partId": "2", "type": "text/html"}],
+            "bodyValues": {"1": {"value": "Hallo\nWelt"}, "2": {"value": "<p>Grüße</p>"}},
+        });
+        let contents = compose(create.as_object().unwrap(), now).unwrap();
+        let message = MessageParser::default().parse(&contents).unwrap();
+        let from = message.from().unwrap().first().unwrap();
+        assert_eq!(from.name(), Some("Jörg"));
+        assert_eq!(from.address(), Some("joerg@example.com"));
+        let to: Vec<_> = message.to().unwrap().iter().collect();
+        assert_eq!(to[1].name(), Some("A \"B\""));
+        assert_eq!(message.subject(), Some("Grüße"));
+        assert_eq!(message.date().unwrap().to_timestamp(), 1700000000);
+        assert!(message.message_id().unwrap().ends_with("@example.com"));
+        assert_eq!(message.in_reply_to().as_text(), Some("1@example.com"));
+        assert_eq!(message.body_text(0).unwrap(), "Hallo\r\nWelt");
+        assert_eq!(message.body_html(0).unwrap(), "<p>Grüße</p>");
+
+        let create = json!({"subject": "Injected\r\nBcc: x@example.com"});
+        assert_eq!(
+            compose(create.as_object().unwrap(), now),
+            Err(InvalidProperty("subject"))
+        );
+        let create = json!({"textBody": [{"partId": "1"}]});
+        assert_eq!(
+            compose(create.as_object().unwrap(), now),
+            Err(InvalidProperty("bodyValues"))
+        );
+    }
+}
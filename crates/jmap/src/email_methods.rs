@@ -0,0 +1,1032 @@
+//! Email/get, Email/query, Email/set, Email/import [RFC 8621 Section 4](https://www.rfc-editor.org/rfc/rfc8621#section-4)
+//! and Thread/get [RFC 8621 Section 3](https://www.rfc-editor.org/rfc/rfc8621#section-3)
+//!
+//! An email is in exactly one mailbox, see [ids](crate::ids). Messages flagged `\Deleted` are
+//! waiting for an IMAP expunge and are not shown
+use ahash::{HashMap, HashSet};
+use chrono::{DateTime, SecondsFormat, Utc};
+use mail_parser::{Address, HeaderValue, Message, MessageParser, MimeHeaders};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use storages::folders::{MessageInfo, NewMessage, StorageError, DELETED_FLAG};
+use storages::search::SearchQuery;
+use storages::storage_type::Storage;
+
+use crate::compose::{compose, InvalidProperty, BODY_PROPERTIES, HEADER_PROPERTIES};
+use crate::email_query::email_filter;
+use crate::ids::{
+    email_id, folder, mailbox_id, parse_email_id, parse_thread_id, thread_id, BlobId,
+};
+use crate::methods::{
+    check_state, folders, parse_arguments, resolve_id, set_error, state, storage_error,
+    storage_result, MethodContext,
+};
+use crate::request::{MethodError, MethodErrorType};
+use crate::uploads::Uploads;
+
+/// IMAP system flags and the keywords of RFC 8621 Section 4.1.1 for them
+const KEYWORDS: [(&str, &str); 4] = [
+    ("\\Seen", "$seen"),
+    ("\\Flagged", "$flagged"),
+    ("\\Answered", "$answered"),
+    ("\\Draft", "$draft"),
+];
+
+/// Properties that are known without reading the message
+const METADATA_PROPERTIES: [&str; 7] = [
+    "id",
+    "blobId",
+    "threadId",
+    "mailboxIds",
+    "keywords",
+    "size",
+    "receivedAt",
+];
+/// Returned when Email/get has no `properties`
+const DEFAULT_PROPERTIES: [&str; 24] = [
+    "id",
+    "blobId",
+    "threadId",
+    "mailboxIds",
+    "keywords",
+    "size",
+    "receivedAt",
+    "messageId",
+    "inReplyTo",
+    "references",
+    "sender",
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "replyTo",
+    "subject",
+    "sentAt",
+    "hasAttachment",
+    "preview",
+    "bodyValues",
+    "textBody",
+    "htmlBody",
+    "attachments",
+];
+const DEFAULT_BODY_PROPERTIES: [&str; 10] = [
+    "partId",
+    "blobId",
+    "size",
+    "name",
+    "type",
+    "charset",
+    "disposition",
+    "cid",
+    "language",
+    "location",
+];
+/// Characters of the preview. Whitespace is collapsed afterwards
+const PREVIEW_LENGTH: usize = 256;
+
+/// The keywords of the flags. `\Deleted` and other system flags have none
+pub(crate) fn keywords(flags: &[String]) -> Map<String, Value> {
+    flags
+        .iter()
+        .filter_map(
+            |flag| match KEYWORDS.iter().find(|(system, _)| system == flag) {
+                Some((_, keyword)) => Some(keyword.to_string()),
+                None if flag.starts_with('\\') => None,
+                None => Some(flag.to_lowercase()),
+            },
+        )
+        .map(|keyword| (keyword, Value::Bool(true)))
+        .collect()
+}
+
+/// The flags for the keywords. System flags without a keyword are kept from `old_flags`.
+/// None if a keyword is not `true` or is not an IMAP atom
+pub(crate) fn flags(keywords: &Map<String, Value>, old_flags: &[String]) -> Option<Vec<String>> {
+    let mut flags: Vec<String> = old_flags
+        .iter()
+        .filter(|flag| flag.starts_with('\\'))
+        .filter(|flag| !KEYWORDS.iter().any(|(system, _)| system == flag))
+        .cloned()
+        .collect();
+    for (keyword, value) in keywords {
+        if value != &Value::Bool(true) {
+            return None;
+        }
+        let keyword = keyword.to_lowercase();
+        match KEYWORDS.iter().find(|(_, known)| *known == keyword) {
+            Some((system, _)) => flags.push(system.to_string()),
+            None => {
+                let atom = !keyword.is_empty()
+                    && keyword
+                        .bytes()
+                        .all(|c| (0x21..0x7f).contains(&c) && !b"(){]%*\"\\".contains(&c));
+                if !atom {
+                    return None;
+                }
+                flags.push(keyword);
+            }
+        }
+    }
+    Some(flags)
+}
+
+/// Unix seconds as a UTCDate
+fn utc_date(seconds: i64) -> Value {
+    DateTime::<Utc>::from_timestamp(seconds, 0)
+        .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .into()
+}
+/// A UTCDate or Date as unix seconds
+fn parse_date(date: &str) -> Option<i64> {
+    DateTime::parse_from_rfc3339(date)
+        .ok()
+        .map(|date| date.timestamp())
+}
+fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+/// The messages of the folder that are not `\Deleted`. A folder that does not exist has none
+async fn messages<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    folder: &str,
+) -> Result<Vec<MessageInfo>, MethodError> {
+    let messages = context
+        .storage
+        .list_messages(context.account.mailbox_id, folder.to_string())
+        .await
+        .map_err(storage_error)?;
+    match messages {
+        Ok(messages) => Ok(messages
+            .into_iter()
+            .filter(|message| !message.is_deleted())
+            .collect()),
+        Err(StorageError::FolderNotFound(_)) => Ok(vec![]),
+        Err(error) => Err(storage_error(error)),
+    }
+}
+
+/// Lists every folder only once per method call
+#[derive(Default)]
+struct MessageCache(HashMap<String, Vec<MessageInfo>>);
+impl MessageCache {
+    async fn find<D, S: Storage>(
+        &mut self,
+        context: &MethodContext<'_, D, S>,
+        folder: &str,
+        uid: u32,
+    ) -> Result<Option<MessageInfo>, MethodError> {
+        if !self.0.contains_key(folder) {
+            let messages = messages(context, folder).await?;
+            self.0.insert(folder.to_string(), messages);
+        }
+        Ok(self.0[folder]
+            .iter()
+            .find(|message| message.uid == uid)
+            .cloned())
+    }
+    /// Changed messages are listed again
+    fn forget(&mut self, folder: &str) {
+        self.0.remove(folder);
+    }
+}
+
+/// The contents of a message. None if it was removed
+async fn read_message<S: Storage>(
+    storage: &S,
+    mailbox_id: Uuid,
+    folder: &str,
+    uid: u32,
+) -> Result<Option<Vec<u8>>, MethodError> {
+    match storage
+        .read_message(mailbox_id, folder.to_string(), uid)
+        .await
+        .map_err(storage_error)?
+    {
+        Ok(contents) => Ok(Some(contents)),
+        Err(StorageError::MessageNotFound(_)) | Err(StorageError::FolderNotFound(_)) => Ok(None),
+        Err(error) => Err(storage_error(error)),
+    }
+}
+
+/// The contents of a blob of the account. None if it does not exist
+pub async fn read_blob<S: Storage>(
+    storage: &S,
+    uploads: &Uploads,
+    mailbox_id: Uuid,
+    blob: &BlobId,
+) -> Result<Option<Vec<u8>>, MethodError> {
+    match blob {
+        BlobId::Upload(id) => Ok(uploads.get(mailbox_id, id).map(|upload| upload.to_vec())),
+        BlobId::Message { folder, uid } => read_message(storage, mailbox_id, folder, *uid).await,
+        BlobId::Part { folder, uid, part } => {
+            let Some(contents) = read_message(storage, mailbox_id, folder, *uid).await? else {
+                return Ok(None);
+            };
+            Ok(MessageParser::default()
+                .parse(&contents)
+                .and_then(|message| message.part(*part).map(|part| part.contents().to_vec())))
+        }
+    }
+}
+
+fn addresses(address: Option<&Address>) -> Value {
+    let Some(address) = address else {
+        return Value::Null;
+    };
+    address
+        .iter()
+        .map(|address| json!({"name": address.name(), "email": address.address()}))
+        .collect()
+}
+fn message_ids(value: &HeaderValue) -> Value {
+    value.as_text_list().map_or(Value::Null, |ids| json!(ids))
+}
+
+/// Which body values Email/get returns
+#[derive(Debug, Clone, Copy, Default)]
+struct BodyValues {
+    text: bool,
+    html: bool,
+    /// Zero is no limit
+    max_bytes: usize,
+}
+
+/// An EmailBodyPart [RFC 8621 Section 4.1.4](https://www.rfc-editor.org/rfc/rfc8621#section-4.1.4)
+fn body_part(
+    folder: &str,
+    uid: u32,
+    message: &Message,
+    part_id: usize,
+    properties: &[String],
+) -> Value {
+    let Some(part) = message.part(part_id) else {
+        return Value::Null;
+    };
+    let content_type = part.content_type();
+    let mime_type = content_type.map_or(
+        "text/plain".to_string(),
+        |content_type| match content_type.subtype() {
+            Some(subtype) => format!("{}/{}", content_type.ctype(), subtype),
+            None => content_type.ctype().to_string(),
+        },
+    );
+    let blob_id = BlobId::Part {
+        folder: folder.to_string(),
+        uid,
+        part: part_id,
+    };
+    let mut body_part = json!({
+        "partId": part_id.to_string(),
+        "blobId": blob_id.to_string(),
+        "size": part.len(),
+        "name": part.attachment_name(),
+        "type": mime_type.to_lowercase(),
+        "charset": content_type.and_then(|content_type| content_type.attribute("charset")),
+        "disposition": part.content_disposition().map(|disposition| disposition.ctype()),
+        "cid": part.content_id(),
+        "language": message_ids(part.content_language()),
+        "location": part.content_location(),
+    });
+    if let Some(body_part) = body_part.as_object_mut() {
+        body_part.retain(|name, _| properties.contains(name));
+    }
+    body_part
+}
+
+/// An EmailBodyValue. Cut at a character boundary when longer than `max_bytes`
+fn body_value(message: &Message, part_id: usize, max_bytes: usize) -> Option<Value> {
+    let part = message.part(part_id)?;
+    let text = part.text_contents().unwrap_or_default();
+    let mut end = text.len();
+    if max_bytes > 0 && end > max_bytes {
+        end = max_bytes;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+    }
+    Some(json!({
+        "value": &text[..end],
+        "isEncodingProblem": part.is_encoding_problem,
+        "isTruncated": end < text.len(),
+    }))
+}
+
+/// The requested properties of an email. `contents` is only needed for those not in
+/// [METADATA_PROPERTIES]
+fn email(
+    folder: &str,
+    info: &MessageInfo,
+    contents: Option<&[u8]>,
+    properties: &[String],
+    body_properties: &[String],
+    body_values: BodyValues,
+) -> Value {
+    let message = contents.and_then(|contents| MessageParser::default().parse(contents));
+    let mut email = Map::new();
+    for property in properties {
+        let value = match (property.as_str(), &message) {
+            ("id", _) => email_id(folder, info.uid).into(),
+            ("blobId", _) => BlobId::Message {
+                folder: folder.to_string(),
+                uid: info.uid,
+            }
+            .to_string()
+            .into(),
+            ("threadId", _) => thread_id(folder, info.uid).into(),
+            ("mailboxIds", _) => json!({ mailbox_id(folder): true }),
+            ("keywords", _) => keywords(&info.flags).into(),
+            ("size", _) => info.size.into(),
+            ("receivedAt", _) => utc_date(info.internal_date),
+            (_, None) => Value::Null,
+            ("messageId", Some(message)) => message
+                .message_id()
+                .map_or(Value::Null, |message_id| json!([message_id])),
+            ("inReplyTo", Some(message)) => message_ids(message.in_reply_to()),
+            ("references", Some(message)) => message_ids(message.references()),
+            ("sender", Some(message)) => addresses(message.sender()),
+            ("from", Some(message)) => addresses(message.from()),
+            ("to", Some(message)) => addresses(message.to()),
+            ("cc", Some(message)) => addresses(message.cc()),
+            ("bcc", Some(message)) => addresses(message.bcc()),
+            ("replyTo", Some(message)) => addresses(message.reply_to()),
+            ("subject", Some(message)) => message.subject().into(),
+            ("sentAt", Some(message)) => message.date().map(|date| date.to_rfc3339()).into(),
+            ("hasAttachment", Some(message)) => (!message.attachments.is_empty()).into(),
+            ("preview", Some(message)) => {
+                let preview = message.body_preview(PREVIEW_LENGTH).unwrap_or_default();
+                let words: Vec<&str> = preview.split_whitespace().collect();
+                words.join(" ").into()
+            }
+            ("textBody" | "htmlBody" | "attachments", Some(message)) => {
+                let parts = match property.as_str() {
+                    "textBody" => &message.text_body,
+                    "htmlBody" => &message.html_body,
+                    _ => &message.attachments,
+                };
+                parts
+                    .iter()
+                    .map(|part| body_part(folder, info.uid, message, *part, body_properties))
+                    .collect()
+            }
+            ("bodyValues", Some(message)) => {
+                let mut parts: Vec<usize> = Vec::new();
+                if body_values.text {
+                    parts.extend(&message.text_body);
+                }
+                if body_values.html {
+                    parts.extend(&message.html_body);
+                }
+                parts
+                    .into_iter()
+                    .filter_map(|part| {
+                        let value = body_value(message, part, body_values.max_bytes)?;
+                        Some((part.to_string(), value))
+                    })
+                    .collect::<Map<String, Value>>()
+                    .into()
+            }
+            _ => continue,
+        };
+        email.insert(property.clone(), value);
+    }
+    email.into()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetArguments {
+    #[serde(default)]
+    ids: Option<Vec<String>>,
+    #[serde(default)]
+    properties: Option<Vec<String>>,
+    #[serde(default)]
+    body_properties: Option<Vec<String>>,
+    #[serde(default)]
+    fetch_text_body_values: bool,
+    #[serde(default, rename = "fetchHTMLBodyValues")]
+    fetch_html_body_values: bool,
+    #[serde(default)]
+    fetch_all_body_values: bool,
+    #[serde(default)]
+    max_body_value_bytes: usize,
+}
+
+pub async fn get<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    arguments: &Value,
+) -> Result<Value, MethodError> {
+    let arguments: GetArguments = parse_arguments(arguments)?;
+    let folders = folders(context).await?;
+    let ids = match arguments.ids {
+        Some(ids) => ids,
+        None => {
+            let mut ids = Vec::new();
+            for folder in &folders {
+                let messages = messages(context, &folder.name).await?;
+                ids.extend(messages.iter().map(|info| email_id(&folder.name, info.uid)));
+            }
+            ids
+        }
+    };
+    if ids.len() as u64 > context.limits.max_objects_in_get {
+        return Err(MethodError::new(MethodErrorType::RequestTooLarge));
+    }
+    let mut properties = arguments
+        .properties
+        .unwrap_or_else(|| DEFAULT_PROPERTIES.map(String::from).to_vec());
+    // The id is always returned
+    if !properties.iter().any(|property| property == "id") {
+        properties.insert(0, "id".to_string());
+    }
+    let body_properties = arguments
+        .body_properties
+        .unwrap_or_else(|| DEFAULT_BODY_PROPERTIES.map(String::from).to_vec());
+    let body_values = BodyValues {
+        text: arguments.fetch_text_body_values || arguments.fetch_all_body_values,
+        html: arguments.fetch_html_body_values || arguments.fetch_all_body_values,
+        max_bytes: arguments.max_body_value_bytes,
+    };
+    let needs_contents = properties
+        .iter()
+        .any(|property| !METADATA_PROPERTIES.contains(&property.as_str()));
+
+    let mut cache = MessageCache::default();
+    let mut list = Vec::with_capacity(ids.len());
+    let mut not_found = Vec::new();
+    for id in ids {
+        let Some((folder, uid)) = parse_email_id(&id) else {
+            not_found.push(id);
+            continue;
+        };
+        let Some(info) = cache.find(context, &folder, uid).await? else {
+            not_found.push(id);
+            continue;
+        };
+        let contents = match needs_contents {
+            true => {
+                let contents =
+                    read_message(context.storage, context.account.mailbox_id, &folder, uid).await?;
+                let Some(contents) = contents else {
+                    not_found.push(id);
+                    continue;
+                };
+                Some(contents)
+            }
+            false => None,
+        };
+        list.push(email(
+            &folder,
+            &info,
+            contents.as_deref(),
+            &properties,
+            &body_properties,
+            body_values,
+        ));
+    }
+    Ok(json!({
+        "accountId": context.account_id(),
+        "state": state(&folders),
+        "list": list,
+        "notFound": not_found,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Comparator {
+    property: String,
+    #[serde(default = "ascending")]
+    is_ascending: bool,
+}
+fn ascending() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryArguments {
+    #[serde(default)]
+    filter: Option<Value>,
+    #[serde(default)]
+    sort: Option<Vec<Comparator>>,
+    #[serde(default)]
+    position: i64,
+    #[serde(default)]
+    anchor: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    calculate_total: bool,
+}
+
+/// Filters with [email_filter] and the search of the storage. Only `receivedAt` can be sorted by.
+/// Emails with the same date are in the order they were stored
+pub async fn query<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    arguments: &Value,
+) -> Result<Value, MethodError> {
+    let arguments: QueryArguments = parse_arguments(arguments)?;
+    let filter = email_filter(arguments.filter.as_ref())?;
+    if arguments.anchor.is_some() {
+        return Err(MethodError::with_description(
+            MethodErrorType::InvalidArguments,
+            "anchor is not supported",
+        ));
+    }
+    let mut is_ascending = true;
+    for comparator in arguments.sort.unwrap_or_default() {
+        if comparator.property != "receivedAt" {
+            return Err(MethodError::with_description(
+                MethodErrorType::UnsupportedSort,
+                format!("Emails can not be sorted by {}", comparator.property),
+            ));
+        }
+        is_ascending = comparator.is_ascending;
+    }
+
+    let folders = folders(context).await?;
+    let searched: Vec<&str> = match &filter.in_mailbox {
+        Some(mailbox_id) => {
+            let name = folder(mailbox_id);
+            folders
+                .iter()
+                .filter(|folder| Some(&folder.name) == name.as_ref())
+                .map(|folder| folder.name.as_str())
+                .collect()
+        }
+        None => folders.iter().map(|folder| folder.name.as_str()).collect(),
+    };
+    let mut found = Vec::new();
+    for folder in searched {
+        let mut messages = messages(context, folder).await?;
+        if filter.query != SearchQuery::All {
+            let uids: HashSet<u32> = storage_result(
+                context
+                    .storage
+                    .search(
+                        context.account.mailbox_id,
+                        folder.to_string(),
+                        filter.query.clone(),
+                    )
+                    .await,
+            )?
+            .into_iter()
+            .collect();
+            messages.retain(|message| uids.contains(&message.uid));
+        }
+        found.extend(
+            messages
+                .into_iter()
+                .map(|message| (message.internal_date, email_id(folder, message.uid))),
+        );
+    }
+    found.sort_by_key(|(date, _)| *date);
+    if !is_ascending {
+        found.reverse();
+    }
+
+    let total = found.len();
+    let position = match arguments.position {
+        position if position < 0 => total.saturating_sub(position.unsigned_abs() as usize),
+        position => (position as usize).min(total),
+    };
+    let ids: Vec<String> = found
+        .into_iter()
+        .skip(position)
+        .take(arguments.limit.unwrap_or(usize::MAX))
+        .map(|(_, id)| id)
+        .collect();
+    let mut response = json!({
+        "accountId": context.account_id(),
+        "queryState": state(&folders),
+        "canCalculateChanges": false,
+        "position": position,
+        "ids": ids,
+    });
+    if arguments.calculate_total {
+        response["total"] = total.into();
+    }
+    if let Some(limit) = arguments.limit {
+        response["limit"] = limit.into();
+    }
+    Ok(response)
+}
+
+/// The folder of `mailboxIds` when it has exactly one mailbox
+fn single_folder(
+    mailbox_ids: Option<&Value>,
+    created_ids: &HashMap<String, String>,
+) -> Option<String> {
+    let mailbox_ids = mailbox_ids?.as_object()?;
+    let [(mailbox_id, value)] = mailbox_ids.iter().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    if value != &Value::Bool(true) {
+        return None;
+    }
+    folder(&resolve_id(mailbox_id, created_ids)?)
+}
+
+fn invalid_properties(properties: &[&str], description: impl Into<String>) -> Value {
+    let mut error = set_error("invalidProperties", description);
+    error["properties"] = json!(properties);
+    error
+}
+
+/// Stores a new message and returns what Email/set and Email/import return for it
+async fn store<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    folder: String,
+    message: NewMessage,
+) -> Result<Result<Value, Value>, MethodError> {
+    let stored = context
+        .storage
+        .append_message(context.account.mailbox_id, folder.clone(), message)
+        .await
+        .map_err(storage_error)?;
+    match stored {
+        Ok(info) => Ok(Ok(json!({
+            "id": email_id(&folder, info.uid),
+            "blobId": BlobId::Message { folder: folder.clone(), uid: info.uid }.to_string(),
+            "threadId": thread_id(&folder, info.uid),
+            "size": info.size,
+        }))),
+        Err(StorageError::FolderNotFound(_)) => {
+            Ok(Err(invalid_properties(&["mailboxIds"], "No such mailbox")))
+        }
+        Err(error) => Err(storage_error(error)),
+    }
+}
+
+/// The flags and date of a created or imported email
+fn new_message(
+    keywords: Option<&Value>,
+    received_at: Option<&Value>,
+    contents: Vec<u8>,
+) -> Result<NewMessage, Value> {
+    let flags = match keywords {
+        Some(keywords) => keywords
+            .as_object()
+            .and_then(|keywords| flags(keywords, &[]))
+            .ok_or_else(|| invalid_properties(&["keywords"], "Invalid keywords"))?,
+        None => vec![],
+    };
+    let internal_date = match received_at {
+        Some(received_at) => received_at
+            .as_str()
+            .and_then(parse_date)
+            .ok_or_else(|| invalid_properties(&["receivedAt"], "Not a UTCDate"))?,
+        None => now(),
+    };
+    Ok(NewMessage {
+        flags,
+        internal_date,
+        contents,
+    })
+}
+
+/// The flags after applying a PatchObject. Only keywords can be changed
+fn patch_flags(old_flags: &[String], patch: &Map<String, Value>) -> Result<Vec<String>, Value> {
+    let mut keywords = keywords(old_flags);
+    for (path, value) in patch {
+        if path == "keywords" {
+            keywords = value
+                .as_object()
+                .cloned()
+                .ok_or_else(|| invalid_properties(&["keywords"], "Invalid keywords"))?;
+        } else if let Some(keyword) = path.strip_prefix("keywords/") {
+            let keyword = keyword.replace("~1", "/").replace("~0", "~");
+            match value {
+                Value::Bool(true) => {
+                    keywords.insert(keyword, Value::Bool(true));
+                }
+                Value::Null => {
+                    keywords.remove(&keyword.to_lowercase());
+                }
+                _ => return Err(invalid_properties(&[path], "Keywords can only be true")),
+            }
+        } else if path == "mailboxIds" || path.starts_with("mailboxIds/") {
+            return Err(invalid_properties(
+                &[path],
+                "Emails can not be moved to other mailboxes",
+            ));
+        } else {
+            return Err(invalid_properties(&[path], "Only keywords can be changed"));
+        }
+    }
+    flags(&keywords, old_flags).ok_or_else(|| invalid_properties(&["keywords"], "Invalid keywords"))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetArguments {
+    #[serde(default)]
+    if_in_state: Option<String>,
+    #[serde(default)]
+    create: Option<HashMap<String, Map<String, Value>>>,
+    #[serde(default)]
+    update: Option<HashMap<String, Map<String, Value>>>,
+    #[serde(default)]
+    destroy: Option<Vec<String>>,
+}
+
+/// Destroying expunges the folders of the emails. Other messages flagged `\Deleted` in them, which
+/// are already hidden, are expunged too
+pub async fn set<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    arguments: &Value,
+    created_ids: &mut HashMap<String, String>,
+) -> Result<Value, MethodError> {
+    let arguments: SetArguments = parse_arguments(arguments)?;
+    let old_state = state(&folders(context).await?);
+    check_state(arguments.if_in_state.as_deref(), &old_state)?;
+    let objects = arguments.create.as_ref().map_or(0, |create| create.len())
+        + arguments.update.as_ref().map_or(0, |update| update.len())
+        + arguments
+            .destroy
+            .as_ref()
+            .map_or(0, |destroy| destroy.len());
+    if objects as u64 > context.limits.max_objects_in_set {
+        return Err(MethodError::new(MethodErrorType::RequestTooLarge));
+    }
+    let account_mailbox = context.account.mailbox_id;
+
+    let mut created = Map::new();
+    let mut not_created = Map::new();
+    for (creation_id, create) in arguments.create.unwrap_or_default() {
+        let unknown: Vec<&str> = create
+            .keys()
+            .map(String::as_str)
+            .filter(|property| {
+                !HEADER_PROPERTIES.contains(property)
+                    && !BODY_PROPERTIES.contains(property)
+                    && !["mailboxIds", "keywords", "receivedAt"].contains(property)
+            })
+            .collect();
+        if !unknown.is_empty() {
+            let error = invalid_properties(&unknown, "These properties can not be set");
+            not_created.insert(creation_id, error);
+            continue;
+        }
+        let Some(folder) = single_folder(create.get("mailboxIds"), created_ids) else {
+            let error = invalid_properties(&["mailboxIds"], "An email is in exactly one mailbox");
+            not_created.insert(creation_id, error);
+            continue;
+        };
+        let contents = match compose(&create, Utc::now()) {
+            Ok(contents) => contents,
+            Err(InvalidProperty(property)) => {
+                let error = invalid_properties(&[property], format!("Invalid {property}"));
+                not_created.insert(creation_id, error);
+                continue;
+            }
+        };
+        let message = match new_message(create.get("keywords"), create.get("receivedAt"), contents)
+        {
+            Ok(message) => message,
+            Err(error) => {
+                not_created.insert(creation_id, error);
+                continue;
+            }
+        };
+        match store(context, folder, message).await? {
+            Ok(email) => {
+                created_ids.insert(
+                    creation_id.clone(),
+                    email["id"].as_str().unwrap_or_default().to_string(),
+                );
+                created.insert(creation_id, email);
+            }
+            Err(error) => {
+                not_created.insert(creation_id, error);
+            }
+        }
+    }
+
+    let mut cache = MessageCache::default();
+    let mut updated = Map::new();
+    let mut not_updated = Map::new();
+    for (id, patch) in arguments.update.unwrap_or_default() {
+        let found = match resolve_id(&id, created_ids)
+            .as_deref()
+            .and_then(parse_email_id)
+        {
+            Some((folder, uid)) => cache
+                .find(context, &folder, uid)
+                .await?
+                .map(|info| (folder, info)),
+            None => None,
+        };
+        let Some((folder, info)) = found else {
+            not_updated.insert(id, set_error("notFound", "No such email"));
+            continue;
+        };
+        let flags = match patch_flags(&info.flags, &patch) {
+            Ok(flags) => flags,
+            Err(error) => {
+                not_updated.insert(id, error);
+                continue;
+            }
+        };
+        let set = context
+            .storage
+            .set_flags(account_mailbox, folder.clone(), info.uid, flags)
+            .await
+            .map_err(storage_error)?;
+        match set {
+            Ok(_) => {
+                updated.insert(id, Value::Null);
+            }
+            Err(StorageError::MessageNotFound(_)) | Err(StorageError::FolderNotFound(_)) => {
+                not_updated.insert(id, set_error("notFound", "No such email"));
+            }
+            Err(error) => return Err(storage_error(error)),
+        }
+        cache.forget(&folder);
+    }
+
+    let mut destroyed = Vec::new();
+    let mut not_destroyed = Map::new();
+    let mut expunge = HashSet::default();
+    for id in arguments.destroy.unwrap_or_default() {
+        let found = match resolve_id(&id, created_ids)
+            .as_deref()
+            .and_then(parse_email_id)
+        {
+            Some((folder, uid)) => cache
+                .find(context, &folder, uid)
+                .await?
+                .map(|info| (folder, info)),
+            None => None,
+        };
+        let Some((folder, info)) = found else {
+            not_destroyed.insert(id, set_error("notFound", "No such email"));
+            continue;
+        };
+        let mut flags = info.flags.clone();
+        flags.push(DELETED_FLAG.to_string());
+        let set = context
+            .storage
+            .set_flags(account_mailbox, folder.clone(), info.uid, flags)
+            .await
+            .map_err(storage_error)?;
+        match set {
+            Ok(_) => {
+                destroyed.push(id);
+                expunge.insert(folder);
+            }
+            Err(StorageError::MessageNotFound(_)) | Err(StorageError::FolderNotFound(_)) => {
+                not_destroyed.insert(id, set_error("notFound", "No such email"));
+            }
+            Err(error) => return Err(storage_error(error)),
+        }
+    }
+    for folder in expunge {
+        storage_result(context.storage.expunge(account_mailbox, folder).await)?;
+    }
+
+    Ok(json!({
+        "accountId": context.account_id(),
+        "oldState": old_state,
+        "newState": state(&folders(context).await?),
+        "created": created,
+        "notCreated": not_created,
+        "updated": updated,
+        "notUpdated": not_updated,
+        "destroyed": destroyed,
+        "notDestroyed": not_destroyed,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmailImport {
+    blob_id: String,
+    mailbox_ids: Value,
+    #[serde(default)]
+    keywords: Option<Value>,
+    #[serde(default)]
+    received_at: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportArguments {
+    #[serde(default)]
+    if_in_state: Option<String>,
+    emails: HashMap<String, EmailImport>,
+}
+
+/// Imports uploaded blobs or copies stored messages
+pub async fn import<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    arguments: &Value,
+    created_ids: &mut HashMap<String, String>,
+) -> Result<Value, MethodError> {
+    let arguments: ImportArguments = parse_arguments(arguments)?;
+    let old_state = state(&folders(context).await?);
+    check_state(arguments.if_in_state.as_deref(), &old_state)?;
+    if arguments.emails.len() as u64 > context.limits.max_objects_in_set {
+        return Err(MethodError::new(MethodErrorType::RequestTooLarge));
+    }
+
+    let mut created = Map::new();
+    let mut not_created = Map::new();
+    for (creation_id, import) in arguments.emails {
+        let contents = match BlobId::parse(&import.blob_id) {
+            Some(blob) => {
+                read_blob(
+                    context.storage,
+                    context.uploads,
+                    context.account.mailbox_id,
+                    &blob,
+                )
+                .await?
+            }
+            None => None,
+        };
+        let Some(contents) = contents else {
+            let mut error = set_error("blobNotFound", "No such blob");
+            error["notFound"] = json!([import.blob_id]);
+            not_created.insert(creation_id, error);
+            continue;
+        };
+        let Some(folder) = single_folder(Some(&import.mailbox_ids), created_ids) else {
+            let error = invalid_properties(&["mailboxIds"], "An email is in exactly one mailbox");
+            not_created.insert(creation_id, error);
+            continue;
+        };
+        let message = match new_message(
+            import.keywords.as_ref(),
+            import.received_at.as_ref(),
+            contents,
+        ) {
+            Ok(message) => message,
+            Err(error) => {
+                not_created.insert(creation_id, error);
+                continue;
+            }
+        };
+        match store(context, folder, message).await? {
+            Ok(email) => {
+                created_ids.insert(
+                    creation_id.clone(),
+                    email["id"].as_str().unwrap_or_default().to_string(),
+                );
+                created.insert(creation_id, email);
+            }
+            Err(error) => {
+                not_created.insert(creation_id, error);
+            }
+        }
+    }
+    Ok(json!({
+        "accountId": context.account_id(),
+        "oldState": old_state,
+        "newState": state(&folders(context).await?),
+        "created": created,
+        "notCreated": not_created,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ThreadArguments {
+    ids: Vec<String>,
+}
+
+/// Every email is its own thread, see [thread_id]
+pub async fn thread_get<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    arguments: &Value,
+) -> Result<Value, MethodError> {
+    let arguments: ThreadArguments = parse_arguments(arguments)?;
+    if arguments.ids.len() as u64 > context.limits.max_objects_in_get {
+        return Err(MethodError::new(MethodErrorType::RequestTooLarge));
+    }
+    let folders = folders(context).await?;
+    let mut cache = MessageCache::default();
+    let mut list = Vec::new();
+    let mut not_found = Vec::new();
+    for id in arguments.ids {
+        let found = match parse_thread_id(&id) {
+            Some((folder, uid)) => cache
+                .find(context, &folder, uid)
+                .await?
+                .map(|_| email_id(&folder, uid)),
+            None => None,
+        };
+        match found {
+            Some(email_id) => list.push(json!({"id": id, "emailIds": [email_id]})),
+            None => not_found.push(id),
+        }
+    }
+    Ok(json!({
+        "accountId": context.account_id(),
+        "state": state(&folders),
+        "list": list,
+        "notFound": not_found,
+    }))
+}
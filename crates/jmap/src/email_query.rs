@@ -0,0 +1,177 @@
+//! Translates the filter of [Email/query](https://www.rfc-editor.org/rfc/rfc8621#section-4.4) into a [SearchQuery]
+//!
+//! The search index searches one folder, so `inMailbox` is only accepted in the top level condition
+use chrono::DateTime;
+use serde_json::{Map, Value};
+
+use storages::search::SearchQuery;
+
+use crate::request::{MethodError, MethodErrorType};
+
+/// A translated filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailFilter {
+    /// The id of the mailbox to search. None for every mailbox of the account
+    pub in_mailbox: Option<String>,
+    pub query: SearchQuery,
+}
+
+fn unsupported(description: impl Into<String>) -> MethodError {
+    MethodError::with_description(MethodErrorType::UnsupportedFilter, description)
+}
+
+fn string<'a>(name: &str, value: &'a Value) -> Result<&'a str, MethodError> {
+    value
+        .as_str()
+        .ok_or_else(|| unsupported(format!("{} must be a string", name)))
+}
+
+/// A UTCDate as unix seconds
+fn date(name: &str, value: &Value) -> Result<i64, MethodError> {
+    let date = string(name, value)?;
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.timestamp())
+        .map_err(|_| unsupported(format!("{} is not a UTCDate", name)))
+}
+
+/// A FilterOperator or FilterCondition
+fn filter(filter: &Value) -> Result<SearchQuery, MethodError> {
+    let Some(filter) = filter.as_object() else {
+        return Err(unsupported("Filters must be objects"));
+    };
+    if let Some(operator) = filter.get("operator") {
+        let conditions = filter
+            .get("conditions")
+            .and_then(Value::as_array)
+            .ok_or_else(|| unsupported("FilterOperator without conditions"))?
+            .iter()
+            .map(self::filter)
+            .collect::<Result<Vec<_>, _>>()?;
+        return match operator.as_str() {
+            Some("AND") => Ok(SearchQuery::And(conditions)),
+            Some("OR") => Ok(SearchQuery::Or(conditions)),
+            Some("NOT") => Ok(SearchQuery::Not(Box::new(SearchQuery::Or(conditions)))),
+            _ => Err(unsupported("Unknown operator")),
+        };
+    }
+    condition(filter)
+}
+
+/// Every property of the condition must match
+fn condition(condition: &Map<String, Value>) -> Result<SearchQuery, MethodError> {
+    let mut queries = Vec::with_capacity(condition.len());
+    for (name, value) in condition {
+        let query = match name.as_str() {
+            "text" => SearchQuery::Text(string(name, value)?.to_string()),
+            "from" => SearchQuery::From(string(name, value)?.to_string()),
+            "to" => SearchQuery::To(string(name, value)?.to_string()),
+            "cc" => SearchQuery::Cc(string(name, value)?.to_string()),
+            "bcc" => SearchQuery::Bcc(string(name, value)?.to_string()),
+            "subject" => SearchQuery::Subject(string(name, value)?.to_string()),
+            "body" => SearchQuery::Body(string(name, value)?.to_string()),
+            "before" => SearchQuery::ReceivedBefore(date(name, value)?),
+            "after" => SearchQuery::ReceivedSince(date(name, value)?),
+            "header" => {
+                let header: Vec<&str> = value
+                    .as_array()
+                    .map(|header| header.iter().filter_map(Value::as_str).collect())
+                    .unwrap_or_default();
+                match header.as_slice() {
+                    [name] => SearchQuery::Header(name.to_string(), String::new()),
+                    [name, value] => SearchQuery::Header(name.to_string(), value.to_string()),
+                    _ => return Err(unsupported("header must be one or two strings")),
+                }
+            }
+            _ => return Err(unsupported(format!("{} is not supported", name))),
+        };
+        queries.push(query);
+    }
+    Ok(SearchQuery::and(queries))
+}
+
+/// The `filter` argument of Email/query. No filter matches every email
+pub fn email_filter(argument: Option<&Value>) -> Result<EmailFilter, MethodError> {
+    let Some(argument) = argument.filter(|argument| !argument.is_null()) else {
+        return Ok(EmailFilter {
+            in_mailbox: None,
+            query: SearchQuery::All,
+        });
+    };
+    let mut top = argument.clone();
+    let in_mailbox = match top.as_object_mut().and_then(|top| top.remove("inMailbox")) {
+        Some(in_mailbox) => Some(string("inMailbox", &in_mailbox)?.to_string()),
+        None => None,
+    };
+    let query = match top.as_object() {
+        Some(condition) if condition.is_empty() => SearchQuery::All,
+        _ => filter(&top)?,
+    };
+    Ok(EmailFilter { in_mailbox, query })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use storages::search::SearchQuery;
+
+    use crate::email_query::{email_filter, EmailFilter};
+    use crate::request::MethodErrorType;
+
+    #[test]
+    pub fn test_email_filter() {
+        assert_eq!(
+            email_filter(None).unwrap(),
+            EmailFilter {
+                in_mailbox: None,
+                query: SearchQuery::All
+            }
+        );
+        assert_eq!(
+            email_filter(Some(&json!({"inMailbox": "INBOX"}))).unwrap(),
+            EmailFilter {
+                in_mailbox: Some("INBOX".to_string()),
+                query: SearchQuery::All
+            }
+        );
+        let filter = json!({
+            "inMailbox": "INBOX",
+            "from": "alice",
+            "after": "2023-11-14T00:00:00Z",
+        });
+        assert_eq!(
+            email_filter(Some(&filter)).unwrap(),
+            EmailFilter {
+                in_mailbox: Some("INBOX".to_string()),
+                query: SearchQuery::And(vec![
+                    SearchQuery::ReceivedSince(1_699_920_000),
+                    SearchQuery::From("alice".to_string()),
+                ])
+            }
+        );
+        let filter = json!({
+            "operator": "NOT",
+            "conditions": [{"subject": "report"}, {"header": ["X-Priority", "urgent"]}],
+        });
+        assert_eq!(
+            email_filter(Some(&filter)).unwrap().query,
+            SearchQuery::Not(Box::new(SearchQuery::Or(vec![
+                SearchQuery::Subject("report".to_string()),
+                SearchQuery::Header("X-Priority".to_string(), "urgent".to_string()),
+            ])))
+        );
+    }
+    #[test]
+    pub fn test_unsupported_filter() {
+        for filter in [
+            json!({"hasKeyword": "$seen"}),
+            json!({"operator": "AND", "conditions": [{"inMailbox": "INBOX"}]}),
+            json!({"before": "yesterday"}),
+            json!({"operator": "XOR", "conditions": []}),
+            json!({"header": []}),
+        ] {
+            let error = email_filter(Some(&filter)).unwrap_err();
+            assert_eq!(error.error_type, MethodErrorType::UnsupportedFilter);
+        }
+    }
+}
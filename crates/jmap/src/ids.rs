@@ -0,0 +1,121 @@
+//! The JMAP ids of stored objects
+//!
+//! A JMAP mailbox is a storage folder and an email is a message in it. Ids are made of the folder
+//! name and UID, so the storage needs no table of ids. An email that moves to another folder is a
+//! new email with a new id
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+/// The folder name in unpadded base64url
+pub fn mailbox_id(folder: &str) -> String {
+    URL_SAFE_NO_PAD.encode(folder)
+}
+pub fn folder(mailbox_id: &str) -> Option<String> {
+    let folder = URL_SAFE_NO_PAD.decode(mailbox_id).ok()?;
+    String::from_utf8(folder).ok()
+}
+
+/// `<prefix><uid>-<mailbox id>`
+fn message_id(prefix: char, folder: &str, uid: u32) -> String {
+    format!("{prefix}{uid}-{}", mailbox_id(folder))
+}
+fn parse_message_id(prefix: char, id: &str) -> Option<(String, u32)> {
+    let (uid, mailbox) = id.strip_prefix(prefix)?.split_once('-')?;
+    Some((folder(mailbox)?, uid.parse().ok()?))
+}
+
+pub fn email_id(folder: &str, uid: u32) -> String {
+    message_id('e', folder, uid)
+}
+/// The folder and UID of the email
+pub fn parse_email_id(id: &str) -> Option<(String, u32)> {
+    parse_message_id('e', id)
+}
+
+/// Threads are not computed. Every email is the only email of its thread
+pub fn thread_id(folder: &str, uid: u32) -> String {
+    message_id('t', folder, uid)
+}
+pub fn parse_thread_id(id: &str) -> Option<(String, u32)> {
+    parse_message_id('t', id)
+}
+
+/// A blob of a stored message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlobId {
+    /// The raw message
+    Message { folder: String, uid: u32 },
+    /// The decoded contents of a part. The index is the position in the parsed message
+    Part {
+        folder: String,
+        uid: u32,
+        part: usize,
+    },
+    /// Uploaded and not stored yet. See [Uploads](crate::uploads::Uploads)
+    Upload(String),
+}
+impl BlobId {
+    pub fn parse(id: &str) -> Option<Self> {
+        if let Some(upload) = id.strip_prefix('u') {
+            return Some(BlobId::Upload(upload.to_string()));
+        }
+        if let Some((folder, uid)) = parse_message_id('b', id) {
+            return Some(BlobId::Message { folder, uid });
+        }
+        let (part, message) = id.strip_prefix('p')?.split_once('_')?;
+        let (folder, uid) = parse_message_id('m', message)?;
+        Some(BlobId::Part {
+            folder,
+            uid,
+            part: part.parse().ok()?,
+        })
+    }
+}
+impl std::fmt::Display for BlobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlobId::Message { folder, uid } => write!(f, "{}", message_id('b', folder, *uid)),
+            BlobId::Part { folder, uid, part } => {
+                write!(f, "p{part}_{}", message_id('m', folder, *uid))
+            }
+            BlobId::Upload(id) => write!(f, "u{id}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ids::{email_id, folder, mailbox_id, parse_email_id, parse_thread_id, BlobId};
+
+    #[test]
+    pub fn test_ids() {
+        for name in ["INBOX", "Archive.2023", "Entwürfe", "a-b_c"] {
+            let id = mailbox_id(name);
+            assert!(id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+            assert_eq!(folder(&id).as_deref(), Some(name));
+
+            let email = email_id(name, 42);
+            assert_eq!(parse_email_id(&email), Some((name.to_string(), 42)));
+            assert_eq!(parse_thread_id(&email), None);
+
+            for blob in [
+                BlobId::Message {
+                    folder: name.to_string(),
+                    uid: 7,
+                },
+                BlobId::Part {
+                    folder: name.to_string(),
+                    uid: 7,
+                    part: 3,
+                },
+                BlobId::Upload("0123abcd".to_string()),
+            ] {
+                assert_eq!(BlobId::parse(&blob.to_string()), Some(blob));
+            }
+        }
+        assert_eq!(parse_email_id("eX-SU5CT1g"), None);
+        assert_eq!(BlobId::parse("x1-SU5CT1g"), None);
+    }
+}
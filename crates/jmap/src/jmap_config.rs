@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use utils::configs::{Config, ConfigName};
+
+use helper_macros::const_and_default_function;
+
+const_and_default_function!(DEFAULT_MAX_SIZE_UPLOAD: u64 = 50_000_000);
+const_and_default_function!(DEFAULT_MAX_CONCURRENT_UPLOAD: u64 = 4);
+const_and_default_function!(DEFAULT_MAX_SIZE_REQUEST: u64 = 10_000_000);
+const_and_default_function!(DEFAULT_MAX_CONCURRENT_REQUESTS: u64 = 4);
+const_and_default_function!(DEFAULT_MAX_CALLS_IN_REQUEST: u64 = 16);
+const_and_default_function!(DEFAULT_MAX_OBJECTS_IN_GET: u64 = 500);
+const_and_default_function!(DEFAULT_MAX_OBJECTS_IN_SET: u64 = 500);
+
+/// Limits advertised in the `urn:ietf:params:jmap:core` capability
+///
+/// # Example
+/// ```toml
+/// [limits]
+/// max_size_upload = 50000000
+/// max_calls_in_request = 16
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct JMAPLimits {
+    #[serde(default = "default_max_size_upload")]
+    pub max_size_upload: u64,
+    #[serde(default = "default_max_concurrent_upload")]
+    pub max_concurrent_upload: u64,
+    #[serde(default = "default_max_size_request")]
+    pub max_size_request: u64,
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: u64,
+    #[serde(default = "default_max_calls_in_request")]
+    pub max_calls_in_request: u64,
+    #[serde(default = "default_max_objects_in_get")]
+    pub max_objects_in_get: u64,
+    #[serde(default = "default_max_objects_in_set")]
+    pub max_objects_in_set: u64,
+}
+impl Default for JMAPLimits {
+    fn default() -> Self {
+        JMAPLimits {
+            max_size_upload: default_max_size_upload(),
+            max_concurrent_upload: default_max_concurrent_upload(),
+            max_size_request: default_max_size_request(),
+            max_concurrent_requests: default_max_concurrent_requests(),
+            max_calls_in_request: default_max_calls_in_request(),
+            max_objects_in_get: default_max_objects_in_get(),
+            max_objects_in_set: default_max_objects_in_set(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct JMAPConfig {
+    /// The address the HTTP server binds to
+    pub bind: String,
+    /// The public URL clients use to reach this server. Used to build the URLs in the Session
+    pub base_url: String,
+    #[serde(default)]
+    pub limits: JMAPLimits,
+}
+impl Default for JMAPConfig {
+    fn default() -> Self {
+        JMAPConfig {
+            bind: "0.0.0.0:8080".to_string(),
+            base_url: "http://localhost:8080".to_string(),
+            limits: JMAPLimits::default(),
+        }
+    }
+}
+impl Config for JMAPConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/jmap")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("jmap.toml")
+    }
+}
@@ -0,0 +1,589 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::io;
+use std::net::{AddrParseError, IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::body::HttpBody;
+use hyper::header::{
+    CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER, WWW_AUTHENTICATE,
+};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use serde_json::json;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use directories::directory_type::Directory;
+use storages::storage_type::Storage;
+use utils::account::Account;
+use utils::app_password::LoginProtocol;
+use utils::auth_failures::Lockout;
+use utils::configs::domain_configs::DomainConfiguration;
+use utils::configs::{Config, IOOrToml};
+use utils::service::ServiceAccess;
+
+use crate::authentication::BasicCredentials;
+use crate::email_methods::read_blob;
+use crate::ids::BlobId;
+use crate::jmap_config::JMAPConfig;
+use crate::methods::{call, route, MethodContext, Route};
+use crate::request::{
+    self, resolve_references, Invocation, MethodError, MethodErrorType, RequestError,
+    SUPPORTED_CAPABILITIES,
+};
+use crate::session::Session;
+use crate::uploads::Uploads;
+
+pub type Configs = (JMAPConfig, DomainConfiguration);
+
+#[derive(Debug, Error)]
+pub enum JMAPServiceError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Config(#[from] IOOrToml),
+    #[error("Invalid bind address: {0}")]
+    InvalidBind(#[from] AddrParseError),
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error(transparent)]
+    GettingDirectoryAccess(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    Directory(Box<dyn Error + Send + Sync + 'static>),
+}
+
+/// Why a request body was not read
+enum BodyError {
+    TooLarge,
+    Read(hyper::Error),
+}
+
+/// Reads the body unless it is larger than `limit`. A larger Content-Length is rejected before
+/// anything is read
+async fn read_body(request: Request<Body>, limit: u64) -> Result<Vec<u8>, BodyError> {
+    let length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    if length.is_some_and(|length| length > limit) {
+        return Err(BodyError::TooLarge);
+    }
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(BodyError::Read)?;
+        if (bytes.len() + chunk.len()) as u64 > limit {
+            return Err(BodyError::TooLarge);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Decodes `%XX`. Invalid escapes are kept
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| value.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Encodes everything but unreserved characters, for `filename*`
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// `type/subtype` made of token characters. Parameters are not accepted
+fn is_media_type(value: &str) -> bool {
+    let token = |token: &str| {
+        !token.is_empty()
+            && token
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&c))
+    };
+    matches!(value.split_once('/'), Some((main, sub)) if token(main) && token(sub))
+}
+
+enum Authentication {
+    Authenticated(Account),
+    /// Missing or invalid credentials
+    Failed,
+    LockedOut(Lockout),
+}
+
+pub struct JMAPServiceInner<DirectoryAccess: ServiceAccess, StorageAccess: ServiceAccess>
+where
+    DirectoryAccess::ServiceResponse: Directory,
+    StorageAccess::ServiceResponse: Storage,
+{
+    pub config: JMAPConfig,
+    pub domain_config: DomainConfiguration,
+    /// Blobs uploaded for Email/import
+    pub uploads: Uploads,
+    pub directory_service_access: DirectoryAccess,
+    pub storage_service_access: StorageAccess,
+}
+pub type JMAPServiceAccess<DirectoryAccess, StorageAccess> =
+    Arc<JMAPServiceInner<DirectoryAccess, StorageAccess>>;
+
+pub struct JMAPService<DirectoryAccess: ServiceAccess, StorageAccess: ServiceAccess>
+where
+    DirectoryAccess::ServiceResponse: Directory,
+    StorageAccess::ServiceResponse: Storage,
+{
+    pub inner: JMAPServiceAccess<DirectoryAccess, StorageAccess>,
+    handle: JoinHandle<()>,
+}
+
+impl<DirectoryAccess: ServiceAccess, StorageAccess: ServiceAccess>
+    JMAPService<DirectoryAccess, StorageAccess>
+where
+    DirectoryAccess::ServiceResponse: Directory,
+    StorageAccess::ServiceResponse: Storage,
+{
+    pub fn start(
+        working_directory: PathBuf,
+        directory_service_access: DirectoryAccess,
+        storage_service_access: StorageAccess,
+    ) -> Result<Self, JMAPServiceError> {
+        let (config, domain_config) = Configs::get_or_save_default(working_directory)?;
+        let bind: SocketAddr = config.bind.parse()?;
+        let server = Server::try_bind(&bind)?;
+
+        let service = Arc::new(JMAPServiceInner {
+            config,
+            domain_config,
+            uploads: Uploads::default(),
+            directory_service_access,
+            storage_service_access,
+        });
+        let make_service = {
+            let service = service.clone();
+            make_service_fn(move |connection: &AddrStream| {
+                let service = service.clone();
+                let remote = connection.remote_addr().ip();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        Self::handle_request(service.clone(), remote, request)
+                    }))
+                }
+            })
+        };
+        info!("Starting JMAP service on {}", bind);
+        let handle = tokio::spawn(async move {
+            if let Err(e) = server.serve(make_service).await {
+                error!("Error in JMAP service: {:?}", e);
+            }
+        });
+        Ok(JMAPService {
+            inner: service,
+            handle,
+        })
+    }
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+
+    async fn handle_request(
+        service: JMAPServiceAccess<DirectoryAccess, StorageAccess>,
+        remote: IpAddr,
+        request: Request<Body>,
+    ) -> Result<Response<Body>, Infallible> {
+        let account = match Self::authenticate(&service, remote, &request).await {
+            Ok(Authentication::Authenticated(account)) => account,
+            Ok(Authentication::LockedOut(lockout)) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs() as i64;
+                let retry_after = lockout.until - now;
+                return Ok(Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .header(RETRY_AFTER, retry_after.max(1))
+                    .body(Body::empty())
+                    .unwrap());
+            }
+            Ok(Authentication::Failed) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(WWW_AUTHENTICATE, "Basic realm=\"nitro_mail\"")
+                    .body(Body::empty())
+                    .unwrap());
+            }
+            Err(error) => {
+                error!("Error authenticating JMAP request: {}", error);
+                return Ok(Self::status(StatusCode::INTERNAL_SERVER_ERROR));
+            }
+        };
+        // The mailbox id, so the JMAP account id survives renaming the account
+        let account_id = account.mailbox_id.simple().to_string();
+
+        let path = request.uri().path().to_string();
+        let response = match (request.method(), path.as_str()) {
+            (&Method::GET, "/.well-known/jmap") | (&Method::GET, "/jmap/session") => {
+                let session = Session::new(&service.config, account.username, account_id);
+                Self::json(StatusCode::OK, "application/json", &session)
+            }
+            (&Method::POST, "/jmap/api") => match Self::api(&service, request, &account).await {
+                Ok(response) => Self::json(StatusCode::OK, "application/json", &response),
+                Err(error) => Self::json(
+                    StatusCode::BAD_REQUEST,
+                    "application/problem+json",
+                    &error.to_problem(),
+                ),
+            },
+            (&Method::POST, path) if path.starts_with("/jmap/upload/") => {
+                let account_id = &path["/jmap/upload/".len()..];
+                Self::upload(&service, request, &account, account_id).await
+            }
+            (&Method::GET, path) if path.starts_with("/jmap/download/") => {
+                let path = &path["/jmap/download/".len()..];
+                Self::download(&service, &request, &account, path).await
+            }
+            _ => Self::status(StatusCode::NOT_FOUND),
+        };
+        Ok(response)
+    }
+
+    /// Checks HTTP Basic credentials. Failures are counted by the directory
+    async fn authenticate(
+        service: &JMAPServiceAccess<DirectoryAccess, StorageAccess>,
+        remote: IpAddr,
+        request: &Request<Body>,
+    ) -> Result<Authentication, JMAPServiceError> {
+        let Some(credentials) = BasicCredentials::from_headers(request.headers()) else {
+            return Ok(Authentication::Failed);
+        };
+        let directory = service
+            .directory_service_access
+            .get_service()
+            .await
+            .map_err(|e| JMAPServiceError::GettingDirectoryAccess(Box::new(e)))?;
+        let directory_error = |e| JMAPServiceError::Directory(Box::new(e));
+        let lockout = directory
+            .check_auth_lockout(Some(remote), Some(credentials.username.clone()))
+            .await
+            .map_err(directory_error)?;
+        if let Some(lockout) = lockout {
+            return Ok(Authentication::LockedOut(lockout));
+        }
+        let account = directory
+            .login_account(
+                credentials.username.clone(),
+                credentials.password,
+                LoginProtocol::Http,
+            )
+            .await
+            .map_err(directory_error)?;
+        match account {
+            Some(account) => {
+                directory
+                    .record_auth_success(account.username.clone())
+                    .await
+                    .map_err(directory_error)?;
+                Ok(Authentication::Authenticated(account))
+            }
+            None => {
+                directory
+                    .record_auth_failure(Some(remote), Some(credentials.username))
+                    .await
+                    .map_err(directory_error)?;
+                Ok(Authentication::Failed)
+            }
+        }
+    }
+
+    async fn api(
+        service: &JMAPServiceAccess<DirectoryAccess, StorageAccess>,
+        request: Request<Body>,
+        account: &Account,
+    ) -> Result<request::Response, RequestError> {
+        let limits = &service.config.limits;
+        let body =
+            read_body(request, limits.max_size_request)
+                .await
+                .map_err(|error| match error {
+                    BodyError::TooLarge => RequestError::Limit("maxSizeRequest"),
+                    BodyError::Read(_) => RequestError::NotJSON,
+                })?;
+        let value: serde_json::Value =
+            serde_json::from_slice(&body).map_err(|_| RequestError::NotJSON)?;
+        let jmap_request: request::Request = serde_json::from_value(value)
+            .map_err(|error| RequestError::NotRequest(error.to_string()))?;
+
+        if let Some(capability) = jmap_request
+            .using
+            .iter()
+            .find(|capability| !SUPPORTED_CAPABILITIES.contains(&capability.as_str()))
+        {
+            return Err(RequestError::UnknownCapability(capability.clone()));
+        }
+        if jmap_request.method_calls.len() as u64 > limits.max_calls_in_request {
+            return Err(RequestError::Limit("maxCallsInRequest"));
+        }
+
+        let account_id = account.mailbox_id.simple().to_string();
+        let mut created_ids = jmap_request.created_ids.clone().unwrap_or_default();
+        let mut services = None;
+        let mut method_responses: Vec<Invocation> =
+            Vec::with_capacity(jmap_request.method_calls.len());
+        for invocation in &jmap_request.method_calls {
+            let call_id = invocation.call_id().to_string();
+            let arguments = match resolve_references(invocation.arguments(), &method_responses) {
+                Ok(arguments) => arguments,
+                Err(error) => {
+                    method_responses.push(error.into_invocation(call_id));
+                    continue;
+                }
+            };
+            let invocation = Invocation(invocation.name().to_string(), arguments, call_id);
+            let method = match route(&invocation, &jmap_request.using, &account_id) {
+                Route::Call(method) => method,
+                Route::Response(response) => {
+                    method_responses.push(response);
+                    continue;
+                }
+            };
+            if services.is_none() {
+                services = Self::services(service).await;
+            }
+            let Invocation(_, arguments, call_id) = invocation;
+            let Some((directory, storage)) = &services else {
+                method_responses.push(
+                    MethodError::new(MethodErrorType::ServerUnavailable).into_invocation(call_id),
+                );
+                continue;
+            };
+            let context = MethodContext {
+                account,
+                directory,
+                storage,
+                domains: &service.domain_config,
+                limits,
+                uploads: &service.uploads,
+            };
+            let response = match call(&context, method, &arguments, &mut created_ids).await {
+                Ok(arguments) => Invocation(method.to_string(), arguments, call_id),
+                Err(error) => error.into_invocation(call_id),
+            };
+            method_responses.push(response);
+        }
+        Ok(request::Response {
+            method_responses,
+            session_state: account_id,
+            created_ids: jmap_request.created_ids.map(|_| created_ids),
+        })
+    }
+
+    /// The directory and storage the methods use. None if one can not be reached
+    async fn services(
+        service: &JMAPServiceAccess<DirectoryAccess, StorageAccess>,
+    ) -> Option<(
+        DirectoryAccess::ServiceResponse,
+        StorageAccess::ServiceResponse,
+    )> {
+        let directory = match service.directory_service_access.get_service().await {
+            Ok(directory) => directory,
+            Err(error) => {
+                warn!("Unable to reach the directory: {}", error);
+                return None;
+            }
+        };
+        match service.storage_service_access.get_service().await {
+            Ok(storage) => Some((directory, storage)),
+            Err(error) => {
+                warn!("Unable to reach the storage: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Keeps the blob in [Uploads] [RFC 8620 Section 6.1](https://www.rfc-editor.org/rfc/rfc8620#section-6.1)
+    async fn upload(
+        service: &JMAPServiceAccess<DirectoryAccess, StorageAccess>,
+        request: Request<Body>,
+        account: &Account,
+        account_id: &str,
+    ) -> Response<Body> {
+        if account_id != account.mailbox_id.simple().to_string() {
+            return Self::status(StatusCode::NOT_FOUND);
+        }
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let contents = match read_body(request, service.config.limits.max_size_upload).await {
+            Ok(contents) => contents,
+            Err(BodyError::TooLarge) => return Self::status(StatusCode::PAYLOAD_TOO_LARGE),
+            Err(BodyError::Read(error)) => {
+                warn!("Unable to read the upload: {}", error);
+                return Self::status(StatusCode::BAD_REQUEST);
+            }
+        };
+        let size = contents.len();
+        let id = service.uploads.add(account.mailbox_id, contents);
+        let response = json!({
+            "accountId": account_id,
+            "blobId": BlobId::Upload(id).to_string(),
+            "type": content_type,
+            "size": size,
+        });
+        Self::json(StatusCode::CREATED, "application/json", &response)
+    }
+
+    /// `{accountId}/{blobId}/{name}?accept={type}` [RFC 8620 Section 6.2](https://www.rfc-editor.org/rfc/rfc8620#section-6.2)
+    async fn download(
+        service: &JMAPServiceAccess<DirectoryAccess, StorageAccess>,
+        request: &Request<Body>,
+        account: &Account,
+        path: &str,
+    ) -> Response<Body> {
+        let mut segments = path.splitn(3, '/');
+        let (Some(account_id), Some(blob_id), Some(name)) =
+            (segments.next(), segments.next(), segments.next())
+        else {
+            return Self::status(StatusCode::NOT_FOUND);
+        };
+        if account_id != account.mailbox_id.simple().to_string() {
+            return Self::status(StatusCode::NOT_FOUND);
+        }
+        let Some(blob) = BlobId::parse(blob_id) else {
+            return Self::status(StatusCode::NOT_FOUND);
+        };
+        let accept = request
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|parameter| parameter.strip_prefix("accept="))
+            .map(percent_decode)
+            .filter(|accept| is_media_type(accept))
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let storage = match service.storage_service_access.get_service().await {
+            Ok(storage) => storage,
+            Err(error) => {
+                warn!("Unable to reach the storage: {}", error);
+                return Self::status(StatusCode::SERVICE_UNAVAILABLE);
+            }
+        };
+        match read_blob(&storage, &service.uploads, account.mailbox_id, &blob).await {
+            Ok(Some(contents)) => Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, accept)
+                .header(
+                    CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename*=UTF-8''{}",
+                        percent_encode(&percent_decode(name))
+                    ),
+                )
+                .body(Body::from(contents))
+                .unwrap(),
+            Ok(None) => Self::status(StatusCode::NOT_FOUND),
+            Err(_) => Self::status(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    fn json(status: StatusCode, content_type: &str, value: &impl Serialize) -> Response<Body> {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, content_type)
+                .body(Body::from(body))
+                .unwrap(),
+            Err(error) => {
+                error!("Failed to serialize JMAP response: {}", error);
+                Self::status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+    fn status(status: StatusCode) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::CONTENT_LENGTH;
+    use hyper::{Body, Request};
+
+    use crate::jmap_service::{is_media_type, percent_decode, read_body, BodyError};
+
+    #[tokio::test]
+    pub async fn test_read_body() {
+        let request = |length: Option<&str>, body: &'static str| {
+            let mut request = Request::builder();
+            if let Some(length) = length {
+                request = request.header(CONTENT_LENGTH, length);
+            }
+            request.body(Body::from(body)).unwrap()
+        };
+        assert_eq!(
+            read_body(request(Some("5"), "hello"), 5).await.ok(),
+            Some(b"hello".to_vec())
+        );
+        // Rejected without reading the body
+        assert!(matches!(
+            read_body(request(Some("1000000"), ""), 5).await,
+            Err(BodyError::TooLarge)
+        ));
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..3 {
+                if sender.send_data("hello".into()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let request = Request::builder().body(body).unwrap();
+        assert!(matches!(
+            read_body(request, 12).await,
+            Err(BodyError::TooLarge)
+        ));
+    }
+
+    #[test]
+    pub fn test_download_parameters() {
+        assert_eq!(percent_decode("message%2Frfc822"), "message/rfc822");
+        assert_eq!(percent_decode("a%20b%zz%"), "a b%zz%");
+        assert!(is_media_type("message/rfc822"));
+        assert!(!is_media_type("text/html\r\nX-Header: 1"));
+        assert!(!is_media_type("text"));
+    }
+}
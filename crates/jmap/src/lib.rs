@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use directories::directory_type::Directory;
+use storages::storage_type::Storage;
+use utils::service::ServiceAccess;
+
+use crate::jmap_service::{JMAPService, JMAPServiceError};
+
+pub mod authentication;
+pub mod compose;
+pub mod email_methods;
+pub mod email_query;
+pub mod ids;
+pub mod jmap_config;
+pub mod jmap_service;
+pub mod mailbox_methods;
+#[cfg(test)]
+mod method_tests;
+pub mod methods;
+pub mod request;
+pub mod session;
+pub mod submission;
+pub mod uploads;
+
+/// Starts the JMAP (RFC 8620/8621) HTTP API
+///
+/// Requests are authenticated with HTTP Basic against [Directory::login_account].
+/// Locked out clients get 429 Too Many Requests, see [Directory::check_auth_lockout]
+pub fn start_jmap_service<DirectoryAccess, StorageAccess>(
+    working_directory: PathBuf,
+    directory_service_access: DirectoryAccess,
+    storage_service_access: StorageAccess,
+) -> Result<JMAPService<DirectoryAccess, StorageAccess>, JMAPServiceError>
+where
+    DirectoryAccess: ServiceAccess,
+    DirectoryAccess::ServiceResponse: Directory,
+    StorageAccess: ServiceAccess,
+    StorageAccess::ServiceResponse: Storage,
+{
+    JMAPService::start(
+        working_directory,
+        directory_service_access,
+        storage_service_access,
+    )
+}
@@ -0,0 +1,274 @@
+//! Mailbox/get and Mailbox/set [RFC 8621 Section 2](https://www.rfc-editor.org/rfc/rfc8621#section-2)
+//!
+//! Every folder of the storage is a top level mailbox. Folders can be created and destroyed but
+//! not renamed, so mailboxes can not be updated
+use ahash::HashMap;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use storages::folders::{Folder, StorageError, INBOX};
+use storages::storage_type::Storage;
+
+use crate::ids::{folder, mailbox_id};
+use crate::methods::{
+    check_state, folders, parse_arguments, set_error, state, storage_error, storage_result,
+    MethodContext,
+};
+use crate::request::{MethodError, MethodErrorType};
+
+/// Folders named like a role, ignoring case, have it
+const ROLES: [(&str, &str); 6] = [
+    ("archive", "archive"),
+    ("drafts", "drafts"),
+    ("junk", "junk"),
+    ("sent", "sent"),
+    ("spam", "junk"),
+    ("trash", "trash"),
+];
+
+fn role(folder: &str) -> Option<&'static str> {
+    if folder == INBOX {
+        return Some("inbox");
+    }
+    ROLES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(folder))
+        .map(|(_, role)| *role)
+}
+
+async fn mailbox<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    folder: &Folder,
+) -> Result<Value, MethodError> {
+    let messages = storage_result(
+        context
+            .storage
+            .list_messages(context.account.mailbox_id, folder.name.clone())
+            .await,
+    )?;
+    let messages: Vec<_> = messages
+        .iter()
+        .filter(|message| !message.is_deleted())
+        .collect();
+    let unread = messages
+        .iter()
+        .filter(|message| !message.flags.iter().any(|flag| flag == "\\Seen"))
+        .count();
+    let inbox = folder.name == INBOX;
+    Ok(json!({
+        "id": mailbox_id(&folder.name),
+        "name": folder.name,
+        "parentId": null,
+        "role": role(&folder.name),
+        "sortOrder": if inbox { 0 } else { 1 },
+        "totalEmails": messages.len(),
+        "unreadEmails": unread,
+        "totalThreads": messages.len(),
+        "unreadThreads": unread,
+        "myRights": {
+            "mayReadItems": true,
+            "mayAddItems": true,
+            "mayRemoveItems": true,
+            "maySetSeen": true,
+            "maySetKeywords": true,
+            "mayCreateChild": false,
+            "mayRename": false,
+            "mayDelete": !inbox,
+            "maySubmit": true,
+        },
+        "isSubscribed": true,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetArguments {
+    #[serde(default)]
+    ids: Option<Vec<String>>,
+    #[serde(default)]
+    properties: Option<Vec<String>>,
+}
+
+pub async fn get<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    arguments: &Value,
+) -> Result<Value, MethodError> {
+    let arguments: GetArguments = parse_arguments(arguments)?;
+    let folders = folders(context).await?;
+    let mut list = Vec::with_capacity(folders.len());
+    let mut not_found = Vec::new();
+    match arguments.ids {
+        None => {
+            for folder in &folders {
+                list.push(mailbox(context, folder).await?);
+            }
+        }
+        Some(ids) => {
+            if ids.len() as u64 > context.limits.max_objects_in_get {
+                return Err(MethodError::new(MethodErrorType::RequestTooLarge));
+            }
+            for id in ids {
+                let found =
+                    folder(&id).and_then(|name| folders.iter().find(|folder| folder.name == name));
+                match found {
+                    Some(folder) => list.push(mailbox(context, folder).await?),
+                    None => not_found.push(id),
+                }
+            }
+        }
+    }
+    if let Some(properties) = arguments.properties {
+        for mailbox in &mut list {
+            if let Some(mailbox) = mailbox.as_object_mut() {
+                mailbox.retain(|name, _| name == "id" || properties.contains(name));
+            }
+        }
+    }
+    Ok(json!({
+        "accountId": context.account_id(),
+        "state": state(&folders),
+        "list": list,
+        "notFound": not_found,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetArguments {
+    #[serde(default)]
+    if_in_state: Option<String>,
+    #[serde(default)]
+    create: Option<HashMap<String, Map<String, Value>>>,
+    #[serde(default)]
+    update: Option<HashMap<String, Value>>,
+    #[serde(default)]
+    destroy: Option<Vec<String>>,
+    #[serde(default)]
+    on_destroy_remove_emails: bool,
+}
+
+pub async fn set<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    arguments: &Value,
+    created_ids: &mut HashMap<String, String>,
+) -> Result<Value, MethodError> {
+    let arguments: SetArguments = parse_arguments(arguments)?;
+    let old_state = state(&folders(context).await?);
+    check_state(arguments.if_in_state.as_deref(), &old_state)?;
+    let objects = arguments.create.as_ref().map_or(0, |create| create.len())
+        + arguments.update.as_ref().map_or(0, |update| update.len())
+        + arguments
+            .destroy
+            .as_ref()
+            .map_or(0, |destroy| destroy.len());
+    if objects as u64 > context.limits.max_objects_in_set {
+        return Err(MethodError::new(MethodErrorType::RequestTooLarge));
+    }
+    let account_mailbox = context.account.mailbox_id;
+
+    let mut created = Map::new();
+    let mut not_created = Map::new();
+    for (creation_id, create) in arguments.create.unwrap_or_default() {
+        let invalid: Vec<&String> = create
+            .iter()
+            .filter(|(name, value)| match name.as_str() {
+                "name" => !value.is_string(),
+                "parentId" => !value.is_null(),
+                "isSubscribed" | "sortOrder" | "role" => false,
+                _ => true,
+            })
+            .map(|(name, _)| name)
+            .collect();
+        let name = create.get("name").and_then(Value::as_str);
+        let (Some(name), true) = (name, invalid.is_empty()) else {
+            let mut error = set_error(
+                "invalidProperties",
+                "Only a name is accepted. Mailboxes can not be nested",
+            );
+            error["properties"] = json!(invalid);
+            not_created.insert(creation_id, error);
+            continue;
+        };
+        match context
+            .storage
+            .create_folder(account_mailbox, name.to_string())
+            .await
+            .map_err(storage_error)?
+        {
+            Ok(folder) => {
+                let id = mailbox_id(&folder.name);
+                created_ids.insert(creation_id.clone(), id.clone());
+                created.insert(creation_id, json!({"id": id}));
+            }
+            Err(StorageError::FolderExists(_)) => {
+                let mut error = set_error("alreadyExists", format!("{name} already exists"));
+                error["existingId"] = mailbox_id(name).into();
+                not_created.insert(creation_id, error);
+            }
+            Err(StorageError::InvalidFolderName(_)) => {
+                let mut error = set_error(
+                    "invalidProperties",
+                    format!("{name} can not be used as a name"),
+                );
+                error["properties"] = json!(["name"]);
+                not_created.insert(creation_id, error);
+            }
+            Err(error) => return Err(storage_error(error)),
+        }
+    }
+
+    let mut not_updated = Map::new();
+    for (id, _) in arguments.update.unwrap_or_default() {
+        not_updated.insert(
+            id,
+            set_error("forbidden", "Mailboxes can not be renamed or moved"),
+        );
+    }
+
+    let mut destroyed = Vec::new();
+    let mut not_destroyed = Map::new();
+    for id in arguments.destroy.unwrap_or_default() {
+        let Some(name) = folder(&id) else {
+            not_destroyed.insert(id, set_error("notFound", "No such mailbox"));
+            continue;
+        };
+        if name == INBOX {
+            not_destroyed.insert(id, set_error("forbidden", "The inbox can not be destroyed"));
+            continue;
+        }
+        if !arguments.on_destroy_remove_emails {
+            let messages = context
+                .storage
+                .list_messages(account_mailbox, name.clone())
+                .await
+                .map_err(storage_error)?;
+            match messages {
+                Ok(messages) if !messages.is_empty() => {
+                    not_destroyed
+                        .insert(id, set_error("mailboxHasEmail", "The mailbox is not empty"));
+                    continue;
+                }
+                Ok(_) | Err(StorageError::FolderNotFound(_)) => {}
+                Err(error) => return Err(storage_error(error)),
+            }
+        }
+        match storage_result(context.storage.delete_folder(account_mailbox, name).await)? {
+            true => destroyed.push(id),
+            false => {
+                not_destroyed.insert(id, set_error("notFound", "No such mailbox"));
+            }
+        }
+    }
+
+    Ok(json!({
+        "accountId": context.account_id(),
+        "oldState": old_state,
+        "newState": state(&folders(context).await?),
+        "created": created,
+        "notCreated": not_created,
+        "updated": {},
+        "notUpdated": not_updated,
+        "destroyed": destroyed,
+        "notDestroyed": not_destroyed,
+    }))
+}
@@ -0,0 +1,445 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ahash::HashMap;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use directories::directory_type::Directory;
+use directory_file::file_config::FileDirectoryConfig;
+use directory_file::file_directory::FileDirectory;
+use storage_mail_directory::maildir_storage::MaildirStorage;
+use storage_search::indexed_storage::IndexedStorage;
+use storage_search::search_index::SearchIndex;
+use storages::folders::INBOX;
+use storages::storage_type::Storage;
+use utils::account::Account;
+use utils::configs::domain_configs::DomainConfiguration;
+
+use crate::ids::{email_id, mailbox_id, BlobId};
+use crate::jmap_config::JMAPLimits;
+use crate::methods::{call, JMAPMethod, MethodContext};
+use crate::request::{MethodError, MethodErrorType};
+use crate::uploads::Uploads;
+
+const ALICE_MAILBOX: Uuid = Uuid::from_u128(1);
+const BOB_MAILBOX: Uuid = Uuid::from_u128(2);
+
+/// A directory in the temp folder that is removed on drop
+struct TestFiles(PathBuf);
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `alice@example.com` calls the methods. `bob@example.com` is another local account
+struct TestAccount {
+    account: Account,
+    directory: FileDirectory,
+    storage: IndexedStorage<MaildirStorage>,
+    domains: DomainConfiguration,
+    limits: JMAPLimits,
+    uploads: Uploads,
+    created_ids: HashMap<String, String>,
+    _files: TestFiles,
+}
+impl TestAccount {
+    async fn new() -> Self {
+        let files =
+            TestFiles(std::env::temp_dir().join(format!("nitro_mail_jmap_{}", Uuid::new_v4())));
+        std::fs::create_dir_all(&files.0).unwrap();
+        let mut accounts = String::new();
+        for (username, mailbox_id) in [("alice", ALICE_MAILBOX), ("bob", BOB_MAILBOX)] {
+            accounts.push_str(&format!(
+                r#"
+                [[accounts]]
+                username = "{username}"
+                password = "$2y$05$abc"
+                email = "{username}@example.com"
+                mailbox_id = "{mailbox_id}"
+                "#
+            ));
+        }
+        std::fs::write(files.0.join("accounts.toml"), accounts).unwrap();
+        let directory = FileDirectory::load(FileDirectoryConfig {
+            accounts_file: files.0.join("accounts.toml"),
+            state_file: files.0.join("accounts.state.toml"),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let account = directory
+            .get_account_by_email("alice@example.com".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        let index = Arc::new(SearchIndex::in_memory(15_000_000).unwrap());
+        let storage = IndexedStorage::new(MaildirStorage::new(files.0.join("mail")), index);
+        Self {
+            account,
+            directory,
+            storage,
+            domains: DomainConfiguration::default(),
+            limits: JMAPLimits::default(),
+            uploads: Uploads::default(),
+            created_ids: HashMap::default(),
+            _files: files,
+        }
+    }
+
+    async fn call(&mut self, method: JMAPMethod, arguments: Value) -> Result<Value, MethodError> {
+        let context = MethodContext {
+            account: &self.account,
+            directory: &self.directory,
+            storage: &self.storage,
+            domains: &self.domains,
+            limits: &self.limits,
+            uploads: &self.uploads,
+        };
+        let mut arguments = arguments;
+        arguments["accountId"] = ALICE_MAILBOX.simple().to_string().into();
+        call(&context, method, &arguments, &mut self.created_ids).await
+    }
+
+    /// Imports the message into the folder and returns the email id
+    async fn import(&mut self, folder: &str, contents: &str) -> String {
+        let upload = self
+            .uploads
+            .add(ALICE_MAILBOX, contents.as_bytes().to_vec());
+        let response = self
+            .call(
+                JMAPMethod::EmailImport,
+                json!({"emails": {"i": {
+                    "blobId": BlobId::Upload(upload).to_string(),
+                    "mailboxIds": {mailbox_id(folder): true},
+                    "receivedAt": "2023-11-14T22:13:20Z",
+                }}}),
+            )
+            .await
+            .unwrap();
+        response["created"]["i"]["id"].as_str().unwrap().to_string()
+    }
+}
+
+#[tokio::test]
+pub async fn test_mailboxes() {
+    let mut test = TestAccount::new().await;
+    let response = test
+        .call(JMAPMethod::MailboxGet, json!({"ids": null}))
+        .await
+        .unwrap();
+    assert_eq!(response["list"][0]["id"], mailbox_id(INBOX));
+    assert_eq!(response["list"][0]["role"], "inbox");
+    let state = response["state"].clone();
+
+    let response = test
+        .call(
+            JMAPMethod::MailboxSet,
+            json!({
+                "ifInState": state,
+                "create": {"a": {"name": "Archive"}, "b": {"name": "INBOX"}, "c": {"name": "Sub", "parentId": "x"}},
+                "destroy": [mailbox_id(INBOX)],
+            }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["created"]["a"]["id"], mailbox_id("Archive"));
+    assert_eq!(test.created_ids["a"], mailbox_id("Archive"));
+    assert_eq!(response["notCreated"]["b"]["type"], "alreadyExists");
+    assert_eq!(
+        response["notCreated"]["c"]["properties"],
+        json!(["parentId"])
+    );
+    assert_eq!(
+        response["notDestroyed"][mailbox_id(INBOX)]["type"],
+        "forbidden"
+    );
+    assert_ne!(response["newState"], state);
+
+    let error = test
+        .call(JMAPMethod::MailboxSet, json!({"ifInState": state}))
+        .await
+        .unwrap_err();
+    assert_eq!(error.error_type, MethodErrorType::StateMismatch);
+    let error = test
+        .call(JMAPMethod::MailboxChanges, json!({"sinceState": state}))
+        .await
+        .unwrap_err();
+    assert_eq!(error.error_type, MethodErrorType::CannotCalculateChanges);
+
+    test.import("Archive", "Subject: Kept\r\n\r\nBody\r\n")
+        .await;
+    let response = test
+        .call(
+            JMAPMethod::MailboxGet,
+            json!({"ids": [mailbox_id("Archive")], "properties": ["totalEmails", "role"]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response["list"][0],
+        json!({"id": mailbox_id("Archive"), "totalEmails": 1, "role": "archive"})
+    );
+    let response = test
+        .call(
+            JMAPMethod::MailboxSet,
+            json!({"destroy": [mailbox_id("Archive")]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response["notDestroyed"][mailbox_id("Archive")]["type"],
+        "mailboxHasEmail"
+    );
+    let response = test
+        .call(
+            JMAPMethod::MailboxSet,
+            json!({"destroy": [mailbox_id("Archive")], "onDestroyRemoveEmails": true}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["destroyed"], json!([mailbox_id("Archive")]));
+    let response = test
+        .call(
+            JMAPMethod::MailboxChanges,
+            json!({"sinceState": response["newState"]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["created"], json!([]));
+}
+
+#[tokio::test]
+pub async fn test_emails() {
+    let mut test = TestAccount::new().await;
+    let id = test
+        .import(
+            INBOX,
+            "From: Bob <bob@example.com>\r\nTo: alice@example.com\r\nSubject: Hello\r\nMessage-ID: <1@example.com>\r\n\r\nHi Alice\r\n",
+        )
+        .await;
+    assert_eq!(id, email_id(INBOX, 1));
+
+    let response = test
+        .call(
+            JMAPMethod::EmailGet,
+            json!({"ids": [id, "e9-SU5CT1g", "x"], "fetchTextBodyValues": true}),
+        )
+        .await
+        .unwrap();
+    let email = &response["list"][0];
+    assert_eq!(email["subject"], "Hello");
+    assert_eq!(
+        email["from"],
+        json!([{"name": "Bob", "email": "bob@example.com"}])
+    );
+    assert_eq!(email["messageId"], json!(["1@example.com"]));
+    assert_eq!(email["receivedAt"], "2023-11-14T22:13:20Z");
+    assert_eq!(email["keywords"], json!({}));
+    assert_eq!(email["preview"], "Hi Alice");
+    let part_id = email["textBody"][0]["partId"].as_str().unwrap();
+    assert_eq!(email["bodyValues"][part_id]["value"], "Hi Alice\r\n");
+    assert_eq!(response["notFound"], json!(["e9-SU5CT1g", "x"]));
+
+    let response = test
+        .call(
+            JMAPMethod::EmailSet,
+            json!({
+                "create": {"draft": {
+                    "mailboxIds": {mailbox_id(INBOX): true},
+                    "keywords": {"$draft": true},
+                    "from": [{"email": "alice@example.com"}],
+                    "subject": "Draft",
+                    "textBody": [{"partId": "1", "type": "text/plain"}],
+                    "bodyValues": {"1": {"value": "Later"}},
+                }},
+                "update": {id.clone(): {"keywords/$seen": true, "keywords/Custom": true}},
+            }),
+        )
+        .await
+        .unwrap();
+    let draft = response["created"]["draft"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(test.created_ids["draft"], draft);
+    assert!(response["updated"].get(&id).is_some());
+
+    let response = test
+        .call(
+            JMAPMethod::EmailGet,
+            json!({"ids": [id, draft], "properties": ["keywords", "subject"]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response["list"],
+        json!([
+            {"id": id, "keywords": {"$seen": true, "custom": true}, "subject": "Hello"},
+            {"id": draft, "keywords": {"$draft": true}, "subject": "Draft"},
+        ])
+    );
+
+    let response = test
+        .call(
+            JMAPMethod::EmailQuery,
+            json!({"sort": [{"property": "receivedAt", "isAscending": false}], "calculateTotal": true}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["ids"], json!([draft, id]));
+    assert_eq!(response["total"], 2);
+    let response = test
+        .call(JMAPMethod::EmailQuery, json!({"position": -1, "limit": 5}))
+        .await
+        .unwrap();
+    assert_eq!(response["ids"], json!([draft]));
+    let error = test
+        .call(
+            JMAPMethod::EmailQuery,
+            json!({"sort": [{"property": "size"}]}),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.error_type, MethodErrorType::UnsupportedSort);
+
+    let response = test
+        .call(
+            JMAPMethod::EmailSet,
+            json!({"update": {draft.clone(): {"mailboxIds": {}}}, "destroy": [draft]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["notUpdated"][&draft]["type"], "invalidProperties");
+    assert_eq!(response["destroyed"], json!([draft]));
+    let response = test
+        .call(
+            JMAPMethod::ThreadGet,
+            json!({"ids": [email_id(INBOX, 1).replacen('e', "t", 1)]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["list"][0]["emailIds"], json!([id]));
+    let response = test
+        .call(
+            JMAPMethod::EmailGet,
+            json!({"ids": null, "properties": ["id"]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["list"], json!([{"id": id}]));
+}
+
+#[tokio::test]
+pub async fn test_email_query_filter() {
+    let mut test = TestAccount::new().await;
+    let report = test
+        .import(INBOX, "Subject: Quarterly report\r\n\r\nNumbers\r\n")
+        .await;
+    test.import(INBOX, "Subject: Weekly meeting\r\n\r\nAgenda\r\n")
+        .await;
+    test.storage
+        .rebuild_search_index(ALICE_MAILBOX)
+        .await
+        .unwrap()
+        .unwrap();
+    let response = test
+        .call(
+            JMAPMethod::EmailQuery,
+            json!({"filter": {"inMailbox": mailbox_id(INBOX), "subject": "REPORT"}}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["ids"], json!([report]));
+    let response = test
+        .call(
+            JMAPMethod::EmailQuery,
+            json!({"filter": {"inMailbox": mailbox_id("Missing")}}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["ids"], json!([]));
+}
+
+#[tokio::test]
+pub async fn test_submission() {
+    let mut test = TestAccount::new().await;
+    let response = test
+        .call(
+            JMAPMethod::EmailSet,
+            json!({"create": {
+                "local": {
+                    "mailboxIds": {mailbox_id(INBOX): true},
+                    "from": [{"email": "alice@example.com"}],
+                    "to": [{"email": "bob@example.com"}],
+                    "bcc": [{"email": "alice@example.com"}],
+                    "subject": "Lunch",
+                },
+                "remote": {
+                    "mailboxIds": {mailbox_id(INBOX): true},
+                    "from": [{"email": "alice@example.com"}],
+                    "to": [{"email": "someone@example.org"}],
+                },
+                "spoofed": {
+                    "mailboxIds": {mailbox_id(INBOX): true},
+                    "from": [{"email": "bob@example.com"}],
+                    "to": [{"email": "alice@example.com"}],
+                },
+            }}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["notCreated"], json!({}));
+    let response = test
+        .call(
+            JMAPMethod::EmailSubmissionSet,
+            json!({"create": {
+                "1": {"emailId": "#local"},
+                "2": {"emailId": "#remote"},
+                "3": {"emailId": "#spoofed"},
+                "4": {"emailId": "e99-SU5CT1g"},
+            }}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response["created"]["1"]["undoStatus"], "final");
+    assert_eq!(response["notCreated"]["2"]["type"], "forbiddenToSend");
+    assert!(response["notCreated"]["2"]["description"]
+        .as_str()
+        .unwrap()
+        .contains("no outbound queue"));
+    assert_eq!(
+        response["notCreated"]["2"]["invalidRecipients"],
+        json!(["someone@example.org"])
+    );
+    assert_eq!(response["notCreated"]["3"]["type"], "forbiddenMailFrom");
+    assert_eq!(response["notCreated"]["4"]["type"], "invalidEmail");
+
+    // Alice is in Bcc but the envelope comes from the headers, so she gets it too
+    let delivered = test
+        .storage
+        .read_message(BOB_MAILBOX, INBOX.to_string(), 1)
+        .await
+        .unwrap()
+        .unwrap();
+    let delivered = String::from_utf8(delivered).unwrap();
+    assert!(delivered.contains("Subject: Lunch"));
+    assert!(!delivered.contains("Bcc:"));
+    let messages = test
+        .storage
+        .list_messages(ALICE_MAILBOX, INBOX.to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages.len(), 4);
+
+    let error = test
+        .call(
+            JMAPMethod::EmailSubmissionSet,
+            json!({"onSuccessDestroyEmail": ["#1"]}),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.error_type, MethodErrorType::InvalidArguments);
+}
@@ -0,0 +1,334 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use ahash::HashMap;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use strum::{AsRefStr, EnumIter, EnumString, IntoStaticStr};
+use tracing::error;
+
+use directories::directory_type::Directory;
+use storages::folders::{Folder, StorageError, StorageResult, INBOX};
+use storages::storage_type::Storage;
+use utils::account::Account;
+use utils::configs::domain_configs::DomainConfiguration;
+
+use crate::jmap_config::JMAPLimits;
+use crate::request::{
+    Invocation, MethodError, MethodErrorType, CORE_CAPABILITY, MAIL_CAPABILITY,
+    SUBMISSION_CAPABILITY,
+};
+use crate::uploads::Uploads;
+use crate::{email_methods, mailbox_methods, submission};
+
+/// The methods this server knows about
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, AsRefStr, IntoStaticStr, EnumString, strum::Display, EnumIter,
+)]
+pub enum JMAPMethod {
+    #[strum(serialize = "Core/echo")]
+    CoreEcho,
+    #[strum(serialize = "Mailbox/get")]
+    MailboxGet,
+    #[strum(serialize = "Mailbox/set")]
+    MailboxSet,
+    #[strum(serialize = "Mailbox/changes")]
+    MailboxChanges,
+    #[strum(serialize = "Email/get")]
+    EmailGet,
+    #[strum(serialize = "Email/query")]
+    EmailQuery,
+    #[strum(serialize = "Email/set")]
+    EmailSet,
+    #[strum(serialize = "Email/changes")]
+    EmailChanges,
+    #[strum(serialize = "Email/import")]
+    EmailImport,
+    #[strum(serialize = "Thread/get")]
+    ThreadGet,
+    #[strum(serialize = "EmailSubmission/set")]
+    EmailSubmissionSet,
+}
+impl JMAPMethod {
+    /// The capability that must be in `using` for the method to be called
+    pub fn required_capability(&self) -> &'static str {
+        match self {
+            JMAPMethod::CoreEcho => CORE_CAPABILITY,
+            JMAPMethod::EmailSubmissionSet => SUBMISSION_CAPABILITY,
+            _ => MAIL_CAPABILITY,
+        }
+    }
+}
+
+/// What [route] decided for a method call
+#[derive(Debug, Clone, PartialEq)]
+pub enum Route {
+    /// A method of the account that needs the storage
+    Call(JMAPMethod),
+    /// Answered without the storage. Core/echo and errors
+    Response(Invocation),
+}
+
+/// Checks the method, its capability and the account of a single method call.
+///
+/// `account_id` is the only account the authenticated user has access to.
+pub fn route(invocation: &Invocation, using: &[String], account_id: &str) -> Route {
+    let call_id = invocation.call_id();
+    let Ok(method) = JMAPMethod::from_str(invocation.name()) else {
+        return Route::Response(
+            MethodError::new(MethodErrorType::UnknownMethod).into_invocation(call_id),
+        );
+    };
+    if !using.iter().any(|v| v == method.required_capability()) {
+        return Route::Response(
+            MethodError::with_description(
+                MethodErrorType::UnknownMethod,
+                format!("{} requires {}", method, method.required_capability()),
+            )
+            .into_invocation(call_id),
+        );
+    }
+    if method == JMAPMethod::CoreEcho {
+        return Route::Response(Invocation(
+            method.to_string(),
+            invocation.arguments().clone(),
+            call_id.to_string(),
+        ));
+    }
+    match invocation.arguments().get("accountId") {
+        Some(value) if value.as_str() == Some(account_id) => Route::Call(method),
+        Some(_) => Route::Response(
+            MethodError::new(MethodErrorType::AccountNotFound).into_invocation(call_id),
+        ),
+        None => Route::Response(
+            MethodError::with_description(MethodErrorType::InvalidArguments, "Missing accountId")
+                .into_invocation(call_id),
+        ),
+    }
+}
+
+/// What the method calls of one request act on
+pub struct MethodContext<'a, D, S> {
+    pub account: &'a Account,
+    pub directory: &'a D,
+    pub storage: &'a S,
+    pub domains: &'a DomainConfiguration,
+    pub limits: &'a JMAPLimits,
+    pub uploads: &'a Uploads,
+}
+impl<D, S> MethodContext<'_, D, S> {
+    pub fn account_id(&self) -> String {
+        self.account.mailbox_id.simple().to_string()
+    }
+}
+
+/// Calls a method [route] accepted. `created_ids` maps the creation ids of the request to the
+/// ids of what was created, so later calls can refer to them as `#creationId`
+pub async fn call<D: Directory, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    method: JMAPMethod,
+    arguments: &Value,
+    created_ids: &mut HashMap<String, String>,
+) -> Result<Value, MethodError> {
+    match method {
+        JMAPMethod::CoreEcho => Ok(arguments.clone()),
+        JMAPMethod::MailboxGet => mailbox_methods::get(context, arguments).await,
+        JMAPMethod::MailboxSet => mailbox_methods::set(context, arguments, created_ids).await,
+        JMAPMethod::MailboxChanges | JMAPMethod::EmailChanges => {
+            changes(context, method, arguments).await
+        }
+        JMAPMethod::EmailGet => email_methods::get(context, arguments).await,
+        JMAPMethod::EmailQuery => email_methods::query(context, arguments).await,
+        JMAPMethod::EmailSet => email_methods::set(context, arguments, created_ids).await,
+        JMAPMethod::EmailImport => email_methods::import(context, arguments, created_ids).await,
+        JMAPMethod::ThreadGet => email_methods::thread_get(context, arguments).await,
+        JMAPMethod::EmailSubmissionSet => submission::set(context, arguments, created_ids).await,
+    }
+}
+
+pub(crate) fn parse_arguments<T: DeserializeOwned>(arguments: &Value) -> Result<T, MethodError> {
+    serde_json::from_value(arguments.clone()).map_err(|error| {
+        MethodError::with_description(MethodErrorType::InvalidArguments, error.to_string())
+    })
+}
+
+/// The storage could not be reached or failed
+pub(crate) fn storage_error(error: impl Display) -> MethodError {
+    error!("JMAP storage request failed: {}", error);
+    MethodError::with_description(MethodErrorType::ServerFail, error.to_string())
+}
+/// [StorageError::NotSupported] is serverUnavailable. Other storage errors are serverFail
+pub(crate) fn storage_result<T, E: Display>(
+    result: Result<StorageResult<T>, E>,
+) -> Result<T, MethodError> {
+    match result.map_err(storage_error)? {
+        Ok(value) => Ok(value),
+        Err(StorageError::NotSupported) => Err(MethodError::with_description(
+            MethodErrorType::ServerUnavailable,
+            "Not supported by the configured storage",
+        )),
+        Err(error) => Err(storage_error(error)),
+    }
+}
+
+/// The folders of the account. Accounts that never received mail are given their INBOX
+pub(crate) async fn folders<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+) -> Result<Vec<Folder>, MethodError> {
+    let mailbox_id = context.account.mailbox_id;
+    let folders = storage_result(context.storage.list_folders(mailbox_id).await)?;
+    if folders.iter().any(|folder| folder.name == INBOX) {
+        return Ok(folders);
+    }
+    match context
+        .storage
+        .create_folder(mailbox_id, INBOX.to_string())
+        .await
+        .map_err(storage_error)?
+    {
+        Ok(_) | Err(StorageError::FolderExists(_)) => {}
+        Err(error) => return Err(storage_error(error)),
+    }
+    storage_result(context.storage.list_folders(mailbox_id).await)
+}
+
+/// Changes whenever a folder or a message in it changes. Mailboxes and emails share it
+pub(crate) fn state(folders: &[Folder]) -> String {
+    let mut hasher = DefaultHasher::new();
+    for folder in folders {
+        folder.name.hash(&mut hasher);
+        folder.uid_validity.hash(&mut hasher);
+        folder.uid_next.hash(&mut hasher);
+        folder.highest_modseq.hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+/// `ifInState` of a /set method
+pub(crate) fn check_state(if_in_state: Option<&str>, state: &str) -> Result<(), MethodError> {
+    match if_in_state {
+        Some(if_in_state) if if_in_state != state => {
+            Err(MethodError::new(MethodErrorType::StateMismatch))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A SetError [RFC 8620 Section 5.3](https://www.rfc-editor.org/rfc/rfc8620#section-5.3)
+pub(crate) fn set_error(error_type: &str, description: impl Into<String>) -> Value {
+    json!({"type": error_type, "description": description.into()})
+}
+
+/// An id or `#creationId` of something created earlier in the request
+pub(crate) fn resolve_id(id: &str, created_ids: &HashMap<String, String>) -> Option<String> {
+    match id.strip_prefix('#') {
+        Some(creation_id) => created_ids.get(creation_id).cloned(),
+        None => Some(id.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangesArguments {
+    since_state: String,
+}
+
+/// Changes are not recorded. Only a client that is up to date is told so
+async fn changes<D, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    method: JMAPMethod,
+    arguments: &Value,
+) -> Result<Value, MethodError> {
+    let arguments: ChangesArguments = parse_arguments(arguments)?;
+    let state = state(&folders(context).await?);
+    if arguments.since_state != state {
+        return Err(MethodError::new(MethodErrorType::CannotCalculateChanges));
+    }
+    let mut response = json!({
+        "accountId": context.account_id(),
+        "oldState": state,
+        "newState": state,
+        "hasMoreChanges": false,
+        "created": [],
+        "updated": [],
+        "destroyed": [],
+    });
+    if method == JMAPMethod::MailboxChanges {
+        response["updatedProperties"] = Value::Null;
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::methods::{route, JMAPMethod, Route};
+    use crate::request::{Invocation, CORE_CAPABILITY, MAIL_CAPABILITY};
+
+    fn error_type(invocation: &Invocation, using: &[String], account_id: &str) -> String {
+        match route(invocation, using, account_id) {
+            Route::Response(response) => response.arguments()["type"].as_str().unwrap().to_string(),
+            Route::Call(method) => panic!("{method} was called"),
+        }
+    }
+
+    #[test]
+    pub fn test_echo() {
+        let invocation = Invocation(
+            "Core/echo".to_string(),
+            json!({"hello": true}),
+            "c1".to_string(),
+        );
+        let response = route(&invocation, &[CORE_CAPABILITY.to_string()], "a");
+        assert_eq!(response, Route::Response(invocation));
+    }
+    #[test]
+    pub fn test_unknown_method_and_capability() {
+        let using = [CORE_CAPABILITY.to_string()];
+        let response = error_type(
+            &Invocation("Foo/bar".to_string(), json!({}), "c1".to_string()),
+            &using,
+            "a",
+        );
+        assert_eq!(response, "unknownMethod");
+        let response = error_type(
+            &Invocation(
+                "Mailbox/get".to_string(),
+                json!({"accountId": "a"}),
+                "c2".to_string(),
+            ),
+            &using,
+            "a",
+        );
+        assert_eq!(response, "unknownMethod");
+    }
+    #[test]
+    pub fn test_account_not_found() {
+        let response = error_type(
+            &Invocation(
+                "Mailbox/get".to_string(),
+                json!({"accountId": "b"}),
+                "c1".to_string(),
+            ),
+            &[CORE_CAPABILITY.to_string(), MAIL_CAPABILITY.to_string()],
+            "a",
+        );
+        assert_eq!(response, "accountNotFound");
+        assert_eq!(
+            route(
+                &Invocation(
+                    "Mailbox/get".to_string(),
+                    json!({"accountId": "a"}),
+                    "c2".to_string(),
+                ),
+                &[CORE_CAPABILITY.to_string(), MAIL_CAPABILITY.to_string()],
+                "a",
+            ),
+            Route::Call(JMAPMethod::MailboxGet)
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use ahash::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use strum::{AsRefStr, Display, IntoStaticStr};
+
+pub const CORE_CAPABILITY: &str = "urn:ietf:params:jmap:core";
+pub const MAIL_CAPABILITY: &str = "urn:ietf:params:jmap:mail";
+pub const SUBMISSION_CAPABILITY: &str = "urn:ietf:params:jmap:submission";
+
+pub const SUPPORTED_CAPABILITIES: [&str; 3] =
+    [CORE_CAPABILITY, MAIL_CAPABILITY, SUBMISSION_CAPABILITY];
+
+/// A JMAP Request Object [RFC 8620 Section 3.3](https://www.rfc-editor.org/rfc/rfc8620#section-3.3)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub using: Vec<String>,
+    pub method_calls: Vec<Invocation>,
+    #[serde(default)]
+    pub created_ids: Option<HashMap<String, String>>,
+}
+
+/// `[name, arguments, method call id]` [RFC 8620 Section 3.2](https://www.rfc-editor.org/rfc/rfc8620#section-3.2)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Invocation(pub String, pub Value, pub String);
+
+impl Invocation {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+    pub fn arguments(&self) -> &Value {
+        &self.1
+    }
+    pub fn call_id(&self) -> &str {
+        &self.2
+    }
+}
+
+/// A JMAP Response Object [RFC 8620 Section 3.4](https://www.rfc-editor.org/rfc/rfc8620#section-3.4)
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub method_responses: Vec<Invocation>,
+    pub session_state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_ids: Option<HashMap<String, String>>,
+}
+
+/// Request-level errors [RFC 8620 Section 3.6.1](https://www.rfc-editor.org/rfc/rfc8620#section-3.6.1)
+///
+/// These are returned as a `application/problem+json` body instead of a Response Object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    UnknownCapability(String),
+    NotJSON,
+    NotRequest(String),
+    Limit(&'static str),
+}
+impl RequestError {
+    pub fn problem_type(&self) -> &'static str {
+        match self {
+            RequestError::UnknownCapability(_) => "urn:ietf:params:jmap:error:unknownCapability",
+            RequestError::NotJSON => "urn:ietf:params:jmap:error:notJSON",
+            RequestError::NotRequest(_) => "urn:ietf:params:jmap:error:notRequest",
+            RequestError::Limit(_) => "urn:ietf:params:jmap:error:limit",
+        }
+    }
+    pub fn to_problem(&self) -> Value {
+        let mut problem = json!({
+            "type": self.problem_type(),
+            "status": 400,
+        });
+        match self {
+            RequestError::UnknownCapability(capability) => {
+                problem["detail"] = format!("Unknown capability: {}", capability).into();
+            }
+            RequestError::NotJSON => {
+                problem["detail"] = "The request body was not valid JSON".into();
+            }
+            RequestError::NotRequest(error) => {
+                problem["detail"] = error.clone().into();
+            }
+            RequestError::Limit(limit) => {
+                problem["limit"] = (*limit).into();
+                problem["detail"] = format!("The request exceeded the {} limit", limit).into();
+            }
+        }
+        problem
+    }
+}
+
+/// Method-level error types [RFC 8620 Section 3.6.2](https://www.rfc-editor.org/rfc/rfc8620#section-3.6.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, AsRefStr, IntoStaticStr, Display)]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
+pub enum MethodErrorType {
+    ServerUnavailable,
+    ServerFail,
+    UnknownMethod,
+    InvalidArguments,
+    AccountNotFound,
+    AccountNotSupportedByMethod,
+    AccountReadOnly,
+    Forbidden,
+    /// The filter of a /query method can not be processed
+    UnsupportedFilter,
+    /// The sort of a /query method can not be processed
+    UnsupportedSort,
+    InvalidResultReference,
+    /// The changes since the state are not known. The client has to fetch everything again
+    CannotCalculateChanges,
+    /// `ifInState` of a /set method did not match
+    StateMismatch,
+    /// More ids than `maxObjectsInGet` or `maxObjectsInSet`
+    RequestTooLarge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MethodError {
+    #[serde(rename = "type")]
+    pub error_type: MethodErrorType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+impl MethodError {
+    pub fn new(error_type: MethodErrorType) -> Self {
+        Self {
+            error_type,
+            description: None,
+        }
+    }
+    pub fn with_description(error_type: MethodErrorType, description: impl Into<String>) -> Self {
+        Self {
+            error_type,
+            description: Some(description.into()),
+        }
+    }
+    pub fn into_invocation(self, call_id: impl Into<String>) -> Invocation {
+        Invocation(
+            "error".to_string(),
+            serde_json::to_value(self).expect("Failed to serialize MethodError. This is a bug."),
+            call_id.into(),
+        )
+    }
+}
+
+/// `{"resultOf": "c1", "name": "Email/query", "path": "/ids"}`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResultReference {
+    result_of: String,
+    name: String,
+    path: String,
+}
+
+/// Replaces the `#name` arguments by the values they reference
+/// [RFC 8620 Section 3.7](https://www.rfc-editor.org/rfc/rfc8620#section-3.7)
+pub fn resolve_references(
+    arguments: &Value,
+    responses: &[Invocation],
+) -> Result<Value, MethodError> {
+    let Some(object) = arguments.as_object() else {
+        return Ok(arguments.clone());
+    };
+    let mut resolved = serde_json::Map::with_capacity(object.len());
+    for (name, value) in object {
+        let Some(name) = name.strip_prefix('#') else {
+            resolved.insert(name.clone(), value.clone());
+            continue;
+        };
+        if object.contains_key(name) {
+            return Err(MethodError::with_description(
+                MethodErrorType::InvalidArguments,
+                format!("{name} and #{name} are both given"),
+            ));
+        }
+        let invalid = |description: String| {
+            MethodError::with_description(MethodErrorType::InvalidResultReference, description)
+        };
+        let reference: ResultReference =
+            serde_json::from_value(value.clone()).map_err(|error| invalid(error.to_string()))?;
+        let response = responses
+            .iter()
+            .find(|response| response.call_id() == reference.result_of)
+            .filter(|response| response.name() == reference.name)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "No {} response for {}",
+                    reference.name, reference.result_of
+                ))
+            })?;
+        let value = evaluate_pointer(response.arguments(), &reference.path)
+            .ok_or_else(|| invalid(format!("{} does not match the result", reference.path)))?;
+        resolved.insert(name.to_string(), value);
+    }
+    Ok(Value::Object(resolved))
+}
+
+/// A JSON pointer where `*` maps over an array. Arrays it returns are flattened
+fn evaluate_pointer(value: &Value, path: &str) -> Option<Value> {
+    let Some(path) = path.strip_prefix('/') else {
+        return path.is_empty().then(|| value.clone());
+    };
+    let (token, rest) = match path.split_once('/') {
+        Some((token, rest)) => (token, format!("/{rest}")),
+        None => (path, String::new()),
+    };
+    let token = token.replace("~1", "/").replace("~0", "~");
+    match value {
+        Value::Array(items) if token == "*" => {
+            let mut values = Vec::with_capacity(items.len());
+            for item in items {
+                match evaluate_pointer(item, &rest)? {
+                    Value::Array(item) => values.extend(item),
+                    item => values.push(item),
+                }
+            }
+            Some(Value::Array(values))
+        }
+        Value::Array(items) => evaluate_pointer(items.get(token.parse::<usize>().ok()?)?, &rest),
+        Value::Object(object) => evaluate_pointer(object.get(&token)?, &rest),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::request::{resolve_references, Invocation, MethodError, MethodErrorType, Request};
+
+    #[test]
+    pub fn test_parse_request() {
+        let request: Request = serde_json::from_value(json!({
+            "using": ["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail"],
+            "methodCalls": [
+                ["Core/echo", {"hello": true}, "c1"],
+                ["Mailbox/get", {"accountId": "A13824"}, "c2"]
+            ]
+        }))
+        .unwrap();
+        assert_eq!(request.method_calls.len(), 2);
+        assert_eq!(request.method_calls[0].name(), "Core/echo");
+        assert_eq!(request.method_calls[1].call_id(), "c2");
+    }
+    #[test]
+    pub fn test_method_error() {
+        let invocation = MethodError::new(MethodErrorType::UnknownMethod).into_invocation("c1");
+        assert_eq!(
+            invocation,
+            Invocation(
+                "error".to_string(),
+                json!({"type": "unknownMethod"}),
+                "c1".to_string()
+            )
+        );
+    }
+    #[test]
+    pub fn test_resolve_references() {
+        let responses = [
+            Invocation(
+                "Email/query".to_string(),
+                json!({"ids": ["e1", "e2"]}),
+                "c1".to_string(),
+            ),
+            Invocation(
+                "Email/get".to_string(),
+                json!({"list": [{"threadId": "t1"}, {"threadId": "t2"}]}),
+                "c2".to_string(),
+            ),
+        ];
+        let resolved = resolve_references(
+            &json!({
+                "accountId": "a",
+                "#ids": {"resultOf": "c2", "name": "Email/get", "path": "/list/*/threadId"}
+            }),
+            &responses,
+        )
+        .unwrap();
+        assert_eq!(resolved, json!({"accountId": "a", "ids": ["t1", "t2"]}));
+        let resolved = resolve_references(
+            &json!({"#ids": {"resultOf": "c1", "name": "Email/query", "path": "/ids"}}),
+            &responses,
+        )
+        .unwrap();
+        assert_eq!(resolved, json!({"ids": ["e1", "e2"]}));
+
+        for arguments in [
+            json!({"#ids": {"resultOf": "c1", "name": "Email/get", "path": "/ids"}}),
+            json!({"#ids": {"resultOf": "c3", "name": "Email/query", "path": "/ids"}}),
+            json!({"#ids": {"resultOf": "c1", "name": "Email/query", "path": "/list"}}),
+        ] {
+            let error = resolve_references(&arguments, &responses).unwrap_err();
+            assert_eq!(error.error_type, MethodErrorType::InvalidResultReference);
+        }
+        let error = resolve_references(
+            &json!({"ids": [], "#ids": {"resultOf": "c1", "name": "Email/query", "path": "/ids"}}),
+            &responses,
+        )
+        .unwrap_err();
+        assert_eq!(error.error_type, MethodErrorType::InvalidArguments);
+    }
+}
@@ -0,0 +1,109 @@
+use ahash::{HashMap, HashMapExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::jmap_config::JMAPConfig;
+use crate::request::{CORE_CAPABILITY, MAIL_CAPABILITY, SUBMISSION_CAPABILITY};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionAccount {
+    pub name: String,
+    pub is_personal: bool,
+    pub is_read_only: bool,
+    pub account_capabilities: HashMap<&'static str, Value>,
+}
+
+/// The JMAP Session Resource [RFC 8620 Section 2](https://www.rfc-editor.org/rfc/rfc8620#section-2)
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub capabilities: HashMap<&'static str, Value>,
+    pub accounts: HashMap<String, SessionAccount>,
+    pub primary_accounts: HashMap<&'static str, String>,
+    pub username: String,
+    pub api_url: String,
+    pub download_url: String,
+    pub upload_url: String,
+    pub event_source_url: String,
+    pub state: String,
+}
+
+impl Session {
+    /// Builds the Session for a single personal account
+    ///
+    /// The account id is the mailbox id of the account. So it is also used as the session state.
+    pub fn new(config: &JMAPConfig, username: String, account_id: String) -> Self {
+        let limits = &config.limits;
+        let base_url = config.base_url.trim_end_matches('/');
+
+        let mut capabilities = HashMap::new();
+        capabilities.insert(
+            CORE_CAPABILITY,
+            json!({
+                "maxSizeUpload": limits.max_size_upload,
+                "maxConcurrentUpload": limits.max_concurrent_upload,
+                "maxSizeRequest": limits.max_size_request,
+                "maxConcurrentRequests": limits.max_concurrent_requests,
+                "maxCallsInRequest": limits.max_calls_in_request,
+                "maxObjectsInGet": limits.max_objects_in_get,
+                "maxObjectsInSet": limits.max_objects_in_set,
+                "collationAlgorithms": ["i;ascii-casemap", "i;octet"],
+            }),
+        );
+        capabilities.insert(MAIL_CAPABILITY, json!({}));
+        capabilities.insert(SUBMISSION_CAPABILITY, json!({}));
+
+        let mut account_capabilities = HashMap::new();
+        account_capabilities.insert(
+            MAIL_CAPABILITY,
+            json!({
+                "maxMailboxesPerEmail": null,
+                "maxMailboxDepth": null,
+                "maxSizeMailboxName": 255,
+                "maxSizeAttachmentsPerEmail": limits.max_size_upload,
+                "emailQuerySortOptions": ["receivedAt"],
+                "mayCreateTopLevelMailbox": true,
+            }),
+        );
+        account_capabilities.insert(
+            SUBMISSION_CAPABILITY,
+            json!({
+                "maxDelayedSend": 0,
+                "submissionExtensions": {},
+            }),
+        );
+
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            account_id.clone(),
+            SessionAccount {
+                name: username.clone(),
+                is_personal: true,
+                is_read_only: false,
+                account_capabilities,
+            },
+        );
+        let mut primary_accounts = HashMap::new();
+        primary_accounts.insert(MAIL_CAPABILITY, account_id.clone());
+        primary_accounts.insert(SUBMISSION_CAPABILITY, account_id.clone());
+
+        Session {
+            capabilities,
+            accounts,
+            primary_accounts,
+            username,
+            api_url: format!("{}/jmap/api", base_url),
+            download_url: format!(
+                "{}/jmap/download/{{accountId}}/{{blobId}}/{{name}}?accept={{type}}",
+                base_url
+            ),
+            upload_url: format!("{}/jmap/upload/{{accountId}}", base_url),
+            event_source_url: format!(
+                "{}/jmap/eventsource?types={{types}}&closeafter={{closeafter}}&ping={{ping}}",
+                base_url
+            ),
+            state: account_id,
+        }
+    }
+}
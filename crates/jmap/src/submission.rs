@@ -0,0 +1,335 @@
+//! EmailSubmission/set [RFC 8621 Section 7.5](https://www.rfc-editor.org/rfc/rfc8621#section-7.5)
+//!
+//! Submissions are meant to be handed to the outbound SMTP queue, which does not exist yet.
+//! Until it does only recipients of this server can be sent to. Their messages are delivered to their INBOX
+//! like the SMTP service does and the submission is final at once. Any other recipient fails the submission
+//! with `forbiddenToSend`, listing them in `invalidRecipients`
+use ahash::HashMap;
+use chrono::Utc;
+use mail_parser::{Address, MessageParser};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use directories::directory_type::Directory;
+use directories::recipient::resolve_recipient;
+use storages::folders::{Delivery, NewMessage, StorageError, INBOX};
+use storages::storage_type::Storage;
+use utils::helper_types::EmailAddress;
+
+use crate::ids::parse_email_id;
+use crate::methods::{parse_arguments, resolve_id, set_error, storage_error, MethodContext};
+use crate::request::{MethodError, MethodErrorType};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnvelopeAddress {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Envelope {
+    mail_from: EnvelopeAddress,
+    rcpt_to: Vec<EnvelopeAddress>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Submission {
+    email_id: String,
+    #[serde(default)]
+    envelope: Option<Envelope>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetArguments {
+    #[serde(default)]
+    create: Option<HashMap<String, Submission>>,
+    #[serde(default)]
+    update: Option<HashMap<String, Value>>,
+    #[serde(default)]
+    destroy: Option<Vec<String>>,
+    #[serde(default)]
+    on_success_update_email: Option<Value>,
+    #[serde(default)]
+    on_success_destroy_email: Option<Value>,
+}
+
+/// The addresses of the headers
+fn header_addresses<'a>(
+    addresses: impl IntoIterator<Item = Option<&'a Address<'a>>>,
+) -> Vec<String> {
+    addresses
+        .into_iter()
+        .flatten()
+        .flat_map(|address| address.iter())
+        .filter_map(|address| address.address())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Removes the Bcc header fields so recipients do not see them
+fn strip_bcc(contents: &[u8]) -> Vec<u8> {
+    let header_end = contents
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map_or(contents.len(), |end| end + 2);
+    let (headers, body) = contents.split_at(header_end);
+    let mut stripped = Vec::with_capacity(contents.len());
+    let mut in_bcc = false;
+    for line in headers.split_inclusive(|c| *c == b'\n') {
+        let continuation = line.first().is_some_and(|c| *c == b' ' || *c == b'\t');
+        if !continuation {
+            in_bcc = line.len() >= 4 && line[..4].eq_ignore_ascii_case(b"bcc:");
+        }
+        if !in_bcc {
+            stripped.extend_from_slice(line);
+        }
+    }
+    stripped.extend_from_slice(body);
+    stripped
+}
+
+/// Only envelopes of local recipients can be sent until there is an outbound queue
+pub async fn set<D: Directory, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    arguments: &Value,
+    created_ids: &mut HashMap<String, String>,
+) -> Result<Value, MethodError> {
+    let arguments: SetArguments = parse_arguments(arguments)?;
+    let on_success = [
+        &arguments.on_success_update_email,
+        &arguments.on_success_destroy_email,
+    ];
+    if on_success
+        .iter()
+        .any(|value| value.as_ref().is_some_and(|value| !value.is_null()))
+    {
+        return Err(MethodError::with_description(
+            MethodErrorType::InvalidArguments,
+            "onSuccessUpdateEmail and onSuccessDestroyEmail are not supported",
+        ));
+    }
+    let objects = arguments.create.as_ref().map_or(0, |create| create.len())
+        + arguments.update.as_ref().map_or(0, |update| update.len())
+        + arguments
+            .destroy
+            .as_ref()
+            .map_or(0, |destroy| destroy.len());
+    if objects as u64 > context.limits.max_objects_in_set {
+        return Err(MethodError::new(MethodErrorType::RequestTooLarge));
+    }
+
+    let mut created = Map::new();
+    let mut not_created = Map::new();
+    for (creation_id, submission) in arguments.create.unwrap_or_default() {
+        match submit(context, submission, created_ids).await? {
+            Ok(id) => {
+                created_ids.insert(creation_id.clone(), id.clone());
+                created.insert(
+                    creation_id,
+                    json!({"id": id, "undoStatus": "final", "sendAt": Utc::now().to_rfc3339()}),
+                );
+            }
+            Err(error) => {
+                not_created.insert(creation_id, error);
+            }
+        }
+    }
+    let mut not_updated = Map::new();
+    for (id, _) in arguments.update.unwrap_or_default() {
+        not_updated.insert(id, set_error("cannotUnsend", "Submissions are final"));
+    }
+    let mut not_destroyed = Map::new();
+    for id in arguments.destroy.unwrap_or_default() {
+        not_destroyed.insert(id, set_error("notFound", "Submissions are not kept"));
+    }
+    Ok(json!({
+        "accountId": context.account_id(),
+        "oldState": null,
+        "newState": "0",
+        "created": created,
+        "notCreated": not_created,
+        "updated": {},
+        "notUpdated": not_updated,
+        "destroyed": [],
+        "notDestroyed": not_destroyed,
+    }))
+}
+
+/// Delivers one submission. The id of the submission or a SetError
+async fn submit<D: Directory, S: Storage>(
+    context: &MethodContext<'_, D, S>,
+    submission: Submission,
+    created_ids: &HashMap<String, String>,
+) -> Result<Result<String, Value>, MethodError> {
+    let mailbox_id = context.account.mailbox_id;
+    let email = resolve_id(&submission.email_id, created_ids)
+        .as_deref()
+        .and_then(parse_email_id);
+    let Some((folder, uid)) = email else {
+        return Ok(Err(set_error("invalidEmail", "No such email")));
+    };
+    let contents = match context
+        .storage
+        .read_message(mailbox_id, folder, uid)
+        .await
+        .map_err(storage_error)?
+    {
+        Ok(contents) => contents,
+        Err(StorageError::MessageNotFound(_)) | Err(StorageError::FolderNotFound(_)) => {
+            return Ok(Err(set_error("invalidEmail", "No such email")));
+        }
+        Err(error) => return Err(storage_error(error)),
+    };
+    let Some(message) = MessageParser::default().parse(&contents) else {
+        return Ok(Err(set_error(
+            "invalidEmail",
+            "The email can not be parsed",
+        )));
+    };
+    let (mail_from, rcpt_to) = match submission.envelope {
+        Some(envelope) => (
+            Some(envelope.mail_from.email),
+            envelope
+                .rcpt_to
+                .into_iter()
+                .map(|address| address.email)
+                .collect(),
+        ),
+        None => (
+            header_addresses([message.sender().or(message.from())])
+                .into_iter()
+                .next(),
+            header_addresses([message.to(), message.cc(), message.bcc()]),
+        ),
+    };
+
+    let own_address = |address: &str| {
+        context
+            .account
+            .addresses
+            .iter()
+            .any(|own| own.email_address.eq_ignore_ascii_case(address))
+    };
+    let Some(mail_from) = mail_from.filter(|mail_from| own_address(mail_from)) else {
+        return Ok(Err(set_error(
+            "forbiddenMailFrom",
+            "Mail can only be sent from the addresses of the account",
+        )));
+    };
+    if rcpt_to.is_empty() {
+        return Ok(Err(set_error(
+            "noRecipients",
+            "The email has no recipients",
+        )));
+    }
+    let mut deliveries = Vec::with_capacity(rcpt_to.len());
+    let mut invalid = Vec::new();
+    let mut remote = Vec::new();
+    for recipient in rcpt_to {
+        let Ok(address) = EmailAddress::new(recipient.clone()) else {
+            invalid.push(recipient);
+            continue;
+        };
+        let resolved = resolve_recipient(context.directory, context.domains, &address)
+            .await
+            .map_err(|error| {
+                MethodError::with_description(MethodErrorType::ServerFail, error.to_string())
+            })?;
+        match resolved {
+            Some(resolved) if resolved.account.active => deliveries.push(Delivery {
+                mailbox_id: resolved.account.mailbox_id,
+                folder: INBOX.to_string(),
+            }),
+            _ => remote.push(recipient),
+        }
+    }
+    if !invalid.is_empty() {
+        let mut error = set_error("invalidRecipients", "Not email addresses");
+        error["invalidRecipients"] = json!(invalid);
+        return Ok(Err(error));
+    }
+    if !remote.is_empty() {
+        let mut error = set_error(
+            "forbiddenToSend",
+            format!(
+                "There is no outbound queue for {mail_from} to send through, only recipients of this server can be sent to"
+            ),
+        );
+        error["invalidRecipients"] = json!(remote);
+        return Ok(Err(error));
+    }
+
+    let message = NewMessage {
+        flags: vec![],
+        internal_date: Utc::now().timestamp(),
+        contents: strip_bcc(&contents),
+    };
+    let results = deliver(context.storage, deliveries, message).await?;
+    if let Some(error) = results.into_iter().find_map(Result::err) {
+        return Err(storage_error(error));
+    }
+    Ok(Ok(format!("s{}", Uuid::new_v4().simple())))
+}
+
+/// Mailboxes without an [INBOX] get one and a second call
+async fn deliver<S: Storage>(
+    storage: &S,
+    deliveries: Vec<Delivery>,
+    message: NewMessage,
+) -> Result<Vec<Result<(), StorageError>>, MethodError> {
+    let mut results: Vec<Result<(), StorageError>> = storage
+        .deliver_message(deliveries.clone(), message.clone())
+        .await
+        .map_err(storage_error)?
+        .into_iter()
+        .map(|result| result.map(|_| ()))
+        .collect();
+    let missing: Vec<usize> = results
+        .iter()
+        .enumerate()
+        .filter(|(_, result)| matches!(result, Err(StorageError::FolderNotFound(_))))
+        .map(|(index, _)| index)
+        .collect();
+    if missing.is_empty() {
+        return Ok(results);
+    }
+    let mut retry = Vec::with_capacity(missing.len());
+    for index in &missing {
+        let delivery = deliveries[*index].clone();
+        match storage
+            .create_folder(delivery.mailbox_id, delivery.folder.clone())
+            .await
+            .map_err(storage_error)?
+        {
+            Ok(_) | Err(StorageError::FolderExists(_)) => {}
+            Err(error) => return Err(storage_error(error)),
+        }
+        retry.push(delivery);
+    }
+    let retried = storage
+        .deliver_message(retry, message)
+        .await
+        .map_err(storage_error)?;
+    for (index, result) in missing.into_iter().zip(retried) {
+        results[index] = result.map(|_| ());
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::submission::strip_bcc;
+
+    #[test]
+    pub fn test_strip_bcc() {
+        let message = b"From: a@example.com\r\nBCC: b@example.com,\r\n c@example.com\r\nTo: d@example.com\r\n\r\nBcc: body\r\n";
+        assert_eq!(
+            strip_bcc(message),
+            b"From: a@example.com\r\nTo: d@example.com\r\n\r\nBcc: body\r\n"
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! Blobs uploaded to `/jmap/upload/{accountId}` for Email/import
+//!
+//! They are kept in memory and only until [UPLOAD_LIFETIME] has passed, as
+//! [RFC 8620 Section 6.1](https://www.rfc-editor.org/rfc/rfc8620#section-6.1) allows
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ahash::HashMap;
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+pub const UPLOAD_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+struct Upload {
+    uploaded: Instant,
+    contents: Arc<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct Uploads(Mutex<HashMap<(Uuid, String), Upload>>);
+impl Uploads {
+    /// Returns the id the upload has in a [BlobId::Upload](crate::ids::BlobId::Upload).
+    /// Expired uploads are removed
+    pub fn add(&self, mailbox_id: Uuid, contents: Vec<u8>) -> String {
+        let id = Uuid::new_v4().simple().to_string();
+        let mut uploads = self.0.lock();
+        uploads.retain(|_, upload| upload.uploaded.elapsed() < UPLOAD_LIFETIME);
+        uploads.insert(
+            (mailbox_id, id.clone()),
+            Upload {
+                uploaded: Instant::now(),
+                contents: Arc::new(contents),
+            },
+        );
+        id
+    }
+    /// Only the account that uploaded the blob can use it
+    pub fn get(&self, mailbox_id: Uuid, id: &str) -> Option<Arc<Vec<u8>>> {
+        self.0
+            .lock()
+            .get(&(mailbox_id, id.to_string()))
+            .filter(|upload| upload.uploaded.elapsed() < UPLOAD_LIFETIME)
+            .map(|upload| upload.contents.clone())
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::{bail, Context};
+use clap::{Args, Subcommand};
+
+use directories::directory_type::Directory;
+use utils::account::Account;
+use utils::admin::{AccountUpdate, NewAccount};
+use utils::common_types::{AccountType, EmailType};
+
+use crate::output::{print_list, print_message, print_one, yes_no, OutputFormat, Row};
+
+#[derive(Debug, Subcommand)]
+pub enum AccountCommand {
+    List,
+    Show {
+        username: String,
+    },
+    Create(CreateAccount),
+    /// Changes the name, description or type of an account
+    Update {
+        username: String,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        description: Option<String>,
+        /// Individual
+        #[arg(long = "type")]
+        account_type: Option<AccountType>,
+    },
+    /// Changes the username. The account keeps its mail, addresses and groups
+    Rename {
+        username: String,
+        new_username: String,
+    },
+    Enable {
+        username: String,
+    },
+    /// Stops the account from logging in or receiving mail
+    Disable {
+        username: String,
+    },
+    /// Deletes the account with its addresses, app passwords and group memberships
+    Delete {
+        username: String,
+    },
+    /// Sets the password of an account
+    Passwd {
+        username: String,
+        /// Prompted for if not given
+        #[arg(long)]
+        password: Option<String>,
+    },
+}
+#[derive(Debug, Args)]
+pub struct CreateAccount {
+    username: String,
+    /// The display name. The username if not set
+    #[arg(long)]
+    name: Option<String>,
+    #[arg(long)]
+    description: Option<String>,
+    /// Individual
+    #[arg(long = "type", default_value_t)]
+    account_type: AccountType,
+    /// The primary email address
+    #[arg(long)]
+    address: Option<String>,
+    /// Prompted for if not given
+    #[arg(long)]
+    password: Option<String>,
+}
+
+impl Row for Account {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Username",
+            "Name",
+            "Type",
+            "Active",
+            "Primary Address",
+            "Aliases",
+            "Groups",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let addresses = |email_type: EmailType| {
+            self.addresses
+                .iter()
+                .filter(|address| address.email_type == email_type)
+                .map(|address| address.email_address.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        vec![
+            self.username.clone(),
+            self.name.clone(),
+            self.account_type.to_string(),
+            yes_no(self.active),
+            addresses(EmailType::Primary),
+            addresses(EmailType::Alias),
+            self.groups.join(", "),
+        ]
+    }
+}
+
+/// The password from the command line or a prompt that does not echo
+fn password(password: Option<String>) -> anyhow::Result<String> {
+    if let Some(password) = password {
+        return Ok(password);
+    }
+    let password = rpassword::prompt_password("Password: ")?;
+    if password != rpassword::prompt_password("Repeat password: ")? {
+        bail!("The passwords do not match");
+    }
+    Ok(password)
+}
+
+pub async fn run<D: Directory>(
+    directory: &D,
+    command: AccountCommand,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match command {
+        AccountCommand::List => {
+            let accounts = directory.list_accounts().await??;
+            print_list(format, &accounts)
+        }
+        AccountCommand::Show { username } => {
+            let account = directory
+                .get_account(username.clone())
+                .await?
+                .with_context(|| format!("Account {username} does not exist"))?;
+            print_one(format, &account)
+        }
+        AccountCommand::Create(account) => {
+            let password = password(account.password)?;
+            let created = directory
+                .create_account(NewAccount {
+                    username: account.username,
+                    name: account.name,
+                    description: account.description,
+                    password,
+                    account_type: account.account_type,
+                    primary_address: account.address,
+                })
+                .await??;
+            print_message(format, format!("Created {}", created.username))
+        }
+        AccountCommand::Update {
+            username,
+            name,
+            description,
+            account_type,
+        } => {
+            let update = AccountUpdate {
+                name,
+                description,
+                account_type,
+            };
+            if update == AccountUpdate::default() {
+                bail!("Nothing to update");
+            }
+            directory.update_account(username.clone(), update).await??;
+            print_message(format, format!("Updated {username}"))
+        }
+        AccountCommand::Rename {
+            username,
+            new_username,
+        } => {
+            directory
+                .rename_account(username.clone(), new_username.clone())
+                .await??;
+            print_message(format, format!("Renamed {username} to {new_username}"))
+        }
+        AccountCommand::Enable { username } => {
+            directory
+                .set_account_active(username.clone(), true)
+                .await??;
+            print_message(format, format!("Enabled {username}"))
+        }
+        AccountCommand::Disable { username } => {
+            directory
+                .set_account_active(username.clone(), false)
+                .await??;
+            print_message(format, format!("Disabled {username}"))
+        }
+        AccountCommand::Delete { username } => {
+            if !directory.delete_account(username.clone()).await?? {
+                bail!("Account {username} does not exist");
+            }
+            print_message(format, format!("Deleted {username}"))
+        }
+        AccountCommand::Passwd { username, password } => {
+            let password = self::password(password)?;
+            directory.set_password(username.clone(), password).await??;
+            print_message(format, format!("Changed the password of {username}"))
+        }
+    }
+}
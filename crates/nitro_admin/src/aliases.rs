@@ -0,0 +1,80 @@
+use anyhow::{bail, Context};
+use clap::Subcommand;
+use serde::Serialize;
+
+use directories::directory_type::Directory;
+use utils::admin::AccountAddress;
+use utils::common_types::EmailType;
+
+use crate::output::{print_list, print_message, OutputFormat, Row};
+
+#[derive(Debug, Subcommand)]
+pub enum AliasCommand {
+    /// The addresses of an account
+    List {
+        username: String,
+    },
+    Add {
+        username: String,
+        email_address: String,
+        /// Makes the address the primary address. The old one becomes an alias
+        #[arg(long)]
+        primary: bool,
+    },
+    Remove {
+        email_address: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Address<'a>(&'a AccountAddress);
+impl Row for Address<'_> {
+    fn headers() -> Vec<&'static str> {
+        vec!["Address", "Type"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.0.email_address.clone(), self.0.email_type.to_string()]
+    }
+}
+
+pub async fn run<D: Directory>(
+    directory: &D,
+    command: AliasCommand,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match command {
+        AliasCommand::List { username } => {
+            let account = directory
+                .get_account(username.clone())
+                .await?
+                .with_context(|| format!("Account {username} does not exist"))?;
+            let addresses: Vec<Address> = account.addresses.iter().map(Address).collect();
+            print_list(format, &addresses)
+        }
+        AliasCommand::Add {
+            username,
+            email_address,
+            primary,
+        } => {
+            let email_type = if primary {
+                EmailType::Primary
+            } else {
+                EmailType::Alias
+            };
+            directory
+                .add_email_address(username.clone(), email_address.clone(), email_type)
+                .await??;
+            print_message(format, format!("Added {email_address} to {username}"))
+        }
+        AliasCommand::Remove { email_address } => {
+            if !directory
+                .remove_email_address(email_address.clone())
+                .await??
+            {
+                bail!("No account has the address {email_address}");
+            }
+            print_message(format, format!("Removed {email_address}"))
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use clap::Subcommand;
+use serde::Serialize;
+
+use directories::caching::CacheConfig;
+use directories::chain::ChainConfig;
+use directory_file::file_config::FileDirectoryConfig;
+use directory_ldap::ldap_config::LdapConfig;
+use directory_sql::database_config::DatabaseConfig;
+use imap::imap_config::IMAPConfig;
+use jmap::jmap_config::JMAPConfig;
+use smtp::smtp_config::SMTPConfig;
+use storage_search::search_config::SearchConfig;
+use storage_sql::storage_config::SqlStorageConfig;
+use utils::configs::dkim::DKIMConfig;
+use utils::configs::domain_configs::DomainConfiguration;
+use utils::configs::{Config, ConfigName};
+
+use crate::output::{print_list, OutputFormat, Row};
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Parses every config file in the directory
+    Validate {
+        /// The working directory of nitro_mail and its services
+        #[arg(long, default_value = ".")]
+        directory: PathBuf,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "snake_case")]
+enum ConfigStatus {
+    Valid,
+    /// The defaults are used
+    Missing,
+    Invalid(String),
+}
+#[derive(Debug, Serialize)]
+struct ConfigCheck {
+    file: &'static str,
+    #[serde(flatten)]
+    status: ConfigStatus,
+}
+impl Row for ConfigCheck {
+    fn headers() -> Vec<&'static str> {
+        vec!["File", "Status"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let status = match &self.status {
+            ConfigStatus::Valid => "valid".to_string(),
+            ConfigStatus::Missing => "missing, defaults are used".to_string(),
+            ConfigStatus::Invalid(error) => format!("invalid: {error}"),
+        };
+        vec![self.file.to_string(), status]
+    }
+}
+
+fn check<C: Config>(directory: &Path) -> Option<ConfigCheck> {
+    let ConfigName::Name(file) = C::config_name() else {
+        return None;
+    };
+    let path = directory.join(file);
+    let status = if !path.exists() {
+        ConfigStatus::Missing
+    } else {
+        match C::load_from_path(path) {
+            Ok(_) => ConfigStatus::Valid,
+            Err(error) => ConfigStatus::Invalid(error.to_string()),
+        }
+    };
+    Some(ConfigCheck { file, status })
+}
+
+pub async fn run(command: ConfigCommand, format: OutputFormat) -> anyhow::Result<()> {
+    let ConfigCommand::Validate { directory } = command;
+    let checks: Vec<ConfigCheck> = [
+        check::<SMTPConfig>(&directory),
+        check::<IMAPConfig>(&directory),
+        check::<JMAPConfig>(&directory),
+        check::<DomainConfiguration>(&directory),
+        check::<DKIMConfig>(&directory),
+        check::<CacheConfig>(&directory),
+        check::<ChainConfig>(&directory),
+        check::<DatabaseConfig>(&directory),
+        check::<LdapConfig>(&directory),
+        check::<FileDirectoryConfig>(&directory),
+        check::<SqlStorageConfig>(&directory),
+        check::<SearchConfig>(&directory),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    print_list(format, &checks)?;
+    let invalid = checks
+        .iter()
+        .filter(|check| matches!(check.status, ConfigStatus::Invalid(_)))
+        .count();
+    if invalid > 0 {
+        bail!("{invalid} config files are invalid");
+    }
+    Ok(())
+}
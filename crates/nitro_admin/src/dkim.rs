@@ -0,0 +1,128 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use clap::Subcommand;
+use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::Serialize;
+
+use crate::output::{print_one, OutputFormat, Row};
+
+/// RFC 8301 forbids shorter keys
+const MIN_BITS: usize = 1024;
+
+#[derive(Debug, Subcommand)]
+pub enum DkimCommand {
+    /// Creates an RSA key and prints the DNS record for it
+    Generate {
+        #[arg(long)]
+        domain: String,
+        #[arg(long, default_value = "default")]
+        selector: String,
+        #[arg(long, default_value_t = 2048)]
+        bits: usize,
+        /// Where the PKCS#8 private key is written. `<selector>.<domain>.pem` if not set
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct DkimKey {
+    private_key: PathBuf,
+    /// The name of the TXT record
+    dns_name: String,
+    dns_record: String,
+}
+impl Row for DkimKey {
+    fn headers() -> Vec<&'static str> {
+        vec!["Private Key", "DNS Name", "DNS Record"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.private_key.display().to_string(),
+            self.dns_name.clone(),
+            self.dns_record.clone(),
+        ]
+    }
+}
+
+fn dns_name(selector: &str, domain: &str) -> String {
+    format!("{selector}._domainkey.{domain}")
+}
+fn dns_record(public_key: &RsaPublicKey) -> anyhow::Result<String> {
+    let der = public_key.to_public_key_der()?;
+    Ok(format!(
+        "v=DKIM1; k=rsa; p={}",
+        STANDARD.encode(der.as_bytes())
+    ))
+}
+
+/// Never replaces an existing key. Only the owner can read the new one
+pub(crate) fn write_private_key(path: &PathBuf, pem: &str) -> anyhow::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Unable to create {}", path.display()))?;
+    file.write_all(pem.as_bytes())?;
+    Ok(())
+}
+
+pub async fn run(command: DkimCommand, format: OutputFormat) -> anyhow::Result<()> {
+    let DkimCommand::Generate {
+        domain,
+        selector,
+        bits,
+        output,
+    } = command;
+    if bits < MIN_BITS {
+        bail!("DKIM keys must have at least {MIN_BITS} bits");
+    }
+    let private_key =
+        tokio::task::spawn_blocking(move || RsaPrivateKey::new(&mut rand::thread_rng(), bits))
+            .await??;
+    let path = output.unwrap_or_else(|| PathBuf::from(format!("{selector}.{domain}.pem")));
+    write_private_key(&path, &private_key.to_pkcs8_pem(LineEnding::LF)?)?;
+    let key = DkimKey {
+        private_key: path,
+        dns_name: dns_name(&selector, &domain),
+        dns_record: dns_record(&RsaPublicKey::from(&private_key))?,
+    };
+    print_one(format, &key)
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use rsa::pkcs8::DecodePublicKey;
+    use rsa::{RsaPrivateKey, RsaPublicKey};
+
+    use crate::dkim::{dns_name, dns_record, MIN_BITS};
+
+    #[test]
+    pub fn test_dns_record() {
+        assert_eq!(
+            dns_name("mail", "example.com"),
+            "mail._domainkey.example.com"
+        );
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), MIN_BITS).unwrap();
+        let public_key = RsaPublicKey::from(&private_key);
+        let record = dns_record(&public_key).unwrap();
+        let encoded = record.strip_prefix("v=DKIM1; k=rsa; p=").unwrap();
+        let decoded =
+            RsaPublicKey::from_public_key_der(&STANDARD.decode(encoded).unwrap()).unwrap();
+        assert_eq!(decoded, public_key);
+    }
+}
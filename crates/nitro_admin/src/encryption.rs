@@ -0,0 +1,242 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use clap::Subcommand;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::Serialize;
+use uuid::Uuid;
+
+use directories::directory_type::Directory;
+use storages::encryption::{EncryptionKey, KeyFile};
+use storages::storage_type::Storage;
+
+use crate::dkim::write_private_key;
+use crate::output::{print_one, OutputFormat, Row};
+use crate::storage;
+
+/// Recovery keys wrap 32 byte data keys. Shorter keys are not considered secure
+const MIN_RECOVERY_BITS: usize = 2048;
+
+#[derive(Debug, Subcommand)]
+pub enum EncryptionCommand {
+    /// Creates a master key file
+    GenerateKey {
+        /// Where the key file is written
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Creates the RSA key pair that data keys are also wrapped by. Keep the private key offline
+    GenerateRecoveryKey {
+        #[arg(long, default_value_t = 4096)]
+        bits: usize,
+        /// Where the PKCS#8 private key is written
+        #[arg(long)]
+        private_key: PathBuf,
+        /// Where the public key is written. Set it as `recovery_public_key`
+        #[arg(long)]
+        public_key: PathBuf,
+    },
+    /// Wraps every data key with the configured master key. Run after changing `master_key`
+    RotateMasterKey {
+        /// The working directory of the storage
+        #[arg(long, default_value = ".")]
+        directory: PathBuf,
+    },
+    /// Gives an account a new data key and seals its mail with it.
+    /// Accounts that are not encrypted are encrypted
+    RotateAccountKey {
+        username: String,
+        /// The working directory of the storage
+        #[arg(long, default_value = ".")]
+        directory: PathBuf,
+    },
+    /// Wraps every data key with the configured master key using the recovery private key.
+    /// For when the master key is lost
+    Recover {
+        /// The PKCS#8 private key from `generate-recovery-key`
+        #[arg(long)]
+        recovery_key: PathBuf,
+        /// The working directory of the storage
+        #[arg(long, default_value = ".")]
+        directory: PathBuf,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct GeneratedKey {
+    path: PathBuf,
+    fingerprint: String,
+}
+impl Row for GeneratedKey {
+    fn headers() -> Vec<&'static str> {
+        vec!["Path", "Fingerprint"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.path.display().to_string(), self.fingerprint.clone()]
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RecoveryKeyPair {
+    private_key: PathBuf,
+    public_key: PathBuf,
+}
+impl Row for RecoveryKeyPair {
+    fn headers() -> Vec<&'static str> {
+        vec!["Private Key", "Public Key"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.private_key.display().to_string(),
+            self.public_key.display().to_string(),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RewrappedKeys {
+    rewrapped: u64,
+}
+impl Row for RewrappedKeys {
+    fn headers() -> Vec<&'static str> {
+        vec!["Rewrapped Keys"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.rewrapped.to_string()]
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ResealedAccount {
+    username: String,
+    mailbox_id: Uuid,
+    messages: u64,
+}
+impl Row for ResealedAccount {
+    fn headers() -> Vec<&'static str> {
+        vec!["Username", "Mailbox", "Messages Sealed"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.username.clone(),
+            self.mailbox_id.to_string(),
+            self.messages.to_string(),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RecoveredKeys {
+    recovered: u64,
+    /// Mailboxes whose keys have no recovery copy
+    unrecoverable: Vec<Uuid>,
+}
+impl Row for RecoveredKeys {
+    fn headers() -> Vec<&'static str> {
+        vec!["Recovered Keys", "Unrecoverable Mailboxes"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let unrecoverable: Vec<String> = self.unrecoverable.iter().map(Uuid::to_string).collect();
+        vec![self.recovered.to_string(), unrecoverable.join(", ")]
+    }
+}
+
+/// Never replaces an existing file
+fn write_public_key(path: &PathBuf, pem: &str) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Unable to create {}", path.display()))?;
+    file.write_all(pem.as_bytes())?;
+    Ok(())
+}
+
+pub async fn run(command: EncryptionCommand, format: OutputFormat) -> anyhow::Result<()> {
+    match command {
+        EncryptionCommand::GenerateKey { output } => {
+            let key = EncryptionKey::generate();
+            let file = toml::to_string(&KeyFile {
+                key: key.to_base64(),
+            })?;
+            write_private_key(&output, &file)?;
+            let key = GeneratedKey {
+                path: output,
+                fingerprint: key.fingerprint(),
+            };
+            print_one(format, &key)
+        }
+        EncryptionCommand::GenerateRecoveryKey {
+            bits,
+            private_key,
+            public_key,
+        } => {
+            if bits < MIN_RECOVERY_BITS {
+                bail!("Recovery keys must have at least {MIN_RECOVERY_BITS} bits");
+            }
+            let key = tokio::task::spawn_blocking(move || {
+                RsaPrivateKey::new(&mut rand::thread_rng(), bits)
+            })
+            .await??;
+            write_private_key(&private_key, &key.to_pkcs8_pem(LineEnding::LF)?)?;
+            write_public_key(
+                &public_key,
+                &RsaPublicKey::from(&key).to_public_key_pem(LineEnding::LF)?,
+            )?;
+            let pair = RecoveryKeyPair {
+                private_key,
+                public_key,
+            };
+            print_one(format, &pair)
+        }
+        EncryptionCommand::RotateMasterKey { directory } => {
+            let rewrapped = storage(directory).await?.rewrap_mailbox_keys().await?;
+            print_one(format, &RewrappedKeys { rewrapped })
+        }
+        EncryptionCommand::RotateAccountKey {
+            username,
+            directory,
+        } => {
+            let account = crate::directory()
+                .await?
+                .get_account(username.clone())
+                .await?
+                .with_context(|| format!("Account {username} does not exist"))?;
+            let messages = storage(directory)
+                .await?
+                .rotate_mailbox_key(account.mailbox_id)
+                .await??;
+            let account = ResealedAccount {
+                username,
+                mailbox_id: account.mailbox_id,
+                messages,
+            };
+            print_one(format, &account)
+        }
+        EncryptionCommand::Recover {
+            recovery_key,
+            directory,
+        } => {
+            let pem = std::fs::read_to_string(&recovery_key)
+                .with_context(|| format!("Unable to read {}", recovery_key.display()))?;
+            let recovery_key = RsaPrivateKey::from_pkcs8_pem(&pem)?;
+            let recovery = storage(directory)
+                .await?
+                .recover_mailbox_keys(&recovery_key)
+                .await?;
+            let recovered = RecoveredKeys {
+                recovered: recovery.recovered,
+                unrecoverable: recovery.unrecoverable,
+            };
+            print_one(format, &recovered)
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::bail;
+use clap::{Args, Subcommand};
+use serde::Serialize;
+
+use directories::directory_type::Directory;
+use utils::admin::NewGroup;
+use utils::groups::{GroupType, PostingPolicy};
+
+use crate::output::{print_list, print_message, OutputFormat, Row};
+
+#[derive(Debug, Subcommand)]
+pub enum GroupCommand {
+    List,
+    Create(CreateGroup),
+    Delete {
+        name: String,
+    },
+    /// The usernames of the members
+    Members {
+        name: String,
+    },
+    AddMember {
+        name: String,
+        username: String,
+    },
+    RemoveMember {
+        name: String,
+        username: String,
+    },
+}
+#[derive(Debug, Args)]
+pub struct CreateGroup {
+    name: String,
+    /// List or Group
+    #[arg(long = "type", default_value_t)]
+    group_type: GroupType,
+    /// MembersOnly, Moderated or Open
+    #[arg(long, default_value_t)]
+    posting_policy: PostingPolicy,
+    /// Where mail for a list is sent
+    #[arg(long)]
+    list_address: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(transparent)]
+struct Name(String);
+impl Row for Name {
+    fn headers() -> Vec<&'static str> {
+        vec!["Name"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.0.clone()]
+    }
+}
+
+pub async fn run<D: Directory>(
+    directory: &D,
+    command: GroupCommand,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match command {
+        GroupCommand::List => {
+            let groups: Vec<Name> = directory
+                .get_groups()
+                .await?
+                .into_iter()
+                .map(Name)
+                .collect();
+            print_list(format, &groups)
+        }
+        GroupCommand::Create(group) => {
+            let name = group.name.clone();
+            directory
+                .create_group(NewGroup {
+                    name: group.name,
+                    group_type: group.group_type,
+                    posting_policy: group.posting_policy,
+                    list_address: group.list_address,
+                })
+                .await??;
+            print_message(format, format!("Created {name}"))
+        }
+        GroupCommand::Delete { name } => {
+            if !directory.delete_group(name.clone()).await?? {
+                bail!("Group {name} does not exist");
+            }
+            print_message(format, format!("Deleted {name}"))
+        }
+        GroupCommand::Members { name } => {
+            let members: Vec<Name> = directory
+                .list_group_members(name)
+                .await??
+                .into_iter()
+                .map(Name)
+                .collect();
+            print_list(format, &members)
+        }
+        GroupCommand::AddMember { name, username } => {
+            directory
+                .add_group_member(name.clone(), username.clone())
+                .await??;
+            print_message(format, format!("Added {username} to {name}"))
+        }
+        GroupCommand::RemoveMember { name, username } => {
+            if !directory
+                .remove_group_member(name.clone(), username.clone())
+                .await??
+            {
+                bail!("{username} is not a member of {name}");
+            }
+            print_message(format, format!("Removed {username} from {name}"))
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! `nitro-admin` manages a running nitro_mail through the sockets of its services
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+
+use directories::directory_service::directory_service_directory::DirectoryServiceDirectory;
+use directories::directory_type::Directory;
+use storage_sql::database_storage::DatabaseStorage;
+use storage_sql::storage_config::SqlStorageConfig;
+use utils::configs::Config;
+
+use crate::accounts::AccountCommand;
+use crate::aliases::AliasCommand;
+use crate::config_check::ConfigCommand;
+use crate::dkim::DkimCommand;
+use crate::encryption::EncryptionCommand;
+use crate::groups::GroupCommand;
+use crate::output::OutputFormat;
+use crate::quota::QuotaCommand;
+use crate::search::SearchCommand;
+use crate::status::StatusArgs;
+
+mod accounts;
+mod aliases;
+mod config_check;
+mod dkim;
+mod encryption;
+mod groups;
+mod output;
+mod quota;
+mod search;
+mod status;
+
+#[derive(Debug, Parser)]
+#[command(name = "nitro-admin", version, about)]
+struct Cli {
+    #[arg(long, global = true, value_enum, default_value_t)]
+    format: OutputFormat,
+    #[command(subcommand)]
+    command: Command,
+}
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[command(subcommand)]
+    Account(AccountCommand),
+    #[command(subcommand)]
+    Group(GroupCommand),
+    /// The email addresses of accounts
+    #[command(subcommand)]
+    Alias(AliasCommand),
+    #[command(subcommand)]
+    Quota(QuotaCommand),
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    #[command(subcommand)]
+    Dkim(DkimCommand),
+    /// Encryption at rest of stored mail
+    #[command(subcommand)]
+    Encryption(EncryptionCommand),
+    /// The full-text search index
+    #[command(subcommand)]
+    Search(SearchCommand),
+    /// Checks that the services are running and shows their versions
+    Status(StatusArgs),
+}
+
+async fn directory() -> anyhow::Result<DirectoryServiceDirectory> {
+    DirectoryServiceDirectory::load(())
+        .await
+        .context("Unable to connect to the directory service")
+}
+
+/// The SQL storage configured in the working directory
+async fn storage(working_directory: PathBuf) -> anyhow::Result<DatabaseStorage> {
+    let config =
+        SqlStorageConfig::load(working_directory).context("Unable to load the storage config")?;
+    DatabaseStorage::connect(&config)
+        .await
+        .context("Unable to open the storage")
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let format = cli.format;
+    match cli.command {
+        Command::Account(command) => accounts::run(&directory().await?, command, format).await,
+        Command::Group(command) => groups::run(&directory().await?, command, format).await,
+        Command::Alias(command) => aliases::run(&directory().await?, command, format).await,
+        Command::Quota(command) => quota::run(&directory().await?, command, format).await,
+        Command::Config(command) => config_check::run(command, format).await,
+        Command::Dkim(command) => dkim::run(command, format).await,
+        Command::Encryption(command) => encryption::run(command, format).await,
+        Command::Search(command) => search::run(command, format).await,
+        Command::Status(args) => status::run(args, format).await,
+    }
+}
@@ -0,0 +1,79 @@
+use clap::ValueEnum;
+use comfy_table::presets::UTF8_FULL_CONDENSED;
+use comfy_table::Table;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Tables for people
+    #[default]
+    Table,
+    /// JSON for scripts
+    Json,
+}
+
+/// A value that can be printed as a table row
+pub trait Row: Serialize {
+    fn headers() -> Vec<&'static str>;
+    fn row(&self) -> Vec<String>;
+}
+
+fn table(headers: Vec<&'static str>) -> Table {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED).set_header(headers);
+    table
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+pub fn print_list<T: Row>(format: OutputFormat, values: &[T]) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => print_json(values),
+        OutputFormat::Table => {
+            let mut table = table(T::headers());
+            for value in values {
+                table.add_row(value.row());
+            }
+            println!("{table}");
+            Ok(())
+        }
+    }
+}
+
+/// One value as a two column table of fields
+pub fn print_one<T: Row>(format: OutputFormat, value: &T) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => print_json(value),
+        OutputFormat::Table => {
+            let mut table = table(vec!["Field", "Value"]);
+            for (header, field) in T::headers().into_iter().zip(value.row()) {
+                table.add_row(vec![header.to_string(), field]);
+            }
+            println!("{table}");
+            Ok(())
+        }
+    }
+}
+
+/// The outcome of a change. JSON output is `{"message": "..."}`
+pub fn print_message(format: OutputFormat, message: impl Into<String>) -> anyhow::Result<()> {
+    #[derive(Serialize)]
+    struct Message {
+        message: String,
+    }
+    let message = message.into();
+    match format {
+        OutputFormat::Json => print_json(&Message { message }),
+        OutputFormat::Table => {
+            println!("{message}");
+            Ok(())
+        }
+    }
+}
+
+pub fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
@@ -0,0 +1,81 @@
+use anyhow::Context;
+use clap::Subcommand;
+use serde::Serialize;
+
+use directories::directory_type::Directory;
+use utils::account::Account;
+
+use crate::output::{print_list, print_one, OutputFormat, Row};
+
+#[derive(Debug, Subcommand)]
+pub enum QuotaCommand {
+    /// The quotas of every account
+    List,
+    Show {
+        username: String,
+    },
+}
+
+/// Usage is not reported until storage accounts for it
+#[derive(Serialize)]
+struct Quota {
+    username: String,
+    /// Bytes. 0 for no limit
+    storage: u64,
+    /// 0 for no limit
+    messages: u64,
+}
+impl From<Account> for Quota {
+    fn from(account: Account) -> Self {
+        Self {
+            username: account.username,
+            storage: account.quota.storage,
+            messages: account.quota.messages,
+        }
+    }
+}
+impl Row for Quota {
+    fn headers() -> Vec<&'static str> {
+        vec!["Username", "Storage", "Messages"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let limit = |limit: u64, unit: &str| {
+            if limit == 0 {
+                "unlimited".to_string()
+            } else {
+                format!("{limit} {unit}")
+            }
+        };
+        vec![
+            self.username.clone(),
+            limit(self.storage, "bytes"),
+            limit(self.messages, "messages"),
+        ]
+    }
+}
+
+pub async fn run<D: Directory>(
+    directory: &D,
+    command: QuotaCommand,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match command {
+        QuotaCommand::List => {
+            let quotas: Vec<Quota> = directory
+                .list_accounts()
+                .await??
+                .into_iter()
+                .map(Quota::from)
+                .collect();
+            print_list(format, &quotas)
+        }
+        QuotaCommand::Show { username } => {
+            let account = directory
+                .get_account(username.clone())
+                .await?
+                .with_context(|| format!("Account {username} does not exist"))?;
+            print_one(format, &Quota::from(account))
+        }
+    }
+}
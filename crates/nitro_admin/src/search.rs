@@ -0,0 +1,95 @@
+use anyhow::{bail, Context};
+use clap::Subcommand;
+use serde::Serialize;
+use uuid::Uuid;
+
+use directories::directory_type::Directory;
+use storages::storage_service::storage_service_storage::StorageServiceStorage;
+use storages::storage_type::Storage;
+use storages::SOCKET_NAME;
+
+use crate::output::{print_list, OutputFormat, Row};
+
+#[derive(Debug, Subcommand)]
+pub enum SearchCommand {
+    /// Indexes the stored messages again. For a new index or one that missed changes.
+    /// The running storage service rebuilds the index it searches
+    Rebuild {
+        /// Every account if neither this nor `--mailbox-id` is given
+        username: Option<String>,
+        /// A mailbox that is not an account's. For example a group's
+        #[arg(long, conflicts_with = "username")]
+        mailbox_id: Option<Uuid>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct RebuiltMailbox {
+    /// Empty for mailboxes given by id
+    username: String,
+    mailbox_id: Uuid,
+    folders: u64,
+    messages: u64,
+}
+impl Row for RebuiltMailbox {
+    fn headers() -> Vec<&'static str> {
+        vec!["Username", "Mailbox", "Folders", "Messages"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.username.clone(),
+            self.mailbox_id.to_string(),
+            self.folders.to_string(),
+            self.messages.to_string(),
+        ]
+    }
+}
+
+pub async fn run(command: SearchCommand, format: OutputFormat) -> anyhow::Result<()> {
+    match command {
+        SearchCommand::Rebuild {
+            username,
+            mailbox_id,
+        } => {
+            let mailboxes: Vec<(String, Uuid)> = match (username, mailbox_id) {
+                (_, Some(mailbox_id)) => vec![(String::new(), mailbox_id)],
+                (Some(username), None) => {
+                    let account = crate::directory()
+                        .await?
+                        .get_account(username.clone())
+                        .await?
+                        .with_context(|| format!("Account {username} does not exist"))?;
+                    vec![(account.username, account.mailbox_id)]
+                }
+                (None, None) => crate::directory()
+                    .await?
+                    .list_accounts()
+                    .await??
+                    .into_iter()
+                    .map(|account| (account.username, account.mailbox_id))
+                    .collect(),
+            };
+            if mailboxes.is_empty() {
+                bail!("There are no accounts to index");
+            }
+            let storage = StorageServiceStorage::connect(SOCKET_NAME.to_string())
+                .await
+                .context("Unable to connect to the storage service")?;
+            let mut rebuilt = Vec::with_capacity(mailboxes.len());
+            for (username, mailbox_id) in mailboxes {
+                let rebuild = storage
+                    .rebuild_search_index(mailbox_id)
+                    .await?
+                    .with_context(|| format!("Unable to index mailbox {mailbox_id}"))?;
+                rebuilt.push(RebuiltMailbox {
+                    username,
+                    mailbox_id,
+                    folders: rebuild.folders,
+                    messages: rebuild.messages,
+                });
+            }
+            print_list(format, &rebuilt)
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::bail;
+use clap::Args;
+use interprocess::local_socket::tokio::LocalSocketStream;
+use serde::Serialize;
+use uuid::Uuid;
+
+use directories::directory_service::directory_service_directory::DirectoryServiceDirectory;
+use directories::directory_type::Directory;
+use directories::ValidateDirectoryRequest;
+use utils::admin::DirectoryCapabilities;
+use utils::service_configuration::ServiceConfigurationResponse;
+
+use crate::output::{print_list, yes_no, OutputFormat, Row};
+
+#[derive(Debug, Args)]
+pub struct StatusArgs {
+    /// The namespaces nitro_mail was installed with.
+    /// The directory reports a mismatch if it was set up with others
+    #[arg(long, env = "NITRO_MAIL_ACCOUNT_NAMESPACE")]
+    account_namespace: Uuid,
+    #[arg(long, env = "NITRO_MAIL_GROUP_NAMESPACE")]
+    group_namespace: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+struct ServiceStatus {
+    service: &'static str,
+    reachable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    configuration: Option<ServiceConfigurationResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capabilities: Option<DirectoryCapabilities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+impl ServiceStatus {
+    fn unreachable(service: &'static str, error: impl ToString) -> Self {
+        Self {
+            service,
+            reachable: false,
+            configuration: None,
+            capabilities: None,
+            error: Some(error.to_string()),
+        }
+    }
+    fn is_healthy(&self) -> bool {
+        self.error.is_none()
+            && !matches!(
+                self.configuration,
+                Some(ServiceConfigurationResponse::NamespaceMismatch)
+            )
+    }
+}
+impl Row for ServiceStatus {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Service",
+            "Reachable",
+            "Name",
+            "Version",
+            "Commit",
+            "Branch",
+            "Status",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let mut row = vec![self.service.to_string(), yes_no(self.reachable)];
+        match &self.configuration {
+            Some(ServiceConfigurationResponse::Success {
+                new_install,
+                internal_service_name,
+                git,
+                version,
+                ..
+            }) => {
+                let status = if *new_install {
+                    "ok, new install"
+                } else {
+                    "ok"
+                };
+                row.extend([
+                    internal_service_name.clone(),
+                    version.clone(),
+                    git.commit.clone(),
+                    git.branch.clone(),
+                    status.to_string(),
+                ]);
+            }
+            Some(ServiceConfigurationResponse::NamespaceMismatch) => {
+                row.extend(["", "", "", ""].map(String::from));
+                row.push("namespace mismatch".to_string());
+            }
+            None => {
+                row.extend(["", "", "", ""].map(String::from));
+                row.push(self.error.clone().unwrap_or_else(|| "ok".to_string()));
+            }
+        }
+        row
+    }
+}
+
+async fn directory_status(request: ValidateDirectoryRequest) -> ServiceStatus {
+    const SERVICE: &str = "directory";
+    let directory = match DirectoryServiceDirectory::load(()).await {
+        Ok(directory) => directory,
+        Err(error) => return ServiceStatus::unreachable(SERVICE, error),
+    };
+    let configuration = match directory.validate_config(request).await {
+        Ok(configuration) => configuration,
+        Err(error) => return ServiceStatus::unreachable(SERVICE, error),
+    };
+    let capabilities = directory.directory_capabilities().await;
+    ServiceStatus {
+        service: SERVICE,
+        reachable: true,
+        configuration: Some(configuration),
+        error: capabilities.as_ref().err().map(ToString::to_string),
+        capabilities: capabilities.ok(),
+    }
+}
+
+/// Storage does not answer any requests yet, so only the connection is checked
+async fn storage_status() -> ServiceStatus {
+    const SERVICE: &str = "storage";
+    match LocalSocketStream::connect(storages::SOCKET_NAME).await {
+        Ok(_) => ServiceStatus {
+            service: SERVICE,
+            reachable: true,
+            configuration: None,
+            capabilities: None,
+            error: None,
+        },
+        Err(error) => ServiceStatus::unreachable(SERVICE, error),
+    }
+}
+
+pub async fn run(args: StatusArgs, format: OutputFormat) -> anyhow::Result<()> {
+    let statuses = vec![
+        directory_status(ValidateDirectoryRequest {
+            group_namespace: args.group_namespace,
+            account_namespace: args.account_namespace,
+        })
+        .await,
+        storage_status().await,
+    ];
+    print_list(format, &statuses)?;
+    if !statuses.iter().all(ServiceStatus::is_healthy) {
+        bail!("Not every service is healthy");
+    }
+    Ok(())
+}
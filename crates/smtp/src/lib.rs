@@ -4,10 +4,15 @@ use std::path::PathBuf;
 use storages::storage_type::Storage;
 use utils::service::ServiceAccess;
 
+pub mod mailing_list;
+pub mod quota;
 pub mod smtp_client;
 pub mod smtp_config;
 pub mod smtp_listener;
 pub mod smtp_service;
+pub mod smtp_session;
+#[cfg(test)]
+mod smtp_tests;
 
 pub fn start_smtp_service<
     D: Directory,
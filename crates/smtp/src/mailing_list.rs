@@ -0,0 +1,321 @@
+use thiserror::Error;
+
+use utils::groups::{MailingList, PostingPolicy};
+use utils::helper_types::EmailAddress;
+
+use crate::smtp_session::Reply;
+
+/// Headers set by the list. Copies already in the message are removed
+const LIST_HEADERS: [&str; 3] = ["List-Id", "List-Unsubscribe", "List-Post"];
+/// Appended to the local part of the list for the bounce address. `list-bounces@example.com`
+const BOUNCE_SUFFIX: &str = "-bounces";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ListRejection {
+    #[error("Only members may post to this list")]
+    NotMember,
+    #[error("Posts to this list must be approved by a moderator")]
+    ModerationRequired,
+    #[error("The message has already been sent through this list")]
+    Loop,
+    #[error("Invalid list address")]
+    InvalidListAddress,
+}
+impl ListRejection {
+    pub fn reply(&self) -> Reply {
+        match self {
+            ListRejection::NotMember => Reply::new(550, format!("5.7.1 {}", self)),
+            ListRejection::ModerationRequired => Reply::new(550, format!("5.7.2 {}", self)),
+            ListRejection::Loop => Reply::new(554, format!("5.4.6 {}", self)),
+            ListRejection::InvalidListAddress => Reply::new(550, format!("5.1.3 {}", self)),
+        }
+    }
+}
+
+/// One copy of the list message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListDelivery {
+    /// The VERP return path. Bounces identify the member that failed
+    pub return_path: EmailAddress,
+    pub recipient: EmailAddress,
+}
+
+/// A list message ready to be delivered to the members
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandedList {
+    /// The message with the list headers added
+    pub message: Vec<u8>,
+    pub deliveries: Vec<ListDelivery>,
+}
+
+/// Checks the posting policy and creates a copy of the message for every member
+///
+/// `sender` is None for the null reverse-path. Bounces are never sent to a list.
+pub fn expand(
+    list: &MailingList,
+    sender: Option<&EmailAddress>,
+    message: &[u8],
+) -> Result<ExpandedList, ListRejection> {
+    let list_address = EmailAddress::new_lenient(list.list_address.as_str())
+        .map_err(|_| ListRejection::InvalidListAddress)?;
+    let list_id = list_id(&list_address);
+    if header_values(message, "List-Id")
+        .iter()
+        .any(|value| value.contains(&format!("<{}>", list_id)))
+    {
+        return Err(ListRejection::Loop);
+    }
+    check_posting_policy(list, sender)?;
+
+    let message = add_list_headers(message, &list_headers(list, &list_address));
+    let deliveries = list
+        .members
+        .iter()
+        .filter_map(|member| EmailAddress::new_lenient(member.as_str()).ok())
+        .map(|recipient| ListDelivery {
+            return_path: verp_return_path(&list_address, &recipient),
+            recipient,
+        })
+        .collect();
+    Ok(ExpandedList {
+        message,
+        deliveries,
+    })
+}
+
+pub fn check_posting_policy(
+    list: &MailingList,
+    sender: Option<&EmailAddress>,
+) -> Result<(), ListRejection> {
+    let Some(sender) = sender else {
+        return Err(ListRejection::NotMember);
+    };
+    match list.posting_policy {
+        PostingPolicy::Open => Ok(()),
+        PostingPolicy::MembersOnly if list.is_member(sender) => Ok(()),
+        PostingPolicy::MembersOnly => Err(ListRejection::NotMember),
+        // TODO hold the message for the list owner once Storage can keep it
+        PostingPolicy::Moderated => Err(ListRejection::ModerationRequired),
+    }
+}
+
+/// The RFC 2919 list identifier. `list@example.com` becomes `list.example.com`
+pub fn list_id(list_address: &EmailAddress) -> String {
+    format!("{}.{}", list_address.local_part(), list_address.domain())
+}
+
+/// The RFC 2369 and RFC 2919 headers for the list
+pub fn list_headers(
+    list: &MailingList,
+    list_address: &EmailAddress,
+) -> Vec<(&'static str, String)> {
+    let description = if list.group.description.is_empty() {
+        &list.group.name
+    } else {
+        &list.group.description
+    };
+    let description = description.replace(['\\', '"'], "");
+    vec![
+        (
+            "List-Id",
+            format!("\"{}\" <{}>", description, list_id(list_address)),
+        ),
+        (
+            "List-Unsubscribe",
+            format!(
+                "<mailto:{}-request@{}?subject=unsubscribe>",
+                list_address.local_part(),
+                list_address.domain()
+            ),
+        ),
+        ("List-Post", format!("<mailto:{}>", list_address)),
+    ]
+}
+
+/// Encodes the member in the return path. `list-bounces+user=example.org@example.com`
+///
+/// Falls back to `list-bounces@example.com` if the result would be too long
+pub fn verp_return_path(list_address: &EmailAddress, recipient: &EmailAddress) -> EmailAddress {
+    let bounces = format!("{}{}", list_address.local_part(), BOUNCE_SUFFIX);
+    let verp = format!(
+        "{}+{}={}@{}",
+        bounces,
+        recipient.local_part(),
+        recipient.domain(),
+        list_address.domain()
+    );
+    EmailAddress::new_lenient(verp).unwrap_or_else(|_| {
+        EmailAddress::new_lenient(format!("{}@{}", bounces, list_address.domain()))
+            .expect("List bounce address is valid")
+    })
+}
+
+/// Reverses [verp_return_path] returning the list address and the member that bounced
+pub fn parse_verp_return_path(return_path: &EmailAddress) -> Option<(EmailAddress, EmailAddress)> {
+    let (bounces, member) = return_path.local_part().split_once('+')?;
+    let list = bounces.strip_suffix(BOUNCE_SUFFIX)?;
+    let (member_local_part, member_domain) = member.rsplit_once('=')?;
+    let list = EmailAddress::new_lenient(format!("{}@{}", list, return_path.domain())).ok()?;
+    let member =
+        EmailAddress::new_lenient(format!("{}@{}", member_local_part, member_domain)).ok()?;
+    Some((list, member))
+}
+
+/// Prepends the headers, removing any existing headers with the same names
+pub fn add_list_headers(message: &[u8], headers: &[(&'static str, String)]) -> Vec<u8> {
+    let (header_section, body) = split_message(message);
+    let mut result = Vec::with_capacity(message.len() + 256);
+    for (name, value) in headers {
+        result.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    let mut skipping = false;
+    for line in header_section.split_inclusive(|b| *b == b'\n') {
+        let continuation = line.first().is_some_and(|b| *b == b' ' || *b == b'\t');
+        if !continuation {
+            skipping = header_name(line).is_some_and(|name| {
+                LIST_HEADERS
+                    .iter()
+                    .any(|list_header| list_header.eq_ignore_ascii_case(name))
+            });
+        }
+        if !skipping {
+            result.extend_from_slice(line);
+        }
+    }
+    result.extend_from_slice(body);
+    result
+}
+
+/// Splits the message after the blank line that ends the header section
+fn split_message(message: &[u8]) -> (&[u8], &[u8]) {
+    let mut offset = 0;
+    for line in message.split_inclusive(|b| *b == b'\n') {
+        if line == b"\r\n" || line == b"\n" {
+            return message.split_at(offset);
+        }
+        offset += line.len();
+    }
+    (message, &[])
+}
+
+fn header_name(line: &[u8]) -> Option<&str> {
+    let colon = line.iter().position(|b| *b == b':')?;
+    std::str::from_utf8(&line[..colon]).ok().map(str::trim)
+}
+
+/// The unfolded values of every header with the name
+fn header_values(message: &[u8], name: &str) -> Vec<String> {
+    let (header_section, _) = split_message(message);
+    let mut values: Vec<String> = Vec::new();
+    let mut matching = false;
+    for line in header_section.split_inclusive(|b| *b == b'\n') {
+        let text = String::from_utf8_lossy(line);
+        if line.first().is_some_and(|b| *b == b' ' || *b == b'\t') {
+            if let (true, Some(value)) = (matching, values.last_mut()) {
+                value.push(' ');
+                value.push_str(text.trim());
+            }
+            continue;
+        }
+        matching = header_name(line).is_some_and(|header| header.eq_ignore_ascii_case(name));
+        if matching {
+            let (_, value) = text.split_once(':').unwrap_or_default();
+            values.push(value.trim().to_string());
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use utils::groups::{Group, GroupType, MailingList, PostingPolicy};
+    use utils::helper_types::EmailAddress;
+
+    use crate::mailing_list::{
+        add_list_headers, expand, parse_verp_return_path, verp_return_path, ListRejection,
+    };
+
+    fn list(posting_policy: PostingPolicy) -> MailingList {
+        MailingList {
+            group: Group {
+                group_type: GroupType::List,
+                name: "dev".to_string(),
+                description: "Developers".to_string(),
+                mailbox_id: Uuid::nil(),
+            },
+            list_address: "dev@example.com".to_string(),
+            posting_policy,
+            members: vec!["a@example.com".to_string(), "b@example.org".to_string()],
+        }
+    }
+    fn address(address: &str) -> EmailAddress {
+        EmailAddress::new(address).unwrap()
+    }
+    const MESSAGE: &[u8] =
+        b"Subject: Hello\r\nList-Id: old\r\n <old.example.com>\r\n\r\nList-Id: in the body\r\n";
+
+    #[test]
+    pub fn test_expand() {
+        let expanded = expand(
+            &list(PostingPolicy::MembersOnly),
+            Some(&address("a@example.com")),
+            MESSAGE,
+        )
+        .unwrap();
+        let message = String::from_utf8(expanded.message).unwrap();
+        assert_eq!(
+            message,
+            "List-Id: \"Developers\" <dev.example.com>\r\n\
+             List-Unsubscribe: <mailto:dev-request@example.com?subject=unsubscribe>\r\n\
+             List-Post: <mailto:dev@example.com>\r\n\
+             Subject: Hello\r\n\r\nList-Id: in the body\r\n"
+        );
+        assert_eq!(expanded.deliveries.len(), 2);
+        assert_eq!(expanded.deliveries[1].recipient, "b@example.org");
+        assert_eq!(
+            expanded.deliveries[1].return_path,
+            "dev-bounces+b=example.org@example.com"
+        );
+
+        let resent = expand(
+            &list(PostingPolicy::Open),
+            Some(&address("a@example.com")),
+            &add_list_headers(MESSAGE, &[("List-Id", "<dev.example.com>".to_string())]),
+        );
+        assert_eq!(resent, Err(ListRejection::Loop));
+    }
+    #[test]
+    pub fn test_posting_policy() {
+        let outsider = address("outsider@example.net");
+        assert!(expand(&list(PostingPolicy::Open), Some(&outsider), MESSAGE).is_ok());
+        assert_eq!(
+            expand(&list(PostingPolicy::MembersOnly), Some(&outsider), MESSAGE),
+            Err(ListRejection::NotMember)
+        );
+        assert_eq!(
+            expand(&list(PostingPolicy::MembersOnly), None, MESSAGE),
+            Err(ListRejection::NotMember)
+        );
+        assert_eq!(
+            expand(
+                &list(PostingPolicy::Moderated),
+                Some(&address("a@example.com")),
+                MESSAGE
+            ),
+            Err(ListRejection::ModerationRequired)
+        );
+    }
+    #[test]
+    pub fn test_verp() {
+        let list_address = address("dev@example.com");
+        let member = address("first.last@example.org");
+        let return_path = verp_return_path(&list_address, &member);
+        assert_eq!(
+            parse_verp_return_path(&return_path),
+            Some((list_address, member))
+        );
+        assert_eq!(parse_verp_return_path(&address("dev@example.com")), None);
+    }
+}
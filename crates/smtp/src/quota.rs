@@ -0,0 +1,79 @@
+use thiserror::Error;
+
+use utils::quota::{Quota, QuotaResource, QuotaUsage};
+
+use crate::smtp_session::Reply;
+
+/// A recipient whose mailbox can not take more mail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum QuotaRejection {
+    /// Found at RCPT. The mailbox may have room again once mail is deleted
+    #[error("Mailbox full")]
+    Full(QuotaResource),
+    /// Found at DATA. This message does not fit
+    #[error("Message exceeds the mailbox quota")]
+    TooLarge(QuotaResource),
+    /// The storage could not report the usage of the mailbox. The client should try again later
+    #[error("Unable to check the mailbox quota")]
+    Unavailable,
+}
+impl QuotaRejection {
+    pub fn reply(&self) -> Reply {
+        match self {
+            QuotaRejection::Full(_) => Reply::new(452, format!("4.2.2 {}", self)),
+            QuotaRejection::TooLarge(_) => Reply::new(552, format!("5.2.2 {}", self)),
+            QuotaRejection::Unavailable => Reply::new(451, format!("4.3.0 {}", self)),
+        }
+    }
+}
+
+/// Checked at RCPT before the size of the message is known
+pub fn check_recipient(quota: &Quota, usage: &QuotaUsage) -> Result<(), QuotaRejection> {
+    match quota.exhausted(usage) {
+        Some(resource) => Err(QuotaRejection::Full(resource)),
+        None => Ok(()),
+    }
+}
+
+/// Checked once the message has been read
+pub fn check_message(quota: &Quota, usage: &QuotaUsage, size: u64) -> Result<(), QuotaRejection> {
+    match quota.exceeded_by(usage, size, 1) {
+        Some(resource) => Err(QuotaRejection::TooLarge(resource)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::quota::{Quota, QuotaResource, QuotaUsage};
+
+    use crate::quota::{check_message, check_recipient, QuotaRejection};
+
+    #[test]
+    pub fn test_quota_replies() {
+        let quota = Quota {
+            storage: 1000,
+            messages: 10,
+        };
+        let usage = QuotaUsage {
+            storage: 900,
+            messages: 9,
+        };
+        assert_eq!(check_recipient(&quota, &usage), Ok(()));
+        assert_eq!(check_message(&quota, &usage, 100), Ok(()));
+        let rejection = check_message(&quota, &usage, 200).unwrap_err();
+        assert_eq!(rejection, QuotaRejection::TooLarge(QuotaResource::Storage));
+        assert_eq!(
+            rejection.reply().to_string(),
+            "552 5.2.2 Message exceeds the mailbox quota\r\n"
+        );
+
+        let full = QuotaUsage {
+            storage: 900,
+            messages: 10,
+        };
+        let rejection = check_recipient(&quota, &full).unwrap_err();
+        assert_eq!(rejection, QuotaRejection::Full(QuotaResource::Message));
+        assert_eq!(rejection.reply().to_string(), "452 4.2.2 Mailbox full\r\n");
+    }
+}
@@ -1,19 +1,84 @@
-use crate::smtp_config::SMTPHost;
+use crate::mailing_list::{self, ExpandedList, ListRejection};
+use crate::quota::{self, QuotaRejection};
+use crate::smtp_config::{SMTPHost, SMTPProtocol};
 use crate::smtp_service::{SMTPServiceAccess, SMTPServiceError};
+use crate::smtp_session::{Reply, Session, SessionResponse, MAX_AUTH_LINE};
+use ahash::{HashMap, HashMapExt};
 use directories::directory_type::Directory;
-use std::net::SocketAddr;
+use directories::oauth::{BearerAuthentication, OAuthAuthenticator};
+use directories::recipient::resolve_recipient;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
+use std::time::{SystemTime, UNIX_EPOCH};
+use storages::folders::{Delivery, MessageInfo, NewMessage, StorageError, StorageResult, INBOX};
 use storages::storage_type::Storage;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+use tokio::net::{TcpStream, UnixStream};
+use tracing::warn;
+use utils::app_password::LoginProtocol;
+use utils::helper_types::EmailAddress;
+use utils::quota::{Quota, QuotaUsage};
+use utils::sasl::{self, SaslMechanism};
 use utils::service::ServiceAccess;
+use uuid::Uuid;
+
+/// A stream accepted by an [Instance](crate::smtp_listener::Instance)
+pub enum SMTPStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+impl AsyncRead for SMTPStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SMTPStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            SMTPStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+impl AsyncWrite for SMTPStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            SMTPStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            SMTPStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SMTPStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            SMTPStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SMTPStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            SMTPStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
 pub struct Connection<
     D: Directory,
     DirectoryAccess: ServiceAccess<ServiceResponse = D>,
     S: Storage,
     StorageAccess: ServiceAccess<ServiceResponse = S>,
 > {
-    pub stream: TcpStream,
-    pub addr: SocketAddr,
+    pub stream: SMTPStream,
+    /// None for Unix sockets
+    pub addr: Option<SocketAddr>,
+    pub host: SMTPHost,
     pub service: SMTPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
 }
 impl<
@@ -31,6 +96,679 @@ impl<
             .await
             .map_err(|e| SMTPServiceError::GettingDirectoryAccess(Box::new(e)))?;
 
+        let mut session = Session::new(self.host.protocol, self.service.config.hostname.clone());
+        session.max_message_size = self.service.config.max_message_size;
+        if self.service.oauth.is_some() && self.host.protocol.is_smtp() {
+            session.auth_mechanisms = vec![SaslMechanism::OAuthBearer, SaslMechanism::XOAuth2];
+        }
+        let (reader, mut writer) = tokio::io::split(self.stream);
+        let mut reader = BufReader::new(reader);
+        writer
+            .write_all(
+                session
+                    .greeting(self.host.greeting.as_deref())
+                    .to_string()
+                    .as_bytes(),
+            )
+            .await?;
+
+        let mut line = Vec::new();
+        loop {
+            // The session checks the shorter limit for commands other than AUTH
+            match read_line(&mut reader, &mut line, MAX_AUTH_LINE).await? {
+                Line::Complete => {}
+                Line::TooLong => {
+                    let reply = session.line_too_long();
+                    writer.write_all(reply.to_string().as_bytes()).await?;
+                    continue;
+                }
+                Line::Closed => break,
+            }
+            let command = String::from_utf8_lossy(&line);
+            match session.handle_command(&command) {
+                SessionResponse::Reply(reply) => {
+                    writer.write_all(reply.to_string().as_bytes()).await?;
+                }
+                SessionResponse::Quit(reply) => {
+                    writer.write_all(reply.to_string().as_bytes()).await?;
+                    break;
+                }
+                SessionResponse::Authenticate {
+                    mechanism,
+                    initial_response,
+                } => {
+                    let reply = Self::authenticate(
+                        self.service.oauth.as_ref(),
+                        &directory,
+                        self.addr.map(|addr| addr.ip()),
+                        &mut session,
+                        &mut reader,
+                        &mut writer,
+                        mechanism,
+                        initial_response,
+                    )
+                    .await?;
+                    writer.write_all(reply.to_string().as_bytes()).await?;
+                }
+                SessionResponse::CheckRecipient(address) => {
+                    let rejection = Self::check_recipient(
+                        &self.service,
+                        &directory,
+                        self.host.protocol,
+                        session.mail_from.as_deref(),
+                        &address,
+                    )
+                    .await?;
+                    let reply = match rejection {
+                        Some(rejection) => rejection,
+                        None => session.accept_recipient(address),
+                    };
+                    writer.write_all(reply.to_string().as_bytes()).await?;
+                }
+                SessionResponse::StartData(reply) => {
+                    writer.write_all(reply.to_string().as_bytes()).await?;
+                    let max_size = session.max_message_size;
+                    let Some(message) = read_data(&mut reader, max_size).await? else {
+                        for reply in session.reject_data() {
+                            writer.write_all(reply.to_string().as_bytes()).await?;
+                        }
+                        continue;
+                    };
+                    let lists = Self::expand_lists(&directory, &session, &message).await?;
+                    let mut list_replies = HashMap::with_capacity(lists.len());
+                    for (list, expanded) in lists {
+                        let reply = match expanded {
+                            Ok(expanded) => {
+                                Self::deliver_list(&self.service, &directory, expanded).await?
+                            }
+                            Err(rejection) => rejection.reply(),
+                        };
+                        list_replies.insert(list, reply);
+                    }
+                    let rejections =
+                        Self::check_quotas(&self.service, &directory, &session, &message).await?;
+                    let recipients = session
+                        .recipients
+                        .iter()
+                        .filter(|recipient| {
+                            !list_replies.contains_key(*recipient)
+                                && !rejections.contains_key(*recipient)
+                        })
+                        .cloned()
+                        .collect();
+                    let delivered =
+                        Self::deliver(&self.service, &directory, recipients, message).await?;
+                    let replies =
+                        session.finish_data(|recipient| match list_replies.get(recipient) {
+                            Some(reply) => reply.clone(),
+                            None => match rejections.get(recipient) {
+                                Some(rejection) => rejection.reply(),
+                                None => delivered.get(recipient).cloned().unwrap_or_else(|| {
+                                    Reply::new(451, "4.3.0 Unable to store the message")
+                                }),
+                            },
+                        });
+                    for reply in replies {
+                        writer.write_all(reply.to_string().as_bytes()).await?;
+                    }
+                }
+            }
+        }
         Ok(())
     }
+
+    /// Runs the SASL exchange for OAUTHBEARER or XOAUTH2 and returns the final reply
+    ///
+    /// Failures are counted by the directory. Locked out addresses and accounts get a temporary error
+    #[allow(clippy::too_many_arguments)]
+    async fn authenticate(
+        oauth: Option<&OAuthAuthenticator>,
+        directory: &D,
+        remote: Option<IpAddr>,
+        session: &mut Session,
+        reader: &mut (impl AsyncBufReadExt + Unpin),
+        writer: &mut (impl AsyncWrite + Unpin),
+        mechanism: SaslMechanism,
+        initial_response: Option<String>,
+    ) -> Result<Reply, SMTPServiceError> {
+        let Some(oauth) = oauth else {
+            return Ok(Reply::new(502, "5.5.1 AUTH not available"));
+        };
+        let response = match initial_response {
+            Some(response) => response,
+            None => {
+                writer.write_all(b"334 \r\n").await?;
+                match Self::read_response(reader).await? {
+                    Some(response) => response,
+                    None => {
+                        return Ok(Reply::new(
+                            500,
+                            "5.5.6 Authentication Exchange line is too long",
+                        ))
+                    }
+                }
+            }
+        };
+        if response == "*" {
+            return Ok(Reply::new(501, "5.7.0 Authentication cancelled"));
+        }
+        let credentials = match sasl::decode_response(&response)
+            .and_then(|response| sasl::parse_response(mechanism, &response))
+        {
+            Ok(ok) => ok,
+            Err(error) => return Ok(Reply::new(501, format!("5.5.2 {}", error))),
+        };
+        let directory_error = |e| SMTPServiceError::Directory(Box::new(e));
+        // The authzid is chosen by the client. So only the address is checked until the token names the account
+        let lockout = directory
+            .check_auth_lockout(remote, None)
+            .await
+            .map_err(directory_error)?;
+        if lockout.is_some() {
+            return Ok(session.locked_out());
+        }
+        match oauth
+            .authenticate(directory, &credentials, LoginProtocol::Smtp)
+            .await
+        {
+            Ok(BearerAuthentication::Authenticated(account)) => {
+                let lockout = directory
+                    .check_auth_lockout(None, Some(account.username.clone()))
+                    .await
+                    .map_err(directory_error)?;
+                if lockout.is_some() {
+                    return Ok(session.locked_out());
+                }
+                directory
+                    .record_auth_success(account.username.clone())
+                    .await
+                    .map_err(directory_error)?;
+                Ok(session.authenticated(account.username))
+            }
+            Ok(failed) => {
+                directory
+                    .record_auth_failure(remote, failed.username().map(str::to_string))
+                    .await
+                    .map_err(directory_error)?;
+                // The client has to acknowledge the error before the exchange fails
+                let challenge = format!("334 {}\r\n", sasl::error_challenge("invalid_token"));
+                writer.write_all(challenge.as_bytes()).await?;
+                Self::read_response(reader).await?;
+                Ok(session.authentication_failed())
+            }
+            Err(error) => {
+                warn!("Unable to check the bearer token: {}", error);
+                Ok(Reply::new(454, "4.7.0 Temporary authentication failure"))
+            }
+        }
+    }
+
+    /// None if the line is longer than [MAX_AUTH_LINE]
+    async fn read_response(
+        reader: &mut (impl AsyncBufReadExt + Unpin),
+    ) -> io::Result<Option<String>> {
+        let mut line = Vec::new();
+        match read_line(reader, &mut line, MAX_AUTH_LINE).await? {
+            Line::Complete => {}
+            Line::TooLong => return Ok(None),
+            Line::Closed => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed during AUTH",
+                ))
+            }
+        }
+        let line = String::from_utf8_lossy(&line);
+        Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+    }
+
+    /// Expands every recipient that is a mailing list
+    async fn expand_lists(
+        directory: &D,
+        session: &Session,
+        message: &[u8],
+    ) -> Result<HashMap<EmailAddress, Result<ExpandedList, ListRejection>>, SMTPServiceError> {
+        let sender = session
+            .mail_from
+            .as_deref()
+            .and_then(|sender| EmailAddress::new_lenient(sender).ok());
+        let mut lists = HashMap::new();
+        for recipient in &session.recipients {
+            let list = directory
+                .get_mailing_list(recipient.to_string())
+                .await
+                .map_err(|e| SMTPServiceError::Directory(Box::new(e)))?;
+            if let Some(list) = list {
+                let expanded = mailing_list::expand(&list, sender.as_ref(), message);
+                lists.insert(recipient.clone(), expanded);
+            }
+        }
+        Ok(lists)
+    }
+
+    /// The reply rejecting a recipient given in RCPT. None if it is accepted
+    ///
+    /// Only recipients with a local mailbox and lists the sender may post to are accepted.
+    /// There is no queue to hand other mail to, and rejecting them here keeps one recipient from failing the whole SMTP transaction after DATA
+    async fn check_recipient(
+        service: &SMTPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+        directory: &D,
+        protocol: SMTPProtocol,
+        sender: Option<&str>,
+        recipient: &EmailAddress,
+    ) -> Result<Option<Reply>, SMTPServiceError> {
+        let directory_error = |e| SMTPServiceError::Directory(Box::new(e));
+        let list = directory
+            .get_mailing_list(recipient.to_string())
+            .await
+            .map_err(directory_error)?;
+        if let Some(list) = list {
+            let sender = sender.and_then(|sender| EmailAddress::new_lenient(sender).ok());
+            let rejection = mailing_list::check_posting_policy(&list, sender.as_ref()).err();
+            return Ok(rejection.map(|rejection| rejection.reply()));
+        }
+        let resolved = resolve_recipient(directory, &service.domain_config, recipient)
+            .await
+            .map_err(directory_error)?;
+        let Some(resolved) = resolved else {
+            let local = protocol.is_lmtp()
+                || service
+                    .domain_config
+                    .get_domain(recipient.domain())
+                    .is_some();
+            let reply = match local {
+                true => Reply::new(550, "5.1.1 No such user"),
+                false => Reply::new(550, "5.7.1 Relaying is not available"),
+            };
+            return Ok(Some(reply));
+        };
+        let rejection =
+            match Self::mailbox_quota(service, directory, resolved.account.mailbox_id).await? {
+                Ok(quota) => {
+                    quota.and_then(|(quota, usage)| quota::check_recipient(&quota, &usage).err())
+                }
+                Err(rejection) => Some(rejection),
+            };
+        Ok(rejection.map(|rejection| rejection.reply()))
+    }
+
+    /// The limits and usage of the mailbox. None if it has no limits
+    ///
+    /// If the storage can not report the usage the recipient gets [QuotaRejection::Unavailable]
+    async fn mailbox_quota(
+        service: &SMTPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+        directory: &D,
+        mailbox_id: Uuid,
+    ) -> Result<Result<Option<(Quota, QuotaUsage)>, QuotaRejection>, SMTPServiceError> {
+        let Some(quota) = directory
+            .get_quota(mailbox_id)
+            .await
+            .map_err(|e| SMTPServiceError::Directory(Box::new(e)))?
+        else {
+            return Ok(Ok(None));
+        };
+        // Storage is only needed for mailboxes with limits
+        let storage = match service.storage_service_access.get_service().await {
+            Ok(storage) => storage,
+            Err(error) => {
+                warn!("Unable to reach the storage for {}: {}", mailbox_id, error);
+                return Ok(Err(QuotaRejection::Unavailable));
+            }
+        };
+        match storage.quota_usage(mailbox_id).await {
+            Ok(usage) => Ok(Ok(Some((quota, usage)))),
+            Err(error) => {
+                warn!("Unable to get the quota usage of {}: {}", mailbox_id, error);
+                Ok(Err(QuotaRejection::Unavailable))
+            }
+        }
+    }
+
+    /// Rechecks the quota of every recipient now that the size of the message is known
+    async fn check_quotas(
+        service: &SMTPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+        directory: &D,
+        session: &Session,
+        message: &[u8],
+    ) -> Result<HashMap<EmailAddress, QuotaRejection>, SMTPServiceError> {
+        let mut rejections = HashMap::new();
+        for recipient in &session.recipients {
+            let resolved = resolve_recipient(directory, &service.domain_config, recipient)
+                .await
+                .map_err(|e| SMTPServiceError::Directory(Box::new(e)))?;
+            // Mailing lists and recipients removed since RCPT have no mailbox here
+            let Some(resolved) = resolved else {
+                continue;
+            };
+            let mailbox_id = resolved.account.mailbox_id;
+            let rejection = match Self::mailbox_quota(service, directory, mailbox_id).await? {
+                Ok(Some((quota, usage))) => {
+                    quota::check_message(&quota, &usage, message.len() as u64).err()
+                }
+                Ok(None) => None,
+                Err(rejection) => Some(rejection),
+            };
+            if let Some(rejection) = rejection {
+                rejections.insert(recipient.clone(), rejection);
+            }
+        }
+        Ok(rejections)
+    }
+
+    /// Delivers the list message to the members with a local mailbox, like a message sent to each of them
+    ///
+    /// There is no queue, so members on other servers do not get it. The list accepts the message if any member stored it
+    async fn deliver_list(
+        service: &SMTPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+        directory: &D,
+        expanded: ExpandedList,
+    ) -> Result<Reply, SMTPServiceError> {
+        let mut members = Vec::with_capacity(expanded.deliveries.len());
+        for delivery in expanded.deliveries {
+            let resolved =
+                resolve_recipient(directory, &service.domain_config, &delivery.recipient)
+                    .await
+                    .map_err(|e| SMTPServiceError::Directory(Box::new(e)))?;
+            match resolved {
+                Some(_) => members.push(delivery.recipient),
+                None => warn!(
+                    "List member {} is not on this server and needs the queue",
+                    delivery.recipient
+                ),
+            }
+        }
+        if members.is_empty() {
+            return Ok(Reply::new(
+                550,
+                "5.1.1 The list has no members on this server",
+            ));
+        }
+        let replies = Self::deliver(service, directory, members, expanded.message).await?;
+        if replies.values().any(Reply::is_success) {
+            return Ok(Reply::new(250, "2.0.0 Delivered to the list"));
+        }
+        Ok(Reply::new(451, "4.3.0 Unable to store the message"))
+    }
+
+    /// Stores the message once for every recipient with a local mailbox and returns the reply of each recipient
+    ///
+    /// Messages to `user+detail@` go to the folder named `detail` if the mailbox has one. Everything else goes to the [INBOX],
+    /// which is created by the first delivery. Other recipients were rejected at RCPT
+    async fn deliver(
+        service: &SMTPServiceAccess<D, DirectoryAccess, S, StorageAccess>,
+        directory: &D,
+        recipients: Vec<EmailAddress>,
+        message: Vec<u8>,
+    ) -> Result<HashMap<EmailAddress, Reply>, SMTPServiceError> {
+        let mut replies = HashMap::with_capacity(recipients.len());
+        let mut local = Vec::with_capacity(recipients.len());
+        for recipient in recipients {
+            let resolved = resolve_recipient(directory, &service.domain_config, &recipient)
+                .await
+                .map_err(|e| SMTPServiceError::Directory(Box::new(e)))?;
+            match resolved {
+                Some(resolved) => {
+                    let folder = resolved
+                        .detail
+                        .filter(|detail| !detail.eq_ignore_ascii_case(INBOX))
+                        .unwrap_or_else(|| INBOX.to_string());
+                    let delivery = Delivery {
+                        mailbox_id: resolved.account.mailbox_id,
+                        folder,
+                    };
+                    local.push((recipient, delivery));
+                }
+                // Removed from the directory since RCPT
+                None => {
+                    replies.insert(recipient, Reply::new(550, "5.1.1 No such user"));
+                }
+            }
+        }
+        if local.is_empty() {
+            return Ok(replies);
+        }
+        let message = NewMessage {
+            flags: vec![],
+            internal_date: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs() as i64)
+                .unwrap_or_default(),
+            contents: message,
+        };
+        let deliveries = local.iter().map(|(_, delivery)| delivery.clone()).collect();
+        let results = match service.storage_service_access.get_service().await {
+            Ok(storage) => Self::store(&storage, deliveries, message).await,
+            Err(error) => {
+                warn!("Unable to reach the storage: {}", error);
+                None
+            }
+        };
+        let Some(results) = results else {
+            for (recipient, _) in local {
+                replies.insert(
+                    recipient,
+                    Reply::new(451, "4.3.0 Unable to store the message"),
+                );
+            }
+            return Ok(replies);
+        };
+        for ((recipient, delivery), result) in local.into_iter().zip(results) {
+            let reply = match result {
+                Ok(_) => Reply::new(250, "2.0.0 Delivered"),
+                Err(error) => {
+                    warn!("Unable to deliver to {}: {}", delivery.mailbox_id, error);
+                    Reply::new(451, "4.3.0 Unable to store the message")
+                }
+            };
+            replies.insert(recipient, reply);
+        }
+        Ok(replies)
+    }
+
+    /// Hands the message to the storage in one call. None if the storage failed
+    ///
+    /// Deliveries to a folder that does not exist are moved to the [INBOX]. Mailboxes without an [INBOX] get one.
+    /// Each of those steps costs another call
+    async fn store(
+        storage: &S,
+        mut deliveries: Vec<Delivery>,
+        message: NewMessage,
+    ) -> Option<Vec<StorageResult<MessageInfo>>> {
+        let stored = storage
+            .deliver_message(deliveries.clone(), message.clone())
+            .await;
+        let mut results = match stored {
+            Ok(results) => results,
+            Err(error) => {
+                warn!("Unable to store the message: {}", error);
+                return None;
+            }
+        };
+        // A missing detail folder falls back to the inbox, which may be missing as well
+        for _ in 0..2 {
+            let missing: Vec<usize> = results
+                .iter()
+                .enumerate()
+                .filter(|(_, result)| matches!(result, Err(StorageError::FolderNotFound(_))))
+                .map(|(index, _)| index)
+                .collect();
+            if missing.is_empty() {
+                break;
+            }
+            let mut retry = Vec::with_capacity(missing.len());
+            for index in &missing {
+                let delivery = &mut deliveries[*index];
+                if delivery.folder != INBOX {
+                    delivery.folder = INBOX.to_string();
+                    retry.push(delivery.clone());
+                    continue;
+                }
+                match storage
+                    .create_folder(delivery.mailbox_id, delivery.folder.clone())
+                    .await
+                {
+                    Ok(Ok(_)) | Ok(Err(StorageError::FolderExists(_))) => {}
+                    Ok(Err(error)) => warn!("Unable to create the inbox: {}", error),
+                    Err(error) => {
+                        warn!("Unable to create the inbox: {}", error);
+                        return Some(results);
+                    }
+                }
+                retry.push(delivery.clone());
+            }
+            match storage.deliver_message(retry, message.clone()).await {
+                Ok(retried) => {
+                    for (index, result) in missing.into_iter().zip(retried) {
+                        results[index] = result;
+                    }
+                }
+                Err(error) => {
+                    warn!("Unable to store the message: {}", error);
+                    break;
+                }
+            }
+        }
+        Some(results)
+    }
+}
+
+/// Reads the message until `<CRLF>.<CRLF>` removing the dot stuffing
+///
+/// None if the message is larger than `max_size`. It is still read to the end so the session can continue.
+/// Zero is no limit
+async fn read_data(
+    reader: &mut (impl AsyncBufReadExt + Unpin),
+    max_size: u64,
+) -> io::Result<Option<Vec<u8>>> {
+    let max_size = match max_size {
+        0 => usize::MAX,
+        max_size => usize::try_from(max_size).unwrap_or(usize::MAX),
+    };
+    let mut message = Vec::new();
+    let mut line = Vec::new();
+    let mut too_big = false;
+    loop {
+        // Room for the stuffed dot. Once too big only the terminating dot has to fit
+        let limit = match too_big {
+            true => 3,
+            false => (max_size - message.len()).saturating_add(1).max(3),
+        };
+        match read_line(reader, &mut line, limit).await? {
+            Line::Complete => {}
+            Line::TooLong => {
+                too_big = true;
+                continue;
+            }
+            Line::Closed => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed during DATA",
+                ))
+            }
+        }
+        if line == b".\r\n" || line == b".\n" {
+            return Ok((!too_big).then_some(message));
+        }
+        let line = line.strip_prefix(b".").unwrap_or(&line);
+        if too_big || message.len() + line.len() > max_size {
+            too_big = true;
+        } else {
+            message.extend_from_slice(line);
+        }
+    }
+}
+
+/// The result of [read_line]
+#[derive(Debug, PartialEq, Eq)]
+enum Line {
+    Complete,
+    /// The line was longer than the limit. It has been read to the end and discarded
+    TooLong,
+    Closed,
+}
+
+/// Reads a line including the `\n` into `line` without buffering more than `limit` octets
+async fn read_line(
+    reader: &mut (impl AsyncBufReadExt + Unpin),
+    line: &mut Vec<u8>,
+    limit: usize,
+) -> io::Result<Line> {
+    line.clear();
+    let mut too_long = false;
+    loop {
+        let buffer = reader.fill_buf().await?;
+        if buffer.is_empty() {
+            return Ok(Line::Closed);
+        }
+        let (used, complete) = match buffer.iter().position(|byte| *byte == b'\n') {
+            Some(end) => (end + 1, true),
+            None => (buffer.len(), false),
+        };
+        if !too_long {
+            if line.len() + used > limit {
+                too_long = true;
+                line.clear();
+            } else {
+                line.extend_from_slice(&buffer[..used]);
+            }
+        }
+        reader.consume(used);
+        if complete {
+            return Ok(if too_long {
+                Line::TooLong
+            } else {
+                Line::Complete
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smtp_client::{read_data, read_line, Line};
+
+    #[tokio::test]
+    pub async fn test_read_line_limit() {
+        let mut reader: &[u8] = b"NOOP\r\nMAIL FROM:<a@example.com>\r\nQUIT\r\nRSET";
+        let mut line = Vec::new();
+        assert_eq!(
+            read_line(&mut reader, &mut line, 10).await.unwrap(),
+            Line::Complete
+        );
+        assert_eq!(line, b"NOOP\r\n");
+        assert_eq!(
+            read_line(&mut reader, &mut line, 10).await.unwrap(),
+            Line::TooLong
+        );
+        assert!(line.is_empty());
+        assert_eq!(
+            read_line(&mut reader, &mut line, 10).await.unwrap(),
+            Line::Complete
+        );
+        assert_eq!(line, b"QUIT\r\n");
+        assert_eq!(
+            read_line(&mut reader, &mut line, 10).await.unwrap(),
+            Line::Closed
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_read_data_size() {
+        let data: &[u8] = b"Subject: a\r\n\r\n..dot\r\n.\r\nNOOP\r\n";
+        let mut reader = data;
+        let message = read_data(&mut reader, 20).await.unwrap();
+        assert_eq!(message.as_deref(), Some(&b"Subject: a\r\n\r\n.dot\r\n"[..]));
+        assert_eq!(reader, b"NOOP\r\n");
+
+        // The rest of the message is read so the next command is not taken from it
+        let mut reader = data;
+        assert_eq!(read_data(&mut reader, 19).await.unwrap(), None);
+        assert_eq!(reader, b"NOOP\r\n");
+        let mut reader: &[u8] = b"Subject: a very long subject\r\n.\r\nNOOP\r\n";
+        assert_eq!(read_data(&mut reader, 5).await.unwrap(), None);
+        assert_eq!(reader, b"NOOP\r\n");
+
+        let mut reader: &[u8] = b"Subject: a\r\n";
+        assert!(read_data(&mut reader, 0).await.is_err());
+    }
 }
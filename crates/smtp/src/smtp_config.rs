@@ -1,9 +1,27 @@
+use directories::oauth::OAuthConfig;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use strum::{AsRefStr, Display, EnumIs, EnumString, IntoStaticStr};
 use utils::configs::{Config, ConfigName};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SMTPConfig {
+    /// The name this server uses in greetings
+    #[serde(default = "default_hostname")]
+    pub hostname: String,
     pub hosts: Vec<SMTPHost>,
+    /// Enables AUTH OAUTHBEARER and XOAUTH2 on SMTP hosts
+    #[serde(default)]
+    pub oauth: Option<OAuthConfig>,
+    /// Bytes. Advertised with SIZE. Larger messages are rejected with 552
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: u64,
+}
+fn default_hostname() -> String {
+    "localhost".to_string()
+}
+fn default_max_message_size() -> u64 {
+    50 * 1024 * 1024
 }
 impl Default for SMTPConfig {
     fn default() -> Self {
@@ -11,16 +29,24 @@ impl Default for SMTPConfig {
         hosts.push(SMTPHost {
             bind: "0.0.0.0:25".to_string(),
             greeting: None,
+            protocol: SMTPProtocol::SMTP,
         });
         hosts.push(SMTPHost {
             bind: "0.0.0.0:587".to_string(),
             greeting: None,
+            protocol: SMTPProtocol::SMTP,
         });
         hosts.push(SMTPHost {
             bind: "0.0.0.0:465".to_string(),
             greeting: None,
+            protocol: SMTPProtocol::SMTP,
         });
-        return SMTPConfig { hosts };
+        return SMTPConfig {
+            hostname: default_hostname(),
+            hosts,
+            oauth: None,
+            max_message_size: default_max_message_size(),
+        };
     }
 }
 impl Config for SMTPConfig {
@@ -38,8 +64,61 @@ impl Config for SMTPConfig {
         ConfigName::Name("smtp.toml")
     }
 }
+/// The protocol spoken by an [SMTPHost]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    Serialize,
+    Deserialize,
+    AsRefStr,
+    IntoStaticStr,
+    EnumIs,
+    EnumString,
+    Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SMTPProtocol {
+    #[default]
+    SMTP,
+    /// [RFC 2033](https://www.rfc-editor.org/rfc/rfc2033) Local Mail Transfer Protocol.
+    ///
+    /// Greets with LHLO and replies once per recipient after DATA.
+    /// Mail is delivered straight into storage instead of being queued.
+    LMTP,
+}
+/// Where an [SMTPHost] listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+/// # Example
+/// ```toml
+/// [[hosts]]
+/// bind = "0.0.0.0:25"
+///
+/// [[hosts]]
+/// bind = "unix:/run/nitro_mail/lmtp.sock"
+/// protocol = "lmtp"
+/// ```
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SMTPHost {
+    /// A TCP socket address or `unix:<path>` for a Unix socket
     pub bind: String,
     pub greeting: Option<String>,
+    #[serde(default)]
+    pub protocol: SMTPProtocol,
+}
+impl SMTPHost {
+    pub fn bind_address(&self) -> BindAddress {
+        match self.bind.strip_prefix("unix:") {
+            Some(path) => BindAddress::Unix(PathBuf::from(path)),
+            None => BindAddress::Tcp(self.bind.clone()),
+        }
+    }
 }
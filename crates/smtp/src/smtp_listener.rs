@@ -1,10 +1,11 @@
-use crate::smtp_config::SMTPHost;
+use crate::smtp_client::{Connection, SMTPStream};
+use crate::smtp_config::{BindAddress, SMTPHost};
 use crate::smtp_service::{SMTPServiceAccess, SMTPServiceError};
 use directories::directory_type::Directory;
 use std::net::SocketAddr;
 
 use storages::storage_type::Storage;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, UnixListener};
 use utils::service::ServiceAccess;
 
 pub struct Instance<
@@ -24,21 +25,37 @@ impl<
     > Instance<D, DirectoryAccess, S, StorageAccess>
 {
     pub async fn run(self) -> Result<(), SMTPServiceError> {
-        let socket = TcpListener::bind(self.host.bind).await?;
-
-        while let Ok((stream, addr)) = socket.accept().await {
-            let connection = Connection {
-                stream,
-                addr,
-
-                service: self.service.clone(),
-            };
-            tokio::spawn(async move {
-                if let Err(e) = connection.run().await {
-                    eprintln!("Error in SMTP connection: {:?}", e);
+        match self.host.bind_address() {
+            BindAddress::Tcp(bind) => {
+                let socket = TcpListener::bind(bind).await?;
+                while let Ok((stream, addr)) = socket.accept().await {
+                    self.spawn_connection(SMTPStream::Tcp(stream), Some(addr));
+                }
+            }
+            BindAddress::Unix(path) => {
+                if path.exists() {
+                    // Left behind by a previous run
+                    std::fs::remove_file(&path)?;
                 }
-            });
+                let socket = UnixListener::bind(path)?;
+                while let Ok((stream, _)) = socket.accept().await {
+                    self.spawn_connection(SMTPStream::Unix(stream), None);
+                }
+            }
         }
         Ok(())
     }
+    fn spawn_connection(&self, stream: SMTPStream, addr: Option<SocketAddr>) {
+        let connection = Connection {
+            stream,
+            addr,
+            host: self.host.clone(),
+            service: self.service.clone(),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = connection.run().await {
+                eprintln!("Error in SMTP connection: {:?}", e);
+            }
+        });
+    }
 }
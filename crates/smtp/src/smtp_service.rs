@@ -1,6 +1,7 @@
 use crate::smtp_config::SMTPConfig;
 use crate::smtp_listener::Instance;
 use directories::directory_type::Directory;
+use directories::oauth::{OAuthAuthenticator, TokenError};
 use std::error::Error;
 use std::io;
 use std::path::PathBuf;
@@ -23,6 +24,14 @@ pub enum SMTPServiceError {
     Config(#[from] IOOrToml),
     #[error(transparent)]
     GettingDirectoryAccess(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    Directory(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    GettingStorageAccess(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    Storage(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    OAuth(#[from] TokenError),
 }
 pub struct SMTPServiceInner<
     D: Directory,
@@ -33,6 +42,8 @@ pub struct SMTPServiceInner<
     pub config: SMTPConfig,
     pub domain_config: DomainConfiguration,
     pub dkim_config: DKIMConfig,
+    /// Built from [SMTPConfig::oauth]
+    pub oauth: Option<OAuthAuthenticator>,
     pub running: AtomicBool,
     pub directory_service_access: DirectoryAccess,
     pub storage_service_access: StorageAccess,
@@ -52,11 +63,17 @@ impl<
     ) -> Result<SMTPService<D, DirectoryAccess, S, StorageAccess>, SMTPServiceError> {
         let (smtp_config, domain_config, dkim_config) =
             Configs::get_or_save_default(working_directory)?;
+        let oauth = smtp_config
+            .oauth
+            .as_ref()
+            .map(OAuthAuthenticator::from_config)
+            .transpose()?;
 
         let service = Arc::new(SMTPServiceInner {
             config: smtp_config.clone(),
             domain_config,
             dkim_config,
+            oauth,
             running: AtomicBool::new(true),
             directory_service_access: directory_service_access.clone(),
             storage_service_access: storage_service_access.clone(),
@@ -0,0 +1,530 @@
+use std::fmt::{Display, Formatter};
+
+use utils::helper_types::EmailAddress;
+use utils::sasl::SaslMechanism;
+
+use crate::smtp_config::SMTPProtocol;
+
+/// Octets in a command line including the CRLF. [RFC 5321 Section 4.5.3.1.4](https://www.rfc-editor.org/rfc/rfc5321#section-4.5.3.1.4)
+pub const MAX_COMMAND_LINE: usize = 512;
+/// AUTH commands and responses carry SASL data so they may be longer. [RFC 4954 Section 4](https://www.rfc-editor.org/rfc/rfc4954#section-4)
+pub const MAX_AUTH_LINE: usize = 12288;
+
+/// A reply sent to the client. Multiple lines are sent as a multiline reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    pub code: u16,
+    pub lines: Vec<String>,
+}
+impl Reply {
+    pub fn new(code: u16, line: impl Into<String>) -> Self {
+        Self {
+            code,
+            lines: vec![line.into()],
+        }
+    }
+    pub fn is_success(&self) -> bool {
+        (200..400).contains(&self.code)
+    }
+}
+impl Display for Reply {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let last = self.lines.len().saturating_sub(1);
+        for (index, line) in self.lines.iter().enumerate() {
+            let separator = if index == last { ' ' } else { '-' };
+            write!(f, "{}{}{}\r\n", self.code, separator, line)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// Waiting for HELO/EHLO or LHLO
+    Connected,
+    Ready,
+    MailFrom,
+    RcptTo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionResponse {
+    Reply(Reply),
+    /// The client may now send the message. Read until `<CRLF>.<CRLF>` then call [Session::finish_data]
+    StartData(Reply),
+    Quit(Reply),
+    /// Run the SASL exchange then call [Session::authenticated] or [Session::authentication_failed]
+    Authenticate {
+        mechanism: SaslMechanism,
+        /// The base64 initial response sent with the command
+        initial_response: Option<String>,
+    },
+    /// Check the recipient can receive mail, for example its quota, then call [Session::accept_recipient] or reply with the rejection
+    CheckRecipient(EmailAddress),
+}
+
+/// The SMTP/LMTP command state machine shared by every connection.
+///
+/// This does not do any I/O. The connection feeds it lines and writes back the replies.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub protocol: SMTPProtocol,
+    pub hostname: String,
+    pub state: SessionState,
+    pub client_name: Option<String>,
+    pub mail_from: Option<String>,
+    pub recipients: Vec<EmailAddress>,
+    /// Advertised in EHLO. Empty when AUTH is not offered
+    pub auth_mechanisms: Vec<SaslMechanism>,
+    /// The username of the authenticated account
+    pub authenticated: Option<String>,
+    /// Bytes. Advertised with SIZE. Zero for no limit
+    pub max_message_size: u64,
+}
+
+impl Session {
+    pub fn new(protocol: SMTPProtocol, hostname: impl Into<String>) -> Self {
+        Self {
+            protocol,
+            hostname: hostname.into(),
+            state: SessionState::Connected,
+            client_name: None,
+            mail_from: None,
+            recipients: vec![],
+            auth_mechanisms: vec![],
+            authenticated: None,
+            max_message_size: 0,
+        }
+    }
+    pub fn greeting(&self, greeting: Option<&str>) -> Reply {
+        let greeting = greeting.unwrap_or("nitro_mail");
+        let service = match self.protocol {
+            SMTPProtocol::SMTP => "ESMTP",
+            SMTPProtocol::LMTP => "LMTP",
+        };
+        Reply::new(220, format!("{} {} {}", self.hostname, service, greeting))
+    }
+    fn reset_transaction(&mut self) {
+        self.mail_from = None;
+        self.recipients.clear();
+        if self.state != SessionState::Connected {
+            self.state = SessionState::Ready;
+        }
+    }
+
+    /// `line` is the whole line including the CRLF. It may be up to [MAX_AUTH_LINE] octets long
+    pub fn handle_command(&mut self, line: &str) -> SessionResponse {
+        let length = line.len();
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (verb, argument) = match line.split_once(' ') {
+            Some((verb, argument)) => (verb, argument.trim()),
+            None => (line, ""),
+        };
+        if length > MAX_COMMAND_LINE && !verb.eq_ignore_ascii_case("AUTH") {
+            return SessionResponse::Reply(self.line_too_long());
+        }
+        let reply = match verb.to_ascii_uppercase().as_str() {
+            "HELO" | "EHLO" if self.protocol.is_lmtp() => {
+                Reply::new(500, "5.5.1 This is an LMTP server. Use LHLO")
+            }
+            "LHLO" if self.protocol.is_smtp() => Reply::new(500, "5.5.1 Unknown command"),
+            "HELO" => self.hello(argument, false),
+            "EHLO" | "LHLO" => self.hello(argument, true),
+            "MAIL" => self.mail(argument),
+            "RCPT" => return self.rcpt(argument),
+            "DATA" => return self.data(),
+            "AUTH" => return self.auth(argument),
+            "RSET" => {
+                self.reset_transaction();
+                Reply::new(250, "2.0.0 Ok")
+            }
+            "NOOP" => Reply::new(250, "2.0.0 Ok"),
+            "VRFY" => Reply::new(252, "2.5.0 Cannot VRFY user"),
+            "QUIT" => {
+                return SessionResponse::Quit(Reply::new(
+                    221,
+                    format!("2.0.0 {} closing connection", self.hostname),
+                ))
+            }
+            _ => Reply::new(502, "5.5.2 Command not implemented"),
+        };
+        SessionResponse::Reply(reply)
+    }
+
+    fn hello(&mut self, argument: &str, extended: bool) -> Reply {
+        if argument.is_empty() {
+            return Reply::new(501, "5.5.4 Syntax: EHLO hostname");
+        }
+        self.client_name = Some(argument.to_string());
+        self.state = SessionState::Ready;
+        self.reset_transaction();
+        if !extended {
+            return Reply::new(250, self.hostname.clone());
+        }
+        let mut lines = vec![
+            self.hostname.clone(),
+            "PIPELINING".to_string(),
+            "8BITMIME".to_string(),
+            "ENHANCEDSTATUSCODES".to_string(),
+            "SMTPUTF8".to_string(),
+        ];
+        if self.max_message_size > 0 {
+            lines.push(format!("SIZE {}", self.max_message_size));
+        }
+        if !self.auth_mechanisms.is_empty() {
+            let mechanisms: Vec<&str> = self.auth_mechanisms.iter().map(AsRef::as_ref).collect();
+            lines.push(format!("AUTH {}", mechanisms.join(" ")));
+        }
+        Reply { code: 250, lines }
+    }
+
+    /// `AUTH <mechanism> [initial-response]` from [RFC 4954](https://www.rfc-editor.org/rfc/rfc4954)
+    fn auth(&mut self, argument: &str) -> SessionResponse {
+        if self.auth_mechanisms.is_empty() {
+            return SessionResponse::Reply(Reply::new(502, "5.5.1 AUTH not available"));
+        }
+        match self.state {
+            SessionState::Connected => {
+                return SessionResponse::Reply(Reply::new(503, "5.5.1 Send EHLO first"))
+            }
+            SessionState::MailFrom | SessionState::RcptTo => {
+                return SessionResponse::Reply(Reply::new(
+                    503,
+                    "5.5.1 AUTH not allowed during a mail transaction",
+                ))
+            }
+            SessionState::Ready => {}
+        }
+        if self.authenticated.is_some() {
+            return SessionResponse::Reply(Reply::new(503, "5.5.1 Already authenticated"));
+        }
+        let mut parts = argument.split_whitespace();
+        let mechanism = parts
+            .next()
+            .and_then(SaslMechanism::from_name)
+            .filter(|mechanism| self.auth_mechanisms.contains(mechanism));
+        let Some(mechanism) = mechanism else {
+            return SessionResponse::Reply(Reply::new(
+                504,
+                "5.5.4 Unrecognized authentication type",
+            ));
+        };
+        SessionResponse::Authenticate {
+            mechanism,
+            initial_response: parts.next().map(str::to_string),
+        }
+    }
+
+    /// For command lines longer than [MAX_COMMAND_LINE], or [MAX_AUTH_LINE] for AUTH
+    pub fn line_too_long(&self) -> Reply {
+        Reply::new(500, "5.5.2 Line too long")
+    }
+
+    pub fn authenticated(&mut self, username: impl Into<String>) -> Reply {
+        self.authenticated = Some(username.into());
+        Reply::new(235, "2.7.0 Authentication successful")
+    }
+    pub fn authentication_failed(&self) -> Reply {
+        Reply::new(535, "5.7.8 Authentication credentials invalid")
+    }
+    /// Sent instead of checking credentials while the address or account is locked out
+    pub fn locked_out(&self) -> Reply {
+        Reply::new(454, "4.7.0 Too many failed logins, try again later")
+    }
+
+    fn mail(&mut self, argument: &str) -> Reply {
+        match self.state {
+            SessionState::Connected => return Reply::new(503, "5.5.1 Send hello first"),
+            SessionState::MailFrom | SessionState::RcptTo => {
+                return Reply::new(503, "5.5.1 Sender already specified")
+            }
+            SessionState::Ready => {}
+        }
+        let Some(path) = Self::parse_path(argument, "FROM:") else {
+            return Reply::new(501, "5.5.4 Syntax: MAIL FROM:<address>");
+        };
+        // The null reverse-path is used for bounces
+        if !path.is_empty() && EmailAddress::new(path).is_err() {
+            return Reply::new(553, "5.1.7 Invalid sender address");
+        }
+        if let Some(size) = Self::declared_size(argument) {
+            if self.max_message_size > 0 && size > self.max_message_size {
+                return Self::message_too_big();
+            }
+        }
+        self.mail_from = Some(path.to_string());
+        self.state = SessionState::MailFrom;
+        Reply::new(250, "2.1.0 Ok")
+    }
+
+    fn rcpt(&mut self, argument: &str) -> SessionResponse {
+        match self.state {
+            SessionState::MailFrom | SessionState::RcptTo => {}
+            _ => return SessionResponse::Reply(Reply::new(503, "5.5.1 Need MAIL command")),
+        }
+        let Some(path) = Self::parse_path(argument, "TO:") else {
+            return SessionResponse::Reply(Reply::new(501, "5.5.4 Syntax: RCPT TO:<address>"));
+        };
+        let Ok(address) = EmailAddress::new(path) else {
+            return SessionResponse::Reply(Reply::new(553, "5.1.3 Invalid recipient address"));
+        };
+        SessionResponse::CheckRecipient(address)
+    }
+
+    pub fn accept_recipient(&mut self, address: EmailAddress) -> Reply {
+        self.recipients.push(address);
+        self.state = SessionState::RcptTo;
+        Reply::new(250, "2.1.5 Ok")
+    }
+
+    fn data(&mut self) -> SessionResponse {
+        if self.state != SessionState::RcptTo {
+            return SessionResponse::Reply(Reply::new(503, "5.5.1 Need RCPT command"));
+        }
+        SessionResponse::StartData(Reply::new(354, "End data with <CR><LF>.<CR><LF>"))
+    }
+
+    /// The reply to a message larger than [max_message_size](Self::max_message_size)
+    fn message_too_big() -> Reply {
+        Reply::new(552, "5.3.4 Message size exceeds fixed maximum message size")
+    }
+
+    /// Called instead of [finish_data](Self::finish_data) when the message was larger than [max_message_size](Self::max_message_size)
+    pub fn reject_data(&mut self) -> Vec<Reply> {
+        self.finish_data(|_| Self::message_too_big())
+    }
+
+    /// Called once the message has been read.
+    ///
+    /// `deliver` is called once per recipient.
+    /// LMTP gets one reply per recipient, SMTP gets a single reply for the whole transaction.
+    ///
+    /// The SMTP transaction only fails if no recipient got the message.
+    /// Otherwise a retry by the client would deliver it again to the recipients that did
+    pub fn finish_data(&mut self, mut deliver: impl FnMut(&EmailAddress) -> Reply) -> Vec<Reply> {
+        let replies: Vec<Reply> = self.recipients.iter().map(&mut deliver).collect();
+        self.reset_transaction();
+        match self.protocol {
+            SMTPProtocol::LMTP => replies,
+            SMTPProtocol::SMTP if replies.iter().any(Reply::is_success) => {
+                vec![Reply::new(250, "2.0.0 Ok")]
+            }
+            SMTPProtocol::SMTP => {
+                let reply = replies
+                    .into_iter()
+                    .next()
+                    .unwrap_or_else(|| Reply::new(250, "2.0.0 Ok"));
+                vec![reply]
+            }
+        }
+    }
+
+    /// The `SIZE=` parameter of MAIL FROM. [RFC 1870](https://www.rfc-editor.org/rfc/rfc1870)
+    fn declared_size(argument: &str) -> Option<u64> {
+        argument.split_whitespace().skip(1).find_map(|parameter| {
+            let (name, value) = parameter.split_once('=')?;
+            name.eq_ignore_ascii_case("SIZE")
+                .then(|| value.parse().ok())
+                .flatten()
+        })
+    }
+
+    /// Parses `FROM:<path> [parameters]` returning the path without the angle brackets
+    fn parse_path<'a>(argument: &'a str, prefix: &str) -> Option<&'a str> {
+        if argument.len() < prefix.len() || !argument[..prefix.len()].eq_ignore_ascii_case(prefix) {
+            return None;
+        }
+        let path = argument[prefix.len()..].trim_start();
+        let path = path.split_once(' ').map(|(path, _)| path).unwrap_or(path);
+        path.strip_prefix('<')?.strip_suffix('>')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smtp_config::SMTPProtocol;
+    use crate::smtp_session::{Reply, Session, SessionResponse};
+    use utils::sasl::SaslMechanism;
+
+    fn reply_code(response: SessionResponse) -> u16 {
+        match response {
+            SessionResponse::Reply(reply)
+            | SessionResponse::StartData(reply)
+            | SessionResponse::Quit(reply) => reply.code,
+            SessionResponse::Authenticate { .. } => 334,
+            SessionResponse::CheckRecipient(_) => panic!("RCPT must be sent with rcpt()"),
+        }
+    }
+    /// Accepts the recipient like a connection does if it has no quota
+    fn rcpt(session: &mut Session, line: &str) -> u16 {
+        match session.handle_command(line) {
+            SessionResponse::CheckRecipient(address) => session.accept_recipient(address).code,
+            response => reply_code(response),
+        }
+    }
+
+    #[test]
+    pub fn test_reply_format() {
+        let reply = Reply {
+            code: 250,
+            lines: vec!["localhost".to_string(), "PIPELINING".to_string()],
+        };
+        assert_eq!(reply.to_string(), "250-localhost\r\n250 PIPELINING\r\n");
+    }
+
+    #[test]
+    pub fn test_lmtp_requires_lhlo() {
+        let mut session = Session::new(SMTPProtocol::LMTP, "localhost");
+        assert_eq!(reply_code(session.handle_command("EHLO client")), 500);
+        assert_eq!(reply_code(session.handle_command("LHLO client")), 250);
+
+        let mut session = Session::new(SMTPProtocol::SMTP, "localhost");
+        assert_eq!(reply_code(session.handle_command("LHLO client")), 500);
+    }
+
+    #[test]
+    pub fn test_lmtp_reply_per_recipient() {
+        let mut session = Session::new(SMTPProtocol::LMTP, "localhost");
+        session.handle_command("LHLO client");
+        assert_eq!(reply_code(session.handle_command("MAIL FROM:<>")), 250);
+        assert_eq!(rcpt(&mut session, "RCPT TO:<a@example.com>"), 250);
+        assert_eq!(rcpt(&mut session, "RCPT TO:<b@example.com>"), 250);
+        assert_eq!(reply_code(session.handle_command("DATA")), 354);
+        let replies = session.finish_data(|address| {
+            if address.as_str() == "a@example.com" {
+                Reply::new(250, "2.0.0 Ok")
+            } else {
+                Reply::new(550, "5.1.1 No such user")
+            }
+        });
+        assert_eq!(
+            replies.iter().map(|r| r.code).collect::<Vec<_>>(),
+            vec![250, 550]
+        );
+        assert!(session.recipients.is_empty());
+    }
+
+    #[test]
+    pub fn test_smtp_single_reply() {
+        let mut session = Session::new(SMTPProtocol::SMTP, "localhost");
+        session.handle_command("EHLO client");
+        session.handle_command("MAIL FROM:<sender@example.com> SIZE=100");
+        rcpt(&mut session, "RCPT TO:<a@example.com>");
+        rcpt(&mut session, "RCPT TO:<b@example.com>");
+        let replies = session.finish_data(|_| Reply::new(250, "2.0.0 Ok"));
+        assert_eq!(replies.len(), 1);
+
+        // Stored for one recipient, so a retry would deliver it twice
+        session.handle_command("MAIL FROM:<sender@example.com>");
+        rcpt(&mut session, "RCPT TO:<a@example.com>");
+        rcpt(&mut session, "RCPT TO:<b@example.com>");
+        let replies = session.finish_data(|address| match address.as_str() {
+            "a@example.com" => Reply::new(451, "4.3.0 Unable to store the message"),
+            _ => Reply::new(250, "2.0.0 Delivered"),
+        });
+        assert_eq!(replies, vec![Reply::new(250, "2.0.0 Ok")]);
+
+        session.handle_command("MAIL FROM:<sender@example.com>");
+        rcpt(&mut session, "RCPT TO:<a@example.com>");
+        let replies = session.finish_data(|_| Reply::new(451, "4.3.0 Unable to store the message"));
+        assert_eq!(replies[0].code, 451);
+    }
+
+    #[test]
+    pub fn test_bad_sequence() {
+        let mut session = Session::new(SMTPProtocol::SMTP, "localhost");
+        assert_eq!(reply_code(session.handle_command("MAIL FROM:<>")), 503);
+        session.handle_command("HELO client");
+        assert_eq!(reply_code(session.handle_command("DATA")), 503);
+        assert_eq!(rcpt(&mut session, "RCPT TO:<a@example.com>"), 503);
+    }
+
+    #[test]
+    pub fn test_rejected_recipient() {
+        let mut session = Session::new(SMTPProtocol::SMTP, "localhost");
+        session.handle_command("EHLO client");
+        session.handle_command("MAIL FROM:<>");
+        assert_eq!(rcpt(&mut session, "RCPT TO:<invalid>"), 553);
+        let SessionResponse::CheckRecipient(address) =
+            session.handle_command("RCPT TO:<full@example.com>")
+        else {
+            panic!("RCPT should be checked");
+        };
+        assert_eq!(address.as_str(), "full@example.com");
+        // The connection replied with the rejection instead of accepting it
+        assert!(session.recipients.is_empty());
+        assert_eq!(reply_code(session.handle_command("DATA")), 503);
+    }
+
+    #[test]
+    pub fn test_auth() {
+        let mut session = Session::new(SMTPProtocol::SMTP, "localhost");
+        session.handle_command("EHLO client");
+        assert_eq!(reply_code(session.handle_command("AUTH XOAUTH2")), 502);
+
+        session.auth_mechanisms = vec![SaslMechanism::OAuthBearer, SaslMechanism::XOAuth2];
+        let SessionResponse::Reply(reply) = session.handle_command("EHLO client") else {
+            panic!("EHLO should reply");
+        };
+        assert_eq!(reply.lines.last().unwrap(), "AUTH OAUTHBEARER XOAUTH2");
+        assert_eq!(reply_code(session.handle_command("AUTH PLAIN")), 504);
+        assert_eq!(
+            session.handle_command("AUTH oauthbearer bixhPXVzZXIsAQ=="),
+            SessionResponse::Authenticate {
+                mechanism: SaslMechanism::OAuthBearer,
+                initial_response: Some("bixhPXVzZXIsAQ==".to_string()),
+            }
+        );
+        assert_eq!(session.authenticated("test").code, 235);
+        assert_eq!(reply_code(session.handle_command("AUTH XOAUTH2")), 503);
+
+        let mut session = Session::new(SMTPProtocol::SMTP, "localhost");
+        session.auth_mechanisms = vec![SaslMechanism::XOAuth2];
+        assert_eq!(reply_code(session.handle_command("AUTH XOAUTH2")), 503);
+        session.handle_command("EHLO client");
+        session.handle_command("MAIL FROM:<>");
+        assert_eq!(reply_code(session.handle_command("AUTH XOAUTH2")), 503);
+    }
+
+    #[test]
+    pub fn test_line_too_long() {
+        let mut session = Session::new(SMTPProtocol::SMTP, "localhost");
+        session.handle_command("EHLO client");
+        let line = format!("MAIL FROM:<{}@example.com>\r\n", "a".repeat(512));
+        assert_eq!(reply_code(session.handle_command(&line)), 500);
+        assert_eq!(reply_code(session.handle_command("MAIL FROM:<>\r\n")), 250);
+
+        let mut session = Session::new(SMTPProtocol::SMTP, "localhost");
+        session.auth_mechanisms = vec![SaslMechanism::XOAuth2];
+        session.handle_command("EHLO client");
+        let line = format!("AUTH XOAUTH2 {}\r\n", "a".repeat(1024));
+        assert_eq!(reply_code(session.handle_command(&line)), 334);
+    }
+
+    #[test]
+    pub fn test_message_size() {
+        let mut session = Session::new(SMTPProtocol::LMTP, "localhost");
+        session.max_message_size = 1000;
+        let SessionResponse::Reply(reply) = session.handle_command("LHLO client") else {
+            panic!("LHLO should reply");
+        };
+        assert!(reply.lines.contains(&"SIZE 1000".to_string()));
+        assert_eq!(
+            reply_code(session.handle_command("MAIL FROM:<> SIZE=1001")),
+            552
+        );
+        assert_eq!(
+            reply_code(session.handle_command("MAIL FROM:<> size=1000")),
+            250
+        );
+        rcpt(&mut session, "RCPT TO:<a@example.com>");
+        rcpt(&mut session, "RCPT TO:<b@example.com>");
+        session.handle_command("DATA");
+        let replies = session.reject_data();
+        assert_eq!(
+            replies.iter().map(|r| r.code).collect::<Vec<_>>(),
+            vec![552, 552]
+        );
+        assert!(session.recipients.is_empty());
+    }
+}
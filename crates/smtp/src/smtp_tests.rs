@@ -0,0 +1,419 @@
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use ahash::{HashMap, HashMapExt, HashSet};
+use async_trait::async_trait;
+use interprocess::local_socket::tokio::LocalSocketListener;
+use parking_lot::Mutex;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::UnixStream;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use directories::directory_type::Directory;
+use directory_file::file_config::FileDirectoryConfig;
+use directory_file::file_directory::FileDirectory;
+use storages::folders::{Delivery, Folder, MessageInfo, NewMessage, StorageError, StorageResult};
+use storages::storage_service::storage_service_storage::StorageServiceStorageAccess;
+use storages::storage_service::StorageService;
+use storages::storage_type::Storage;
+use utils::configs::domain_configs::{Domain, DomainConfiguration};
+use utils::quota::QuotaUsage;
+use utils::service::Service;
+
+use crate::smtp_client::{Connection, SMTPStream};
+use crate::smtp_config::{SMTPConfig, SMTPHost, SMTPProtocol};
+use crate::smtp_service::{SMTPServiceError, SMTPServiceInner};
+
+const FULL_MAILBOX: Uuid = Uuid::from_u128(1);
+const UNCOUNTED_MAILBOX: Uuid = Uuid::from_u128(2);
+const UNLIMITED_MAILBOX: Uuid = Uuid::from_u128(3);
+const OTHER_MAILBOX: Uuid = Uuid::from_u128(4);
+
+#[derive(Debug, Error)]
+#[error("No usage for {0}")]
+struct UsageError(Uuid);
+
+/// Reports fixed usage. Mailboxes it does not know fail like a storage that is down
+///
+/// Every call to [Storage::deliver_message] is recorded
+#[derive(Clone, Default)]
+struct TestStorage {
+    usage: Arc<HashMap<Uuid, QuotaUsage>>,
+    folders: Arc<Mutex<HashSet<(Uuid, String)>>>,
+    deliveries: Arc<Mutex<Vec<Vec<Delivery>>>>,
+}
+impl Service for TestStorage {
+    type ServiceConfig = ();
+    type ServiceError = UsageError;
+}
+#[async_trait]
+impl Storage for TestStorage {
+    fn storage_name() -> &'static str {
+        "Test"
+    }
+
+    fn storage_path(&self) -> String {
+        String::new()
+    }
+
+    async fn quota_usage(&self, mailbox_id: Uuid) -> Result<QuotaUsage, UsageError> {
+        self.usage
+            .get(&mailbox_id)
+            .copied()
+            .ok_or(UsageError(mailbox_id))
+    }
+
+    async fn create_folder(
+        &self,
+        mailbox_id: Uuid,
+        name: String,
+    ) -> Result<StorageResult<Folder>, UsageError> {
+        self.folders.lock().insert((mailbox_id, name.clone()));
+        Ok(Ok(Folder {
+            name,
+            uid_validity: 1,
+            uid_next: 1,
+            highest_modseq: 0,
+        }))
+    }
+
+    async fn deliver_message(
+        &self,
+        deliveries: Vec<Delivery>,
+        message: NewMessage,
+    ) -> Result<Vec<StorageResult<MessageInfo>>, UsageError> {
+        self.deliveries.lock().push(deliveries.clone());
+        let folders = self.folders.lock();
+        let results = deliveries
+            .into_iter()
+            .map(|delivery| {
+                if !folders.contains(&(delivery.mailbox_id, delivery.folder.clone())) {
+                    return Err(StorageError::FolderNotFound(delivery.folder));
+                }
+                Ok(MessageInfo {
+                    uid: 1,
+                    modseq: 1,
+                    flags: message.flags.clone(),
+                    size: message.contents.len() as u64,
+                    internal_date: message.internal_date,
+                })
+            })
+            .collect();
+        Ok(results)
+    }
+}
+
+/// A directory in the temp folder that is removed on drop
+struct TestFiles(PathBuf);
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `full@example.com` may store one message and stores one. `uncounted@example.com` has a limit the storage can not check.
+/// `unlimited@example.com` and `other@example.com` have no limits. Only they may post to the list `staff@example.com`
+async fn file_directory(files: &TestFiles) -> FileDirectory {
+    std::fs::create_dir_all(&files.0).unwrap();
+    let mut accounts = String::new();
+    for (username, mailbox_id, quota) in [
+        ("full", FULL_MAILBOX, "{ messages = 1 }"),
+        ("uncounted", UNCOUNTED_MAILBOX, "{ messages = 1 }"),
+        ("unlimited", UNLIMITED_MAILBOX, "{}"),
+        ("other", OTHER_MAILBOX, "{}"),
+    ] {
+        accounts.push_str(&format!(
+            r#"
+            [[accounts]]
+            username = "{username}"
+            password = "$2y$05$abc"
+            email = "{username}@example.com"
+            quota = {quota}
+            mailbox_id = "{mailbox_id}"
+            "#
+        ));
+    }
+    accounts.push_str(
+        r#"
+        [[groups]]
+        name = "staff"
+        group_type = "List"
+        list_address = "staff@example.com"
+        members = ["unlimited", "other"]
+        "#,
+    );
+    std::fs::write(files.0.join("accounts.toml"), accounts).unwrap();
+    FileDirectory::load(FileDirectoryConfig {
+        accounts_file: files.0.join("accounts.toml"),
+        state_file: files.0.join("accounts.state.toml"),
+        ..Default::default()
+    })
+    .await
+    .unwrap()
+}
+
+fn domain_config() -> DomainConfiguration {
+    let mut domains = HashMap::new();
+    domains.insert(
+        "example".to_string(),
+        Domain {
+            domain: "example.com".to_string(),
+            ..Default::default()
+        },
+    );
+    DomainConfiguration { domains }
+}
+
+/// An SMTP or LMTP session whose storage is reached through a storage service. `example.com` is a local domain
+struct TestSession {
+    reader: BufReader<ReadHalf<UnixStream>>,
+    writer: WriteHalf<UnixStream>,
+    session: JoinHandle<Result<(), SMTPServiceError>>,
+    _files: TestFiles,
+}
+impl TestSession {
+    async fn start(storage: TestStorage) -> Self {
+        Self::start_with(storage, SMTPProtocol::LMTP).await
+    }
+    async fn start_with(storage: TestStorage, protocol: SMTPProtocol) -> Self {
+        let files =
+            TestFiles(std::env::temp_dir().join(format!("nitro_mail_smtp_{}", Uuid::new_v4())));
+        let directory = file_directory(&files).await;
+
+        let socket_name = format!("nitro_mail_storage_test_{}", Uuid::new_v4());
+        let listener = LocalSocketListener::bind(socket_name.as_str()).unwrap();
+        tokio::spawn(StorageService::new(storage).serve(listener));
+
+        let service = Arc::new(SMTPServiceInner {
+            config: SMTPConfig::default(),
+            domain_config: domain_config(),
+            dkim_config: Default::default(),
+            oauth: None,
+            running: AtomicBool::new(true),
+            directory_service_access: directory,
+            storage_service_access: StorageServiceStorageAccess { socket_name },
+        });
+        let (client, server) = UnixStream::pair().unwrap();
+        let connection = Connection {
+            stream: SMTPStream::Unix(server),
+            addr: None,
+            host: SMTPHost {
+                bind: "unix:/run/nitro_mail/lmtp.sock".to_string(),
+                greeting: None,
+                protocol,
+            },
+            service,
+        };
+        let (reader, writer) = tokio::io::split(client);
+        let mut session = TestSession {
+            reader: BufReader::new(reader),
+            writer,
+            session: tokio::spawn(connection.run()),
+            _files: files,
+        };
+        assert!(session.reply().await.starts_with("220"));
+        let hello = match protocol {
+            SMTPProtocol::SMTP => "EHLO client",
+            SMTPProtocol::LMTP => "LHLO client",
+        };
+        assert!(session.command(hello).await.starts_with("250"));
+        session
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{line}\r\n").as_bytes())
+            .await
+            .unwrap();
+    }
+    async fn command(&mut self, line: &str) -> String {
+        self.send(line).await;
+        self.reply().await
+    }
+    /// The last line of the reply
+    async fn reply(&mut self) -> String {
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).await.unwrap();
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return line.trim_end().to_string();
+            }
+        }
+    }
+
+    async fn quit(mut self) {
+        assert!(self.command("QUIT").await.starts_with("221"));
+        self.session.await.unwrap().unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_rcpt_quota_through_storage_service() {
+    let mut usage = HashMap::new();
+    usage.insert(
+        FULL_MAILBOX,
+        QuotaUsage {
+            storage: 100,
+            messages: 1,
+        },
+    );
+    let storage = TestStorage {
+        usage: Arc::new(usage),
+        ..Default::default()
+    };
+    let mut session = TestSession::start(storage).await;
+    assert!(session.command("MAIL FROM:<>").await.starts_with("250"));
+    assert_eq!(
+        session.command("RCPT TO:<full@example.com>").await,
+        "452 4.2.2 Mailbox full"
+    );
+    assert_eq!(
+        session.command("RCPT TO:<uncounted@example.com>").await,
+        "451 4.3.0 Unable to check the mailbox quota"
+    );
+    assert!(session
+        .command("RCPT TO:<unlimited@example.com>")
+        .await
+        .starts_with("250"));
+    session.quit().await;
+}
+
+#[tokio::test]
+async fn test_lmtp_delivers_once_per_message() {
+    let storage = TestStorage::default();
+    let mut session = TestSession::start(storage.clone()).await;
+    for _ in 0..2 {
+        assert!(session.command("MAIL FROM:<>").await.starts_with("250"));
+        for recipient in ["unlimited", "other"] {
+            let reply = session
+                .command(&format!("RCPT TO:<{recipient}@example.com>"))
+                .await;
+            assert!(reply.starts_with("250"));
+        }
+        assert_eq!(
+            session.command("RCPT TO:<unknown@example.com>").await,
+            "550 5.1.1 No such user"
+        );
+        assert!(session.command("DATA").await.starts_with("354"));
+        session.send("Subject: Hello\r\n\r\nHello\r\n.").await;
+        assert_eq!(session.reply().await, "250 2.0.0 Delivered");
+        assert_eq!(session.reply().await, "250 2.0.0 Delivered");
+    }
+    session.quit().await;
+
+    let inbox = |mailbox_id| Delivery {
+        mailbox_id,
+        folder: "INBOX".to_string(),
+    };
+    let both = vec![inbox(UNLIMITED_MAILBOX), inbox(OTHER_MAILBOX)];
+    // The first message finds no inbox. They are created and the message is handed over again
+    assert_eq!(
+        *storage.deliveries.lock(),
+        vec![both.clone(), both.clone(), both]
+    );
+}
+
+#[tokio::test]
+async fn test_smtp_rejects_undeliverable_recipients_at_rcpt() {
+    let storage = TestStorage::default();
+    let mut session = TestSession::start_with(storage.clone(), SMTPProtocol::SMTP).await;
+    assert!(session
+        .command("MAIL FROM:<sender@example.org>")
+        .await
+        .starts_with("250"));
+    assert_eq!(
+        session.command("RCPT TO:<unknown@example.com>").await,
+        "550 5.1.1 No such user"
+    );
+    assert_eq!(
+        session.command("RCPT TO:<someone@example.org>").await,
+        "550 5.7.1 Relaying is not available"
+    );
+    assert!(session
+        .command("RCPT TO:<unlimited@example.com>")
+        .await
+        .starts_with("250"));
+    assert!(session.command("DATA").await.starts_with("354"));
+    session.send("Subject: Hello\r\n\r\nHello\r\n.").await;
+    assert_eq!(session.reply().await, "250 2.0.0 Ok");
+    session.quit().await;
+    assert_eq!(storage.deliveries.lock().last().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_list_delivers_to_local_members() {
+    let storage = TestStorage::default();
+    let mut session = TestSession::start_with(storage.clone(), SMTPProtocol::SMTP).await;
+    assert!(session
+        .command("MAIL FROM:<sender@example.org>")
+        .await
+        .starts_with("250"));
+    assert_eq!(
+        session.command("RCPT TO:<staff@example.com>").await,
+        "550 5.7.1 Only members may post to this list"
+    );
+    assert!(session.command("RSET").await.starts_with("250"));
+    assert!(session
+        .command("MAIL FROM:<unlimited@example.com>")
+        .await
+        .starts_with("250"));
+    assert!(session
+        .command("RCPT TO:<staff@example.com>")
+        .await
+        .starts_with("250"));
+    assert!(session.command("DATA").await.starts_with("354"));
+    session.send("Subject: Hello\r\n\r\nHello\r\n.").await;
+    assert_eq!(session.reply().await, "250 2.0.0 Ok");
+    session.quit().await;
+
+    let inbox = |mailbox_id| Delivery {
+        mailbox_id,
+        folder: "INBOX".to_string(),
+    };
+    assert_eq!(
+        storage.deliveries.lock().last().unwrap(),
+        &vec![inbox(UNLIMITED_MAILBOX), inbox(OTHER_MAILBOX)]
+    );
+}
+
+#[tokio::test]
+async fn test_detail_files_into_existing_folder() {
+    let storage = TestStorage::default();
+    storage
+        .folders
+        .lock()
+        .insert((UNLIMITED_MAILBOX, "Lists".to_string()));
+    let mut session = TestSession::start(storage.clone()).await;
+    assert!(session.command("MAIL FROM:<>").await.starts_with("250"));
+    for recipient in ["unlimited+Lists", "other+Missing"] {
+        let reply = session
+            .command(&format!("RCPT TO:<{recipient}@example.com>"))
+            .await;
+        assert!(reply.starts_with("250"));
+    }
+    assert!(session.command("DATA").await.starts_with("354"));
+    session.send("Subject: Hello\r\n\r\nHello\r\n.").await;
+    assert_eq!(session.reply().await, "250 2.0.0 Delivered");
+    assert_eq!(session.reply().await, "250 2.0.0 Delivered");
+    session.quit().await;
+
+    let delivery = |mailbox_id, folder: &str| Delivery {
+        mailbox_id,
+        folder: folder.to_string(),
+    };
+    // The missing folder falls back to the inbox, which is created on the next call
+    assert_eq!(
+        *storage.deliveries.lock(),
+        vec![
+            vec![
+                delivery(UNLIMITED_MAILBOX, "Lists"),
+                delivery(OTHER_MAILBOX, "Missing"),
+            ],
+            vec![delivery(OTHER_MAILBOX, "INBOX")],
+            vec![delivery(OTHER_MAILBOX, "INBOX")],
+        ]
+    );
+}
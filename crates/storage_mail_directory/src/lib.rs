@@ -0,0 +1,5 @@
+pub mod maildir_config;
+pub mod maildir_storage;
+#[cfg(test)]
+mod maildir_tests;
+pub mod uid_list;
@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use utils::configs::{Config, ConfigName};
+
+fn default_path() -> PathBuf {
+    PathBuf::from("maildir")
+}
+
+/// # Example
+/// ```toml
+/// path = "/var/lib/nitro_mail/maildir"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MaildirStorageConfig {
+    /// Holds a Maildir++ directory for every mailbox and the blobs their messages are linked to.
+    /// Hard links can not cross filesystems, so all of it has to be on one
+    #[serde(default = "default_path")]
+    pub path: PathBuf,
+}
+impl Config for MaildirStorageConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/maildir_storage")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("maildir.storage.toml")
+    }
+}
+impl Default for MaildirStorageConfig {
+    fn default() -> Self {
+        MaildirStorageConfig {
+            path: default_path(),
+        }
+    }
+}
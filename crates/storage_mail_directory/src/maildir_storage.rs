@@ -0,0 +1,403 @@
+use std::convert::Infallible;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ahash::HashSet;
+use async_trait::async_trait;
+use futures::future::Ready;
+use thiserror::Error;
+use tokio::fs;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use storages::blob_store::{BlobStore, GarbageCollection};
+use storages::events::{StorageEvent, StorageEvents};
+use storages::folders::{
+    Delivery, Folder, MessageInfo, NewMessage, StorageError, StorageResult, DELETED_FLAG, INBOX,
+};
+use storages::storage_type::Storage;
+use utils::quota::QuotaUsage;
+use utils::service::{Service, ServiceAccess};
+
+use crate::maildir_config::MaildirStorageConfig;
+use crate::uid_list::{new_file_name, split_flags, UidEntry, UidList, UID_LIST_FILE};
+
+const MAILBOX_DIRECTORY: &str = "mailboxes";
+const BLOB_DIRECTORY: &str = "blobs";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Every mailbox is a Maildir++ directory. INBOX is the directory itself and other folders are `.name` in it
+///
+/// A message is written to the [BlobStore] once and hard linked into every folder it is delivered to.
+/// The filesystem counts the links, so a blob is garbage once no folder links it
+#[derive(Debug, Clone)]
+pub struct MaildirStorage {
+    mailboxes: PathBuf,
+    blobs: Arc<BlobStore>,
+    /// Held while changing a folder or collecting garbage.
+    /// Otherwise a blob could be removed between being written and being linked
+    lock: Arc<Mutex<()>>,
+    events: StorageEvents,
+}
+impl Service for MaildirStorage {
+    type ServiceConfig = MaildirStorageConfig;
+    type ServiceError = Error;
+}
+impl ServiceAccess for MaildirStorage {
+    type ServiceResponse = Self;
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn get_service(&self) -> Self::Future {
+        futures::future::ready(Ok(self.clone()))
+    }
+}
+impl MaildirStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            mailboxes: path.join(MAILBOX_DIRECTORY),
+            blobs: Arc::new(BlobStore::new(path.join(BLOB_DIRECTORY))),
+            lock: Arc::new(Mutex::new(())),
+            events: StorageEvents::default(),
+        }
+    }
+
+    fn mailbox(&self, mailbox_id: Uuid) -> PathBuf {
+        self.mailboxes.join(mailbox_id.to_string())
+    }
+    /// None for names that would leave the mailbox or clash with the Maildir directories
+    fn folder_path(&self, mailbox_id: Uuid, name: &str) -> Option<PathBuf> {
+        if name == INBOX {
+            return Some(self.mailbox(mailbox_id));
+        }
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        Some(self.mailbox(mailbox_id).join(format!(".{name}")))
+    }
+    /// The folder and its UIDs or the error for the request
+    async fn folder(
+        &self,
+        mailbox_id: Uuid,
+        name: &str,
+    ) -> Result<StorageResult<(PathBuf, UidList)>, Error> {
+        let Some(path) = self.folder_path(mailbox_id, name) else {
+            return Ok(Err(StorageError::InvalidFolderName(name.to_string())));
+        };
+        match UidList::load(&path).await? {
+            Some(list) => Ok(Ok((path, list))),
+            None => Ok(Err(StorageError::FolderNotFound(name.to_string()))),
+        }
+    }
+    /// The names of the folders of the mailbox that have a UID list
+    async fn folder_names(&self, mailbox_id: Uuid) -> io::Result<Vec<String>> {
+        let mailbox = self.mailbox(mailbox_id);
+        let mut entries = match fs::read_dir(&mailbox).await {
+            Ok(ok) => ok,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error),
+        };
+        let mut names = vec![];
+        if fs::try_exists(mailbox.join(UID_LIST_FILE)).await? {
+            names.push(INBOX.to_string());
+        }
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(name) = file_name.strip_prefix('.') else {
+                continue;
+            };
+            if fs::try_exists(entry.path().join(UID_LIST_FILE)).await? {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+    /// The path of the message. Maildir clients may have moved it, for example from `new` to `cur`,
+    /// in which case the entry is updated. None if it is gone
+    async fn locate(folder: &Path, message: &mut UidEntry) -> io::Result<Option<PathBuf>> {
+        let path = folder.join(&message.file);
+        if fs::try_exists(&path).await? {
+            return Ok(Some(path));
+        }
+        for directory in ["cur", "new"] {
+            let mut files = fs::read_dir(folder.join(directory)).await?;
+            while let Some(file) = files.next_entry().await? {
+                let name = file.file_name().to_string_lossy().to_string();
+                let base = name.split_once(':').map(|(base, _)| base).unwrap_or(&name);
+                if base == message.base_name() {
+                    message.file = format!("{directory}/{name}");
+                    return Ok(Some(file.path()));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl Storage for MaildirStorage {
+    fn storage_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "storage_mail_directory"
+    }
+
+    fn storage_path(&self) -> String {
+        self.mailboxes.display().to_string()
+    }
+
+    /// Changes made by other Maildir clients are not published
+    fn subscribe(&self) -> Option<Receiver<StorageEvent>> {
+        Some(self.events.subscribe())
+    }
+
+    async fn quota_usage(&self, mailbox_id: Uuid) -> Result<QuotaUsage, Self::ServiceError> {
+        let mut usage = QuotaUsage::default();
+        for name in self.folder_names(mailbox_id).await? {
+            let Ok((_, list)) = self.folder(mailbox_id, &name).await? else {
+                continue;
+            };
+            usage.messages += list.messages.len() as u64;
+            usage.storage += list
+                .messages
+                .iter()
+                .map(|message| message.size)
+                .sum::<u64>();
+        }
+        Ok(usage)
+    }
+
+    async fn create_folder(
+        &self,
+        mailbox_id: Uuid,
+        name: String,
+    ) -> Result<StorageResult<Folder>, Self::ServiceError> {
+        let Some(path) = self.folder_path(mailbox_id, &name) else {
+            return Ok(Err(StorageError::InvalidFolderName(name)));
+        };
+        let _guard = self.lock.lock().await;
+        if UidList::load(&path).await?.is_some() {
+            return Ok(Err(StorageError::FolderExists(name)));
+        }
+        for directory in ["cur", "new", "tmp"] {
+            fs::create_dir_all(path.join(directory)).await?;
+        }
+        let uid_validity = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32;
+        let list = UidList::new(uid_validity);
+        list.save(&path).await?;
+        Ok(Ok(list.folder(name)))
+    }
+
+    async fn list_folders(
+        &self,
+        mailbox_id: Uuid,
+    ) -> Result<StorageResult<Vec<Folder>>, Self::ServiceError> {
+        let mut folders = vec![];
+        for name in self.folder_names(mailbox_id).await? {
+            if let Ok((_, list)) = self.folder(mailbox_id, &name).await? {
+                folders.push(list.folder(name));
+            }
+        }
+        Ok(Ok(folders))
+    }
+
+    /// The blobs of the messages are left for [collect_garbage](Storage::collect_garbage)
+    async fn delete_folder(
+        &self,
+        mailbox_id: Uuid,
+        name: String,
+    ) -> Result<StorageResult<bool>, Self::ServiceError> {
+        let _guard = self.lock.lock().await;
+        let path = match self.folder(mailbox_id, &name).await? {
+            Ok((path, _)) => path,
+            Err(StorageError::FolderNotFound(_)) => return Ok(Ok(false)),
+            Err(error) => return Ok(Err(error)),
+        };
+        if name == INBOX {
+            // The other folders are inside the INBOX directory
+            fs::remove_file(path.join(UID_LIST_FILE)).await?;
+            for directory in ["cur", "new", "tmp"] {
+                fs::remove_dir_all(path.join(directory)).await?;
+            }
+        } else {
+            fs::remove_dir_all(path).await?;
+        }
+        self.events.publish(StorageEvent::FolderDeleted {
+            mailbox_id,
+            folder: name,
+        });
+        Ok(Ok(true))
+    }
+
+    async fn append_message(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        message: NewMessage,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        let mut results = self
+            .deliver_message(vec![Delivery { mailbox_id, folder }], message)
+            .await?;
+        Ok(results.remove(0))
+    }
+
+    /// The contents are written once and linked into every folder.
+    /// Messages without flags go to `new`, others to `cur` with their flags in the name
+    async fn deliver_message(
+        &self,
+        deliveries: Vec<Delivery>,
+        message: NewMessage,
+    ) -> Result<Vec<StorageResult<MessageInfo>>, Self::ServiceError> {
+        let _guard = self.lock.lock().await;
+        let hash = self.blobs.write(&message.contents).await?;
+        let (info, keywords) = split_flags(&message.flags);
+        let mut results = Vec::with_capacity(deliveries.len());
+        for delivery in deliveries {
+            let (path, mut list) = match self.folder(delivery.mailbox_id, &delivery.folder).await? {
+                Ok(ok) => ok,
+                Err(error) => {
+                    results.push(Err(error));
+                    continue;
+                }
+            };
+            let name = new_file_name(message.internal_date);
+            let file = match message.flags.is_empty() {
+                true => format!("new/{name}"),
+                false => format!("cur/{name}{info}"),
+            };
+            self.blobs.link(&hash, &path.join(&file)).await?;
+            let size = message.contents.len() as u64;
+            let info = list
+                .push(message.internal_date, size, file, keywords.clone())
+                .info();
+            list.save(&path).await?;
+            self.events.publish(StorageEvent::MessageAppended {
+                mailbox_id: delivery.mailbox_id,
+                folder: delivery.folder,
+                uid: info.uid,
+                internal_date: info.internal_date,
+            });
+            results.push(Ok(info));
+        }
+        Ok(results)
+    }
+
+    async fn list_messages(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+    ) -> Result<StorageResult<Vec<MessageInfo>>, Self::ServiceError> {
+        Ok(self
+            .folder(mailbox_id, &folder)
+            .await?
+            .map(|(_, list)| list.messages.iter().map(UidEntry::info).collect()))
+    }
+
+    async fn read_message(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+    ) -> Result<StorageResult<Vec<u8>>, Self::ServiceError> {
+        let (path, mut list) = match self.folder(mailbox_id, &folder).await? {
+            Ok(ok) => ok,
+            Err(error) => return Ok(Err(error)),
+        };
+        let Some(message) = list.get_mut(uid) else {
+            return Ok(Err(StorageError::MessageNotFound(uid)));
+        };
+        match Self::locate(&path, message).await? {
+            Some(file) => Ok(Ok(fs::read(file).await?)),
+            None => Ok(Err(StorageError::MessageNotFound(uid))),
+        }
+    }
+
+    /// The message is moved to `cur` as it has been seen by a client
+    async fn set_flags(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+        flags: Vec<String>,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        let _guard = self.lock.lock().await;
+        let (path, mut list) = match self.folder(mailbox_id, &folder).await? {
+            Ok(ok) => ok,
+            Err(error) => return Ok(Err(error)),
+        };
+        list.highest_modseq += 1;
+        let modseq = list.highest_modseq;
+        let Some(message) = list.get_mut(uid) else {
+            return Ok(Err(StorageError::MessageNotFound(uid)));
+        };
+        let Some(current) = Self::locate(&path, message).await? else {
+            return Ok(Err(StorageError::MessageNotFound(uid)));
+        };
+        let (info, keywords) = split_flags(&flags);
+        let file = format!("cur/{}{info}", message.base_name());
+        fs::rename(current, path.join(&file)).await?;
+        message.file = file;
+        message.keywords = keywords;
+        message.modseq = modseq;
+        let info = message.info();
+        list.save(&path).await?;
+        Ok(Ok(info))
+    }
+
+    async fn expunge(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+    ) -> Result<StorageResult<Vec<u32>>, Self::ServiceError> {
+        let _guard = self.lock.lock().await;
+        let (path, mut list) = match self.folder(mailbox_id, &folder).await? {
+            Ok(ok) => ok,
+            Err(error) => return Ok(Err(error)),
+        };
+        let mut expunged = vec![];
+        let mut kept = Vec::with_capacity(list.messages.len());
+        for mut message in std::mem::take(&mut list.messages) {
+            if !message.flags().iter().any(|flag| flag == DELETED_FLAG) {
+                kept.push(message);
+                continue;
+            }
+            if let Some(file) = Self::locate(&path, &mut message).await? {
+                fs::remove_file(file).await?;
+            }
+            expunged.push(message.uid);
+        }
+        list.messages = kept;
+        if !expunged.is_empty() {
+            list.highest_modseq += 1;
+            list.save(&path).await?;
+            self.events.publish(StorageEvent::MessagesExpunged {
+                mailbox_id,
+                folder,
+                uids: expunged.clone(),
+            });
+        }
+        Ok(Ok(expunged))
+    }
+
+    /// Removes the blobs no folder links anymore
+    async fn collect_garbage(
+        &self,
+    ) -> Result<StorageResult<GarbageCollection>, Self::ServiceError> {
+        let _guard = self.lock.lock().await;
+        Ok(Ok(self.blobs.collect_garbage(&HashSet::default()).await?))
+    }
+}
@@ -0,0 +1,362 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use interprocess::local_socket::tokio::LocalSocketListener;
+use uuid::Uuid;
+
+use storage_search::indexed_storage::IndexedStorage;
+use storage_search::search_index::SearchIndex;
+use storages::blob_store::BlobStore;
+use storages::folders::{Delivery, NewMessage, StorageError, DELETED_FLAG, INBOX};
+use storages::search::{Rebuild, SearchQuery};
+use storages::storage_service::storage_service_storage::StorageServiceStorage;
+use storages::storage_service::StorageService;
+use storages::storage_type::Storage;
+use utils::quota::QuotaUsage;
+
+use crate::maildir_storage::MaildirStorage;
+
+/// A directory in the temp folder that is removed on drop
+struct TestFiles(PathBuf);
+impl TestFiles {
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("nitro_mail_maildir_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+    fn blob(&self, contents: &str) -> PathBuf {
+        let hash = BlobStore::hash(contents.as_bytes());
+        self.0.join("blobs").join(&hash[..2]).join(&hash[2..])
+    }
+    fn folder(&self, mailbox_id: Uuid, folder: &str) -> PathBuf {
+        let mailbox = self.0.join("mailboxes").join(mailbox_id.to_string());
+        match folder {
+            INBOX => mailbox,
+            folder => mailbox.join(format!(".{folder}")),
+        }
+    }
+}
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn message(contents: &str) -> NewMessage {
+    NewMessage {
+        flags: vec![],
+        internal_date: 1700000000,
+        contents: contents.as_bytes().to_vec(),
+    }
+}
+fn files_in(directory: PathBuf) -> Vec<String> {
+    let mut files: Vec<String> = std::fs::read_dir(directory)
+        .unwrap()
+        .map(|file| file.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    files
+}
+
+#[tokio::test]
+async fn test_folders() {
+    let files = TestFiles::new();
+    let storage = MaildirStorage::new(&files.0);
+    let mailbox = Uuid::new_v4();
+    assert!(storage
+        .list_folders(mailbox)
+        .await
+        .unwrap()
+        .unwrap()
+        .is_empty());
+
+    let inbox = storage
+        .create_folder(mailbox, INBOX.to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((inbox.uid_next, inbox.highest_modseq), (1, 1));
+    assert_eq!(
+        storage
+            .create_folder(mailbox, INBOX.to_string())
+            .await
+            .unwrap(),
+        Err(StorageError::FolderExists(INBOX.to_string()))
+    );
+    storage
+        .create_folder(mailbox, "Archive.2023".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    for name in ["", "../other", ".hidden", "a/b"] {
+        assert_eq!(
+            storage
+                .create_folder(mailbox, name.to_string())
+                .await
+                .unwrap(),
+            Err(StorageError::InvalidFolderName(name.to_string()))
+        );
+    }
+    let names: Vec<String> = storage
+        .list_folders(mailbox)
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|folder| folder.name)
+        .collect();
+    assert_eq!(names, vec!["Archive.2023", "INBOX"]);
+    // Maildir++ keeps the other folders inside the INBOX directory
+    assert!(files.folder(mailbox, "Archive.2023").join("cur").is_dir());
+
+    assert!(storage
+        .delete_folder(mailbox, INBOX.to_string())
+        .await
+        .unwrap()
+        .unwrap());
+    assert!(!storage
+        .delete_folder(mailbox, INBOX.to_string())
+        .await
+        .unwrap()
+        .unwrap());
+    let names: Vec<String> = storage
+        .list_folders(mailbox)
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|folder| folder.name)
+        .collect();
+    assert_eq!(names, vec!["Archive.2023"]);
+}
+
+#[tokio::test]
+async fn test_delivery_links_one_blob() {
+    let files = TestFiles::new();
+    let storage = MaildirStorage::new(&files.0);
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    for (mailbox, folder) in [(first, INBOX), (first, "Lists"), (second, INBOX)] {
+        storage
+            .create_folder(mailbox, folder.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    let delivery = |mailbox_id, folder: &str| Delivery {
+        mailbox_id,
+        folder: folder.to_string(),
+    };
+    let results = storage
+        .deliver_message(
+            vec![
+                delivery(first, INBOX),
+                delivery(first, "Lists"),
+                delivery(second, INBOX),
+                delivery(second, "Missing"),
+            ],
+            message("Hello"),
+        )
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 4);
+    assert!(results[..3].iter().all(|result| result.is_ok()));
+    assert_eq!(
+        results[3],
+        Err(StorageError::FolderNotFound("Missing".to_string()))
+    );
+
+    // The blob and the three folders share one file
+    let blob = std::fs::metadata(files.blob("Hello")).unwrap();
+    assert_eq!(blob.nlink(), 4);
+    let new = files_in(files.folder(second, INBOX).join("new"));
+    assert_eq!(new.len(), 1);
+    assert_eq!(
+        std::fs::metadata(files.folder(second, INBOX).join("new").join(&new[0]))
+            .unwrap()
+            .ino(),
+        blob.ino()
+    );
+    assert_eq!(
+        storage
+            .read_message(first, "Lists".to_string(), 1)
+            .await
+            .unwrap(),
+        Ok(b"Hello".to_vec())
+    );
+    assert_eq!(
+        storage.quota_usage(first).await.unwrap(),
+        QuotaUsage {
+            storage: 10,
+            messages: 2
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_flags_and_garbage_collection() {
+    let files = TestFiles::new();
+    let storage = MaildirStorage::new(&files.0);
+    let mailbox = Uuid::new_v4();
+    storage
+        .create_folder(mailbox, INBOX.to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    let inbox = files.folder(mailbox, INBOX);
+    storage
+        .append_message(mailbox, INBOX.to_string(), message("First"))
+        .await
+        .unwrap()
+        .unwrap();
+    let flagged = NewMessage {
+        flags: vec!["\\Seen".to_string(), "$Important".to_string()],
+        ..message("Second")
+    };
+    let second = storage
+        .append_message(mailbox, INBOX.to_string(), flagged)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((second.uid, second.modseq), (2, 3));
+    assert_eq!(second.flags, vec!["\\Seen", "$Important"]);
+    let cur = files_in(inbox.join("cur"));
+    assert!(cur[0].ends_with(":2,S"));
+
+    // A Maildir client reads the first message and moves it to cur
+    let new = files_in(inbox.join("new"));
+    std::fs::rename(
+        inbox.join("new").join(&new[0]),
+        inbox.join("cur").join(format!("{}:2,", new[0])),
+    )
+    .unwrap();
+    let first = storage
+        .set_flags(
+            mailbox,
+            INBOX.to_string(),
+            1,
+            vec![DELETED_FLAG.to_string()],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        (first.modseq, first.flags.clone()),
+        (4, vec![DELETED_FLAG.to_string()])
+    );
+    assert!(files_in(inbox.join("cur")).contains(&format!("{}:2,T", new[0])));
+
+    assert_eq!(
+        storage
+            .collect_garbage()
+            .await
+            .unwrap()
+            .unwrap()
+            .blobs_removed,
+        0
+    );
+    assert_eq!(
+        storage
+            .expunge(mailbox, INBOX.to_string())
+            .await
+            .unwrap()
+            .unwrap(),
+        vec![1]
+    );
+    let messages = storage
+        .list_messages(mailbox, INBOX.to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages, vec![second]);
+    let collection = storage.collect_garbage().await.unwrap().unwrap();
+    assert_eq!((collection.blobs_removed, collection.blobs_kept), (1, 1));
+    assert!(!files.blob("First").exists());
+
+    // Deleting the folder unlinks the rest
+    storage
+        .delete_folder(mailbox, INBOX.to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    let collection = storage.collect_garbage().await.unwrap().unwrap();
+    assert_eq!(collection.blobs_removed, 1);
+    assert_eq!(
+        storage.quota_usage(mailbox).await.unwrap(),
+        QuotaUsage::default()
+    );
+}
+
+#[tokio::test]
+async fn test_search_through_storage_service() {
+    let files = TestFiles::new();
+    let storage = MaildirStorage::new(&files.0);
+    let mailbox = Uuid::new_v4();
+    storage
+        .create_folder(mailbox, INBOX.to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    // Stored before the indexer started so only a rebuild finds it
+    storage
+        .append_message(
+            mailbox,
+            INBOX.to_string(),
+            message("Subject: Quarterly report\r\n\r\nNumbers\r\n"),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    let index = Arc::new(SearchIndex::in_memory(15_000_000).unwrap());
+    let socket_name = format!("nitro_mail_storage_test_{}", Uuid::new_v4());
+    let listener = LocalSocketListener::bind(socket_name.as_str()).unwrap();
+    tokio::spawn(StorageService::new(IndexedStorage::new(storage, index)).serve(listener));
+    let client = StorageServiceStorage::connect(socket_name).await.unwrap();
+
+    client
+        .append_message(
+            mailbox,
+            INBOX.to_string(),
+            message("Subject: Weekly Meeting\r\n\r\nAgenda\r\n"),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    let search = |query: SearchQuery| {
+        let client = &client;
+        async move {
+            client
+                .search(mailbox, INBOX.to_string(), query)
+                .await
+                .unwrap()
+                .unwrap()
+        }
+    };
+    let meet = SearchQuery::Subject("MEET".to_string());
+    let mut found = vec![];
+    for _ in 0..100 {
+        found = search(meet.clone()).await;
+        if !found.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(found, vec![2]);
+    assert!(search(SearchQuery::Subject("report".to_string()))
+        .await
+        .is_empty());
+
+    assert_eq!(
+        client.rebuild_search_index(mailbox).await.unwrap(),
+        Ok(Rebuild {
+            folders: 1,
+            messages: 2
+        })
+    );
+    assert_eq!(
+        search(SearchQuery::Subject("report".to_string())).await,
+        vec![1]
+    );
+    assert_eq!(search(SearchQuery::All).await, vec![1, 2]);
+}
@@ -1,3 +1,21 @@
-fn main() {
-    println!("Hello, world!");
+use std::env::current_dir;
+use std::sync::Arc;
+
+use storage_mail_directory::maildir_config::MaildirStorageConfig;
+use storage_mail_directory::maildir_storage::MaildirStorage;
+use storage_search::indexed_storage::IndexedStorage;
+use storage_search::search_config::SearchConfig;
+use storage_search::search_index::SearchIndex;
+use storages::storage_service::StorageService;
+use utils::configs::Config;
+
+#[tokio::main]
+async fn main() {
+    let config = MaildirStorageConfig::get_or_save_default(current_dir().unwrap()).unwrap();
+    let search = SearchConfig::get_or_save_default(current_dir().unwrap()).unwrap();
+    let index = SearchIndex::open(&search.index_path, search.writer_memory)
+        .expect("Unable to open the search index");
+    let storage = IndexedStorage::new(MaildirStorage::new(config.path), Arc::new(index));
+    let service = StorageService::new(storage);
+    service.run().await;
 }
@@ -0,0 +1,264 @@
+//! The UIDs of a Maildir folder. Maildir only names messages by file, so every folder keeps a
+//! `nitro_mail-uidlist` next to its `cur`, `new` and `tmp` directories
+//!
+//! ```text
+//! <uid validity> <uid next> <highest modseq>
+//! <uid> <modseq> <internal date> <size> <file> [keyword...]
+//! ```
+//!
+//! `file` is relative to the folder, for example `cur/1700000000.abc.nitro_mail:2,S`.
+//! System flags are kept in the file name as Maildir clients expect. Keywords are only here
+use std::io;
+use std::path::Path;
+
+use tokio::fs;
+use uuid::Uuid;
+
+use storages::folders::{Folder, MessageInfo};
+
+pub const UID_LIST_FILE: &str = "nitro_mail-uidlist";
+
+/// The Maildir info letters of the IMAP system flags. In the order they are written
+const SYSTEM_FLAGS: [(char, &str); 5] = [
+    ('D', "\\Draft"),
+    ('F', "\\Flagged"),
+    ('R', "\\Answered"),
+    ('S', "\\Seen"),
+    ('T', "\\Deleted"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UidEntry {
+    pub uid: u32,
+    pub modseq: u64,
+    /// Unix seconds
+    pub internal_date: i64,
+    /// Bytes
+    pub size: u64,
+    pub file: String,
+    pub keywords: Vec<String>,
+}
+impl UidEntry {
+    /// The name of the message without the directory and the info
+    pub fn base_name(&self) -> &str {
+        let name = self.file.rsplit('/').next().unwrap_or(&self.file);
+        name.split_once(':').map(|(base, _)| base).unwrap_or(name)
+    }
+    /// System flags from the file name followed by the keywords
+    pub fn flags(&self) -> Vec<String> {
+        let info = self
+            .file
+            .rsplit_once(":2,")
+            .map(|(_, info)| info)
+            .unwrap_or_default();
+        SYSTEM_FLAGS
+            .iter()
+            .filter(|(letter, _)| info.contains(*letter))
+            .map(|(_, flag)| flag.to_string())
+            .chain(self.keywords.iter().cloned())
+            .collect()
+    }
+    pub fn info(&self) -> MessageInfo {
+        MessageInfo {
+            uid: self.uid,
+            modseq: self.modseq,
+            flags: self.flags(),
+            size: self.size,
+            internal_date: self.internal_date,
+        }
+    }
+}
+
+/// Splits flags into the info of a file in `cur` and the keywords
+pub fn split_flags(flags: &[String]) -> (String, Vec<String>) {
+    let mut info = String::from(":2,");
+    for (letter, flag) in SYSTEM_FLAGS {
+        if flags.iter().any(|set| set.eq_ignore_ascii_case(flag)) {
+            info.push(letter);
+        }
+    }
+    let keywords = flags
+        .iter()
+        .filter(|flag| {
+            !SYSTEM_FLAGS
+                .iter()
+                .any(|(_, system)| flag.eq_ignore_ascii_case(system))
+        })
+        .cloned()
+        .collect();
+    (info, keywords)
+}
+
+/// A unique name for a new message
+pub fn new_file_name(internal_date: i64) -> String {
+    format!("{}.{}.nitro_mail", internal_date, Uuid::new_v4().simple())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UidList {
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    pub highest_modseq: u64,
+    /// Sorted by UID
+    pub messages: Vec<UidEntry>,
+}
+impl UidList {
+    pub fn new(uid_validity: u32) -> Self {
+        Self {
+            uid_validity,
+            uid_next: 1,
+            highest_modseq: 1,
+            messages: vec![],
+        }
+    }
+    pub fn folder(&self, name: String) -> Folder {
+        Folder {
+            name,
+            uid_validity: self.uid_validity,
+            uid_next: self.uid_next,
+            highest_modseq: self.highest_modseq,
+        }
+    }
+    /// Gives a new message the next UID and modseq
+    pub fn push(
+        &mut self,
+        internal_date: i64,
+        size: u64,
+        file: String,
+        keywords: Vec<String>,
+    ) -> &UidEntry {
+        self.highest_modseq += 1;
+        self.messages.push(UidEntry {
+            uid: self.uid_next,
+            modseq: self.highest_modseq,
+            internal_date,
+            size,
+            file,
+            keywords,
+        });
+        self.uid_next += 1;
+        self.messages.last().expect("A message was just added")
+    }
+    pub fn get_mut(&mut self, uid: u32) -> Option<&mut UidEntry> {
+        self.messages
+            .binary_search_by_key(&uid, |message| message.uid)
+            .ok()
+            .map(|index| &mut self.messages[index])
+    }
+
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid uid list line {line:?}"),
+            )
+        };
+        let mut lines = contents.lines().filter(|line| !line.is_empty());
+        let header = lines.next().ok_or_else(|| invalid(""))?;
+        let mut fields = header.split(' ').map(str::parse::<u64>);
+        let (Some(Ok(uid_validity)), Some(Ok(uid_next)), Some(Ok(highest_modseq))) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid(header));
+        };
+        let mut list = Self {
+            uid_validity: uid_validity as u32,
+            uid_next: uid_next as u32,
+            highest_modseq,
+            messages: vec![],
+        };
+        for line in lines {
+            let mut fields = line.split(' ');
+            let mut number = || fields.next().and_then(|field| field.parse::<i64>().ok());
+            let (Some(uid), Some(modseq), Some(internal_date), Some(size)) =
+                (number(), number(), number(), number())
+            else {
+                return Err(invalid(line));
+            };
+            let file = fields.next().ok_or_else(|| invalid(line))?.to_string();
+            list.messages.push(UidEntry {
+                uid: uid as u32,
+                modseq: modseq as u64,
+                internal_date,
+                size: size as u64,
+                file,
+                keywords: fields.map(str::to_string).collect(),
+            });
+        }
+        list.messages.sort_by_key(|message| message.uid);
+        Ok(list)
+    }
+    pub fn to_file(&self) -> String {
+        let mut contents = format!(
+            "{} {} {}\n",
+            self.uid_validity, self.uid_next, self.highest_modseq
+        );
+        for message in &self.messages {
+            contents.push_str(&format!(
+                "{} {} {} {} {}",
+                message.uid, message.modseq, message.internal_date, message.size, message.file
+            ));
+            for keyword in &message.keywords {
+                contents.push(' ');
+                contents.push_str(keyword);
+            }
+            contents.push('\n');
+        }
+        contents
+    }
+
+    /// None if the folder does not exist
+    pub async fn load(folder: &Path) -> io::Result<Option<Self>> {
+        match fs::read_to_string(folder.join(UID_LIST_FILE)).await {
+            Ok(contents) => Self::parse(&contents).map(Some),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+    /// Written to `tmp` and renamed so readers never see half of it
+    pub async fn save(&self, folder: &Path) -> io::Result<()> {
+        let temporary = folder.join("tmp").join(UID_LIST_FILE);
+        fs::write(&temporary, self.to_file()).await?;
+        fs::rename(&temporary, folder.join(UID_LIST_FILE)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::uid_list::{split_flags, UidList};
+
+    #[test]
+    pub fn test_uid_list_format() {
+        let mut list = UidList::new(1700000000);
+        list.push(
+            1700000001,
+            10,
+            "new/1700000001.a.nitro_mail".to_string(),
+            vec![],
+        );
+        let message = list.get_mut(1).unwrap();
+        let (info, keywords) = split_flags(&[
+            "\\Seen".to_string(),
+            "$Important".to_string(),
+            "\\Flagged".to_string(),
+        ]);
+        assert_eq!(info, ":2,FS");
+        message.file = format!("cur/{}{}", message.base_name(), info);
+        message.keywords = keywords;
+        list.push(
+            1700000002,
+            20,
+            "new/1700000002.b.nitro_mail".to_string(),
+            vec![],
+        );
+
+        let parsed = UidList::parse(&list.to_file()).unwrap();
+        assert_eq!(parsed, list);
+        assert_eq!((parsed.uid_next, parsed.highest_modseq), (3, 3));
+        let first = &parsed.messages[0];
+        assert_eq!(first.base_name(), "1700000001.a.nitro_mail");
+        assert_eq!(first.flags(), vec!["\\Flagged", "\\Seen", "$Important"]);
+        assert!(parsed.messages[1].flags().is_empty());
+        assert!(UidList::parse("1 2\n").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! The searchable parts of a raw message
+use mail_parser::{Address, Header, HeaderValue, Message, MessageParser, MimeHeaders};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageDocument {
+    pub from: String,
+    pub to: String,
+    pub cc: String,
+    pub bcc: String,
+    pub subject: String,
+    /// A `name: value` line for every header. Encoded words are decoded
+    pub headers: String,
+    /// The text bodies. HTML only bodies are converted to text
+    pub body: String,
+    /// The filenames of the attachments
+    pub attachments: String,
+    /// Unix seconds of the `Date` header
+    pub sent: Option<i64>,
+}
+impl MessageDocument {
+    /// Messages that can not be parsed are empty
+    pub fn parse(contents: &[u8]) -> Self {
+        let Some(message) = MessageParser::default().parse(contents) else {
+            return Self::default();
+        };
+        let headers: Vec<String> = message
+            .headers()
+            .iter()
+            .map(|header| format!("{}: {}", header.name(), header_text(&message, header)))
+            .collect();
+        let body: Vec<String> = (0..message.text_body_count())
+            .filter_map(|index| message.body_text(index))
+            .map(|text| text.into_owned())
+            .collect();
+        let attachments: Vec<&str> = message
+            .attachments()
+            .filter_map(|attachment| attachment.attachment_name())
+            .collect();
+        Self {
+            from: message.from().map(addresses).unwrap_or_default(),
+            to: message.to().map(addresses).unwrap_or_default(),
+            cc: message.cc().map(addresses).unwrap_or_default(),
+            bcc: message.bcc().map(addresses).unwrap_or_default(),
+            subject: message.subject().unwrap_or_default().to_string(),
+            headers: headers.join("\n"),
+            body: body.join("\n"),
+            attachments: attachments.join("\n"),
+            sent: message.date().map(|date| date.to_timestamp()),
+        }
+    }
+}
+
+/// `Name <address>` separated by commas
+fn addresses(address: &Address) -> String {
+    let addresses: Vec<String> = address
+        .iter()
+        .map(|addr| match (addr.name(), addr.address()) {
+            (Some(name), Some(address)) => format!("{name} <{address}>"),
+            (name, address) => name.or(address).unwrap_or_default().to_string(),
+        })
+        .collect();
+    addresses.join(", ")
+}
+
+/// Structured values other than addresses are left as they were sent.
+/// Unfolded so every header is one line
+fn header_text(message: &Message, header: &Header) -> String {
+    let text = match header.value() {
+        HeaderValue::Text(text) => text.to_string(),
+        HeaderValue::TextList(list) => list.join(" "),
+        HeaderValue::Address(address) => addresses(address),
+        _ => {
+            let raw = &message.raw_message()[header.offset_start()..header.offset_end()];
+            String::from_utf8_lossy(raw).trim().to_string()
+        }
+    };
+    text.replace("\r\n", "").replace('\n', "")
+}
@@ -0,0 +1,183 @@
+//! A [Storage] that answers searches with a [SearchIndex]
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::broadcast::Receiver;
+use tracing::warn;
+use uuid::Uuid;
+
+use storages::blob_store::GarbageCollection;
+use storages::events::StorageEvent;
+use storages::folders::{Delivery, Folder, MessageInfo, NewMessage, StorageError, StorageResult};
+use storages::search::{Rebuild, SearchQuery};
+use storages::storage_type::Storage;
+use utils::quota::QuotaUsage;
+use utils::service::Service;
+
+use crate::indexer::{rebuild_mailbox, spawn_indexer};
+use crate::search_index::{SearchError, SearchIndex};
+
+/// Every other call goes to the wrapped storage
+#[derive(Clone)]
+pub struct IndexedStorage<S> {
+    storage: S,
+    index: Arc<SearchIndex>,
+}
+impl<S: Storage + Clone> IndexedStorage<S> {
+    /// Starts the [indexer](crate::indexer) for the storage.
+    /// Rebuilds and searches use the same index, so it has one writer
+    pub fn new(storage: S, index: Arc<SearchIndex>) -> Self {
+        if spawn_indexer(index.clone(), storage.clone()).is_none() {
+            warn!(
+                "{} does not publish changes. The search index only changes when it is rebuilt",
+                S::storage_name()
+            );
+        }
+        Self { storage, index }
+    }
+}
+impl<S: Storage> Service for IndexedStorage<S> {
+    type ServiceConfig = S::ServiceConfig;
+    type ServiceError = S::ServiceError;
+}
+
+/// Failures of the storage while rebuilding are reported like those of the index
+fn index_error<T>(error: SearchError) -> StorageResult<T> {
+    match error {
+        SearchError::StorageRequest(error) => Err(error),
+        error => Err(StorageError::SearchIndex(error.to_string())),
+    }
+}
+
+#[async_trait]
+impl<S: Storage + Clone> Storage for IndexedStorage<S> {
+    fn storage_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        S::storage_name()
+    }
+
+    fn storage_path(&self) -> String {
+        self.storage.storage_path()
+    }
+
+    async fn quota_usage(&self, mailbox_id: Uuid) -> Result<QuotaUsage, Self::ServiceError> {
+        self.storage.quota_usage(mailbox_id).await
+    }
+
+    fn subscribe(&self) -> Option<Receiver<StorageEvent>> {
+        self.storage.subscribe()
+    }
+
+    async fn create_folder(
+        &self,
+        mailbox_id: Uuid,
+        name: String,
+    ) -> Result<StorageResult<Folder>, Self::ServiceError> {
+        self.storage.create_folder(mailbox_id, name).await
+    }
+
+    async fn list_folders(
+        &self,
+        mailbox_id: Uuid,
+    ) -> Result<StorageResult<Vec<Folder>>, Self::ServiceError> {
+        self.storage.list_folders(mailbox_id).await
+    }
+
+    async fn delete_folder(
+        &self,
+        mailbox_id: Uuid,
+        name: String,
+    ) -> Result<StorageResult<bool>, Self::ServiceError> {
+        self.storage.delete_folder(mailbox_id, name).await
+    }
+
+    async fn append_message(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        message: NewMessage,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        self.storage
+            .append_message(mailbox_id, folder, message)
+            .await
+    }
+
+    async fn deliver_message(
+        &self,
+        deliveries: Vec<Delivery>,
+        message: NewMessage,
+    ) -> Result<Vec<StorageResult<MessageInfo>>, Self::ServiceError> {
+        self.storage.deliver_message(deliveries, message).await
+    }
+
+    async fn list_messages(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+    ) -> Result<StorageResult<Vec<MessageInfo>>, Self::ServiceError> {
+        self.storage.list_messages(mailbox_id, folder).await
+    }
+
+    async fn read_message(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+    ) -> Result<StorageResult<Vec<u8>>, Self::ServiceError> {
+        self.storage.read_message(mailbox_id, folder, uid).await
+    }
+
+    async fn set_flags(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+        flags: Vec<String>,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        self.storage.set_flags(mailbox_id, folder, uid, flags).await
+    }
+
+    async fn expunge(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+    ) -> Result<StorageResult<Vec<u32>>, Self::ServiceError> {
+        self.storage.expunge(mailbox_id, folder).await
+    }
+
+    async fn collect_garbage(
+        &self,
+    ) -> Result<StorageResult<GarbageCollection>, Self::ServiceError> {
+        self.storage.collect_garbage().await
+    }
+
+    async fn rotate_mailbox_key(
+        &self,
+        mailbox_id: Uuid,
+    ) -> Result<StorageResult<u64>, Self::ServiceError> {
+        self.storage.rotate_mailbox_key(mailbox_id).await
+    }
+
+    async fn search(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        query: SearchQuery,
+    ) -> Result<StorageResult<Vec<u32>>, Self::ServiceError> {
+        Ok(self
+            .index
+            .search(mailbox_id, &folder, &query)
+            .or_else(index_error))
+    }
+
+    async fn rebuild_search_index(
+        &self,
+        mailbox_id: Uuid,
+    ) -> Result<StorageResult<Rebuild>, Self::ServiceError> {
+        Ok(rebuild_mailbox(&self.index, &self.storage, mailbox_id)
+            .await
+            .or_else(index_error))
+    }
+}
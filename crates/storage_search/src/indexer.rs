@@ -0,0 +1,132 @@
+//! Keeps the [SearchIndex] up to date with a [Storage]
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use storages::events::StorageEvent;
+use storages::folders::StorageError;
+use storages::search::Rebuild;
+use storages::storage_type::Storage;
+
+use crate::search_index::{SearchError, SearchIndex};
+
+/// Follows the events of the storage. None if it does not publish them
+///
+/// Events that arrive together are committed together
+pub fn spawn_indexer<S: Storage>(index: Arc<SearchIndex>, storage: S) -> Option<JoinHandle<()>> {
+    let mut events = storage.subscribe()?;
+    Some(tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(
+                        "The search index missed {} storage events. Rebuild it to find the missing messages",
+                        missed
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            apply_logged(&index, &storage, event).await;
+            loop {
+                match events.try_recv() {
+                    Ok(event) => apply_logged(&index, &storage, event).await,
+                    Err(TryRecvError::Lagged(missed)) => {
+                        warn!(
+                            "The search index missed {} storage events. Rebuild it to find the missing messages",
+                            missed
+                        );
+                    }
+                    Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+                }
+            }
+            if let Err(error) = index.commit() {
+                error!("Failed to commit the search index: {}", error);
+            }
+        }
+    }))
+}
+
+async fn apply_logged<S: Storage>(index: &SearchIndex, storage: &S, event: StorageEvent) {
+    if let Err(error) = apply(index, storage, &event).await {
+        error!("Failed to index {:?}: {}", event, error);
+    }
+}
+
+/// Does not commit
+pub async fn apply<S: Storage>(
+    index: &SearchIndex,
+    storage: &S,
+    event: &StorageEvent,
+) -> Result<(), SearchError> {
+    match event {
+        StorageEvent::MessageAppended {
+            mailbox_id,
+            folder,
+            uid,
+            internal_date,
+        } => {
+            let contents = storage
+                .read_message(*mailbox_id, folder.clone(), *uid)
+                .await
+                .map_err(|error| SearchError::Storage(Box::new(error)))?;
+            match contents {
+                Ok(contents) => {
+                    index.index_message(*mailbox_id, folder, *uid, *internal_date, &contents)
+                }
+                // Removed before it was indexed
+                Err(StorageError::MessageNotFound(_) | StorageError::FolderNotFound(_)) => Ok(()),
+                Err(error) => Err(error.into()),
+            }
+        }
+        StorageEvent::MessagesExpunged {
+            mailbox_id,
+            folder,
+            uids,
+        } => index.remove_messages(*mailbox_id, folder, uids),
+        StorageEvent::FolderDeleted { mailbox_id, folder } => {
+            index.remove_folder(*mailbox_id, folder)
+        }
+    }
+}
+
+/// Indexes every message of the mailbox again. For when the index is new or missed events
+pub async fn rebuild_mailbox<S: Storage>(
+    index: &SearchIndex,
+    storage: &S,
+    mailbox_id: Uuid,
+) -> Result<Rebuild, SearchError> {
+    let folders = storage
+        .list_folders(mailbox_id)
+        .await
+        .map_err(|error| SearchError::Storage(Box::new(error)))??;
+    index.remove_mailbox(mailbox_id)?;
+    let mut rebuild = Rebuild::default();
+    for folder in folders {
+        let messages = storage
+            .list_messages(mailbox_id, folder.name.clone())
+            .await
+            .map_err(|error| SearchError::Storage(Box::new(error)))??;
+        for message in messages {
+            let contents = storage
+                .read_message(mailbox_id, folder.name.clone(), message.uid)
+                .await
+                .map_err(|error| SearchError::Storage(Box::new(error)))??;
+            index.index_message(
+                mailbox_id,
+                &folder.name,
+                message.uid,
+                message.internal_date,
+                &contents,
+            )?;
+            rebuild.messages += 1;
+        }
+        rebuild.folders += 1;
+    }
+    index.commit()?;
+    Ok(rebuild)
+}
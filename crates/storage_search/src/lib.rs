@@ -0,0 +1,12 @@
+//! A full-text index of stored messages for IMAP SEARCH and JMAP Email/query
+//!
+//! Every message is indexed under the `mailbox_id` and folder it is stored in.
+//! The [indexer] follows the [storage events](storages::events) to keep it up to date.
+//! [IndexedStorage](indexed_storage::IndexedStorage) runs it for the storage service and answers its searches
+pub mod document;
+pub mod indexed_storage;
+pub mod indexer;
+pub mod search_config;
+pub mod search_index;
+#[cfg(test)]
+mod search_tests;
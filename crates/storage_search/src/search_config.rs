@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use utils::configs::{Config, ConfigName};
+
+fn default_index_path() -> PathBuf {
+    PathBuf::from("search_index")
+}
+fn default_writer_memory() -> usize {
+    50_000_000
+}
+
+/// # Example
+/// ```toml
+/// index_path = "/var/lib/nitro_mail/search_index"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SearchConfig {
+    /// The directory of the index. Created if it does not exist
+    #[serde(default = "default_index_path")]
+    pub index_path: PathBuf,
+    /// Bytes buffered before new documents are written to disk. At least 15MB
+    #[serde(default = "default_writer_memory")]
+    pub writer_memory: usize,
+}
+impl Config for SearchConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/search")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("search.toml")
+    }
+}
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            index_path: default_index_path(),
+            writer_memory: default_writer_memory(),
+        }
+    }
+}
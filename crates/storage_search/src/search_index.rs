@@ -0,0 +1,403 @@
+use std::error::Error;
+use std::io;
+use std::path::Path;
+
+use parking_lot::Mutex;
+use tantivy::collector::DocSetCollector;
+use tantivy::directory::error::OpenDirectoryError;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query, RangeQuery, TermQuery};
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value, FAST, INDEXED, STORED,
+    STRING,
+};
+use tantivy::tokenizer::NgramTokenizer;
+use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+use thiserror::Error;
+use uuid::Uuid;
+
+use storages::folders::StorageError;
+use storages::search::SearchQuery;
+
+use crate::document::MessageDocument;
+
+#[derive(Debug, Error)]
+pub enum SearchError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Index(#[from] tantivy::TantivyError),
+    #[error(transparent)]
+    OpenDirectory(#[from] OpenDirectoryError),
+    #[error(transparent)]
+    Storage(Box<dyn Error + Send + Sync + 'static>),
+    #[error(transparent)]
+    StorageRequest(#[from] StorageError),
+}
+
+/// Splits text into every run of three characters
+const TRIGRAMS: &str = "trigrams";
+
+/// Text fields are lowercased and indexed by [TRIGRAMS] to find the messages that may contain a
+/// substring. The stored text decides if they do
+fn substring_text() -> TextOptions {
+    TextOptions::default()
+        .set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(TRIGRAMS)
+                .set_index_option(IndexRecordOption::Basic),
+        )
+        .set_stored()
+}
+
+struct Fields {
+    /// `<mailbox_id>/<folder>/<uid>`. Replaced when a message is indexed again
+    id: Field,
+    mailbox: Field,
+    /// `<mailbox_id>/<folder>`
+    folder: Field,
+    uid: Field,
+    from: Field,
+    to: Field,
+    cc: Field,
+    bcc: Field,
+    subject: Field,
+    /// A `name: value` value for every header
+    headers: Field,
+    /// The lowercase name of every header
+    header_names: Field,
+    body: Field,
+    attachments: Field,
+    received: Field,
+    /// The `Date` header. The internal date if it has none
+    sent: Field,
+}
+impl Fields {
+    fn schema() -> (Schema, Fields) {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            id: builder.add_text_field("id", STRING),
+            mailbox: builder.add_text_field("mailbox", STRING),
+            folder: builder.add_text_field("folder", STRING),
+            uid: builder.add_u64_field("uid", INDEXED | STORED),
+            from: builder.add_text_field("from", substring_text()),
+            to: builder.add_text_field("to", substring_text()),
+            cc: builder.add_text_field("cc", substring_text()),
+            bcc: builder.add_text_field("bcc", substring_text()),
+            subject: builder.add_text_field("subject", substring_text()),
+            headers: builder.add_text_field("headers", substring_text()),
+            header_names: builder.add_text_field("header_names", STRING),
+            body: builder.add_text_field("body", substring_text()),
+            attachments: builder.add_text_field("attachments", substring_text()),
+            received: builder.add_i64_field("received", INDEXED | FAST | STORED),
+            sent: builder.add_i64_field("sent", INDEXED | FAST | STORED),
+        };
+        (builder.build(), fields)
+    }
+}
+
+fn folder_key(mailbox_id: Uuid, folder: &str) -> String {
+    format!("{mailbox_id}/{folder}")
+}
+fn message_key(mailbox_id: Uuid, folder: &str, uid: u32) -> String {
+    format!("{mailbox_id}/{folder}/{uid}")
+}
+
+/// Changes are searchable once they are committed
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+}
+impl SearchIndex {
+    /// Creates the index if the directory has none.
+    /// An index written with another schema is an error. Remove it and rebuild it
+    pub fn open(path: impl AsRef<Path>, writer_memory: usize) -> Result<Self, SearchError> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+        let (schema, fields) = Fields::schema();
+        let index = Index::open_or_create(MmapDirectory::open(path)?, schema)?;
+        Self::new(index, fields, writer_memory)
+    }
+    /// Nothing is written to disk
+    pub fn in_memory(writer_memory: usize) -> Result<Self, SearchError> {
+        let (schema, fields) = Fields::schema();
+        Self::new(Index::create_in_ram(schema), fields, writer_memory)
+    }
+    fn new(index: Index, fields: Fields, writer_memory: usize) -> Result<Self, SearchError> {
+        index
+            .tokenizers()
+            .register(TRIGRAMS, NgramTokenizer::all_ngrams(3, 3)?);
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let writer = index.writer_with_num_threads(1, writer_memory)?;
+        Ok(Self {
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            fields,
+        })
+    }
+
+    /// Replaces the message if it was already indexed
+    pub fn index_message(
+        &self,
+        mailbox_id: Uuid,
+        folder: &str,
+        uid: u32,
+        internal_date: i64,
+        contents: &[u8],
+    ) -> Result<(), SearchError> {
+        let document = MessageDocument::parse(contents);
+        let id = message_key(mailbox_id, folder, uid);
+        let fields = &self.fields;
+        let mut indexed = doc!(
+            fields.id => id.clone(),
+            fields.mailbox => mailbox_id.to_string(),
+            fields.folder => folder_key(mailbox_id, folder),
+            fields.uid => uid as u64,
+            fields.from => document.from.to_lowercase(),
+            fields.to => document.to.to_lowercase(),
+            fields.cc => document.cc.to_lowercase(),
+            fields.bcc => document.bcc.to_lowercase(),
+            fields.subject => document.subject.to_lowercase(),
+            fields.body => document.body.to_lowercase(),
+            fields.attachments => document.attachments.to_lowercase(),
+            fields.received => internal_date,
+            fields.sent => document.sent.unwrap_or(internal_date),
+        );
+        for header in document.headers.to_lowercase().lines() {
+            if let Some((name, _)) = header.split_once(": ") {
+                indexed.add_text(fields.header_names, name);
+            }
+            indexed.add_text(fields.headers, header);
+        }
+        let writer = self.writer.lock();
+        writer.delete_term(Term::from_field_text(fields.id, &id));
+        writer.add_document(indexed)?;
+        Ok(())
+    }
+    pub fn remove_messages(
+        &self,
+        mailbox_id: Uuid,
+        folder: &str,
+        uids: &[u32],
+    ) -> Result<(), SearchError> {
+        let writer = self.writer.lock();
+        for uid in uids {
+            let id = message_key(mailbox_id, folder, *uid);
+            writer.delete_term(Term::from_field_text(self.fields.id, &id));
+        }
+        Ok(())
+    }
+    pub fn remove_folder(&self, mailbox_id: Uuid, folder: &str) -> Result<(), SearchError> {
+        let folder = folder_key(mailbox_id, folder);
+        self.writer
+            .lock()
+            .delete_term(Term::from_field_text(self.fields.folder, &folder));
+        Ok(())
+    }
+    pub fn remove_mailbox(&self, mailbox_id: Uuid) -> Result<(), SearchError> {
+        let mailbox = mailbox_id.to_string();
+        self.writer
+            .lock()
+            .delete_term(Term::from_field_text(self.fields.mailbox, &mailbox));
+        Ok(())
+    }
+    /// Writes the changes and makes them searchable
+    pub fn commit(&self) -> Result<(), SearchError> {
+        self.writer.lock().commit()?;
+        self.reader.reload()?;
+        Ok(())
+    }
+
+    /// The UIDs of the matching messages in the folder. Sorted
+    ///
+    /// The index finds the candidates. Unless it answered the whole query they are checked against
+    /// their stored text
+    pub fn search(
+        &self,
+        mailbox_id: Uuid,
+        folder: &str,
+        query: &SearchQuery,
+    ) -> Result<Vec<u32>, SearchError> {
+        let folder = Term::from_field_text(self.fields.folder, &folder_key(mailbox_id, folder));
+        let (candidates, exact) = self.candidates(query)?;
+        let candidates = BooleanQuery::intersection(vec![
+            Box::new(TermQuery::new(folder, IndexRecordOption::Basic)),
+            candidates,
+        ]);
+        let searcher = self.reader.searcher();
+        let mut uids = Vec::new();
+        for address in searcher.search(&candidates, &DocSetCollector)? {
+            let document: TantivyDocument = searcher.doc(address)?;
+            if !exact && !self.matches(&document, query) {
+                continue;
+            }
+            if let Some(uid) = document
+                .get_first(self.fields.uid)
+                .and_then(|value| value.as_u64())
+            {
+                uids.push(uid as u32);
+            }
+        }
+        uids.sort_unstable();
+        Ok(uids)
+    }
+
+    /// A query matching at least the messages that match. True if it matches exactly those
+    fn candidates(&self, query: &SearchQuery) -> Result<(Box<dyn Query>, bool), SearchError> {
+        let fields = &self.fields;
+        Ok(match query {
+            SearchQuery::All => (Box::new(AllQuery), true),
+            SearchQuery::From(value) => self.substring(&[fields.from], value)?,
+            SearchQuery::To(value) => self.substring(&[fields.to], value)?,
+            SearchQuery::Cc(value) => self.substring(&[fields.cc], value)?,
+            SearchQuery::Bcc(value) => self.substring(&[fields.bcc], value)?,
+            SearchQuery::Subject(value) => self.substring(&[fields.subject], value)?,
+            SearchQuery::Header(name, value) => {
+                let name = Term::from_field_text(fields.header_names, &name.to_lowercase());
+                let name = Box::new(TermQuery::new(name, IndexRecordOption::Basic));
+                if value.is_empty() {
+                    return Ok((name, true));
+                }
+                let (value, _) = self.substring(&[fields.headers], value)?;
+                (
+                    Box::new(BooleanQuery::intersection(vec![name, value])),
+                    false,
+                )
+            }
+            SearchQuery::Body(value) => self.substring(&[fields.body], value)?,
+            SearchQuery::Text(value) => {
+                self.substring(&[fields.headers, fields.body, fields.attachments], value)?
+            }
+            SearchQuery::Attachment(value) => self.substring(&[fields.attachments], value)?,
+            SearchQuery::ReceivedBefore(time) => (
+                Box::new(RangeQuery::new_i64("received".to_string(), i64::MIN..*time)),
+                true,
+            ),
+            SearchQuery::ReceivedSince(time) => (
+                Box::new(RangeQuery::new_i64("received".to_string(), *time..i64::MAX)),
+                true,
+            ),
+            SearchQuery::SentBefore(time) => (
+                Box::new(RangeQuery::new_i64("sent".to_string(), i64::MIN..*time)),
+                true,
+            ),
+            SearchQuery::SentSince(time) => (
+                Box::new(RangeQuery::new_i64("sent".to_string(), *time..i64::MAX)),
+                true,
+            ),
+            SearchQuery::And(queries) => {
+                let (queries, exact) = self.all_candidates(queries)?;
+                (Box::new(BooleanQuery::intersection(queries)), exact)
+            }
+            SearchQuery::Or(queries) => {
+                let (queries, exact) = self.all_candidates(queries)?;
+                (Box::new(BooleanQuery::union(queries)), exact)
+            }
+            SearchQuery::Not(query) => match self.candidates(query)? {
+                (query, true) => (
+                    Box::new(BooleanQuery::new(vec![
+                        (Occur::Must, Box::new(AllQuery)),
+                        (Occur::MustNot, query),
+                    ])),
+                    true,
+                ),
+                // Candidates that do not contain the text may still be messages that match
+                (_, false) => (Box::new(AllQuery), false),
+            },
+        })
+    }
+    fn all_candidates(
+        &self,
+        queries: &[SearchQuery],
+    ) -> Result<(Vec<Box<dyn Query>>, bool), SearchError> {
+        let mut candidates = Vec::with_capacity(queries.len());
+        let mut exact = true;
+        for query in queries {
+            let (query, query_exact) = self.candidates(query)?;
+            candidates.push(query);
+            exact &= query_exact;
+        }
+        Ok((candidates, exact))
+    }
+
+    /// The messages with every trigram of the value in one of the fields.
+    /// An empty value matches everything. Shorter values are only found in the stored text
+    fn substring(
+        &self,
+        fields: &[Field],
+        value: &str,
+    ) -> Result<(Box<dyn Query>, bool), SearchError> {
+        if value.is_empty() {
+            return Ok((Box::new(AllQuery), true));
+        }
+        let value = value.to_lowercase();
+        let mut queries: Vec<Box<dyn Query>> = Vec::with_capacity(fields.len());
+        for field in fields {
+            let mut tokenizer = self.index.tokenizer_for_field(*field)?;
+            let mut stream = tokenizer.token_stream(&value);
+            let mut trigrams: Vec<Box<dyn Query>> = Vec::new();
+            while let Some(token) = stream.next() {
+                let term = Term::from_field_text(*field, &token.text);
+                trigrams.push(Box::new(TermQuery::new(term, IndexRecordOption::Basic)));
+            }
+            if trigrams.is_empty() {
+                return Ok((Box::new(AllQuery), false));
+            }
+            queries.push(Box::new(BooleanQuery::intersection(trigrams)));
+        }
+        Ok((Box::new(BooleanQuery::union(queries)), false))
+    }
+
+    /// Checks the query against the stored fields of the message
+    fn matches(&self, document: &TantivyDocument, query: &SearchQuery) -> bool {
+        let fields = &self.fields;
+        let contains = |fields: &[Field], value: &str| {
+            let value = value.to_lowercase();
+            fields.iter().any(|field| {
+                document
+                    .get_all(*field)
+                    .filter_map(|text| text.as_str())
+                    .any(|text| text.contains(&value))
+            })
+        };
+        let time = |field: Field| {
+            document
+                .get_first(field)
+                .and_then(|value| value.as_i64())
+                .unwrap_or_default()
+        };
+        match query {
+            SearchQuery::All => true,
+            SearchQuery::From(value) => contains(&[fields.from], value),
+            SearchQuery::To(value) => contains(&[fields.to], value),
+            SearchQuery::Cc(value) => contains(&[fields.cc], value),
+            SearchQuery::Bcc(value) => contains(&[fields.bcc], value),
+            SearchQuery::Subject(value) => contains(&[fields.subject], value),
+            SearchQuery::Header(name, value) => {
+                let (name, value) = (name.to_lowercase(), value.to_lowercase());
+                document
+                    .get_all(fields.headers)
+                    .filter_map(|header| header.as_str()?.split_once(": "))
+                    .any(|(header, text)| header == name && text.contains(&value))
+            }
+            SearchQuery::Body(value) => contains(&[fields.body], value),
+            SearchQuery::Text(value) => {
+                contains(&[fields.headers, fields.body, fields.attachments], value)
+            }
+            SearchQuery::Attachment(value) => contains(&[fields.attachments], value),
+            SearchQuery::ReceivedBefore(before) => time(fields.received) < *before,
+            SearchQuery::ReceivedSince(since) => time(fields.received) >= *since,
+            SearchQuery::SentBefore(before) => time(fields.sent) < *before,
+            SearchQuery::SentSince(since) => time(fields.sent) >= *since,
+            SearchQuery::And(queries) => queries.iter().all(|query| self.matches(document, query)),
+            SearchQuery::Or(queries) => queries.iter().any(|query| self.matches(document, query)),
+            SearchQuery::Not(query) => !self.matches(document, query),
+        }
+    }
+}
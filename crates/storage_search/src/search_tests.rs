@@ -0,0 +1,370 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sea_orm::{ConnectOptions, Database};
+use uuid::Uuid;
+
+use storage_migration::{Migrator, MigratorTrait};
+use storage_sql::database_storage::DatabaseStorage;
+use storages::folders::{NewMessage, DELETED_FLAG};
+use storages::search::{Rebuild, SearchQuery, DAY};
+use storages::storage_type::Storage;
+
+use crate::document::MessageDocument;
+use crate::indexer::{rebuild_mailbox, spawn_indexer};
+use crate::search_index::SearchIndex;
+
+const WRITER_MEMORY: usize = 15_000_000;
+/// 2023-11-14 00:00:00 UTC
+const NOV_14: i64 = 1_699_920_000;
+
+const PLAIN: &str = "From: Alice Smith <alice@example.com>\r\n\
+To: Bob <bob@example.com>\r\n\
+Subject: Quarterly report\r\n\
+Date: Tue, 14 Nov 2023 10:00:00 +0000\r\n\
+X-Priority: urgent\r\n\
+\r\n\
+The numbers are in the attached spreadsheet.\r\n";
+
+const ENCODED: &str = "From: carol@example.org\r\n\
+To: bob@example.com\r\n\
+Cc: dave@example.net\r\n\
+Subject: =?UTF-8?B?TMO2c3VuZyBnZWZ1bmRlbg==?=\r\n\
+Date: Mon, 13 Nov 2023 09:00:00 +0000\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b1\"\r\n\
+\r\n\
+--b1\r\n\
+Content-Type: text/html; charset=utf-8\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\
+\r\n\
+<p>Lunch on <b>Friday</b>?</p>\r\n\
+--b1\r\n\
+Content-Type: application/pdf\r\n\
+Content-Disposition: attachment; filename=\"menu.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0=\r\n\
+--b1--\r\n";
+
+#[test]
+pub fn test_message_document() {
+    let document = MessageDocument::parse(PLAIN.as_bytes());
+    assert_eq!(document.from, "Alice Smith <alice@example.com>");
+    assert_eq!(document.subject, "Quarterly report");
+    assert!(document.headers.contains("X-Priority: urgent"));
+    assert!(document.body.contains("attached spreadsheet"));
+    assert_eq!(document.sent, Some(NOV_14 + 10 * 3600));
+
+    let document = MessageDocument::parse(ENCODED.as_bytes());
+    assert_eq!(document.subject, "Lösung gefunden");
+    assert!(document.headers.contains("Subject: Lösung gefunden"));
+    assert!(document.body.contains("Friday"));
+    assert!(!document.body.contains("<b>"));
+    assert_eq!(document.attachments, "menu.pdf");
+}
+
+#[test]
+pub fn test_search() {
+    let index = SearchIndex::in_memory(WRITER_MEMORY).unwrap();
+    let mailbox = Uuid::new_v4();
+    let other_mailbox = Uuid::new_v4();
+    index
+        .index_message(mailbox, "INBOX", 1, NOV_14 + DAY, PLAIN.as_bytes())
+        .unwrap();
+    index
+        .index_message(mailbox, "INBOX", 2, NOV_14, ENCODED.as_bytes())
+        .unwrap();
+    index
+        .index_message(mailbox, "Archive", 1, NOV_14, PLAIN.as_bytes())
+        .unwrap();
+    index
+        .index_message(other_mailbox, "INBOX", 7, NOV_14, PLAIN.as_bytes())
+        .unwrap();
+    index.commit().unwrap();
+
+    let search = |query: SearchQuery| index.search(mailbox, "INBOX", &query).unwrap();
+    assert_eq!(search(SearchQuery::All), vec![1, 2]);
+    assert_eq!(search(SearchQuery::From("ALICE".to_string())), vec![1]);
+    assert_eq!(
+        search(SearchQuery::From("alice@example.com".to_string())),
+        vec![1]
+    );
+    assert_eq!(search(SearchQuery::To("bob".to_string())), vec![1, 2]);
+    assert_eq!(search(SearchQuery::Cc("dave".to_string())), vec![2]);
+    assert_eq!(
+        search(SearchQuery::Subject("quarterly report".to_string())),
+        vec![1]
+    );
+    // The value is a substring, not words in any order
+    assert!(search(SearchQuery::Subject("report quarterly".to_string())).is_empty());
+    assert_eq!(search(SearchQuery::Subject("lösung".to_string())), vec![2]);
+    assert_eq!(search(SearchQuery::Subject(String::new())), vec![1, 2]);
+    assert_eq!(search(SearchQuery::Body("friday".to_string())), vec![2]);
+    assert!(search(SearchQuery::Body("quarterly".to_string())).is_empty());
+    assert_eq!(search(SearchQuery::Text("quarterly".to_string())), vec![1]);
+    assert_eq!(search(SearchQuery::Text("menu".to_string())), vec![2]);
+    assert_eq!(
+        search(SearchQuery::Attachment("menu.pdf".to_string())),
+        vec![2]
+    );
+    assert_eq!(
+        search(SearchQuery::Header(
+            "X-Priority".to_string(),
+            "urgent".to_string()
+        )),
+        vec![1]
+    );
+    assert_eq!(
+        search(SearchQuery::Header("cc".to_string(), String::new())),
+        vec![2]
+    );
+
+    assert_eq!(search(SearchQuery::ReceivedBefore(NOV_14 + DAY)), vec![2]);
+    assert_eq!(search(SearchQuery::ReceivedSince(NOV_14 + DAY)), vec![1]);
+    assert_eq!(search(SearchQuery::received_on(NOV_14)), vec![2]);
+    assert_eq!(search(SearchQuery::sent_on(NOV_14)), vec![1]);
+    assert_eq!(search(SearchQuery::SentBefore(NOV_14)), vec![2]);
+
+    assert_eq!(
+        search(SearchQuery::Or(vec![
+            SearchQuery::From("alice".to_string()),
+            SearchQuery::From("carol".to_string()),
+        ])),
+        vec![1, 2]
+    );
+    assert_eq!(
+        search(SearchQuery::And(vec![
+            SearchQuery::To("bob".to_string()),
+            SearchQuery::Not(Box::new(SearchQuery::From("alice".to_string()))),
+        ])),
+        vec![2]
+    );
+
+    index.remove_messages(mailbox, "INBOX", &[1]).unwrap();
+    index.remove_folder(mailbox, "Archive").unwrap();
+    index.commit().unwrap();
+    assert_eq!(search(SearchQuery::All), vec![2]);
+    assert!(index
+        .search(mailbox, "Archive", &SearchQuery::All)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        index
+            .search(other_mailbox, "INBOX", &SearchQuery::All)
+            .unwrap(),
+        vec![7]
+    );
+}
+
+const MEETING: &str = "From: Erin <erin@example.com>\r\n\
+To: team@example.com\r\n\
+Subject: Team MEETING tomorrow\r\n\
+X-Mailing-List:\r\n\
+X-Long: first part\r\n\
+\x20second part\r\n\
+\r\n\
+See you at 10.\r\n";
+
+#[test]
+pub fn test_substring_search() {
+    let index = SearchIndex::in_memory(WRITER_MEMORY).unwrap();
+    let mailbox = Uuid::new_v4();
+    index
+        .index_message(mailbox, "INBOX", 1, NOV_14, PLAIN.as_bytes())
+        .unwrap();
+    index
+        .index_message(mailbox, "INBOX", 2, NOV_14, MEETING.as_bytes())
+        .unwrap();
+    index.commit().unwrap();
+
+    let search = |query: SearchQuery| index.search(mailbox, "INBOX", &query).unwrap();
+    // IMAP SEARCH matches substrings case insensitively
+    assert_eq!(search(SearchQuery::Subject("meet".to_string())), vec![2]);
+    assert_eq!(search(SearchQuery::Subject("ING TOM".to_string())), vec![2]);
+    assert_eq!(search(SearchQuery::Subject("port".to_string())), vec![1]);
+    assert_eq!(search(SearchQuery::From("ice smi".to_string())), vec![1]);
+    // Too short for the index, so only the stored text is checked
+    assert_eq!(search(SearchQuery::Subject("qu".to_string())), vec![1]);
+    assert_eq!(search(SearchQuery::Body("10".to_string())), vec![2]);
+    assert_eq!(search(SearchQuery::Text("spread".to_string())), vec![1]);
+    assert_eq!(search(SearchQuery::Text("erin@ex".to_string())), vec![2]);
+    assert_eq!(
+        search(SearchQuery::Not(Box::new(SearchQuery::Subject(
+            "meet".to_string()
+        )))),
+        vec![1]
+    );
+    assert_eq!(
+        search(SearchQuery::Or(vec![
+            SearchQuery::Subject("meet".to_string()),
+            SearchQuery::ReceivedBefore(NOV_14),
+        ])),
+        vec![2]
+    );
+
+    // The value must be in the named header
+    assert_eq!(
+        search(SearchQuery::Header(
+            "x-priority".to_string(),
+            "URG".to_string()
+        )),
+        vec![1]
+    );
+    assert!(search(SearchQuery::Header(
+        "Subject".to_string(),
+        "urgent".to_string()
+    ))
+    .is_empty());
+    assert_eq!(
+        search(SearchQuery::Header(
+            "X-Long".to_string(),
+            "part second".to_string()
+        )),
+        vec![2]
+    );
+    // An empty value only asks for the header to exist
+    assert_eq!(
+        search(SearchQuery::Header(
+            "X-Mailing-List".to_string(),
+            String::new()
+        )),
+        vec![2]
+    );
+    assert_eq!(
+        search(SearchQuery::Header("x-priority".to_string(), String::new())),
+        vec![1]
+    );
+    assert!(search(SearchQuery::Header("X-Missing".to_string(), String::new())).is_empty());
+}
+
+#[test]
+pub fn test_reopen_index() {
+    let path = std::env::temp_dir().join(format!("nitro_mail_search_{}", Uuid::new_v4()));
+    let mailbox = Uuid::new_v4();
+    {
+        let index = SearchIndex::open(&path, WRITER_MEMORY).unwrap();
+        index
+            .index_message(mailbox, "INBOX", 3, NOV_14, PLAIN.as_bytes())
+            .unwrap();
+        index.commit().unwrap();
+    }
+    let index = SearchIndex::open(&path, WRITER_MEMORY).unwrap();
+    let found = index
+        .search(mailbox, "INBOX", &SearchQuery::From("alice".to_string()))
+        .unwrap();
+    drop(index);
+    let _ = std::fs::remove_dir_all(&path);
+    assert_eq!(found, vec![3]);
+}
+
+/// Removed when the test ends
+struct TestFiles(PathBuf);
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+async fn sqlite_storage(files: &TestFiles) -> DatabaseStorage {
+    let mut options = ConnectOptions::new("sqlite::memory:".to_string());
+    options.max_connections(1).min_connections(1);
+    let database = Database::connect(options)
+        .await
+        .expect("Failed to open sqlite database");
+    Migrator::up(&database, None)
+        .await
+        .expect("Failed to run migrations");
+    DatabaseStorage::new(database, files.0.join("blobs"))
+}
+
+/// The indexer commits in the background
+async fn wait_for(index: &SearchIndex, mailbox: Uuid, query: &SearchQuery, expected: Vec<u32>) {
+    for _ in 0..100 {
+        if index.search(mailbox, "INBOX", query).unwrap() == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("The index never returned {:?} for {:?}", expected, query);
+}
+
+#[tokio::test]
+async fn test_indexer() {
+    let files =
+        TestFiles(std::env::temp_dir().join(format!("nitro_mail_search_{}", Uuid::new_v4())));
+    let storage = sqlite_storage(&files).await;
+    let index = Arc::new(SearchIndex::in_memory(WRITER_MEMORY).unwrap());
+    let mailbox = Uuid::new_v4();
+    storage
+        .create_folder(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    // Appended before the indexer started so only a rebuild finds it
+    storage
+        .append_message(
+            mailbox,
+            "INBOX".to_string(),
+            NewMessage {
+                flags: vec![],
+                internal_date: NOV_14,
+                contents: ENCODED.as_bytes().to_vec(),
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    let indexer = spawn_indexer(index.clone(), storage.clone()).expect("SQL storage has events");
+
+    storage
+        .append_message(
+            mailbox,
+            "INBOX".to_string(),
+            NewMessage {
+                flags: vec![DELETED_FLAG.to_string()],
+                internal_date: NOV_14,
+                contents: PLAIN.as_bytes().to_vec(),
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    let alice = SearchQuery::From("alice".to_string());
+    wait_for(&index, mailbox, &alice, vec![2]).await;
+
+    storage
+        .expunge(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for(&index, mailbox, &alice, vec![]).await;
+    assert!(index
+        .search(mailbox, "INBOX", &SearchQuery::All)
+        .unwrap()
+        .is_empty());
+
+    let rebuild = rebuild_mailbox(&index, &storage, mailbox).await.unwrap();
+    assert_eq!(
+        rebuild,
+        Rebuild {
+            folders: 1,
+            messages: 1
+        }
+    );
+    assert_eq!(
+        index
+            .search(mailbox, "INBOX", &SearchQuery::Body("lunch".to_string()))
+            .unwrap(),
+        vec![1]
+    );
+
+    storage
+        .delete_folder(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for(&index, mailbox, &SearchQuery::All, vec![]).await;
+    indexer.abort();
+}
@@ -0,0 +1,20 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A blob in the blob store and how many messages use it
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "blobs")]
+pub struct Model {
+    /// The BLAKE3 hash the contents are stored under
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub hash: String,
+    /// Bytes
+    pub size: i64,
+    /// The number of messages using the blob. It is deleted once this reaches 0
+    pub reference_count: i64,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
@@ -0,0 +1,43 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use storages::folders::Folder;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "folders")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    /// The mailbox of the account or group. Together with the name it is unique
+    pub mailbox_id: Uuid,
+    /// Not `Text` because MySQL can not index it
+    pub name: String,
+    pub uid_validity: i64,
+    pub uid_next: i64,
+    pub highest_modseq: i64,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for Folder {
+    fn from(value: Model) -> Self {
+        Folder {
+            name: value.name,
+            uid_validity: value.uid_validity as u32,
+            uid_next: value.uid_next as u32,
+            highest_modseq: value.highest_modseq as u64,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::messages::Entity")]
+    Message,
+}
+
+impl Related<super::messages::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Message.def()
+    }
+}
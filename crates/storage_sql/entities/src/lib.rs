@@ -0,0 +1,13 @@
+pub use blobs::{ActiveModel as ActiveBlobModel, Entity as BlobEntity, Model as BlobModel};
+pub use folders::{ActiveModel as ActiveFolderModel, Entity as FolderEntity, Model as FolderModel};
+pub use mailbox_keys::{
+    ActiveModel as ActiveMailboxKeyModel, Entity as MailboxKeyEntity, Model as MailboxKeyModel,
+};
+pub use messages::{
+    ActiveModel as ActiveMessageModel, Entity as MessageEntity, Model as MessageModel,
+};
+
+pub mod blobs;
+pub mod folders;
+pub mod mailbox_keys;
+pub mod messages;
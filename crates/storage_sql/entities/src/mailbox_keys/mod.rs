@@ -0,0 +1,36 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use storages::encryption::WrappedKey;
+
+/// The data keys of encrypted mailboxes. The newest key of a mailbox seals new messages
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "mailbox_keys")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    pub mailbox_id: Uuid,
+    /// The fingerprint of the master key that wraps it
+    pub master_key: String,
+    #[sea_orm(column_type = "Text")]
+    pub wrapped_key: String,
+    /// Wrapped by the recovery public key
+    #[sea_orm(column_type = "Text", nullable)]
+    pub recovery_key: Option<String>,
+    /// Unix seconds
+    pub created: i64,
+}
+impl Model {
+    pub fn wrapped(&self) -> WrappedKey {
+        WrappedKey {
+            master_key: self.master_key.clone(),
+            wrapped: self.wrapped_key.clone(),
+            recovery: self.recovery_key.clone(),
+        }
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
@@ -0,0 +1,69 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use storages::folders::MessageInfo;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "messages")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    pub folder: i64,
+    /// Unique within the folder
+    pub uid: i64,
+    pub modseq: i64,
+    /// Separated by spaces. IMAP flags never contain one
+    #[sea_orm(column_type = "Text")]
+    pub flags: String,
+    /// Bytes
+    pub size: i64,
+    /// Unix seconds
+    pub internal_date: i64,
+    /// The hash the contents are stored under in the blob store
+    pub blob: String,
+    /// The mailbox key the contents are sealed with. Not encrypted if none
+    #[sea_orm(nullable)]
+    pub mailbox_key: Option<i64>,
+}
+impl Model {
+    pub fn flags(&self) -> Vec<String> {
+        self.flags.split_whitespace().map(str::to_string).collect()
+    }
+    pub fn join_flags(flags: &[String]) -> String {
+        flags.join(" ")
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for MessageInfo {
+    fn from(value: Model) -> Self {
+        MessageInfo {
+            flags: value.flags(),
+            uid: value.uid as u32,
+            modseq: value.modseq as u64,
+            size: value.size as u64,
+            internal_date: value.internal_date,
+        }
+    }
+}
+
+// Foreign Key folder to folders::id
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::folders::Entity",
+        from = "Column::Folder",
+        to = "super::folders::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Folder,
+}
+
+impl Related<super::folders::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Folder.def()
+    }
+}
@@ -0,0 +1,80 @@
+use sea_orm_migration::prelude::*;
+
+use storage_entities::{folders, messages, FolderEntity, MessageEntity};
+
+use crate::sea_orm::Schema;
+
+/// Creates the folders and messages
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const FOLDER_NAME_INDEX: &str = "idx-folders-mailbox_id-name";
+const MESSAGE_UID_INDEX: &str = "idx-messages-folder-uid";
+const MESSAGE_BLOB_INDEX: &str = "idx-messages-blob";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(
+                schema
+                    .create_table_from_entity(FolderEntity)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                schema
+                    .create_table_from_entity(MessageEntity)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(FOLDER_NAME_INDEX)
+                    .table(FolderEntity)
+                    .col(folders::Column::MailboxId)
+                    .col(folders::Column::Name)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(MESSAGE_UID_INDEX)
+                    .table(MessageEntity)
+                    .col(messages::Column::Folder)
+                    .col(messages::Column::Uid)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        // Blobs are only removed once no message uses them
+        manager
+            .create_index(
+                Index::create()
+                    .name(MESSAGE_BLOB_INDEX)
+                    .table(MessageEntity)
+                    .col(messages::Column::Blob)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MessageEntity).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(FolderEntity).to_owned())
+            .await
+    }
+}
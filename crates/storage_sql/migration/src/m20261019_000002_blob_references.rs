@@ -0,0 +1,53 @@
+use sea_orm_migration::prelude::*;
+
+use storage_entities::{blobs, messages, BlobEntity, MessageEntity};
+
+use crate::sea_orm::Schema;
+
+/// Counts the references of every blob so it can be shared by messages in many mailboxes
+///
+/// Existing blobs get the number of messages already using them
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(
+                schema
+                    .create_table_from_entity(BlobEntity)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        let existing = Query::select()
+            .column(messages::Column::Blob)
+            .expr(Expr::col(messages::Column::Size).max())
+            .expr(Expr::col(messages::Column::Id).count())
+            .from(MessageEntity)
+            .group_by_col(messages::Column::Blob)
+            .to_owned();
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table(BlobEntity)
+                    .columns([
+                        blobs::Column::Hash,
+                        blobs::Column::Size,
+                        blobs::Column::ReferenceCount,
+                    ])
+                    .select_from(existing)
+                    .map_err(|error| DbErr::Migration(error.to_string()))?
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(BlobEntity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,71 @@
+use sea_orm_migration::prelude::*;
+
+use storage_entities::{mailbox_keys, messages, MailboxKeyEntity, MessageEntity};
+
+use crate::sea_orm::{EntityName, Schema};
+
+/// Stores the data keys of encrypted mailboxes and which key sealed each message
+///
+/// Existing messages are not encrypted
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const MAILBOX_KEY_INDEX: &str = "idx-mailbox_keys-mailbox_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(
+                schema
+                    .create_table_from_entity(MailboxKeyEntity)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(MAILBOX_KEY_INDEX)
+                    .table(MailboxKeyEntity)
+                    .col(mailbox_keys::Column::MailboxId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        // Databases created by the first migration already have the column
+        if !manager
+            .has_column(MessageEntity.table_name(), "mailbox_key")
+            .await?
+        {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MessageEntity.table_ref())
+                        .add_column(
+                            ColumnDef::new(messages::Column::MailboxKey)
+                                .big_integer()
+                                .null(),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MessageEntity.table_ref())
+                    .drop_column(messages::Column::MailboxKey)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(MailboxKeyEntity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,552 @@
+use std::convert::Infallible;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ahash::{HashMap, HashMapExt, HashSet};
+use async_trait::async_trait;
+use futures::future::Ready;
+use sea_orm::sea_query::{Alias, Expr, Func, OnConflict, SimpleExpr};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, Database, DatabaseConnection,
+    DbBackend, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use thiserror::Error;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
+
+use storage_entities::blobs::Column as BlobColumn;
+use storage_entities::folders::Column as FolderColumn;
+use storage_entities::messages::Column as MessageColumn;
+use storage_entities::{
+    ActiveBlobModel, ActiveFolderModel, ActiveMessageModel, BlobEntity, FolderEntity, FolderModel,
+    MessageEntity, MessageModel,
+};
+use storage_migration::{Migrator, MigratorTrait};
+use storages::blob_store::{BlobStore, GarbageCollection};
+use storages::encryption::{EncryptionError, Keyring};
+use storages::events::{StorageEvent, StorageEvents};
+use storages::folders::{
+    Delivery, Folder, MessageInfo, NewMessage, StorageError, StorageResult, DELETED_FLAG,
+};
+use storages::storage_type::Storage;
+use utils::quota::QuotaUsage;
+use utils::service::{Service, ServiceAccess};
+
+use crate::storage_config::SqlStorageConfig;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] DbErr),
+    #[error(transparent)]
+    Blob(#[from] io::Error),
+    #[error(transparent)]
+    Encryption(#[from] EncryptionError),
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseStorage {
+    pub(crate) database: DatabaseConnection,
+    pub(crate) blobs: Arc<BlobStore>,
+    /// Held shared while appending and exclusively while deleting blobs.
+    /// Otherwise a blob could be deleted just after a new message started referencing it
+    pub(crate) blob_lock: Arc<RwLock<()>>,
+    /// None if encryption is not configured
+    pub(crate) keyring: Option<Arc<Keyring>>,
+    pub(crate) events: StorageEvents,
+}
+impl Service for DatabaseStorage {
+    type ServiceConfig = SqlStorageConfig;
+    type ServiceError = Error;
+}
+impl ServiceAccess for DatabaseStorage {
+    type ServiceResponse = Self;
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn get_service(&self) -> Self::Future {
+        futures::future::ready(Ok(self.clone()))
+    }
+}
+impl DatabaseStorage {
+    /// The database must already be migrated
+    pub fn new(database: DatabaseConnection, blob_path: impl Into<PathBuf>) -> Self {
+        Self {
+            database,
+            blobs: Arc::new(BlobStore::new(blob_path)),
+            blob_lock: Arc::new(RwLock::new(())),
+            keyring: None,
+            events: StorageEvents::default(),
+        }
+    }
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(Arc::new(keyring));
+        self
+    }
+    /// Connects and runs the migrations
+    pub async fn connect(config: &SqlStorageConfig) -> Result<Self, Error> {
+        let keyring = config.encryption.keyring()?;
+        let database = Database::connect(config.connect_options()).await?;
+        Migrator::up(&database, None).await?;
+        let storage = Self::new(database, config.blob_path.clone());
+        Ok(match keyring {
+            Some(keyring) => storage.with_keyring(keyring),
+            None => storage,
+        })
+    }
+
+    pub(crate) async fn folder(
+        connection: &impl ConnectionTrait,
+        mailbox_id: Uuid,
+        name: &str,
+    ) -> Result<Option<FolderModel>, DbErr> {
+        FolderEntity::find()
+            .filter(FolderColumn::MailboxId.eq(mailbox_id))
+            .filter(FolderColumn::Name.eq(name))
+            .one(connection)
+            .await
+    }
+    async fn message(
+        connection: &impl ConnectionTrait,
+        folder: &FolderModel,
+        uid: u32,
+    ) -> Result<Option<MessageModel>, DbErr> {
+        MessageEntity::find()
+            .filter(MessageColumn::Folder.eq(folder.id))
+            .filter(MessageColumn::Uid.eq(uid as i64))
+            .one(connection)
+            .await
+    }
+    /// Bumps the highest modseq of the folder and returns it
+    async fn next_modseq(connection: &impl ConnectionTrait, folder: i64) -> Result<i64, DbErr> {
+        FolderEntity::update_many()
+            .col_expr(
+                FolderColumn::HighestModseq,
+                Expr::col(FolderColumn::HighestModseq).add(1),
+            )
+            .filter(FolderColumn::Id.eq(folder))
+            .exec(connection)
+            .await?;
+        let folder = FolderEntity::find_by_id(folder)
+            .one(connection)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("Folder {folder}")))?;
+        Ok(folder.highest_modseq)
+    }
+    /// Gives the folder a new UID and modseq for a message. Returns them
+    pub(crate) async fn next_uid(
+        connection: &impl ConnectionTrait,
+        folder: i64,
+    ) -> Result<(i64, i64), DbErr> {
+        FolderEntity::update_many()
+            .col_expr(
+                FolderColumn::UidNext,
+                Expr::col(FolderColumn::UidNext).add(1),
+            )
+            .col_expr(
+                FolderColumn::HighestModseq,
+                Expr::col(FolderColumn::HighestModseq).add(1),
+            )
+            .filter(FolderColumn::Id.eq(folder))
+            .exec(connection)
+            .await?;
+        let folder = FolderEntity::find_by_id(folder)
+            .one(connection)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("Folder {folder}")))?;
+        Ok((folder.uid_next - 1, folder.highest_modseq))
+    }
+    /// Counts another message using the blob
+    pub(crate) async fn reference_blob(
+        connection: &impl ConnectionTrait,
+        hash: &str,
+        size: i64,
+    ) -> Result<(), DbErr> {
+        let blob = ActiveBlobModel {
+            hash: ActiveValue::Set(hash.to_string()),
+            size: ActiveValue::Set(size),
+            reference_count: ActiveValue::Set(1),
+        };
+        BlobEntity::insert(blob)
+            .on_conflict(
+                OnConflict::column(BlobColumn::Hash)
+                    .value(
+                        BlobColumn::ReferenceCount,
+                        Expr::col((BlobEntity, BlobColumn::ReferenceCount)).add(1),
+                    )
+                    .to_owned(),
+            )
+            .exec_without_returning(connection)
+            .await?;
+        Ok(())
+    }
+    /// Stops counting the messages as using their blobs. Returns the blobs nothing uses anymore
+    pub(crate) async fn dereference_blobs(
+        connection: &impl ConnectionTrait,
+        messages: &[MessageModel],
+    ) -> Result<Vec<String>, DbErr> {
+        let mut references: HashMap<&str, i64> = HashMap::new();
+        for message in messages {
+            *references.entry(message.blob.as_str()).or_default() += 1;
+        }
+        for (hash, count) in &references {
+            BlobEntity::update_many()
+                .col_expr(
+                    BlobColumn::ReferenceCount,
+                    Expr::col(BlobColumn::ReferenceCount).sub(*count),
+                )
+                .filter(BlobColumn::Hash.eq(*hash))
+                .exec(connection)
+                .await?;
+        }
+        BlobEntity::find()
+            .select_only()
+            .column(BlobColumn::Hash)
+            .filter(BlobColumn::Hash.is_in(references.into_keys()))
+            .filter(BlobColumn::ReferenceCount.lte(0))
+            .into_tuple()
+            .all(connection)
+            .await
+    }
+    /// Deletes blobs that were dereferenced. Ones that got used again in the meantime are kept
+    pub(crate) async fn release_blobs(&self, blobs: Vec<String>) -> Result<(), Error> {
+        if blobs.is_empty() {
+            return Ok(());
+        }
+        let _guard = self.blob_lock.write().await;
+        for blob in blobs {
+            let deleted = BlobEntity::delete_many()
+                .filter(BlobColumn::Hash.eq(blob.as_str()))
+                .filter(BlobColumn::ReferenceCount.lte(0))
+                .exec(&self.database)
+                .await?;
+            if deleted.rows_affected > 0 && !self.blobs.delete(&blob).await? {
+                warn!(?blob, "Released a blob that was already deleted");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for DatabaseStorage {
+    fn storage_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "storage_sql"
+    }
+
+    fn storage_path(&self) -> String {
+        self.blobs.root().display().to_string()
+    }
+
+    async fn quota_usage(&self, mailbox_id: Uuid) -> Result<QuotaUsage, Self::ServiceError> {
+        // SUM of a bigint is a numeric on Postgres and a decimal on MySQL
+        let integer = match self.database.get_database_backend() {
+            DbBackend::MySql => "SIGNED",
+            DbBackend::Postgres | DbBackend::Sqlite => "BIGINT",
+        };
+        let (messages, storage): (i64, Option<i64>) = MessageEntity::find()
+            .select_only()
+            .column_as(MessageColumn::Id.count(), "messages")
+            .column_as(
+                SimpleExpr::from(Func::cast_as(
+                    Func::sum(Expr::col((MessageEntity, MessageColumn::Size))),
+                    Alias::new(integer),
+                )),
+                "storage",
+            )
+            .inner_join(FolderEntity)
+            .filter(FolderColumn::MailboxId.eq(mailbox_id))
+            .into_tuple()
+            .one(&self.database)
+            .await?
+            .unwrap_or_default();
+        Ok(QuotaUsage {
+            storage: storage.unwrap_or_default() as u64,
+            messages: messages as u64,
+        })
+    }
+
+    fn subscribe(&self) -> Option<Receiver<StorageEvent>> {
+        Some(self.events.subscribe())
+    }
+
+    async fn create_folder(
+        &self,
+        mailbox_id: Uuid,
+        name: String,
+    ) -> Result<StorageResult<Folder>, Self::ServiceError> {
+        if Self::folder(&self.database, mailbox_id, &name)
+            .await?
+            .is_some()
+        {
+            return Ok(Err(StorageError::FolderExists(name)));
+        }
+        let uid_validity = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32;
+        let folder = ActiveFolderModel {
+            mailbox_id: ActiveValue::Set(mailbox_id),
+            name: ActiveValue::Set(name),
+            uid_validity: ActiveValue::Set(uid_validity as i64),
+            uid_next: ActiveValue::Set(1),
+            highest_modseq: ActiveValue::Set(1),
+            ..Default::default()
+        }
+        .insert(&self.database)
+        .await?;
+        Ok(Ok(folder.into()))
+    }
+
+    async fn list_folders(
+        &self,
+        mailbox_id: Uuid,
+    ) -> Result<StorageResult<Vec<Folder>>, Self::ServiceError> {
+        let folders = FolderEntity::find()
+            .filter(FolderColumn::MailboxId.eq(mailbox_id))
+            .order_by_asc(FolderColumn::Name)
+            .all(&self.database)
+            .await?;
+        Ok(Ok(folders.into_iter().map(Folder::from).collect()))
+    }
+
+    async fn delete_folder(
+        &self,
+        mailbox_id: Uuid,
+        name: String,
+    ) -> Result<StorageResult<bool>, Self::ServiceError> {
+        let transaction = self.database.begin().await?;
+        let Some(folder) = Self::folder(&transaction, mailbox_id, &name).await? else {
+            return Ok(Ok(false));
+        };
+        let messages = MessageEntity::find()
+            .filter(MessageColumn::Folder.eq(folder.id))
+            .all(&transaction)
+            .await?;
+        // Not left to the foreign key. SQLite only cascades with foreign keys enabled
+        MessageEntity::delete_many()
+            .filter(MessageColumn::Folder.eq(folder.id))
+            .exec(&transaction)
+            .await?;
+        FolderEntity::delete_by_id(folder.id)
+            .exec(&transaction)
+            .await?;
+        let unused = Self::dereference_blobs(&transaction, &messages).await?;
+        transaction.commit().await?;
+        self.events.publish(StorageEvent::FolderDeleted {
+            mailbox_id,
+            folder: name,
+        });
+
+        self.release_blobs(unused).await?;
+        Ok(Ok(true))
+    }
+
+    async fn append_message(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        message: NewMessage,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        let mut results = self
+            .deliver_message(vec![Delivery { mailbox_id, folder }], message)
+            .await?;
+        Ok(results.remove(0))
+    }
+
+    /// The contents are written to the blob store once however many folders get the message
+    async fn deliver_message(
+        &self,
+        deliveries: Vec<Delivery>,
+        message: NewMessage,
+    ) -> Result<Vec<StorageResult<MessageInfo>>, Self::ServiceError> {
+        let _guard = self.blob_lock.read().await;
+        let size = message.contents.len() as i64;
+        let flags = MessageModel::join_flags(&message.flags);
+        // Each mailbox key seals the message differently so there is a blob for each
+        let mut blobs: HashMap<Option<i64>, (String, i64)> = HashMap::new();
+        let mut results = Vec::with_capacity(deliveries.len());
+        let mut events = Vec::with_capacity(deliveries.len());
+
+        // A blob written before the transaction fails is left for garbage collection
+        let transaction = self.database.begin().await?;
+        for delivery in deliveries {
+            let Some(folder) =
+                Self::folder(&transaction, delivery.mailbox_id, &delivery.folder).await?
+            else {
+                results.push(Err(StorageError::FolderNotFound(delivery.folder)));
+                continue;
+            };
+            let key = self.mailbox_key(&transaction, delivery.mailbox_id).await?;
+            let key_id = key.as_ref().map(|(id, _)| *id);
+            let (hash, blob_size) = match blobs.get(&key_id) {
+                Some(blob) => blob.clone(),
+                None => {
+                    let sealed = key
+                        .as_ref()
+                        .map(|(_, key)| key.seal_deterministic(&message.contents));
+                    let contents = sealed.as_deref().unwrap_or(&message.contents);
+                    let blob = (self.blobs.write(contents).await?, contents.len() as i64);
+                    blobs.insert(key_id, blob.clone());
+                    blob
+                }
+            };
+            let (uid, modseq) = Self::next_uid(&transaction, folder.id).await?;
+            let stored = ActiveMessageModel {
+                folder: ActiveValue::Set(folder.id),
+                uid: ActiveValue::Set(uid),
+                modseq: ActiveValue::Set(modseq),
+                flags: ActiveValue::Set(flags.clone()),
+                size: ActiveValue::Set(size),
+                internal_date: ActiveValue::Set(message.internal_date),
+                blob: ActiveValue::Set(hash.clone()),
+                mailbox_key: ActiveValue::Set(key_id),
+                ..Default::default()
+            }
+            .insert(&transaction)
+            .await?;
+            Self::reference_blob(&transaction, &hash, blob_size).await?;
+            events.push(StorageEvent::MessageAppended {
+                mailbox_id: delivery.mailbox_id,
+                folder: delivery.folder,
+                uid: uid as u32,
+                internal_date: message.internal_date,
+            });
+            results.push(Ok(stored.into()));
+        }
+        transaction.commit().await?;
+        for event in events {
+            self.events.publish(event);
+        }
+        Ok(results)
+    }
+
+    async fn list_messages(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+    ) -> Result<StorageResult<Vec<MessageInfo>>, Self::ServiceError> {
+        let Some(folder) = Self::folder(&self.database, mailbox_id, &folder).await? else {
+            return Ok(Err(StorageError::FolderNotFound(folder)));
+        };
+        let messages = MessageEntity::find()
+            .filter(MessageColumn::Folder.eq(folder.id))
+            .order_by_asc(MessageColumn::Uid)
+            .all(&self.database)
+            .await?;
+        Ok(Ok(messages.into_iter().map(MessageInfo::from).collect()))
+    }
+
+    async fn read_message(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+    ) -> Result<StorageResult<Vec<u8>>, Self::ServiceError> {
+        let Some(folder) = Self::folder(&self.database, mailbox_id, &folder).await? else {
+            return Ok(Err(StorageError::FolderNotFound(folder)));
+        };
+        let Some(message) = Self::message(&self.database, &folder, uid).await? else {
+            return Ok(Err(StorageError::MessageNotFound(uid)));
+        };
+        Ok(Ok(self.contents(&self.database, &message).await?))
+    }
+
+    async fn set_flags(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+        flags: Vec<String>,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        let transaction = self.database.begin().await?;
+        let Some(folder) = Self::folder(&transaction, mailbox_id, &folder).await? else {
+            return Ok(Err(StorageError::FolderNotFound(folder)));
+        };
+        let Some(message) = Self::message(&transaction, &folder, uid).await? else {
+            return Ok(Err(StorageError::MessageNotFound(uid)));
+        };
+        let modseq = Self::next_modseq(&transaction, folder.id).await?;
+        let mut message: ActiveMessageModel = message.into();
+        message.flags = ActiveValue::Set(MessageModel::join_flags(&flags));
+        message.modseq = ActiveValue::Set(modseq);
+        let message = message.update(&transaction).await?;
+        transaction.commit().await?;
+        Ok(Ok(message.into()))
+    }
+
+    async fn expunge(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+    ) -> Result<StorageResult<Vec<u32>>, Self::ServiceError> {
+        let transaction = self.database.begin().await?;
+        let Some(folder) = Self::folder(&transaction, mailbox_id, &folder).await? else {
+            return Ok(Err(StorageError::FolderNotFound(folder)));
+        };
+        // Flags are not queryable as a column so the deleted messages are found here
+        let deleted: Vec<MessageModel> = MessageEntity::find()
+            .filter(MessageColumn::Folder.eq(folder.id))
+            .order_by_asc(MessageColumn::Uid)
+            .all(&transaction)
+            .await?
+            .into_iter()
+            .filter(|message| message.flags().iter().any(|flag| flag == DELETED_FLAG))
+            .collect();
+        if deleted.is_empty() {
+            return Ok(Ok(vec![]));
+        }
+        MessageEntity::delete_many()
+            .filter(MessageColumn::Id.is_in(deleted.iter().map(|message| message.id)))
+            .exec(&transaction)
+            .await?;
+        Self::next_modseq(&transaction, folder.id).await?;
+        let unused = Self::dereference_blobs(&transaction, &deleted).await?;
+        transaction.commit().await?;
+        let uids: Vec<u32> = deleted.iter().map(|message| message.uid as u32).collect();
+        self.events.publish(StorageEvent::MessagesExpunged {
+            mailbox_id,
+            folder: folder.name,
+            uids: uids.clone(),
+        });
+
+        self.release_blobs(unused).await?;
+        Ok(Ok(uids))
+    }
+
+    /// Also removes blobs that were left behind when writing a message or deleting its blob failed
+    async fn collect_garbage(
+        &self,
+    ) -> Result<StorageResult<GarbageCollection>, Self::ServiceError> {
+        let _guard = self.blob_lock.write().await;
+        BlobEntity::delete_many()
+            .filter(BlobColumn::ReferenceCount.lte(0))
+            .exec(&self.database)
+            .await?;
+        let referenced: Vec<String> = BlobEntity::find()
+            .select_only()
+            .column(BlobColumn::Hash)
+            .into_tuple()
+            .all(&self.database)
+            .await?;
+        let referenced = HashSet::from_iter(referenced);
+        Ok(Ok(self.blobs.collect_garbage(&referenced).await?))
+    }
+
+    async fn rotate_mailbox_key(
+        &self,
+        mailbox_id: Uuid,
+    ) -> Result<StorageResult<u64>, Self::ServiceError> {
+        if self.keyring.is_none() {
+            return Ok(Err(StorageError::EncryptionNotConfigured));
+        }
+        Ok(Ok(self.reseal_mailbox(mailbox_id).await?))
+    }
+}
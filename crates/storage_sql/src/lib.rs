@@ -0,0 +1,5 @@
+pub mod database_storage;
+pub mod mailbox_keys;
+pub mod storage_config;
+#[cfg(test)]
+pub mod storage_tests;
@@ -0,0 +1,201 @@
+//! The data keys of encrypted mailboxes. See [storages::encryption]
+use std::collections::hash_map::Entry;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ahash::{HashMap, HashMapExt};
+use rsa::RsaPrivateKey;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter,
+    QueryOrder, TransactionTrait,
+};
+use uuid::Uuid;
+
+use storage_entities::folders::Column as FolderColumn;
+use storage_entities::mailbox_keys::Column as MailboxKeyColumn;
+use storage_entities::{
+    ActiveMailboxKeyModel, ActiveMessageModel, FolderEntity, MailboxKeyEntity, MailboxKeyModel,
+    MessageEntity, MessageModel,
+};
+use storages::encryption::{recover_data_key, EncryptionError, EncryptionKey, Keyring, WrappedKey};
+
+use crate::database_storage::{DatabaseStorage, Error};
+
+/// The result of [DatabaseStorage::recover_mailbox_keys]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyRecovery {
+    pub recovered: u64,
+    /// Mailboxes with keys that have no recovery copy. Their messages can not be read
+    pub unrecoverable: Vec<Uuid>,
+}
+
+impl DatabaseStorage {
+    fn keyring(&self) -> Result<&Keyring, EncryptionError> {
+        self.keyring
+            .as_deref()
+            .ok_or(EncryptionError::MissingMasterKey)
+    }
+
+    /// The newest key of the mailbox. One is created if new mailboxes are encrypted and it has none
+    pub(crate) async fn mailbox_key(
+        &self,
+        connection: &impl ConnectionTrait,
+        mailbox_id: Uuid,
+    ) -> Result<Option<(i64, EncryptionKey)>, Error> {
+        let key = MailboxKeyEntity::find()
+            .filter(MailboxKeyColumn::MailboxId.eq(mailbox_id))
+            .order_by_desc(MailboxKeyColumn::Id)
+            .one(connection)
+            .await?;
+        match (key, self.keyring.as_deref()) {
+            (Some(key), _) => Ok(Some((key.id, self.keyring()?.unwrap(&key.wrapped())?))),
+            (None, Some(keyring)) if keyring.encrypt_new_mailboxes => Ok(Some(
+                Self::create_mailbox_key(connection, keyring, mailbox_id).await?,
+            )),
+            (None, _) => Ok(None),
+        }
+    }
+    async fn create_mailbox_key(
+        connection: &impl ConnectionTrait,
+        keyring: &Keyring,
+        mailbox_id: Uuid,
+    ) -> Result<(i64, EncryptionKey), Error> {
+        let key = EncryptionKey::generate();
+        let wrapped = keyring.wrap(&key)?;
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let model = ActiveMailboxKeyModel {
+            mailbox_id: ActiveValue::Set(mailbox_id),
+            master_key: ActiveValue::Set(wrapped.master_key),
+            wrapped_key: ActiveValue::Set(wrapped.wrapped),
+            recovery_key: ActiveValue::Set(wrapped.recovery),
+            created: ActiveValue::Set(created),
+            ..Default::default()
+        }
+        .insert(connection)
+        .await?;
+        Ok((model.id, key))
+    }
+    async fn data_key(
+        &self,
+        connection: &impl ConnectionTrait,
+        id: i64,
+    ) -> Result<EncryptionKey, Error> {
+        let key = MailboxKeyEntity::find_by_id(id)
+            .one(connection)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("Mailbox key {id}")))?;
+        Ok(self.keyring()?.unwrap(&key.wrapped())?)
+    }
+
+    /// The contents of the message. Opened if they are sealed
+    pub(crate) async fn contents(
+        &self,
+        connection: &impl ConnectionTrait,
+        message: &MessageModel,
+    ) -> Result<Vec<u8>, Error> {
+        let contents = self.blobs.read(&message.blob).await?;
+        match message.mailbox_key {
+            Some(key) => Ok(self.data_key(connection, key).await?.open(&contents)?),
+            None => Ok(contents),
+        }
+    }
+
+    /// Seals every message of the mailbox with a new data key and removes the old keys
+    pub(crate) async fn reseal_mailbox(&self, mailbox_id: Uuid) -> Result<u64, Error> {
+        let keyring = self.keyring()?;
+        let guard = self.blob_lock.read().await;
+        let transaction = self.database.begin().await?;
+        let (key_id, key) = Self::create_mailbox_key(&transaction, keyring, mailbox_id).await?;
+        let messages = MessageEntity::find()
+            .inner_join(FolderEntity)
+            .filter(FolderColumn::MailboxId.eq(mailbox_id))
+            .all(&transaction)
+            .await?;
+        let mut old_keys: HashMap<i64, EncryptionKey> = HashMap::new();
+        for message in &messages {
+            let mut contents = self.blobs.read(&message.blob).await?;
+            if let Some(old_key) = message.mailbox_key {
+                if let Entry::Vacant(entry) = old_keys.entry(old_key) {
+                    entry.insert(self.data_key(&transaction, old_key).await?);
+                }
+                contents = old_keys[&old_key].open(&contents)?;
+            }
+            let sealed = key.seal_deterministic(&contents);
+            let hash = self.blobs.write(&sealed).await?;
+            Self::reference_blob(&transaction, &hash, sealed.len() as i64).await?;
+            let mut message: ActiveMessageModel = message.clone().into();
+            message.blob = ActiveValue::Set(hash);
+            message.mailbox_key = ActiveValue::Set(Some(key_id));
+            message.update(&transaction).await?;
+        }
+        let unused = Self::dereference_blobs(&transaction, &messages).await?;
+        MailboxKeyEntity::delete_many()
+            .filter(MailboxKeyColumn::MailboxId.eq(mailbox_id))
+            .filter(MailboxKeyColumn::Id.ne(key_id))
+            .exec(&transaction)
+            .await?;
+        transaction.commit().await?;
+        drop(guard);
+
+        self.release_blobs(unused).await?;
+        Ok(messages.len() as u64)
+    }
+
+    async fn store_wrapped_key(
+        &self,
+        key: MailboxKeyModel,
+        wrapped: WrappedKey,
+    ) -> Result<(), Error> {
+        let mut key: ActiveMailboxKeyModel = key.into();
+        key.master_key = ActiveValue::Set(wrapped.master_key);
+        key.wrapped_key = ActiveValue::Set(wrapped.wrapped);
+        key.recovery_key = ActiveValue::Set(wrapped.recovery);
+        key.update(&self.database).await?;
+        Ok(())
+    }
+
+    /// Wraps every data key with the current master key and recovery key. Returns how many were wrapped again
+    ///
+    /// Keys still wrapped by a previous master key are read with it
+    pub async fn rewrap_mailbox_keys(&self) -> Result<u64, Error> {
+        let keyring = self.keyring()?;
+        let mut rewrapped = 0;
+        for key in MailboxKeyEntity::find().all(&self.database).await? {
+            let wrapped = key.wrapped();
+            if !keyring.needs_rewrap(&wrapped) {
+                continue;
+            }
+            let data_key = keyring.unwrap(&wrapped)?;
+            self.store_wrapped_key(key, keyring.wrap(&data_key)?)
+                .await?;
+            rewrapped += 1;
+        }
+        Ok(rewrapped)
+    }
+
+    /// Wraps every data key with the current master key using their recovery copies.
+    /// For when the master key that wrapped them is lost
+    pub async fn recover_mailbox_keys(
+        &self,
+        recovery_key: &RsaPrivateKey,
+    ) -> Result<KeyRecovery, Error> {
+        let keyring = self.keyring()?;
+        let mut recovery = KeyRecovery::default();
+        for key in MailboxKeyEntity::find().all(&self.database).await? {
+            let data_key = match recover_data_key(recovery_key, &key.wrapped()) {
+                Ok(ok) => ok,
+                Err(EncryptionError::MissingRecoveryKey) => {
+                    recovery.unrecoverable.push(key.mailbox_id);
+                    continue;
+                }
+                Err(error) => return Err(error.into()),
+            };
+            self.store_wrapped_key(key, keyring.wrap(&data_key)?)
+                .await?;
+            recovery.recovered += 1;
+        }
+        Ok(recovery)
+    }
+}
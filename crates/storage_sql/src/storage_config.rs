@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use sea_orm::ConnectOptions;
+use serde::{Deserialize, Serialize};
+
+use directory_sql::database_config::{Database, PoolConfig};
+use storages::encryption::EncryptionConfig;
+use utils::configs::{Config, ConfigName};
+
+fn default_blob_path() -> PathBuf {
+    PathBuf::from("blobs")
+}
+
+/// The database is configured like the [SQL directory](directory_sql::database_config::DatabaseConfig)
+///
+/// # Example
+/// ```toml
+/// blob_path = "/var/lib/nitro_mail/blobs"
+/// [database]
+/// type = "postgres"
+/// [database.settings]
+/// user = "nitro_mail"
+/// password = "password"
+/// host = "localhost:5432"
+/// database = "nitro_mail"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SqlStorageConfig {
+    pub database: Database,
+    #[serde(default)]
+    pub pool: PoolConfig,
+    /// Where message contents are stored. Everything else is in the database
+    #[serde(default = "default_blob_path")]
+    pub blob_path: PathBuf,
+    /// Encrypted mailboxes seal messages with their own keys, so a message is only stored once per mailbox.
+    /// A message to a list with 500 encrypted members is stored 500 times, once per member.
+    /// Unencrypted mailboxes share one copy
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+}
+impl SqlStorageConfig {
+    pub fn connect_options(&self) -> ConnectOptions {
+        let mut connect_options = ConnectOptions::new(self.database.to_string());
+        connect_options
+            .max_connections(self.pool.max_size)
+            .min_connections(self.pool.min_connections);
+        connect_options
+    }
+}
+impl Config for SqlStorageConfig {
+    fn config_header() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        Some("https://docs.nitro_mail.kingtux.dev/configs/sql_storage")
+    }
+
+    fn config_name() -> ConfigName
+    where
+        Self: Sized,
+    {
+        ConfigName::Name("sql.storage.toml")
+    }
+}
+impl Default for SqlStorageConfig {
+    fn default() -> Self {
+        SqlStorageConfig {
+            database: Database::default(),
+            pool: PoolConfig::default(),
+            blob_path: default_blob_path(),
+            encryption: EncryptionConfig::default(),
+        }
+    }
+}
@@ -0,0 +1,743 @@
+use std::path::PathBuf;
+
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sea_orm::{ActiveValue, ConnectOptions, Database, EntityTrait};
+use uuid::Uuid;
+
+use storage_entities::{ActiveBlobModel, BlobEntity, MailboxKeyEntity};
+use storage_migration::{Migrator, MigratorTrait};
+use storages::blob_store::{BlobStore, GarbageCollection};
+use storages::encryption::{EncryptionKey, Keyring};
+use storages::events::StorageEvent;
+use storages::folders::{Delivery, NewMessage, StorageError, DELETED_FLAG};
+use storages::storage_type::Storage;
+use utils::quota::QuotaUsage;
+
+use crate::database_storage::{DatabaseStorage, Error};
+use crate::mailbox_keys::KeyRecovery;
+
+/// Removed when the test ends
+struct TestFiles(PathBuf);
+impl TestFiles {
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("nitro_mail_storage_sql_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A single connection so every query sees the same in memory database
+async fn sqlite_storage(files: &TestFiles) -> DatabaseStorage {
+    let mut options = ConnectOptions::new("sqlite::memory:".to_string());
+    options.max_connections(1).min_connections(1);
+    let database = Database::connect(options)
+        .await
+        .expect("Failed to open sqlite database");
+    Migrator::up(&database, None)
+        .await
+        .expect("Failed to run migrations");
+    DatabaseStorage::new(database, files.0.join("blobs"))
+}
+
+fn message(contents: &str) -> NewMessage {
+    NewMessage {
+        flags: vec![],
+        internal_date: 1_700_000_000,
+        contents: contents.as_bytes().to_vec(),
+    }
+}
+
+fn blob_exists(storage: &DatabaseStorage, contents: &str) -> bool {
+    let hash = BlobStore::hash(contents.as_bytes());
+    storage
+        .blobs
+        .root()
+        .join(&hash[..2])
+        .join(&hash[2..])
+        .exists()
+}
+
+#[tokio::test]
+async fn test_folders() {
+    let files = TestFiles::new();
+    let storage = sqlite_storage(&files).await;
+    let mailbox = Uuid::new_v4();
+    let other_mailbox = Uuid::new_v4();
+
+    let inbox = storage
+        .create_folder(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(inbox.name, "INBOX");
+    assert_eq!(inbox.uid_next, 1);
+    storage
+        .create_folder(mailbox, "Archive".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        storage
+            .create_folder(mailbox, "INBOX".to_string())
+            .await
+            .unwrap(),
+        Err(StorageError::FolderExists("INBOX".to_string()))
+    );
+    // Names are only unique within a mailbox
+    storage
+        .create_folder(other_mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let names: Vec<String> = storage
+        .list_folders(mailbox)
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|folder| folder.name)
+        .collect();
+    assert_eq!(names, vec!["Archive", "INBOX"]);
+
+    assert!(storage
+        .delete_folder(mailbox, "Archive".to_string())
+        .await
+        .unwrap()
+        .unwrap());
+    assert!(!storage
+        .delete_folder(mailbox, "Archive".to_string())
+        .await
+        .unwrap()
+        .unwrap());
+    assert_eq!(
+        storage.list_folders(mailbox).await.unwrap().unwrap().len(),
+        1
+    );
+    assert_eq!(
+        storage
+            .list_folders(other_mailbox)
+            .await
+            .unwrap()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_messages() {
+    let files = TestFiles::new();
+    let storage = sqlite_storage(&files).await;
+    let mailbox = Uuid::new_v4();
+    let inbox = storage
+        .create_folder(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        storage
+            .append_message(mailbox, "Missing".to_string(), message("Hello"))
+            .await
+            .unwrap(),
+        Err(StorageError::FolderNotFound("Missing".to_string()))
+    );
+
+    let first = storage
+        .append_message(mailbox, "INBOX".to_string(), message("Hello"))
+        .await
+        .unwrap()
+        .unwrap();
+    let second = storage
+        .append_message(
+            mailbox,
+            "INBOX".to_string(),
+            NewMessage {
+                flags: vec!["\\Seen".to_string()],
+                ..message("World!")
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(first.uid, 1);
+    assert_eq!(second.uid, 2);
+    assert_eq!(second.size, 6);
+    assert_eq!(second.flags, vec!["\\Seen"]);
+    assert!(second.modseq > first.modseq);
+    assert!(first.modseq > inbox.highest_modseq);
+
+    assert_eq!(
+        storage
+            .read_message(mailbox, "INBOX".to_string(), 2)
+            .await
+            .unwrap()
+            .unwrap(),
+        b"World!".to_vec()
+    );
+    assert_eq!(
+        storage
+            .read_message(mailbox, "INBOX".to_string(), 3)
+            .await
+            .unwrap(),
+        Err(StorageError::MessageNotFound(3))
+    );
+
+    let flagged = storage
+        .set_flags(
+            mailbox,
+            "INBOX".to_string(),
+            1,
+            vec!["\\Seen".to_string(), DELETED_FLAG.to_string()],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(flagged.is_deleted());
+    assert!(flagged.modseq > second.modseq);
+
+    let messages = storage
+        .list_messages(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages, vec![flagged.clone(), second.clone()]);
+
+    assert_eq!(
+        storage
+            .expunge(mailbox, "INBOX".to_string())
+            .await
+            .unwrap()
+            .unwrap(),
+        vec![1]
+    );
+    assert!(!blob_exists(&storage, "Hello"));
+    let inbox = storage.list_folders(mailbox).await.unwrap().unwrap();
+    assert!(inbox[0].highest_modseq > flagged.modseq);
+    // UIDs are never reused
+    assert_eq!(inbox[0].uid_next, 3);
+    let third = storage
+        .append_message(mailbox, "INBOX".to_string(), message("Again"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(third.uid, 3);
+}
+
+#[tokio::test]
+async fn test_shared_blobs() {
+    let files = TestFiles::new();
+    let storage = sqlite_storage(&files).await;
+    let mailbox = Uuid::new_v4();
+    let other_mailbox = Uuid::new_v4();
+    for (mailbox, folder) in [
+        (mailbox, "INBOX"),
+        (mailbox, "Archive"),
+        (other_mailbox, "INBOX"),
+    ] {
+        storage
+            .create_folder(mailbox, folder.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        storage
+            .append_message(mailbox, folder.to_string(), message("Shared"))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    assert!(blob_exists(&storage, "Shared"));
+
+    storage
+        .delete_folder(mailbox, "Archive".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    storage
+        .set_flags(
+            mailbox,
+            "INBOX".to_string(),
+            1,
+            vec![DELETED_FLAG.to_string()],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    storage
+        .expunge(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    // Still referenced by the other mailbox
+    assert!(blob_exists(&storage, "Shared"));
+    assert_eq!(
+        storage
+            .read_message(other_mailbox, "INBOX".to_string(), 1)
+            .await
+            .unwrap()
+            .unwrap(),
+        b"Shared".to_vec()
+    );
+
+    storage
+        .delete_folder(other_mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!blob_exists(&storage, "Shared"));
+}
+
+#[tokio::test]
+async fn test_quota_usage() {
+    let files = TestFiles::new();
+    let storage = sqlite_storage(&files).await;
+    let mailbox = Uuid::new_v4();
+    assert_eq!(
+        storage.quota_usage(mailbox).await.unwrap(),
+        QuotaUsage::default()
+    );
+    for folder in ["INBOX", "Sent"] {
+        storage
+            .create_folder(mailbox, folder.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    for (folder, contents) in [("INBOX", "Hello"), ("INBOX", "World!"), ("Sent", "Hi")] {
+        storage
+            .append_message(mailbox, folder.to_string(), message(contents))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    assert_eq!(
+        storage.quota_usage(mailbox).await.unwrap(),
+        QuotaUsage {
+            storage: 13,
+            messages: 3
+        }
+    );
+    assert_eq!(
+        storage.quota_usage(Uuid::new_v4()).await.unwrap(),
+        QuotaUsage::default()
+    );
+}
+
+#[tokio::test]
+async fn test_deliver_once() {
+    let files = TestFiles::new();
+    let storage = sqlite_storage(&files).await;
+    let members: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+    for member in &members {
+        storage
+            .create_folder(*member, "INBOX".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    let mut deliveries: Vec<Delivery> = members
+        .iter()
+        .map(|member| Delivery {
+            mailbox_id: *member,
+            folder: "INBOX".to_string(),
+        })
+        .collect();
+    deliveries.push(Delivery {
+        mailbox_id: Uuid::new_v4(),
+        folder: "INBOX".to_string(),
+    });
+    let results = storage
+        .deliver_message(deliveries, message("To the list"))
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 4);
+    assert!(results[..3].iter().all(|result| result.is_ok()));
+    assert_eq!(
+        results[3],
+        Err(StorageError::FolderNotFound("INBOX".to_string()))
+    );
+
+    let blobs = BlobEntity::find().all(&storage.database).await.unwrap();
+    assert_eq!(blobs.len(), 1);
+    assert_eq!(blobs[0].hash, BlobStore::hash(b"To the list"));
+    assert_eq!(blobs[0].reference_count, 3);
+    // Each mailbox is still charged for its copy
+    assert_eq!(
+        storage.quota_usage(members[0]).await.unwrap(),
+        QuotaUsage {
+            storage: 11,
+            messages: 1
+        }
+    );
+
+    storage
+        .delete_folder(members[0], "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    let blob = BlobEntity::find_by_id(BlobStore::hash(b"To the list"))
+        .one(&storage.database)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(blob.reference_count, 2);
+    assert!(blob_exists(&storage, "To the list"));
+}
+
+#[tokio::test]
+async fn test_events() {
+    let files = TestFiles::new();
+    let storage = sqlite_storage(&files).await;
+    let mut events = storage.subscribe().expect("SQL storage publishes events");
+    let mailbox = Uuid::new_v4();
+    for name in ["INBOX", "Archive"] {
+        storage
+            .create_folder(mailbox, name.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    let deliveries = ["INBOX", "Missing", "Archive"].map(|folder| Delivery {
+        mailbox_id: mailbox,
+        folder: folder.to_string(),
+    });
+    storage
+        .deliver_message(deliveries.to_vec(), message("Subject: Hello\r\n\r\nHi"))
+        .await
+        .unwrap();
+    // Failed deliveries are not published
+    for folder in ["INBOX", "Archive"] {
+        assert_eq!(
+            events.try_recv().unwrap(),
+            StorageEvent::MessageAppended {
+                mailbox_id: mailbox,
+                folder: folder.to_string(),
+                uid: 1,
+                internal_date: 1_700_000_000,
+            }
+        );
+    }
+
+    storage
+        .set_flags(
+            mailbox,
+            "INBOX".to_string(),
+            1,
+            vec![DELETED_FLAG.to_string()],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    storage
+        .expunge(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        events.try_recv().unwrap(),
+        StorageEvent::MessagesExpunged {
+            mailbox_id: mailbox,
+            folder: "INBOX".to_string(),
+            uids: vec![1],
+        }
+    );
+    // Nothing was expunged
+    storage
+        .expunge(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+
+    storage
+        .delete_folder(mailbox, "Archive".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        events.try_recv().unwrap(),
+        StorageEvent::FolderDeleted {
+            mailbox_id: mailbox,
+            folder: "Archive".to_string(),
+        }
+    );
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_collect_garbage() {
+    let files = TestFiles::new();
+    let storage = sqlite_storage(&files).await;
+    let mailbox = Uuid::new_v4();
+    storage
+        .create_folder(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    storage
+        .append_message(mailbox, "INBOX".to_string(), message("Kept"))
+        .await
+        .unwrap()
+        .unwrap();
+    // Left behind by a delivery that failed after writing its blob
+    storage.blobs.write(b"Orphaned").await.unwrap();
+    // Left behind by a release that failed before deleting the blob
+    let released = storage.blobs.write(b"Released").await.unwrap();
+    BlobEntity::insert(ActiveBlobModel {
+        hash: ActiveValue::Set(released),
+        size: ActiveValue::Set(8),
+        reference_count: ActiveValue::Set(0),
+    })
+    .exec(&storage.database)
+    .await
+    .unwrap();
+
+    let collection = storage.collect_garbage().await.unwrap().unwrap();
+    assert_eq!(
+        collection,
+        GarbageCollection {
+            blobs_removed: 2,
+            bytes_freed: 16,
+            blobs_kept: 1,
+        }
+    );
+    assert!(blob_exists(&storage, "Kept"));
+    assert!(!blob_exists(&storage, "Orphaned"));
+    assert!(!blob_exists(&storage, "Released"));
+    assert_eq!(
+        BlobEntity::find()
+            .all(&storage.database)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+fn encrypting_keyring(master: &EncryptionKey) -> Keyring {
+    Keyring {
+        encrypt_new_mailboxes: true,
+        ..Keyring::new(master.clone())
+    }
+}
+/// The same database and blobs with other keys
+fn with_keyring(storage: &DatabaseStorage, keyring: Keyring) -> DatabaseStorage {
+    DatabaseStorage::new(storage.database.clone(), storage.blobs.root()).with_keyring(keyring)
+}
+
+#[tokio::test]
+async fn test_encrypted_delivery() {
+    let files = TestFiles::new();
+    let master = EncryptionKey::generate();
+    let storage = sqlite_storage(&files)
+        .await
+        .with_keyring(encrypting_keyring(&master));
+    let mailbox = Uuid::new_v4();
+    let other_mailbox = Uuid::new_v4();
+    for mailbox in [mailbox, other_mailbox] {
+        storage
+            .create_folder(mailbox, "INBOX".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    let deliveries = [mailbox, mailbox, other_mailbox]
+        .into_iter()
+        .map(|mailbox_id| Delivery {
+            mailbox_id,
+            folder: "INBOX".to_string(),
+        })
+        .collect();
+    storage
+        .deliver_message(deliveries, message("Secret"))
+        .await
+        .unwrap();
+
+    // Nothing is stored in plain text and each mailbox seals the message with its own key
+    assert!(!blob_exists(&storage, "Secret"));
+    let blobs = BlobEntity::find().all(&storage.database).await.unwrap();
+    assert_eq!(blobs.len(), 2);
+    for blob in &blobs {
+        let stored = storage.blobs.read(&blob.hash).await.unwrap();
+        assert!(!stored.windows(6).any(|window| window == b"Secret"));
+    }
+    assert_eq!(
+        blobs.iter().map(|blob| blob.reference_count).max().unwrap(),
+        2
+    );
+    for (mailbox, uid) in [(mailbox, 2), (other_mailbox, 1)] {
+        assert_eq!(
+            storage
+                .read_message(mailbox, "INBOX".to_string(), uid)
+                .await
+                .unwrap()
+                .unwrap(),
+            b"Secret".to_vec()
+        );
+    }
+    // Quotas count the message and not what sealing adds
+    assert_eq!(
+        storage.quota_usage(mailbox).await.unwrap(),
+        QuotaUsage {
+            storage: 12,
+            messages: 2
+        }
+    );
+
+    // Without the master key the messages can not be read
+    let without_key = DatabaseStorage::new(storage.database.clone(), storage.blobs.root());
+    assert!(matches!(
+        without_key
+            .read_message(mailbox, "INBOX".to_string(), 1)
+            .await,
+        Err(Error::Encryption(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_rotate_mailbox_key() {
+    let files = TestFiles::new();
+    let storage = sqlite_storage(&files).await;
+    let mailbox = Uuid::new_v4();
+    storage
+        .create_folder(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    storage
+        .append_message(mailbox, "INBOX".to_string(), message("Plain"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        storage.rotate_mailbox_key(mailbox).await.unwrap(),
+        Err(StorageError::EncryptionNotConfigured)
+    );
+
+    // Encryption is enabled for the mailbox by giving it its first key
+    let storage = with_keyring(&storage, Keyring::new(EncryptionKey::generate()));
+    assert_eq!(storage.rotate_mailbox_key(mailbox).await.unwrap(), Ok(1));
+    assert!(!blob_exists(&storage, "Plain"));
+    let sealed = BlobEntity::find().all(&storage.database).await.unwrap();
+    assert_eq!(sealed.len(), 1);
+    storage
+        .append_message(mailbox, "INBOX".to_string(), message("Sealed"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!blob_exists(&storage, "Sealed"));
+
+    assert_eq!(storage.rotate_mailbox_key(mailbox).await.unwrap(), Ok(2));
+    let keys = MailboxKeyEntity::find()
+        .all(&storage.database)
+        .await
+        .unwrap();
+    assert_eq!(keys.len(), 1);
+    // Blobs sealed with the old key are gone
+    assert!(storage.blobs.read(&sealed[0].hash).await.is_err());
+    for (uid, contents) in [(1, "Plain"), (2, "Sealed")] {
+        assert_eq!(
+            storage
+                .read_message(mailbox, "INBOX".to_string(), uid)
+                .await
+                .unwrap()
+                .unwrap(),
+            contents.as_bytes().to_vec()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_rotate_master_key() {
+    let files = TestFiles::new();
+    let old_master = EncryptionKey::generate();
+    let storage = sqlite_storage(&files)
+        .await
+        .with_keyring(encrypting_keyring(&old_master));
+    let mailbox = Uuid::new_v4();
+    storage
+        .create_folder(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    storage
+        .append_message(mailbox, "INBOX".to_string(), message("Hello"))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let new_master = EncryptionKey::generate();
+    let storage = with_keyring(
+        &storage,
+        Keyring {
+            previous: vec![old_master],
+            ..encrypting_keyring(&new_master)
+        },
+    );
+    // Readable while the keys are not rewrapped yet
+    assert!(storage
+        .read_message(mailbox, "INBOX".to_string(), 1)
+        .await
+        .unwrap()
+        .is_ok());
+    assert_eq!(storage.rewrap_mailbox_keys().await.unwrap(), 1);
+    assert_eq!(storage.rewrap_mailbox_keys().await.unwrap(), 0);
+
+    let storage = with_keyring(&storage, encrypting_keyring(&new_master));
+    assert_eq!(
+        storage
+            .read_message(mailbox, "INBOX".to_string(), 1)
+            .await
+            .unwrap()
+            .unwrap(),
+        b"Hello".to_vec()
+    );
+}
+
+#[tokio::test]
+async fn test_recover_mailbox_keys() {
+    let files = TestFiles::new();
+    let recovery_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let storage = sqlite_storage(&files).await.with_keyring(Keyring {
+        recovery: Some(RsaPublicKey::from(&recovery_key)),
+        ..encrypting_keyring(&EncryptionKey::generate())
+    });
+    let mailbox = Uuid::new_v4();
+    storage
+        .create_folder(mailbox, "INBOX".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    storage
+        .append_message(mailbox, "INBOX".to_string(), message("Hello"))
+        .await
+        .unwrap()
+        .unwrap();
+
+    // The master key is lost
+    let storage = with_keyring(&storage, encrypting_keyring(&EncryptionKey::generate()));
+    assert!(storage
+        .read_message(mailbox, "INBOX".to_string(), 1)
+        .await
+        .is_err());
+    assert_eq!(
+        storage.recover_mailbox_keys(&recovery_key).await.unwrap(),
+        KeyRecovery {
+            recovered: 1,
+            unrecoverable: vec![],
+        }
+    );
+    assert_eq!(
+        storage
+            .read_message(mailbox, "INBOX".to_string(), 1)
+            .await
+            .unwrap()
+            .unwrap(),
+        b"Hello".to_vec()
+    );
+}
@@ -0,0 +1,259 @@
+//! Message contents stored on the filesystem under the BLAKE3 hash of the contents
+//!
+//! A blob is at `ab/cdef...` where `ab` are the first two hex digits of its hash.
+//! Identical contents are stored once, so a message sent to many mailboxes uses a single blob.
+//!
+//! Storages track what references a blob themselves. A database keeps a reference count and
+//! a Maildir links the blob into every folder it is delivered to, so the filesystem counts the links.
+//! [BlobStore::collect_garbage] removes the blobs neither of them references
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ahash::HashSet;
+use tokio::fs;
+use tracing::warn;
+use uuid::Uuid;
+
+const TEMPORARY_DIRECTORY: &str = "tmp";
+/// Temporary files older than this were left by a write that never finished
+const TEMPORARY_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// What a garbage collection removed
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct GarbageCollection {
+    pub blobs_removed: u64,
+    /// Bytes
+    pub bytes_freed: u64,
+    pub blobs_kept: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    root: PathBuf,
+}
+impl BlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    /// The lowercase hex BLAKE3 hash
+    pub fn hash(contents: &[u8]) -> String {
+        blake3::hash(contents).to_hex().to_string()
+    }
+    pub fn is_hash(hash: &str) -> bool {
+        hash.len() == blake3::OUT_LEN * 2
+            && hash
+                .bytes()
+                .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+    }
+    /// Fails for anything that is not a hash so a path can never leave the root
+    pub fn path(&self, hash: &str) -> io::Result<PathBuf> {
+        if !Self::is_hash(hash) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{hash} is not a blob hash"),
+            ));
+        }
+        Ok(self.root.join(&hash[..2]).join(&hash[2..]))
+    }
+
+    /// Returns the hash. Contents are written to a temporary file and renamed so a blob is never seen half written
+    pub async fn write(&self, contents: &[u8]) -> io::Result<String> {
+        let hash = Self::hash(contents);
+        let path = self.path(&hash)?;
+        if fs::try_exists(&path).await? {
+            return Ok(hash);
+        }
+        let temporary_directory = self.root.join(TEMPORARY_DIRECTORY);
+        fs::create_dir_all(&temporary_directory).await?;
+        let temporary = temporary_directory.join(Uuid::new_v4().to_string());
+        fs::write(&temporary, contents).await?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        if let Err(error) = fs::rename(&temporary, &path).await {
+            let _ = fs::remove_file(&temporary).await;
+            return Err(error);
+        }
+        Ok(hash)
+    }
+
+    pub async fn read(&self, hash: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(hash)?).await
+    }
+
+    /// Hard links the blob to the destination. For example the `new` directory of a Maildir folder
+    ///
+    /// The destination has to be on the same filesystem as the store
+    pub async fn link(&self, hash: &str, destination: &Path) -> io::Result<()> {
+        fs::hard_link(self.path(hash)?, destination).await
+    }
+
+    /// Returns false if the blob did not exist
+    pub async fn delete(&self, hash: &str) -> io::Result<bool> {
+        match fs::remove_file(self.path(hash)?).await {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Removes every blob that is not in `referenced` and not linked anywhere else.
+    /// Temporary files of writes that never finished are removed too
+    ///
+    /// Nothing may write a blob it is about to reference while this runs
+    pub async fn collect_garbage(
+        &self,
+        referenced: &HashSet<String>,
+    ) -> io::Result<GarbageCollection> {
+        let mut collection = GarbageCollection::default();
+        let mut directories = match fs::read_dir(&self.root).await {
+            Ok(ok) => ok,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(collection),
+            Err(error) => return Err(error),
+        };
+        while let Some(directory) = directories.next_entry().await? {
+            let prefix = directory.file_name().to_string_lossy().to_string();
+            if prefix == TEMPORARY_DIRECTORY {
+                self.remove_stale_temporary_files(&directory.path()).await?;
+                continue;
+            }
+            if !directory.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut blobs = fs::read_dir(directory.path()).await?;
+            while let Some(blob) = blobs.next_entry().await? {
+                let hash = format!("{prefix}{}", blob.file_name().to_string_lossy());
+                if !Self::is_hash(&hash) {
+                    warn!(path = ?blob.path(), "Found a file that is not a blob");
+                    continue;
+                }
+                let metadata = blob.metadata().await?;
+                if referenced.contains(&hash) || link_count(&metadata) > 1 {
+                    collection.blobs_kept += 1;
+                    continue;
+                }
+                fs::remove_file(blob.path()).await?;
+                collection.blobs_removed += 1;
+                collection.bytes_freed += metadata.len();
+            }
+        }
+        Ok(collection)
+    }
+    async fn remove_stale_temporary_files(&self, directory: &Path) -> io::Result<()> {
+        let mut files = fs::read_dir(directory).await?;
+        while let Some(file) = files.next_entry().await? {
+            let age = file
+                .metadata()
+                .await?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default();
+            if age > TEMPORARY_LIFETIME {
+                fs::remove_file(file.path()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &std::fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::nlink(metadata)
+}
+/// Only unix counts links. Elsewhere the store references blobs itself
+#[cfg(not(unix))]
+fn link_count(_metadata: &std::fs::Metadata) -> u64 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use ahash::{HashSet, HashSetExt};
+    use uuid::Uuid;
+
+    use crate::blob_store::{BlobStore, GarbageCollection};
+
+    struct TestFiles(PathBuf);
+    impl TestFiles {
+        fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("nitro_mail_blob_store_{}", Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+    impl Drop for TestFiles {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_blob_store() {
+        let files = TestFiles::new();
+        let blobs = BlobStore::new(files.0.join("blobs"));
+        assert!(blobs.read("../../etc/passwd").await.is_err());
+        let hash = blobs.write(b"Hello").await.unwrap();
+        assert_eq!(hash, BlobStore::hash(b"Hello"));
+        assert_eq!(blobs.write(b"Hello").await.unwrap(), hash);
+        assert_eq!(blobs.read(&hash).await.unwrap(), b"Hello".to_vec());
+        assert!(blobs.delete(&hash).await.unwrap());
+        assert!(!blobs.delete(&hash).await.unwrap());
+    }
+
+    #[tokio::test]
+    pub async fn test_garbage_collection() {
+        let files = TestFiles::new();
+        let blobs = BlobStore::new(files.0.join("blobs"));
+        let referenced = blobs.write(b"Referenced").await.unwrap();
+        let linked = blobs.write(b"Linked").await.unwrap();
+        let unreferenced = blobs.write(b"Unreferenced").await.unwrap();
+
+        // A Maildir folder holding the message the same way a delivery would
+        let maildir = files.0.join("maildir").join("new");
+        std::fs::create_dir_all(&maildir).unwrap();
+        let message = maildir.join("1700000000.1.example.org");
+        blobs.link(&linked, &message).await.unwrap();
+        assert_eq!(std::fs::read(&message).unwrap(), b"Linked".to_vec());
+
+        let collection = blobs
+            .collect_garbage(&HashSet::from_iter([referenced.clone()]))
+            .await
+            .unwrap();
+        assert_eq!(
+            collection,
+            GarbageCollection {
+                blobs_removed: 1,
+                bytes_freed: 12,
+                blobs_kept: 2,
+            }
+        );
+        assert!(blobs.read(&referenced).await.is_ok());
+        assert!(blobs.read(&linked).await.is_ok());
+        assert!(blobs.read(&unreferenced).await.is_err());
+
+        // Once the message is expunged from the Maildir the blob is garbage
+        std::fs::remove_file(&message).unwrap();
+        let collection = blobs.collect_garbage(&HashSet::new()).await.unwrap();
+        assert_eq!(collection.blobs_removed, 2);
+    }
+}
@@ -0,0 +1,360 @@
+//! Encryption at rest of message contents
+//!
+//! Every encrypted mailbox has a data key that seals its messages with XChaCha20-Poly1305.
+//! Data keys are stored wrapped by the master key of the server, and optionally by a recovery
+//! public key whose private key is kept offline.
+//!
+//! The nonce of a message is derived from its contents with a subkey of the data key.
+//! So the same message stored twice in a mailbox is sealed the same way and its blob is shared,
+//! while copies in other mailboxes are sealed under their own keys.
+//! This reveals which messages of a mailbox are identical to anyone who can read the blobs
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::rngs::OsRng;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+use utils::configs::PathOrType;
+
+const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 24;
+
+#[derive(Debug, Error)]
+pub enum EncryptionError {
+    #[error("Keys must be 32 bytes encoded as base64")]
+    InvalidKey,
+    #[error("Unable to load key: {0}")]
+    LoadingKey(String),
+    #[error("Unable to decrypt. The data is damaged or the key is wrong")]
+    Decryption,
+    #[error("No master key is configured")]
+    MissingMasterKey,
+    #[error("The data key is wrapped by master key {0} which is not configured")]
+    UnknownMasterKey(String),
+    #[error("The data key has no recovery copy")]
+    MissingRecoveryKey,
+    #[error(transparent)]
+    Rsa(#[from] rsa::Error),
+}
+
+/// An XChaCha20-Poly1305 key
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey(Key);
+impl EncryptionKey {
+    pub fn generate() -> Self {
+        EncryptionKey(XChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+    fn from_bytes(key: &[u8]) -> Result<Self, EncryptionError> {
+        if key.len() != KEY_LENGTH {
+            return Err(EncryptionError::InvalidKey);
+        }
+        Ok(EncryptionKey(*Key::from_slice(key)))
+    }
+    /// Parses 32 bytes encoded as base64
+    pub fn from_base64(key: &str) -> Result<Self, EncryptionError> {
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|_| EncryptionError::InvalidKey)?;
+        Self::from_bytes(&key)
+    }
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(self.0)
+    }
+    /// Names the key without revealing it. Wrapped data keys record the fingerprint of their master key
+    pub fn fingerprint(&self) -> String {
+        let hash = blake3::derive_key("nitro_mail key fingerprint", &self.0);
+        hash[..8].iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Returns the random nonce followed by the ciphertext
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        self.seal_with_nonce(&XChaCha20Poly1305::generate_nonce(&mut OsRng), plaintext)
+    }
+    /// Like [seal](Self::seal) but the nonce is a keyed hash of the plaintext.
+    /// The hash is keyed with a subkey so the data key is never used for anything but sealing
+    ///
+    /// Identical plaintexts give identical results. Anyone who can read the sealed data learns
+    /// which messages of a mailbox are identical, but nothing else about them
+    pub fn seal_deterministic(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce_key = blake3::derive_key("nitro_mail message nonce", &self.0);
+        let hash = blake3::keyed_hash(&nonce_key, plaintext);
+        self.seal_with_nonce(
+            XNonce::from_slice(&hash.as_bytes()[..NONCE_LENGTH]),
+            plaintext,
+        )
+    }
+    fn seal_with_nonce(&self, nonce: &XNonce, plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = XChaCha20Poly1305::new(&self.0)
+            .encrypt(nonce, plaintext)
+            .expect("Encrypting into a Vec can not fail");
+        let mut result = nonce.to_vec();
+        result.extend(ciphertext);
+        result
+    }
+    pub fn open(&self, data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        if data.len() < NONCE_LENGTH {
+            return Err(EncryptionError::Decryption);
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+        XChaCha20Poly1305::new(&self.0)
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| EncryptionError::Decryption)
+    }
+}
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EncryptionKey({})", self.fingerprint())
+    }
+}
+
+/// A data key as it is stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrappedKey {
+    /// The fingerprint of the master key it is wrapped by
+    pub master_key: String,
+    /// Base64
+    pub wrapped: String,
+    /// Wrapped by the recovery public key. Base64
+    pub recovery: Option<String>,
+}
+
+/// A master key file
+///
+/// # Example
+/// ```toml
+/// key = "<BASE64_KEY>"
+/// ```
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyFile {
+    pub key: String,
+}
+impl Debug for KeyFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KeyFile(..)")
+    }
+}
+impl KeyFile {
+    fn load(key: &PathOrType<KeyFile>) -> Result<EncryptionKey, EncryptionError> {
+        let file = key
+            .clone()
+            .get_value_toml()
+            .map_err(|error| EncryptionError::LoadingKey(error.to_string()))?;
+        EncryptionKey::from_base64(&file.key)
+    }
+}
+
+/// Encryption at rest. Mailboxes stay unencrypted until they are given a data key
+///
+/// Keys are best kept in their own files only nitro_mail can read. `nitro-admin encryption generate-key` creates them.
+///
+/// # Rotating the master key
+/// 1. Generate a new key and make it `master_key`. Move the old one to `previous_master_keys`
+/// 2. Restart. Data keys wrapped by the old key can still be read
+/// 3. Run `nitro-admin encryption rotate-master-key` to wrap every data key with the new key
+/// 4. Remove the old key from `previous_master_keys`
+///
+/// # Recovery
+/// With `recovery_public_key` set every data key is also wrapped by that RSA key.
+/// `nitro-admin encryption generate-recovery-key` creates the pair. Keep the private key offline.
+/// If the master key is lost configure a new one and run `nitro-admin encryption recover` with the private key.
+/// Data keys created before the recovery key was set are only covered once they are rewrapped by a rotation
+///
+/// # Example
+/// ```toml
+/// [encryption]
+/// encrypt_new_mailboxes = true
+/// master_key = "/etc/nitro_mail/keys/master.toml"
+/// previous_master_keys = []
+/// recovery_public_key = "/etc/nitro_mail/keys/recovery.pub.pem"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// Mailboxes are given a data key when the first message is stored for them
+    #[serde(default)]
+    pub encrypt_new_mailboxes: bool,
+    #[serde(default)]
+    pub master_key: Option<PathOrType<KeyFile>>,
+    /// Only used to read data keys that were not rewrapped yet
+    #[serde(default)]
+    pub previous_master_keys: Vec<PathOrType<KeyFile>>,
+    /// A PEM encoded RSA public key
+    #[serde(default)]
+    pub recovery_public_key: Option<PathBuf>,
+}
+impl EncryptionConfig {
+    /// None if no master key is configured
+    pub fn keyring(&self) -> Result<Option<Keyring>, EncryptionError> {
+        let Some(master_key) = &self.master_key else {
+            if self.encrypt_new_mailboxes {
+                return Err(EncryptionError::MissingMasterKey);
+            }
+            return Ok(None);
+        };
+        let previous = self
+            .previous_master_keys
+            .iter()
+            .map(KeyFile::load)
+            .collect::<Result<_, _>>()?;
+        let recovery = match &self.recovery_public_key {
+            Some(path) => {
+                let pem = std::fs::read_to_string(path)
+                    .map_err(|error| EncryptionError::LoadingKey(error.to_string()))?;
+                Some(
+                    RsaPublicKey::from_public_key_pem(&pem)
+                        .map_err(|error| EncryptionError::LoadingKey(error.to_string()))?,
+                )
+            }
+            None => None,
+        };
+        Ok(Some(Keyring {
+            encrypt_new_mailboxes: self.encrypt_new_mailboxes,
+            master: KeyFile::load(master_key)?,
+            previous,
+            recovery,
+        }))
+    }
+}
+
+/// The keys that wrap data keys
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    pub encrypt_new_mailboxes: bool,
+    pub master: EncryptionKey,
+    pub previous: Vec<EncryptionKey>,
+    pub recovery: Option<RsaPublicKey>,
+}
+impl Keyring {
+    pub fn new(master: EncryptionKey) -> Self {
+        Keyring {
+            encrypt_new_mailboxes: false,
+            master,
+            previous: vec![],
+            recovery: None,
+        }
+    }
+    pub fn wrap(&self, data_key: &EncryptionKey) -> Result<WrappedKey, EncryptionError> {
+        let recovery = match &self.recovery {
+            Some(recovery) => {
+                let wrapped = recovery.encrypt(&mut OsRng, Oaep::new::<Sha256>(), &data_key.0)?;
+                Some(STANDARD.encode(wrapped))
+            }
+            None => None,
+        };
+        Ok(WrappedKey {
+            master_key: self.master.fingerprint(),
+            wrapped: STANDARD.encode(self.master.seal(&data_key.0)),
+            recovery,
+        })
+    }
+    pub fn unwrap(&self, wrapped: &WrappedKey) -> Result<EncryptionKey, EncryptionError> {
+        let master = std::iter::once(&self.master)
+            .chain(&self.previous)
+            .find(|key| key.fingerprint() == wrapped.master_key)
+            .ok_or_else(|| EncryptionError::UnknownMasterKey(wrapped.master_key.clone()))?;
+        let sealed = STANDARD
+            .decode(&wrapped.wrapped)
+            .map_err(|_| EncryptionError::Decryption)?;
+        EncryptionKey::from_bytes(&master.open(&sealed)?)
+    }
+    /// Whether the data key should be wrapped again. For example after the master key changed
+    pub fn needs_rewrap(&self, wrapped: &WrappedKey) -> bool {
+        wrapped.master_key != self.master.fingerprint()
+            || (self.recovery.is_some() && wrapped.recovery.is_none())
+    }
+}
+
+/// Unwraps the recovery copy of a data key with the offline private key
+pub fn recover_data_key(
+    recovery_key: &RsaPrivateKey,
+    wrapped: &WrappedKey,
+) -> Result<EncryptionKey, EncryptionError> {
+    let recovery = wrapped
+        .recovery
+        .as_deref()
+        .ok_or(EncryptionError::MissingRecoveryKey)?;
+    let sealed = STANDARD
+        .decode(recovery)
+        .map_err(|_| EncryptionError::Decryption)?;
+    EncryptionKey::from_bytes(&recovery_key.decrypt(Oaep::new::<Sha256>(), &sealed)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rsa::{RsaPrivateKey, RsaPublicKey};
+
+    use crate::encryption::{recover_data_key, EncryptionError, EncryptionKey, Keyring};
+
+    #[test]
+    pub fn test_seal() {
+        let key = EncryptionKey::generate();
+        let sealed = key.seal(b"Hello");
+        assert_ne!(key.seal(b"Hello"), sealed);
+        assert_eq!(key.open(&sealed).unwrap(), b"Hello".to_vec());
+        assert!(EncryptionKey::generate().open(&sealed).is_err());
+
+        let deterministic = key.seal_deterministic(b"Hello");
+        assert_eq!(key.seal_deterministic(b"Hello"), deterministic);
+        assert_ne!(key.seal_deterministic(b"World"), deterministic);
+        assert_ne!(
+            EncryptionKey::generate().seal_deterministic(b"Hello"),
+            deterministic
+        );
+        assert_eq!(key.open(&deterministic).unwrap(), b"Hello".to_vec());
+        // The nonce is not keyed with the data key itself
+        let data_key_hash = blake3::keyed_hash(&key.0.into(), b"Hello");
+        assert_ne!(deterministic[..24], data_key_hash.as_bytes()[..24]);
+
+        assert_eq!(EncryptionKey::from_base64(&key.to_base64()).unwrap(), key);
+        assert!(EncryptionKey::from_base64("c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    pub fn test_keyring() {
+        let old_master = EncryptionKey::generate();
+        let data_key = EncryptionKey::generate();
+        let old_keyring = Keyring::new(old_master.clone());
+        let wrapped = old_keyring.wrap(&data_key).unwrap();
+        assert_eq!(wrapped.master_key, old_master.fingerprint());
+        assert_eq!(old_keyring.unwrap(&wrapped).unwrap(), data_key);
+
+        // The new master key can still read data keys wrapped by the old one
+        let mut keyring = Keyring::new(EncryptionKey::generate());
+        assert!(matches!(
+            keyring.unwrap(&wrapped),
+            Err(EncryptionError::UnknownMasterKey(_))
+        ));
+        keyring.previous.push(old_master);
+        assert!(keyring.needs_rewrap(&wrapped));
+        assert_eq!(keyring.unwrap(&wrapped).unwrap(), data_key);
+        let rewrapped = keyring.wrap(&data_key).unwrap();
+        assert!(!keyring.needs_rewrap(&rewrapped));
+        assert_eq!(
+            Keyring::new(keyring.master.clone())
+                .unwrap(&rewrapped)
+                .unwrap(),
+            data_key
+        );
+    }
+
+    #[test]
+    pub fn test_recovery() {
+        let recovery_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let mut keyring = Keyring::new(EncryptionKey::generate());
+        let data_key = EncryptionKey::generate();
+        assert!(matches!(
+            recover_data_key(&recovery_key, &keyring.wrap(&data_key).unwrap()),
+            Err(EncryptionError::MissingRecoveryKey)
+        ));
+        keyring.recovery = Some(RsaPublicKey::from(&recovery_key));
+        let wrapped = keyring.wrap(&data_key).unwrap();
+        assert_eq!(recover_data_key(&recovery_key, &wrapped).unwrap(), data_key);
+    }
+}
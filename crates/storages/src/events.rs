@@ -0,0 +1,53 @@
+//! Changes to stored messages that other parts of the server follow. For example the search index
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use uuid::Uuid;
+
+/// Events not received by then are lost. Subscribers are told how many they missed
+pub const EVENT_CAPACITY: usize = 1024;
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub enum StorageEvent {
+    MessageAppended {
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+        /// Unix seconds
+        internal_date: i64,
+    },
+    MessagesExpunged {
+        mailbox_id: Uuid,
+        folder: String,
+        uids: Vec<u32>,
+    },
+    /// The messages of the folder are gone with it
+    FolderDeleted { mailbox_id: Uuid, folder: String },
+}
+
+/// Publishes the events of a storage to every subscriber
+#[derive(Debug, Clone)]
+pub struct StorageEvents(Sender<StorageEvent>);
+impl Default for StorageEvents {
+    fn default() -> Self {
+        Self(broadcast::channel(EVENT_CAPACITY).0)
+    }
+}
+impl StorageEvents {
+    /// Nothing happens if no one is subscribed
+    pub fn publish(&self, event: StorageEvent) {
+        let _ = self.0.send(event);
+    }
+    pub fn subscribe(&self) -> Receiver<StorageEvent> {
+        self.0.subscribe()
+    }
+}
@@ -0,0 +1,133 @@
+//! The folders of a mailbox and the messages in them. IMAP calls folders mailboxes
+//!
+//! A mailbox is the `mailbox_id` of an account or group. Folders are found by name within it
+use thiserror::Error;
+use uuid::Uuid;
+
+/// The flag of messages that are removed on expunge
+pub const DELETED_FLAG: &str = "\\Deleted";
+/// The folder incoming mail is delivered to
+pub const INBOX: &str = "INBOX";
+
+/// Errors about the request itself. Failures of the storage are its service errors
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Error,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub enum StorageError {
+    #[error("The storage does not support this")]
+    NotSupported,
+    #[error("Folder {0} already exists")]
+    FolderExists(String),
+    #[error("Folder {0} does not exist")]
+    FolderNotFound(String),
+    #[error("{0} can not be used as a folder name")]
+    InvalidFolderName(String),
+    #[error("Message {0} does not exist")]
+    MessageNotFound(u32),
+    #[error("Encryption is not configured")]
+    EncryptionNotConfigured,
+    #[error("The search index failed: {0}")]
+    SearchIndex(String),
+}
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct Folder {
+    pub name: String,
+    /// Changes if the UIDs of the folder are reused. For example when it is deleted and created again
+    pub uid_validity: u32,
+    /// The UID the next message is given
+    pub uid_next: u32,
+    /// The modseq of the last change. See [RFC 7162](https://www.rfc-editor.org/rfc/rfc7162)
+    pub highest_modseq: u64,
+}
+
+/// A message to store
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct NewMessage {
+    /// System flags such as `\Seen` and keywords
+    pub flags: Vec<String>,
+    /// Unix seconds
+    pub internal_date: i64,
+    /// The raw message
+    pub contents: Vec<u8>,
+}
+
+/// Where a delivered message is stored
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct Delivery {
+    pub mailbox_id: Uuid,
+    pub folder: String,
+}
+
+/// A stored message without its contents
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct MessageInfo {
+    pub uid: u32,
+    /// The modseq of the last change to the message
+    pub modseq: u64,
+    pub flags: Vec<String>,
+    /// Bytes
+    pub size: u64,
+    /// Unix seconds
+    pub internal_date: i64,
+}
+impl MessageInfo {
+    pub fn is_deleted(&self) -> bool {
+        self.flags.iter().any(|flag| flag == DELETED_FLAG)
+    }
+}
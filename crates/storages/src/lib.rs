@@ -1,4 +1,13 @@
-mod storage_service;
+pub mod blob_store;
+pub mod encryption;
+pub mod events;
+pub mod folders;
+pub mod storage_service;
+pub mod search;
 pub mod storage_type;
 
 pub const SOCKET_NAME: &str = "nitro_mail_storage_service";
+/// The version of the packets exchanged with the storage service
+///
+/// Bump it whenever a packet or a type it carries, such as [NewMessage](folders::NewMessage), changes
+pub const PROTOCOL_VERSION: u32 = 3;
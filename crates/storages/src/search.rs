@@ -0,0 +1,109 @@
+//! Searches of the messages in a folder. Built from IMAP SEARCH keys and JMAP Email/query filters
+//!
+//! Text is matched as a substring, case insensitively, like IMAP SEARCH.
+//! `SUBJECT "meet"` finds "Meeting notes" but `SUBJECT "notes meeting"` does not
+
+/// Seconds in a day. IMAP dates have no time so ranges are whole days
+pub const DAY: i64 = 86_400;
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(
+    compare(PartialEq),
+    check_bytes,
+    bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer")
+)]
+#[archive_attr(check_bytes(
+    bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: rkyv::bytecheck::Error"
+))]
+pub enum SearchQuery {
+    All,
+    From(String),
+    To(String),
+    Cc(String),
+    Bcc(String),
+    Subject(String),
+    /// A header by name containing the value. An empty value matches messages with the header
+    Header(String, String),
+    /// The decoded text bodies. HTML bodies are converted to text
+    Body(String),
+    /// Headers, bodies and attachment filenames
+    Text(String),
+    /// Attachment filenames
+    Attachment(String),
+    /// Received before the unix time
+    ReceivedBefore(i64),
+    /// Received at or after the unix time
+    ReceivedSince(i64),
+    /// The `Date` header is before the unix time
+    SentBefore(i64),
+    /// The `Date` header is at or after the unix time
+    SentSince(i64),
+    And(
+        #[omit_bounds]
+        #[archive_attr(omit_bounds)]
+        Vec<SearchQuery>,
+    ),
+    Or(
+        #[omit_bounds]
+        #[archive_attr(omit_bounds)]
+        Vec<SearchQuery>,
+    ),
+    Not(
+        #[omit_bounds]
+        #[archive_attr(omit_bounds)]
+        Box<SearchQuery>,
+    ),
+}
+/// The result of indexing every message of a mailbox again
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct Rebuild {
+    pub folders: u64,
+    pub messages: u64,
+}
+
+impl SearchQuery {
+    /// Combines the queries. A single query is returned as is
+    pub fn and(mut queries: Vec<SearchQuery>) -> SearchQuery {
+        match queries.len() {
+            0 => SearchQuery::All,
+            1 => queries.remove(0),
+            _ => SearchQuery::And(queries),
+        }
+    }
+    /// Received on the day starting at the unix time
+    pub fn received_on(day: i64) -> SearchQuery {
+        SearchQuery::And(vec![
+            SearchQuery::ReceivedSince(day),
+            SearchQuery::ReceivedBefore(day + DAY),
+        ])
+    }
+    /// Sent on the day starting at the unix time
+    pub fn sent_on(day: i64) -> SearchQuery {
+        SearchQuery::And(vec![
+            SearchQuery::SentSince(day),
+            SearchQuery::SentBefore(day + DAY),
+        ])
+    }
+}
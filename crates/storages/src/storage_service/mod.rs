@@ -2,7 +2,118 @@ use std::error::Error;
 use std::io;
 use std::ops::DerefMut;
 
-use futures_lite::{AsyncReadExt, AsyncWriteExt, FutureExt};
+use bytes::BytesMut;
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
+use interprocess::local_socket::tokio::{LocalSocketListener, LocalSocketStream};
+use tracing::{error, info, trace};
+
+use crate::storage_service::packets::{FromServicePackets, ToServicePackets};
+use crate::storage_type::Storage;
+use crate::{PROTOCOL_VERSION, SOCKET_NAME};
 
 pub mod packets;
 pub mod storage_service_storage;
+
+/// Serves a storage to the other processes over a local socket
+pub struct StorageService<S: Storage + Clone> {
+    storage: S,
+}
+impl<S: Storage + Clone> StorageService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    pub async fn run(self) {
+        info!("Starting storage service for {}", S::storage_name());
+        let listener = LocalSocketListener::bind(SOCKET_NAME).expect("Failed to bind socket");
+        self.serve(listener).await;
+    }
+
+    /// Accepts connections until the listener fails
+    pub async fn serve(self, listener: LocalSocketListener) {
+        while let Ok(request) = listener.accept().await {
+            let storage = self.storage.clone();
+            trace!("Accepted connection");
+            tokio::spawn(async move {
+                if let Err(error) = Self::handle_client(storage, request).await {
+                    info!("Error handling client: {}", error);
+                };
+            });
+        }
+    }
+    async fn write(
+        connection: &mut LocalSocketStream,
+        packet: FromServicePackets,
+    ) -> io::Result<()> {
+        let result = rkyv::to_bytes::<_, 256>(&packet);
+        match result {
+            Ok(ok) => {
+                connection
+                    .write_all(&(ok.len() as u32).to_be_bytes())
+                    .await?;
+                connection.write_all(&ok).await
+            }
+            Err(err) => {
+                error!("Failed to serialize packet: {}", err);
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn handle_client(
+        storage: S,
+        mut stream: LocalSocketStream,
+    ) -> Result<(), anyhow::Error> {
+        let mut number_buffer = [0; 4];
+        // The client sends its protocol version first. The service answers with its own
+        stream.read_exact(&mut number_buffer).await?;
+        let client_version = u32::from_be_bytes(number_buffer);
+        stream.write_all(&PROTOCOL_VERSION.to_be_bytes()).await?;
+        if client_version != PROTOCOL_VERSION {
+            anyhow::bail!(
+                "Client speaks protocol version {}. Expected {}",
+                client_version,
+                PROTOCOL_VERSION
+            );
+        }
+        let mut buffer = BytesMut::new();
+        loop {
+            stream.read_exact(&mut number_buffer).await?;
+            let packet_size = u32::from_be_bytes(number_buffer);
+            buffer.resize(packet_size as usize, 0);
+            trace!("Reading packet of size {}", packet_size);
+            stream.read_exact(buffer.deref_mut()).await?;
+            let packet =
+                match rkyv::from_bytes::<ToServicePackets>(&buffer).map_err(|e| e.to_string()) {
+                    Ok(ok) => ok,
+                    Err(err) => {
+                        error!("Failed to deserialize packet: {}", err);
+                        // The client waits for an answer to every packet
+                        let error = FromServicePackets::InternalStorageError(err);
+                        Self::write(&mut stream, error).await?;
+                        continue;
+                    }
+                };
+            match packet.handle::<S>(&storage).await {
+                Ok(ok) => {
+                    Self::write(&mut stream, ok).await?;
+                }
+                Err(err) => {
+                    error!("Failed to handle packet: {}", err);
+                    Self::handle_storage_error(&mut stream, err).await?;
+                }
+            }
+        }
+    }
+
+    async fn handle_storage_error(
+        connection: &mut LocalSocketStream,
+        error: impl Error,
+    ) -> io::Result<()> {
+        Self::write(
+            connection,
+            FromServicePackets::InternalStorageError(error.to_string()),
+        )
+        .await
+    }
+}
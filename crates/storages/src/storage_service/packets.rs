@@ -1,13 +1,131 @@
 use rkyv::{Archive, Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Archive)]
+use helper_macros::ToServicePacket;
+use utils::quota::QuotaUsage;
+
+use crate::blob_store::GarbageCollection;
+use crate::folders::{Delivery, Folder, MessageInfo, NewMessage, StorageResult};
+use crate::search::{Rebuild, SearchQuery};
+use crate::storage_type::Storage;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Archive, ToServicePacket)]
 #[archive(compare(PartialEq), check_bytes)]
+#[non_exhaustive]
+#[service_packet(
+service_type = Storage,
+from_service_type = FromServicePackets,
+)]
 pub enum ToServicePackets {
-    TODO,
+    #[packet(
+    service_method = Storage::quota_usage,
+    from_service_variant = FromServicePackets::QuotaUsage
+    )]
+    QuotaUsage(Uuid),
+    #[packet(
+    service_method = Storage::create_folder,
+    from_service_variant = FromServicePackets::CreateFolder
+    )]
+    CreateFolder { mailbox_id: Uuid, name: String },
+    #[packet(
+    service_method = Storage::list_folders,
+    from_service_variant = FromServicePackets::ListFolders
+    )]
+    ListFolders(Uuid),
+    #[packet(
+    service_method = Storage::delete_folder,
+    from_service_variant = FromServicePackets::DeleteFolder
+    )]
+    DeleteFolder { mailbox_id: Uuid, name: String },
+    #[packet(
+    service_method = Storage::append_message,
+    from_service_variant = FromServicePackets::AppendMessage
+    )]
+    AppendMessage {
+        mailbox_id: Uuid,
+        folder: String,
+        message: NewMessage,
+    },
+    #[packet(
+    service_method = Storage::deliver_message,
+    from_service_variant = FromServicePackets::DeliverMessage
+    )]
+    DeliverMessage {
+        deliveries: Vec<Delivery>,
+        message: NewMessage,
+    },
+    #[packet(
+    service_method = Storage::list_messages,
+    from_service_variant = FromServicePackets::ListMessages
+    )]
+    ListMessages { mailbox_id: Uuid, folder: String },
+    #[packet(
+    service_method = Storage::read_message,
+    from_service_variant = FromServicePackets::ReadMessage
+    )]
+    ReadMessage {
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+    },
+    #[packet(
+    service_method = Storage::set_flags,
+    from_service_variant = FromServicePackets::SetFlags
+    )]
+    SetFlags {
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+        flags: Vec<String>,
+    },
+    #[packet(
+    service_method = Storage::expunge,
+    from_service_variant = FromServicePackets::Expunge
+    )]
+    Expunge { mailbox_id: Uuid, folder: String },
+    #[packet(
+    service_method = Storage::collect_garbage,
+    from_service_variant = FromServicePackets::CollectGarbage
+    )]
+    CollectGarbage,
+    #[packet(
+    service_method = Storage::rotate_mailbox_key,
+    from_service_variant = FromServicePackets::RotateMailboxKey
+    )]
+    RotateMailboxKey(Uuid),
+    #[packet(
+    service_method = Storage::search,
+    from_service_variant = FromServicePackets::Search
+    )]
+    Search {
+        mailbox_id: Uuid,
+        folder: String,
+        query: SearchQuery,
+    },
+    #[packet(
+    service_method = Storage::rebuild_search_index,
+    from_service_variant = FromServicePackets::RebuildSearchIndex
+    )]
+    RebuildSearchIndex(Uuid),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Archive)]
 #[archive(compare(PartialEq), check_bytes)]
+#[non_exhaustive]
 pub enum FromServicePackets {
-    TODO,
+    QuotaUsage(QuotaUsage),
+    CreateFolder(StorageResult<Folder>),
+    ListFolders(StorageResult<Vec<Folder>>),
+    DeleteFolder(StorageResult<bool>),
+    AppendMessage(StorageResult<MessageInfo>),
+    DeliverMessage(Vec<StorageResult<MessageInfo>>),
+    ListMessages(StorageResult<Vec<MessageInfo>>),
+    ReadMessage(StorageResult<Vec<u8>>),
+    SetFlags(StorageResult<MessageInfo>),
+    Expunge(StorageResult<Vec<u32>>),
+    CollectGarbage(StorageResult<GarbageCollection>),
+    RotateMailboxKey(StorageResult<u64>),
+    Search(StorageResult<Vec<u32>>),
+    RebuildSearchIndex(StorageResult<Rebuild>),
+    InternalStorageError(String),
 }
@@ -1,11 +1,29 @@
+use std::future::Future;
 use std::io;
+use std::io::ErrorKind;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::Arc;
 
-use futures_lite::AsyncWriteExt as FuturesLightAsyncWriteExt;
+use async_trait::async_trait;
+use bytes::BytesMut;
+use futures_lite::{
+    AsyncReadExt as FuturesLiteAsyncRead, AsyncWriteExt as FuturesLightAsyncWriteExt,
+};
+use interprocess::local_socket::tokio::LocalSocketStream;
 use thiserror::Error;
+use tracing::trace;
 use utils::interprocess_guard::InterprocessConnectionInner;
-use utils::service::Service;
+use utils::quota::QuotaUsage;
+use utils::service::{Service, ServiceAccess};
+use uuid::Uuid;
 
+use crate::blob_store::GarbageCollection;
+use crate::folders::{Delivery, Folder, MessageInfo, NewMessage, StorageResult};
+use crate::search::{Rebuild, SearchQuery};
+use crate::storage_service::packets::{FromServicePackets, ToServicePackets};
 use crate::storage_type::Storage;
+use crate::{PROTOCOL_VERSION, SOCKET_NAME};
 
 #[derive(Debug, Error)]
 pub enum StorageServiceError {
@@ -13,24 +31,320 @@ pub enum StorageServiceError {
     Service(String),
     #[error(transparent)]
     Connection(#[from] io::Error),
+    #[error("The storage service speaks protocol version {service}. Expected {client}")]
+    ProtocolMismatch { client: u32, service: u32 },
 }
 
-pub struct StorageService(InterprocessConnectionInner);
+/// A connection to a [StorageService](crate::storage_service::StorageService)
+pub struct StorageServiceStorage {
+    connection: Arc<InterprocessConnectionInner>,
+    socket_name: String,
+}
+impl Deref for StorageServiceStorage {
+    type Target = InterprocessConnectionInner;
+    fn deref(&self) -> &Self::Target {
+        &self.connection
+    }
+}
+#[derive(Clone, Debug)]
+pub struct StorageServiceStorageAccess {
+    pub socket_name: String,
+}
+impl Default for StorageServiceStorageAccess {
+    fn default() -> Self {
+        Self {
+            socket_name: SOCKET_NAME.to_string(),
+        }
+    }
+}
+
+impl ServiceAccess for StorageServiceStorageAccess {
+    type ServiceResponse = StorageServiceStorage;
+    type Error = StorageServiceError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<StorageServiceStorage, StorageServiceError>> + Send>>;
+
+    fn get_service(&self) -> Self::Future {
+        let socket_name = self.socket_name.clone();
+        Box::pin(async move { StorageServiceStorage::connect(socket_name).await })
+    }
+}
+impl StorageServiceStorage {
+    pub async fn connect(socket_name: String) -> Result<Self, StorageServiceError> {
+        let mut connection = LocalSocketStream::connect(socket_name.as_str()).await?;
+        connection
+            .write_all(&PROTOCOL_VERSION.to_be_bytes())
+            .await?;
+        let mut version = [0u8; 4];
+        connection.read_exact(&mut version).await?;
+        let version = u32::from_be_bytes(version);
+        if version != PROTOCOL_VERSION {
+            return Err(StorageServiceError::ProtocolMismatch {
+                client: PROTOCOL_VERSION,
+                service: version,
+            });
+        }
+        Ok(Self {
+            connection: Arc::new(InterprocessConnectionInner::new(connection)),
+            socket_name,
+        })
+    }
+    async fn write_packet(
+        connection: &mut LocalSocketStream,
+        packet: ToServicePackets,
+    ) -> Result<(), StorageServiceError> {
+        let bytes = rkyv::to_bytes::<_, 256>(&packet).map_err(|e| {
+            StorageServiceError::Connection(io::Error::new(ErrorKind::InvalidData, e))
+        })?;
+        trace!("Sending packet with size {}", bytes.len());
+        connection
+            .write_all(&(bytes.len() as u32).to_be_bytes())
+            .await?;
+        connection
+            .write_all(bytes.as_slice())
+            .await
+            .map_err(StorageServiceError::Connection)
+    }
+    async fn get_packet(
+        connection: &mut LocalSocketStream,
+    ) -> Result<FromServicePackets, StorageServiceError> {
+        let mut len = [0u8; 4];
+        connection.read_exact(&mut len).await?;
+        let len = u32::from_be_bytes(len);
 
-impl Service for StorageService {
+        let mut packet = BytesMut::zeroed(len as usize);
+        connection.read_exact(packet.deref_mut()).await?;
+
+        rkyv::from_bytes::<FromServicePackets>(&packet).map_err(|e| {
+            StorageServiceError::Connection(io::Error::new(ErrorKind::InvalidData, e.to_string()))
+        })
+    }
+    /// Sends the packet and waits for its response
+    async fn request(
+        &self,
+        packet: ToServicePackets,
+    ) -> Result<FromServicePackets, StorageServiceError> {
+        let mut connection = self.get_guard_panic();
+        Self::write_packet(connection.deref_mut(), packet).await?;
+        Self::get_packet(connection.deref_mut()).await
+    }
+    /// The error for a response that does not answer the request
+    fn unexpected_packet(packet: FromServicePackets) -> StorageServiceError {
+        match packet {
+            FromServicePackets::InternalStorageError(error) => StorageServiceError::Service(error),
+            packet => StorageServiceError::Service(format!("Unexpected response {:?}", packet)),
+        }
+    }
+}
+
+impl Service for StorageServiceStorage {
     type ServiceConfig = ();
     type ServiceError = StorageServiceError;
 }
 
-impl Storage for StorageService {
+#[async_trait]
+impl Storage for StorageServiceStorage {
     fn storage_name() -> &'static str
     where
         Self: Sized,
     {
-        "storage_service"
+        "Storage Service Connection"
     }
 
     fn storage_path(&self) -> String {
-        todo!()
+        self.socket_name.clone()
+    }
+
+    async fn quota_usage(&self, mailbox_id: Uuid) -> Result<QuotaUsage, Self::ServiceError> {
+        match self
+            .request(ToServicePackets::QuotaUsage(mailbox_id))
+            .await?
+        {
+            FromServicePackets::QuotaUsage(usage) => Ok(usage),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn create_folder(
+        &self,
+        mailbox_id: Uuid,
+        name: String,
+    ) -> Result<StorageResult<Folder>, Self::ServiceError> {
+        let packet = ToServicePackets::CreateFolder { mailbox_id, name };
+        match self.request(packet).await? {
+            FromServicePackets::CreateFolder(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn list_folders(
+        &self,
+        mailbox_id: Uuid,
+    ) -> Result<StorageResult<Vec<Folder>>, Self::ServiceError> {
+        match self
+            .request(ToServicePackets::ListFolders(mailbox_id))
+            .await?
+        {
+            FromServicePackets::ListFolders(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn delete_folder(
+        &self,
+        mailbox_id: Uuid,
+        name: String,
+    ) -> Result<StorageResult<bool>, Self::ServiceError> {
+        let packet = ToServicePackets::DeleteFolder { mailbox_id, name };
+        match self.request(packet).await? {
+            FromServicePackets::DeleteFolder(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn append_message(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        message: NewMessage,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        let packet = ToServicePackets::AppendMessage {
+            mailbox_id,
+            folder,
+            message,
+        };
+        match self.request(packet).await? {
+            FromServicePackets::AppendMessage(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn deliver_message(
+        &self,
+        deliveries: Vec<Delivery>,
+        message: NewMessage,
+    ) -> Result<Vec<StorageResult<MessageInfo>>, Self::ServiceError> {
+        let packet = ToServicePackets::DeliverMessage {
+            deliveries,
+            message,
+        };
+        match self.request(packet).await? {
+            FromServicePackets::DeliverMessage(results) => Ok(results),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn list_messages(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+    ) -> Result<StorageResult<Vec<MessageInfo>>, Self::ServiceError> {
+        let packet = ToServicePackets::ListMessages { mailbox_id, folder };
+        match self.request(packet).await? {
+            FromServicePackets::ListMessages(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn read_message(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+    ) -> Result<StorageResult<Vec<u8>>, Self::ServiceError> {
+        let packet = ToServicePackets::ReadMessage {
+            mailbox_id,
+            folder,
+            uid,
+        };
+        match self.request(packet).await? {
+            FromServicePackets::ReadMessage(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn set_flags(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        uid: u32,
+        flags: Vec<String>,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        let packet = ToServicePackets::SetFlags {
+            mailbox_id,
+            folder,
+            uid,
+            flags,
+        };
+        match self.request(packet).await? {
+            FromServicePackets::SetFlags(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn expunge(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+    ) -> Result<StorageResult<Vec<u32>>, Self::ServiceError> {
+        let packet = ToServicePackets::Expunge { mailbox_id, folder };
+        match self.request(packet).await? {
+            FromServicePackets::Expunge(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn collect_garbage(
+        &self,
+    ) -> Result<StorageResult<GarbageCollection>, Self::ServiceError> {
+        match self.request(ToServicePackets::CollectGarbage).await? {
+            FromServicePackets::CollectGarbage(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn rotate_mailbox_key(
+        &self,
+        mailbox_id: Uuid,
+    ) -> Result<StorageResult<u64>, Self::ServiceError> {
+        match self
+            .request(ToServicePackets::RotateMailboxKey(mailbox_id))
+            .await?
+        {
+            FromServicePackets::RotateMailboxKey(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn search(
+        &self,
+        mailbox_id: Uuid,
+        folder: String,
+        query: SearchQuery,
+    ) -> Result<StorageResult<Vec<u32>>, Self::ServiceError> {
+        match self
+            .request(ToServicePackets::Search {
+                mailbox_id,
+                folder,
+                query,
+            })
+            .await?
+        {
+            FromServicePackets::Search(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
+    }
+
+    async fn rebuild_search_index(
+        &self,
+        mailbox_id: Uuid,
+    ) -> Result<StorageResult<Rebuild>, Self::ServiceError> {
+        match self
+            .request(ToServicePackets::RebuildSearchIndex(mailbox_id))
+            .await?
+        {
+            FromServicePackets::RebuildSearchIndex(result) => Ok(result),
+            packet => Err(Self::unexpected_packet(packet)),
+        }
     }
 }
@@ -1,11 +1,153 @@
 use async_trait::async_trait;
+use tokio::sync::broadcast::Receiver;
+use utils::quota::QuotaUsage;
+use uuid::Uuid;
 
 use utils::service::Service;
 
+use crate::blob_store::GarbageCollection;
+use crate::events::StorageEvent;
+use crate::folders::{Delivery, Folder, MessageInfo, NewMessage, StorageError, StorageResult};
+use crate::search::{Rebuild, SearchQuery};
+
 #[async_trait]
 pub trait Storage: Service {
     fn storage_name() -> &'static str
     where
         Self: Sized;
     fn storage_path(&self) -> String;
+    /// The bytes and messages stored for the mailbox of an account or group
+    async fn quota_usage(&self, mailbox_id: Uuid) -> Result<QuotaUsage, Self::ServiceError>;
+    /// Changes to stored messages. None if the storage does not publish them
+    fn subscribe(&self) -> Option<Receiver<StorageEvent>> {
+        None
+    }
+
+    // Folders and messages. Storages that do not keep messages keep the defaults
+
+    async fn create_folder(
+        &self,
+        _mailbox_id: Uuid,
+        _name: String,
+    ) -> Result<StorageResult<Folder>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// Sorted by name
+    async fn list_folders(
+        &self,
+        _mailbox_id: Uuid,
+    ) -> Result<StorageResult<Vec<Folder>>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// Removes the folder with its messages. Returns false if it did not exist
+    async fn delete_folder(
+        &self,
+        _mailbox_id: Uuid,
+        _name: String,
+    ) -> Result<StorageResult<bool>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// The message is given the next UID of the folder
+    async fn append_message(
+        &self,
+        _mailbox_id: Uuid,
+        _folder: String,
+        _message: NewMessage,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// Stores one message in many folders. For example every recipient of a mailing list
+    ///
+    /// Storages that deduplicate write the contents once. The results are in the order of the deliveries
+    async fn deliver_message(
+        &self,
+        deliveries: Vec<Delivery>,
+        message: NewMessage,
+    ) -> Result<Vec<StorageResult<MessageInfo>>, Self::ServiceError> {
+        let mut results = Vec::with_capacity(deliveries.len());
+        for delivery in deliveries {
+            let result = self
+                .append_message(delivery.mailbox_id, delivery.folder, message.clone())
+                .await?;
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    /// Sorted by UID
+    async fn list_messages(
+        &self,
+        _mailbox_id: Uuid,
+        _folder: String,
+    ) -> Result<StorageResult<Vec<MessageInfo>>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// The raw message
+    async fn read_message(
+        &self,
+        _mailbox_id: Uuid,
+        _folder: String,
+        _uid: u32,
+    ) -> Result<StorageResult<Vec<u8>>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// Replaces the flags of the message and gives it a new modseq
+    async fn set_flags(
+        &self,
+        _mailbox_id: Uuid,
+        _folder: String,
+        _uid: u32,
+        _flags: Vec<String>,
+    ) -> Result<StorageResult<MessageInfo>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// Removes the messages flagged [DELETED_FLAG](crate::folders::DELETED_FLAG). Returns their UIDs
+    async fn expunge(
+        &self,
+        _mailbox_id: Uuid,
+        _folder: String,
+    ) -> Result<StorageResult<Vec<u32>>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// Removes the stored contents no message references anymore
+    async fn collect_garbage(
+        &self,
+    ) -> Result<StorageResult<GarbageCollection>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// Gives the mailbox a new data key and seals its messages with it.
+    /// A mailbox that was not encrypted is encrypted. Returns the number of messages sealed
+    async fn rotate_mailbox_key(
+        &self,
+        _mailbox_id: Uuid,
+    ) -> Result<StorageResult<u64>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// The UIDs of the messages in the folder that match the query. Sorted
+    async fn search(
+        &self,
+        _mailbox_id: Uuid,
+        _folder: String,
+        _query: SearchQuery,
+    ) -> Result<StorageResult<Vec<u32>>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
+
+    /// Indexes every message of the mailbox again. For when the index is new or missed changes
+    async fn rebuild_search_index(
+        &self,
+        _mailbox_id: Uuid,
+    ) -> Result<StorageResult<Rebuild>, Self::ServiceError> {
+        Ok(Err(StorageError::NotSupported))
+    }
 }
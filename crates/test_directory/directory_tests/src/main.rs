@@ -3,6 +3,7 @@ use tracing_subscriber::util::SubscriberInitExt;
 
 use directories::directory_service::directory_service_directory::DirectoryServiceDirectory;
 use directories::directory_type::Directory;
+use utils::app_password::LoginProtocol;
 
 #[tokio::main]
 async fn main() {
@@ -21,7 +22,11 @@ async fn main() {
                 .unwrap();
             println!("{:?}", account);
             let account = directory_service_connector
-                .login_account("will_always_exist".to_string(), String::new())
+                .login_account(
+                    "will_always_exist".to_string(),
+                    String::new(),
+                    LoginProtocol::Imap,
+                )
                 .await
                 .unwrap();
             println!("{:?}", account);
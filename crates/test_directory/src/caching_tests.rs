@@ -0,0 +1,255 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Duration as ChronoDuration;
+use uuid::Uuid;
+
+use directories::caching::{CacheConfig, CachingDirectory, DirectoryCache};
+use directories::directory_type::Directory;
+use utils::account::{Account, EmailAddress};
+use utils::admin::{NewAccount, NewGroup};
+use utils::app_password::LoginProtocol;
+use utils::common_types::EmailType;
+
+use crate::test_directory::{TestAccount, TestConfig, TestDirectory};
+
+fn account(username: &str, email_address: &str) -> TestAccount {
+    TestAccount {
+        account: Account::new(username, Default::default(), Uuid::new_v4()),
+        email_addresses: vec![EmailAddress {
+            email_address: email_address.to_string(),
+            email_type: EmailType::Primary,
+            mailbox_id: Uuid::new_v4(),
+        }],
+    }
+}
+async fn cached(config: CacheConfig) -> (TestDirectory, CachingDirectory<TestDirectory>) {
+    let directory = TestDirectory::load(TestConfig {
+        accounts: vec![account("john", "john@example.com")],
+        mailing_lists: vec![],
+        brute_force: Default::default(),
+        quotas: Default::default(),
+    })
+    .await
+    .unwrap();
+    let cache = Arc::new(DirectoryCache::new(config));
+    (directory.clone(), CachingDirectory::new(directory, cache))
+}
+fn remove_accounts(directory: &TestDirectory) {
+    directory.0.accounts.write().clear();
+}
+
+#[tokio::test]
+pub async fn test_positive_and_negative_caching() {
+    let (directory, cached) = cached(CacheConfig::default()).await;
+    assert!(cached
+        .get_account_by_email("john@example.com".to_string())
+        .await
+        .unwrap()
+        .is_some());
+    assert!(cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_none());
+
+    remove_accounts(&directory);
+    directory
+        .0
+        .accounts
+        .write()
+        .insert(account("jane", "jane@example.com"));
+    assert!(cached
+        .get_account_by_email("john@example.com".to_string())
+        .await
+        .unwrap()
+        .is_some());
+    assert!(cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_none());
+
+    cached.cache().invalidate_account("john");
+    assert!(cached
+        .get_account_by_email("john@example.com".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    cached.cache().invalidate_all();
+    assert!(cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+pub async fn test_expiry_and_size() {
+    let (directory, cached) = cached(CacheConfig {
+        positive_ttl: ChronoDuration::milliseconds(50).into(),
+        max_entries: 1,
+        ..Default::default()
+    })
+    .await;
+    directory
+        .0
+        .accounts
+        .write()
+        .insert(account("jane", "jane@example.com"));
+    assert!(cached
+        .get_account("john".to_string())
+        .await
+        .unwrap()
+        .is_some());
+    // Pushes john out
+    assert!(cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_some());
+    remove_accounts(&directory);
+    assert!(cached
+        .get_account("john".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+pub async fn test_login_caching() {
+    let (directory, cached) = cached(CacheConfig::default()).await;
+    let login = |password: &str, protocol: LoginProtocol| {
+        cached.login_account("john".to_string(), password.to_string(), protocol)
+    };
+    assert!(login("password", LoginProtocol::Imap)
+        .await
+        .unwrap()
+        .is_some());
+    remove_accounts(&directory);
+    assert!(login("password", LoginProtocol::Imap)
+        .await
+        .unwrap()
+        .is_some());
+    // Only the exact password and protocol that succeeded
+    assert!(login("other", LoginProtocol::Imap).await.unwrap().is_none());
+    assert!(login("password", LoginProtocol::Smtp)
+        .await
+        .unwrap()
+        .is_none());
+
+    cached.cache().invalidate_logins("john");
+    assert!(login("password", LoginProtocol::Imap)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+pub async fn test_administration_invalidates() {
+    let (_, cached) = cached(CacheConfig::default()).await;
+    assert!(cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(cached
+        .get_account_by_email("jane@example.com".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    cached
+        .create_account(NewAccount {
+            username: "jane".to_string(),
+            name: None,
+            description: None,
+            password: "password".to_string(),
+            account_type: Default::default(),
+            primary_address: Some("jane@example.com".to_string()),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_some());
+    assert!(cached
+        .get_account_by_email("jane@example.com".to_string())
+        .await
+        .unwrap()
+        .is_some());
+
+    let jane = cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    cached
+        .rename_account("jane".to_string(), "janet".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(cached
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    let janet = cached
+        .get_account("janet".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(janet.mailbox_id, jane.mailbox_id);
+
+    // Cached accounts list their groups
+    cached
+        .create_group(NewGroup {
+            name: "staff".to_string(),
+            group_type: Default::default(),
+            posting_policy: Default::default(),
+            list_address: None,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    cached
+        .add_group_member("staff".to_string(), "janet".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    let groups = |account: Option<Account>| account.unwrap().groups;
+    assert_eq!(
+        groups(cached.get_account("janet".to_string()).await.unwrap()),
+        vec!["staff"]
+    );
+    assert!(cached
+        .delete_group("staff".to_string())
+        .await
+        .unwrap()
+        .unwrap());
+    assert!(groups(cached.get_account("janet".to_string()).await.unwrap()).is_empty());
+
+    assert!(cached
+        .delete_account("john".to_string())
+        .await
+        .unwrap()
+        .unwrap());
+    assert!(cached
+        .get_account_by_email("john@example.com".to_string())
+        .await
+        .unwrap()
+        .is_none());
+}
@@ -0,0 +1,382 @@
+use std::io;
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use directories::chain::{ChainDirectory, ChainMode};
+use directories::directory_type::Directory;
+use directories::ValidateDirectoryRequest;
+use utils::account::{Account, EmailAddress};
+use utils::admin::{AdminError, NewAccount};
+use utils::app_password::{AppPassword, LoginProtocol, NewAppPassword};
+use utils::auth_failures::{Lockout, LockoutKey};
+use utils::common_types::EmailType;
+use utils::groups::{Group, GroupType, MailingList, PostingPolicy};
+use utils::quota::Quota;
+use utils::service::Service;
+use utils::service_configuration::ServiceConfigurationResponse;
+use utils::two_factor::TotpEnrollment;
+
+use crate::test_directory::{TestAccount, TestConfig, TestDirectory};
+
+/// A directory whose backend is down
+struct Unavailable;
+impl Unavailable {
+    fn error<T>() -> Result<T, io::Error> {
+        Err(io::Error::new(io::ErrorKind::ConnectionRefused, "down"))
+    }
+}
+impl Service for Unavailable {
+    type ServiceConfig = ();
+    type ServiceError = io::Error;
+}
+#[async_trait]
+impl Directory for Unavailable {
+    fn directory_name() -> &'static str {
+        "unavailable"
+    }
+    async fn load(_: ()) -> Result<Self, io::Error> {
+        Ok(Self)
+    }
+    async fn get_account(&self, _: String) -> Result<Option<Account>, io::Error> {
+        Self::error()
+    }
+    async fn get_account_by_email(&self, _: String) -> Result<Option<Account>, io::Error> {
+        Self::error()
+    }
+    async fn get_mailing_list(&self, _: String) -> Result<Option<MailingList>, io::Error> {
+        Self::error()
+    }
+    async fn login_account(
+        &self,
+        _: String,
+        _: String,
+        _: LoginProtocol,
+    ) -> Result<Option<Account>, io::Error> {
+        Self::error()
+    }
+    async fn login_account_with_totp(
+        &self,
+        _: String,
+        _: String,
+        _: String,
+    ) -> Result<Option<Account>, io::Error> {
+        Self::error()
+    }
+    async fn create_app_password(
+        &self,
+        _: String,
+        _: String,
+        _: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, io::Error> {
+        Self::error()
+    }
+    async fn list_app_passwords(&self, _: String) -> Result<Vec<AppPassword>, io::Error> {
+        Self::error()
+    }
+    async fn revoke_app_password(&self, _: String, _: i64) -> Result<bool, io::Error> {
+        Self::error()
+    }
+    async fn enroll_totp(&self, _: String) -> Result<Option<TotpEnrollment>, io::Error> {
+        Self::error()
+    }
+    async fn verify_totp(&self, _: String, _: String) -> Result<bool, io::Error> {
+        Self::error()
+    }
+    async fn disable_totp(&self, _: String) -> Result<bool, io::Error> {
+        Self::error()
+    }
+    async fn check_auth_lockout(
+        &self,
+        _: Option<IpAddr>,
+        _: Option<String>,
+    ) -> Result<Option<Lockout>, io::Error> {
+        Self::error()
+    }
+    async fn record_auth_failure(
+        &self,
+        _: Option<IpAddr>,
+        _: Option<String>,
+    ) -> Result<Option<Lockout>, io::Error> {
+        Self::error()
+    }
+    async fn record_auth_success(&self, _: String) -> Result<bool, io::Error> {
+        Self::error()
+    }
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, io::Error> {
+        Self::error()
+    }
+    async fn clear_lockout(&self, _: LockoutKey) -> Result<bool, io::Error> {
+        Self::error()
+    }
+    async fn get_groups(&self) -> Result<Vec<String>, io::Error> {
+        Self::error()
+    }
+    async fn validate_config(
+        &self,
+        _: ValidateDirectoryRequest,
+    ) -> Result<ServiceConfigurationResponse, io::Error> {
+        Self::error()
+    }
+}
+
+fn account(username: &str, email_address: &str) -> TestAccount {
+    TestAccount {
+        account: Account::new(username, Default::default(), Uuid::new_v4()),
+        email_addresses: vec![EmailAddress {
+            email_address: email_address.to_string(),
+            email_type: EmailType::Primary,
+            mailbox_id: Uuid::new_v4(),
+        }],
+    }
+}
+fn mailing_list(name: &str, list_address: &str) -> MailingList {
+    MailingList {
+        group: Group {
+            group_type: GroupType::List,
+            name: name.to_string(),
+            description: String::new(),
+            mailbox_id: Uuid::new_v4(),
+        },
+        list_address: list_address.to_string(),
+        posting_policy: PostingPolicy::default(),
+        members: vec![],
+    }
+}
+/// `john` is in both directories. The first one owns him
+async fn directories() -> (TestDirectory, TestDirectory) {
+    let local = TestDirectory::load(TestConfig {
+        accounts: vec![account("john", "john@example.com")],
+        mailing_lists: vec![mailing_list("staff", "staff@example.com")],
+        brute_force: Default::default(),
+        quotas: Default::default(),
+    })
+    .await
+    .unwrap();
+    let staff = TestDirectory::load(TestConfig {
+        accounts: vec![
+            account("john", "john@staff.example.com"),
+            account("jane", "jane@staff.example.com"),
+        ],
+        mailing_lists: vec![
+            mailing_list("staff", "staff@staff.example.com"),
+            mailing_list("ops", "ops@staff.example.com"),
+        ],
+        brute_force: Default::default(),
+        quotas: Default::default(),
+    })
+    .await
+    .unwrap();
+    (local, staff)
+}
+
+#[tokio::test]
+pub async fn test_chain_lookups() {
+    let (local, staff) = directories().await;
+    let chain = ChainDirectory::new(ChainMode::FirstMatch)
+        .with(local.clone())
+        .with(staff.clone());
+
+    let jane = chain
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(jane.username, "jane");
+    let quota = Quota {
+        storage: 1024,
+        messages: 10,
+    };
+    staff.0.quotas.write().insert(jane.mailbox_id, quota);
+    assert_eq!(chain.get_quota(jane.mailbox_id).await.unwrap(), Some(quota));
+    assert_eq!(chain.get_quota(Uuid::new_v4()).await.unwrap(), None);
+    let john = chain
+        .get_account_by_email("john@staff.example.com".to_string())
+        .await
+        .unwrap();
+    assert_eq!(john.unwrap().username, "john");
+    assert!(chain
+        .get_mailing_list("ops@staff.example.com".to_string())
+        .await
+        .unwrap()
+        .is_some());
+    assert!(chain
+        .get_account("nobody".to_string())
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(chain.get_groups().await.unwrap(), vec!["staff", "ops"]);
+
+    // Only the owner of the username gets the app password
+    assert!(chain
+        .create_app_password(
+            "john".to_string(),
+            "Phone".to_string(),
+            vec![LoginProtocol::Imap]
+        )
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        local
+            .list_app_passwords("john".to_string())
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(staff
+        .list_app_passwords("john".to_string())
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+pub async fn test_chain_modes() {
+    let (_, staff) = directories().await;
+    let first_match = ChainDirectory::new(ChainMode::FirstMatch)
+        .with(Unavailable)
+        .with(staff.clone());
+    assert!(first_match
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        first_match.get_groups().await.unwrap(),
+        vec!["staff", "ops"]
+    );
+    // Nothing is missing until every directory has answered
+    assert!(first_match.get_account("nobody".to_string()).await.is_err());
+
+    let fail_closed = ChainDirectory::new(ChainMode::FailClosed)
+        .with(Unavailable)
+        .with(staff.clone());
+    assert!(fail_closed
+        .login_account(
+            "jane".to_string(),
+            "password".to_string(),
+            LoginProtocol::Imap
+        )
+        .await
+        .is_err());
+    assert!(fail_closed.get_groups().await.is_err());
+
+    let fail_closed = ChainDirectory::new(ChainMode::FailClosed)
+        .with(staff)
+        .with(Unavailable);
+    assert!(fail_closed
+        .login_account(
+            "jane".to_string(),
+            "password".to_string(),
+            LoginProtocol::Imap
+        )
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+pub async fn test_chain_administration() {
+    let (local, staff) = directories().await;
+    assert!(Unavailable
+        .directory_capabilities()
+        .await
+        .unwrap()
+        .is_read_only());
+    let chain = ChainDirectory::new(ChainMode::FirstMatch)
+        .with(local.clone())
+        .with(staff.clone());
+    assert!(chain.directory_capabilities().await.unwrap().groups);
+
+    let new_account = |username: &str| NewAccount {
+        username: username.to_string(),
+        name: None,
+        description: None,
+        password: "password".to_string(),
+        account_type: Default::default(),
+        primary_address: None,
+    };
+    assert_eq!(
+        chain.create_account(new_account("jane")).await.unwrap(),
+        Err(AdminError::AccountExists("jane".to_string()))
+    );
+    chain
+        .create_account(new_account("alice"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(local
+        .get_account("alice".to_string())
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        chain
+            .set_password("alice".to_string(), "secret".to_string())
+            .await
+            .unwrap(),
+        Err(AdminError::NotSupported)
+    );
+
+    // `ops` only exists in the second directory
+    chain
+        .add_group_member("ops".to_string(), "jane".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        staff
+            .list_group_members("ops".to_string())
+            .await
+            .unwrap()
+            .unwrap(),
+        vec!["jane"]
+    );
+
+    let usernames: Vec<String> = chain
+        .list_accounts()
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|account| account.username)
+        .collect();
+    assert_eq!(usernames, vec!["alice", "john", "jane"]);
+
+    // The new username must be free in every directory
+    assert_eq!(
+        chain
+            .rename_account("jane".to_string(), "john".to_string())
+            .await
+            .unwrap(),
+        Err(AdminError::AccountExists("john".to_string()))
+    );
+    let jane = staff
+        .get_account("jane".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    let janet = chain
+        .rename_account("jane".to_string(), "janet".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(janet.mailbox_id, jane.mailbox_id);
+    assert!(staff
+        .get_account("janet".to_string())
+        .await
+        .unwrap()
+        .is_some());
+
+    // A directory that is down may have the username
+    let with_unavailable = ChainDirectory::new(ChainMode::FirstMatch)
+        .with(Unavailable)
+        .with(local);
+    assert!(with_unavailable
+        .create_account(new_account("bob"))
+        .await
+        .is_err());
+}
@@ -11,6 +11,10 @@ use utils::configs::Config;
 
 use crate::test_directory::{TestConfig, TestDirectory};
 
+#[cfg(test)]
+pub mod caching_tests;
+#[cfg(test)]
+pub mod chain_tests;
 pub mod test_directory;
 
 #[tokio::main]
@@ -1,6 +1,8 @@
 use std::collections::HashSet;
 use std::convert::Infallible;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use ahash::{HashMap, HashMapExt};
 use async_trait::async_trait;
@@ -11,9 +13,23 @@ use uuid::Uuid;
 use directories::directory_type::Directory;
 use directories::ValidateDirectoryRequest;
 use utils::account::{Account, EmailAddress};
+use utils::admin::{
+    AccountAddress, AccountUpdate, AdminError, AdminResult, DirectoryCapabilities, NewAccount,
+    NewGroup,
+};
+use utils::app_password::{generate_app_password, AppPassword, LoginProtocol, NewAppPassword};
+use utils::auth_failures::{AuthFailureTracker, Lockout, LockoutKey};
+use utils::common_types::EmailType;
+use utils::configs::brute_force::BruteForceConfig;
 use utils::configs::{Config, ConfigName};
+use utils::groups::{Group, GroupType, MailingList};
+use utils::helper_types;
+use utils::quota::Quota;
 use utils::service::Service;
 use utils::service_configuration::ServiceConfigurationResponse;
+use utils::two_factor::{
+    enrollment, generate_recovery_codes, generate_totp_secret, verify_totp, TotpEnrollment,
+};
 
 pub mod shared_constants {
     include!("../../../tests/shared_constants.rs");
@@ -30,19 +46,32 @@ pub struct TestAccount {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TestConfig {
     pub accounts: Vec<TestAccount>,
+    #[serde(default)]
+    pub mailing_lists: Vec<MailingList>,
+    #[serde(default)]
+    pub brute_force: BruteForceConfig,
+    /// Limits by mailbox id
+    #[serde(default)]
+    pub quotas: HashMap<Uuid, Quota>,
 }
 
 impl Default for TestConfig {
     fn default() -> Self {
         let mut config = Vec::new();
         config.push(TestAccount {
-            account: Account {
-                username: shared_constants::TEST_USER_NAME.to_string(),
-                account_type: Default::default(),
-            },
+            account: Account::new(
+                shared_constants::TEST_USER_NAME,
+                Default::default(),
+                shared_constants::TEST_USER_UUID,
+            ),
             email_addresses: vec![],
         });
-        Self { accounts: config }
+        Self {
+            accounts: config,
+            mailing_lists: vec![],
+            brute_force: Default::default(),
+            quotas: HashMap::new(),
+        }
     }
 }
 impl Config for TestConfig {
@@ -60,13 +89,97 @@ impl Config for TestConfig {
         ConfigName::Name("test_directory.toml")
     }
 }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestTotp {
+    pub secret: Vec<u8>,
+    pub enabled: bool,
+    pub last_step: Option<i64>,
+    pub recovery_codes: Vec<String>,
+}
 #[derive(Debug)]
 pub struct TestDirectoryInner {
     pub accounts: RwLock<HashSet<TestAccount>>,
     pub mail_boxes: RwLock<HashMap<Uuid, MailBox>>,
+    pub mailing_lists: RwLock<Vec<MailingList>>,
+    pub quotas: RwLock<HashMap<Uuid, Quota>>,
+    /// App passwords by username
+    pub app_passwords: RwLock<HashMap<String, Vec<AppPassword>>>,
+    /// TOTP secrets by username
+    pub totp: RwLock<HashMap<String, TestTotp>>,
+    /// Usernames of deactivated accounts
+    pub inactive: RwLock<HashSet<String>>,
+    pub auth_failures: AuthFailureTracker,
+}
+impl TestDirectory {
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+    fn same_address(a: &str, b: &helper_types::EmailAddress) -> bool {
+        helper_types::EmailAddress::new_lenient(a).is_ok_and(|a| a == *b)
+    }
+    fn address_in_use(&self, email_address: &helper_types::EmailAddress) -> bool {
+        let accounts = self.0.accounts.read();
+        let mailing_lists = self.0.mailing_lists.read();
+        accounts
+            .iter()
+            .flat_map(|account| &account.email_addresses)
+            .any(|address| Self::same_address(&address.email_address, email_address))
+            || mailing_lists
+                .iter()
+                .any(|list| Self::same_address(&list.list_address, email_address))
+    }
+    /// Replaces the account with the changed copy. Returns false if there is no account
+    fn change_account(&self, username: &str, change: impl FnOnce(&mut TestAccount)) -> bool {
+        let mut accounts = self.0.accounts.write();
+        let Some(mut account) = accounts
+            .iter()
+            .find(|account| account.account.username == username)
+            .cloned()
+        else {
+            return false;
+        };
+        accounts.remove(&account);
+        change(&mut account);
+        accounts.insert(account);
+        true
+    }
+    /// The account as the directory state has it
+    fn account(&self, account: &TestAccount) -> Account {
+        let mailing_lists = self.0.mailing_lists.read();
+        let groups = mailing_lists
+            .iter()
+            .filter(|list| {
+                list.members.iter().any(|member| {
+                    account
+                        .email_addresses
+                        .iter()
+                        .any(|address| address.email_address == *member)
+                })
+            })
+            .map(|list| list.group.name.clone())
+            .collect();
+        let mut result = account.account.clone();
+        result.active = !self.0.inactive.read().contains(&result.username);
+        if let Some(quota) = self.0.quotas.read().get(&result.mailbox_id) {
+            result.quota = *quota;
+        }
+        result.addresses = account
+            .email_addresses
+            .iter()
+            .map(|address| AccountAddress {
+                email_address: address.email_address.clone(),
+                email_type: address.email_type,
+            })
+            .collect();
+        result.groups = groups;
+        result
+    }
 }
 #[derive(Debug, Clone)]
-pub struct TestDirectory(Arc<TestDirectoryInner>);
+pub struct TestDirectory(pub(crate) Arc<TestDirectoryInner>);
 impl Service for TestDirectory {
     type ServiceConfig = TestConfig;
     type ServiceError = Infallible;
@@ -91,6 +204,12 @@ impl Directory for TestDirectory {
         Ok(Self(Arc::new(TestDirectoryInner {
             accounts: RwLock::new(accounts),
             mail_boxes: RwLock::new(HashMap::new()),
+            mailing_lists: RwLock::new(config.mailing_lists),
+            quotas: RwLock::new(config.quotas),
+            app_passwords: RwLock::new(HashMap::new()),
+            totp: RwLock::new(HashMap::new()),
+            inactive: RwLock::new(HashSet::new()),
+            auth_failures: AuthFailureTracker::new(config.brute_force),
         })))
     }
 
@@ -99,25 +218,578 @@ impl Directory for TestDirectory {
         Ok(accounts
             .iter()
             .find(|a| a.account.username == username)
-            .cloned()
-            .map(|a| a.account))
+            .map(|a| self.account(a)))
+    }
+
+    async fn get_account_by_email(
+        &self,
+        email_address: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let Ok(email_address) = helper_types::EmailAddress::new_lenient(email_address) else {
+            return Ok(None);
+        };
+        let accounts = self.0.accounts.read();
+        Ok(accounts
+            .iter()
+            .find(|a| {
+                a.email_addresses.iter().any(|e| {
+                    helper_types::EmailAddress::new_lenient(e.email_address.as_str())
+                        .is_ok_and(|e| e == email_address)
+                })
+            })
+            .map(|a| self.account(a)))
+    }
+
+    async fn get_mailing_list(
+        &self,
+        email_address: String,
+    ) -> Result<Option<MailingList>, Self::ServiceError> {
+        let Ok(email_address) = helper_types::EmailAddress::new_lenient(email_address) else {
+            return Ok(None);
+        };
+        let mailing_lists = self.0.mailing_lists.read();
+        Ok(mailing_lists
+            .iter()
+            .filter(|list| list.group.group_type == GroupType::List)
+            .find(|list| {
+                helper_types::EmailAddress::new_lenient(list.list_address.as_str())
+                    .is_ok_and(|list_address| list_address == email_address)
+            })
+            .cloned())
+    }
+
+    async fn get_quota(&self, mailbox_id: Uuid) -> Result<Option<Quota>, Self::ServiceError> {
+        Ok(self.0.quotas.read().get(&mailbox_id).copied())
     }
 
     async fn login_account(
         &self,
         username: String,
         _: String,
+        protocol: LoginProtocol,
     ) -> Result<Option<Account>, Self::ServiceError> {
-        let accounts = self.0.accounts.read();
-        Ok(accounts
+        Ok(self
+            .get_account(username)
+            .await?
+            .filter(|account| account.can_login(protocol)))
+    }
+
+    async fn login_account_with_totp(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<Option<Account>, Self::ServiceError> {
+        let enabled = self
+            .0
+            .totp
+            .read()
+            .get(&username)
+            .is_some_and(|totp| totp.enabled);
+        if enabled && !self.verify_totp(username.clone(), code).await? {
+            return Ok(None);
+        }
+        self.login_account(username, password, LoginProtocol::Http)
+            .await
+    }
+
+    async fn create_app_password(
+        &self,
+        username: String,
+        name: String,
+        protocols: Vec<LoginProtocol>,
+    ) -> Result<Option<NewAppPassword>, Self::ServiceError> {
+        if self.get_account(username.clone()).await?.is_none() {
+            return Ok(None);
+        }
+        let mut app_passwords = self.0.app_passwords.write();
+        let id = app_passwords
+            .values()
+            .flatten()
+            .map(|app_password| app_password.id)
+            .max()
+            .unwrap_or_default()
+            + 1;
+        let app_password = AppPassword {
+            id,
+            name,
+            protocols: protocols
+                .into_iter()
+                .filter(LoginProtocol::accepts_app_passwords)
+                .collect(),
+            created: 0,
+            last_used: None,
+        };
+        app_passwords
+            .entry(username)
+            .or_default()
+            .push(app_password.clone());
+        Ok(Some(NewAppPassword {
+            app_password,
+            password: generate_app_password(),
+        }))
+    }
+
+    async fn list_app_passwords(
+        &self,
+        username: String,
+    ) -> Result<Vec<AppPassword>, Self::ServiceError> {
+        let app_passwords = self.0.app_passwords.read();
+        Ok(app_passwords.get(&username).cloned().unwrap_or_default())
+    }
+
+    async fn revoke_app_password(
+        &self,
+        username: String,
+        id: i64,
+    ) -> Result<bool, Self::ServiceError> {
+        let mut app_passwords = self.0.app_passwords.write();
+        let Some(app_passwords) = app_passwords.get_mut(&username) else {
+            return Ok(false);
+        };
+        let before = app_passwords.len();
+        app_passwords.retain(|app_password| app_password.id != id);
+        Ok(app_passwords.len() != before)
+    }
+
+    async fn enroll_totp(
+        &self,
+        username: String,
+    ) -> Result<Option<TotpEnrollment>, Self::ServiceError> {
+        if self.get_account(username.clone()).await?.is_none() {
+            return Ok(None);
+        }
+        let mut totp = self.0.totp.write();
+        if totp.get(&username).is_some_and(|totp| totp.enabled) {
+            return Ok(None);
+        }
+        let secret = generate_totp_secret();
+        let recovery_codes = generate_recovery_codes();
+        let Ok(enrollment) = enrollment(
+            secret.clone(),
+            "Test Directory",
+            &username,
+            recovery_codes.clone(),
+        ) else {
+            return Ok(None);
+        };
+        totp.insert(
+            username,
+            TestTotp {
+                secret,
+                enabled: false,
+                last_step: None,
+                recovery_codes,
+            },
+        );
+        Ok(Some(enrollment))
+    }
+
+    async fn verify_totp(
+        &self,
+        username: String,
+        code: String,
+    ) -> Result<bool, Self::ServiceError> {
+        let mut totp = self.0.totp.write();
+        let Some(totp) = totp.get_mut(&username) else {
+            return Ok(false);
+        };
+        if let Some(step) = verify_totp(&totp.secret, &code, Self::now(), totp.last_step) {
+            totp.enabled = true;
+            totp.last_step = Some(step);
+            return Ok(true);
+        }
+        if !totp.enabled {
+            return Ok(false);
+        }
+        let before = totp.recovery_codes.len();
+        totp.recovery_codes
+            .retain(|recovery_code| *recovery_code != code);
+        Ok(totp.recovery_codes.len() != before)
+    }
+
+    async fn disable_totp(&self, username: String) -> Result<bool, Self::ServiceError> {
+        Ok(self.0.totp.write().remove(&username).is_some())
+    }
+
+    async fn check_auth_lockout(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        Ok(self
+            .0
+            .auth_failures
+            .check(remote, username.as_deref(), Self::now() as i64))
+    }
+
+    async fn record_auth_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<String>,
+    ) -> Result<Option<Lockout>, Self::ServiceError> {
+        // Test accounts can not be deactivated
+        Ok(self
+            .0
+            .auth_failures
+            .record_failure(remote, username.as_deref(), false, Self::now() as i64)
+            .lockout)
+    }
+
+    async fn record_auth_success(&self, username: String) -> Result<bool, Self::ServiceError> {
+        Ok(self.0.auth_failures.record_success(&username))
+    }
+
+    async fn list_lockouts(&self) -> Result<Vec<Lockout>, Self::ServiceError> {
+        Ok(self.0.auth_failures.lockouts(Self::now() as i64))
+    }
+
+    async fn clear_lockout(&self, key: LockoutKey) -> Result<bool, Self::ServiceError> {
+        Ok(self.0.auth_failures.clear(&key))
+    }
+
+    /// Passwords are not checked, so they can not be changed either
+    async fn directory_capabilities(&self) -> Result<DirectoryCapabilities, Self::ServiceError> {
+        Ok(DirectoryCapabilities {
+            passwords: false,
+            ..DirectoryCapabilities::ALL
+        })
+    }
+
+    async fn list_accounts(&self) -> Result<AdminResult<Vec<Account>>, Self::ServiceError> {
+        let mut accounts: Vec<Account> = self
+            .0
+            .accounts
+            .read()
             .iter()
-            .find(|a| a.account.username == username)
-            .cloned()
-            .map(|a| a.account))
+            .map(|account| self.account(account))
+            .collect();
+        accounts.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(Ok(accounts))
+    }
+
+    async fn create_account(
+        &self,
+        account: NewAccount,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        if self.get_account(account.username.clone()).await?.is_some() {
+            return Ok(Err(AdminError::AccountExists(account.username)));
+        }
+        let mut email_addresses = vec![];
+        if let Some(primary_address) = account.primary_address {
+            let Ok(normalized) = helper_types::EmailAddress::new_lenient(primary_address.clone())
+            else {
+                return Ok(Err(AdminError::InvalidEmailAddress(primary_address)));
+            };
+            if self.address_in_use(&normalized) {
+                return Ok(Err(AdminError::AddressInUse(primary_address)));
+            }
+            email_addresses.push(EmailAddress {
+                email_address: primary_address,
+                email_type: EmailType::Primary,
+                mailbox_id: Uuid::new_v4(),
+            });
+        }
+        // The test directory has no namespaces
+        let mut new_account = Account::new(account.username, account.account_type, Uuid::new_v4());
+        if let Some(name) = account.name {
+            new_account.name = name;
+        }
+        new_account.description = account.description;
+        let new_account = TestAccount {
+            account: new_account,
+            email_addresses,
+        };
+        let created = self.account(&new_account);
+        self.0.accounts.write().insert(new_account);
+        Ok(Ok(created))
+    }
+
+    async fn update_account(
+        &self,
+        username: String,
+        update: AccountUpdate,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        self.change_account(&username, |account| {
+            if let Some(name) = update.name {
+                account.account.name = name;
+            }
+            if let Some(description) = update.description {
+                account.account.description = Some(description);
+            }
+            if let Some(account_type) = update.account_type {
+                account.account.account_type = account_type;
+            }
+        });
+        Ok(self
+            .get_account(username.clone())
+            .await?
+            .ok_or(AdminError::AccountNotFound(username)))
+    }
+
+    async fn rename_account(
+        &self,
+        username: String,
+        new_username: String,
+    ) -> Result<AdminResult<Account>, Self::ServiceError> {
+        if self.get_account(new_username.clone()).await?.is_some() {
+            return Ok(Err(AdminError::AccountExists(new_username)));
+        }
+        if !self.change_account(&username, |account| {
+            account.account.username = new_username.clone()
+        }) {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        }
+        if let Some(app_passwords) = self.0.app_passwords.write().remove(&username) {
+            self.0
+                .app_passwords
+                .write()
+                .insert(new_username.clone(), app_passwords);
+        }
+        if let Some(totp) = self.0.totp.write().remove(&username) {
+            self.0.totp.write().insert(new_username.clone(), totp);
+        }
+        if self.0.inactive.write().remove(&username) {
+            self.0.inactive.write().insert(new_username.clone());
+        }
+        Ok(self
+            .get_account(new_username.clone())
+            .await?
+            .ok_or(AdminError::AccountNotFound(new_username)))
+    }
+
+    async fn set_account_active(
+        &self,
+        username: String,
+        active: bool,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        if self.get_account(username.clone()).await?.is_none() {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        }
+        let mut inactive = self.0.inactive.write();
+        if active {
+            inactive.remove(&username);
+        } else {
+            inactive.insert(username);
+        }
+        Ok(Ok(()))
+    }
+
+    async fn delete_account(
+        &self,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let account = self
+            .0
+            .accounts
+            .read()
+            .iter()
+            .find(|account| account.account.username == username)
+            .cloned();
+        let Some(account) = account else {
+            return Ok(Ok(false));
+        };
+        self.0.accounts.write().remove(&account);
+        for list in self.0.mailing_lists.write().iter_mut() {
+            list.members.retain(|member| {
+                !account
+                    .email_addresses
+                    .iter()
+                    .any(|address| address.email_address == *member)
+            });
+        }
+        self.0.app_passwords.write().remove(&username);
+        self.0.totp.write().remove(&username);
+        self.0.inactive.write().remove(&username);
+        Ok(Ok(true))
+    }
+
+    async fn add_email_address(
+        &self,
+        username: String,
+        email_address: String,
+        email_type: EmailType,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        if email_type == EmailType::List {
+            return Ok(Err(AdminError::NotSupported));
+        }
+        let Ok(normalized) = helper_types::EmailAddress::new_lenient(email_address.clone()) else {
+            return Ok(Err(AdminError::InvalidEmailAddress(email_address)));
+        };
+        if self.address_in_use(&normalized) {
+            return Ok(Err(AdminError::AddressInUse(email_address)));
+        }
+        let changed = self.change_account(&username, |account| {
+            if email_type == EmailType::Primary {
+                for address in &mut account.email_addresses {
+                    if address.email_type == EmailType::Primary {
+                        address.email_type = EmailType::Alias;
+                    }
+                }
+            }
+            account.email_addresses.push(EmailAddress {
+                email_address,
+                email_type,
+                mailbox_id: Uuid::new_v4(),
+            });
+        });
+        if !changed {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        }
+        Ok(Ok(()))
+    }
+
+    async fn remove_email_address(
+        &self,
+        email_address: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let Some(account) = self.get_account_by_email(email_address.clone()).await? else {
+            return Ok(Ok(false));
+        };
+        let Ok(normalized) = helper_types::EmailAddress::new_lenient(email_address) else {
+            return Ok(Ok(false));
+        };
+        self.change_account(&account.username, |account| {
+            account
+                .email_addresses
+                .retain(|address| !Self::same_address(&address.email_address, &normalized))
+        });
+        Ok(Ok(true))
+    }
+
+    async fn create_group(&self, group: NewGroup) -> Result<AdminResult<()>, Self::ServiceError> {
+        if self.get_groups().await?.contains(&group.name) {
+            return Ok(Err(AdminError::GroupExists(group.name)));
+        }
+        let list_address = group.list_address.unwrap_or_default();
+        if !list_address.is_empty() {
+            let Ok(normalized) = helper_types::EmailAddress::new_lenient(list_address.clone())
+            else {
+                return Ok(Err(AdminError::InvalidEmailAddress(list_address)));
+            };
+            if self.address_in_use(&normalized) {
+                return Ok(Err(AdminError::AddressInUse(list_address)));
+            }
+        }
+        self.0.mailing_lists.write().push(MailingList {
+            group: Group {
+                group_type: group.group_type,
+                name: group.name,
+                description: String::new(),
+                mailbox_id: Uuid::new_v4(),
+            },
+            list_address,
+            posting_policy: group.posting_policy,
+            members: vec![],
+        });
+        Ok(Ok(()))
+    }
+
+    async fn delete_group(&self, name: String) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let mut mailing_lists = self.0.mailing_lists.write();
+        let before = mailing_lists.len();
+        mailing_lists.retain(|list| list.group.name != name);
+        Ok(Ok(mailing_lists.len() != before))
+    }
+
+    async fn list_group_members(
+        &self,
+        group: String,
+    ) -> Result<AdminResult<Vec<String>>, Self::ServiceError> {
+        let Some(members) = self
+            .0
+            .mailing_lists
+            .read()
+            .iter()
+            .find(|list| list.group.name == group)
+            .map(|list| list.members.clone())
+        else {
+            return Ok(Err(AdminError::GroupNotFound(group)));
+        };
+        let mut usernames = vec![];
+        for member in members {
+            if let Some(account) = self.get_account_by_email(member).await? {
+                usernames.push(account.username);
+            }
+        }
+        Ok(Ok(usernames))
+    }
+
+    /// Members are stored by address, so the account needs one
+    async fn add_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<()>, Self::ServiceError> {
+        let account = self
+            .0
+            .accounts
+            .read()
+            .iter()
+            .find(|account| account.account.username == username)
+            .cloned();
+        let Some(account) = account else {
+            return Ok(Err(AdminError::AccountNotFound(username)));
+        };
+        let Some(address) = account
+            .email_addresses
+            .iter()
+            .find(|address| address.email_type == EmailType::Primary)
+            .or(account.email_addresses.first())
+        else {
+            return Ok(Err(AdminError::NotSupported));
+        };
+        let mut mailing_lists = self.0.mailing_lists.write();
+        let Some(list) = mailing_lists
+            .iter_mut()
+            .find(|list| list.group.name == group)
+        else {
+            return Ok(Err(AdminError::GroupNotFound(group)));
+        };
+        if !list.members.contains(&address.email_address) {
+            list.members.push(address.email_address.clone());
+        }
+        Ok(Ok(()))
+    }
+
+    async fn remove_group_member(
+        &self,
+        group: String,
+        username: String,
+    ) -> Result<AdminResult<bool>, Self::ServiceError> {
+        let account = self
+            .0
+            .accounts
+            .read()
+            .iter()
+            .find(|account| account.account.username == username)
+            .cloned();
+        let Some(account) = account else {
+            return Ok(Ok(false));
+        };
+        let mut mailing_lists = self.0.mailing_lists.write();
+        let Some(list) = mailing_lists
+            .iter_mut()
+            .find(|list| list.group.name == group)
+        else {
+            return Ok(Ok(false));
+        };
+        let before = list.members.len();
+        list.members.retain(|member| {
+            !account
+                .email_addresses
+                .iter()
+                .any(|address| address.email_address == *member)
+        });
+        Ok(Ok(list.members.len() != before))
     }
 
     async fn get_groups(&self) -> Result<Vec<String>, Self::ServiceError> {
-        todo!()
+        let mailing_lists = self.0.mailing_lists.read();
+        Ok(mailing_lists
+            .iter()
+            .map(|list| list.group.name.clone())
+            .collect())
     }
 
     async fn validate_config(
@@ -127,3 +799,101 @@ impl Directory for TestDirectory {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ahash::HashMap;
+    use uuid::Uuid;
+
+    use directories::directory_type::Directory;
+    use directories::recipient::{resolve_recipient, RecipientMatch};
+    use utils::account::{Account, EmailAddress};
+    use utils::common_types::EmailType;
+    use utils::configs::domain_configs::{Domain, DomainConfiguration};
+    use utils::helper_types;
+
+    use crate::test_directory::{TestAccount, TestConfig, TestDirectory};
+
+    fn account(username: &str, email_address: &str) -> TestAccount {
+        TestAccount {
+            account: Account::new(username, Default::default(), Uuid::new_v4()),
+            email_addresses: vec![EmailAddress {
+                email_address: email_address.to_string(),
+                email_type: EmailType::Primary,
+                mailbox_id: Uuid::new_v4(),
+            }],
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_resolve_recipient() {
+        let directory = TestDirectory::load(TestConfig {
+            accounts: vec![
+                account("user", "user@example.com"),
+                account("postmaster", "postmaster@example.com"),
+                account("other", "other@other.com"),
+            ],
+            mailing_lists: vec![],
+            brute_force: Default::default(),
+            quotas: Default::default(),
+        })
+        .await
+        .unwrap();
+        let mut domains = HashMap::default();
+        domains.insert(
+            "example".to_string(),
+            Domain {
+                domain: "example.com".to_string(),
+                catch_all: Some(helper_types::EmailAddress::new("postmaster@example.com").unwrap()),
+                ..Default::default()
+            },
+        );
+        domains.insert(
+            "other".to_string(),
+            Domain {
+                domain: "other.com".to_string(),
+                sub_address_separator: "-".to_string(),
+                ..Default::default()
+            },
+        );
+        let domains = DomainConfiguration { domains };
+        let resolve = |address: &str| {
+            let address = helper_types::EmailAddress::new(address).unwrap();
+            let directory = directory.clone();
+            let domains = &domains;
+            async move {
+                resolve_recipient(&directory, domains, &address)
+                    .await
+                    .unwrap()
+                    .map(|r| (r.account.username, r.detail, r.match_type))
+            }
+        };
+
+        assert_eq!(
+            resolve("user@EXAMPLE.com").await,
+            Some(("user".to_string(), None, RecipientMatch::Exact))
+        );
+        assert_eq!(
+            resolve("user+receipts@example.com").await,
+            Some((
+                "user".to_string(),
+                Some("receipts".to_string()),
+                RecipientMatch::SubAddress
+            ))
+        );
+        assert_eq!(
+            resolve("nobody@example.com").await,
+            Some(("postmaster".to_string(), None, RecipientMatch::CatchAll))
+        );
+        assert_eq!(
+            resolve("other-lists@other.com").await,
+            Some((
+                "other".to_string(),
+                Some("lists".to_string()),
+                RecipientMatch::SubAddress
+            ))
+        );
+        assert_eq!(resolve("other+lists@other.com").await, None);
+        assert_eq!(resolve("user@unknown.com").await, None);
+    }
+}
@@ -1,7 +1,10 @@
 use rkyv::{Archive, Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::admin::AccountAddress;
+use crate::app_password::LoginProtocol;
 use crate::common_types::{AccountType, EmailType};
+use crate::quota::Quota;
 
 #[derive(
     Debug,
@@ -18,11 +21,50 @@ use crate::common_types::{AccountType, EmailType};
 #[archive(compare(PartialEq), check_bytes)]
 pub struct Account {
     pub username: String,
+    /// The display name
+    pub name: String,
+    pub description: Option<String>,
     pub account_type: AccountType,
+    /// Where the mail of the account is stored. Never changes, even if the account is renamed
+    pub mailbox_id: Uuid,
+    /// Inactive accounts can not log in or receive mail
+    pub active: bool,
+    pub quota: Quota,
+    pub addresses: Vec<AccountAddress>,
+    /// The names of the groups the account is a member of
+    pub groups: Vec<String>,
 }
 impl Account {
-    pub fn get_mailbox_id_from_namespace(&self, namespace: &Uuid) -> Uuid {
-        Uuid::new_v5(namespace, self.username.as_bytes())
+    /// An active account with no limits, addresses or groups. The name is the username
+    pub fn new(username: impl Into<String>, account_type: AccountType, mailbox_id: Uuid) -> Self {
+        let username = username.into();
+        Self {
+            name: username.clone(),
+            username,
+            description: None,
+            account_type,
+            mailbox_id,
+            active: true,
+            quota: Quota::default(),
+            addresses: vec![],
+            groups: vec![],
+        }
+    }
+    /// Inactive accounts and account types that do not allow the protocol can not log in
+    pub fn can_login(&self, protocol: LoginProtocol) -> bool {
+        self.active && self.account_type.allows_login(protocol)
+    }
+    pub fn primary_address(&self) -> Option<&str> {
+        self.addresses
+            .iter()
+            .find(|address| address.email_type == EmailType::Primary)
+            .map(|address| address.email_address.as_str())
+    }
+    /// The mailbox id of a new account
+    ///
+    /// Only used when the account is created. Afterwards the directory keeps the id with the account
+    pub fn generate_mailbox_id(namespace: &Uuid, username: &str) -> Uuid {
+        Uuid::new_v5(namespace, username.as_bytes())
     }
 }
 
@@ -49,21 +91,44 @@ pub struct EmailAddress {
 mod tests {
     use uuid::Uuid;
 
+    use strum::IntoEnumIterator;
+
     use crate::account::{Account, EmailAddress};
-    use crate::common_types::EmailType;
+    use crate::admin::AccountAddress;
+    use crate::app_password::LoginProtocol;
+    use crate::common_types::{AccountType, EmailType};
 
     #[test]
     pub fn test_serialize_and_deserialize() {
-        let account = Account {
-            username: "test".to_string(),
-            account_type: Default::default(),
-        };
+        let mut account = Account::new("test", Default::default(), Uuid::new_v4());
+        account.quota.storage = 1024;
+        account.addresses.push(AccountAddress {
+            email_address: "test@localhost".to_string(),
+            email_type: EmailType::Primary,
+        });
+        account.groups.push("staff".to_string());
 
         let serialized = rkyv::to_bytes::<_, 256>(&account).unwrap().to_vec();
         let deserialized: Account = rkyv::from_bytes(&serialized).unwrap();
         assert_eq!(account, deserialized)
     }
     #[test]
+    pub fn test_can_login() {
+        let mut account = Account::new("test", AccountType::Individual, Uuid::new_v4());
+        assert!(account.can_login(LoginProtocol::Imap));
+        account.active = false;
+        assert!(!account.can_login(LoginProtocol::Imap));
+
+        let service = Account::new("app", AccountType::Service, Uuid::new_v4());
+        assert!(service.can_login(LoginProtocol::Smtp));
+        assert!(!service.can_login(LoginProtocol::Imap));
+        assert!(!service.can_login(LoginProtocol::Http));
+        for account_type in [AccountType::Shared, AccountType::Resource] {
+            let account = Account::new("support", account_type, Uuid::new_v4());
+            assert!(LoginProtocol::iter().all(|protocol| !account.can_login(protocol)));
+        }
+    }
+    #[test]
     pub fn test_serialize_and_deserialize_email() {
         let account = super::EmailAddress {
             email_address: "test@localhost".to_string(),
@@ -0,0 +1,195 @@
+//! Types for changing accounts, addresses and groups in a directory
+use thiserror::Error;
+
+use crate::common_types::{AccountType, EmailType};
+use crate::groups::{GroupType, PostingPolicy};
+
+/// Errors about the request itself. Failures of the directory are its service errors
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Error,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub enum AdminError {
+    #[error("The directory does not support this change")]
+    NotSupported,
+    #[error("Account {0} already exists")]
+    AccountExists(String),
+    #[error("Account {0} does not exist")]
+    AccountNotFound(String),
+    #[error("Group {0} already exists")]
+    GroupExists(String),
+    #[error("Group {0} does not exist")]
+    GroupNotFound(String),
+    #[error("{0} is already in use")]
+    AddressInUse(String),
+    #[error("Invalid email address {0}")]
+    InvalidEmailAddress(String),
+    #[error("Unable to set the password: {0}")]
+    InvalidPassword(String),
+    #[error("Invalid username {0:?}")]
+    InvalidUsername(String),
+}
+pub type AdminResult<T> = Result<T, AdminError>;
+
+/// The changes a directory supports. Read only directories such as LDAP support none
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct DirectoryCapabilities {
+    /// Create, update, deactivate and delete accounts
+    pub accounts: bool,
+    pub passwords: bool,
+    /// Add and remove the email addresses of accounts
+    pub addresses: bool,
+    /// Create and delete groups and change their members
+    pub groups: bool,
+}
+impl DirectoryCapabilities {
+    pub const READ_ONLY: Self = Self {
+        accounts: false,
+        passwords: false,
+        addresses: false,
+        groups: false,
+    };
+    pub const ALL: Self = Self {
+        accounts: true,
+        passwords: true,
+        addresses: true,
+        groups: true,
+    };
+    pub fn is_read_only(&self) -> bool {
+        *self == Self::READ_ONLY
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct AccountAddress {
+    pub email_address: String,
+    pub email_type: EmailType,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct NewAccount {
+    pub username: String,
+    /// The display name. The username if not set
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Hashed with the directory's preferred password type
+    pub password: String,
+    #[serde(default)]
+    pub account_type: AccountType,
+    #[serde(default)]
+    pub primary_address: Option<String>,
+}
+
+/// Fields that are None are left unchanged
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct AccountUpdate {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub account_type: Option<AccountType>,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct NewGroup {
+    pub name: String,
+    #[serde(default)]
+    pub group_type: GroupType,
+    #[serde(default)]
+    pub posting_policy: PostingPolicy,
+    /// Where mail for a [GroupType::List] is sent
+    #[serde(default)]
+    pub list_address: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::account::Account;
+    use crate::admin::{AdminError, AdminResult};
+
+    #[test]
+    pub fn test_serialize_and_deserialize_result() {
+        let results: Vec<AdminResult<Account>> = vec![
+            Ok(Account::new("test", Default::default(), Uuid::new_v4())),
+            Err(AdminError::AddressInUse("test@example.com".to_string())),
+        ];
+        for result in results {
+            let serialized = rkyv::to_bytes::<_, 256>(&result).unwrap().to_vec();
+            let deserialized: AdminResult<Account> = rkyv::from_bytes(&serialized).unwrap();
+            assert_eq!(result, deserialized);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use rand::distributions::Uniform;
+use rand::rngs::OsRng;
+use rand::Rng;
+use strum::{AsRefStr, Display, EnumIs, EnumIter, EnumString, IntoStaticStr};
+
+const APP_PASSWORD_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const APP_PASSWORD_GROUPS: usize = 4;
+const APP_PASSWORD_GROUP_LENGTH: usize = 4;
+
+/// The protocol a login is made with
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    rkyv::Archive,
+    AsRefStr,
+    IntoStaticStr,
+    EnumIs,
+    EnumString,
+    Display,
+    EnumIter,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub enum LoginProtocol {
+    Smtp,
+    Imap,
+    Pop3,
+    /// JMAP and the web interfaces
+    Http,
+}
+impl LoginProtocol {
+    /// App passwords are for mail clients. Only SMTP, IMAP and POP3 accept them
+    pub fn accepts_app_passwords(&self) -> bool {
+        !matches!(self, LoginProtocol::Http)
+    }
+}
+
+/// A password for one device or mail client that can be revoked without changing the account password
+///
+/// Times are unix timestamps in seconds
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct AppPassword {
+    pub id: i64,
+    pub name: String,
+    pub protocols: Vec<LoginProtocol>,
+    pub created: i64,
+    pub last_used: Option<i64>,
+}
+impl AppPassword {
+    pub fn allows(&self, protocol: LoginProtocol) -> bool {
+        protocol.accepts_app_passwords() && self.protocols.contains(&protocol)
+    }
+}
+
+/// A newly created app password
+///
+/// The password is only ever returned here. Only its hash is stored
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct NewAppPassword {
+    pub app_password: AppPassword,
+    pub password: String,
+}
+
+/// Generates a password in the form `abcd-efgh-ijkl-mnop`
+pub fn generate_app_password() -> String {
+    let characters: Vec<char> = OsRng
+        .sample_iter(Uniform::from(0..APP_PASSWORD_ALPHABET.len()))
+        .take(APP_PASSWORD_GROUPS * APP_PASSWORD_GROUP_LENGTH)
+        .map(|index| APP_PASSWORD_ALPHABET[index] as char)
+        .collect();
+    characters
+        .chunks(APP_PASSWORD_GROUP_LENGTH)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Removes the separators so the password can be typed with or without them
+///
+/// App passwords are hashed and checked after normalizing
+pub fn normalize_app_password(password: &str) -> String {
+    password
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app_password::{
+        generate_app_password, normalize_app_password, AppPassword, LoginProtocol,
+    };
+
+    #[test]
+    pub fn test_generate() {
+        let password = generate_app_password();
+        assert_eq!(password.len(), 19);
+        assert_eq!(password.matches('-').count(), 3);
+        assert_eq!(normalize_app_password(&password).len(), 16);
+        assert_eq!(
+            normalize_app_password("ABCD efgh-ijkl-mnop"),
+            "abcdefghijklmnop"
+        );
+        assert_ne!(password, generate_app_password());
+    }
+    #[test]
+    pub fn test_allows() {
+        let app_password = AppPassword {
+            id: 1,
+            name: "Phone".to_string(),
+            protocols: vec![LoginProtocol::Imap, LoginProtocol::Http],
+            created: 0,
+            last_used: None,
+        };
+        assert!(app_password.allows(LoginProtocol::Imap));
+        assert!(!app_password.allows(LoginProtocol::Smtp));
+        assert!(!app_password.allows(LoginProtocol::Http));
+
+        let serialized = rkyv::to_bytes::<_, 256>(&app_password).unwrap().to_vec();
+        let deserialized: AppPassword = rkyv::from_bytes(&serialized).unwrap();
+        assert_eq!(deserialized, app_password);
+    }
+}
@@ -0,0 +1,389 @@
+//! Counts failed logins by remote address and by username
+//!
+//! Kept by the directory so every protocol server shares the same counts
+use std::net::IpAddr;
+
+use ahash::{HashMap, HashMapExt};
+use parking_lot::Mutex;
+
+use crate::configs::brute_force::BruteForceConfig;
+
+/// What a lockout applies to
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum LockoutKey {
+    RemoteIp(IpAddr),
+    /// Lowercase
+    Username(String),
+}
+impl LockoutKey {
+    pub fn username(username: &str) -> Self {
+        LockoutKey::Username(username.to_lowercase())
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct Lockout {
+    pub key: LockoutKey,
+    pub failures: u32,
+    /// Unix time in seconds
+    pub until: i64,
+}
+
+/// The result of [AuthFailureTracker::record_failure]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FailureOutcome {
+    /// Set if this failure caused a lockout
+    pub lockout: Option<Lockout>,
+    /// The existing account has been locked out [deactivate_after](BruteForceConfig::deactivate_after)
+    /// times in a row
+    pub deactivate: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Entry {
+    failures: u32,
+    window_start: i64,
+    locked_until: Option<i64>,
+    /// Lockouts in a row of an existing account. Reset by a successful login, clearing the
+    /// lockout or a window without one
+    lockouts: u32,
+    /// When the last lockout ended
+    last_lockout_end: i64,
+}
+impl Entry {
+    fn is_locked(&self, now: i64) -> bool {
+        self.locked_until.is_some_and(|until| until > now)
+    }
+    /// If failures or lockouts are still counted
+    fn is_counted(&self, window: i64, now: i64) -> bool {
+        self.is_locked(now)
+            || now - self.window_start < window
+            || (self.lockouts > 0 && now - self.last_lockout_end < window)
+    }
+}
+
+#[derive(Debug)]
+pub struct AuthFailureTracker {
+    config: BruteForceConfig,
+    entries: Mutex<HashMap<LockoutKey, Entry>>,
+}
+impl AuthFailureTracker {
+    pub fn new(config: BruteForceConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn keys(remote: Option<IpAddr>, username: Option<&str>) -> Vec<LockoutKey> {
+        remote
+            .map(LockoutKey::RemoteIp)
+            .into_iter()
+            .chain(username.map(LockoutKey::username))
+            .collect()
+    }
+    fn limit(&self, key: &LockoutKey) -> u32 {
+        match key {
+            LockoutKey::RemoteIp(_) => self.config.max_ip_failures,
+            LockoutKey::Username(_) => self.config.max_username_failures,
+        }
+    }
+
+    /// The active lockout on the address or username if there is one
+    pub fn check(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<&str>,
+        now: i64,
+    ) -> Option<Lockout> {
+        if !self.config.enabled {
+            return None;
+        }
+        let entries = self.entries.lock();
+        Self::keys(remote, username).into_iter().find_map(|key| {
+            let entry = entries.get(&key).filter(|entry| entry.is_locked(now))?;
+            Some(Lockout {
+                failures: entry.failures,
+                until: entry.locked_until?,
+                key,
+            })
+        })
+    }
+
+    /// `existing_account` is if the username belongs to an account. Only those are deactivated
+    pub fn record_failure(
+        &self,
+        remote: Option<IpAddr>,
+        username: Option<&str>,
+        existing_account: bool,
+        now: i64,
+    ) -> FailureOutcome {
+        let mut outcome = FailureOutcome::default();
+        if !self.config.enabled {
+            return outcome;
+        }
+        let window = self.config.window.num_seconds();
+        let lockout = self.config.lockout.num_seconds();
+        let mut entries = self.entries.lock();
+        entries.retain(|_, entry| entry.is_counted(window, now));
+        for key in Self::keys(remote, username) {
+            let limit = self.limit(&key);
+            if !entries.contains_key(&key) && entries.len() >= self.config.max_tracked {
+                Self::forget_oldest(&mut entries, now);
+            }
+            let entry = entries.entry(key.clone()).or_insert_with(|| Entry {
+                window_start: now,
+                ..Default::default()
+            });
+            if entry.is_locked(now) {
+                continue;
+            }
+            if entry.locked_until.take().is_some() || now - entry.window_start >= window {
+                entry.failures = 0;
+                entry.window_start = now;
+            }
+            entry.failures += 1;
+            if entry.failures < limit {
+                continue;
+            }
+            entry.locked_until = Some(now + lockout);
+            if let (LockoutKey::Username(_), true) = (&key, existing_account) {
+                if entry.lockouts > 0 && now - entry.last_lockout_end >= window {
+                    entry.lockouts = 0;
+                }
+                entry.lockouts += 1;
+                entry.last_lockout_end = now + lockout;
+                outcome.deactivate = self
+                    .config
+                    .deactivate_after
+                    .is_some_and(|after| entry.lockouts >= after);
+            }
+            outcome.lockout.get_or_insert(Lockout {
+                key,
+                failures: entry.failures,
+                until: now + lockout,
+            });
+        }
+        outcome
+    }
+
+    /// Forgets the failures for the username. Failures from the address are kept
+    ///
+    /// Returns false if there were none
+    pub fn record_success(&self, username: &str) -> bool {
+        self.entries
+            .lock()
+            .remove(&LockoutKey::username(username))
+            .is_some()
+    }
+
+    /// Every active lockout
+    pub fn lockouts(&self, now: i64) -> Vec<Lockout> {
+        self.entries
+            .lock()
+            .iter()
+            .filter(|(_, entry)| entry.is_locked(now))
+            .filter_map(|(key, entry)| {
+                Some(Lockout {
+                    key: key.clone(),
+                    failures: entry.failures,
+                    until: entry.locked_until?,
+                })
+            })
+            .collect()
+    }
+
+    /// Removes the lockout and failure count. Returns false if there was nothing to clear
+    pub fn clear(&self, key: &LockoutKey) -> bool {
+        let key = match key {
+            LockoutKey::Username(username) => LockoutKey::username(username),
+            key => key.clone(),
+        };
+        self.entries.lock().remove(&key).is_some()
+    }
+
+    /// Makes room when [max_tracked](BruteForceConfig::max_tracked) is reached.
+    /// Forgets the entry counted the longest ago, active lockouts last
+    fn forget_oldest(entries: &mut HashMap<LockoutKey, Entry>, now: i64) {
+        let oldest = entries
+            .iter()
+            .min_by_key(|(_, entry)| {
+                let locked = entry.is_locked(now);
+                (
+                    locked,
+                    entry
+                        .locked_until
+                        .filter(|_| locked)
+                        .unwrap_or(entry.window_start),
+                )
+            })
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            entries.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use chrono::Duration;
+
+    use crate::auth_failures::{AuthFailureTracker, LockoutKey};
+    use crate::configs::brute_force::BruteForceConfig;
+
+    const REMOTE: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn tracker() -> AuthFailureTracker {
+        AuthFailureTracker::new(BruteForceConfig {
+            max_ip_failures: 5,
+            max_username_failures: 3,
+            window: Duration::minutes(10).into(),
+            lockout: Duration::minutes(5).into(),
+            deactivate_after: Some(2),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    pub fn test_username_lockout() {
+        let tracker = tracker();
+        assert!(tracker
+            .record_failure(None, Some("Test"), true, 0)
+            .lockout
+            .is_none());
+        assert!(tracker
+            .record_failure(None, Some("test"), true, 10)
+            .lockout
+            .is_none());
+        let outcome = tracker.record_failure(None, Some("test"), true, 20);
+        assert_eq!(outcome.lockout.unwrap().until, 320);
+        assert!(!outcome.deactivate);
+
+        assert!(tracker.check(None, Some("TEST"), 100).is_some());
+        assert!(tracker.check(None, Some("other"), 100).is_none());
+        assert!(tracker.check(None, Some("test"), 320).is_none());
+
+        // The second lockout deactivates the account
+        for time in 400..402 {
+            tracker.record_failure(None, Some("test"), true, time);
+        }
+        let outcome = tracker.record_failure(None, Some("test"), true, 402);
+        assert!(outcome.deactivate);
+    }
+    #[test]
+    pub fn test_unknown_usernames_are_not_deactivated() {
+        let tracker = tracker();
+        for time in [0, 10, 20, 400, 401, 402] {
+            let outcome = tracker.record_failure(None, Some("unknown"), false, time);
+            assert!(!outcome.deactivate);
+        }
+        assert!(tracker.check(None, Some("unknown"), 403).is_some());
+    }
+    #[test]
+    pub fn test_lockouts_in_a_row() {
+        let tracker = tracker();
+        for time in [0, 10, 20] {
+            tracker.record_failure(None, Some("test"), true, time);
+        }
+        // The next lockout starts more than a window after the first one ended
+        for time in [1000, 1001] {
+            tracker.record_failure(None, Some("test"), true, time);
+        }
+        let outcome = tracker.record_failure(None, Some("test"), true, 1002);
+        assert!(outcome.lockout.is_some());
+        assert!(!outcome.deactivate);
+    }
+    #[test]
+    pub fn test_entries_expire() {
+        let tracker = tracker();
+        for time in [0, 10, 20] {
+            tracker.record_failure(None, Some("test"), true, time);
+        }
+        tracker.record_failure(Some(REMOTE), Some("other"), false, 30);
+        assert_eq!(tracker.entries.lock().len(), 3);
+        // The lockout ended at 320 and the window has passed since
+        tracker.record_failure(None, Some("last"), false, 920);
+        assert_eq!(tracker.entries.lock().len(), 1);
+    }
+    #[test]
+    pub fn test_max_tracked() {
+        let tracker = AuthFailureTracker::new(BruteForceConfig {
+            max_username_failures: 2,
+            max_tracked: 3,
+            ..Default::default()
+        });
+        tracker.record_failure(None, Some("locked"), true, 0);
+        tracker.record_failure(None, Some("locked"), true, 1);
+        for (time, username) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            tracker.record_failure(None, Some(username), false, 2 + time as i64);
+        }
+        let entries = tracker.entries.lock();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.contains_key(&LockoutKey::username("locked")));
+        assert!(entries.contains_key(&LockoutKey::username("d")));
+        assert!(!entries.contains_key(&LockoutKey::username("a")));
+    }
+    #[test]
+    pub fn test_window() {
+        let tracker = tracker();
+        tracker.record_failure(None, Some("test"), true, 0);
+        tracker.record_failure(None, Some("test"), true, 10);
+        // The window has passed so counting starts again
+        assert!(tracker
+            .record_failure(None, Some("test"), true, 600)
+            .lockout
+            .is_none());
+        tracker.record_success("test");
+        tracker.record_failure(None, Some("test"), true, 610);
+        assert!(tracker
+            .record_failure(None, Some("test"), true, 620)
+            .lockout
+            .is_none());
+    }
+    #[test]
+    pub fn test_remote_lockout() {
+        let tracker = tracker();
+        for (index, username) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            tracker.record_failure(Some(REMOTE), Some(username), true, index as i64);
+        }
+        let lockout = tracker
+            .record_failure(Some(REMOTE), Some("e"), true, 4)
+            .lockout
+            .unwrap();
+        assert_eq!(lockout.key, LockoutKey::RemoteIp(REMOTE));
+        assert!(tracker.check(Some(REMOTE), Some("f"), 5).is_some());
+        // A successful login does not clear the address
+        tracker.record_success("a");
+        assert_eq!(tracker.lockouts(5).len(), 1);
+
+        assert!(tracker.clear(&LockoutKey::RemoteIp(REMOTE)));
+        assert!(tracker.check(Some(REMOTE), None, 5).is_none());
+        assert!(!tracker.clear(&LockoutKey::RemoteIp(REMOTE)));
+    }
+}
@@ -1,6 +1,8 @@
 use rkyv::Archive;
 use strum::{AsRefStr, Display, EnumIs, EnumIter, EnumString, IntoStaticStr};
 
+use crate::app_password::LoginProtocol;
+
 #[derive(
     Clone,
     Debug,
@@ -24,9 +26,28 @@ use strum::{AsRefStr, Display, EnumIs, EnumIter, EnumString, IntoStaticStr};
 #[cfg_attr(feature = "sea-orm", derive(sea_orm::prelude::DeriveActiveEnum))]
 #[cfg_attr(feature = "sea-orm", sea_orm(rs_type = "String", db_type = "Text"))]
 pub enum AccountType {
+    /// A person. Logs in with every protocol
     #[default]
     #[cfg_attr(feature = "sea-orm", sea_orm(string_value = "Individual"))]
     Individual,
+    /// A mailbox shared by a team, such as `support@`. Members open it from their own accounts
+    #[cfg_attr(feature = "sea-orm", sea_orm(string_value = "Shared"))]
+    Shared,
+    /// An application that sends mail. It can only log in to submit over SMTP
+    #[cfg_attr(feature = "sea-orm", sea_orm(string_value = "Service"))]
+    Service,
+    /// A room or piece of equipment that is booked by mail. Never logs in
+    #[cfg_attr(feature = "sea-orm", sea_orm(string_value = "Resource"))]
+    Resource,
+}
+impl AccountType {
+    pub fn allows_login(&self, protocol: LoginProtocol) -> bool {
+        match self {
+            AccountType::Individual => true,
+            AccountType::Service => protocol == LoginProtocol::Smtp,
+            AccountType::Shared | AccountType::Resource => false,
+        }
+    }
 }
 
 #[derive(
@@ -0,0 +1,96 @@
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::configs::ConfigDuration;
+
+fn default_enabled() -> bool {
+    true
+}
+fn default_max_ip_failures() -> u32 {
+    20
+}
+fn default_max_username_failures() -> u32 {
+    5
+}
+fn default_window() -> ConfigDuration {
+    Duration::minutes(15).into()
+}
+fn default_lockout() -> ConfigDuration {
+    Duration::minutes(15).into()
+}
+fn default_max_tracked() -> usize {
+    100_000
+}
+
+/// Locks out remote addresses and usernames after repeated failed logins
+///
+/// Failures are counted within `window`. Once a limit is reached logins are refused for `lockout`.
+/// At most `max_tracked` addresses and usernames are counted. The oldest are forgotten first
+///
+/// If `deactivate_after` is set an existing account that is locked out that many times in a row is
+/// deactivated. Lockouts are in a row if each starts within `window` of the previous one ending.
+/// Anyone who knows a username can cause this, so it turns password guessing into a way to lock
+/// users out until an administrator activates them again
+///
+/// # Example
+/// ```toml
+/// [brute_force]
+/// max_ip_failures = 20
+/// max_username_failures = 5
+/// window = "15m"
+/// lockout = "15m"
+/// deactivate_after = 10
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BruteForceConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_max_ip_failures")]
+    pub max_ip_failures: u32,
+    #[serde(default = "default_max_username_failures")]
+    pub max_username_failures: u32,
+    #[serde(default = "default_window")]
+    pub window: ConfigDuration,
+    #[serde(default = "default_lockout")]
+    pub lockout: ConfigDuration,
+    #[serde(default)]
+    pub deactivate_after: Option<u32>,
+    #[serde(default = "default_max_tracked")]
+    pub max_tracked: usize,
+}
+impl Default for BruteForceConfig {
+    fn default() -> Self {
+        BruteForceConfig {
+            enabled: default_enabled(),
+            max_ip_failures: default_max_ip_failures(),
+            max_username_failures: default_max_username_failures(),
+            window: default_window(),
+            lockout: default_lockout(),
+            deactivate_after: None,
+            max_tracked: default_max_tracked(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use crate::configs::brute_force::BruteForceConfig;
+
+    #[test]
+    pub fn test_parse() {
+        let config: BruteForceConfig = toml::from_str(
+            r#"
+            window = "1h"
+            lockout = "90s"
+            deactivate_after = 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(*config.window, Duration::hours(1));
+        assert_eq!(*config.lockout, Duration::seconds(90));
+        assert_eq!(config.max_username_failures, 5);
+        assert_eq!(config.deactivate_after, Some(3));
+    }
+}
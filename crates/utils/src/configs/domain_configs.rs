@@ -1,4 +1,5 @@
 use crate::configs::{Config, ConfigName};
+use crate::helper_types::EmailAddress;
 use ahash::HashMap;
 use serde::{Deserialize, Serialize};
 
@@ -6,6 +7,14 @@ use serde::{Deserialize, Serialize};
 pub struct DomainConfiguration {
     pub domains: HashMap<String, Domain>,
 }
+impl DomainConfiguration {
+    /// Finds the domain configuration for the domain. Case-insensitive
+    pub fn get_domain(&self, domain: &str) -> Option<&Domain> {
+        self.domains
+            .values()
+            .find(|value| value.domain.eq_ignore_ascii_case(domain))
+    }
+}
 impl Config for DomainConfiguration {
     fn config_header() -> Option<&'static str>
     where
@@ -22,8 +31,38 @@ impl Config for DomainConfiguration {
         ConfigName::Name("domains.toml")
     }
 }
-#[derive(Debug, Serialize, Deserialize, Default)]
+fn default_sub_address_separator() -> String {
+    "+".to_string()
+}
+///
+/// # Example
+/// ```toml
+/// [domains.example]
+/// domain = "example.com"
+/// sign_with = []
+/// sub_address_separator = "+"
+/// catch_all = "postmaster@example.com"
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Domain {
     pub domain: String,
     pub sign_with: Vec<String>,
+    /// Separates the address from its detail. `user+tag@example.com` is delivered to `user@example.com`
+    ///
+    /// An empty string disables sub-addressing
+    #[serde(default = "default_sub_address_separator")]
+    pub sub_address_separator: String,
+    /// Receives mail for any address in the domain that does not exist
+    #[serde(default)]
+    pub catch_all: Option<EmailAddress>,
+}
+impl Default for Domain {
+    fn default() -> Self {
+        Domain {
+            domain: String::default(),
+            sign_with: Vec::default(),
+            sub_address_separator: default_sub_address_separator(),
+            catch_all: None,
+        }
+    }
 }
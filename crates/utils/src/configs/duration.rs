@@ -164,7 +164,9 @@ impl<'de> Deserialize<'de> for ConfigDuration {
                     )))
                 }
             };
-            let duration_as_int = string.parse().map_err(serde::de::Error::custom)?;
+            let duration_as_int = string[..string.len() - 1]
+                .parse()
+                .map_err(serde::de::Error::custom)?;
             let duration = match unit {
                 Unit::Seconds => Duration::seconds(duration_as_int),
                 Unit::Minutes => Duration::minutes(duration_as_int),
@@ -9,6 +9,9 @@ use thiserror::Error;
 pub mod dkim;
 pub mod domain_configs;
 mod duration;
+pub mod brute_force;
+pub mod password;
+pub mod two_factor;
 mod type_or_path;
 pub use duration::ConfigDuration;
 pub use type_or_path::PathOrType;
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+use crate::helper_types::password::PasswordType;
+
+fn default_memory_cost() -> u32 {
+    argon2::Params::DEFAULT_M_COST
+}
+fn default_time_cost() -> u32 {
+    argon2::Params::DEFAULT_T_COST
+}
+fn default_parallelism() -> u32 {
+    argon2::Params::DEFAULT_P_COST
+}
+
+/// Argon2id parameters used for new hashes
+///
+/// The defaults are the OWASP minimum. Memory is in KiB
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Argon2Config {
+    #[serde(default = "default_memory_cost")]
+    pub memory_cost: u32,
+    #[serde(default = "default_time_cost")]
+    pub time_cost: u32,
+    #[serde(default = "default_parallelism")]
+    pub parallelism: u32,
+}
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Argon2Config {
+            memory_cost: default_memory_cost(),
+            time_cost: default_time_cost(),
+            parallelism: default_parallelism(),
+        }
+    }
+}
+impl TryFrom<Argon2Config> for argon2::Params {
+    type Error = argon2::Error;
+
+    fn try_from(value: Argon2Config) -> Result<Self, Self::Error> {
+        argon2::Params::new(value.memory_cost, value.time_cost, value.parallelism, None)
+    }
+}
+
+/// How passwords are hashed
+///
+/// Passwords using another type, or Argon2 with weaker parameters, are rehashed on login
+///
+/// # Example
+/// ```toml
+/// [password]
+/// preferred = "Argon2"
+///
+/// [password.argon2]
+/// memory_cost = 19456
+/// time_cost = 2
+/// parallelism = 1
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PasswordConfig {
+    #[serde(default)]
+    pub preferred: PasswordType,
+    #[serde(default)]
+    pub argon2: Argon2Config,
+}
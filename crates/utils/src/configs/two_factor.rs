@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+use crate::two_factor::{SecretKey, TwoFactorError};
+
+fn default_issuer() -> String {
+    "Nitro Mail".to_string()
+}
+
+/// TOTP two-factor authentication
+///
+/// TOTP secrets are encrypted with `encryption_key`, 32 bytes encoded as base64.
+/// Accounts can not enroll until it is set. Changing it makes existing secrets unreadable
+///
+/// # Example
+/// ```toml
+/// [two_factor]
+/// issuer = "Nitro Mail"
+/// encryption_key = "<BASE64_KEY>"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TwoFactorConfig {
+    /// Shown in authenticator apps
+    #[serde(default = "default_issuer")]
+    pub issuer: String,
+    #[serde(default)]
+    pub encryption_key: Option<String>,
+}
+impl Default for TwoFactorConfig {
+    fn default() -> Self {
+        TwoFactorConfig {
+            issuer: default_issuer(),
+            encryption_key: None,
+        }
+    }
+}
+impl TwoFactorConfig {
+    pub fn secret_key(&self) -> Result<SecretKey, TwoFactorError> {
+        let key = self
+            .encryption_key
+            .as_deref()
+            .ok_or(TwoFactorError::MissingEncryptionKey)?;
+        SecretKey::from_base64(key)
+    }
+}
@@ -48,10 +48,79 @@ pub struct Group {
     pub group_type: GroupType,
     pub name: String,
     pub description: String,
+    /// Never changes, even if the group is renamed
+    pub mailbox_id: Uuid,
 }
 impl Group {
-    pub fn get_mailbox_id_from_namespace(&self, namespace: &Uuid) -> Uuid {
-        Uuid::new_v5(namespace, self.name.as_bytes())
+    /// The mailbox id of a new group. See [Account::generate_mailbox_id](crate::account::Account::generate_mailbox_id)
+    pub fn generate_mailbox_id(namespace: &Uuid, name: &str) -> Uuid {
+        Uuid::new_v5(namespace, name.as_bytes())
+    }
+}
+
+/// Who may post to a [MailingList]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Default,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    rkyv::Archive,
+    AsRefStr,
+    IntoStaticStr,
+    EnumIs,
+    EnumString,
+    Display,
+    EnumIter,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+#[cfg_attr(feature = "sea-orm", derive(sea_orm::prelude::DeriveActiveEnum))]
+#[cfg_attr(feature = "sea-orm", sea_orm(rs_type = "String", db_type = "Text"))]
+pub enum PostingPolicy {
+    /// Only members of the list may post
+    #[default]
+    #[cfg_attr(feature = "sea-orm", sea_orm(string_value = "MembersOnly"))]
+    MembersOnly,
+    /// Every post must be approved
+    #[cfg_attr(feature = "sea-orm", sea_orm(string_value = "Moderated"))]
+    Moderated,
+    /// Anyone may post
+    #[cfg_attr(feature = "sea-orm", sea_orm(string_value = "Open"))]
+    Open,
+}
+
+/// A group with [GroupType::List] and the address mail is sent to
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct MailingList {
+    pub group: Group,
+    pub list_address: String,
+    pub posting_policy: PostingPolicy,
+    /// The primary email address of every member
+    pub members: Vec<String>,
+}
+impl MailingList {
+    pub fn is_member(&self, email_address: &str) -> bool {
+        self.members
+            .iter()
+            .any(|member| member.eq_ignore_ascii_case(email_address))
     }
 }
 
@@ -68,11 +137,9 @@ mod group_tests {
             group_type: GroupType::Group,
             name: TEST_GROUP_NAME.to_string(),
             description: TEST_GROUP_DESCRIPTION.to_string(),
+            mailbox_id: Group::generate_mailbox_id(&GROUP_NAMESPACE, TEST_GROUP_NAME),
         };
-        assert_eq!(
-            group.get_mailbox_id_from_namespace(&GROUP_NAMESPACE),
-            TEST_GROUP_UUID
-        );
+        assert_eq!(group.mailbox_id, TEST_GROUP_UUID);
 
         let serialized = rkyv::to_bytes::<_, 256>(&group).unwrap().to_vec();
         let deserialized: Group = rkyv::from_bytes(&serialized).unwrap();
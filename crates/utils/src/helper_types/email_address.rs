@@ -1,15 +1,85 @@
 use std::fmt::Display;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::ops::Deref;
 use std::str::FromStr;
 
 use serde::Serialize;
+use strum::{AsRefStr, Display as StrumDisplay, EnumIs, EnumIter, EnumString, IntoStaticStr};
 use thiserror::Error;
 
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_DOMAIN_LENGTH: usize = 255;
+const MAX_LABEL_LENGTH: usize = 63;
+/// RFC 5321 limits a path to 256 octets including the angle brackets
+const MAX_ADDRESS_LENGTH: usize = 254;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Error)]
-#[error("Invalid Email Address")]
-pub struct InvalidEmailAddress;
+pub enum InvalidEmailAddress {
+    #[error("Invalid Email Address: missing @")]
+    MissingAt,
+    #[error("Invalid Email Address: the local part is empty")]
+    EmptyLocalPart,
+    #[error("Invalid Email Address: the local part is longer than 64 octets")]
+    LocalPartTooLong,
+    #[error("Invalid Email Address: invalid character in the local part")]
+    InvalidLocalPartCharacter,
+    #[error("Invalid Email Address: the local part has a leading, trailing or double dot")]
+    InvalidLocalPartDot,
+    #[error("Invalid Email Address: unterminated quoted local part")]
+    UnterminatedQuotedString,
+    #[error("Invalid Email Address: the domain is empty")]
+    EmptyDomain,
+    #[error("Invalid Email Address: the domain is longer than 255 octets")]
+    DomainTooLong,
+    #[error("Invalid Email Address: invalid domain label")]
+    InvalidDomainLabel,
+    #[error("Invalid Email Address: a domain label is longer than 63 octets")]
+    DomainLabelTooLong,
+    #[error("Invalid Email Address: invalid domain literal")]
+    InvalidDomainLiteral,
+    #[error("Invalid Email Address: invalid internationalized domain")]
+    InvalidInternationalizedDomain,
+    #[error("Invalid Email Address: the address is longer than 254 octets")]
+    AddressTooLong,
+}
+
+/// How strictly [EmailAddress] follows the RFCs
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+    AsRefStr,
+    IntoStaticStr,
+    EnumIs,
+    EnumString,
+    StrumDisplay,
+    EnumIter,
+)]
+pub enum ValidationMode {
+    /// RFC 5321 Mailbox syntax with RFC 6531 UTF-8 extensions
+    #[default]
+    Strict,
+    /// Also accepts forms that are invalid but seen in the wild.
+    ///
+    /// - Leading, trailing and consecutive dots in an unquoted local part
+    /// - A trailing dot on the domain
+    /// - Underscores in domain labels
+    /// - General address literals such as `[tag:content]`
+    Lenient,
+}
 
 /// A newtype wrapper around a String that represents an email address
+///
+/// The address is normalized on creation.
+/// The domain is lower cased and converted to its IDNA (punycode) form,
+/// and a quoted local part that does not need quoting is unquoted.
+/// The local part is otherwise kept as is because it is case-sensitive.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
 pub struct EmailAddress(String);
 impl PartialEq<String> for EmailAddress {
@@ -23,37 +93,237 @@ impl PartialEq<&str> for EmailAddress {
     }
 }
 impl EmailAddress {
+    /// Parses the address in [ValidationMode::Strict]
     pub fn new(email_address: impl Into<String>) -> Result<Self, InvalidEmailAddress> {
+        Self::new_with_mode(email_address, ValidationMode::Strict)
+    }
+    pub fn new_lenient(email_address: impl Into<String>) -> Result<Self, InvalidEmailAddress> {
+        Self::new_with_mode(email_address, ValidationMode::Lenient)
+    }
+    pub fn new_with_mode(
+        email_address: impl Into<String>,
+        mode: ValidationMode,
+    ) -> Result<Self, InvalidEmailAddress> {
         let email_address: String = email_address.into();
-        let email_address_split = email_address.splitn(2, '@').collect::<Vec<_>>();
-        if email_address_split.len() != 2 {
-            return Err(InvalidEmailAddress);
-        }
-        let user = email_address_split[0];
-        let domain = email_address_split[1];
-        if Self::validate_domain(domain) && Self::validate_user(user) {
-            Ok(EmailAddress(email_address))
-        } else {
-            return Err(InvalidEmailAddress);
+        let (local_part, domain) = Self::split(&email_address)?;
+        let local_part = Self::parse_local_part(local_part, mode)?;
+        let domain = Self::parse_domain(domain, mode)?;
+        let email_address = format!("{}@{}", local_part, domain);
+        if email_address.len() > MAX_ADDRESS_LENGTH {
+            return Err(InvalidEmailAddress::AddressTooLong);
+        }
+        Ok(EmailAddress(email_address))
+    }
+    /// Everything before the last `@`. Includes the quotes of a quoted local part
+    pub fn local_part(&self) -> &str {
+        &self.0[..self.at_index()]
+    }
+    /// The normalized domain. Lower case and in its ASCII form
+    pub fn domain(&self) -> &str {
+        &self.0[self.at_index() + 1..]
+    }
+    /// The domain with any punycode labels converted back to Unicode
+    pub fn domain_unicode(&self) -> String {
+        idna::domain_to_unicode(self.domain()).0
+    }
+    /// If the address can only be sent with the SMTPUTF8 extension
+    pub fn requires_smtputf8(&self) -> bool {
+        !self.local_part().is_ascii()
+    }
+    /// Splits a sub-address such as `user+tag@example.com` into `user@example.com` and `tag`
+    ///
+    /// The split happens at the first separator in the local part.
+    /// Returns None if the local part has no separator, or if either side of it is empty.
+    /// Quoted local parts are never split.
+    pub fn split_sub_address(&self, separator: &str) -> Option<(EmailAddress, String)> {
+        let local_part = self.local_part();
+        if separator.is_empty() || local_part.starts_with('"') {
+            return None;
+        }
+        let (base, detail) = local_part.split_once(separator)?;
+        if base.is_empty() || detail.is_empty() {
+            return None;
+        }
+        let base = EmailAddress::new_lenient(format!("{}@{}", base, self.domain())).ok()?;
+        Some((base, detail.to_string()))
+    }
+    fn at_index(&self) -> usize {
+        self.0
+            .rfind('@')
+            .expect("EmailAddress without an @. This is a bug.")
+    }
+
+    /// Splits on the `@` that ends the local part
+    fn split(email_address: &str) -> Result<(&str, &str), InvalidEmailAddress> {
+        if email_address.starts_with('"') {
+            let mut escaped = false;
+            for (index, c) in email_address.char_indices().skip(1) {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => {
+                        let rest = &email_address[index + 1..];
+                        return match rest.strip_prefix('@') {
+                            Some(domain) => Ok((&email_address[..=index], domain)),
+                            None if rest.is_empty() => Err(InvalidEmailAddress::MissingAt),
+                            None => Err(InvalidEmailAddress::InvalidLocalPartCharacter),
+                        };
+                    }
+                    _ => {}
+                }
+            }
+            return Err(InvalidEmailAddress::UnterminatedQuotedString);
         }
+        email_address
+            .rsplit_once('@')
+            .ok_or(InvalidEmailAddress::MissingAt)
+    }
+
+    fn is_atext(c: char) -> bool {
+        c.is_ascii_alphanumeric()
+            || "!#$%&'*+-/=?^_`{|}~".contains(c)
+            || (!c.is_ascii() && !c.is_control())
+    }
+    fn is_qtext(c: char) -> bool {
+        matches!(c as u32, 32..=33 | 35..=91 | 93..=126) || (!c.is_ascii() && !c.is_control())
     }
-    fn validate_domain(domain: &str) -> bool {
-        if domain.is_empty() || domain.len() > 255 || !domain.contains(".") {
-            false
+    fn is_dot_atom(value: &str) -> bool {
+        !value.is_empty()
+            && value
+                .split('.')
+                .all(|atom| !atom.is_empty() && atom.chars().all(Self::is_atext))
+    }
+
+    fn parse_local_part(
+        local_part: &str,
+        mode: ValidationMode,
+    ) -> Result<String, InvalidEmailAddress> {
+        if local_part.is_empty() {
+            return Err(InvalidEmailAddress::EmptyLocalPart);
+        }
+        let local_part = if let Some(quoted) = local_part.strip_prefix('"') {
+            let quoted = quoted
+                .strip_suffix('"')
+                .ok_or(InvalidEmailAddress::UnterminatedQuotedString)?;
+            let mut unquoted = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => match chars.next() {
+                        Some(c) if matches!(c as u32, 32..=126) => unquoted.push(c),
+                        _ => return Err(InvalidEmailAddress::InvalidLocalPartCharacter),
+                    },
+                    c if Self::is_qtext(c) => unquoted.push(c),
+                    _ => return Err(InvalidEmailAddress::InvalidLocalPartCharacter),
+                }
+            }
+            if unquoted.is_empty() {
+                return Err(InvalidEmailAddress::EmptyLocalPart);
+            }
+            if Self::is_dot_atom(&unquoted) {
+                unquoted
+            } else {
+                format!("\"{}\"", quoted)
+            }
         } else {
-            true
+            if !local_part.chars().all(|c| c == '.' || Self::is_atext(c)) {
+                return Err(InvalidEmailAddress::InvalidLocalPartCharacter);
+            }
+            let valid_dots = match mode {
+                ValidationMode::Strict => Self::is_dot_atom(local_part),
+                ValidationMode::Lenient => local_part.chars().any(|c| c != '.'),
+            };
+            if !valid_dots {
+                return Err(InvalidEmailAddress::InvalidLocalPartDot);
+            }
+            local_part.to_string()
+        };
+        if local_part.len() > MAX_LOCAL_PART_LENGTH {
+            return Err(InvalidEmailAddress::LocalPartTooLong);
         }
+        Ok(local_part)
     }
-    fn validate_user(user: &str) -> bool {
-        if user.is_empty() || user.len() > 64 {
-            false
+
+    fn parse_domain(domain: &str, mode: ValidationMode) -> Result<String, InvalidEmailAddress> {
+        if domain.is_empty() {
+            return Err(InvalidEmailAddress::EmptyDomain);
+        }
+        if let Some(literal) = domain.strip_prefix('[') {
+            let literal = literal
+                .strip_suffix(']')
+                .ok_or(InvalidEmailAddress::InvalidDomainLiteral)?;
+            return Self::parse_domain_literal(literal, mode);
+        }
+        let domain = match mode {
+            ValidationMode::Lenient => domain.strip_suffix('.').unwrap_or(domain),
+            ValidationMode::Strict => domain,
+        };
+        let domain = if domain.is_ascii() {
+            domain.to_ascii_lowercase()
         } else {
-            true
+            idna::domain_to_ascii(domain)
+                .map_err(|_| InvalidEmailAddress::InvalidInternationalizedDomain)?
+        };
+        if domain.len() > MAX_DOMAIN_LENGTH {
+            return Err(InvalidEmailAddress::DomainTooLong);
+        }
+        for label in domain.split('.') {
+            if label.len() > MAX_LABEL_LENGTH {
+                return Err(InvalidEmailAddress::DomainLabelTooLong);
+            }
+            let valid_characters = label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || (mode.is_lenient() && c == '_'));
+            if label.is_empty()
+                || !valid_characters
+                || label.starts_with('-')
+                || label.ends_with('-')
+            {
+                return Err(InvalidEmailAddress::InvalidDomainLabel);
+            }
+        }
+        // Makes sure any existing punycode labels decode
+        if domain.split('.').any(|label| label.starts_with("xn--")) {
+            if let (_, Err(_)) = idna::domain_to_unicode(&domain) {
+                return Err(InvalidEmailAddress::InvalidInternationalizedDomain);
+            }
+        }
+        Ok(domain)
+    }
+
+    fn parse_domain_literal(
+        literal: &str,
+        mode: ValidationMode,
+    ) -> Result<String, InvalidEmailAddress> {
+        if literal.len() > 5 && literal[..5].eq_ignore_ascii_case("IPv6:") {
+            let address = Ipv6Addr::from_str(&literal[5..])
+                .map_err(|_| InvalidEmailAddress::InvalidDomainLiteral)?;
+            return Ok(format!("[IPv6:{}]", address));
+        }
+        if let Ok(address) = Ipv4Addr::from_str(literal) {
+            return Ok(format!("[{}]", address));
         }
+        // General-address-literal = Standardized-tag ":" 1*dcontent
+        if let (ValidationMode::Lenient, Some((tag, content))) = (mode, literal.split_once(':')) {
+            let valid_tag = !tag.is_empty()
+                && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !tag.ends_with('-');
+            let valid_content = !content.is_empty()
+                && content
+                    .chars()
+                    .all(|c| matches!(c as u32, 33..=90 | 94..=126) && c != '@');
+            if valid_tag && valid_content {
+                return Ok(format!("[{}:{}]", tag, content));
+            }
+        }
+        Err(InvalidEmailAddress::InvalidDomainLiteral)
     }
 }
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
+    use crate::helper_types::email_address::{InvalidEmailAddress, ValidationMode};
     use crate::helper_types::EmailAddress;
 
     #[test]
@@ -61,6 +331,164 @@ mod tests {
         assert!(EmailAddress::new("test@gmail.com").is_ok());
         assert!(EmailAddress::new("fail.com").is_err());
     }
+    #[test]
+    pub fn test_valid_addresses() {
+        for address in [
+            "user@localhost",
+            "first.last@example.com",
+            "user+tag@example.com",
+            "\"a b\"@example.com",
+            "\"quoted\\\"quote\"@example.com",
+            "user@[192.168.1.1]",
+            "user@[IPv6:2001:db8::1]",
+            "用户@例子.广告",
+            "jörg@bücher.de",
+        ] {
+            assert!(EmailAddress::new(address).is_ok(), "{}", address);
+        }
+    }
+    #[test]
+    pub fn test_invalid_addresses() {
+        for (address, error) in [
+            ("a b@c.d", InvalidEmailAddress::InvalidLocalPartCharacter),
+            ("@example.com", InvalidEmailAddress::EmptyLocalPart),
+            ("user@", InvalidEmailAddress::EmptyDomain),
+            (
+                ".user@example.com",
+                InvalidEmailAddress::InvalidLocalPartDot,
+            ),
+            (
+                "us..er@example.com",
+                InvalidEmailAddress::InvalidLocalPartDot,
+            ),
+            (
+                "\"unterminated@example.com",
+                InvalidEmailAddress::UnterminatedQuotedString,
+            ),
+            ("user@-example.com", InvalidEmailAddress::InvalidDomainLabel),
+            ("user@example..com", InvalidEmailAddress::InvalidDomainLabel),
+            (
+                "user@[300.1.1.1]",
+                InvalidEmailAddress::InvalidDomainLiteral,
+            ),
+            (
+                "user@[tag:content]",
+                InvalidEmailAddress::InvalidDomainLiteral,
+            ),
+            (
+                "user@xn--a.com",
+                InvalidEmailAddress::InvalidInternationalizedDomain,
+            ),
+        ] {
+            assert_eq!(EmailAddress::new(address), Err(error), "{}", address);
+        }
+        let long_local_part = format!("{}@example.com", "a".repeat(65));
+        assert_eq!(
+            EmailAddress::new(long_local_part),
+            Err(InvalidEmailAddress::LocalPartTooLong)
+        );
+        let long_label = format!("a@{}.com", "a".repeat(64));
+        assert_eq!(
+            EmailAddress::new(long_label),
+            Err(InvalidEmailAddress::DomainLabelTooLong)
+        );
+    }
+    #[test]
+    pub fn test_lenient() {
+        for address in [
+            "us..er@example.com",
+            "user.@example.com",
+            "user@example.com.",
+            "user@under_score.example.com",
+            "user@[tag:content]",
+        ] {
+            assert!(EmailAddress::new(address).is_err(), "{}", address);
+            assert!(EmailAddress::new_lenient(address).is_ok(), "{}", address);
+        }
+        assert!(EmailAddress::new_lenient("a b@c.d").is_err());
+    }
+    #[test]
+    pub fn test_normalization() {
+        let address = EmailAddress::new("User@Example.COM").unwrap();
+        assert_eq!(address, "User@example.com");
+        assert_eq!(address.local_part(), "User");
+        assert_eq!(address.domain(), "example.com");
+
+        let address = EmailAddress::new("jörg@Bücher.de").unwrap();
+        assert_eq!(address.domain(), "xn--bcher-kva.de");
+        assert_eq!(address.domain_unicode(), "bücher.de");
+        assert!(address.requires_smtputf8());
+
+        assert_eq!(
+            EmailAddress::new("\"user\"@example.com").unwrap(),
+            "user@example.com"
+        );
+        assert_eq!(
+            EmailAddress::new("\"a@b\"@example.com")
+                .unwrap()
+                .local_part(),
+            "\"a@b\""
+        );
+        assert_eq!(
+            EmailAddress::new("user@[IPv6:2001:DB8:0:0::1]").unwrap(),
+            "user@[IPv6:2001:db8::1]"
+        );
+    }
+
+    #[test]
+    pub fn test_sub_address() {
+        let address = EmailAddress::new("user+folder+nested@example.com").unwrap();
+        let (base, detail) = address.split_sub_address("+").unwrap();
+        assert_eq!(base, "user@example.com");
+        assert_eq!(detail, "folder+nested");
+
+        let address = EmailAddress::new("user-tag@example.com").unwrap();
+        assert!(address.split_sub_address("+").is_none());
+        assert_eq!(address.split_sub_address("-").unwrap().1, "tag");
+        for address in [
+            "+tag@example.com",
+            "user+@example.com",
+            "\"a b+c\"@example.com",
+        ] {
+            let address = EmailAddress::new(address).unwrap();
+            assert!(address.split_sub_address("+").is_none(), "{}", address);
+        }
+    }
+
+    fn dot_atom() -> impl Strategy<Value = String> {
+        proptest::collection::vec("[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]{1,8}", 1..4)
+            .prop_map(|atoms| atoms.join("."))
+    }
+    fn domain() -> impl Strategy<Value = String> {
+        // Labels starting with xn-- must be valid punycode
+        let label = "[a-zA-Z0-9]([a-zA-Z0-9-]{0,10}[a-zA-Z0-9])?"
+            .prop_filter("punycode label", |label| {
+                !label.to_ascii_lowercase().starts_with("xn--")
+            });
+        proptest::collection::vec(label, 1..4).prop_map(|labels| labels.join("."))
+    }
+
+    proptest! {
+        #[test]
+        fn test_accepts_dot_atom_addresses(local_part in dot_atom(), domain in domain()) {
+            let address = EmailAddress::new(format!("{}@{}", local_part, domain)).unwrap();
+            prop_assert_eq!(address.local_part(), local_part.as_str());
+            prop_assert_eq!(address.domain(), domain.to_ascii_lowercase());
+        }
+        #[test]
+        fn test_normalization_is_idempotent(address in "\\PC{0,40}", lenient in any::<bool>()) {
+            let mode = if lenient { ValidationMode::Lenient } else { ValidationMode::Strict };
+            if let Ok(address) = EmailAddress::new_with_mode(address, mode) {
+                let reparsed = EmailAddress::new_with_mode(address.to_string(), mode).unwrap();
+                prop_assert_eq!(address, reparsed);
+            }
+        }
+        #[test]
+        fn test_quoted_local_parts(content in "[ !#-\\[\\]-~]{1,20}", domain in domain()) {
+            let address = EmailAddress::new(format!("\"{}\"@{}", content, domain)).unwrap();
+            prop_assert_eq!(address.domain(), domain.to_ascii_lowercase());
+        }
+    }
 }
 mod _serde {
     use crate::helper_types::EmailAddress;
@@ -121,7 +549,7 @@ impl Display for EmailAddress {
 #[cfg(feature = "sea-orm")]
 mod database {
     use sea_orm::sea_query::{ArrayType, Nullable, ValueType, ValueTypeErr};
-    use sea_orm::{ColIdx, ColumnType, QueryResult, TryGetError, TryGetable, Value};
+    use sea_orm::{ColIdx, ColumnType, DbErr, QueryResult, TryGetError, TryGetable, Value};
 
     use crate::helper_types::email_address::EmailAddress;
 
@@ -130,19 +558,20 @@ mod database {
             Value::String(Some(value.0.into()))
         }
     }
+    /// Stored addresses are parsed again so a malformed row is an error and not a panic.
+    /// They are parsed [leniently](crate::helper_types::email_address::ValidationMode::Lenient)
+    /// because addresses of either mode can be stored
     impl TryGetable for EmailAddress {
         fn try_get_by<I: ColIdx>(res: &QueryResult, index: I) -> Result<Self, TryGetError> {
             let value = String::try_get_by(res, index)?;
-            Ok(EmailAddress(value))
+            EmailAddress::new_lenient(&*value)
+                .map_err(|error| TryGetError::DbErr(DbErr::Type(format!("{value:?}: {error}"))))
         }
     }
     impl ValueType for EmailAddress {
         fn try_from(v: Value) -> Result<Self, ValueTypeErr> {
             match v {
-                Value::String(Some(s)) => {
-                    let email_address: String = *s;
-                    Ok(EmailAddress(email_address))
-                }
+                Value::String(Some(s)) => EmailAddress::new_lenient(*s).map_err(|_| ValueTypeErr),
                 _ => Err(ValueTypeErr),
             }
         }
@@ -1,4 +1,4 @@
-pub use email_address::EmailAddress;
+pub use email_address::{EmailAddress, InvalidEmailAddress, ValidationMode};
 pub use optional_email::OptionalEmailAddress;
 pub use password::Password;
 
@@ -2,18 +2,30 @@ use std::fmt::{Debug, Display};
 use std::ops::Deref;
 use std::str::FromStr;
 
-use argon2::password_hash::{Error, SaltString};
-use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{Error, PasswordHash, SaltString};
+use argon2::{Algorithm, Argon2, PasswordHasher, PasswordVerifier, Version};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use pbkdf2::Pbkdf2;
 use rand::rngs::OsRng;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::digest::Digest;
+use sha2::{Sha256, Sha512};
 use strum::{AsRefStr, Display, EnumIs, EnumIter, EnumString, IntoStaticStr};
+use subtle::ConstantTimeEq;
 use thiserror::Error;
 use tracing::error;
 
+use crate::configs::password::{Argon2Config, PasswordConfig};
+
 /// Hash Types supported by Stalwart [More Info](https://stalw.art/docs/directory/users#passwords)
 ///
-/// Types [PasswordType::PlainText] and [PasswordType::None] are not checked by panel
-/// and will always return false
+/// Hashes may be prefixed with a Dovecot style `{SCHEME}` tag. Such as `{SHA512-CRYPT}$6$...`.
+/// The tag is kept when the hash is stored.
+///
+/// [PasswordType::Unknown] hashes can not be checked
 #[derive(
     Debug,
     Clone,
@@ -40,6 +52,17 @@ pub enum PasswordType {
     /// Argon2 Hash. Recommended and What Stalwart Panel uses
     #[default]
     Argon2,
+    /// `$2a$`, `$2b$` and `$2y$` hashes. Dovecot `{BLF-CRYPT}`
+    Bcrypt,
+    /// `$6$` hashes. Dovecot `{SHA512-CRYPT}`
+    Sha512Crypt,
+    /// `$5$` hashes. Dovecot `{SHA256-CRYPT}`
+    Sha256Crypt,
+    /// PHC `$pbkdf2-sha256$` hashes, with or without a `{PBKDF2}` tag.
+    /// Dovecot `{PBKDF2}$1$salt$rounds$hash`
+    Pbkdf2,
+    /// Salted SHA. `{SSHA}`, `{SSHA256}` and `{SSHA512}`
+    Ssha,
     Unknown,
 }
 #[derive(Debug, Error)]
@@ -59,14 +82,59 @@ impl From<argon2::password_hash::Error> for PasswordErrors {
         PasswordErrors::InternalPasswordError(value.to_string())
     }
 }
+impl From<bcrypt::BcryptError> for PasswordErrors {
+    fn from(value: bcrypt::BcryptError) -> Self {
+        PasswordErrors::InternalPasswordError(value.to_string())
+    }
+}
+impl From<pwhash::error::Error> for PasswordErrors {
+    fn from(value: pwhash::error::Error) -> Self {
+        PasswordErrors::InternalPasswordError(value.to_string())
+    }
+}
 impl PasswordType {
     pub fn identify(password: &str) -> Self {
-        if password.starts_with("$argon2") {
+        let (scheme, hash) = Self::split_scheme(password);
+        let Some(scheme) = scheme else {
+            return Self::identify_prefix(hash);
+        };
+        match scheme.to_ascii_uppercase().as_str() {
+            "ARGON2I" | "ARGON2ID" => PasswordType::Argon2,
+            "BLF-CRYPT" => PasswordType::Bcrypt,
+            "SHA512-CRYPT" => PasswordType::Sha512Crypt,
+            "SHA256-CRYPT" => PasswordType::Sha256Crypt,
+            "PBKDF2" => PasswordType::Pbkdf2,
+            "SSHA" | "SSHA256" | "SSHA512" => PasswordType::Ssha,
+            "CRYPT" => Self::identify_prefix(hash),
+            _ => PasswordType::Unknown,
+        }
+    }
+    fn identify_prefix(hash: &str) -> Self {
+        if hash.starts_with("$argon2") {
             PasswordType::Argon2
+        } else if ["$2a$", "$2b$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+        {
+            PasswordType::Bcrypt
+        } else if hash.starts_with("$6$") {
+            PasswordType::Sha512Crypt
+        } else if hash.starts_with("$5$") {
+            PasswordType::Sha256Crypt
+        } else if hash.starts_with("$pbkdf2") {
+            PasswordType::Pbkdf2
         } else {
             PasswordType::Unknown
         }
     }
+    /// Splits `{SCHEME}hash` into the scheme and the hash
+    fn split_scheme(password: &str) -> (Option<&str>, &str) {
+        password
+            .strip_prefix('{')
+            .and_then(|password| password.split_once('}'))
+            .map(|(scheme, hash)| (Some(scheme), hash))
+            .unwrap_or((None, password))
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -93,19 +161,35 @@ impl Password {
         password: impl AsRef<str>,
         method: PasswordType,
     ) -> Result<Self, PasswordErrors> {
-        match method {
-            PasswordType::Argon2 => {
+        let password = password.as_ref().as_bytes();
+        let hash = match method {
+            PasswordType::Argon2 => return Self::new_argon2(password),
+            PasswordType::Bcrypt => bcrypt::hash(password, bcrypt::DEFAULT_COST)?,
+            PasswordType::Sha512Crypt => pwhash::sha512_crypt::hash(password)?,
+            // Deprecated by pwhash for new passwords. Only used if it is the preferred type
+            #[allow(deprecated)]
+            PasswordType::Sha256Crypt => pwhash::sha256_crypt::hash(password)?,
+            PasswordType::Pbkdf2 => {
                 let salt = SaltString::generate(&mut OsRng);
-                let argon2 = Argon2::default();
-                let password = password.as_ref();
-                let hash = argon2.hash_password(password.as_bytes(), &salt)?;
-                Ok(Password {
-                    password: hash.to_string(),
-                    hash_type: PasswordType::Argon2,
-                })
+                Pbkdf2.hash_password(password, &salt)?.to_string()
             }
-            _ => Err(PasswordErrors::UnsupportedHashType(method)),
-        }
+            PasswordType::Ssha => {
+                let mut salt = [0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+                let mut hash = Sha512::new()
+                    .chain_update(password)
+                    .chain_update(salt)
+                    .finalize()
+                    .to_vec();
+                hash.extend_from_slice(&salt);
+                format!("{{SSHA512}}{}", STANDARD.encode(hash))
+            }
+            PasswordType::Unknown => return Err(PasswordErrors::UnsupportedHashType(method)),
+        };
+        Ok(Password {
+            password: hash,
+            hash_type: method,
+        })
     }
     pub fn new_argon2(password: impl AsRef<[u8]>) -> Result<Self, PasswordErrors> {
         let salt = SaltString::generate(&mut OsRng);
@@ -118,6 +202,54 @@ impl Password {
             })
             .map_err(PasswordErrors::from)
     }
+    /// Argon2id with the configured parameters
+    pub fn new_argon2_with_config(
+        password: impl AsRef<[u8]>,
+        config: Argon2Config,
+    ) -> Result<Self, PasswordErrors> {
+        let salt = SaltString::generate(&mut OsRng);
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, config.try_into()?);
+        argon2
+            .hash_password(password.as_ref(), &salt)
+            .map(|v| Password {
+                password: v.to_string(),
+                hash_type: PasswordType::Argon2,
+            })
+            .map_err(PasswordErrors::from)
+    }
+    /// Hashes the password with the preferred [PasswordType]
+    pub fn new_preferred(
+        password: impl AsRef<str>,
+        config: &PasswordConfig,
+    ) -> Result<Self, PasswordErrors> {
+        match config.preferred {
+            PasswordType::Argon2 => Self::new_argon2_with_config(password.as_ref(), config.argon2),
+            method => Self::new_hash(password, method),
+        }
+    }
+    /// If the hash is not the preferred type or is Argon2 with weaker parameters
+    ///
+    /// Only call after [Password::check_password] succeeds, then replace it with [Password::new_preferred]
+    pub fn needs_rehash(&self, config: &PasswordConfig) -> bool {
+        if self.hash_type != config.preferred {
+            return true;
+        }
+        if !self.hash_type.is_argon_2() {
+            return false;
+        }
+        let (_, hash) = PasswordType::split_scheme(&self.password);
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return true;
+        };
+        let Ok(params) = argon2::Params::try_from(&hash) else {
+            return true;
+        };
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || params.m_cost() < config.argon2.memory_cost
+            || params.t_cost() < config.argon2.time_cost
+            || params.p_cost() < config.argon2.parallelism
+    }
+    /// An existing hash. Any `{SCHEME}` prefix is kept
     pub fn new_hashed(password: impl Into<String>) -> Self {
         let password = password.into();
         let hash_type = PasswordType::identify(&password);
@@ -127,36 +259,102 @@ impl Password {
         }
     }
     pub fn check_password(&self, password: impl AsRef<str>) -> Result<bool, PasswordErrors> {
+        let password = password.as_ref().as_bytes();
+        let (scheme, hash) = PasswordType::split_scheme(&self.password);
         match &self.hash_type {
-            PasswordType::Argon2 => self
-                .check_argon2(password.as_ref())
-                .map_err(PasswordErrors::from),
+            PasswordType::Argon2 => {
+                Self::check_argon2(hash, password).map_err(PasswordErrors::from)
+            }
+            PasswordType::Bcrypt => bcrypt::verify(password, hash).map_err(PasswordErrors::from),
+            PasswordType::Sha512Crypt => Ok(pwhash::sha512_crypt::verify(password, hash)),
+            PasswordType::Sha256Crypt => Ok(pwhash::sha256_crypt::verify(password, hash)),
+            // `{PBKDF2}` is also used as the tag of PHC hashes
+            PasswordType::Pbkdf2 if hash.starts_with("$pbkdf2") => {
+                Ok(Self::check_phc(&Pbkdf2, hash, password))
+            }
+            PasswordType::Pbkdf2 => Ok(Self::check_dovecot_pbkdf2(hash, password)),
+            PasswordType::Ssha => Ok(Self::check_ssha(scheme.unwrap_or_default(), hash, password)),
             v => {
                 error!("Unsupported hash type: {:?}", self.hash_type);
-                Err(PasswordErrors::UnsupportedHashType(v.clone()))
+                Err(PasswordErrors::UnsupportedHashType(*v))
             }
         }
     }
 
-    fn check_argon2(&self, password: impl AsRef<[u8]>) -> Result<bool, argon2::Error> {
+    fn check_argon2(hash: &str, password: &[u8]) -> Result<bool, argon2::Error> {
         let argon2 = Argon2::default();
-        let hash = match argon2::PasswordHash::new(&self.password) {
+        let hash = match PasswordHash::new(hash) {
             Ok(ok) => ok,
             Err(err) => {
                 error!("Error parsing password hash: {}", err);
                 return Ok(false);
             }
         };
-        if let Err(error) = argon2.verify_password(password.as_ref(), &hash) {
-            match error {
-                Error::Password => {}
-                error => {
-                    error!("Error verifying password: {}", error);
-                }
+        match argon2.verify_password(password, &hash) {
+            Ok(()) => Ok(true),
+            Err(Error::Password) => Ok(false),
+            Err(error) => {
+                error!("Error verifying password: {}", error);
+                Ok(false)
             }
-            Ok(false)
-        } else {
-            Ok(false)
+        }
+    }
+    fn check_phc(verifier: &impl PasswordVerifier, hash: &str, password: &[u8]) -> bool {
+        match PasswordHash::new(hash) {
+            Ok(hash) => verifier.verify_password(password, &hash).is_ok(),
+            Err(err) => {
+                error!("Error parsing password hash: {}", err);
+                false
+            }
+        }
+    }
+    /// `$1$salt$rounds$hex`. PBKDF2-HMAC-SHA1 with a 20 byte key
+    fn check_dovecot_pbkdf2(hash: &str, password: &[u8]) -> bool {
+        let mut parts = hash.split('$');
+        let (Some(""), Some("1"), Some(salt), Some(rounds), Some(expected), None) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            error!("Error parsing PBKDF2 password hash");
+            return false;
+        };
+        let Ok(rounds) = rounds.parse::<u32>() else {
+            return false;
+        };
+        let mut key = [0u8; 20];
+        pbkdf2::pbkdf2_hmac::<Sha1>(password, salt.as_bytes(), rounds, &mut key);
+        let key: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+        key.as_bytes()
+            .ct_eq(expected.to_ascii_lowercase().as_bytes())
+            .into()
+    }
+    /// `base64(digest(password + salt) + salt)`
+    fn check_ssha(scheme: &str, hash: &str, password: &[u8]) -> bool {
+        let Ok(decoded) = STANDARD.decode(hash.trim()) else {
+            error!("Error decoding SSHA password hash");
+            return false;
+        };
+        fn check<D: Digest>(decoded: &[u8], password: &[u8]) -> bool {
+            let size = <D as Digest>::output_size();
+            if decoded.len() <= size {
+                return false;
+            }
+            let (expected, salt) = decoded.split_at(size);
+            let digest = D::new()
+                .chain_update(password)
+                .chain_update(salt)
+                .finalize();
+            digest.as_slice().ct_eq(expected).into()
+        }
+        match scheme.to_ascii_uppercase().as_str() {
+            "SSHA" => check::<Sha1>(&decoded, password),
+            "SSHA256" => check::<Sha256>(&decoded, password),
+            "SSHA512" => check::<Sha512>(&decoded, password),
+            _ => false,
         }
     }
     pub fn hash_type(&self) -> PasswordType {
@@ -283,3 +481,125 @@ mod _serde {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+
+    use crate::configs::password::{Argon2Config, PasswordConfig};
+    use crate::helper_types::password::{Password, PasswordType};
+
+    #[test]
+    pub fn test_identify() {
+        for (hash, hash_type) in [
+            (
+                "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA",
+                PasswordType::Argon2,
+            ),
+            (
+                "{ARGON2ID}$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA",
+                PasswordType::Argon2,
+            ),
+            ("$2y$10$abcdefghijklmnopqrstuu", PasswordType::Bcrypt),
+            (
+                "{BLF-CRYPT}$2y$10$abcdefghijklmnopqrstuu",
+                PasswordType::Bcrypt,
+            ),
+            ("$6$salt$hash", PasswordType::Sha512Crypt),
+            ("{CRYPT}$6$salt$hash", PasswordType::Sha512Crypt),
+            ("{SHA256-CRYPT}$5$salt$hash", PasswordType::Sha256Crypt),
+            ("$pbkdf2-sha256$i=1000$salt$hash", PasswordType::Pbkdf2),
+            ("{PBKDF2}$1$salt$1000$hash", PasswordType::Pbkdf2),
+            ("{SSHA}aGFzaA==", PasswordType::Ssha),
+            ("{MD5}aGFzaA==", PasswordType::Unknown),
+            ("plain", PasswordType::Unknown),
+        ] {
+            assert_eq!(PasswordType::identify(hash), hash_type, "{}", hash);
+        }
+    }
+    #[test]
+    pub fn test_new_hash_and_check() {
+        for hash_type in PasswordType::iter().filter(|t| !t.is_unknown()) {
+            let password = Password::new_hash("password", hash_type).unwrap();
+            assert_eq!(password.hash_type(), hash_type);
+            assert_eq!(PasswordType::identify(&password), hash_type);
+            assert!(
+                password.check_password("password").unwrap(),
+                "{}",
+                hash_type
+            );
+            assert!(!password.check_password("wrong").unwrap(), "{}", hash_type);
+        }
+        assert!(Password::new_hash("password", PasswordType::Unknown).is_err());
+    }
+    #[test]
+    pub fn test_needs_rehash() {
+        let config = PasswordConfig::default();
+        let password = Password::new_preferred("password", &config).unwrap();
+        assert!(password.check_password("password").unwrap());
+        assert!(!password.needs_rehash(&config));
+
+        let stronger = PasswordConfig {
+            argon2: Argon2Config {
+                time_cost: config.argon2.time_cost + 1,
+                ..config.argon2
+            },
+            ..config
+        };
+        assert!(password.needs_rehash(&stronger));
+        let stronger_password = Password::new_preferred("password", &stronger).unwrap();
+        assert!(stronger_password.check_password("password").unwrap());
+        assert!(!stronger_password.needs_rehash(&stronger));
+        // Weaker parameters than the stored hash are fine
+        assert!(!stronger_password.needs_rehash(&config));
+
+        let legacy = Password::new_hashed("{SSHA}yI6cZwQadOA1e+/f+T+H3eCQQhRzYWx0");
+        assert!(legacy.needs_rehash(&config));
+        let bcrypt = PasswordConfig {
+            preferred: PasswordType::Bcrypt,
+            ..config
+        };
+        assert!(password.needs_rehash(&bcrypt));
+        let argon2i = Password::new_hashed(
+            "$argon2i$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$ZJtM/Ey+zFhKqFnbFvXo1kRLBBPDAqyPJUOOx0pDXb0",
+        );
+        assert!(argon2i.needs_rehash(&config));
+    }
+    /// Test vectors from [the specification](https://www.akkadia.org/drepper/SHA-crypt.txt)
+    #[test]
+    pub fn test_sha_crypt_vectors() {
+        for hash in [
+            "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5",
+            "$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA",
+            "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1",
+            "$6$rounds=10000$saltstringsaltst$OW1/O6BYHV6BcXZu8QVeXbDWra3Oeqh0sbHbbMCVNSnCM/UrjmM0Dp8vOuZeHBy/YTBmSK6H9qs/y3RnOaw5v.",
+        ] {
+            let password = Password::new_hashed(hash);
+            assert!(password.check_password("Hello world!").unwrap(), "{}", hash);
+            assert!(!password.check_password("wrong").unwrap(), "{}", hash);
+        }
+        assert!(!Password::new_hashed("{SHA256-CRYPT}$6$saltstring$abc")
+            .check_password("Hello world!")
+            .unwrap());
+    }
+    /// Hashes created by OpenSSL and Python's hashlib. The password is always `password`
+    #[test]
+    pub fn test_imported_hashes() {
+        let bcrypt = format!("{{BLF-CRYPT}}{}", bcrypt::hash("password", 4).unwrap());
+        for hash in [
+            "{SHA512-CRYPT}$6$saltsalt$qFmFH.bQmmtXzyBY0s9v7Oicd2z4XSIecDzlB5KiA2/jctKu9YterLp8wwnSq.qc.eoxqOmSuNp2xS0ktL3nh/",
+            "$5$saltsalt$gOjOtoMpVhru2uyjeJSEc/JaLQWOXMNmlOnj6T4AtC.",
+            "{SSHA}yI6cZwQadOA1e+/f+T+H3eCQQhRzYWx0",
+            "{SSHA256}eje4XIkY6sGakInA+loqtNzj+QUo3N7sEIsj3fNge5lzYWx0",
+            "{PBKDF2}$1$saltsalt$1000$e9febff54bfce668fde301acc85563cc9dc71ef6",
+            "$pbkdf2-sha256$i=1000,l=32$c2FsdHNhbHQ$E196ZhRPzw+wA84EjzHwJO1cv/MFJdO6C/sxmUeTYqY",
+            "{PBKDF2}$pbkdf2-sha256$i=1000,l=32$c2FsdHNhbHQ$E196ZhRPzw+wA84EjzHwJO1cv/MFJdO6C/sxmUeTYqY",
+            &bcrypt,
+        ] {
+            let password = Password::new_hashed(hash);
+            assert_eq!(password.as_str(), hash);
+            assert!(password.check_password("password").unwrap(), "{}", hash);
+            assert!(!password.check_password("wrong").unwrap(), "{}", hash);
+        }
+    }
+}
@@ -44,7 +44,7 @@ impl Drop for InterprocessConnectionInner {
 impl InterprocessConnectionInner {
     pub fn new(connection: LocalSocketStream) -> Self {
         Self {
-            has_connection: Arc::new(AtomicBool::new(true)),
+            has_connection: Arc::new(AtomicBool::new(false)),
             connection: UnsafeCell::new(connection),
         }
     }
@@ -5,11 +5,17 @@ use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 pub mod account;
+pub mod admin;
+pub mod app_password;
+pub mod auth_failures;
 pub mod chrono_serde;
 pub mod common_types;
 pub mod configs;
 pub mod groups;
 pub mod helper_types;
 pub mod interprocess_guard;
+pub mod quota;
+pub mod sasl;
 pub mod service;
 pub mod service_configuration;
+pub mod two_factor;
@@ -0,0 +1,144 @@
+//! Storage quotas. The directory keeps the limits of each mailbox and storage counts what it uses
+use strum::{AsRefStr, Display, EnumIter, IntoStaticStr};
+
+/// The limits of a mailbox. 0 is no limit
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+#[serde(default)]
+pub struct Quota {
+    /// Bytes
+    pub storage: u64,
+    pub messages: u64,
+}
+
+/// What a mailbox currently stores
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct QuotaUsage {
+    /// Bytes
+    pub storage: u64,
+    pub messages: u64,
+}
+
+/// The resources of the [IMAP QUOTA extension](https://www.rfc-editor.org/rfc/rfc9208)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, AsRefStr, IntoStaticStr, Display, EnumIter)]
+#[strum(serialize_all = "UPPERCASE")]
+pub enum QuotaResource {
+    /// Measured in KiB
+    Storage,
+    Message,
+}
+
+impl Quota {
+    /// Limits stored as signed integers by the directories. 0 or less is no limit
+    pub fn from_limits(storage: i64, messages: i64) -> Self {
+        Self {
+            storage: storage.max(0) as u64,
+            messages: messages.max(0) as u64,
+        }
+    }
+    pub fn is_unlimited(&self) -> bool {
+        self.storage == 0 && self.messages == 0
+    }
+    /// The limit of the resource. None if it has no limit
+    pub fn limit(&self, resource: QuotaResource) -> Option<u64> {
+        let limit = match resource {
+            QuotaResource::Storage => self.storage,
+            QuotaResource::Message => self.messages,
+        };
+        (limit != 0).then_some(limit)
+    }
+    /// The first resource that is used up. Checked before the size of a new message is known
+    pub fn exhausted(&self, usage: &QuotaUsage) -> Option<QuotaResource> {
+        self.exceeded_by(usage, 1, 1)
+    }
+    /// The first resource that would go over its limit if `messages` messages of `size` bytes were added
+    pub fn exceeded_by(
+        &self,
+        usage: &QuotaUsage,
+        size: u64,
+        messages: u64,
+    ) -> Option<QuotaResource> {
+        let over = |resource, used: u64, added: u64| {
+            self.limit(resource)
+                .is_some_and(|limit| used.saturating_add(added) > limit)
+        };
+        if over(QuotaResource::Storage, usage.storage, size) {
+            Some(QuotaResource::Storage)
+        } else if over(QuotaResource::Message, usage.messages, messages) {
+            Some(QuotaResource::Message)
+        } else {
+            None
+        }
+    }
+}
+impl QuotaUsage {
+    /// The usage in the unit IMAP reports the resource in
+    pub fn imap_value(&self, resource: QuotaResource) -> u64 {
+        match resource {
+            QuotaResource::Storage => self.storage.div_ceil(1024),
+            QuotaResource::Message => self.messages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quota::{Quota, QuotaResource, QuotaUsage};
+
+    #[test]
+    pub fn test_quota() {
+        let usage = QuotaUsage {
+            storage: 900,
+            messages: 9,
+        };
+        assert!(Quota::from_limits(0, -1).is_unlimited());
+        assert_eq!(Quota::default().exceeded_by(&usage, u64::MAX, 1), None);
+
+        let quota = Quota::from_limits(1000, 10);
+        assert_eq!(quota.exhausted(&usage), None);
+        assert_eq!(quota.exceeded_by(&usage, 100, 1), None);
+        assert_eq!(
+            quota.exceeded_by(&usage, 101, 1),
+            Some(QuotaResource::Storage)
+        );
+        assert_eq!(
+            quota.exceeded_by(&usage, 10, 2),
+            Some(QuotaResource::Message)
+        );
+
+        let full = QuotaUsage {
+            storage: 1000,
+            messages: 9,
+        };
+        assert_eq!(quota.exhausted(&full), Some(QuotaResource::Storage));
+        assert_eq!(full.imap_value(QuotaResource::Storage), 1);
+        assert_eq!(QuotaResource::Storage.as_ref(), "STORAGE");
+    }
+}
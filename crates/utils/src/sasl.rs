@@ -0,0 +1,264 @@
+//! SASL OAUTHBEARER ([RFC 7628](https://www.rfc-editor.org/rfc/rfc7628)) and XOAUTH2
+//!
+//! Shared by SMTP `AUTH` and IMAP `AUTHENTICATE`. Only parses the client responses, validating the token is up to the caller
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use strum::{AsRefStr, Display, EnumIs, EnumIter, EnumString, IntoStaticStr};
+use thiserror::Error;
+
+const SEPARATOR: char = '\x01';
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+    AsRefStr,
+    IntoStaticStr,
+    EnumIs,
+    EnumString,
+    Display,
+    EnumIter,
+)]
+pub enum SaslMechanism {
+    #[strum(serialize = "OAUTHBEARER")]
+    #[serde(rename = "OAUTHBEARER")]
+    OAuthBearer,
+    #[strum(serialize = "XOAUTH2")]
+    #[serde(rename = "XOAUTH2")]
+    XOAuth2,
+}
+impl SaslMechanism {
+    /// Parses the mechanism name ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "OAUTHBEARER" => Some(SaslMechanism::OAuthBearer),
+            "XOAUTH2" => Some(SaslMechanism::XOAuth2),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SaslError {
+    #[error("Invalid base64")]
+    InvalidBase64,
+    #[error("Invalid UTF-8")]
+    InvalidUtf8,
+    #[error("Malformed {0} response")]
+    Malformed(SaslMechanism),
+    #[error("Channel binding is not supported")]
+    ChannelBindingNotSupported,
+    #[error("Missing bearer token")]
+    MissingToken,
+}
+
+/// The credentials sent with OAUTHBEARER or XOAUTH2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BearerCredentials {
+    /// The user the client wants to act as. `a=` for OAUTHBEARER and `user=` for XOAUTH2
+    pub authzid: Option<String>,
+    pub token: String,
+}
+
+/// Decodes a base64 client response. `=` is an empty response
+pub fn decode_response(response: &str) -> Result<Vec<u8>, SaslError> {
+    let response = response.trim();
+    if response == "=" {
+        return Ok(Vec::new());
+    }
+    STANDARD
+        .decode(response)
+        .map_err(|_| SaslError::InvalidBase64)
+}
+
+/// Parses a decoded client response
+pub fn parse_response(
+    mechanism: SaslMechanism,
+    response: &[u8],
+) -> Result<BearerCredentials, SaslError> {
+    let response = std::str::from_utf8(response).map_err(|_| SaslError::InvalidUtf8)?;
+    match mechanism {
+        SaslMechanism::OAuthBearer => parse_oauthbearer(response),
+        SaslMechanism::XOAuth2 => parse_xoauth2(response),
+    }
+}
+
+/// `n,a=user@example.com,^Ahost=server^Aauth=Bearer <token>^A^A`
+fn parse_oauthbearer(response: &str) -> Result<BearerCredentials, SaslError> {
+    let malformed = SaslError::Malformed(SaslMechanism::OAuthBearer);
+    let (gs2_header, key_values) = response.split_once(SEPARATOR).ok_or(malformed.clone())?;
+    let mut gs2_parts = gs2_header.split(',');
+    match gs2_parts.next() {
+        Some("n") | Some("y") => {}
+        Some(flag) if flag.starts_with("p=") => return Err(SaslError::ChannelBindingNotSupported),
+        _ => return Err(malformed),
+    }
+    let authzid = match gs2_parts.next() {
+        Some("") | None => None,
+        Some(authzid) => Some(unescape_saslname(
+            authzid.strip_prefix("a=").ok_or(malformed.clone())?,
+        )?),
+    };
+    let token = bearer_token(key_values, "auth")?;
+    Ok(BearerCredentials { authzid, token })
+}
+
+/// `user=user@example.com^Aauth=Bearer <token>^A^A`
+fn parse_xoauth2(response: &str) -> Result<BearerCredentials, SaslError> {
+    let authzid = key_value(response, "user")
+        .filter(|user| !user.is_empty())
+        .map(str::to_string);
+    let token = bearer_token(response, "auth")?;
+    Ok(BearerCredentials { authzid, token })
+}
+
+fn key_value<'a>(key_values: &'a str, key: &str) -> Option<&'a str> {
+    key_values
+        .split(SEPARATOR)
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
+
+fn bearer_token(key_values: &str, key: &str) -> Result<String, SaslError> {
+    let auth = key_value(key_values, key).ok_or(SaslError::MissingToken)?;
+    let (scheme, token) = auth.split_once(' ').ok_or(SaslError::MissingToken)?;
+    let token = token.trim();
+    if !scheme.eq_ignore_ascii_case("Bearer") || token.is_empty() {
+        return Err(SaslError::MissingToken);
+    }
+    Ok(token.to_string())
+}
+
+/// `=2C` is a comma and `=3D` is an equals sign. Any other `=` is invalid
+fn unescape_saslname(name: &str) -> Result<String, SaslError> {
+    let mut result = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(index) = rest.find('=') {
+        result.push_str(&rest[..index]);
+        match rest.get(index..index + 3) {
+            Some("=2C") => result.push(','),
+            Some("=3D") => result.push('='),
+            _ => return Err(SaslError::Malformed(SaslMechanism::OAuthBearer)),
+        }
+        rest = &rest[index + 3..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// The base64 JSON error the server sends before failing the exchange
+///
+/// The client must answer it. OAUTHBEARER clients send `^A` and XOAUTH2 clients an empty line
+pub fn error_challenge(status: &str) -> String {
+    STANDARD.encode(format!(
+        "{{\"status\":\"{}\",\"schemes\":\"bearer\"}}",
+        status.replace(['"', '\\'], "")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    use crate::sasl::{
+        decode_response, error_challenge, parse_response, BearerCredentials, SaslError,
+        SaslMechanism,
+    };
+
+    fn credentials(authzid: Option<&str>, token: &str) -> BearerCredentials {
+        BearerCredentials {
+            authzid: authzid.map(str::to_string),
+            token: token.to_string(),
+        }
+    }
+
+    #[test]
+    pub fn test_oauthbearer() {
+        // RFC 7628 Section 4.1
+        let response = decode_response(
+            "bixhPXVzZXJAZXhhbXBsZS5jb20sAWhvc3Q9c2VydmVyLmV4YW1wbGUuY29tAXBvcnQ9MTQzAWF1dGg9QmVhcmVyIHZGOWRmdDRxbVRjMk52YjNSbGNrQmhiSFJoZG1semRHRXVZMjl0Q2c9PQEB",
+        )
+        .unwrap();
+        assert_eq!(
+            parse_response(SaslMechanism::OAuthBearer, &response),
+            Ok(credentials(
+                Some("user@example.com"),
+                "vF9dft4qmTc2Nvb3RlckBhbHRhdmlzdGEuY29tCg=="
+            ))
+        );
+        assert_eq!(
+            parse_response(
+                SaslMechanism::OAuthBearer,
+                b"n,a=a=2Cb=3Dc,\x01auth=Bearer token\x01\x01"
+            ),
+            Ok(credentials(Some("a,b=c"), "token"))
+        );
+        assert_eq!(
+            parse_response(
+                SaslMechanism::OAuthBearer,
+                b"n,,\x01auth=Bearer token\x01\x01"
+            ),
+            Ok(credentials(None, "token"))
+        );
+        assert_eq!(
+            parse_response(
+                SaslMechanism::OAuthBearer,
+                b"p=tls-unique,,\x01auth=Bearer token\x01\x01"
+            ),
+            Err(SaslError::ChannelBindingNotSupported)
+        );
+        assert_eq!(
+            parse_response(SaslMechanism::OAuthBearer, b"n,,\x01auth=Basic abc\x01\x01"),
+            Err(SaslError::MissingToken)
+        );
+        assert_eq!(
+            parse_response(
+                SaslMechanism::OAuthBearer,
+                b"n,a=bad=,\x01auth=Bearer t\x01\x01"
+            ),
+            Err(SaslError::Malformed(SaslMechanism::OAuthBearer))
+        );
+    }
+    #[test]
+    pub fn test_xoauth2() {
+        let response = STANDARD
+            .encode("user=someuser@example.com\x01auth=Bearer ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg\x01\x01");
+        let response = decode_response(&response).unwrap();
+        assert_eq!(
+            parse_response(SaslMechanism::XOAuth2, &response),
+            Ok(credentials(
+                Some("someuser@example.com"),
+                "ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg"
+            ))
+        );
+        assert_eq!(
+            parse_response(SaslMechanism::XOAuth2, b"user=someone\x01\x01"),
+            Err(SaslError::MissingToken)
+        );
+        assert_eq!(decode_response("="), Ok(Vec::new()));
+        assert_eq!(
+            decode_response("not base64!"),
+            Err(SaslError::InvalidBase64)
+        );
+    }
+    #[test]
+    pub fn test_error_challenge() {
+        let challenge = STANDARD.decode(error_challenge("invalid_token")).unwrap();
+        assert_eq!(
+            String::from_utf8(challenge).unwrap(),
+            r#"{"status":"invalid_token","schemes":"bearer"}"#
+        );
+        assert_eq!(
+            SaslMechanism::from_name("xoauth2"),
+            Some(SaslMechanism::XOAuth2)
+        );
+        assert_eq!(SaslMechanism::OAuthBearer.to_string(), "OAUTHBEARER");
+    }
+}
@@ -0,0 +1,264 @@
+//! RFC 6238 TOTP and recovery codes
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::distributions::Uniform;
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::app_password::normalize_app_password;
+
+pub const TOTP_DIGITS: usize = 6;
+pub const TOTP_STEP: u64 = 30;
+/// Codes from one step before or after the current one are accepted to allow for clock drift
+pub const TOTP_SKEW: u8 = 1;
+/// 160 bits as recommended by RFC 4226
+const SECRET_LENGTH: usize = 20;
+const NONCE_LENGTH: usize = 24;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+const RECOVERY_CODE_GROUP_LENGTH: usize = 5;
+
+#[derive(Debug, Error)]
+pub enum TwoFactorError {
+    #[error("No encryption key is configured for two-factor secrets")]
+    MissingEncryptionKey,
+    #[error("The encryption key must be 32 bytes encoded as base64")]
+    InvalidEncryptionKey,
+    #[error("Unable to decrypt the two-factor secret")]
+    Decryption,
+    #[error("Invalid TOTP secret: {0}")]
+    InvalidSecret(String),
+}
+
+/// Returned once when TOTP is enrolled so the user can add it to their authenticator app
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[archive(compare(PartialEq), check_bytes)]
+pub struct TotpEnrollment {
+    /// The secret encoded as base32
+    pub secret: String,
+    /// An `otpauth://` URL. Usually shown as a QR code
+    pub otpauth_url: String,
+    pub recovery_codes: Vec<String>,
+}
+
+/// Encrypts two-factor secrets stored in the directory with XChaCha20-Poly1305
+#[derive(Clone)]
+pub struct SecretKey(Key);
+impl SecretKey {
+    /// Parses 32 bytes encoded as base64
+    pub fn from_base64(key: &str) -> Result<Self, TwoFactorError> {
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|_| TwoFactorError::InvalidEncryptionKey)?;
+        if key.len() != 32 {
+            return Err(TwoFactorError::InvalidEncryptionKey);
+        }
+        Ok(SecretKey(*Key::from_slice(&key)))
+    }
+    /// A random key encoded as base64
+    pub fn generate_base64() -> String {
+        STANDARD.encode(XChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    /// Returns the random nonce followed by the ciphertext
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = XChaCha20Poly1305::new(&self.0)
+            .encrypt(&nonce, plaintext)
+            .expect("Encrypting into a Vec can not fail");
+        let mut result = nonce.to_vec();
+        result.extend(ciphertext);
+        result
+    }
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, TwoFactorError> {
+        if data.len() < NONCE_LENGTH {
+            return Err(TwoFactorError::Decryption);
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+        XChaCha20Poly1305::new(&self.0)
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| TwoFactorError::Decryption)
+    }
+}
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+pub fn generate_totp_secret() -> Vec<u8> {
+    let mut secret = vec![0; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+pub fn totp(
+    secret: Vec<u8>,
+    issuer: impl Into<String>,
+    account_name: impl Into<String>,
+) -> Result<TOTP, TwoFactorError> {
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW,
+        TOTP_STEP,
+        secret,
+        Some(issuer.into()),
+        account_name.into(),
+    )
+    .map_err(|error| TwoFactorError::InvalidSecret(error.to_string()))
+}
+
+/// The base32 secret and `otpauth://` URL for a new secret
+pub fn enrollment(
+    secret: Vec<u8>,
+    issuer: &str,
+    account_name: &str,
+    recovery_codes: Vec<String>,
+) -> Result<TotpEnrollment, TwoFactorError> {
+    let totp = totp(secret, issuer, account_name)?;
+    Ok(TotpEnrollment {
+        secret: Secret::Raw(totp.secret.clone()).to_encoded().to_string(),
+        otpauth_url: totp.get_url(),
+        recovery_codes,
+    })
+}
+
+/// The time step the unix time falls in
+pub fn totp_step(time: u64) -> i64 {
+    (time / TOTP_STEP) as i64
+}
+
+/// The code for a time step
+pub fn totp_code(secret: &[u8], step: i64) -> String {
+    TOTP {
+        algorithm: Algorithm::SHA1,
+        digits: TOTP_DIGITS,
+        skew: TOTP_SKEW,
+        step: TOTP_STEP,
+        secret: secret.to_vec(),
+        issuer: None,
+        account_name: String::new(),
+    }
+    .generate(step.max(0) as u64 * TOTP_STEP)
+}
+
+/// Checks the code against the steps around `time`
+///
+/// Returns the step that matched. Steps at or before `last_step` are rejected so a code can only be used once
+pub fn verify_totp(secret: &[u8], code: &str, time: u64, last_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS {
+        return None;
+    }
+    let current = totp_step(time);
+    let skew = TOTP_SKEW as i64;
+    (current - skew..=current + skew)
+        .filter(|step| last_step.is_none_or(|last_step| *step > last_step))
+        .find(|step| bool::from(totp_code(secret, *step).as_bytes().ct_eq(code.as_bytes())))
+}
+
+/// Codes in the form `abcde-fghij`. Ambiguous characters are left out
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let characters: String = OsRng
+                .sample_iter(Uniform::from(0..RECOVERY_CODE_ALPHABET.len()))
+                .take(RECOVERY_CODE_GROUP_LENGTH * 2)
+                .map(|index| RECOVERY_CODE_ALPHABET[index] as char)
+                .collect();
+            let (first, second) = characters.split_at(RECOVERY_CODE_GROUP_LENGTH);
+            format!("{}-{}", first, second)
+        })
+        .collect()
+}
+
+/// Recovery codes are hashed and checked after normalizing, the same as app passwords
+pub fn normalize_recovery_code(code: &str) -> String {
+    normalize_app_password(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::two_factor::{
+        enrollment, generate_recovery_codes, generate_totp_secret, totp_code, totp_step,
+        verify_totp, SecretKey, RECOVERY_CODE_COUNT,
+    };
+
+    /// The SHA-1 test vectors from RFC 6238 Appendix B, truncated to 6 digits
+    #[test]
+    pub fn test_rfc_6238_vectors() {
+        let secret = b"12345678901234567890";
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(totp_code(secret, totp_step(time)), code);
+        }
+    }
+    #[test]
+    pub fn test_verify() {
+        let secret = b"12345678901234567890";
+        let time = 1111111111;
+        assert_eq!(
+            verify_totp(secret, "050471", time, None),
+            Some(totp_step(time))
+        );
+        // The step before is still accepted
+        assert!(verify_totp(secret, "050471", time + 30, None).is_some());
+        assert!(verify_totp(secret, "050471", time + 90, None).is_none());
+        // Replay
+        assert!(verify_totp(secret, "050471", time, Some(totp_step(time))).is_none());
+        assert!(verify_totp(secret, "000000", time, None).is_none());
+        assert!(verify_totp(secret, "05047", time, None).is_none());
+    }
+    #[test]
+    pub fn test_encryption() {
+        let key = SecretKey::from_base64(&SecretKey::generate_base64()).unwrap();
+        let secret = generate_totp_secret();
+        let encrypted = key.encrypt(&secret);
+        assert_ne!(encrypted, secret);
+        assert_eq!(key.decrypt(&encrypted).unwrap(), secret);
+
+        let other = SecretKey::from_base64(&SecretKey::generate_base64()).unwrap();
+        assert!(other.decrypt(&encrypted).is_err());
+        assert!(SecretKey::from_base64("c2hvcnQ=").is_err());
+    }
+    #[test]
+    pub fn test_enrollment() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| code.len() == 11));
+        let enrollment = enrollment(
+            b"12345678901234567890".to_vec(),
+            "Nitro Mail",
+            "user@example.com",
+            codes,
+        )
+        .unwrap();
+        assert_eq!(enrollment.secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert!(enrollment.otpauth_url.starts_with("otpauth://totp/"));
+        assert!(enrollment
+            .otpauth_url
+            .contains("secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"));
+    }
+}